@@ -30,14 +30,22 @@
             lockFile = self + "/Cargo.lock";
           };
 
-          nativeBuildInputs = [ pkgs.pkg-config pkgs.makeWrapper ];
+          nativeBuildInputs = [ pkgs.pkg-config pkgs.makeWrapper pkgs.installShellFiles ];
           buildInputs = [ pkgs.openssl ];
 
           # Skip tests that require system binaries not available in Nix sandbox
           doCheck = false;
 
-          # age is a runtime dependency for secrets encryption
+          # Completions and man pages come from the built binary; age is a
+          # runtime dependency for secrets encryption
           postInstall = ''
+            installShellCompletion --cmd dotdipper \
+              --bash <($out/bin/dotdipper completions bash) \
+              --zsh <($out/bin/dotdipper completions zsh) \
+              --fish <($out/bin/dotdipper completions fish)
+            $out/bin/dotdipper manpages --out-dir man
+            installManPage man/*.1
+
             wrapProgram $out/bin/dotdipper \
               --prefix PATH : ${pkgs.lib.makeBinPath [ pkgs.age ]}
           '';
@@ -0,0 +1,291 @@
+//! Archiving tracked files that are no longer in use.
+//!
+//! `dotdipper archive ~/.config/alacritty` moves the compiled copies of the
+//! files under that path into `archive/` in the compiled tree and drops them
+//! from `tracked_files` and the manifest, so apply and status ignore them.
+//! The files in `$HOME` are left alone. The compiled tree is the repository
+//! `dotdipper push` commits, so the move is committed as a rename and
+//! `git log --follow` still shows the files' history. `dotdipper unarchive`
+//! moves them back.
+//!
+//! The manifest entries of archived files are kept in
+//! `archive/.dotdipper-archive.json`, so unarchiving restores them exactly.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hash::Manifest;
+use crate::matching::{display_tilde, expand_tilde, to_home_relative, PathMatcher};
+use crate::ui;
+
+/// Directory in the compiled tree holding archived files
+pub const ARCHIVE_DIR: &str = "archive";
+
+const INDEX_FILE: &str = ".dotdipper-archive.json";
+
+fn index_path(compiled: &Path) -> PathBuf {
+    compiled.join(ARCHIVE_DIR).join(INDEX_FILE)
+}
+
+fn load_index(compiled: &Path) -> Result<Manifest> {
+    let path = index_path(compiled);
+    if path.exists() {
+        Manifest::load(&path)
+    } else {
+        Ok(Manifest::new())
+    }
+}
+
+/// `path` as `~/...`, refusing anything outside $HOME
+fn normalize(input: &str, home: &Path) -> Result<String> {
+    let rel = to_home_relative(&expand_tilde(input, home), home);
+    if rel.is_absolute() || rel.as_os_str().is_empty() {
+        bail!(
+            "Only files and directories under $HOME can be archived: {}",
+            input
+        );
+    }
+    Ok(display_tilde(&rel))
+}
+
+/// Move `from` to `to`, creating `to`'s parents and removing the
+/// directories `from` leaves empty, up to `root`
+fn move_file(from: &Path, to: &Path, root: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)
+        .with_context(|| format!("Failed to move {} to {}", from.display(), to.display()))?;
+    for dir in from.ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Files moved between the active and archived sets
+fn matching_files(manifest: &Manifest, matcher: &PathMatcher) -> Vec<PathBuf> {
+    let mut files: Vec<PathBuf> = manifest
+        .files
+        .keys()
+        .filter(|rel| matcher.is_match(rel))
+        .cloned()
+        .collect();
+    files.sort();
+    files
+}
+
+/// Archive the files under `path` in `compiled`, updating `manifest` and
+/// returning the home-relative paths archived
+fn archive_in(
+    compiled: &Path,
+    manifest: &mut Manifest,
+    matcher: &PathMatcher,
+    path: &str,
+) -> Result<Vec<PathBuf>> {
+    let files = matching_files(manifest, matcher);
+    if files.is_empty() {
+        bail!("No snapshotted files under {}", path);
+    }
+
+    let archive_root = compiled.join(ARCHIVE_DIR);
+    let mut index = load_index(compiled)?;
+    for rel in &files {
+        let active = compiled.join(rel);
+        if active.exists() || active.is_symlink() {
+            move_file(&active, &archive_root.join(rel), compiled)?;
+        }
+        if let Some(entry) = manifest.files.remove(rel) {
+            index.add_file(entry);
+        }
+    }
+    index.save(&index_path(compiled))?;
+    Ok(files)
+}
+
+/// Bring the files under `path` back from the archive in `compiled`
+fn unarchive_in(
+    compiled: &Path,
+    manifest: &mut Manifest,
+    matcher: &PathMatcher,
+    path: &str,
+) -> Result<Vec<PathBuf>> {
+    let mut index = load_index(compiled)?;
+    let files = matching_files(&index, matcher);
+    if files.is_empty() {
+        bail!("Nothing archived under {}", path);
+    }
+    if let Some(tracked) = files.iter().find(|rel| manifest.has_file(rel)) {
+        bail!(
+            "{} has been snapshotted again since it was archived; archive or untrack it first",
+            display_tilde(tracked)
+        );
+    }
+
+    let archive_root = compiled.join(ARCHIVE_DIR);
+    for rel in &files {
+        let archived = archive_root.join(rel);
+        if archived.exists() || archived.is_symlink() {
+            move_file(&archived, &compiled.join(rel), &archive_root)?;
+        }
+        if let Some(entry) = index.files.remove(rel) {
+            manifest.add_file(entry);
+        }
+    }
+
+    if index.files.is_empty() {
+        fs::remove_file(index_path(compiled))?;
+        let _ = fs::remove_dir(&archive_root);
+    } else {
+        index.save(&index_path(compiled))?;
+    }
+    Ok(files)
+}
+
+/// `dotdipper archive <path>`
+pub fn archive(config_path: &Path, path: &str) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let path = normalize(path, &home)?;
+    let manifest_path = crate::paths::manifest_file()?;
+    if !manifest_path.exists() {
+        bail!("No snapshot found. Run 'dotdipper snapshot' first");
+    }
+    let mut config = crate::cfg::load(config_path)?;
+    let mut manifest = Manifest::load(&manifest_path)?;
+
+    let matcher = PathMatcher::for_paths(&home, &[path.as_str()])?;
+    let files = archive_in(
+        &crate::paths::compiled_dir()?,
+        &mut manifest,
+        &matcher,
+        &path,
+    )?;
+    manifest.save(&manifest_path)?;
+
+    config
+        .general
+        .tracked_files
+        .retain(|file| !matcher.is_match(file));
+    crate::cfg::save(config_path, &config)?;
+
+    ui::success(&format!("Archived {} file(s) under {}", files.len(), path));
+    ui::hint(&format!(
+        "The files in $HOME were left as they are. Run 'dotdipper unarchive {}' to track them again",
+        path
+    ));
+    Ok(())
+}
+
+/// `dotdipper unarchive <path>`
+pub fn unarchive(config_path: &Path, path: &str) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let path = normalize(path, &home)?;
+    let manifest_path = crate::paths::manifest_file()?;
+    let mut config = crate::cfg::load(config_path)?;
+    let mut manifest = if manifest_path.exists() {
+        Manifest::load(&manifest_path)?
+    } else {
+        Manifest::new()
+    };
+
+    let matcher = PathMatcher::for_paths(&home, &[path.as_str()])?;
+    let files = unarchive_in(
+        &crate::paths::compiled_dir()?,
+        &mut manifest,
+        &matcher,
+        &path,
+    )?;
+    manifest.save(&manifest_path)?;
+
+    for rel in &files {
+        let file = home.join(rel);
+        if !config.general.tracked_files.contains(&file) {
+            config.general.tracked_files.push(file);
+        }
+    }
+    crate::cfg::save(config_path, &config)?;
+
+    ui::success(&format!(
+        "Unarchived {} file(s) under {}",
+        files.len(),
+        path
+    ));
+    ui::hint("Run 'dotdipper apply' to lay them down again");
+    Ok(())
+}
+
+/// Archived files, as `~/...`
+pub fn list() -> Result<Vec<String>> {
+    let index = load_index(&crate::paths::compiled_dir()?)?;
+    let mut files: Vec<String> = index.files.keys().map(|rel| display_tilde(rel)).collect();
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::FileHash;
+
+    fn entry(path: &str) -> FileHash {
+        FileHash {
+            path: PathBuf::from(path),
+            hash: format!("hash-of-{}", path),
+            size: 1,
+            mode: 0o644,
+            modified: chrono::Utc::now(),
+            xattrs: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_archive_and_unarchive_round_trip() {
+        let temp = tempfile::tempdir().unwrap();
+        let compiled = temp.path();
+        let matcher = PathMatcher::for_paths(compiled, &["~/.config/alacritty"]).unwrap();
+        let mut manifest = Manifest::new();
+        for rel in [
+            ".zshrc",
+            ".config/alacritty/alacritty.toml",
+            ".config/alacritty/themes/dark.toml",
+        ] {
+            fs::create_dir_all(compiled.join(rel).parent().unwrap()).unwrap();
+            fs::write(compiled.join(rel), rel).unwrap();
+            manifest.add_file(entry(rel));
+        }
+
+        let archived =
+            archive_in(compiled, &mut manifest, &matcher, "~/.config/alacritty").unwrap();
+        assert_eq!(archived.len(), 2);
+        assert_eq!(manifest.files.len(), 1);
+        assert!(!compiled.join(".config").exists());
+        assert_eq!(
+            fs::read_to_string(compiled.join("archive/.config/alacritty/themes/dark.toml"))
+                .unwrap(),
+            ".config/alacritty/themes/dark.toml"
+        );
+        assert!(archive_in(compiled, &mut manifest, &matcher, "~/.config/alacritty").is_err());
+
+        let restored =
+            unarchive_in(compiled, &mut manifest, &matcher, "~/.config/alacritty").unwrap();
+        assert_eq!(restored, archived);
+        assert_eq!(
+            manifest
+                .get_file(Path::new(".config/alacritty/alacritty.toml"))
+                .unwrap()
+                .hash,
+            "hash-of-.config/alacritty/alacritty.toml"
+        );
+        assert!(compiled.join(".config/alacritty/alacritty.toml").exists());
+        assert!(!compiled.join(ARCHIVE_DIR).exists());
+        assert!(unarchive_in(
+            compiled,
+            &mut manifest,
+            &PathMatcher::for_paths(compiled, &["~/.zshrc"]).unwrap(),
+            "~/.zshrc"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,258 @@
+//! Extended attributes, ACLs and immutable flags.
+//!
+//! Copy-mode apply writes a fresh file, which silently drops extended
+//! attributes and POSIX ACLs (stored as `system.posix_acl_*` xattrs on Linux),
+//! and fails outright on targets marked immutable (`chattr +i`,
+//! `chflags uchg`). With `[attributes] preserve_xattrs = true` xattrs are
+//! carried from the tracked file into the compiled copy on snapshot, recorded
+//! in the manifest, and put back onto the target on apply. Immutable targets are skipped unless
+//! `unlock_immutable` is set, in which case the flag is cleared for the write
+//! and set again afterwards.
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cfg::{AttributesConfig, Config};
+use crate::hash::Manifest;
+
+/// Extended attributes that hold POSIX ACLs on Linux
+const ACL_XATTRS: &[&str] = &["system.posix_acl_access", "system.posix_acl_default"];
+
+/// One extended attribute: name and raw value
+pub type Xattr = (OsString, Vec<u8>);
+
+pub fn attributes_config(config: &Config) -> AttributesConfig {
+    config.attributes.clone().unwrap_or_default()
+}
+
+/// Extended attributes of a file (not following symlinks). Empty when the
+/// platform or filesystem has none.
+pub fn read_xattrs(path: &Path) -> Vec<Xattr> {
+    if !xattr::SUPPORTED_PLATFORM || path.is_symlink() {
+        return Vec::new();
+    }
+    let Ok(names) = xattr::list(path) else {
+        return Vec::new();
+    };
+    names
+        .filter_map(|name| match xattr::get(path, &name) {
+            Ok(Some(value)) => Some((name, value)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Set extended attributes on `path`, returning a message for each one that
+/// could not be written (unsupported filesystem, missing privileges, ...).
+pub fn write_xattrs(path: &Path, xattrs: &[Xattr]) -> Vec<String> {
+    xattrs
+        .iter()
+        .filter_map(|(name, value)| {
+            xattr::set(path, name, value)
+                .err()
+                .map(|e| format!("{}: {}", name.to_string_lossy(), e))
+        })
+        .collect()
+}
+
+/// Attributes as the manifest records them: values in base64, by name.
+/// Names that aren't UTF-8 are left out.
+pub fn to_manifest(xattrs: &[Xattr]) -> BTreeMap<String, String> {
+    xattrs
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_str()?.to_string(), STANDARD.encode(value))))
+        .collect()
+}
+
+/// Attributes back from [`to_manifest`], skipping values that don't decode
+pub fn from_manifest(recorded: &BTreeMap<String, String>) -> Vec<Xattr> {
+    recorded
+        .iter()
+        .filter_map(|(name, value)| Some((OsString::from(name), STANDARD.decode(value).ok()?)))
+        .collect()
+}
+
+/// True if any of the attributes is a POSIX ACL
+pub fn has_acl(xattrs: &[Xattr]) -> bool {
+    xattrs
+        .iter()
+        .any(|(name, _)| ACL_XATTRS.iter().any(|acl| name == acl))
+}
+
+/// Short description like "ACL, 2 xattr(s)"
+pub fn describe(xattrs: &[Xattr]) -> String {
+    let acls = xattrs
+        .iter()
+        .filter(|(name, _)| ACL_XATTRS.iter().any(|acl| name == acl))
+        .count();
+    let others = xattrs.len() - acls;
+    match (acls > 0, others) {
+        (true, 0) => "ACL".to_string(),
+        (true, n) => format!("ACL, {} xattr(s)", n),
+        (false, n) => format!("{} xattr(s)", n),
+    }
+}
+
+/// Parse `lsattr -d` output: the flags column contains `i` for immutable
+pub fn parse_lsattr(output: &str) -> bool {
+    output
+        .split_whitespace()
+        .next()
+        .is_some_and(|flags| flags.contains('i'))
+}
+
+/// Parse `ls -ldO` output (BSD/macOS): the flags column lists `uchg`/`schg`
+pub fn parse_ls_flags(output: &str) -> bool {
+    output
+        .split_whitespace()
+        .flat_map(|field| field.split(','))
+        .any(|flag| flag == "uchg" || flag == "schg")
+}
+
+/// Whether a file carries the immutable flag. Symlinks and files whose flags
+/// cannot be read count as mutable.
+pub fn is_immutable(path: &Path) -> bool {
+    if path.is_symlink() || !path.exists() {
+        return false;
+    }
+
+    if cfg!(target_os = "linux") {
+        Command::new("lsattr")
+            .arg("-d")
+            .arg(path)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .is_some_and(|o| parse_lsattr(&String::from_utf8_lossy(&o.stdout)))
+    } else if cfg!(target_os = "macos") {
+        Command::new("ls")
+            .arg("-ldO")
+            .arg(path)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .is_some_and(|o| parse_ls_flags(&String::from_utf8_lossy(&o.stdout)))
+    } else {
+        false
+    }
+}
+
+/// Set or clear the immutable flag. Usually needs root (Linux) or the file's
+/// owner (macOS `uchg`).
+pub fn set_immutable(path: &Path, immutable: bool) -> Result<()> {
+    let mut cmd = if cfg!(target_os = "macos") {
+        let mut cmd = Command::new("chflags");
+        cmd.arg(if immutable { "uchg" } else { "nouchg" });
+        cmd
+    } else {
+        let mut cmd = Command::new("chattr");
+        cmd.arg(if immutable { "+i" } else { "-i" });
+        cmd
+    };
+
+    let output = cmd
+        .arg(path)
+        .output()
+        .context("Failed to change the immutable flag")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// Copy attributes from a tracked file into its compiled copy (snapshot step).
+///
+/// Returns messages for attributes that could not be preserved.
+pub fn preserve_on_snapshot(config: &Config, source: &Path, dest: &Path) -> Vec<String> {
+    if !attributes_config(config).preserve_xattrs {
+        return Vec::new();
+    }
+    write_xattrs(dest, &read_xattrs(source))
+}
+
+/// Targets that apply would write, from the tracked files and the manifest
+fn apply_targets(config: &Config) -> Result<Vec<PathBuf>> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let mut targets = config.general.tracked_files.clone();
+
+    let manifest_path = crate::paths::manifest_file()?;
+    if manifest_path.exists() {
+        let manifest = Manifest::load(&manifest_path)?;
+        targets.extend(manifest.files.keys().map(|rel| home.join(rel)));
+    }
+
+    targets.sort();
+    targets.dedup();
+    Ok(targets)
+}
+
+/// Doctor check: immutable targets would make apply skip (or fail on) them
+pub fn check_immutable_targets(config: &Config) -> Result<()> {
+    let immutable: Vec<String> = apply_targets(config)?
+        .iter()
+        .filter(|p| is_immutable(p))
+        .map(|p| p.display().to_string())
+        .collect();
+
+    if immutable.is_empty() {
+        return Ok(());
+    }
+
+    if attributes_config(config).unlock_immutable {
+        bail!(
+            "{} immutable target(s) will be unlocked and relocked by apply (needs privileges): {}",
+            immutable.len(),
+            immutable.join(", ")
+        )
+    }
+    bail!(
+        "{} immutable target(s) will be skipped by apply: {}. Clear the flag (chattr -i / chflags nouchg) or set [attributes] unlock_immutable = true",
+        immutable.len(),
+        immutable.join(", ")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_flag_parsing_and_xattr_round_trip() {
+        assert!(parse_lsattr("----i---------e------- /home/me/.zshrc\n"));
+        assert!(!parse_lsattr("--------------e------- /home/me/.zshrc\n"));
+        assert!(parse_ls_flags(
+            "-rw-r--r--  1 me  staff  uchg,hidden 12 Jan  1 10:00 /Users/me/.zshrc\n"
+        ));
+        assert!(!parse_ls_flags(
+            "-rw-r--r--  1 me  staff  - 12 Jan  1 10:00 /Users/me/.zshrc\n"
+        ));
+
+        let acl = vec![(OsString::from("system.posix_acl_access"), vec![2, 0])];
+        assert!(has_acl(&acl));
+        assert_eq!(describe(&acl), "ACL");
+        assert_eq!(from_manifest(&to_manifest(&acl)), acl);
+
+        // user.* xattrs are not supported everywhere (e.g. some tmpfs setups)
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let dest = temp_dir.path().join("dest");
+        std::fs::write(&source, "a").unwrap();
+        std::fs::write(&dest, "a").unwrap();
+        if xattr::set(&source, "user.dotdipper", b"kept").is_err() {
+            return;
+        }
+        let xattrs = read_xattrs(&source);
+        assert!(!has_acl(&xattrs));
+        assert!(write_xattrs(&dest, &xattrs).is_empty());
+        assert_eq!(
+            xattr::get(&dest, "user.dotdipper").unwrap(),
+            Some(b"kept".to_vec())
+        );
+    }
+}
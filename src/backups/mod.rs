@@ -0,0 +1,480 @@
+//! Backups apply makes of the files it replaces.
+//!
+//! Every backup is recorded in `backups.json` with the file it was made of,
+//! so `dotdipper backups list|restore|prune` can manage them. Backups are
+//! `<file>.bak.<timestamp>` next to the original unless `[backups] relocate
+//! = true`, which keeps them under `backups/files/` in the dotdipper
+//! directory: `home/<path relative to $HOME>` or `root/<absolute path>`.
+//! Backups left by older versions next to tracked files are listed too.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+use crate::hash::Manifest;
+use crate::matching::{display_tilde, expand_tilde, to_home_relative};
+use crate::ui;
+
+const STAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Backup {
+    /// The file the backup was made of
+    #[serde(with = "crate::hash::raw_path")]
+    pub target: PathBuf,
+    /// Where the backup is
+    #[serde(with = "crate::hash::raw_path")]
+    pub path: PathBuf,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Registry {
+    #[serde(default)]
+    backups: Vec<Backup>,
+}
+
+fn load_registry() -> Result<Registry> {
+    let path = crate::paths::backups_file()?;
+    if !path.exists() {
+        return Ok(Registry::default());
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_registry(registry: &Registry) -> Result<()> {
+    let path = crate::paths::backups_file()?;
+    if registry.backups.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(registry)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn relocate(cfg: &Config) -> bool {
+    cfg.backups.as_ref().is_some_and(|b| b.relocate)
+}
+
+/// Where a backup of `target` made now goes: `<target>.bak.<timestamp>`,
+/// next to it or under `backups/files/`. A second backup within the same second
+/// gets a `-1`, `-2`, ... suffix.
+pub fn path_for(cfg: &Config, target: &Path) -> Result<PathBuf> {
+    let stamp = format!(".bak.{}", Utc::now().format(STAMP_FORMAT));
+    let base = if relocate(cfg) {
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let rel = to_home_relative(target, &home);
+        let dir = crate::paths::backups_dir()?;
+        match rel.strip_prefix("/") {
+            Ok(outside) => dir.join("root").join(outside),
+            Err(_) => dir.join("home").join(rel),
+        }
+        .into_os_string()
+    } else {
+        target.as_os_str().to_owned()
+    };
+    let with_suffix = |suffix: String| {
+        let mut path = base.clone();
+        path.push(suffix);
+        PathBuf::from(path)
+    };
+    let mut backup = with_suffix(stamp.clone());
+    let mut n = 0;
+    while backup.exists() || backup.is_symlink() {
+        n += 1;
+        backup = with_suffix(format!("{}-{}", stamp, n));
+    }
+    if let Some(parent) = backup.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(backup)
+}
+
+/// Record a backup of `target` made at `path`
+pub fn register(target: &Path, path: &Path) -> Result<()> {
+    let mut registry = load_registry()?;
+    registry.backups.retain(|b| b.path != path);
+    registry.backups.push(Backup {
+        target: target.to_path_buf(),
+        path: path.to_path_buf(),
+        created_at: Utc::now(),
+    });
+    save_registry(&registry)
+}
+
+/// Forget the backup at `path`, once it has been restored or deleted
+pub fn forget(path: &Path) -> Result<()> {
+    let mut registry = load_registry()?;
+    let before = registry.backups.len();
+    registry.backups.retain(|b| b.path != path);
+    if registry.backups.len() != before {
+        save_registry(&registry)?;
+    }
+    Ok(())
+}
+
+/// The file a `<file>.bak.<timestamp>` backup was made of, and when
+fn parse_backup_name(path: &Path) -> Option<(PathBuf, DateTime<Utc>)> {
+    let name = path.file_name()?.to_string_lossy().into_owned();
+    let (original, stamp) = name.rsplit_once(".bak.")?;
+    if original.is_empty() {
+        return None;
+    }
+    let created_at = NaiveDateTime::parse_from_str(stamp, STAMP_FORMAT)
+        .ok()
+        .and_then(|t| Local.from_local_datetime(&t).single())
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|| {
+            fs::symlink_metadata(path)
+                .and_then(|m| m.modified())
+                .ok()
+                .map(DateTime::<Utc>::from)
+        })?;
+    Some((path.with_file_name(original), created_at))
+}
+
+/// Unregistered `<file>.bak.<timestamp>` backups next to managed files
+fn unregistered(config: &Config, home: &Path) -> Result<Vec<Backup>> {
+    let manifest_path = crate::paths::manifest_file()?;
+    let mut targets: Vec<PathBuf> = config.general.tracked_files.clone();
+    if manifest_path.exists() {
+        let manifest = Manifest::load(&manifest_path)?;
+        targets.extend(manifest.files.keys().map(|rel| home.join(rel)));
+    }
+    targets.sort();
+    targets.dedup();
+
+    let mut found = Vec::new();
+    for target in targets {
+        let (Some(parent), Some(name)) = (target.parent(), target.file_name()) else {
+            continue;
+        };
+        let prefix = format!("{}.bak.", name.to_string_lossy());
+        let Ok(entries) = fs::read_dir(parent) else {
+            continue;
+        };
+        for entry in entries.filter_map(|e| e.ok()) {
+            if !entry.file_name().to_string_lossy().starts_with(&prefix) {
+                continue;
+            }
+            if let Some((_, created_at)) = parse_backup_name(&entry.path()) {
+                found.push(Backup {
+                    target: target.clone(),
+                    path: entry.path(),
+                    created_at,
+                });
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Every backup that still exists, newest first
+pub fn list(config: &Config) -> Result<Vec<Backup>> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let mut backups: Vec<Backup> = load_registry()?
+        .backups
+        .into_iter()
+        .filter(|b| b.path.exists() || b.path.is_symlink())
+        .collect();
+    for backup in unregistered(config, &home)? {
+        if !backups.iter().any(|b| b.path == backup.path) {
+            backups.push(backup);
+        }
+    }
+    backups.sort_by(|a, b| {
+        b.created_at
+            .cmp(&a.created_at)
+            .then_with(|| a.target.cmp(&b.target))
+    });
+    Ok(backups)
+}
+
+fn display(path: &Path, home: &Path) -> String {
+    if path.starts_with(home) {
+        display_tilde(&to_home_relative(path, home))
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// `dotdipper backups list`
+pub fn print_list(config: &Config) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let backups = list(config)?;
+    if ui::is_json() {
+        let entries: Vec<serde_json::Value> = backups
+            .iter()
+            .map(|b| {
+                serde_json::json!({
+                    "target": crate::hash::raw_path::encode(&b.target),
+                    "path": crate::hash::raw_path::encode(&b.path),
+                    "created_at": b.created_at,
+                    "size": crate::usage::dir_size(&b.path),
+                })
+            })
+            .collect();
+        return ui::print_json(&entries);
+    }
+    if backups.is_empty() {
+        ui::info("No backups");
+        return Ok(());
+    }
+
+    let rows = backups
+        .iter()
+        .map(|b| {
+            vec![
+                b.created_at
+                    .with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                display(&b.target, &home),
+                display(&b.path, &home),
+                crate::usage::format_size(crate::usage::dir_size(&b.path)),
+            ]
+        })
+        .collect();
+    ui::print_table(&["Created", "File", "Backup", "Size"], rows);
+    let total: u64 = backups
+        .iter()
+        .map(|b| crate::usage::dir_size(&b.path))
+        .sum();
+    ui::info(&format!(
+        "{} backup(s), {}",
+        backups.len(),
+        crate::usage::format_size(total)
+    ));
+    Ok(())
+}
+
+fn remove(path: &Path) -> Result<()> {
+    if path.is_dir() && !path.is_symlink() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// `dotdipper backups restore <file|backup>`: put the newest backup of a
+/// file (or the given backup) back in place. The current file is backed up
+/// in turn, so a restore can itself be undone.
+pub fn restore(config: &Config, selector: &str, force: bool) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let path = expand_tilde(selector, &home);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let backups = list(config)?;
+    let backup = backups
+        .iter()
+        .find(|b| b.path == path)
+        .or_else(|| backups.iter().find(|b| b.target == path))
+        .with_context(|| format!("No backups of {}", selector))?;
+
+    if !force
+        && !ui::prompt_confirm(
+            &format!(
+                "Restore {} from {}?",
+                display(&backup.target, &home),
+                display(&backup.path, &home)
+            ),
+            false,
+        )
+    {
+        ui::info("Restore cancelled");
+        return Ok(());
+    }
+
+    let target = &backup.target;
+    if target.is_symlink() {
+        fs::remove_file(target)?;
+    } else if target.exists() {
+        let current = path_for(config, target)?;
+        fs::rename(target, &current)
+            .with_context(|| format!("Failed to back up {}", target.display()))?;
+        register(target, &current)?;
+        ui::info(&format!("Backed up to {}", current.display()));
+    } else if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(&backup.path, target).with_context(|| {
+        format!(
+            "Failed to restore {} from {}",
+            target.display(),
+            backup.path.display()
+        )
+    })?;
+    forget(&backup.path)?;
+
+    ui::success(&format!(
+        "Restored {} from {}",
+        display(target, &home),
+        display(&backup.path, &home)
+    ));
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PruneOpts {
+    /// Keep the N newest backups of each file
+    pub keep_count: Option<usize>,
+    /// Keep backups newer than this (e.g. "30d")
+    pub keep_age: Option<String>,
+    pub dry_run: bool,
+}
+
+/// Backups that match none of the criteria, given newest first
+fn to_prune(
+    backups: &[Backup],
+    keep_count: Option<usize>,
+    cutoff: Option<DateTime<Utc>>,
+) -> Vec<&Backup> {
+    let mut seen: std::collections::HashMap<&Path, usize> = std::collections::HashMap::new();
+    backups
+        .iter()
+        .filter(|b| {
+            let rank = seen.entry(b.target.as_path()).or_default();
+            let by_count = keep_count.is_some_and(|n| *rank < n);
+            *rank += 1;
+            let by_age = cutoff.is_some_and(|c| b.created_at >= c);
+            !(by_count || by_age)
+        })
+        .collect()
+}
+
+/// `dotdipper backups prune`
+pub fn prune(config: &Config, opts: &PruneOpts) -> Result<()> {
+    if opts.keep_count.is_none() && opts.keep_age.is_none() {
+        bail!("Pass --keep-age and/or --keep-count to choose the backups to keep");
+    }
+    let cutoff = match &opts.keep_age {
+        Some(age) => Some(
+            Utc::now()
+                - crate::snapshots::parse_duration(age).with_context(|| {
+                    format!("Invalid age '{}' (expected e.g. 30d, 2w, 1m)", age)
+                })?,
+        ),
+        None => None,
+    };
+
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let backups = list(config)?;
+    let doomed = to_prune(&backups, opts.keep_count, cutoff);
+    if doomed.is_empty() {
+        ui::info("No backups to prune based on criteria");
+        return Ok(());
+    }
+
+    ui::section("Backups to delete:");
+    for backup in &doomed {
+        println!("  {}", display(&backup.path, &home));
+    }
+    let freed: u64 = doomed.iter().map(|b| crate::usage::dir_size(&b.path)).sum();
+    if opts.dry_run {
+        ui::info(&format!(
+            "Would delete {} backup(s) and free {} (dry run)",
+            doomed.len(),
+            crate::usage::format_size(freed)
+        ));
+        return Ok(());
+    }
+
+    for backup in &doomed {
+        remove(&backup.path)
+            .with_context(|| format!("Failed to delete {}", backup.path.display()))?;
+    }
+    let mut registry = load_registry()?;
+    registry
+        .backups
+        .retain(|b| !doomed.iter().any(|d| d.path == b.path));
+    save_registry(&registry)?;
+
+    ui::success(&format!(
+        "Deleted {} backup(s), freed {}",
+        doomed.len(),
+        crate::usage::format_size(freed)
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn backup(target: &str, days_ago: i64) -> Backup {
+        Backup {
+            target: PathBuf::from(target),
+            path: PathBuf::from(format!("{}.bak.{}", target, days_ago)),
+            created_at: Utc::now() - Duration::days(days_ago),
+        }
+    }
+
+    #[test]
+    fn test_prune_keeps_recent_and_newest_per_file() {
+        // Newest first, as `list` returns them
+        let backups = vec![
+            backup("/h/.zshrc", 1),
+            backup("/h/.zshrc", 40),
+            backup("/h/.vimrc", 45),
+            backup("/h/.zshrc", 50),
+            backup("/h/.vimrc", 60),
+        ];
+        let cutoff = Some(Utc::now() - Duration::days(30));
+        let pruned = |keep_count, cutoff| -> Vec<PathBuf> {
+            to_prune(&backups, keep_count, cutoff)
+                .iter()
+                .map(|b| b.path.clone())
+                .collect()
+        };
+
+        assert_eq!(
+            pruned(None, cutoff),
+            vec![
+                PathBuf::from("/h/.zshrc.bak.40"),
+                PathBuf::from("/h/.vimrc.bak.45"),
+                PathBuf::from("/h/.zshrc.bak.50"),
+                PathBuf::from("/h/.vimrc.bak.60"),
+            ]
+        );
+        // Keeping the newest of each file as well spares the .vimrc one
+        assert_eq!(
+            pruned(Some(1), cutoff),
+            vec![
+                PathBuf::from("/h/.zshrc.bak.40"),
+                PathBuf::from("/h/.zshrc.bak.50"),
+                PathBuf::from("/h/.vimrc.bak.60"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_backup_name() {
+        let (target, created_at) =
+            parse_backup_name(Path::new("/h/.config/app.toml.bak.20240102-030405")).unwrap();
+        assert_eq!(target, PathBuf::from("/h/.config/app.toml"));
+        assert_eq!(
+            created_at
+                .with_timezone(&Local)
+                .format(STAMP_FORMAT)
+                .to_string(),
+            "20240102-030405"
+        );
+        assert!(parse_backup_name(Path::new("/h/.bak.20240102-030405")).is_none());
+        assert!(parse_backup_name(Path::new("/h/.zshrc")).is_none());
+    }
+}
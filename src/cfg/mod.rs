@@ -4,9 +4,21 @@ use std::collections::BTreeMap;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::OnceLock;
+
+mod overrides;
+mod tilde_paths;
+mod validate;
+pub use overrides::{overrides, parse_assignment, Override, SET_ENV};
+pub use validate::{check, Problem, Severity};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
+    /// Extra TOML fragments merged into this file at load time (globs allowed,
+    /// relative to the including file)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
     #[serde(default)]
     pub general: GeneralConfig,
 
@@ -25,6 +37,10 @@ pub struct Config {
     #[serde(default)]
     pub files: BTreeMap<String, FileOverride>,
 
+    // Directories snapshotted and applied as a unit (e.g. "~/.config/nvim")
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tracked_dirs: BTreeMap<String, TrackedDir>,
+
     #[serde(default)]
     pub push_ignore: Vec<String>,
 
@@ -32,6 +48,10 @@ pub struct Config {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub secrets: Option<SecretsConfig>,
 
+    // In-memory identity agent for secrets
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secrets_agent: Option<SecretsAgentConfig>,
+
     // Hooks configuration
     #[serde(skip_serializing_if = "Option::is_none")]
     pub hooks: Option<HooksConfig>,
@@ -44,15 +64,75 @@ pub struct Config {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub auto_prune: Option<AutoPruneConfig>,
 
+    // Where snapshots are stored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshots: Option<SnapshotsConfig>,
+
     // Remote configuration (future milestone)
     #[serde(skip_serializing_if = "Option::is_none")]
     pub remote: Option<RemoteConfig>,
 
+    // Network/removable mount handling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mounts: Option<MountsConfig>,
+
+    // Output level and theme
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ui: Option<UiConfig>,
+
+    // Retries and offline queue for push/pull
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkConfig>,
+
+    // macOS LaunchAgents and login items
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub macos: Option<MacosConfig>,
+
+    // Extended attributes, ACLs and immutable flags
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<AttributesConfig>,
+
+    // Where apply keeps the backups of files it replaces
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backups: Option<BackupsConfig>,
+
+    // Known host-specific/volatile files to keep out of discover and snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub volatile: Option<VolatileConfig>,
+
+    // Size and binary-file limits for discover and snapshot
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limits: Option<LimitsConfig>,
+
+    // Extra dotfile collections (e.g. a team baseline), below the main one in precedence
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collections: Vec<CollectionConfig>,
+
+    // Host groups that files, packages and hooks can target
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, HostGroup>,
+
+    // Named subsets of the tracked files for `apply --subset` (e.g. "minimal")
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub subsets: BTreeMap<String, Vec<String>>,
+
+    // Variables for files rendered as templates (e.g. `email = "me@example.com"`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, toml::Value>,
+
     // Legacy field for compatibility
     #[serde(skip_serializing_if = "Option::is_none")]
     pub dotfiles: Option<DotfilesConfig>,
+
+    // `[files]` keys compiled on the first override lookup
+    #[serde(skip)]
+    pub file_matchers: FileMatchers,
 }
 
+/// Compiled `[files]` keys, filled by the first [`Config::file_override`]
+#[derive(Debug, Clone, Default)]
+pub struct FileMatchers(OnceLock<Vec<(String, Option<crate::matching::PathMatcher>)>>);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GeneralConfig {
     #[serde(default = "default_mode")]
@@ -61,11 +141,27 @@ pub struct GeneralConfig {
     #[serde(default = "default_backup")]
     pub backup: bool,
 
-    #[serde(default)]
+    /// Absolute once loaded; written with `~/` for files under `$HOME`
+    #[serde(default, with = "tilde_paths")]
     pub tracked_files: Vec<PathBuf>,
 
+    /// Create symlinks relative to the target's directory so they survive a
+    /// moved home directory or a different user name
+    #[serde(default = "default_true")]
+    pub relative_symlinks: bool,
+
     #[serde(skip_serializing_if = "Option::is_none")]
     pub active_profile: Option<String>,
+
+    /// Only hash files whose size or modification time changed since the
+    /// last snapshot, and only copy files whose hash changed
+    #[serde(default = "default_true")]
+    pub incremental_snapshots: bool,
+
+    /// Warn when dotdipper's state (snapshots, cache, backups, ...) grows past
+    /// this size, e.g. "5GB"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_state_size: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
@@ -73,9 +169,13 @@ pub struct GeneralConfig {
 pub enum RestoreMode {
     Symlink,
     Copy,
+    /// Link each `[tracked_dirs]` directory as a whole, like GNU stow, and
+    /// other files one by one
+    #[serde(rename = "symlink_dir")]
+    SymlinkDir,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct FileOverride {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub mode: Option<RestoreMode>,
@@ -85,9 +185,74 @@ pub struct FileOverride {
 
     #[serde(default)]
     pub local_only: bool,
+
+    /// High-blast-radius file: apply asks before overwriting it, even with
+    /// `--force`, unless `--yes-protected` is given
+    #[serde(default)]
+    pub protected: bool,
+
+    /// Only snapshot and apply this file on matching hosts (group names,
+    /// `tag=value` or hostname globs)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub only_hosts: Vec<String>,
+
+    /// Only on hosts whose name matches this glob, e.g. "work-laptop"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+
+    /// Only on this OS ("macos", "linux", "windows", ...) or OS family
+    /// ("unix")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+
+    /// Only while this profile is active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+
+    /// Render the compiled file as a template with this machine's variables
+    /// on apply. The template is edited in the compiled directory; snapshot
+    /// doesn't overwrite it with the rendered file from `$HOME`.
+    #[serde(default)]
+    pub template: bool,
+
+    /// Permissions apply gives the file every time, e.g. "0600", instead of
+    /// the ones it was snapshotted with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chmod: Option<String>,
+
+    /// Owner apply gives the file (a user name or uid; usually needs root)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+
+    /// Group apply gives the file (a group name or gid)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl FileOverride {
+    /// Whether apply enforces permissions or ownership on the file
+    pub fn sets_perms(&self) -> bool {
+        self.chmod.is_some() || self.owner.is_some() || self.group.is_some()
+    }
 }
 
 // Legacy config for migration
+/// A directory tracked as a whole. Its files are found when snapshotting, so
+/// new files are picked up without `discover`, and in symlink mode apply
+/// links the directory itself.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TrackedDir {
+    /// Only track files matching these patterns (gitignore-style, relative to
+    /// the directory); everything when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Leave out files matching these patterns (gitignore-style, relative to
+    /// the directory)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DotfilesConfig {
     #[serde(default = "default_repo_path")]
@@ -106,6 +271,57 @@ pub struct GitHubConfig {
     pub repo_name: Option<String>,
     #[serde(default = "default_private")]
     pub private: bool,
+
+    /// Remote to sync with instead of the GitHub repository, e.g. a GitLab,
+    /// Gitea or self-hosted URL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_url: Option<String>,
+
+    /// Branch to push and pull (default: main)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+
+    /// How to reach GitHub when remote_url is unset
+    #[serde(default, skip_serializing_if = "GitProtocol::is_default")]
+    pub protocol: GitProtocol,
+
+    /// Sign dotdipper's commits (unset: follow git's `commit.gpgsign`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sign: Option<bool>,
+
+    /// GPG key id, or SSH key path, to sign with (unset: `user.signingkey`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_key: Option<String>,
+
+    /// Signature format (unset: git's `gpg.format`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing_format: Option<SigningFormat>,
+
+    /// Message of commits pushed without `-m`; `{hostname}`, `{profile}`,
+    /// `{files}` and `{date}` are filled in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit_template: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GitProtocol {
+    #[default]
+    Ssh,
+    Https,
+}
+
+impl GitProtocol {
+    fn is_default(&self) -> bool {
+        *self == GitProtocol::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SigningFormat {
+    Gpg,
+    Ssh,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -124,6 +340,44 @@ pub struct PackagesConfig {
 
     #[serde(default)]
     pub arch: Vec<String>,
+
+    /// Windows packages: winget IDs, or Scoop names for `windows-scoop`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub windows: Vec<String>,
+
+    /// Tools installed with `cargo install`, e.g. `starship`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cargo: Vec<String>,
+
+    /// Global npm packages (`npm install -g`), e.g. language servers
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub npm_global: Vec<String>,
+
+    /// Python applications installed with `pipx install`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pipx: Vec<String>,
+
+    /// Ruby gems installed with `gem install --user-install`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub gem: Vec<String>,
+
+    /// Extra packages for hosts matching a selector, e.g. `"role=server" = ["nginx"]`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, Vec<String>>,
+
+    /// Binary -> package overrides, e.g. `docker = "docker-ce"`.
+    /// `[packages.map.<os>]` tables only apply to that OS.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub map: BTreeMap<String, PackageMapEntry>,
+}
+
+/// A `[packages.map]` entry: a package for every OS, or a table of
+/// overrides for one OS
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PackageMapEntry {
+    Package(String),
+    Os(BTreeMap<String, String>),
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -135,21 +389,166 @@ pub struct SecretsConfig {
     /// Path to key file (e.g., "~/.config/age/keys.txt")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub key_path: Option<String>,
+
+    /// age backend: "native" (built in, the default) or "cli" (the `age` binary)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+
+    /// Files stored encrypted in the compiled directory and decrypted on
+    /// apply, e.g. `["~/.aws/credentials"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tracked: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SecretsAgentConfig {
+    /// Start the agent on demand when decrypting or encrypting
+    #[serde(default)]
+    pub enabled: bool,
+
+    /// Seconds without requests before the agent exits (default 900)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct HooksConfig {
     #[serde(default)]
-    pub pre_apply: Vec<String>,
+    pub pre_apply: Vec<Hook>,
 
     #[serde(default)]
-    pub post_apply: Vec<String>,
+    pub post_apply: Vec<Hook>,
 
     #[serde(default)]
-    pub pre_snapshot: Vec<String>,
+    pub pre_snapshot: Vec<Hook>,
+
+    #[serde(default)]
+    pub post_snapshot: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_pull: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_pull: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_push: Vec<Hook>,
+
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_push: Vec<Hook>,
+
+    /// Hooks run after an apply that wrote files matching their pattern,
+    /// e.g. `"~/.config/kitty/**" = "kill -SIGUSR1 $(pidof kitty)"`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub on_change: BTreeMap<String, Hook>,
+
+    /// Extra hooks for hosts matching a selector, run after the ones above
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub hosts: BTreeMap<String, HooksConfig>,
+
+    /// Restrictions for running hooks and install scripts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sandbox: Option<SandboxConfig>,
+}
+
+/// A hook command: either a plain string or
+/// `{ run = "...", trusted = true, continue_on_error = true, timeout_secs = 10 }`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Hook {
+    Command(String),
+    Detailed {
+        run: String,
+        /// Run even when the stored dotfiles were just pulled and not yet reviewed
+        #[serde(default)]
+        trusted: bool,
+        /// Warn instead of aborting when the hook fails or times out
+        #[serde(default)]
+        continue_on_error: bool,
+        /// Kill the hook after this many seconds, instead of the sandbox timeout
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        timeout_secs: Option<u64>,
+    },
+}
+
+impl Hook {
+    pub fn command(&self) -> &str {
+        match self {
+            Hook::Command(run) | Hook::Detailed { run, .. } => run,
+        }
+    }
+
+    pub fn trusted(&self) -> bool {
+        matches!(self, Hook::Detailed { trusted: true, .. })
+    }
+
+    pub fn continue_on_error(&self) -> bool {
+        matches!(
+            self,
+            Hook::Detailed {
+                continue_on_error: true,
+                ..
+            }
+        )
+    }
+
+    pub fn timeout_secs(&self) -> Option<u64> {
+        match self {
+            Hook::Command(_) => None,
+            Hook::Detailed { timeout_secs, .. } => *timeout_secs,
+        }
+    }
+}
+
+impl From<&str> for Hook {
+    fn from(run: &str) -> Self {
+        Hook::Command(run.to_string())
+    }
+}
+
+impl std::fmt::Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.command())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SandboxConfig {
+    /// Environment variables passed through; everything else is dropped
+    #[serde(default = "default_env_allowlist")]
+    pub env_allowlist: Vec<String>,
+
+    /// Directory hooks run in (default: $HOME)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_dir: Option<String>,
+
+    /// Kill a hook that runs longer than this
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
 
+    /// Run without network access (Linux, via `unshare`)
     #[serde(default)]
-    pub post_snapshot: Vec<String>,
+    pub no_network: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            env_allowlist: default_env_allowlist(),
+            working_dir: None,
+            timeout_secs: None,
+            no_network: false,
+        }
+    }
+}
+
+fn default_env_allowlist() -> Vec<String> {
+    [
+        "HOME", "PATH", "USER", "LOGNAME", "SHELL", "LANG", "LC_ALL", "TERM", "TMPDIR",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -163,6 +562,16 @@ pub struct DaemonConfig {
 
     #[serde(default = "default_debounce_ms")]
     pub debounce_ms: u64,
+
+    /// Push after snapshotting: "github" (the GitHub repo, as `dotdipper push`)
+    /// or "remote" (the cloud remote, as `dotdipper remote push`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_push: Option<String>,
+
+    /// Minimum minutes between auto-pushes; snapshots taken in between are
+    /// pushed together once the interval is up. 0 pushes after every snapshot.
+    #[serde(default)]
+    pub push_interval_minutes: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -178,12 +587,25 @@ pub struct AutoPruneConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_age: Option<String>,
 
-    /// Keep snapshots until total size is under this limit (e.g., "1GB", "500MB")
+    /// Keep the newest snapshots that fit in this much disk space (e.g., "1GB", "500MB")
     #[serde(skip_serializing_if = "Option::is_none")]
     pub keep_size: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SnapshotsConfig {
+    /// Existing directory to keep snapshots in (e.g. a NAS mount). Snapshots
+    /// fall back to the local store while it is unreachable.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+
+    /// Object store that every snapshot is also uploaded to, as
+    /// `snapshot_<id>.tar.zst`. Must not share a location with `[remote]`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<RemoteConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct RemoteConfig {
     /// Kind: "github", "s3", "gcs", "webdav"
     pub kind: String,
@@ -199,24 +621,285 @@ pub struct RemoteConfig {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub endpoint: Option<String>,
+
+    /// HTTP(S) proxy for remote transfers and git (e.g. "http://proxy:3128")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+
+    /// Maximum upload rate (e.g. "2MB/s")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload_limit: Option<String>,
+
+    /// Maximum download rate (e.g. "10MB/s")
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub download_limit: Option<String>,
+
+    /// age-encrypt bundles with the `[secrets]` key before uploading them
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypt: bool,
+
+    /// Mirror the compiled tree file by file instead of uploading bundles,
+    /// copying only what changed (LocalFS only)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub delta: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountsConfig {
+    /// Skip paths on NFS/SMB/sshfs and other network filesystems
+    #[serde(default = "default_true")]
+    pub skip_network: bool,
+
+    /// Skip paths on USB drives, SD cards and other removable media
+    #[serde(default = "default_true")]
+    pub skip_removable: bool,
+
+    /// Mount points that are always allowed (e.g. "~/nas")
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UiConfig {
+    /// Default output level: "quiet", "normal", "verbose" or "porcelain"
+    /// (`--output json` is only available as a flag)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<crate::ui::OutputLevel>,
+
+    /// Theme: "default", "plain" (no colors) or "ascii"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<crate::ui::Theme>,
+
+    /// How file diffs are shown: "git" (default), the built-in "unified" or
+    /// "side-by-side", or a command given the old and new file, like "delta"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff_tool: Option<String>,
+
+    /// Pager each file diff is shown in on a terminal, like "less -R"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pager: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    /// Extra attempts after a transient network failure
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+
+    /// Delay before the first retry; doubles on each attempt
+    #[serde(default = "default_backoff_ms")]
+    pub backoff_ms: u64,
+
+    /// Queue pushes that fail while offline and send them later
+    #[serde(default = "default_true")]
+    pub offline_queue: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MacosConfig {
+    /// Capture and restore ~/Library/LaunchAgents/*.plist
+    #[serde(default)]
+    pub launch_agents: bool,
+
+    /// Run `launchctl load` for launch agents that apply changed
+    #[serde(default = "default_true")]
+    pub load_agents: bool,
+
+    /// Capture and restore login items
+    #[serde(default)]
+    pub login_items: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AttributesConfig {
+    /// Carry extended attributes and POSIX ACLs through snapshot and copy-mode apply
+    #[serde(default)]
+    pub preserve_xattrs: bool,
+
+    /// Temporarily clear the immutable flag (chattr +i / chflags uchg) to
+    /// replace a target, then set it again
+    #[serde(default)]
+    pub unlock_immutable: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CollectionConfig {
+    /// Name used on the command line and for `collections/<name>/`
+    pub name: String,
+
+    /// Files snapshotted into this collection
+    #[serde(default)]
+    pub tracked_files: Vec<PathBuf>,
+
+    /// Git URL the collection is pushed to and pulled from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HostGroup {
+    /// Hostname globs of the group's members, e.g. "web-*"
+    #[serde(default)]
+    pub patterns: Vec<String>,
+
+    /// Tags given to members, e.g. `role = "server"`
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+
+    /// Template variables for members, overriding `[variables]`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, toml::Value>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VolatileAction {
+    /// Leave volatile files out of discover and snapshot
+    #[default]
+    Exclude,
+    /// Keep them, but warn
+    Warn,
+    /// Don't check for volatile files
+    Off,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BackupsConfig {
+    /// Keep backups under `backups/` in the dotdipper directory instead of
+    /// next to the files they were made of
+    #[serde(default)]
+    pub relocate: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VolatileConfig {
+    /// What to do with files in the volatile files knowledge base
+    #[serde(default)]
+    pub action: VolatileAction,
+
+    /// Extra volatile patterns, mapped to the reason shown when they are skipped
+    #[serde(default)]
+    pub extra: BTreeMap<String, String>,
+
+    /// Paths or patterns to sync even though the knowledge base lists them
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LimitsConfig {
+    /// Largest file discover and snapshot accept, e.g. "5MB" ("0" for no limit)
+    #[serde(default = "default_max_file_size")]
+    pub max_file_size: String,
+
+    /// Leave files that look binary (contain NUL bytes) out as well
+    #[serde(default)]
+    pub skip_binaries: bool,
+
+    /// Paths or patterns to sync regardless of the limits
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            max_file_size: default_max_file_size(),
+            skip_binaries: false,
+            allow: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Look up the `[files]` override that applies to a home-relative path.
+    ///
+    /// Keys are matched with the shared [`crate::matching`] semantics, so both
+    /// exact keys (`"~/.ssh/config"`) and patterns (`"~/.config/nvim/**"`) work.
+    /// An exact key wins; otherwise the longest matching key is used.
+    pub fn file_override(&self, rel_path: &Path) -> Option<&FileOverride> {
+        if let Some(exact) = self.files.get(&crate::matching::display_tilde(rel_path)) {
+            return Some(exact);
+        }
+
+        let home = dirs::home_dir()?;
+        let compile = || {
+            self.files
+                .keys()
+                .map(|key| {
+                    let matcher = crate::matching::PathMatcher::for_paths(&home, &[key.as_str()]);
+                    (key.clone(), matcher.ok())
+                })
+                .collect::<Vec<_>>()
+        };
+        // `files` is public, so recompile rather than trust a cache built
+        // before an edit
+        let fresh;
+        let matchers = match self.file_matchers.0.get_or_init(compile) {
+            cached if cached.iter().map(|(key, _)| key).eq(self.files.keys()) => cached,
+            _ => {
+                fresh = compile();
+                &fresh
+            }
+        };
+        matchers
+            .iter()
+            .filter(|(_, m)| m.as_ref().is_some_and(|m| m.is_match(rel_path)))
+            .max_by_key(|(key, _)| key.len())
+            .and_then(|(key, _)| self.files.get(key))
+    }
+
+    /// Paths of a `[subsets]` entry, for filtering apply
+    pub fn subset(&self, name: &str) -> Result<&[String]> {
+        match self.subsets.get(name) {
+            Some(paths) if paths.is_empty() => anyhow::bail!("Subset '{}' lists no paths", name),
+            Some(paths) => Ok(paths),
+            None if self.subsets.is_empty() => anyhow::bail!(
+                "Unknown subset '{}': no [subsets] are defined in the config",
+                name
+            ),
+            None => anyhow::bail!(
+                "Unknown subset '{}'. Available: {}",
+                name,
+                self.subsets.keys().cloned().collect::<Vec<_>>().join(", ")
+            ),
+        }
+    }
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
+            include: Vec::new(),
             general: GeneralConfig::default(),
             github: GitHubConfig::default(),
             packages: PackagesConfig::default(),
             exclude_patterns: default_exclude_patterns(),
             include_patterns: default_include_patterns(),
             files: BTreeMap::new(),
+            tracked_dirs: BTreeMap::new(),
             push_ignore: Vec::new(),
             secrets: None,
+            secrets_agent: None,
             hooks: None,
             daemon: None,
             auto_prune: None,
+            snapshots: None,
             remote: None,
+            mounts: None,
+            ui: None,
+            network: None,
+            macos: None,
+            attributes: None,
+            backups: None,
+            volatile: None,
+            limits: None,
+            collections: Vec::new(),
+            hosts: BTreeMap::new(),
+            subsets: BTreeMap::new(),
+            variables: BTreeMap::new(),
             dotfiles: None,
+            file_matchers: FileMatchers::default(),
         }
     }
 }
@@ -227,7 +910,40 @@ impl Default for GeneralConfig {
             default_mode: default_mode(),
             backup: default_backup(),
             tracked_files: Vec::new(),
+            relative_symlinks: true,
             active_profile: None,
+            incremental_snapshots: true,
+            max_state_size: None,
+        }
+    }
+}
+
+impl Default for MountsConfig {
+    fn default() -> Self {
+        MountsConfig {
+            skip_network: true,
+            skip_removable: true,
+            allow: Vec::new(),
+        }
+    }
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        NetworkConfig {
+            retries: default_retries(),
+            backoff_ms: default_backoff_ms(),
+            offline_queue: true,
+        }
+    }
+}
+
+impl Default for MacosConfig {
+    fn default() -> Self {
+        MacosConfig {
+            launch_agents: false,
+            load_agents: true,
+            login_items: false,
         }
     }
 }
@@ -248,6 +964,13 @@ impl Default for GitHubConfig {
             username: None,
             repo_name: None,
             private: default_private(),
+            remote_url: None,
+            branch: None,
+            protocol: GitProtocol::default(),
+            sign: None,
+            signing_key: None,
+            signing_format: None,
+            commit_template: None,
         }
     }
 }
@@ -266,6 +989,13 @@ impl Default for PackagesConfig {
             linux: vec![],
             ubuntu: vec![],
             arch: vec![],
+            windows: vec![],
+            cargo: vec![],
+            npm_global: vec![],
+            pipx: vec![],
+            gem: vec![],
+            hosts: BTreeMap::new(),
+            map: BTreeMap::new(),
         }
     }
 }
@@ -278,6 +1008,18 @@ fn default_symlink() -> bool {
     true
 }
 
+fn default_true() -> bool {
+    true
+}
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_backoff_ms() -> u64 {
+    1000
+}
+
 fn default_private() -> bool {
     true
 }
@@ -290,6 +1032,10 @@ fn default_backup() -> bool {
     true
 }
 
+fn default_max_file_size() -> String {
+    "10MB".to_string()
+}
+
 fn default_exclude_patterns() -> Vec<String> {
     vec![]
 }
@@ -300,6 +1046,8 @@ pub const DEFAULT_IGNORE_CONTENTS: &str = "\
 # Patterns prefixed with ~/ are anchored to $HOME.
 
 # --- Dotdipper internal (generated / runtime) ---
+~/.local/share/dotdipper/**
+~/.cache/dotdipper/**
 ~/.config/dotdipper/compiled/**
 ~/.config/dotdipper/cache/**
 ~/.config/dotdipper/install/**
@@ -425,19 +1173,15 @@ pub fn init(config_path: PathBuf, force: bool) -> Result<()> {
     fs::write(&config_path, toml_string).context("Failed to write config file")?;
 
     // Create required directories
-    let base_dir = crate::paths::base_dir()?;
-
-    fs::create_dir_all(base_dir.join("compiled")).context("Failed to create compiled directory")?;
-    fs::create_dir_all(base_dir.join("install")).context("Failed to create install directory")?;
-    fs::create_dir_all(base_dir.join("cache")).context("Failed to create cache directory")?;
+    let data_dir = crate::paths::data_dir()?;
 
-    // Create manifest directory
-    let manifest_dir = config_path
-        .parent()
-        .expect("Config path should have parent");
-    fs::create_dir_all(manifest_dir).context("Failed to create manifest directory")?;
+    fs::create_dir_all(data_dir.join("compiled")).context("Failed to create compiled directory")?;
+    fs::create_dir_all(data_dir.join("install")).context("Failed to create install directory")?;
+    fs::create_dir_all(crate::paths::cache_dir()?).context("Failed to create cache directory")?;
 
-    // Write default .dotdipperignore
+    // Write default .dotdipperignore, which stays in the config directory
+    // when --config points elsewhere
+    fs::create_dir_all(crate::paths::config_dir()?).context("Failed to create config directory")?;
     let ignore_path = crate::paths::ignore_file()?;
     if !ignore_path.exists() || force {
         fs::write(&ignore_path, DEFAULT_IGNORE_CONTENTS)
@@ -447,6 +1191,8 @@ pub fn init(config_path: PathBuf, force: bool) -> Result<()> {
     Ok(())
 }
 
+/// Load the config: `config_path` with the other layers merged in (see
+/// [`layers`])
 pub fn load(config_path: &Path) -> Result<Config> {
     if !config_path.exists() {
         anyhow::bail!(
@@ -455,20 +1201,479 @@ pub fn load(config_path: &Path) -> Result<Config> {
         );
     }
 
-    let contents = fs::read_to_string(config_path).context("Failed to read config file")?;
-    let mut config: Config = toml::from_str(&contents).context("Failed to parse config file")?;
+    let value = load_layers(config_path, None)?;
+    from_value(value).map_err(|e| located(config_path, e))
+}
+
+/// `error` from loading `config_path`, replaced by the first problem
+/// [`check`] finds when that says which file and line it is on
+fn located(config_path: &Path, error: anyhow::Error) -> anyhow::Error {
+    match check(config_path).map(|problems| problems.into_iter().find(Problem::is_error)) {
+        Ok(Some(problem)) => anyhow::anyhow!("Failed to parse config file {}", problem),
+        _ => error,
+    }
+}
+
+/// Load the config as it is while `profile` is active, whichever profile
+/// actually is
+pub fn load_for_profile(config_path: &Path, profile: &str) -> Result<Config> {
+    if !config_path.exists() {
+        anyhow::bail!(
+            "Config not found at {}. Run 'dotdipper init' first.",
+            config_path.display()
+        );
+    }
+
+    from_value(load_layers(config_path, Some(profile))?)
+}
+
+/// Where a layer of the config comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layer {
+    /// Shared by every user of the machine
+    System,
+    /// The config dotdipper was given, which commands write to
+    User,
+    /// The active profile's `config.toml`
+    Profile(String),
+    /// `config.<hostname>.toml` next to the user config
+    Host(String),
+}
+
+impl std::fmt::Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Layer::System => write!(f, "system"),
+            Layer::User => write!(f, "user"),
+            Layer::Profile(name) => write!(f, "profile {}", name),
+            Layer::Host(host) => write!(f, "host {}", host),
+        }
+    }
+}
+
+/// The files the config is merged from, lowest precedence first: the
+/// system config, `config_path`, and next to it the profile's
+/// `profiles/<name>/config.toml` and this host's `config.<hostname>.toml`.
+/// Only `config_path` is listed when missing.
+///
+/// Each layer is merged over the ones before it like an `include` fragment
+/// under its including file: tables key by key, arrays concatenated without
+/// duplicates, and other values replaced. The active profile is the one
+/// `DOTDIPPER_PROFILE` names, or else the system and user configs'
+/// `active_profile`; the profile and host layers cannot change it.
+pub fn layers(config_path: &Path) -> Result<Vec<(Layer, PathBuf)>> {
+    layer_files(config_path, None)
+}
+
+fn layer_files(config_path: &Path, profile: Option<&str>) -> Result<Vec<(Layer, PathBuf)>> {
+    let mut layers = Vec::new();
+    let system = crate::paths::system_config_file();
+    let is_user = |path: &Path| match (path.canonicalize(), config_path.canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => path == config_path,
+    };
+    if system.is_file() && !is_user(&system) {
+        layers.push((Layer::System, system));
+    }
+    layers.push((Layer::User, config_path.to_path_buf()));
+
+    let profile = match profile {
+        Some(profile) => profile.to_string(),
+        None => active_profile(&layers)?,
+    };
+    let dir = config_path.parent().unwrap_or(Path::new("."));
+    let profile_config = dir.join("profiles").join(&profile).join("config.toml");
+    if profile_config.is_file() && !is_generated_default(&profile_config) {
+        layers.push((Layer::Profile(profile), profile_config));
+    }
+
+    let host = crate::hosts::local_hostname();
+    let host_config = dir.join(format!("config.{}.toml", host));
+    if host_config.is_file() && !is_user(&host_config) {
+        layers.push((Layer::Host(host), host_config));
+    }
+    Ok(layers)
+}
+
+/// The profile `DOTDIPPER_PROFILE` names, or the last `active_profile` set
+/// in `layers`
+fn active_profile(layers: &[(Layer, PathBuf)]) -> Result<String> {
+    if let Ok(name) = std::env::var(crate::paths::PROFILE_ENV) {
+        if !name.is_empty() {
+            return Ok(name);
+        }
+    }
+    let mut active = None;
+    for (_, path) in layers {
+        if !path.exists() {
+            continue;
+        }
+        let value = load_value(path, &mut Vec::new())?;
+        if let Some(name) = value
+            .get("general")
+            .and_then(|g| g.get("active_profile"))
+            .and_then(|p| p.as_str())
+        {
+            active = Some(name.to_string());
+        }
+    }
+    Ok(active.unwrap_or_else(|| "default".to_string()))
+}
+
+/// Whether `path` is a profile config as older versions generated it: every
+/// setting at its default, which would undo the user config when merged over it
+fn is_generated_default(path: &Path) -> bool {
+    let generated = toml::Value::try_from(Config::default()).ok();
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| toml::from_str::<toml::Value>(&contents).ok())
+        .is_some_and(|value| Some(value) == generated)
+}
+
+/// Merge the layers of the config, reading `profile`'s config instead of the
+/// active profile's if given
+fn load_layers(config_path: &Path, profile: Option<&str>) -> Result<toml::Value> {
+    let mut merged = toml::Value::Table(toml::map::Map::new());
+    for (layer, path) in layer_files(config_path, profile)? {
+        let mut value = load_value(&path, &mut Vec::new())?;
+        if layer != Layer::User {
+            strip_layer_keys(&layer, &mut value);
+        }
+        merge_values(&mut merged, value);
+    }
+    overrides::apply(&mut merged, &overrides()?)?;
+    Ok(merged)
+}
+
+/// Drop what a layer other than the user config may not set: its own
+/// `include` list, which [`load_value`] has already resolved, and below the
+/// user config, which profile is active
+fn strip_layer_keys(layer: &Layer, value: &mut toml::Value) {
+    let Some(table) = value.as_table_mut() else {
+        return;
+    };
+    table.remove("include");
+    if matches!(layer, Layer::Profile(_) | Layer::Host(_)) {
+        if let Some(general) = table.get_mut("general").and_then(|g| g.as_table_mut()) {
+            general.remove("active_profile");
+        }
+    }
+}
+
+fn from_value(value: toml::Value) -> Result<Config> {
+    let mut config: Config = value.try_into().context("Failed to parse config file")?;
 
     // Migrate from legacy dotfiles config if present
     if let Some(dotfiles) = &config.dotfiles {
         config.general.tracked_files = dotfiles.tracked_files.clone();
         // Note: we keep the dotfiles section for backward compatibility but use general.tracked_files
     }
+    // Layers may name the same file with `~/` and as an absolute path
+    let mut seen = std::collections::HashSet::new();
+    config
+        .general
+        .tracked_files
+        .retain(|path| seen.insert(path.clone()));
 
     Ok(config)
 }
 
+/// Read a config file and merge its `include` fragments into it.
+///
+/// Fragments are merged in the listed order (glob matches sorted by path), and
+/// the including file is applied last so its own values win. Tables merge key
+/// by key, arrays are concatenated without duplicates, so an array entry can
+/// only be removed in the file it comes from (see `save`).
+fn load_value(path: &Path, stack: &mut Vec<PathBuf>) -> Result<toml::Value> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    if stack.contains(&canonical) {
+        let chain: Vec<String> = stack
+            .iter()
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        anyhow::bail!("Config include cycle: {}", chain.join(" -> "));
+    }
+
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file {}", path.display()))?;
+    let own: toml::Value = toml::from_str(&contents)
+        .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+
+    let includes = include_paths(path, &own)?;
+    if includes.is_empty() {
+        return Ok(own);
+    }
+
+    stack.push(canonical);
+    let mut merged = toml::Value::Table(toml::map::Map::new());
+    for include in includes {
+        let mut fragment = load_value(&include, stack)?;
+        if let toml::Value::Table(table) = &mut fragment {
+            table.remove("include");
+        }
+        merge_values(&mut merged, fragment);
+    }
+    stack.pop();
+
+    merge_values(&mut merged, own);
+    Ok(merged)
+}
+
+/// Every file of every layer of the config, including the fragments each
+/// one includes, lowest precedence first
+pub fn config_files(config_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for (_, layer) in layers(config_path)? {
+        for path in with_includes(&layer)?.into_iter().rev() {
+            if !files.contains(&path) {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// `config_path` and every fragment it includes, directly or through another
+/// fragment
+fn with_includes(config_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![config_path.to_path_buf()];
+    let mut next = 0;
+    while next < files.len() {
+        let path = files[next].clone();
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        let own: toml::Value = toml::from_str(&contents)
+            .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+        for include in include_paths(&path, &own)? {
+            if !files.contains(&include) {
+                files.push(include);
+            }
+        }
+        next += 1;
+    }
+    Ok(files)
+}
+
+/// Resolve the `include` entries of a parsed config file to concrete paths.
+fn include_paths(path: &Path, value: &toml::Value) -> Result<Vec<PathBuf>> {
+    let Some(entries) = value.get("include") else {
+        return Ok(Vec::new());
+    };
+    let entries = entries
+        .as_array()
+        .with_context(|| format!("`include` in {} must be an array", path.display()))?;
+    let base = path.parent().unwrap_or(Path::new("."));
+
+    let mut paths = Vec::new();
+    for entry in entries {
+        let entry = entry
+            .as_str()
+            .with_context(|| format!("`include` entries in {} must be strings", path.display()))?;
+        let expanded = PathBuf::from(shellexpand::tilde(entry).to_string());
+        let full = if expanded.is_absolute() {
+            expanded
+        } else {
+            base.join(expanded)
+        };
+
+        if crate::matching::contains_glob_chars(entry) {
+            let pattern = full.to_string_lossy().to_string();
+            let mut matches: Vec<PathBuf> = glob::glob(&pattern)
+                .with_context(|| format!("Invalid include pattern: {}", entry))?
+                .filter_map(|p| p.ok())
+                .filter(|p| p.is_file())
+                .collect();
+            matches.sort();
+            paths.extend(matches);
+        } else if full.exists() {
+            paths.push(full);
+        } else {
+            anyhow::bail!(
+                "Included config {} (from {}) does not exist",
+                full.display(),
+                path.display()
+            );
+        }
+    }
+
+    Ok(paths)
+}
+
+fn merge_values(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (toml::Value::Array(base), toml::Value::Array(overlay)) => {
+            for value in overlay {
+                if !base.contains(&value) {
+                    base.push(value);
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Write the changes between `before` and `after` into `own` (the including
+/// file's raw contents), leaving values that come from fragments out of it.
+/// Array entries removed in `after` that come from a fragment can't be
+/// removed from `own`; they are added to `dropped` with their key path.
+fn apply_changes(
+    own: &mut toml::Value,
+    before: &toml::Value,
+    after: &toml::Value,
+    included: Option<&toml::Value>,
+    key_path: &[String],
+    dropped: &mut Vec<(Vec<String>, toml::Value)>,
+) {
+    let (Some(own), Some(after_table)) = (own.as_table_mut(), after.as_table()) else {
+        return;
+    };
+    let empty = toml::map::Map::new();
+    let before_table = before.as_table().unwrap_or(&empty);
+
+    for (key, new_value) in after_table {
+        let old_value = before_table.get(key);
+        if old_value == Some(new_value) {
+            continue;
+        }
+        let inc = included.and_then(|i| i.get(key));
+        let path = [key_path, std::slice::from_ref(key)].concat();
+
+        match new_value {
+            toml::Value::Table(_) => {
+                let entry = own
+                    .entry(key.clone())
+                    .or_insert_with(|| toml::Value::Table(toml::map::Map::new()));
+                if !entry.is_table() {
+                    *entry = toml::Value::Table(toml::map::Map::new());
+                }
+                let fallback = toml::Value::Table(toml::map::Map::new());
+                apply_changes(
+                    entry,
+                    old_value.unwrap_or(&fallback),
+                    new_value,
+                    inc,
+                    &path,
+                    dropped,
+                );
+            }
+            toml::Value::Array(items) => {
+                let inc_items = inc.and_then(|i| i.as_array());
+                let old_items = old_value.and_then(|v| v.as_array());
+                for item in old_items.into_iter().flatten() {
+                    if !items.contains(item) && inc_items.is_some_and(|i| i.contains(item)) {
+                        dropped.push((path.clone(), item.clone()));
+                    }
+                }
+                let own_items: Vec<toml::Value> = items
+                    .iter()
+                    .filter(|item| !inc_items.is_some_and(|i| i.contains(item)))
+                    .cloned()
+                    .collect();
+                own.insert(key.clone(), toml::Value::Array(own_items));
+            }
+            _ => {
+                own.insert(key.clone(), new_value.clone());
+            }
+        }
+    }
+
+    for key in before_table.keys() {
+        if !after_table.contains_key(key) {
+            own.remove(key);
+        }
+    }
+}
+
 pub fn save(config_path: &Path, config: &Config) -> Result<()> {
-    let toml_string = toml::to_string_pretty(config).context("Failed to serialize config")?;
+    let mut value = toml::Value::try_from(config).context("Failed to serialize config")?;
+
+    // With includes, other layers or overrides, only write what changed into
+    // the main file so their values are not copied into it
+    let layers = match config_path.exists() {
+        true => layers(config_path)?,
+        false => Vec::new(),
+    };
+    if config_path.exists()
+        && (!config.include.is_empty() || layers.len() > 1 || !overrides()?.is_empty())
+    {
+        let contents = fs::read_to_string(config_path).context("Failed to read config file")?;
+        let mut own: toml::Value =
+            toml::from_str(&contents).context("Failed to parse config file")?;
+        let before = toml::Value::try_from(load(config_path)?)?;
+
+        let canonical = config_path.canonicalize()?;
+        let mut sources = Vec::new();
+        for include in include_paths(config_path, &own)? {
+            let mut fragment = load_value(&include, &mut vec![canonical.clone()])?;
+            if let toml::Value::Table(table) = &mut fragment {
+                table.remove("include");
+            }
+            sources.push((include, fragment));
+        }
+        for (layer, path) in layers.into_iter().filter(|(l, _)| *l != Layer::User) {
+            let mut value = load_value(&path, &mut Vec::new())?;
+            strip_layer_keys(&layer, &mut value);
+            sources.push((path, value));
+        }
+        let mut included = toml::Value::Table(toml::map::Map::new());
+        for (_, source) in &mut sources {
+            if let Some(tracked) = source
+                .get_mut("general")
+                .and_then(|g| g.get_mut("tracked_files"))
+            {
+                tilde_paths::normalize(tracked);
+            }
+            merge_values(&mut included, source.clone());
+        }
+
+        let mut dropped = Vec::new();
+        apply_changes(
+            &mut own,
+            &before,
+            &value,
+            Some(&included),
+            &[],
+            &mut dropped,
+        );
+        if let Some((key_path, item)) = dropped.first() {
+            let holds = |value: &toml::Value| {
+                key_path
+                    .iter()
+                    .try_fold(value, |value, key| value.get(key))
+                    .and_then(|v| v.as_array())
+                    .is_some_and(|items| items.contains(item))
+            };
+            let from = sources
+                .iter()
+                .find(|(_, source)| holds(source))
+                .map_or_else(
+                    || "another config file".to_string(),
+                    |(path, _)| path.display().to_string(),
+                );
+            anyhow::bail!(
+                "{} in `{}` comes from {}; remove it there",
+                item,
+                key_path.join("."),
+                from
+            );
+        }
+        value = own;
+    }
+
+    let toml_string = toml::to_string_pretty(&value).context("Failed to serialize config")?;
     fs::write(config_path, toml_string).context("Failed to write config file")?;
     Ok(())
 }
@@ -510,24 +1715,12 @@ pub fn resolve_push_ignored_paths(config: &Config) -> Result<Vec<String>> {
     let mut ignored = Vec::new();
 
     for pattern in &config.push_ignore {
-        let expanded = if let Some(rest) = pattern.strip_prefix("~/") {
-            rest.to_string()
-        } else {
-            pattern.clone()
-        };
-        ignored.push(expanded);
+        ignored.push(strip_home_anchor(pattern, &home));
     }
 
     for (file_path, file_override) in &config.files {
         if file_override.local_only {
-            let expanded = if let Some(rest) = file_path.strip_prefix("~/") {
-                rest.to_string()
-            } else if let Ok(stripped) = PathBuf::from(file_path).strip_prefix(&home) {
-                stripped.to_string_lossy().to_string()
-            } else {
-                file_path.clone()
-            };
-            ignored.push(expanded);
+            ignored.push(strip_home_anchor(file_path, &home));
         }
     }
 
@@ -536,6 +1729,13 @@ pub fn resolve_push_ignored_paths(config: &Config) -> Result<Vec<String>> {
     Ok(ignored)
 }
 
+/// Turn a `~/`-anchored or absolute-under-$HOME pattern into a home-relative one.
+fn strip_home_anchor(pattern: &str, home: &Path) -> String {
+    crate::matching::to_home_relative(Path::new(pattern), home)
+        .to_string_lossy()
+        .to_string()
+}
+
 pub fn add_push_ignore(config_path: &Path, pattern: &str) -> Result<()> {
     let mut config = load(config_path)?;
     let pattern = pattern.trim();
@@ -581,11 +1781,40 @@ pub fn set_config_value(config_path: &Path, key: &str, value: &str) -> Result<()
                 .parse()
                 .context("Invalid boolean value. Use 'true' or 'false'")?
         }
+        "github.remote_url" => config.github.remote_url = Some(value.to_string()),
+        "github.branch" => config.github.branch = Some(value.to_string()),
+        "github.protocol" => {
+            config.github.protocol = match value {
+                "ssh" => GitProtocol::Ssh,
+                "https" => GitProtocol::Https,
+                _ => anyhow::bail!("Invalid protocol '{}'. Use 'ssh' or 'https'", value),
+            }
+        }
+        "github.sign" => {
+            config.github.sign = Some(
+                value
+                    .parse()
+                    .context("Invalid boolean value. Use 'true' or 'false'")?,
+            )
+        }
+        "github.signing_key" => config.github.signing_key = Some(value.to_string()),
+        "github.signing_format" => {
+            config.github.signing_format = Some(match value {
+                "gpg" => SigningFormat::Gpg,
+                "ssh" => SigningFormat::Ssh,
+                _ => anyhow::bail!("Invalid signing format '{}'. Use 'gpg' or 'ssh'", value),
+            })
+        }
+        "github.commit_template" => config.github.commit_template = Some(value.to_string()),
         "general.default_mode" => {
             config.general.default_mode = match value {
                 "symlink" => RestoreMode::Symlink,
                 "copy" => RestoreMode::Copy,
-                _ => anyhow::bail!("Invalid mode '{}'. Use 'symlink' or 'copy'", value),
+                "symlink_dir" => RestoreMode::SymlinkDir,
+                _ => anyhow::bail!(
+                    "Invalid mode '{}'. Use 'symlink', 'copy' or 'symlink_dir'",
+                    value
+                ),
             }
         }
         "general.backup" => {
@@ -593,10 +1822,50 @@ pub fn set_config_value(config_path: &Path, key: &str, value: &str) -> Result<()
                 .parse()
                 .context("Invalid boolean value. Use 'true' or 'false'")?
         }
+        "limits.max_file_size" => {
+            if crate::usage::parse_size(value).is_none() {
+                anyhow::bail!("Invalid size '{}'. Use a size like \"5MB\"", value);
+            }
+            config
+                .limits
+                .get_or_insert_with(Default::default)
+                .max_file_size = value.to_string()
+        }
+        "limits.skip_binaries" => {
+            config
+                .limits
+                .get_or_insert_with(Default::default)
+                .skip_binaries = value
+                .parse()
+                .context("Invalid boolean value. Use 'true' or 'false'")?
+        }
+        "remote.encrypt" => {
+            config
+                .remote
+                .as_mut()
+                .context("No remote configured. Run 'dotdipper remote set <kind>' first")?
+                .encrypt = value
+                .parse()
+                .context("Invalid boolean value. Use 'true' or 'false'")?
+        }
+        "remote.delta" => {
+            config
+                .remote
+                .as_mut()
+                .context("No remote configured. Run 'dotdipper remote set <kind>' first")?
+                .delta = value
+                .parse()
+                .context("Invalid boolean value. Use 'true' or 'false'")?
+        }
         _ => anyhow::bail!(
             "Unknown config key '{}'. Supported keys:\n  \
              github.username, github.repo_name, github.private,\n  \
-             general.default_mode, general.backup",
+             github.remote_url, github.branch, github.protocol,\n  \
+             github.sign, github.signing_key, github.signing_format,\n  \
+             github.commit_template,\n  \
+             general.default_mode, general.backup,\n  \
+             limits.max_file_size, limits.skip_binaries,\n  \
+             remote.encrypt, remote.delta",
             key
         ),
     }
@@ -604,3 +1873,112 @@ pub fn set_config_value(config_path: &Path, key: &str, value: &str) -> Result<()
     save(config_path, &config)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_file_override_prefers_exact_then_longest_key_and_sees_edits() {
+        let mut config = Config::default();
+        let protected = FileOverride {
+            protected: true,
+            ..Default::default()
+        };
+        config
+            .files
+            .insert("~/.config/nvim/**".to_string(), FileOverride::default());
+        config
+            .files
+            .insert("~/.config/nvim/lua/**".to_string(), protected.clone());
+
+        let init = Path::new(".config/nvim/init.lua");
+        let plugins = Path::new(".config/nvim/lua/plugins.lua");
+        assert!(!config.file_override(init).unwrap().protected);
+        assert!(config.file_override(plugins).unwrap().protected);
+        assert!(config.file_override(Path::new(".zshrc")).is_none());
+
+        // Keys added after the first lookup are not hidden by the cache
+        config.files.insert("~/.zshrc".to_string(), protected);
+        assert!(config.file_override(Path::new(".zshrc")).unwrap().protected);
+    }
+
+    #[test]
+    fn test_include_merges_fragments_and_save_keeps_them_separate() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::create_dir_all(dir.join("files.d")).unwrap();
+
+        let main = dir.join("config.toml");
+        fs::write(
+            &main,
+            r#"
+include = ["packages.toml", "files.d/*.toml"]
+
+[general]
+backup = false
+
+[packages]
+common = ["git"]
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("packages.toml"),
+            "[packages]\ncommon = [\"ripgrep\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("files.d/b.toml"),
+            "[files.\"~/.b\"]\nmode = \"copy\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("files.d/a.toml"),
+            "[general]\nbackup = true\n\n[files.\"~/.a\"]\nexclude = true\n",
+        )
+        .unwrap();
+
+        let config = load(&main).unwrap();
+        assert!(!config.general.backup, "main file wins over fragments");
+        assert_eq!(config.packages.common, vec!["ripgrep", "git"]);
+        assert!(config.files["~/.a"].exclude);
+        assert_eq!(config.files["~/.b"].mode, Some(RestoreMode::Copy));
+
+        let mut config = config;
+        config.push_ignore.push("~/.work".to_string());
+        save(&main, &config).unwrap();
+
+        let saved = fs::read_to_string(&main).unwrap();
+        assert!(!saved.contains("ripgrep"));
+        assert!(!saved.contains("~/.a"));
+        let reloaded = load(&main).unwrap();
+        assert_eq!(reloaded.packages.common, vec!["ripgrep", "git"]);
+        assert_eq!(reloaded.push_ignore, vec!["~/.work"]);
+        assert!(reloaded.files["~/.a"].exclude);
+
+        let mut config = reloaded;
+        config.packages.common.retain(|p| p != "ripgrep");
+        let err = save(&main, &config).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("packages.common"), "{}", message);
+        assert!(message.contains("packages.toml"), "{}", message);
+        assert!(load(&main)
+            .unwrap()
+            .packages
+            .common
+            .contains(&"ripgrep".to_string()));
+    }
+
+    #[test]
+    fn test_include_cycle_is_reported() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("config.toml"), "include = [\"a.toml\"]\n").unwrap();
+        fs::write(dir.join("a.toml"), "include = [\"config.toml\"]\n").unwrap();
+
+        let err = load(&dir.join("config.toml")).unwrap_err();
+        assert!(format!("{:#}", err).contains("include cycle"));
+    }
+}
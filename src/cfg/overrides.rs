@@ -0,0 +1,191 @@
+//! Config values overridden for a single invocation.
+//!
+//! `DOTDIPPER_<SECTION>__<KEY>=value` sets `<section>.<key>`, with `__`
+//! between each level and names lowercased; `DOTDIPPER__<KEY>` sets a
+//! top-level key. `dotdipper --set key=value` hands its overrides on in
+//! `DOTDIPPER_SET`, one per line, so commands dotdipper runs see them too.
+//!
+//! Overrides apply over every layer of the config, the environment's first
+//! and then `--set`'s, and replace a value instead of merging with it.
+//! Values are read as TOML (`true`, `5`, `["*.log"]`) when that fits the
+//! setting, and as strings otherwise.
+
+use anyhow::{bail, Result};
+
+use super::validate::{self, Seg};
+use super::Config;
+
+/// Environment variable `--set` passes its `key=value` overrides in
+pub const SET_ENV: &str = "DOTDIPPER_SET";
+
+/// One overridden value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Override {
+    /// Dotted key, e.g. `general.default_mode`
+    pub key: String,
+    pub value: String,
+    /// The environment variable it came from, or `--set`
+    pub source: String,
+}
+
+/// Overrides from the environment, sorted by variable name, then those from
+/// `--set` in the order given
+pub fn overrides() -> Result<Vec<Override>> {
+    let mut overrides: Vec<Override> = std::env::vars()
+        .filter_map(|(name, value)| {
+            Some(Override {
+                key: env_key(&name)?,
+                value,
+                source: name,
+            })
+        })
+        .collect();
+    overrides.sort_by(|a, b| a.source.cmp(&b.source));
+    if let Ok(set) = std::env::var(SET_ENV) {
+        for assignment in set.lines().filter(|l| !l.trim().is_empty()) {
+            overrides.push(parse_assignment(assignment, "--set")?);
+        }
+    }
+    Ok(overrides)
+}
+
+/// The dotted key a `DOTDIPPER_<SECTION>__<KEY>` variable overrides
+fn env_key(name: &str) -> Option<String> {
+    let rest = name.strip_prefix("DOTDIPPER")?;
+    if !rest.contains("__") {
+        return None;
+    }
+    let mut parts: Vec<&str> = rest.split("__").collect();
+    match parts[0].strip_prefix('_') {
+        Some(section) => parts[0] = section,
+        None if parts[0].is_empty() => {
+            parts.remove(0);
+        }
+        None => return None,
+    }
+    if parts.iter().any(|p| p.is_empty()) {
+        return None;
+    }
+    Some(parts.join(".").to_lowercase())
+}
+
+/// Parse a `key=value` override
+pub fn parse_assignment(assignment: &str, source: &str) -> Result<Override> {
+    let Some((key, value)) = assignment.split_once('=') else {
+        bail!(
+            "Invalid override '{}' from {}: expected key=value (e.g. general.default_mode=copy)",
+            assignment,
+            source
+        );
+    };
+    let key = key.trim();
+    if !validate::parse_key(key).is_some_and(|k| k.iter().all(|s| matches!(s, Seg::Key(_)))) {
+        bail!("Invalid config key `{}` from {}", key, source);
+    }
+    Ok(Override {
+        key: key.to_string(),
+        value: value.trim().to_string(),
+        source: source.to_string(),
+    })
+}
+
+/// The ways `value` can be read: as TOML if it parses, and as a string
+fn candidates(value: &str) -> Vec<toml::Value> {
+    let mut candidates = Vec::new();
+    if let Ok(mut table) = toml::from_str::<toml::Table>(&format!("value = {}", value)) {
+        candidates.extend(table.remove("value"));
+    }
+    candidates.push(toml::Value::String(value.to_string()));
+    candidates
+}
+
+/// Apply `overrides` to the merged layers of the config. A config that does
+/// not load without them is left for loading to report.
+pub(super) fn apply(merged: &mut toml::Value, overrides: &[Override]) -> Result<()> {
+    if overrides.is_empty() || merged.clone().try_into::<Config>().is_err() {
+        return Ok(());
+    }
+    for o in overrides {
+        let key = validate::parse_key(&o.key).unwrap_or_default();
+        if key.is_empty() || !validate::is_setting(merged, &key) {
+            let hint = validate::suggest(merged, &key)
+                .map(|s| format!("; did you mean `{}`?", s))
+                .unwrap_or_default();
+            bail!("Unknown config key `{}` from {}{}", o.key, o.source, hint);
+        }
+
+        let mut error = None;
+        for value in candidates(&o.value) {
+            let mut candidate = merged.clone();
+            validate::set(&mut candidate, &key, value);
+            match candidate.clone().try_into::<Config>() {
+                Ok(_) => {
+                    *merged = candidate;
+                    error = None;
+                    break;
+                }
+                Err(e) => error = error.or(Some(e)),
+            }
+        }
+        if let Some(e) = error {
+            bail!(
+                "Invalid value for `{}` from {}: {}",
+                o.key,
+                o.source,
+                e.message()
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overrides_replace_values_with_the_type_the_setting_takes() {
+        assert_eq!(
+            env_key("DOTDIPPER_GENERAL__DEFAULT_MODE").as_deref(),
+            Some("general.default_mode")
+        );
+        assert_eq!(
+            env_key("DOTDIPPER__EXCLUDE_PATTERNS").as_deref(),
+            Some("exclude_patterns")
+        );
+        assert_eq!(env_key("DOTDIPPER_HOME"), None);
+        assert_eq!(env_key("DOTDIPPERX__Y"), None);
+
+        let mut merged: toml::Value = toml::from_str(
+            "exclude_patterns = [\"*.log\"]\n[general]\ndefault_mode = \"symlink\"\n",
+        )
+        .unwrap();
+        let set = |assignment: &str| parse_assignment(assignment, "--set").unwrap();
+        apply(
+            &mut merged,
+            &[
+                set("general.default_mode = copy"),
+                set("exclude_patterns=[\"*.tmp\"]"),
+                set("github.repo_name=1234"),
+                set("limits.skip_binaries=true"),
+            ],
+        )
+        .unwrap();
+        let config: Config = merged.clone().try_into().unwrap();
+        assert_eq!(config.general.default_mode, super::super::RestoreMode::Copy);
+        assert_eq!(config.exclude_patterns, vec!["*.tmp"]);
+        assert_eq!(config.github.repo_name.as_deref(), Some("1234"));
+        assert!(config.limits.unwrap().skip_binaries);
+
+        let error = apply(&mut merged, &[set("general.default_mod=copy")]).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unknown config key `general.default_mod` from --set; did you mean `default_mode`?"
+        );
+        let error = apply(&mut merged, &[set("general.default_mode=cpy")]).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Invalid value for `general.default_mode` from --set: unknown variant"));
+        assert!(parse_assignment("general.default_mode", "--set").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+//! Serde helpers for lists of paths written with `~/`.
+//!
+//! The config is shared between machines with different home directories,
+//! so paths under `$HOME` are written as `~/...` and expanded when read.
+//! Everything else (files outside `$HOME`, or a missing home directory) is
+//! written as is.
+//!
+//! Use `#[serde(with = "tilde_paths")]` on a `Vec<PathBuf>` field.
+
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serializer};
+use std::path::{Path, PathBuf};
+
+use crate::matching::expand_tilde;
+
+/// `path` as written: `~/...` under `home`
+fn collapse(path: &Path, home: Option<&Path>) -> PathBuf {
+    match home.and_then(|h| path.strip_prefix(h).ok()) {
+        Some(rel) if !rel.as_os_str().is_empty() => Path::new("~").join(rel),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Rewrite the strings in a raw TOML array the way [`serialize`] writes
+/// them, so raw config files compare equal to a serialized config
+pub fn normalize(value: &mut toml::Value) {
+    let Some(items) = value.as_array_mut() else {
+        return;
+    };
+    let home = dirs::home_dir();
+    for item in items {
+        if let Some(path) = item.as_str() {
+            let path = match &home {
+                Some(home) => collapse(&expand_tilde(path, home), Some(home)),
+                None => PathBuf::from(path),
+            };
+            if let Some(path) = path.to_str() {
+                *item = toml::Value::String(path.to_string());
+            }
+        }
+    }
+}
+
+pub fn serialize<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+    let home = dirs::home_dir();
+    let mut written = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path = collapse(path, home.as_deref());
+        let path = path
+            .to_str()
+            .ok_or_else(|| S::Error::custom(format!("path is not valid UTF-8: {:?}", path)))?;
+        written.push(path.to_string());
+    }
+    serializer.collect_seq(written)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<PathBuf>, D::Error> {
+    let paths = Vec::<String>::deserialize(deserializer)?;
+    Ok(match dirs::home_dir() {
+        Some(home) => paths.iter().map(|p| expand_tilde(p, &home)).collect(),
+        None => paths.into_iter().map(PathBuf::from).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Serialize;
+
+    #[derive(Serialize, Deserialize)]
+    struct Files {
+        #[serde(with = "super")]
+        paths: Vec<PathBuf>,
+    }
+
+    #[test]
+    fn test_home_paths_round_trip_through_tilde() {
+        let home = dirs::home_dir().unwrap();
+        let files = Files {
+            paths: vec![home.join(".zshrc"), PathBuf::from("/etc/hosts")],
+        };
+
+        let written = toml::to_string(&files).unwrap();
+        assert!(written.contains("\"~/.zshrc\""), "{}", written);
+        assert!(written.contains("\"/etc/hosts\""), "{}", written);
+
+        let read: Files = toml::from_str(&written).unwrap();
+        assert_eq!(read.paths, files.paths);
+    }
+}
@@ -0,0 +1,670 @@
+//! Checks that go beyond deserializing the config.
+//!
+//! Serde skips keys it does not know, so a typo like `exclue_patterns`
+//! silently drops the setting. [`check`] reports such keys with the setting
+//! they are one edit away from, along with patterns that do not compile and
+//! settings that contradict each other, each with the file and line it
+//! comes from.
+
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::Config;
+use crate::matching::PathMatcher;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// The config cannot be used as written
+    Error,
+    /// The config loads, but probably not the way it was meant to
+    Warning,
+}
+
+/// Something wrong with the config, and where
+#[derive(Debug, Clone)]
+pub struct Problem {
+    pub severity: Severity,
+    pub file: PathBuf,
+    pub line: Option<usize>,
+    /// Dotted path of the offending key, e.g. `files."~/.zshrc".mode`;
+    /// empty when the file does not parse
+    pub key: String,
+    pub message: String,
+}
+
+impl Problem {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if !self.key.is_empty() {
+            write!(f, ": `{}`", self.key)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// One step of a key path: a table key or an index into an array of tables
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(super) enum Seg {
+    Key(String),
+    Index(usize),
+}
+
+fn key_path(keys: &[&str]) -> Vec<Seg> {
+    keys.iter().map(|k| Seg::Key(k.to_string())).collect()
+}
+
+pub(super) fn display_key(path: &[Seg]) -> String {
+    let mut shown = String::new();
+    for seg in path {
+        match seg {
+            Seg::Index(i) => shown.push_str(&format!("[{}]", i)),
+            Seg::Key(key) => {
+                if !shown.is_empty() {
+                    shown.push('.');
+                }
+                if !key.is_empty() && key.chars().all(is_bare_key_char) {
+                    shown.push_str(key);
+                } else {
+                    shown.push_str(&format!("{:?}", key));
+                }
+            }
+        }
+    }
+    shown
+}
+
+fn is_bare_key_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// A finding about the merged config, before it is traced to a file
+struct Finding {
+    severity: Severity,
+    key: Vec<Seg>,
+    message: String,
+}
+
+impl Finding {
+    fn error(key: Vec<Seg>, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            key,
+            message,
+        }
+    }
+
+    fn warning(key: Vec<Seg>, message: String) -> Self {
+        Self {
+            severity: Severity::Warning,
+            key,
+            message,
+        }
+    }
+}
+
+/// Check `config_path` and the fragments it includes. Files that do not
+/// parse, or do not deserialize into a config, are reported and stop the
+/// checks that need the merged config.
+pub fn check(config_path: &Path) -> Result<Vec<Problem>> {
+    let mut sources = Vec::new();
+    for path in super::config_files(config_path)? {
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        sources.push((path, contents));
+    }
+
+    let mut problems = Vec::new();
+    for (path, contents) in &sources {
+        let value = match parse(contents) {
+            Ok(value) => value,
+            Err(message) => {
+                problems.push(Problem {
+                    severity: Severity::Error,
+                    file: path.clone(),
+                    line: None,
+                    key: String::new(),
+                    message,
+                });
+                continue;
+            }
+        };
+        for (key, suggestion) in unknown_keys(&value) {
+            let mut message = "unknown key, ignored".to_string();
+            if let Some(suggestion) = suggestion {
+                message.push_str(&format!("; did you mean `{}`?", suggestion));
+            }
+            problems.push(Problem {
+                severity: Severity::Warning,
+                file: path.clone(),
+                line: locate(contents, &key).map(|(line, _)| line),
+                key: display_key(&key),
+                message,
+            });
+        }
+    }
+    if problems.iter().any(Problem::is_error) {
+        return Ok(problems);
+    }
+
+    let config = super::from_value(super::load_layers(config_path, None)?)?;
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let mut findings = invalid_patterns(&config, &home);
+    findings.extend(conflicts(&config, &home));
+    for finding in findings {
+        // Files are in merge order, so of the closest matches the one that
+        // wins the merge comes last
+        let (file, line) = sources
+            .iter()
+            .filter_map(|(path, contents)| Some((path, locate(contents, &finding.key)?)))
+            .max_by_key(|(_, (_, depth))| *depth)
+            .map(|(path, (line, _))| (path.clone(), Some(line)))
+            .unwrap_or_else(|| (config_path.to_path_buf(), None));
+        problems.push(Problem {
+            severity: finding.severity,
+            file,
+            line,
+            key: display_key(&finding.key),
+            message: finding.message,
+        });
+    }
+    Ok(problems)
+}
+
+/// Parse one config file on its own, with the line and column of the first
+/// problem if it does not parse
+fn parse(contents: &str) -> std::result::Result<toml::Value, String> {
+    let describe = |e: toml::de::Error| {
+        let report = e.to_string();
+        let location = report.lines().next().unwrap_or_default();
+        format!("{}: {}", location, e.message().trim_end())
+    };
+    let value: toml::Value = toml::from_str(contents).map_err(describe)?;
+    toml::from_str::<Config>(contents).map_err(describe)?;
+    Ok(value)
+}
+
+/// Keys in `value` that no setting reads, each with the setting it is one
+/// edit away from, if any
+fn unknown_keys(value: &toml::Value) -> Vec<(Vec<Seg>, Option<String>)> {
+    let Ok(config) = value.clone().try_into::<Config>() else {
+        return Vec::new();
+    };
+    let Ok(read) = toml::Value::try_from(&config) else {
+        return Vec::new();
+    };
+    // Settings left at their default are not serialized back, so a key
+    // missing from `read` is only unknown if it accepts any value
+    let mut unread = Vec::new();
+    collect_unread(value, &read, &mut Vec::new(), &mut unread);
+    unread
+        .into_iter()
+        .filter(|key| !is_setting(value, key))
+        .map(|key| {
+            let suggestion = suggest(value, &key);
+            (key, suggestion)
+        })
+        .collect()
+}
+
+fn collect_unread(
+    value: &toml::Value,
+    read: &toml::Value,
+    path: &mut Vec<Seg>,
+    unread: &mut Vec<Vec<Seg>>,
+) {
+    match (value, read) {
+        (toml::Value::Table(table), toml::Value::Table(read)) => {
+            for (key, value) in table {
+                path.push(Seg::Key(key.clone()));
+                match read.get(key) {
+                    Some(read) => collect_unread(value, read, path, unread),
+                    None => unread.push(path.clone()),
+                }
+                path.pop();
+            }
+        }
+        (toml::Value::Array(array), toml::Value::Array(read)) => {
+            for (i, (value, read)) in array.iter().zip(read).enumerate() {
+                path.push(Seg::Index(i));
+                collect_unread(value, read, path, unread);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether some setting reads `key`: every setting rejects either a number
+/// or a table, while an unknown key accepts both
+pub(super) fn is_setting(root: &toml::Value, key: &[Seg]) -> bool {
+    [
+        toml::Value::Integer(0),
+        toml::Value::Table(Default::default()),
+    ]
+    .into_iter()
+    .any(|probe| {
+        let mut probed = root.clone();
+        set(&mut probed, key, probe) && probed.try_into::<Config>().is_err()
+    })
+}
+
+/// Put `new` at `key`, creating missing tables on the way
+pub(super) fn set(root: &mut toml::Value, key: &[Seg], new: toml::Value) -> bool {
+    let Some((last, parents)) = key.split_last() else {
+        return false;
+    };
+    let mut value = root;
+    for seg in parents {
+        let next = match (seg, value) {
+            (Seg::Key(k), toml::Value::Table(table)) => Some(
+                table
+                    .entry(k.clone())
+                    .or_insert_with(|| toml::Value::Table(Default::default())),
+            ),
+            (Seg::Index(i), toml::Value::Array(array)) => array.get_mut(*i),
+            _ => None,
+        };
+        match next {
+            Some(next) => value = next,
+            None => return false,
+        }
+    }
+    match (last, value) {
+        (Seg::Key(k), toml::Value::Table(table)) => {
+            table.insert(k.clone(), new);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// A setting next to `key` whose name is one edit away from it
+pub(super) fn suggest(root: &toml::Value, key: &[Seg]) -> Option<String> {
+    let (Seg::Key(name), parent) = key.split_last()? else {
+        return None;
+    };
+    if name.len() < 3 {
+        return None;
+    }
+    one_edit_away(name).into_iter().find(|candidate| {
+        let mut candidate_key = parent.to_vec();
+        candidate_key.push(Seg::Key(candidate.clone()));
+        is_setting(root, &candidate_key)
+    })
+}
+
+/// Names one transposition, deletion, substitution or insertion away
+fn one_edit_away(name: &str) -> Vec<String> {
+    const ALPHABET: &str = "abcdefghijklmnopqrstuvwxyz_";
+    let chars: Vec<char> = name.chars().collect();
+    let edit = |at: usize, remove: usize, insert: &[char]| -> String {
+        let mut edited = chars[..at].to_vec();
+        edited.extend_from_slice(insert);
+        edited.extend_from_slice(&chars[at + remove..]);
+        edited.into_iter().collect()
+    };
+    let mut edits = Vec::new();
+    for i in 0..chars.len().saturating_sub(1) {
+        edits.push(edit(i, 2, &[chars[i + 1], chars[i]]));
+    }
+    for i in 0..chars.len() {
+        edits.push(edit(i, 1, &[]));
+    }
+    for (i, &old) in chars.iter().enumerate() {
+        for c in ALPHABET.chars().filter(|&c| c != old) {
+            edits.push(edit(i, 1, &[c]));
+        }
+    }
+    for i in 0..=chars.len() {
+        for c in ALPHABET.chars() {
+            edits.push(edit(i, 0, &[c]));
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    edits.retain(|e| e != name && seen.insert(e.clone()));
+    edits
+}
+
+/// The line `key` is set on in a TOML document, or else the line of the
+/// closest table or key above it, with how many segments of `key` matched
+fn locate(contents: &str, key: &[Seg]) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize)> = None;
+    let mut table: Vec<Seg> = Vec::new();
+    let mut array_counts: std::collections::HashMap<Vec<Seg>, usize> = Default::default();
+
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        let found = if let Some(header) = line.strip_prefix("[[") {
+            let Some(path) = header.split("]]").next().and_then(parse_key) else {
+                continue;
+            };
+            let count = array_counts.entry(path.clone()).or_insert(0);
+            table = path;
+            table.push(Seg::Index(*count));
+            *count += 1;
+            table.clone()
+        } else if let Some(header) = line.strip_prefix('[') {
+            let Some(path) = header.split(']').next().and_then(parse_key) else {
+                continue;
+            };
+            table = path;
+            table.clone()
+        } else if let Some(assigned) = assigned_key(line) {
+            let mut path = table.clone();
+            path.extend(assigned);
+            path
+        } else {
+            continue;
+        };
+
+        if found.len() <= key.len()
+            && key.starts_with(&found)
+            && best.is_none_or(|(_, depth)| found.len() > depth)
+        {
+            best = Some((n + 1, found.len()));
+        }
+    }
+    best
+}
+
+/// The key of a `key = value` line
+fn assigned_key(line: &str) -> Option<Vec<Seg>> {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, '#') => return None,
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if c == q => quote = None,
+            (None, '=') => return parse_key(&line[..i]),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split a dotted TOML key into its parts, unquoting quoted ones
+pub(super) fn parse_key(key: &str) -> Option<Vec<Seg>> {
+    let mut parts = Vec::new();
+    let mut rest = key.trim();
+    while !rest.is_empty() {
+        let part;
+        if let Some(quoted) = rest.strip_prefix('"').or_else(|| rest.strip_prefix('\'')) {
+            let end = quoted.find(&rest[..1])?;
+            part = quoted[..end].to_string();
+            rest = quoted[end + 1..].trim_start();
+        } else {
+            let end = rest
+                .find(|c: char| !is_bare_key_char(c))
+                .unwrap_or(rest.len());
+            if end == 0 {
+                return None;
+            }
+            part = rest[..end].to_string();
+            rest = rest[end..].trim_start();
+        }
+        parts.push(Seg::Key(part));
+        match rest.strip_prefix('.') {
+            Some(after) => rest = after.trim_start(),
+            None if rest.is_empty() => {}
+            None => return None,
+        }
+    }
+    (!parts.is_empty()).then_some(parts)
+}
+
+/// Patterns that do not compile, by the setting they are in
+fn invalid_patterns(config: &Config, home: &Path) -> Vec<Finding> {
+    let mut lists: Vec<(Vec<Seg>, &[String])> = vec![
+        (key_path(&["exclude_patterns"]), &config.exclude_patterns),
+        (key_path(&["include_patterns"]), &config.include_patterns),
+        (key_path(&["push_ignore"]), &config.push_ignore),
+    ];
+    if let Some(limits) = &config.limits {
+        lists.push((key_path(&["limits", "allow"]), &limits.allow));
+    }
+    if let Some(volatile) = &config.volatile {
+        lists.push((key_path(&["volatile", "allow"]), &volatile.allow));
+    }
+    for (dir, tracked) in &config.tracked_dirs {
+        lists.push((
+            key_path(&["tracked_dirs", dir, "include"]),
+            &tracked.include,
+        ));
+        lists.push((
+            key_path(&["tracked_dirs", dir, "exclude"]),
+            &tracked.exclude,
+        ));
+    }
+
+    let mut findings = Vec::new();
+    let invalid = |pattern: &str| {
+        PathMatcher::new(home, &[pattern])
+            .err()
+            .map(|e| e.root_cause().to_string())
+    };
+    for (key, patterns) in lists {
+        for pattern in patterns {
+            if let Some(reason) = invalid(pattern) {
+                findings.push(Finding::error(
+                    key.clone(),
+                    format!("invalid pattern `{}`: {}", pattern, reason),
+                ));
+            }
+        }
+    }
+    for pattern in config.files.keys() {
+        if let Some(reason) = invalid(pattern) {
+            findings.push(Finding::error(
+                key_path(&["files", pattern]),
+                format!("invalid pattern: {}", reason),
+            ));
+        }
+    }
+    findings
+}
+
+/// Settings that cannot be used, or that cancel each other out
+fn conflicts(config: &Config, home: &Path) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if let Some(remote) = &config.remote {
+        if remote.delta && !matches!(remote.kind.as_str(), "localfs" | "local") {
+            findings.push(Finding::error(
+                key_path(&["remote", "delta"]),
+                format!(
+                    "delta sync only works with localfs remotes, not {}",
+                    remote.kind
+                ),
+            ));
+        }
+        if remote.delta && remote.encrypt {
+            findings.push(Finding::warning(
+                key_path(&["remote", "encrypt"]),
+                "has no effect with `delta = true`; the mirror is not encrypted".to_string(),
+            ));
+        }
+    }
+
+    if let Some(daemon) = &config.daemon {
+        if !matches!(daemon.mode.as_str(), "ask" | "auto") {
+            findings.push(Finding::error(
+                key_path(&["daemon", "mode"]),
+                format!(
+                    "unknown mode \"{}\", expected \"ask\" or \"auto\"",
+                    daemon.mode
+                ),
+            ));
+        }
+        match daemon.auto_push.as_deref() {
+            None | Some("github") => {}
+            Some("remote") if config.remote.is_none() => findings.push(Finding::warning(
+                key_path(&["daemon", "auto_push"]),
+                "pushes to the remote, but no [remote] is configured".to_string(),
+            )),
+            Some("remote") => {}
+            Some(other) => findings.push(Finding::error(
+                key_path(&["daemon", "auto_push"]),
+                format!(
+                    "unknown target \"{}\", expected \"github\" or \"remote\"",
+                    other
+                ),
+            )),
+        }
+    }
+
+    if let Some(backend) = config.secrets.as_ref().and_then(|s| s.backend.as_deref()) {
+        if crate::secrets::AgeBackend::parse(backend).is_none() {
+            findings.push(Finding::error(
+                key_path(&["secrets", "backend"]),
+                format!(
+                    "unknown backend \"{}\", expected \"native\" or \"cli\"",
+                    backend
+                ),
+            ));
+        }
+    }
+
+    if let Some(limits) = &config.limits {
+        if crate::usage::parse_size(&limits.max_file_size).is_none() {
+            findings.push(Finding::error(
+                key_path(&["limits", "max_file_size"]),
+                format!(
+                    "invalid size \"{}\", use a size like \"5MB\" or \"0\" for no limit",
+                    limits.max_file_size
+                ),
+            ));
+        }
+    }
+
+    for (pattern, file) in &config.files {
+        if let Some(chmod) = &file.chmod {
+            if crate::perms::parse_mode(chmod).is_none() {
+                findings.push(Finding::error(
+                    key_path(&["files", pattern, "chmod"]),
+                    format!(
+                        "invalid mode \"{}\", use octal permissions like \"0600\"",
+                        chmod
+                    ),
+                ));
+            }
+        }
+        if file.mode == Some(super::RestoreMode::SymlinkDir) {
+            let rel = crate::matching::to_home_relative(Path::new(pattern), home);
+            let tracked_dir = config
+                .tracked_dirs
+                .keys()
+                .any(|dir| crate::matching::to_home_relative(Path::new(dir), home) == rel);
+            if !tracked_dir {
+                findings.push(Finding::warning(
+                    key_path(&["files", pattern, "mode"]),
+                    "only [tracked_dirs] directories are linked as a whole; this is linked file by file"
+                        .to_string(),
+                ));
+            }
+        }
+        if !file.exclude {
+            continue;
+        }
+        let ignored: Vec<&str> = [
+            ("mode", file.mode.is_some()),
+            ("template", file.template),
+            ("protected", file.protected),
+            ("chmod", file.chmod.is_some()),
+            ("owner", file.owner.is_some()),
+            ("group", file.group.is_some()),
+        ]
+        .into_iter()
+        .filter_map(|(name, set)| set.then_some(name))
+        .collect();
+        if !ignored.is_empty() {
+            findings.push(Finding::warning(
+                key_path(&["files", pattern, "exclude"]),
+                format!(
+                    "excluded files are never synced, so `{}` has no effect",
+                    ignored.join("`, `")
+                ),
+            ));
+        }
+    }
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_reports_typos_patterns_and_conflicts_with_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        fs::write(
+            &config_path,
+            r#"exclue_patterns = ["*.log"]
+include_patterns = ["~/.config/*.{toml"]
+
+[general]
+default_mode = "copy"
+tracked_files = []
+
+[files."~/.ssh/config"]
+exclude = true
+mode = "copy"
+
+[[hooks.post_apply]]
+run = "echo hi"
+timout_secs = 5
+
+[remote]
+kind = "s3"
+delta = true
+encrypt = false
+"#,
+        )
+        .unwrap();
+
+        let problems = check(&config_path).unwrap();
+        let shown: Vec<String> = problems
+            .iter()
+            .map(|p| format!("{}:{} {}", p.line.unwrap_or(0), p.key, p.message))
+            .collect();
+        assert_eq!(
+            shown,
+            vec![
+                "1:exclue_patterns unknown key, ignored; did you mean `exclude_patterns`?",
+                "14:hooks.post_apply[0].timout_secs unknown key, ignored; \
+                 did you mean `timeout_secs`?",
+                "2:include_patterns invalid pattern `~/.config/*.{toml`: error parsing glob \
+                 '/.config/*.{toml': unclosed alternate group; missing '}' \
+                 (maybe escape '{' with '[{]'?)",
+                "18:remote.delta delta sync only works with localfs remotes, not s3",
+                "9:files.\"~/.ssh/config\".exclude excluded files are never synced, \
+                 so `mode` has no effect",
+            ]
+        );
+        assert!(problems[0].to_string().ends_with(
+            "config.toml:1: `exclue_patterns`: unknown key, ignored; \
+             did you mean `exclude_patterns`?"
+        ));
+
+        // A value of the wrong type stops the checks with its location
+        fs::write(&config_path, "[general]\ndefault_mode = \"cpy\"\n").unwrap();
+        let problems = check(&config_path).unwrap();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].is_error());
+        let message = problems[0].to_string();
+        assert!(message.contains("TOML parse error at line 2, column 16"));
+        assert!(message
+            .contains("unknown variant `cpy`, expected one of `symlink`, `copy`, `symlink_dir`"));
+    }
+}
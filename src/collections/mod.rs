@@ -0,0 +1,328 @@
+//! Multiple independent dotfile collections applied into one `$HOME`.
+//!
+//! Besides the main store (the active profile's compiled directory and
+//! manifest), `[[collections]]` entries name extra sources such as a team
+//! baseline repo. Each collection lives in `collections/<name>/` with its own
+//! `compiled/` directory, `manifest.lock` and git remote, and is snapshotted,
+//! pushed and pulled on its own.
+//!
+//! Precedence: the main store comes first, then collections in the order they
+//! are listed. A file provided by several sources is diffed and applied from
+//! the first one only; the others are reported as shadowed.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::{CollectionConfig, Config};
+use crate::hash::{hash_files, Manifest};
+use crate::matching::display_tilde;
+use crate::offline::RetryPolicy;
+use crate::ui;
+
+/// Name of the main store in layered views
+pub const MAIN: &str = "main";
+
+/// One source of files, restricted to the files it wins
+#[derive(Debug, Clone)]
+pub struct Layer {
+    pub name: String,
+    pub compiled: PathBuf,
+    pub manifest: Manifest,
+}
+
+/// A file hidden by a higher-precedence source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Shadowed {
+    pub rel_path: PathBuf,
+    pub winner: String,
+    pub hidden: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Layered {
+    /// Sources in precedence order
+    pub layers: Vec<Layer>,
+    pub shadowed: Vec<Shadowed>,
+}
+
+/// `collections/<name>/`
+pub fn collection_dir(name: &str) -> Result<PathBuf> {
+    Ok(crate::paths::collections_dir()?.join(name))
+}
+
+/// Look up a configured collection by name
+pub fn find<'a>(config: &'a Config, name: &str) -> Result<&'a CollectionConfig> {
+    if let Some(collection) = config.collections.iter().find(|c| c.name == name) {
+        return Ok(collection);
+    }
+    let names: Vec<&str> = config.collections.iter().map(|c| c.name.as_str()).collect();
+    if names.is_empty() {
+        bail!(
+            "Unknown collection '{}': no [[collections]] are configured",
+            name
+        );
+    }
+    bail!(
+        "Unknown collection '{}'. Configured collections: {}",
+        name,
+        names.join(", ")
+    )
+}
+
+fn load_manifest(dir: &Path) -> Result<Manifest> {
+    let path = dir.join("manifest.lock");
+    if path.exists() {
+        Manifest::load(&path)
+    } else {
+        Ok(Manifest::new())
+    }
+}
+
+/// Stack sources in precedence order (`sources[0]` wins), assigning each file
+/// to the first source that provides it.
+pub fn stack(sources: Vec<(String, PathBuf, Manifest)>) -> Layered {
+    let mut owners: BTreeMap<PathBuf, String> = BTreeMap::new();
+    let mut layered = Layered::default();
+
+    for (name, compiled, manifest) in sources {
+        let mut files: Vec<_> = manifest.files.into_values().collect();
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut kept = Manifest::new();
+        for file in files {
+            match owners.get(&file.path) {
+                Some(winner) => layered.shadowed.push(Shadowed {
+                    rel_path: file.path.clone(),
+                    winner: winner.clone(),
+                    hidden: name.clone(),
+                }),
+                None => {
+                    owners.insert(file.path.clone(), name.clone());
+                    kept.add_file(file);
+                }
+            }
+        }
+        // Links and empty directories belong to the first source with them
+        for (path, link) in manifest.links {
+            if !owners.contains_key(&path) {
+                owners.insert(path.clone(), name.clone());
+                kept.links.insert(path, link);
+            }
+        }
+        for (path, dir) in manifest.empty_dirs {
+            if !owners.contains_key(&path) {
+                owners.insert(path.clone(), name.clone());
+                kept.empty_dirs.insert(path, dir);
+            }
+        }
+        layered.layers.push(Layer {
+            name,
+            compiled,
+            manifest: kept,
+        });
+    }
+
+    layered
+}
+
+/// The main store followed by every configured collection
+pub fn layered(config: &Config, main_compiled: &Path, main_manifest: Manifest) -> Result<Layered> {
+    let mut sources = vec![(MAIN.to_string(), main_compiled.to_path_buf(), main_manifest)];
+    for collection in &config.collections {
+        let dir = collection_dir(&collection.name)?;
+        sources.push((
+            collection.name.clone(),
+            dir.join("compiled"),
+            load_manifest(&dir)?,
+        ));
+    }
+    Ok(stack(sources))
+}
+
+/// Where each file comes from, for combined diff/apply views
+pub fn print_layers(layered: &Layered) {
+    if layered.layers.len() < 2 {
+        return;
+    }
+
+    let summary: Vec<String> = layered
+        .layers
+        .iter()
+        .map(|l| format!("{} ({} files)", l.name, l.manifest.files.len()))
+        .collect();
+    ui::info(&format!(
+        "Collections, highest precedence first: {}",
+        summary.join(", ")
+    ));
+    for s in &layered.shadowed {
+        ui::line(&format!(
+            "  {} from {} (overrides {})",
+            display_tilde(&s.rel_path),
+            s.winner,
+            s.hidden
+        ));
+    }
+}
+
+/// `dotdipper collection list`
+pub fn list(config: &Config) -> Result<()> {
+    if config.collections.is_empty() {
+        ui::info("No collections configured. Add [[collections]] entries to config.toml");
+        return Ok(());
+    }
+
+    let mut rows = Vec::new();
+    for (i, collection) in config.collections.iter().enumerate() {
+        let manifest = load_manifest(&collection_dir(&collection.name)?)?;
+        rows.push(vec![
+            (i + 2).to_string(),
+            collection.name.clone(),
+            manifest.files.len().to_string(),
+            collection
+                .remote_url
+                .clone()
+                .unwrap_or_else(|| "-".to_string()),
+        ]);
+    }
+    ui::info(&format!("Precedence 1 is the main store ({})", MAIN));
+    ui::print_table(&["PRECEDENCE", "NAME", "FILES", "REMOTE"], rows);
+    Ok(())
+}
+
+/// Copy a collection's tracked files into its compiled directory
+pub fn snapshot(config: &Config, name: &str) -> Result<usize> {
+    let collection = find(config, name)?;
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let dir = collection_dir(name)?;
+    let compiled = dir.join("compiled");
+
+    // Rebuild from scratch so untracked files do not linger
+    if compiled.exists() {
+        fs::remove_dir_all(&compiled)
+            .with_context(|| format!("Failed to clear {}", compiled.display()))?;
+    }
+    fs::create_dir_all(&compiled)?;
+
+    let mut manifest = Manifest::new();
+    for mut file_hash in hash_files(&collection.tracked_files, true)? {
+        let rel_path = file_hash
+            .path
+            .strip_prefix(&home)
+            .with_context(|| {
+                format!(
+                    "Collection files must be under $HOME: {}",
+                    file_hash.path.display()
+                )
+            })?
+            .to_path_buf();
+        let dest = compiled.join(&rel_path);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        crate::repo::store_file(config, &file_hash.path, &dest, false)?;
+        file_hash.path = rel_path;
+        manifest.add_file(file_hash);
+    }
+
+    manifest.save(&dir.join("manifest.lock"))?;
+    Ok(manifest.files.len())
+}
+
+fn remote_url(collection: &CollectionConfig) -> Result<&str> {
+    collection.remote_url.as_deref().with_context(|| {
+        format!(
+            "Collection '{}' has no remote_url configured",
+            collection.name
+        )
+    })
+}
+
+/// Commit and push a collection to its own remote
+pub fn push(config: &Config, name: &str, message: Option<String>) -> Result<()> {
+    let collection = find(config, name)?;
+    let url = remote_url(collection)?;
+    let dir = collection_dir(name)?;
+    if !dir.join("manifest.lock").exists() {
+        bail!(
+            "Collection '{}' has no snapshot yet. Run 'dotdipper collection snapshot {}' first.",
+            name,
+            name
+        );
+    }
+
+    let message = message.unwrap_or_else(|| {
+        format!(
+            "Update {} - {}",
+            name,
+            chrono::Utc::now().format("%Y-%m-%d %H:%M:%S")
+        )
+    });
+    crate::vcs::push_to_url(&dir, url, &message, &RetryPolicy::from_config(config))
+}
+
+/// Clone or fast-forward a collection from its remote
+pub fn pull(config: &Config, name: &str) -> Result<usize> {
+    let collection = find(config, name)?;
+    let url = remote_url(collection)?;
+    let dir = collection_dir(name)?;
+    crate::vcs::clone_or_pull(url, &dir, &RetryPolicy::from_config(config))?;
+    Ok(load_manifest(&dir)?.files.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::FileHash;
+
+    fn manifest(paths: &[&str]) -> Manifest {
+        let mut manifest = Manifest::new();
+        for path in paths {
+            manifest.add_file(FileHash {
+                path: PathBuf::from(path),
+                hash: "h".to_string(),
+                size: 1,
+                mode: 0o644,
+                modified: chrono::Utc::now(),
+                xattrs: Default::default(),
+            });
+        }
+        manifest
+    }
+
+    #[test]
+    fn test_first_source_wins_and_others_are_shadowed() {
+        let layered = stack(vec![
+            (
+                MAIN.to_string(),
+                PathBuf::from("/c/main"),
+                manifest(&[".zshrc", ".gitconfig"]),
+            ),
+            (
+                "team".to_string(),
+                PathBuf::from("/c/team"),
+                manifest(&[".gitconfig", ".editorconfig"]),
+            ),
+        ]);
+
+        let files = |i: usize| {
+            let mut files: Vec<_> = layered.layers[i].manifest.files.keys().cloned().collect();
+            files.sort();
+            files
+        };
+        assert_eq!(
+            files(0),
+            vec![PathBuf::from(".gitconfig"), PathBuf::from(".zshrc")]
+        );
+        assert_eq!(files(1), vec![PathBuf::from(".editorconfig")]);
+        assert_eq!(
+            layered.shadowed,
+            vec![Shadowed {
+                rel_path: PathBuf::from(".gitconfig"),
+                winner: MAIN.to_string(),
+                hidden: "team".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Keeping reads and writes inside the directories they belong to.
+//!
+//! Lexical checks like `target.starts_with(home)` are fooled by `..`
+//! components and by symlinked directories: `~/.config/foo` is really
+//! `/etc/foo` once `~/.config` links to `/etc`. The checks here resolve
+//! symlinks first, so a path only counts as inside a root if the file it
+//! names really is. [`walk`] lists the files under a tracked directory
+//! without following symlinks out of it or going round in a loop.
+
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use walkdir::WalkDir;
+
+/// `path` with every symlink resolved, as far as it exists. The part that
+/// doesn't exist yet is appended as written, with `..` taken lexically.
+pub fn resolve(path: &Path) -> PathBuf {
+    for existing in path.ancestors() {
+        let Ok(mut resolved) = fs::canonicalize(existing) else {
+            continue;
+        };
+        for component in path.strip_prefix(existing).unwrap_or(path).components() {
+            match component {
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(name) => resolved.push(name),
+                _ => {}
+            }
+        }
+        return resolved;
+    }
+    path.to_path_buf()
+}
+
+/// True if `path` lies inside `root` once symlinks are resolved
+pub fn is_within(path: &Path, root: &Path) -> bool {
+    resolve(path).starts_with(resolve(root))
+}
+
+/// True for a relative path that stays below whatever it is joined to:
+/// no root, drive prefix or `..` components
+pub fn is_plain(rel_path: &Path) -> bool {
+    rel_path
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// Why `target` must not be written by an operation confined to `home`,
+/// or `None` if it may be.
+///
+/// The target itself may be a symlink, since apply replaces it rather than
+/// writing through it; its parent directories must not lead out of `home`.
+pub fn check_target(target: &Path, home: &Path) -> Option<&'static str> {
+    match target.strip_prefix(home) {
+        Ok(rel_path) if is_plain(rel_path) => {}
+        _ => return Some("Outside $HOME"),
+    }
+    let parent = target.parent().unwrap_or(home);
+    if !is_within(parent, home) {
+        return Some("Escapes $HOME through a symlink");
+    }
+    None
+}
+
+/// Files found under a directory by [`walk`]
+#[derive(Debug, Default)]
+pub struct Walk {
+    pub files: Vec<PathBuf>,
+    /// Every symlink met, followed or not
+    pub links: Vec<PathBuf>,
+    /// Directories below the root with nothing in them
+    pub empty_dirs: Vec<PathBuf>,
+    /// Entries that were not followed, with the reason
+    pub refused: Vec<(PathBuf, &'static str)>,
+}
+
+/// Every file under `root`, staying on its filesystem.
+///
+/// Symlinks to files are included only if they resolve to somewhere inside
+/// `root`; symlinks leading out of it are refused, and symlinked
+/// directories are never descended into (their contents, if inside `root`,
+/// are found under their real path). A directory reached a second time,
+/// as through a bind mount of one of its ancestors, is refused as a loop.
+pub fn walk(root: &Path) -> Walk {
+    walk_allowing(root, &[])
+}
+
+/// [`walk`], also descending into other filesystems mounted at or below one
+/// of the `allowed` directories (`[mounts].allow`)
+pub fn walk_allowing(root: &Path, allowed: &[PathBuf]) -> Walk {
+    let real_root = resolve(root);
+    let root_dev = fs::metadata(root).map(|m| m.dev()).ok();
+    let mut walk = Walk::default();
+    let mut seen_dirs = HashSet::new();
+
+    let mut entries = WalkDir::new(root).follow_links(false).into_iter();
+    while let Some(entry) = entries.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        let file_type = entry.file_type();
+
+        if file_type.is_dir() {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            // Never cross into another filesystem unless it is allowed
+            if root_dev != Some(meta.dev()) && !allowed.iter().any(|a| path.starts_with(a)) {
+                entries.skip_current_dir();
+                continue;
+            }
+            if !seen_dirs.insert((meta.dev(), meta.ino())) {
+                walk.refused.push((path.to_path_buf(), "directory loop"));
+                entries.skip_current_dir();
+            } else if entry.depth() > 0 && fs::read_dir(path).is_ok_and(|mut d| d.next().is_none())
+            {
+                walk.empty_dirs.push(path.to_path_buf());
+            }
+        } else if file_type.is_symlink() {
+            walk.links.push(path.to_path_buf());
+            match fs::canonicalize(path) {
+                Ok(real) if !real.starts_with(&real_root) => walk
+                    .refused
+                    .push((path.to_path_buf(), "symlink leads outside the directory")),
+                Ok(real) if real.is_file() => walk.files.push(path.to_path_buf()),
+                // Dangling links and links to directories
+                _ => {}
+            }
+        } else if file_type.is_file() {
+            walk.files.push(path.to_path_buf());
+        }
+    }
+
+    walk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_symlinks_out_of_a_root_are_refused() {
+        let temp = tempfile::tempdir().unwrap();
+        let home = temp.path().join("home");
+        let outside = temp.path().join("outside");
+        fs::create_dir_all(home.join(".config/nvim")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(home.join(".config/nvim/init.lua"), "").unwrap();
+        fs::write(outside.join("secret"), "").unwrap();
+        symlink(&outside, home.join(".config/escape")).unwrap();
+        symlink(outside.join("secret"), home.join(".config/nvim/secret")).unwrap();
+        symlink(
+            home.join(".config/nvim/init.lua"),
+            home.join(".config/init"),
+        )
+        .unwrap();
+        // A link back to an ancestor would loop if followed
+        symlink(&home, home.join(".config/nvim/home")).unwrap();
+
+        let found = walk(&home.join(".config"));
+        let mut files = found.files.clone();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                home.join(".config/init"),
+                home.join(".config/nvim/init.lua")
+            ]
+        );
+        let refused: Vec<&PathBuf> = found.refused.iter().map(|(p, _)| p).collect();
+        assert!(refused.contains(&&home.join(".config/escape")));
+        assert!(refused.contains(&&home.join(".config/nvim/secret")));
+
+        assert_eq!(check_target(&home.join(".config/nvim/x"), &home), None);
+        assert_eq!(
+            check_target(&home.join(".config/escape/x"), &home),
+            Some("Escapes $HOME through a symlink")
+        );
+        assert_eq!(
+            check_target(&home.join("../outside/secret"), &home),
+            Some("Outside $HOME")
+        );
+        assert_eq!(
+            check_target(&outside.join("x"), &home),
+            Some("Outside $HOME")
+        );
+        assert!(!is_plain(Path::new("a/../../etc")));
+        assert!(is_within(&home.join("new/../.zshrc"), &home));
+    }
+}
@@ -4,96 +4,149 @@
 /// - Watching filesystem for changes to tracked dotfiles
 /// - Debouncing file events to avoid excessive snapshots
 /// - Auto-snapshotting or prompting on drift detection
+/// - Pushing new snapshots to GitHub or the cloud remote (`auto_push`)
 /// - Graceful start/stop/status with PID file management
+/// - Running detached in the background, logging to `logs/daemon.log`
+/// - Simulating what it would do without doing it (see [`simulate`])
+/// - Starting at login as a systemd user service or launchd agent (see
+///   [`service`])
+/// - Re-rendering `status`/`diff` as files change (see [`watch`])
+pub mod service;
+pub mod simulate;
+pub mod watch;
+
 use anyhow::{bail, Context, Result};
-use notify::{Event as NotifyEvent, RecursiveMode, Watcher};
+use chrono::{DateTime, Utc};
 use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
-use std::sync::mpsc::channel;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 use sysinfo::{Pid, System};
 
-use crate::cfg::Config;
+use crate::cfg::{Config, DaemonConfig};
 use crate::ui;
 
 const DAEMON_PID_FILE: &str = "daemon.pid";
 
-/// Start the daemon
-pub fn start(config: &Config) -> Result<()> {
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let pid_file = dotdipper_dir.join(DAEMON_PID_FILE);
+/// File events seen by the daemon, replayed by `daemon simulate`
+const EVENT_LOG_FILE: &str = "daemon-events.log";
+
+/// Lines kept in the event log
+const MAX_EVENT_LOG_LINES: usize = 2000;
+
+/// How often the daemon retries pushes queued while offline
+const QUEUE_FLUSH_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Output of a detached daemon
+const DAEMON_LOG_FILE: &str = "daemon.log";
+
+/// Size at which the daemon log is rotated to `daemon.log.1`
+const MAX_DAEMON_LOG_BYTES: u64 = 1024 * 1024;
+
+/// How long `start --detach` waits for the background daemon to come up
+const DETACH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Quiet time after the config changes before it is reloaded, so a save in
+/// several writes is read once
+const CONFIG_RELOAD_DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Where a detached daemon writes its output
+pub fn log_file() -> Result<PathBuf> {
+    Ok(crate::paths::logs_dir()?.join(DAEMON_LOG_FILE))
+}
+
+/// PID in the PID file, if there is one
+fn read_pid(pid_file: &Path) -> Option<i32> {
+    fs::read_to_string(pid_file).ok()?.trim().parse().ok()
+}
 
-    // Check if already running
+/// Fail if a daemon is running; remove the PID file of one that is not
+fn ensure_not_running(pid_file: &Path) -> Result<()> {
     if pid_file.exists() {
-        let pid_str = fs::read_to_string(&pid_file)?;
-        if let Ok(pid) = pid_str.trim().parse::<i32>() {
+        if let Some(pid) = read_pid(pid_file) {
             if is_process_running(pid) {
                 bail!("Daemon is already running (PID: {})", pid);
             } else {
                 ui::warn("Stale PID file found, removing...");
-                fs::remove_file(&pid_file)?;
+                fs::remove_file(pid_file)?;
             }
         }
     }
+    Ok(())
+}
+
+/// The `[daemon]` config if the daemon is enabled, explaining how to enable
+/// it otherwise
+fn enabled_config(config: &Config) -> Option<&DaemonConfig> {
+    match config.daemon.as_ref() {
+        Some(cfg) if cfg.enabled => return Some(cfg),
+        Some(_) => ui::info("Daemon is not enabled in configuration."),
+        None => ui::info("Daemon is not configured."),
+    }
+    ui::hint("To enable the daemon, add the following to your config.toml:");
+    println!();
+    println!("  [daemon]");
+    println!("  enabled = true");
+    println!("  mode = \"ask\"  # or \"auto\"");
+    println!("  debounce_ms = 1500");
+    println!();
+    ui::hint("Then run: dotdipper daemon start");
+    None
+}
+
+/// Start the daemon
+pub fn start(config: &Config) -> Result<()> {
+    let dotdipper_dir = get_dotdipper_dir()?;
+    let pid_file = dotdipper_dir.join(DAEMON_PID_FILE);
+
+    ensure_not_running(&pid_file)?;
 
     // Check if daemon is configured and enabled
-    let daemon_config = match config.daemon.as_ref() {
-        Some(cfg) if cfg.enabled => cfg,
-        Some(_) => {
-            ui::info("Daemon is not enabled in configuration.");
-            ui::hint("To enable the daemon, add the following to your config.toml:");
-            println!();
-            println!("  [daemon]");
-            println!("  enabled = true");
-            println!("  mode = \"ask\"  # or \"auto\"");
-            println!("  debounce_ms = 1500");
-            println!();
-            ui::hint("Then run: dotdipper daemon start");
-            return Ok(());
-        }
-        None => {
-            ui::info("Daemon is not configured.");
-            ui::hint("To enable the daemon, add the following to your config.toml:");
-            println!();
-            println!("  [daemon]");
-            println!("  enabled = true");
-            println!("  mode = \"ask\"  # or \"auto\"");
-            println!("  debounce_ms = 1500");
-            println!();
-            ui::hint("Then run: dotdipper daemon start");
-            return Ok(());
-        }
+    let Some(daemon_config) = enabled_config(config) else {
+        return Ok(());
     };
 
     let mode = daemon_config.mode.as_str();
     let debounce_ms = daemon_config.debounce_ms;
+    let auto_push = AutoPush::from_config(daemon_config)?;
 
     ui::info(&format!(
         "Starting daemon in '{}' mode (debounce: {}ms)...",
         mode, debounce_ms
     ));
 
-    // Get tracked files
-    let tracked_files: Vec<PathBuf> = config.general.tracked_files.clone();
+    // Get tracked files and directories
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let tracked_files = watch::tracked_paths(config, &home);
 
     if tracked_files.is_empty() {
         bail!("No tracked files configured. Add files with 'dotdipper discover --write'");
     }
 
     ui::info(&format!("Watching {} files", tracked_files.len()));
+    if let Some(auto_push) = &auto_push {
+        ui::info(&format!("Pushing new snapshots: {}", auto_push.describe()));
+    }
 
     // Write PID file
     let current_pid = std::process::id();
     fs::write(&pid_file, current_pid.to_string())?;
 
     ui::success(&format!("Daemon started (PID: {})", current_pid));
+    crate::history::Event::new(
+        crate::history::Kind::Daemon,
+        format!("Daemon started in '{}' mode (PID: {})", mode, current_pid),
+    )
+    .files(tracked_files.len())
+    .record();
     ui::hint("Stop with: dotdipper daemon stop");
 
     // Run daemon loop
-    match run_daemon_loop(tracked_files, debounce_ms, mode) {
+    match run_daemon_loop(tracked_files, debounce_ms, mode, auto_push, false) {
         Ok(_) => {
             ui::info("Daemon stopped gracefully");
+            crate::history::Event::new(crate::history::Kind::Daemon, "Daemon stopped").record();
         }
         Err(e) => {
             ui::error(&format!("Daemon error: {}", e));
@@ -103,12 +156,119 @@ pub fn start(config: &Config) -> Result<()> {
         }
     }
 
-    // Clean up PID file
-    let _ = fs::remove_file(&pid_file);
+    // Clean up PID file, unless another daemon has taken over since
+    if read_pid(&pid_file) == Some(current_pid as i32) {
+        let _ = fs::remove_file(&pid_file);
+    }
 
     Ok(())
 }
 
+/// The `[daemon]` config if the daemon is enabled and can run without a
+/// terminal, failing on settings it would only trip over once started
+fn unattended_config(config: &Config) -> Result<Option<&DaemonConfig>> {
+    let Some(daemon_config) = enabled_config(config) else {
+        return Ok(None);
+    };
+    if daemon_config.mode == "ask" {
+        bail!(
+            "The daemon prompts before each snapshot in 'ask' mode, which needs a terminal. \
+             Set mode = \"auto\" under [daemon] to run it in the background"
+        );
+    }
+    if config.general.tracked_files.is_empty() {
+        bail!("No tracked files configured. Add files with 'dotdipper discover --write'");
+    }
+    AutoPush::from_config(daemon_config)?;
+    Ok(Some(daemon_config))
+}
+
+/// Start the daemon in the background and return once it is running.
+///
+/// The daemon is this binary run again as `daemon start` in its own process
+/// group, with stdin closed and its output appended to [`log_file`].
+/// Forking would copy the async runtime's threads in a broken state.
+pub fn start_detached(config: &Config, config_path: &Path) -> Result<()> {
+    let pid_file = get_dotdipper_dir()?.join(DAEMON_PID_FILE);
+    ensure_not_running(&pid_file)?;
+
+    // Fail here, where the user can see it, rather than in the log
+    if unattended_config(config)?.is_none() {
+        return Ok(());
+    }
+
+    let log_path = log_file()?;
+    let mut log = open_log(&log_path)?;
+    writeln!(
+        log,
+        "--- {} starting daemon ---",
+        chrono::Local::now().to_rfc3339()
+    )?;
+
+    let mut command = std::process::Command::new(
+        std::env::current_exe().context("Failed to find the dotdipper executable")?,
+    );
+    // The daemon must not keep the current directory busy, so it runs from /
+    command
+        .arg("--config")
+        .arg(std::path::absolute(config_path)?)
+        .args(["daemon", "start"])
+        .current_dir("/")
+        .env("NO_COLOR", "1")
+        .stdin(std::process::Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    // Keep Ctrl-C and the terminal's job control away from the daemon
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command.spawn().context("Failed to start the daemon")?;
+
+    let deadline = Instant::now() + DETACH_TIMEOUT;
+    while read_pid(&pid_file) != Some(child.id() as i32) {
+        if let Some(status) = child.try_wait()? {
+            bail!(
+                "Daemon exited during startup ({}); see {}",
+                status,
+                log_path.display()
+            );
+        }
+        if Instant::now() >= deadline {
+            bail!(
+                "Daemon did not start within {}s; see {}",
+                DETACH_TIMEOUT.as_secs(),
+                log_path.display()
+            );
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+
+    ui::success(&format!(
+        "Daemon started in the background (PID: {})",
+        child.id()
+    ));
+    ui::info(&format!("Logging to {}", log_path.display()));
+    ui::hint("Stop with: dotdipper daemon stop");
+    Ok(())
+}
+
+/// Open the daemon log for appending, rotating it first if it got too big
+fn open_log(log_path: &Path) -> Result<fs::File> {
+    if let Some(parent) = log_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::metadata(log_path).is_ok_and(|m| m.len() >= MAX_DAEMON_LOG_BYTES) {
+        fs::rename(log_path, log_path.with_extension("log.1"))?;
+    }
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)
+        .with_context(|| format!("Failed to open {}", log_path.display()))
+}
+
 /// Stop the daemon
 pub fn stop(_config: &Config) -> Result<()> {
     let dotdipper_dir = get_dotdipper_dir()?;
@@ -166,6 +326,11 @@ pub fn stop(_config: &Config) -> Result<()> {
 
     fs::remove_file(&pid_file)?;
     ui::success("Daemon stopped");
+    crate::history::Event::new(
+        crate::history::Kind::Daemon,
+        format!("Stopped daemon (PID: {})", pid),
+    )
+    .record();
 
     Ok(())
 }
@@ -175,6 +340,10 @@ pub fn status(_config: &Config) -> Result<()> {
     let dotdipper_dir = get_dotdipper_dir()?;
     let pid_file = dotdipper_dir.join(DAEMON_PID_FILE);
 
+    if let Some(unit) = service::installed() {
+        ui::info(&format!("Starts at login: {}", unit.display()));
+    }
+
     if !pid_file.exists() {
         ui::info("Daemon is not running");
         return Ok(());
@@ -188,6 +357,10 @@ pub fn status(_config: &Config) -> Result<()> {
 
     if is_process_running(pid) {
         ui::success(&format!("Daemon is running (PID: {})", pid));
+        let log_path = log_file()?;
+        if log_path.exists() {
+            ui::info(&format!("Log: {}", log_path.display()));
+        }
     } else {
         ui::warn("Daemon is not running (stale PID file)");
         ui::hint("Clean up with: dotdipper daemon stop");
@@ -206,6 +379,8 @@ pub fn enable(config_path: &std::path::Path) -> Result<()> {
             enabled: true,
             mode: default_daemon_mode(),
             debounce_ms: default_debounce_ms(),
+            auto_push: None,
+            push_interval_minutes: 0,
         });
     } else {
         // Update existing config
@@ -258,74 +433,185 @@ fn default_debounce_ms() -> u64 {
     1500
 }
 
-fn run_daemon_loop(tracked_files: Vec<PathBuf>, debounce_ms: u64, mode: &str) -> Result<()> {
-    // Set up file watcher
-    let (tx, rx) = channel();
+/// Where and how often the daemon pushes the snapshots it takes
+#[derive(Debug)]
+struct AutoPush {
+    target: crate::offline::PushTarget,
+    interval: Duration,
+}
 
-    let mut watcher =
-        notify::recommended_watcher(move |res: Result<NotifyEvent, notify::Error>| {
-            if let Ok(event) = res {
-                let _ = tx.send(event);
-            }
-        })?;
-
-    // Watch tracked files and their parent directories
-    let mut watched_dirs: HashSet<PathBuf> = HashSet::new();
-
-    for file in &tracked_files {
-        if let Some(parent) = file.parent() {
-            if !watched_dirs.contains(parent) {
-                if let Err(e) = watcher.watch(parent, RecursiveMode::NonRecursive) {
-                    ui::warn(&format!("Failed to watch {}: {}", parent.display(), e));
-                } else {
-                    watched_dirs.insert(parent.to_path_buf());
-                }
+impl AutoPush {
+    fn from_config(daemon_config: &DaemonConfig) -> Result<Option<Self>> {
+        let target = match daemon_config.auto_push.as_deref() {
+            None => return Ok(None),
+            Some("github") => crate::offline::PushTarget::GitHub {
+                message: Some("Auto-push from dotdipper daemon".to_string()),
+                repo: None,
+            },
+            Some("remote") => crate::offline::PushTarget::Remote,
+            Some(other) => bail!(
+                "Unknown [daemon] auto_push '{}': expected \"github\" or \"remote\"",
+                other
+            ),
+        };
+        Ok(Some(Self {
+            target,
+            interval: Duration::from_secs(daemon_config.push_interval_minutes * 60),
+        }))
+    }
+
+    fn describe(&self) -> String {
+        let to = match self.target {
+            crate::offline::PushTarget::GitHub { .. } => "GitHub",
+            crate::offline::PushTarget::Remote => "the remote",
+        };
+        if self.interval.is_zero() {
+            format!("pushed to {} after each snapshot", to)
+        } else {
+            format!(
+                "pushed to {} at most every {} minute(s)",
+                to,
+                self.interval.as_secs() / 60
+            )
+        }
+    }
+
+    /// Whether a push is allowed now, given when the last one happened
+    fn is_due(&self, last_push: Option<Instant>) -> bool {
+        last_push.is_none_or(|t| t.elapsed() >= self.interval)
+    }
+
+    /// Push the latest snapshot. Failures are logged and, if the network is
+    /// down, queued for the periodic queue flush; they never stop the daemon.
+    fn push(&self) {
+        let result = crate::paths::config_file()
+            .and_then(|path| crate::cfg::load(&path))
+            .and_then(|config| crate::offline::push_blocking(&config, self.target.clone()));
+        match result {
+            Ok(()) => {
+                crate::history::Event::new(
+                    crate::history::Kind::Daemon,
+                    format!("Auto-pushed ({})", self.describe()),
+                )
+                .record();
             }
+            Err(e) => ui::warn(&format!("Auto-push failed: {:#}", e)),
         }
     }
+}
 
-    ui::info(&format!("Watching {} directories", watched_dirs.len()));
+/// Watch tracked files and act on debounced changes. With `simulate`, every
+/// decision is reported and nothing is snapshotted, prompted or pushed.
+fn run_daemon_loop(
+    mut tracked_files: Vec<PathBuf>,
+    debounce_ms: u64,
+    mode: &str,
+    auto_push: Option<AutoPush>,
+    simulate: bool,
+) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let config_path = crate::paths::config_file()?;
+    let mut watcher = watch::TrackedWatcher::new(&tracked_files, &home)?;
+    watcher.watch_config(&config_path);
+    ui::info(&format!("Watching {} directories", watcher.dirs()));
 
     // Debouncing state
     let mut last_event_time: Option<Instant> = None;
     let mut pending_changes: HashSet<PathBuf> = HashSet::new();
     let debounce_duration = Duration::from_millis(debounce_ms);
+    let mut last_queue_flush: Option<Instant> = None;
+    let mut pending_events: Vec<simulate::FileEvent> = Vec::new();
+    // A snapshot waiting to be pushed, and when the last push happened
+    let mut push_due = false;
+    let mut last_push: Option<Instant> = None;
+    let mut config_changed: Option<Instant> = None;
 
     // Main event loop
     loop {
         // Use timeout to periodically check for debounced events
-        match rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(event) => {
+        match watcher.recv_timeout(Duration::from_millis(100)) {
+            Ok(changes) => {
+                if changes.config {
+                    config_changed = Some(Instant::now());
+                }
                 // Process event
-                for path in event.paths {
-                    if tracked_files.contains(&path) {
-                        pending_changes.insert(path.clone());
-                        last_event_time = Some(Instant::now());
+                for path in changes.tracked {
+                    if simulate {
+                        let note = match last_event_time {
+                            Some(t) => format!(
+                                "{}ms after the previous change, joins the batch",
+                                t.elapsed().as_millis()
+                            ),
+                            None => "starts a batch".to_string(),
+                        };
+                        ui::info(&format!("Change detected: {} ({})", path.display(), note));
+                        pending_events.push(simulate::FileEvent {
+                            at: Utc::now(),
+                            path: path.clone(),
+                            source: simulate::EventSource::Log,
+                        });
+                    } else {
                         ui::info(&format!("Change detected: {}", path.display()));
+                        record_event(&path);
                     }
+                    pending_changes.insert(path.clone());
+                    last_event_time = Some(Instant::now());
                 }
             }
             Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                if config_changed.is_some_and(|t| t.elapsed() >= CONFIG_RELOAD_DEBOUNCE) {
+                    reload_tracked(&mut watcher, &config_path, &home, &mut tracked_files);
+                    config_changed = None;
+                }
+
+                if !simulate && last_queue_flush.is_none_or(|t| t.elapsed() >= QUEUE_FLUSH_INTERVAL)
+                {
+                    flush_push_queue();
+                    last_queue_flush = Some(Instant::now());
+                }
+
+                if let Some(auto_push) = &auto_push {
+                    if push_due && auto_push.is_due(last_push) {
+                        auto_push.push();
+                        push_due = false;
+                        last_push = Some(Instant::now());
+                    }
+                }
+
                 // Check if we should process pending changes
                 if let Some(last_time) = last_event_time {
                     if last_time.elapsed() >= debounce_duration && !pending_changes.is_empty() {
+                        if simulate {
+                            let batch = simulate::Batch {
+                                events: std::mem::take(&mut pending_events),
+                                fires_at: Utc::now(),
+                            };
+                            let manifest = simulate::load_manifest()?;
+                            ui::info(&format!(
+                                "Quiet for {}ms: {}",
+                                debounce_ms,
+                                simulate::decision(mode, &batch, &manifest, &home)
+                            ));
+                            pending_changes.clear();
+                            last_event_time = None;
+                            continue;
+                        }
+
                         // Process changes
                         ui::info(&format!(
                             "Processing {} changed files...",
                             pending_changes.len()
                         ));
 
-                        match mode {
-                            "auto" => {
-                                handle_changes_auto(&pending_changes)?;
-                            }
-                            "ask" => {
-                                handle_changes_ask(&pending_changes)?;
-                            }
+                        let snapshotted = match mode {
+                            "auto" => handle_changes_auto(&pending_changes)?,
+                            "ask" => handle_changes_ask(&pending_changes)?,
                             _ => {
                                 ui::warn(&format!("Unknown daemon mode: {}", mode));
+                                false
                             }
-                        }
+                        };
+                        push_due |= snapshotted;
 
                         // Reset state
                         pending_changes.clear();
@@ -342,12 +628,60 @@ fn run_daemon_loop(tracked_files: Vec<PathBuf>, debounce_ms: u64, mode: &str) ->
     Ok(())
 }
 
-fn handle_changes_auto(changed_files: &HashSet<PathBuf>) -> Result<()> {
+/// Watch the tracked files of the changed config. Other `[daemon]` settings
+/// take effect when the daemon is restarted.
+fn reload_tracked(
+    watcher: &mut watch::TrackedWatcher,
+    config_path: &Path,
+    home: &Path,
+    tracked_files: &mut Vec<PathBuf>,
+) {
+    let config = match crate::cfg::load(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            ui::warn(&format!(
+                "Config changed but does not load, still watching the previous files: {:#}",
+                e
+            ));
+            return;
+        }
+    };
+    watcher.watch_config(config_path);
+
+    let reloaded = watch::tracked_paths(&config, home);
+    let added = reloaded
+        .iter()
+        .filter(|p| !tracked_files.contains(p))
+        .count();
+    let removed = tracked_files
+        .iter()
+        .filter(|p| !reloaded.contains(p))
+        .count();
+    if added == 0 && removed == 0 {
+        return;
+    }
+    if let Err(e) = watcher.retrack(&reloaded) {
+        ui::warn(&format!(
+            "Failed to watch the reloaded tracked files: {:#}",
+            e
+        ));
+        return;
+    }
+    ui::info(&format!(
+        "Config reloaded: watching {} tracked path(s), {} added and {} removed",
+        reloaded.len(),
+        added,
+        removed
+    ));
+    *tracked_files = reloaded;
+}
+
+/// Snapshot the changes; returns whether a snapshot was taken
+fn handle_changes_auto(changed_files: &HashSet<PathBuf>) -> Result<bool> {
     ui::info("Auto-creating snapshot...");
 
     // Load config
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let config_path = dotdipper_dir.join("config.toml");
+    let config_path = crate::paths::config_file()?;
     let config = crate::cfg::load(&config_path)?;
 
     // Create compiled snapshot first
@@ -355,13 +689,23 @@ fn handle_changes_auto(changed_files: &HashSet<PathBuf>) -> Result<()> {
     ui::success(&format!("Compiled {} files", snapshot.file_count));
 
     // Create versioned snapshot (this will also trigger auto-pruning if configured)
+    crate::history::Event::new(
+        crate::history::Kind::Daemon,
+        format!(
+            "Auto-snapshot after {} changed file(s)",
+            changed_files.len()
+        ),
+    )
+    .files(changed_files.len())
+    .record();
     let message = format!("Auto-snapshot: {} files changed", changed_files.len());
     crate::snapshots::create(&config, Some(message))?;
 
-    Ok(())
+    Ok(true)
 }
 
-fn handle_changes_ask(changed_files: &HashSet<PathBuf>) -> Result<()> {
+/// Ask whether to snapshot the changes; returns whether a snapshot was taken
+fn handle_changes_ask(changed_files: &HashSet<PathBuf>) -> Result<bool> {
     ui::warn(&format!("{} files changed", changed_files.len()));
 
     for file in changed_files.iter().take(5) {
@@ -378,8 +722,7 @@ fn handle_changes_ask(changed_files: &HashSet<PathBuf>) -> Result<()> {
         .interact()?;
 
     if create_snapshot {
-        let dotdipper_dir = get_dotdipper_dir()?;
-        let config_path = dotdipper_dir.join("config.toml");
+        let config_path = crate::paths::config_file()?;
         let config = crate::cfg::load(&config_path)?;
 
         // Create compiled snapshot first
@@ -393,7 +736,58 @@ fn handle_changes_ask(changed_files: &HashSet<PathBuf>) -> Result<()> {
         ui::info("Skipped snapshot");
     }
 
-    Ok(())
+    Ok(create_snapshot)
+}
+
+fn event_log() -> Result<PathBuf> {
+    Ok(crate::paths::logs_dir()?.join(EVENT_LOG_FILE))
+}
+
+/// Append a file event to the event log. Best effort, like the command history.
+fn record_event(path: &std::path::Path) {
+    let Ok(log) = event_log() else {
+        return;
+    };
+    let mut lines: Vec<String> = fs::read_to_string(&log)
+        .map(|s| s.lines().map(str::to_string).collect())
+        .unwrap_or_default();
+    lines.push(format!("{}\t{}", Utc::now().to_rfc3339(), path.display()));
+    let skip = lines.len().saturating_sub(MAX_EVENT_LOG_LINES);
+
+    if let Some(parent) = log.parent() {
+        let _ = fs::create_dir_all(parent);
+    }
+    let _ = fs::write(&log, lines[skip..].join("\n") + "\n");
+}
+
+/// Events recorded by the daemon, oldest first
+fn read_event_log() -> Result<Vec<(DateTime<Utc>, PathBuf)>> {
+    let log = event_log()?;
+    if !log.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(&log)?;
+    Ok(contents
+        .lines()
+        .filter_map(|line| {
+            let (at, path) = line.split_once('\t')?;
+            let at = DateTime::parse_from_rfc3339(at).ok()?;
+            Some((at.with_timezone(&Utc), PathBuf::from(path)))
+        })
+        .collect())
+}
+
+/// Retry pushes that failed while offline. Errors are logged, never fatal.
+fn flush_push_queue() {
+    if !crate::offline::load_pending().is_ok_and(|p| !p.is_empty()) {
+        return;
+    }
+    let result = crate::paths::config_file()
+        .and_then(|path| crate::cfg::load(&path))
+        .and_then(|config| crate::offline::flush_blocking(&config));
+    if let Err(e) = result {
+        ui::warn(&format!("Could not flush queued pushes: {:#}", e));
+    }
 }
 
 fn is_process_running(pid: i32) -> bool {
@@ -404,13 +798,63 @@ fn is_process_running(pid: i32) -> bool {
 }
 
 fn get_dotdipper_dir() -> Result<PathBuf> {
-    crate::paths::base_dir()
+    crate::paths::data_dir()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_open_log_rotates_large_logs() {
+        let temp = tempfile::tempdir().unwrap();
+        let log_path = temp.path().join("logs").join(DAEMON_LOG_FILE);
+
+        writeln!(open_log(&log_path).unwrap(), "first").unwrap();
+        writeln!(open_log(&log_path).unwrap(), "second").unwrap();
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), "first\nsecond\n");
+
+        fs::write(&log_path, vec![b'x'; MAX_DAEMON_LOG_BYTES as usize]).unwrap();
+        writeln!(open_log(&log_path).unwrap(), "third").unwrap();
+        assert_eq!(fs::read_to_string(&log_path).unwrap(), "third\n");
+        assert_eq!(
+            fs::metadata(temp.path().join("logs/daemon.log.1"))
+                .unwrap()
+                .len(),
+            MAX_DAEMON_LOG_BYTES
+        );
+    }
+
+    #[test]
+    fn test_auto_push_config() {
+        let mut daemon_config = DaemonConfig {
+            enabled: true,
+            mode: "auto".to_string(),
+            debounce_ms: 1500,
+            auto_push: None,
+            push_interval_minutes: 0,
+        };
+        assert!(AutoPush::from_config(&daemon_config).unwrap().is_none());
+
+        daemon_config.auto_push = Some("remote".to_string());
+        let every_snapshot = AutoPush::from_config(&daemon_config).unwrap().unwrap();
+        assert_eq!(every_snapshot.target, crate::offline::PushTarget::Remote);
+        assert!(every_snapshot.is_due(Some(Instant::now())));
+
+        daemon_config.auto_push = Some("github".to_string());
+        daemon_config.push_interval_minutes = 30;
+        let throttled = AutoPush::from_config(&daemon_config).unwrap().unwrap();
+        assert!(throttled.is_due(None));
+        assert!(!throttled.is_due(Some(Instant::now())));
+        assert_eq!(
+            throttled.describe(),
+            "pushed to GitHub at most every 30 minute(s)"
+        );
+
+        daemon_config.auto_push = Some("s3".to_string());
+        assert!(AutoPush::from_config(&daemon_config).is_err());
+    }
+
     #[test]
     fn test_is_process_running() {
         // Test with current process (should always be running)
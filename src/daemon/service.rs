@@ -0,0 +1,378 @@
+//! Installing the daemon as a login service.
+//!
+//! `daemon install` writes a systemd user unit on Linux or a launchd agent on
+//! macOS that runs `dotdipper daemon start` with the current config, then
+//! enables and starts it. systemd sends the output to the journal; launchd
+//! appends it to the daemon log. `daemon uninstall` stops and removes it.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cfg::Config;
+use crate::ui;
+
+/// Name of the systemd user unit
+const SYSTEMD_UNIT: &str = "dotdipper.service";
+
+/// Label of the launchd agent
+const LAUNCHD_LABEL: &str = "com.dotdipper.daemon";
+
+/// Service manager the daemon is installed with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manager {
+    Systemd,
+    Launchd,
+}
+
+impl Manager {
+    /// The manager of this platform
+    pub fn current() -> Self {
+        if cfg!(target_os = "macos") {
+            Manager::Launchd
+        } else {
+            Manager::Systemd
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Manager::Systemd => "systemd user service",
+            Manager::Launchd => "launchd agent",
+        }
+    }
+
+    /// Where the unit or agent is installed
+    pub fn unit_path(self) -> Result<PathBuf> {
+        Ok(match self {
+            Manager::Systemd => dirs::config_dir()
+                .context("Failed to find the config directory")?
+                .join("systemd/user")
+                .join(SYSTEMD_UNIT),
+            Manager::Launchd => dirs::home_dir()
+                .context("Failed to find home directory")?
+                .join("Library/LaunchAgents")
+                .join(format!("{}.plist", LAUNCHD_LABEL)),
+        })
+    }
+
+    /// Command that enables the service at login, and with `start` also
+    /// starts it now
+    fn enable_command(self, unit: &Path, start: bool) -> Vec<String> {
+        match self {
+            Manager::Systemd if start => {
+                args(&["systemctl", "--user", "enable", "--now", SYSTEMD_UNIT])
+            }
+            Manager::Systemd => args(&["systemctl", "--user", "enable", SYSTEMD_UNIT]),
+            Manager::Launchd => {
+                let mut command = args(&["launchctl", "load", "-w"]);
+                command.push(unit.to_string_lossy().to_string());
+                command
+            }
+        }
+    }
+
+    /// Command that stops the service and keeps it from starting at login
+    fn disable_command(self, unit: &Path) -> Vec<String> {
+        match self {
+            Manager::Systemd => args(&["systemctl", "--user", "disable", "--now", SYSTEMD_UNIT]),
+            Manager::Launchd => {
+                let mut command = args(&["launchctl", "unload", "-w"]);
+                command.push(unit.to_string_lossy().to_string());
+                command
+            }
+        }
+    }
+}
+
+fn args(parts: &[&str]) -> Vec<String> {
+    parts.iter().map(|s| s.to_string()).collect()
+}
+
+/// Run a service manager command, failing with its output
+fn run(command: &[String]) -> Result<()> {
+    let output = Command::new(&command[0])
+        .args(&command[1..])
+        .output()
+        .with_context(|| format!("Failed to run {}", command[0]))?;
+    if !output.status.success() {
+        bail!(
+            "{} failed: {}",
+            command.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// What the service runs: this binary with the config and environment
+/// `dotdipper` was run with
+struct Invocation {
+    program: PathBuf,
+    config_path: PathBuf,
+    /// PATH, DOTDIPPER_HOME and DOTDIPPER_SYSTEM_CONFIG, so hooks, git and
+    /// the config resolve as they do in a shell
+    env: Vec<(String, String)>,
+}
+
+impl Invocation {
+    fn current(config_path: &Path) -> Result<Self> {
+        let env = ["PATH", "DOTDIPPER_HOME", crate::paths::SYSTEM_CONFIG_ENV]
+            .into_iter()
+            .filter_map(|key| Some((key.to_string(), std::env::var(key).ok()?)))
+            .collect();
+        Ok(Self {
+            program: std::env::current_exe().context("Failed to find the dotdipper executable")?,
+            config_path: std::path::absolute(config_path)?,
+            env,
+        })
+    }
+
+    fn args(&self) -> Vec<String> {
+        vec![
+            self.program.to_string_lossy().to_string(),
+            "--config".to_string(),
+            self.config_path.to_string_lossy().to_string(),
+            "daemon".to_string(),
+            "start".to_string(),
+        ]
+    }
+}
+
+/// Quote an argument or assignment for a systemd unit
+fn systemd_quote(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('%', "%%");
+    format!("\"{}\"", escaped)
+}
+
+fn render_systemd(invocation: &Invocation) -> String {
+    let exec: Vec<String> = invocation.args().iter().map(|a| systemd_quote(a)).collect();
+    let env: String = invocation
+        .env
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "Environment={}\n",
+                systemd_quote(&format!("{}={}", key, value))
+            )
+        })
+        .collect();
+    format!(
+        "# Installed by 'dotdipper daemon install'; remove with 'dotdipper daemon uninstall'\n\
+[Unit]\n\
+Description=dotdipper dotfiles daemon\n\
+\n\
+[Service]\n\
+Type=simple\n\
+ExecStart={}\n\
+{}Environment=NO_COLOR=1\n\
+Restart=on-failure\n\
+RestartSec=30\n\
+\n\
+[Install]\n\
+WantedBy=default.target\n",
+        exec.join(" "),
+        env
+    )
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn render_launchd(invocation: &Invocation, log_path: &Path) -> String {
+    let program_args: String = invocation
+        .args()
+        .iter()
+        .map(|a| format!("        <string>{}</string>\n", xml_escape(a)))
+        .collect();
+    let env: String = invocation
+        .env
+        .iter()
+        .chain(std::iter::once(&("NO_COLOR".to_string(), "1".to_string())))
+        .map(|(key, value)| {
+            format!(
+                "        <key>{}</key>\n        <string>{}</string>\n",
+                xml_escape(key),
+                xml_escape(value)
+            )
+        })
+        .collect();
+    let log = xml_escape(&log_path.to_string_lossy());
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<!-- Installed by 'dotdipper daemon install'; remove with 'dotdipper daemon uninstall' -->
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+{program_args}    </array>
+    <key>EnvironmentVariables</key>
+    <dict>
+{env}    </dict>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <dict>
+        <key>SuccessfulExit</key>
+        <false/>
+    </dict>
+    <key>WorkingDirectory</key>
+    <string>/</string>
+    <key>StandardOutPath</key>
+    <string>{log}</string>
+    <key>StandardErrorPath</key>
+    <string>{log}</string>
+</dict>
+</plist>
+"#,
+        label = LAUNCHD_LABEL,
+        program_args = program_args,
+        env = env,
+        log = log,
+    )
+}
+
+/// The unit or agent for `manager`
+fn render(manager: Manager, invocation: &Invocation) -> Result<String> {
+    Ok(match manager {
+        Manager::Systemd => render_systemd(invocation),
+        Manager::Launchd => render_launchd(invocation, &super::log_file()?),
+    })
+}
+
+/// `daemon install`: run the daemon at login, and with `start` right away
+pub fn install(config: &Config, config_path: &Path, start: bool) -> Result<()> {
+    if super::unattended_config(config)?.is_none() {
+        return Ok(());
+    }
+    let manager = Manager::current();
+    let unit = manager.unit_path()?;
+
+    // Reinstalling replaces a running service
+    if unit.exists() {
+        let _ = run(&manager.disable_command(&unit));
+    }
+    let pid_file = super::get_dotdipper_dir()?.join(super::DAEMON_PID_FILE);
+    if let Err(e) = super::ensure_not_running(&pid_file) {
+        ui::hint("Stop it with 'dotdipper daemon stop' so the service can take over");
+        return Err(e);
+    }
+
+    let contents = render(manager, &Invocation::current(config_path)?)?;
+    if let Some(parent) = unit.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if manager == Manager::Launchd {
+        fs::create_dir_all(crate::paths::logs_dir()?)?;
+    }
+    fs::write(&unit, contents).with_context(|| format!("Failed to write {}", unit.display()))?;
+    ui::success(&format!(
+        "Installed {} at {}",
+        manager.describe(),
+        unit.display()
+    ));
+
+    if manager == Manager::Systemd {
+        run(&args(&["systemctl", "--user", "daemon-reload"]))
+            .and_then(|()| run(&manager.enable_command(&unit, start)))
+            .with_context(|| {
+                format!(
+                    "The unit was written but not enabled; run '{}' once systemd is available",
+                    manager.enable_command(&unit, start).join(" ")
+                )
+            })?;
+    } else if start {
+        run(&manager.enable_command(&unit, start))?;
+    }
+
+    crate::history::Event::new(
+        crate::history::Kind::Daemon,
+        format!("Installed the daemon as a {}", manager.describe()),
+    )
+    .record();
+    match (start, manager) {
+        (true, Manager::Systemd) => {
+            ui::info("The daemon is running and starts at login");
+            ui::hint(&format!("Logs: journalctl --user -u {}", SYSTEMD_UNIT));
+        }
+        (true, Manager::Launchd) => {
+            ui::info("The daemon is running and starts at login");
+            ui::hint(&format!("Logs: {}", super::log_file()?.display()));
+        }
+        (false, _) => ui::info("The daemon starts at your next login"),
+    }
+    Ok(())
+}
+
+/// `daemon uninstall`: stop the service and remove its unit or agent
+pub fn uninstall() -> Result<()> {
+    let manager = Manager::current();
+    let unit = manager.unit_path()?;
+    if !unit.exists() {
+        ui::info(&format!("No {} is installed", manager.describe()));
+        return Ok(());
+    }
+
+    if let Err(e) = run(&manager.disable_command(&unit)) {
+        ui::warn(&format!("Could not stop the service: {:#}", e));
+    }
+    fs::remove_file(&unit).with_context(|| format!("Failed to remove {}", unit.display()))?;
+    if manager == Manager::Systemd {
+        let _ = run(&args(&["systemctl", "--user", "daemon-reload"]));
+    }
+
+    ui::success(&format!(
+        "Removed {} {}",
+        manager.describe(),
+        unit.display()
+    ));
+    crate::history::Event::new(
+        crate::history::Kind::Daemon,
+        format!("Uninstalled the daemon {}", manager.describe()),
+    )
+    .record();
+    Ok(())
+}
+
+/// Where the installed unit or agent is, if there is one
+pub fn installed() -> Option<PathBuf> {
+    Manager::current().unit_path().ok().filter(|p| p.exists())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_quotes_paths_for_each_manager() {
+        let invocation = Invocation {
+            program: PathBuf::from("/opt/my tools/dotdipper"),
+            config_path: PathBuf::from("/home/alice/.config/dotdipper/config.toml"),
+            env: vec![("PATH".to_string(), "/usr/bin:/opt/100%".to_string())],
+        };
+
+        let unit = render_systemd(&invocation);
+        assert!(unit.contains(
+            "ExecStart=\"/opt/my tools/dotdipper\" \"--config\" \
+             \"/home/alice/.config/dotdipper/config.toml\" \"daemon\" \"start\"\n"
+        ));
+        assert!(unit.contains("Environment=\"PATH=/usr/bin:/opt/100%%\"\n"));
+        assert!(unit.contains("WantedBy=default.target"));
+
+        let plist = render_launchd(&invocation, Path::new("/tmp/logs & more/daemon.log"));
+        assert!(plist.contains("<string>com.dotdipper.daemon</string>"));
+        assert!(plist.contains("        <string>/opt/my tools/dotdipper</string>\n"));
+        assert!(plist.contains("<string>/tmp/logs &amp; more/daemon.log</string>"));
+        assert!(plist.contains("<key>NO_COLOR</key>"));
+    }
+}
@@ -0,0 +1,340 @@
+//! `dotdipper daemon simulate`: what the daemon would have done, without doing it.
+//!
+//! Replay mode rebuilds the file events of a recent window from the daemon's
+//! event log (`logs/daemon-events.log`) and, for changes made while no daemon
+//! was running, the modification times of tracked files. The events are run
+//! through the same debounce rule as the daemon (a batch fires once no tracked
+//! file changed for `debounce_ms`) and every batch is reported with the action
+//! the configured mode would take. `--watch` does the same for live events.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, Local, Utc};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+use crate::hash::Manifest;
+use crate::matching::{display_tilde, to_home_relative};
+use crate::ui;
+
+/// Where a replayed event comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventSource {
+    /// Recorded by a running daemon
+    Log,
+    /// Inferred from a tracked file's modification time
+    Mtime,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEvent {
+    pub at: DateTime<Utc>,
+    pub path: PathBuf,
+    pub source: EventSource,
+}
+
+/// Events the daemon would have handled together
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Batch {
+    pub events: Vec<FileEvent>,
+    /// When the debounce window closed and the daemon would have acted
+    pub fires_at: DateTime<Utc>,
+}
+
+impl Batch {
+    pub fn files(&self) -> BTreeSet<&Path> {
+        self.events.iter().map(|e| e.path.as_path()).collect()
+    }
+}
+
+/// Group time-sorted events the way the daemon's debounce does: an event
+/// within `debounce` of the previous one joins the pending batch.
+pub fn debounce(events: Vec<FileEvent>, debounce: Duration) -> Vec<Batch> {
+    let mut batches: Vec<Batch> = Vec::new();
+    for event in events {
+        match batches.last_mut() {
+            Some(batch) if event.at < batch.fires_at => {
+                batch.fires_at = event.at + debounce;
+                batch.events.push(event);
+            }
+            _ => batches.push(Batch {
+                fires_at: event.at + debounce,
+                events: vec![event],
+            }),
+        }
+    }
+    batches
+}
+
+/// What the configured mode would do with a batch, and why
+pub fn decision(mode: &str, batch: &Batch, manifest: &Manifest, home: &Path) -> String {
+    let count = batch.files().len();
+    let action = match mode {
+        "auto" => format!(
+            "would create snapshot \"Auto-snapshot: {} files changed\"",
+            count
+        ),
+        "ask" => format!(
+            "would ask \"Create snapshot now?\" ({} files changed)",
+            count
+        ),
+        other => return format!("would do nothing: unknown daemon mode '{}'", other),
+    };
+
+    // A snapshot only records something if a file differs from the manifest
+    let unchanged = batch.files().into_iter().all(|path| {
+        let Some(stored) = manifest.get_file(&to_home_relative(path, home)) else {
+            return false;
+        };
+        crate::hash::hash_file(path).is_ok_and(|current| current.hash == stored.hash)
+    });
+    if unchanged {
+        format!(
+            "{} (files now match the last snapshot, so it would record nothing)",
+            action
+        )
+    } else {
+        action
+    }
+}
+
+fn time(at: DateTime<Utc>) -> String {
+    at.with_timezone(&Local)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn short(duration: Duration) -> String {
+    let ms = duration.num_milliseconds();
+    if ms < 1000 {
+        format!("{}ms", ms)
+    } else {
+        format!("{:.1}s", ms as f64 / 1000.0)
+    }
+}
+
+/// Print one batch with its debounce decisions and the resulting action
+pub fn print_batch(batch: &Batch, mode: &str, manifest: &Manifest, home: &Path) {
+    let mut previous: Option<DateTime<Utc>> = None;
+    for event in &batch.events {
+        let note = match previous {
+            None => "starts a batch".to_string(),
+            Some(prev) => format!(
+                "{} after the previous change, joins the batch",
+                short(event.at - prev)
+            ),
+        };
+        let source = match event.source {
+            EventSource::Log => "",
+            EventSource::Mtime => " [mtime]",
+        };
+        println!(
+            "  {}  change {}{}: {}",
+            time(event.at),
+            display_tilde(&to_home_relative(&event.path, home)),
+            source,
+            note
+        );
+        previous = Some(event.at);
+    }
+    println!(
+        "  {}  quiet for the debounce window: {}",
+        time(batch.fires_at),
+        decision(mode, batch, manifest, home)
+    );
+}
+
+/// Tracked files, with tracked directories expanded to the files inside
+fn tracked_files(config: &Config) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in &config.general.tracked_files {
+        if path.is_dir() {
+            files.extend(
+                walkdir::WalkDir::new(path)
+                    .into_iter()
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.file_type().is_file())
+                    .map(|e| e.into_path()),
+            );
+        } else if path.is_file() {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// Events since `since`: logged ones, plus mtimes of tracked files changed
+/// while no daemon was recording
+pub fn collect_events(config: &Config, since: DateTime<Utc>) -> Result<Vec<FileEvent>> {
+    let mut events: Vec<FileEvent> = super::read_event_log()?
+        .into_iter()
+        .filter(|(at, _)| *at >= since)
+        .map(|(at, path)| FileEvent {
+            at,
+            path,
+            source: EventSource::Log,
+        })
+        .collect();
+
+    for path in tracked_files(config) {
+        let Ok(modified) = fs::metadata(&path).and_then(|m| m.modified()) else {
+            continue;
+        };
+        let at: DateTime<Utc> = modified.into();
+        let logged = events
+            .iter()
+            .any(|e| e.path == path && (e.at - at).num_milliseconds().abs() < 2000);
+        if at >= since && !logged {
+            events.push(FileEvent {
+                at,
+                path,
+                source: EventSource::Mtime,
+            });
+        }
+    }
+
+    events.sort_by(|a, b| a.at.cmp(&b.at).then_with(|| a.path.cmp(&b.path)));
+    Ok(events)
+}
+
+pub(super) fn load_manifest() -> Result<Manifest> {
+    let path = crate::paths::manifest_file()?;
+    if path.exists() {
+        Manifest::load(&path)
+    } else {
+        Ok(Manifest::new())
+    }
+}
+
+/// Mode and debounce the daemon would run with
+pub fn daemon_settings(config: &Config) -> (String, u64) {
+    match &config.daemon {
+        Some(daemon) => {
+            if !daemon.enabled {
+                ui::warn(
+                    "The daemon is disabled in config.toml; simulating its configured settings",
+                );
+            }
+            (daemon.mode.clone(), daemon.debounce_ms)
+        }
+        None => {
+            ui::warn("No [daemon] section in config.toml; simulating the defaults");
+            (super::default_daemon_mode(), super::default_debounce_ms())
+        }
+    }
+}
+
+/// Replay the last `window` of file events
+pub fn replay(config: &Config, window: &str) -> Result<()> {
+    let Some(window) = crate::snapshots::parse_duration(window) else {
+        bail!(
+            "Invalid duration '{}'. Use a number and a unit: 12h, 2d, 1w",
+            window
+        );
+    };
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let (mode, debounce_ms) = daemon_settings(config);
+    let since = Utc::now() - window;
+
+    ui::info(&format!(
+        "Simulating the daemon in '{}' mode (debounce: {}ms) since {}. Nothing will be changed.",
+        mode,
+        debounce_ms,
+        time(since)
+    ));
+
+    let events = collect_events(config, since)?;
+    let batches = debounce(events, Duration::milliseconds(debounce_ms as i64));
+    if batches.is_empty() {
+        ui::success("No tracked files changed in this window; the daemon would have done nothing");
+    } else {
+        let manifest = load_manifest()?;
+        for (i, batch) in batches.iter().enumerate() {
+            ui::section(&format!("Batch {}", i + 1));
+            print_batch(batch, &mode, &manifest, &home);
+        }
+        println!();
+        ui::info(&format!(
+            "{} file events would have triggered {} {}",
+            batches.iter().map(|b| b.events.len()).sum::<usize>(),
+            batches.len(),
+            if mode == "auto" {
+                "snapshots"
+            } else {
+                "prompts"
+            }
+        ));
+    }
+
+    report_queued_pushes();
+    Ok(())
+}
+
+/// Pushes the daemon would retry from the offline queue
+pub fn report_queued_pushes() {
+    let pending = crate::offline::load_pending().unwrap_or_default();
+    if !pending.is_empty() {
+        ui::info(&format!(
+            "Would retry {} queued push(es) from the offline queue every {}s",
+            pending.len(),
+            super::QUEUE_FLUSH_INTERVAL.as_secs()
+        ));
+    }
+}
+
+/// Watch live events, reporting decisions instead of acting on them
+pub fn watch(config: &Config) -> Result<()> {
+    let (mode, debounce_ms) = daemon_settings(config);
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let tracked = super::watch::tracked_paths(config, &home);
+    if tracked.is_empty() {
+        bail!("No tracked files configured. Add files with 'dotdipper discover --write'");
+    }
+    ui::info(&format!(
+        "Simulating the daemon in '{}' mode (debounce: {}ms). Nothing will be changed; Ctrl-C to stop.",
+        mode, debounce_ms
+    ));
+    report_queued_pushes();
+    super::run_daemon_loop(tracked, debounce_ms, &mode, None, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(secs: i64, path: &str) -> FileEvent {
+        FileEvent {
+            at: DateTime::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+            path: PathBuf::from(path),
+            source: EventSource::Log,
+        }
+    }
+
+    #[test]
+    fn test_debounce_groups_bursts_of_changes() {
+        let batches = debounce(
+            vec![
+                event(0, "/h/.zshrc"),
+                event(1, "/h/.zshrc"),
+                event(2, "/h/.vimrc"),
+                event(10, "/h/.zshrc"),
+            ],
+            Duration::milliseconds(1500),
+        );
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].events.len(), 3);
+        assert_eq!(batches[0].files().len(), 2);
+        assert_eq!(
+            batches[0].fires_at,
+            event(2, "").at + Duration::milliseconds(1500)
+        );
+        assert_eq!(batches[1].events.len(), 1);
+
+        let manifest = Manifest::new();
+        let home = Path::new("/h");
+        assert!(decision("auto", &batches[0], &manifest, home).contains("2 files changed"));
+        assert!(decision("ask", &batches[1], &manifest, home).starts_with("would ask"));
+    }
+}
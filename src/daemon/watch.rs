@@ -0,0 +1,339 @@
+//! Watching tracked files for changes, shared by the daemon and the
+//! `--watch` mode of `status` and `diff`.
+
+use anyhow::{Context, Result};
+use notify::{Event as NotifyEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use crate::cfg::Config;
+use crate::matching::PathMatcher;
+use crate::ui;
+
+/// Quiet time after the last change before `--watch` renders again
+const RERENDER_DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// Everything a snapshot takes files from: `tracked_files`, the
+/// `[tracked_dirs]` directories and the `[secrets] tracked` entries
+pub fn tracked_paths(config: &Config, home: &Path) -> Vec<PathBuf> {
+    let mut paths = config.general.tracked_files.clone();
+    let secrets = config
+        .secrets
+        .as_ref()
+        .map(|s| s.tracked.as_slice())
+        .unwrap_or_default();
+    let entries = config.tracked_dirs.keys().chain(secrets);
+    for path in entries.map(|entry| crate::matching::expand_tilde(entry, home)) {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// What one notification touched
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// Tracked paths that changed
+    pub tracked: Vec<PathBuf>,
+    /// Whether the config file, or a fragment it includes, changed
+    pub config: bool,
+}
+
+/// Notifications for changes to tracked files, from watching the
+/// directories they live in. Tracked directories are watched recursively,
+/// so files created in them later are seen too.
+pub struct TrackedWatcher {
+    // Dropping the watcher stops the notifications
+    watcher: RecommendedWatcher,
+    rx: Receiver<NotifyEvent>,
+    home: PathBuf,
+    tracked: Vec<PathBuf>,
+    config_files: Vec<PathBuf>,
+    matcher: PathMatcher,
+    watched: BTreeMap<PathBuf, RecursiveMode>,
+}
+
+impl TrackedWatcher {
+    pub fn new(tracked_files: &[PathBuf], home: &Path) -> Result<Self> {
+        let (tx, rx) = channel();
+        let watcher =
+            notify::recommended_watcher(move |res: Result<NotifyEvent, notify::Error>| {
+                if let Ok(event) = res {
+                    let _ = tx.send(event);
+                }
+            })?;
+
+        let mut tracked_watcher = Self {
+            watcher,
+            rx,
+            home: home.to_path_buf(),
+            tracked: Vec::new(),
+            config_files: Vec::new(),
+            matcher: PathMatcher::for_paths::<String>(home, &[])?,
+            watched: BTreeMap::new(),
+        };
+        tracked_watcher.retrack(tracked_files)?;
+        Ok(tracked_watcher)
+    }
+
+    /// Watch `tracked_files` instead of the files watched so far
+    pub fn retrack(&mut self, tracked_files: &[PathBuf]) -> Result<()> {
+        let tracked_strs: Vec<String> = tracked_files
+            .iter()
+            .map(|p| p.to_string_lossy().to_string())
+            .collect();
+        self.matcher = PathMatcher::for_paths(&self.home, &tracked_strs)?;
+        self.tracked = tracked_files.to_vec();
+        self.update_watches();
+        Ok(())
+    }
+
+    /// Also report changes to `config_path`, the other layers of the config
+    /// and the fragments they include. Called again after a reload, since
+    /// the includes may have changed.
+    pub fn watch_config(&mut self, config_path: &Path) {
+        self.config_files = crate::cfg::config_files(config_path)
+            .unwrap_or_else(|_| vec![config_path.to_path_buf()]);
+        self.update_watches();
+    }
+
+    /// Directories to watch: tracked directories recursively, and the
+    /// parent of every tracked file and config file on its own
+    fn targets(&self) -> BTreeMap<PathBuf, RecursiveMode> {
+        let mut targets = BTreeMap::new();
+        for path in &self.tracked {
+            if path.is_dir() && !path.is_symlink() {
+                targets.insert(path.clone(), RecursiveMode::Recursive);
+            }
+        }
+        for path in self.tracked.iter().chain(&self.config_files) {
+            if targets.contains_key(path) {
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                targets
+                    .entry(parent.to_path_buf())
+                    .or_insert(RecursiveMode::NonRecursive);
+            }
+        }
+
+        // A recursive watch already covers everything under it
+        let recursive: Vec<PathBuf> = targets
+            .iter()
+            .filter(|(_, mode)| **mode == RecursiveMode::Recursive)
+            .map(|(dir, _)| dir.clone())
+            .collect();
+        targets.retain(|dir, _| {
+            !recursive
+                .iter()
+                .any(|other| other != dir && dir.starts_with(other))
+        });
+        targets
+    }
+
+    fn update_watches(&mut self) {
+        let targets = self.targets();
+        let stale: Vec<PathBuf> = self
+            .watched
+            .iter()
+            .filter(|(dir, mode)| targets.get(*dir) != Some(*mode))
+            .map(|(dir, _)| dir.clone())
+            .collect();
+        for dir in stale {
+            let _ = self.watcher.unwatch(&dir);
+            self.watched.remove(&dir);
+        }
+        for (dir, mode) in targets {
+            if self.watched.contains_key(&dir) {
+                continue;
+            }
+            match self.watcher.watch(&dir, mode) {
+                Ok(()) => {
+                    self.watched.insert(dir, mode);
+                }
+                Err(e) => ui::warn(&format!("Failed to watch {}: {}", dir.display(), e)),
+            }
+        }
+    }
+
+    /// Number of directories being watched
+    pub fn dirs(&self) -> usize {
+        self.watched.len()
+    }
+
+    /// What the next event touched, waiting at most `timeout` (nothing if
+    /// the event only touched untracked files). Editors that save by
+    /// writing a temporary file and renaming it over the original report
+    /// the original, and the temporary file is left out.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Changes, RecvTimeoutError> {
+        let event = self.rx.recv_timeout(timeout)?;
+        let mut changes = Changes::default();
+        for path in event.paths {
+            if self.config_files.contains(&path) {
+                changes.config = true;
+            }
+            // Directories show up as the files in them change
+            let tracked = self.tracked.contains(&path)
+                || (self.matcher.is_match(&path) && !is_editor_temp(&path) && !path.is_dir());
+            if tracked && !changes.tracked.contains(&path) {
+                changes.tracked.push(path);
+            }
+        }
+        Ok(changes)
+    }
+}
+
+/// Whether `path` looks like a file an editor writes next to the one being
+/// edited: a swap, lock or backup file, or the temporary file of a save
+fn is_editor_temp(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return false;
+    };
+    // 4913 is the file vim creates to check that a directory is writable
+    name == "4913"
+        || name.ends_with('~')
+        || name.starts_with(".#")
+        || (name.len() > 1 && name.starts_with('#') && name.ends_with('#'))
+        || [
+            ".swp",
+            ".swo",
+            ".swx",
+            ".tmp",
+            "___jb_tmp___",
+            "___jb_old___",
+        ]
+        .iter()
+        .any(|suffix| name.ends_with(suffix))
+}
+
+/// Run `render` now and again whenever tracked files change, like
+/// `watch git status`, until interrupted. Errors are shown, not fatal.
+pub fn rerun_on_change(config: &Config, mut render: impl FnMut() -> Result<()>) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let watcher = TrackedWatcher::new(&tracked_paths(config, &home), &home)?;
+    let interactive = std::io::stdout().is_terminal();
+
+    let mut rerender = |changed: usize| {
+        if interactive {
+            // Clear the screen and move to the top left
+            print!("\x1B[2J\x1B[H");
+        }
+        if let Err(e) = render() {
+            ui::error(&format!("{:#}", e));
+        }
+        println!();
+        let reason = match changed {
+            0 => String::new(),
+            n => format!(" ({} file(s) changed)", n),
+        };
+        ui::hint(&format!(
+            "Watching {} directories{}; press Ctrl-C to stop",
+            watcher.dirs(),
+            reason
+        ));
+        let _ = std::io::stdout().flush();
+    };
+    rerender(0);
+
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    let mut last_event: Option<Instant> = None;
+    loop {
+        match watcher.recv_timeout(Duration::from_millis(100)) {
+            Ok(changes) if !changes.tracked.is_empty() => {
+                pending.extend(changes.tracked);
+                last_event = Some(Instant::now());
+            }
+            Ok(_) => {}
+            Err(RecvTimeoutError::Timeout) => {
+                if last_event.is_some_and(|t| t.elapsed() >= RERENDER_DEBOUNCE) {
+                    rerender(pending.len());
+                    pending.clear();
+                    last_event = None;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracked_watcher_reports_only_tracked_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let tracked = dir.path().join(".zshrc");
+        std::fs::write(&tracked, "one\n").unwrap();
+        let watcher = TrackedWatcher::new(std::slice::from_ref(&tracked), dir.path()).unwrap();
+        assert_eq!(watcher.dirs(), 1);
+
+        std::fs::write(dir.path().join("other"), "x\n").unwrap();
+        std::fs::write(&tracked, "two\n").unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut seen = Vec::new();
+        while Instant::now() < deadline && !seen.contains(&tracked) {
+            if let Ok(changes) = watcher.recv_timeout(Duration::from_millis(100)) {
+                seen.extend(changes.tracked);
+            }
+        }
+        assert!(seen.contains(&tracked));
+        assert!(seen.iter().all(|p| p == &tracked));
+    }
+
+    #[test]
+    fn test_tracked_watcher_follows_directories_saves_and_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path();
+        let nvim = home.join(".config/nvim");
+        let zshrc = home.join(".zshrc");
+        let config_path = home.join(".config/dotdipper/config.toml");
+        std::fs::create_dir_all(&nvim).unwrap();
+        std::fs::create_dir_all(config_path.parent().unwrap()).unwrap();
+        std::fs::write(nvim.join("init.lua"), "one\n").unwrap();
+        std::fs::write(&zshrc, "one\n").unwrap();
+        std::fs::write(&config_path, "[general]\n").unwrap();
+
+        let mut watcher = TrackedWatcher::new(std::slice::from_ref(&nvim), home).unwrap();
+        watcher.watch_config(&config_path);
+        let collect = |watcher: &TrackedWatcher, until: &dyn Fn(&Changes) -> bool| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            let mut seen = Changes::default();
+            while Instant::now() < deadline && !until(&seen) {
+                if let Ok(changes) = watcher.recv_timeout(Duration::from_millis(100)) {
+                    seen.tracked.extend(changes.tracked);
+                    seen.config |= changes.config;
+                }
+            }
+            seen
+        };
+
+        // A file created in a new subdirectory, and an atomic save
+        let plugins = nvim.join("lua/plugins.lua");
+        std::fs::create_dir_all(plugins.parent().unwrap()).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        std::fs::write(&plugins, "return {}\n").unwrap();
+        std::fs::write(nvim.join("init.lua.tmp"), "two\n").unwrap();
+        std::fs::rename(nvim.join("init.lua.tmp"), nvim.join("init.lua")).unwrap();
+        let seen = collect(&watcher, &|seen| {
+            seen.tracked.contains(&plugins) && seen.tracked.contains(&nvim.join("init.lua"))
+        });
+        assert!(seen.tracked.contains(&plugins), "{:?}", seen);
+        assert!(seen.tracked.contains(&nvim.join("init.lua")), "{:?}", seen);
+        assert!(!seen.tracked.contains(&nvim.join("init.lua.tmp")));
+
+        std::fs::write(&config_path, "[general]\ntracked_files = []\n").unwrap();
+        assert!(collect(&watcher, &|seen| seen.config).config);
+
+        // After retracking only the new set is reported
+        watcher.retrack(std::slice::from_ref(&zshrc)).unwrap();
+        std::fs::write(nvim.join("init.lua"), "three\n").unwrap();
+        std::fs::write(&zshrc, "two\n").unwrap();
+        let seen = collect(&watcher, &|seen| seen.tracked.contains(&zshrc));
+        assert_eq!(seen.tracked, vec![zshrc]);
+    }
+}
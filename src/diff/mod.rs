@@ -3,18 +3,23 @@ use colored::*;
 use dialoguer::{theme::ColorfulTheme, MultiSelect};
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 
 use crate::cfg::Config;
 use crate::hash::Manifest;
+use crate::matching::{display_tilde, PathMatcher};
 use crate::ui;
 
+mod view;
+pub use view::{DiffTool, DiffView};
+
 #[derive(Debug, Clone)]
 pub struct DiffEntry {
     pub rel_path: PathBuf,
     pub source_path: PathBuf,
     pub target_path: PathBuf,
     pub status: DiffStatus,
+    /// Old path of a file renamed since the last apply, still at that path
+    pub renamed_from: Option<PathBuf>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -22,6 +27,7 @@ pub enum DiffStatus {
     Modified,
     New,
     Missing,
+    Renamed,
     Identical,
 }
 
@@ -31,51 +37,103 @@ impl DiffStatus {
             DiffStatus::Modified => "M".yellow(),
             DiffStatus::New => "A".green(),
             DiffStatus::Missing => "D".red(),
+            DiffStatus::Renamed => "R".cyan(),
             DiffStatus::Identical => "=".dimmed(),
         }
     }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DiffStatus::Modified => "modified",
+            DiffStatus::New => "new",
+            DiffStatus::Missing => "missing",
+            DiffStatus::Renamed => "renamed",
+            DiffStatus::Identical => "identical",
+        }
+    }
+}
+
+/// The entries that differ, as JSON for `diff --output json`
+pub fn to_json(entries: &[DiffEntry], config: &Config) -> serde_json::Value {
+    let protected = protected_changes(entries, config);
+    let changed: Vec<serde_json::Value> = entries
+        .iter()
+        .filter(|e| e.status != DiffStatus::Identical)
+        .map(|e| {
+            serde_json::json!({
+                "path": crate::matching::display_tilde(&e.rel_path),
+                "status": e.status.name(),
+                "renamed_from": e.renamed_from.as_deref().map(crate::matching::display_tilde),
+                "protected": protected.iter().any(|p| p.rel_path == e.rel_path),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "identical": entries.iter().filter(|e| e.status == DiffStatus::Identical).count(),
+        "changes": changed,
+    })
 }
 
-/// Generate diff between compiled files and system files
+/// Generate diff between compiled files and system files, showing the
+/// changes to each modified file with `view`
 pub fn diff(
     compiled_root: &Path,
     manifest: &Manifest,
-    _config: &Config,
-    detailed: bool,
+    config: &Config,
+    view: Option<&DiffView>,
+) -> Result<Vec<DiffEntry>> {
+    ui::info("Computing differences...");
+    let entries = compute(compiled_root, manifest, config)?;
+
+    // Print summary
+    print_diff_summary(&entries, config, view)?;
+
+    Ok(entries)
+}
+
+/// Compare compiled files with the files in `$HOME`, without printing
+pub fn compute(
+    compiled_root: &Path,
+    manifest: &Manifest,
+    config: &Config,
 ) -> Result<Vec<DiffEntry>> {
     let home_dir = dirs::home_dir().context("Failed to find home directory")?;
+    let variables = crate::template::Variables::local(config)?;
     let mut entries = Vec::new();
 
-    ui::info("Computing differences...");
-
     // Sort manifest keys for deterministic output
     let mut manifest_files: Vec<_> = manifest.files.iter().collect();
     manifest_files.sort_by_key(|(path, _)| path.as_path());
 
+    // Files renamed since the last apply show up as moves, not as missing
+    let previous = crate::repo::apply::load_applied()?;
+    let moves = crate::repo::apply::pending_moves(&previous, manifest, &home_dir);
+
     for (rel_path, file_hash) in manifest_files {
         let source_path = compiled_root.join(rel_path);
-        let target_path = home_dir.join(rel_path);
-
-        let status = if !target_path.exists() {
-            DiffStatus::Missing
-        } else if target_path.is_symlink() {
-            // Check if symlink points to source
-            match fs::read_link(&target_path) {
-                Ok(link) if link == source_path => DiffStatus::Identical,
-                _ => DiffStatus::Modified,
-            }
+        let target_path = crate::system::target(&home_dir, rel_path);
+
+        let status = match compare(
+            config,
+            &variables,
+            rel_path,
+            &source_path,
+            &target_path,
+            &file_hash.hash,
+        ) {
+            None => DiffStatus::Identical,
+            Some(Drift::Missing | Drift::BrokenLink) => DiffStatus::Missing,
+            Some(Drift::Modified | Drift::Relinked) => DiffStatus::Modified,
+        };
+
+        let renamed_from = moves
+            .iter()
+            .find(|(_, new)| new == rel_path)
+            .map(|(old, _)| old.clone());
+        let status = if renamed_from.is_some() {
+            DiffStatus::Renamed
         } else {
-            // Compare hashes
-            match crate::hash::hash_file(&target_path) {
-                Ok(target_hash) => {
-                    if target_hash.hash == file_hash.hash {
-                        DiffStatus::Identical
-                    } else {
-                        DiffStatus::Modified
-                    }
-                }
-                Err(_) => DiffStatus::Missing,
-            }
+            status
         };
 
         entries.push(DiffEntry {
@@ -83,17 +141,213 @@ pub fn diff(
             source_path: source_path.clone(),
             target_path: target_path.clone(),
             status,
+            renamed_from,
         });
     }
 
-    // Print summary
-    print_diff_summary(&entries, detailed)?;
-
     Ok(entries)
 }
 
-/// Print a summary of the diff
-pub fn print_diff_summary(entries: &[DiffEntry], detailed: bool) -> Result<()> {
+/// How the file at `target_path` differs from its compiled version at
+/// `source_path` (whose contents hash to `hash`), or None if it matches
+fn compare(
+    config: &Config,
+    variables: &crate::template::Variables,
+    rel_path: &Path,
+    source_path: &Path,
+    target_path: &Path,
+    hash: &str,
+) -> Option<Drift> {
+    if target_path.is_symlink() {
+        if !target_path.exists() {
+            return Some(Drift::BrokenLink);
+        }
+        return (!crate::links::points_to(target_path, source_path)).then_some(Drift::Relinked);
+    }
+    if !target_path.exists() {
+        return Some(Drift::Missing);
+    }
+    // Templates are compared with what they render to here
+    if crate::template::is_template(config, rel_path) {
+        return (!variables.is_rendered(source_path, target_path)).then_some(Drift::Modified);
+    }
+    match crate::hash::hash_file(target_path) {
+        Ok(target_hash) => (target_hash.hash != hash).then_some(Drift::Modified),
+        Err(_) => Some(Drift::Missing),
+    }
+}
+
+/// Ways a file applied to `$HOME` stopped matching the compiled repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drift {
+    /// Symlink whose destination is gone
+    BrokenLink,
+    /// Symlink to something other than the compiled file
+    Relinked,
+    /// Contents edited since they were applied
+    Modified,
+    /// Removed from `$HOME`
+    Missing,
+}
+
+impl Drift {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Drift::BrokenLink => "broken-link",
+            Drift::Relinked => "relinked",
+            Drift::Modified => "modified",
+            Drift::Missing => "missing",
+        }
+    }
+
+    fn describe(&self) -> ColoredString {
+        match self {
+            Drift::BrokenLink => "broken symlink".red(),
+            Drift::Relinked => "symlink points elsewhere".yellow(),
+            Drift::Modified => "modified since apply".yellow(),
+            Drift::Missing => "missing".red(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DriftEntry {
+    pub rel_path: PathBuf,
+    pub target_path: PathBuf,
+    pub drift: Drift,
+    /// Where the symlink points, for links
+    pub link: Option<PathBuf>,
+}
+
+/// Files applied on this machine (per the applied record) that no longer
+/// match the compiled repository: broken or redirected symlinks, edited
+/// copies and removed files. Also returns how many files were checked.
+pub fn applied_drift(compiled_root: &Path, config: &Config) -> Result<(Vec<DriftEntry>, usize)> {
+    let home_dir = dirs::home_dir().context("Failed to find home directory")?;
+    let variables = crate::template::Variables::local(config)?;
+    let applied = crate::repo::apply::load_applied()?;
+
+    let mut files: Vec<_> = applied.files.iter().collect();
+    files.sort_by_key(|(path, _)| path.as_path());
+    let mut entries = Vec::new();
+    for (rel_path, file_hash) in &files {
+        let source_path = compiled_root.join(rel_path);
+        let drift = if rel_path.extension().is_some_and(|e| e == "age") {
+            // Decrypted copies can't be compared without the key
+            let target_path = crate::system::target(&home_dir, &rel_path.with_extension(""));
+            (!target_path.exists()).then_some((target_path, Drift::Missing))
+        } else {
+            let target_path = crate::system::target(&home_dir, rel_path);
+            compare(
+                config,
+                &variables,
+                rel_path,
+                &source_path,
+                &target_path,
+                &file_hash.hash,
+            )
+            .map(|drift| (target_path, drift))
+        };
+        if let Some((target_path, drift)) = drift {
+            entries.push(DriftEntry {
+                rel_path: rel_path.to_path_buf(),
+                link: fs::read_link(&target_path).ok(),
+                target_path,
+                drift,
+            });
+        }
+    }
+    Ok((entries, files.len()))
+}
+
+/// Print the result of [`applied_drift`]
+pub fn print_drift(entries: &[DriftEntry], checked: usize) {
+    if checked == 0 {
+        ui::info("Nothing has been applied on this machine yet");
+        return;
+    }
+    if entries.is_empty() {
+        ui::success(&format!(
+            "All {} applied file(s) match the compiled repository",
+            checked
+        ));
+        return;
+    }
+    ui::warn(&format!(
+        "{} of {} applied file(s) drifted from the compiled repository:",
+        entries.len(),
+        checked
+    ));
+    for entry in entries {
+        let link = match &entry.link {
+            Some(dest) => format!(" -> {}", dest.display()),
+            None => String::new(),
+        };
+        println!(
+            "  ~/{}{} ({})",
+            entry.rel_path.display(),
+            link,
+            entry.drift.describe()
+        );
+    }
+    ui::hint("Run 'dotdipper apply' to restore them, or 'dotdipper snapshot' to keep the changes");
+}
+
+/// Drifted files as JSON for `status --applied --output json`
+pub fn drift_to_json(entries: &[DriftEntry], checked: usize) -> serde_json::Value {
+    serde_json::json!({
+        "checked": checked,
+        "drifted": entries
+            .iter()
+            .map(|e| serde_json::json!({
+                "path": crate::matching::display_tilde(&e.rel_path),
+                "drift": e.drift.name(),
+                "link": e.link.as_ref().map(|l| l.display().to_string()),
+            }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+/// Changed entries for files marked `protected = true`
+pub fn protected_changes<'a>(entries: &'a [DiffEntry], config: &Config) -> Vec<&'a DiffEntry> {
+    entries
+        .iter()
+        .filter(|e| e.status != DiffStatus::Identical)
+        .filter(|e| {
+            config
+                .file_override(&e.rel_path)
+                .is_some_and(|o| o.protected)
+        })
+        .collect()
+}
+
+/// Print a summary of the diff, with the changes to each modified file if
+/// given a `view`
+pub fn print_diff_summary(
+    entries: &[DiffEntry],
+    config: &Config,
+    view: Option<&DiffView>,
+) -> Result<()> {
+    let protected = protected_changes(entries, config);
+    if !protected.is_empty() {
+        ui::warn(&format!(
+            "{} protected file(s) differ from the stored version:",
+            protected.len()
+        ));
+        for entry in &protected {
+            ui::line(&format!(
+                "  {} {} {}",
+                "!".red().bold(),
+                entry.status.symbol(),
+                entry_label(entry)
+            ));
+        }
+        ui::hint(
+            "Apply asks before overwriting each one, even with --force (or pass --yes-protected)",
+        );
+        ui::line("");
+    }
+
     let modified: Vec<_> = entries
         .iter()
         .filter(|e| e.status == DiffStatus::Modified)
@@ -110,93 +364,138 @@ pub fn print_diff_summary(entries: &[DiffEntry], detailed: bool) -> Result<()> {
         .iter()
         .filter(|e| e.status == DiffStatus::Identical)
         .collect();
+    let renamed: Vec<_> = entries
+        .iter()
+        .filter(|e| e.status == DiffStatus::Renamed)
+        .collect();
 
     ui::section("Diff Summary");
-    println!("  {} modified", modified.len().to_string().yellow());
-    println!(
+    ui::line(&format!(
+        "  {} modified",
+        modified.len().to_string().yellow()
+    ));
+    ui::line(&format!(
         "  {} new (not yet on system)",
         new.len().to_string().green()
-    );
-    println!("  {} missing from system", missing.len().to_string().red());
-    println!("  {} identical", identical.len().to_string().dimmed());
-    println!();
+    ));
+    ui::line(&format!(
+        "  {} missing from system",
+        missing.len().to_string().red()
+    ));
+    if !renamed.is_empty() {
+        ui::line(&format!("  {} renamed", renamed.len().to_string().cyan()));
+    }
+    ui::line(&format!(
+        "  {} identical",
+        identical.len().to_string().dimmed()
+    ));
+    ui::line("");
 
     // Show detailed listing
     if !modified.is_empty() {
-        println!("{}", "Modified files:".yellow().bold());
+        ui::line(&"Modified files:".yellow().bold().to_string());
         for entry in &modified {
-            println!("  {} ~/{}", entry.status.symbol(), entry.rel_path.display());
-
-            if detailed {
-                show_file_diff(&entry.target_path, &entry.source_path)?;
+            ui::line(&format!(
+                "  {} {}",
+                entry.status.symbol(),
+                display_tilde(&entry.rel_path)
+            ));
+
+            if let Some(view) = view {
+                show_file_diff(&entry.target_path, &entry.source_path, view)?;
             }
         }
-        println!();
+        ui::line("");
     }
 
     if !missing.is_empty() {
-        println!("{}", "Missing from system:".red().bold());
+        ui::line(&"Missing from system:".red().bold().to_string());
         for entry in &missing {
-            println!("  {} ~/{}", entry.status.symbol(), entry.rel_path.display());
+            ui::line(&format!(
+                "  {} {}",
+                entry.status.symbol(),
+                display_tilde(&entry.rel_path)
+            ));
         }
-        println!();
+        ui::line("");
+    }
+
+    if !renamed.is_empty() {
+        ui::line(&"Renamed (moved on apply):".cyan().bold().to_string());
+        for entry in &renamed {
+            ui::line(&format!(
+                "  {} {}",
+                entry.status.symbol(),
+                entry_label(entry)
+            ));
+        }
+        ui::line("");
     }
 
     if !new.is_empty() {
-        println!("{}", "New files (not yet applied):".green().bold());
+        ui::line(&"New files (not yet applied):".green().bold().to_string());
         for entry in &new {
-            println!("  {} ~/{}", entry.status.symbol(), entry.rel_path.display());
+            ui::line(&format!(
+                "  {} {}",
+                entry.status.symbol(),
+                display_tilde(&entry.rel_path)
+            ));
         }
-        println!();
+        ui::line("");
     }
 
     Ok(())
 }
 
+/// The counts of a diff on one line, for `--quiet`
+pub fn result_line(entries: &[DiffEntry]) -> String {
+    let count = |status| entries.iter().filter(|e| e.status == status).count();
+    let renamed = match count(DiffStatus::Renamed) {
+        0 => String::new(),
+        n => format!(", {} renamed", n),
+    };
+    format!(
+        "{} modified, {} new, {} missing{}, {} identical",
+        count(DiffStatus::Modified),
+        count(DiffStatus::New),
+        count(DiffStatus::Missing),
+        renamed,
+        count(DiffStatus::Identical)
+    )
+}
+
+/// `~/path`, or `~/old -> ~/new` for renames
+pub fn entry_label(entry: &DiffEntry) -> String {
+    match &entry.renamed_from {
+        Some(old) => format!(
+            "{} -> {}",
+            display_tilde(old),
+            display_tilde(&entry.rel_path)
+        ),
+        None => display_tilde(&entry.rel_path),
+    }
+}
+
 /// Show detailed diff for a specific file
-pub fn show_file_diff(target: &Path, source: &Path) -> Result<()> {
+pub fn show_file_diff(target: &Path, source: &Path, view: &DiffView) -> Result<()> {
     // Check if files are binary
     if is_binary(source)? || (target.exists() && is_binary(target)?) {
-        println!("    {}", "(binary file)".dimmed());
+        ui::line(&format!("    {}", "(binary file)".dimmed()));
         if target.exists() {
             let source_size = fs::metadata(source)?.len();
             let target_size = fs::metadata(target)?.len();
-            println!(
+            ui::line(&format!(
                 "    Source: {} bytes, Target: {} bytes",
                 source_size, target_size
-            );
+            ));
         }
         return Ok(());
     }
 
-    // Use git diff for text files
     if target.exists() {
-        let output = Command::new("git")
-            .arg("diff")
-            .arg("--no-index")
-            .arg("--color=always")
-            .arg("--")
-            .arg(target)
-            .arg(source)
-            .output();
-
-        match output {
-            Ok(out) if out.status.code() == Some(1) || out.status.success() => {
-                // Exit code 1 means differences found, which is expected
-                let diff_output = String::from_utf8_lossy(&out.stdout);
-                // Print diff with indentation
-                for line in diff_output.lines().skip(4) {
-                    // Skip header lines
-                    println!("    {}", line);
-                }
-            }
-            _ => {
-                // Fallback to simple comparison
-                println!("    {}", "Differs from source".yellow());
-            }
-        }
+        view.show(target, source)?;
     } else {
-        println!("    {}", "File missing from system".red());
+        ui::line(&format!("    {}", "File missing from system".red()));
     }
 
     Ok(())
@@ -219,6 +518,11 @@ fn is_binary(path: &Path) -> Result<bool> {
 
 /// Interactive file selection for apply
 pub fn interactive_select(entries: &[DiffEntry]) -> Result<Vec<PathBuf>> {
+    interactive_select_with(entries, "Select files to apply")
+}
+
+/// Like [`interactive_select`], under a custom heading
+pub fn interactive_select_with(entries: &[DiffEntry], heading: &str) -> Result<Vec<PathBuf>> {
     // Filter to only files that can be applied (not identical)
     let applicable: Vec<_> = entries
         .iter()
@@ -232,10 +536,10 @@ pub fn interactive_select(entries: &[DiffEntry]) -> Result<Vec<PathBuf>> {
 
     let items: Vec<String> = applicable
         .iter()
-        .map(|e| format!("{} ~/{}", e.status.symbol(), e.rel_path.display()))
+        .map(|e| format!("{} {}", e.status.symbol(), entry_label(e)))
         .collect();
 
-    ui::section("Select files to apply");
+    ui::section(heading);
 
     let selections = MultiSelect::with_theme(&ColorfulTheme::default())
         .items(&items)
@@ -250,35 +554,20 @@ pub fn interactive_select(entries: &[DiffEntry]) -> Result<Vec<PathBuf>> {
 }
 
 /// Filter entries by path patterns
+///
+/// Filters use the shared [`crate::matching`] semantics: literal paths are
+/// home-relative and also select everything below them, globs are gitignore-style.
 pub fn filter_by_paths(entries: Vec<DiffEntry>, filter_paths: &[String]) -> Result<Vec<DiffEntry>> {
     if filter_paths.is_empty() {
         return Ok(entries);
     }
 
     let home_dir = dirs::home_dir().context("Failed to find home directory")?;
+    let matcher = PathMatcher::for_paths(&home_dir, filter_paths)?;
 
-    // Expand and normalize filter paths
-    let normalized_filters: Vec<PathBuf> = filter_paths
-        .iter()
-        .map(|p| {
-            let expanded = shellexpand::tilde(p).to_string();
-            let path = PathBuf::from(expanded);
-            if path.is_absolute() {
-                path.strip_prefix(&home_dir).unwrap_or(&path).to_path_buf()
-            } else {
-                path.strip_prefix("~/").unwrap_or(&path).to_path_buf()
-            }
-        })
-        .collect();
-
-    // Filter entries
     let filtered = entries
         .into_iter()
-        .filter(|entry| {
-            normalized_filters
-                .iter()
-                .any(|filter| entry.rel_path.starts_with(filter) || entry.rel_path == *filter)
-        })
+        .filter(|entry| matcher.is_match(&entry.rel_path))
         .collect();
 
     Ok(filtered)
@@ -296,4 +585,41 @@ mod tests {
         let _ = DiffStatus::Missing.symbol();
         let _ = DiffStatus::Identical.symbol();
     }
+
+    #[test]
+    fn test_compare_classifies_drift() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config::default();
+        let variables = crate::template::Variables::local(&config).unwrap();
+        let source = dir.path().join("compiled");
+        fs::write(&source, "set nu\n").unwrap();
+        let hash = crate::hash::hash_file(&source).unwrap().hash;
+        let check = |target: &Path| {
+            compare(
+                &config,
+                &variables,
+                Path::new(".vimrc"),
+                &source,
+                target,
+                &hash,
+            )
+        };
+
+        let copy = dir.path().join("copy");
+        fs::write(&copy, "set nu\n").unwrap();
+        assert_eq!(check(&copy), None);
+        fs::write(&copy, "set rnu\n").unwrap();
+        assert_eq!(check(&copy), Some(Drift::Modified));
+        assert_eq!(check(&dir.path().join("gone")), Some(Drift::Missing));
+
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(&source, &link).unwrap();
+        assert_eq!(check(&link), None);
+        let elsewhere = dir.path().join("elsewhere");
+        std::os::unix::fs::symlink(&copy, &elsewhere).unwrap();
+        assert_eq!(check(&elsewhere), Some(Drift::Relinked));
+        let broken = dir.path().join("broken");
+        std::os::unix::fs::symlink(dir.path().join("nowhere"), &broken).unwrap();
+        assert_eq!(check(&broken), Some(Drift::BrokenLink));
+    }
 }
@@ -0,0 +1,314 @@
+//! How the changes to one file are shown.
+//!
+//! `[ui] diff_tool` (or `--tool`) picks the rendering: `git` runs
+//! `git diff --no-index`, `unified` and `side-by-side` are built in, and
+//! anything else is a command run through `sh` with the old and new file
+//! appended, like `delta` or `difft`. Without git, `git` falls back to the
+//! built-in unified diff. On a terminal, `[ui] pager` shows each file's diff
+//! in a pager such as `less -R`.
+
+use anyhow::{bail, Context, Result};
+use colored::*;
+use similar::{ChangeTag, DiffTag, TextDiff};
+use std::fs;
+use std::io::{IsTerminal, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+use crate::cfg::Config;
+
+/// Lines of context around each change
+const CONTEXT: usize = 3;
+
+/// Width side-by-side diffs use when stdout is not a terminal
+const DEFAULT_WIDTH: usize = 120;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffTool {
+    Git,
+    Unified,
+    SideBySide,
+    /// Shell command given the old and new file as arguments
+    External(String),
+}
+
+impl DiffTool {
+    pub fn parse(tool: &str) -> Result<Self> {
+        Ok(match tool.trim() {
+            "" => bail!("The diff tool can't be empty"),
+            "git" => DiffTool::Git,
+            "unified" => DiffTool::Unified,
+            "side-by-side" => DiffTool::SideBySide,
+            command => DiffTool::External(command.to_string()),
+        })
+    }
+}
+
+/// The diff tool and pager file diffs are shown with
+#[derive(Debug, Clone)]
+pub struct DiffView {
+    pub tool: DiffTool,
+    /// Shell command each diff is piped into on a terminal
+    pub pager: Option<String>,
+}
+
+impl DiffView {
+    /// The view `[ui]` configures, with `tool` (from `--tool`) winning
+    pub fn new(config: &Config, tool: Option<&str>) -> Result<Self> {
+        let ui = config.ui.clone().unwrap_or_default();
+        let tool = match tool.or(ui.diff_tool.as_deref()) {
+            Some(tool) => DiffTool::parse(tool)?,
+            None => DiffTool::Git,
+        };
+        let pager = ui.pager.filter(|p| !p.trim().is_empty());
+        Ok(Self { tool, pager })
+    }
+
+    /// Show how `new` differs from `old`; both are text files
+    pub fn show(&self, old: &Path, new: &Path) -> Result<()> {
+        let paged = self
+            .pager
+            .as_deref()
+            .filter(|_| std::io::stdout().is_terminal());
+        let text = match &self.tool {
+            DiffTool::External(command) => match paged {
+                Some(_) => external(command, old, new, true)?,
+                None => return external(command, old, new, false).map(|_| ()),
+            },
+            DiffTool::Git => match git(old, new) {
+                Some(text) => text,
+                None => unified(&read(old)?, &read(new)?),
+            },
+            DiffTool::Unified => unified(&read(old)?, &read(new)?),
+            DiffTool::SideBySide => side_by_side(&read(old)?, &read(new)?, width()),
+        };
+        match paged {
+            Some(pager) => page(&text, pager),
+            None => {
+                print!("{}", text);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// `git diff --no-index` without its header, or None if git is not
+/// available
+fn git(old: &Path, new: &Path) -> Option<String> {
+    let color = match colored::control::SHOULD_COLORIZE.should_colorize() {
+        true => "--color=always",
+        false => "--color=never",
+    };
+    let out = Command::new("git")
+        .args(["diff", "--no-index", color, "--"])
+        .arg(old)
+        .arg(new)
+        .output()
+        .ok()?;
+    // Exit code 1 means the files differ
+    if !matches!(out.status.code(), Some(0 | 1)) {
+        return None;
+    }
+    let text = String::from_utf8_lossy(&out.stdout);
+    Some(
+        text.lines()
+            .skip(4)
+            .map(|line| format!("    {}\n", line))
+            .collect(),
+    )
+}
+
+/// Run `command` with the two files; with `capture` its output is returned
+/// instead of going straight to the terminal
+fn external(command: &str, old: &Path, new: &Path, capture: bool) -> Result<String> {
+    let mut child = Command::new("sh");
+    child
+        .arg("-c")
+        .arg(format!("{} \"$@\"", command))
+        .arg("dotdipper")
+        .arg(old)
+        .arg(new);
+    let (status, stdout, stderr) = if capture {
+        let out = child
+            .output()
+            .with_context(|| format!("Failed to run diff tool '{}'", command))?;
+        (out.status, out.stdout, out.stderr)
+    } else {
+        let status = child
+            .status()
+            .with_context(|| format!("Failed to run diff tool '{}'", command))?;
+        (status, Vec::new(), Vec::new())
+    };
+    match status.code() {
+        // diff and most tools exit with 1 when the files differ
+        Some(0 | 1) => Ok(String::from_utf8_lossy(&stdout).into_owned()),
+        Some(127) => bail!(
+            "Diff tool '{}' was not found. Install it, or pick another with --tool or [ui] diff_tool",
+            command
+        ),
+        _ => bail!(
+            "Diff tool '{}' failed ({}) {}",
+            command,
+            status,
+            String::from_utf8_lossy(&stderr).trim()
+        ),
+    }
+}
+
+/// Pipe `text` into `pager`
+fn page(text: &str, pager: &str) -> Result<()> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(pager)
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run pager '{}'", pager))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Quitting the pager early closes the pipe, which is fine
+        match stdin.write_all(text.as_bytes()) {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+            _ => {}
+        }
+    }
+    let status = child.wait()?;
+    if status.code() == Some(127) {
+        bail!(
+            "Pager '{}' was not found. Set another with [ui] pager",
+            pager
+        );
+    }
+    Ok(())
+}
+
+/// Built-in unified diff, indented like the file list it's shown under
+fn unified(old: &str, new: &str) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    let mut out = String::new();
+    for hunk in diff.unified_diff().context_radius(CONTEXT).iter_hunks() {
+        out.push_str(&format!("    {}\n", hunk.header().to_string().cyan()));
+        for change in hunk.iter_changes() {
+            let line = change.value().trim_end_matches(['\n', '\r']);
+            let line = match change.tag() {
+                ChangeTag::Delete => format!("-{}", line).red(),
+                ChangeTag::Insert => format!("+{}", line).green(),
+                ChangeTag::Equal => format!(" {}", line).normal(),
+            };
+            out.push_str(&format!("    {}\n", line));
+            if change.missing_newline() {
+                out.push_str(&format!(
+                    "    {}\n",
+                    "\\ No newline at end of file".dimmed()
+                ));
+            }
+        }
+    }
+    out
+}
+
+fn width() -> usize {
+    match console::Term::stdout().size_checked() {
+        Some((_, columns)) => columns as usize,
+        None => DEFAULT_WIDTH,
+    }
+}
+
+/// Built-in side-by-side diff, old on the left and new on the right, fitted
+/// to `width` columns
+fn side_by_side(old: &str, new: &str, width: usize) -> String {
+    // Indent, two line numbers with markers, and the separator
+    let column = (width.saturating_sub(21) / 2).max(20);
+    let diff = TextDiff::from_lines(old, new);
+    let (old_lines, new_lines) = (diff.old_slices(), diff.new_slices());
+    let cell = |lines: &[&str], index: Option<usize>, changed: bool, color: Color| {
+        let Some(index) = index else {
+            return format!("{:4}   {}", "", " ".repeat(column));
+        };
+        let text = lines[index]
+            .trim_end_matches(['\n', '\r'])
+            .replace('\t', "    ");
+        let text = console::pad_str(&text, column, console::Alignment::Left, Some("~"));
+        match changed {
+            true => format!(
+                "{:>4} {} {}",
+                index + 1,
+                "*".color(color),
+                text.color(color)
+            ),
+            false => format!("{:>4}   {}", index + 1, text),
+        }
+    };
+
+    let mut out = String::new();
+    for (i, group) in diff.grouped_ops(CONTEXT).iter().enumerate() {
+        if i > 0 {
+            out.push_str(&format!("    {}\n", "...".dimmed()));
+        }
+        for op in group {
+            let (tag, old_range, new_range) = op.as_tag_tuple();
+            let changed = tag != DiffTag::Equal;
+            for k in 0..old_range.len().max(new_range.len()) {
+                let left = (k < old_range.len()).then_some(old_range.start + k);
+                let right = (k < new_range.len()).then_some(new_range.start + k);
+                let row = format!(
+                    "    {} | {}",
+                    cell(old_lines, left, changed, Color::Red),
+                    cell(new_lines, right, changed, Color::Green)
+                );
+                out.push_str(row.trim_end());
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_renderings_show_only_the_changes() {
+        colored::control::set_override(false);
+        let old = "a\nb\nc\nd\ne\nf\ng\nh\ni\nkeep\n";
+        let new = "a\nb\nc\nd\ne\nf\ng\nh\ni\nchanged\nadded\tline\n";
+
+        let text = unified(old, new);
+        assert_eq!(
+            text.lines().collect::<Vec<_>>(),
+            [
+                "    @@ -7,4 +7,5 @@",
+                "     g",
+                "     h",
+                "     i",
+                "    -keep",
+                "    +changed",
+                "    +added\tline",
+            ]
+        );
+
+        let text = side_by_side(old, new, 61);
+        let rows: Vec<&str> = text.lines().collect();
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0], "       7   g                    |    7   g");
+        assert_eq!(rows[3], "      10 * keep                 |   10 * changed");
+        assert_eq!(
+            rows[4],
+            "                                |   11 * added    line"
+        );
+
+        assert_eq!(
+            DiffTool::parse("side-by-side").unwrap(),
+            DiffTool::SideBySide
+        );
+        assert_eq!(
+            DiffTool::parse("delta --side-by-side").unwrap(),
+            DiffTool::External("delta --side-by-side".into())
+        );
+        assert!(DiffTool::parse(" ").is_err());
+    }
+}
@@ -0,0 +1,377 @@
+//! Diagnostics for `dotdipper doctor`, and the repairs `doctor --fix` makes.
+//!
+//! Every check runs on its own and reports what is wrong under a stable id,
+//! with the offending paths as details; the ones dotdipper can put right
+//! carry a [`Fix`]. Repairs only touch dotdipper's own state and the links it
+//! created, except installing missing tools, which runs the system package
+//! manager after asking.
+
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::cfg::Config;
+use crate::hash::Manifest;
+use crate::ui;
+
+/// A repair `doctor --fix` can make
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fix {
+    /// Recreate the compiled directory by snapshotting the tracked files
+    RecreateCompiled,
+    /// Rebuild a missing or unreadable manifest from the compiled directory
+    RebuildManifest,
+    /// Install packages with the system package manager
+    Install(Vec<String>),
+    /// Point symlinks at missing compiled files back into the compiled
+    /// directory
+    RepairLinks,
+    /// Make the age key readable by its owner only
+    KeyPermissions,
+}
+
+impl Fix {
+    pub fn describe(&self) -> String {
+        match self {
+            Fix::RecreateCompiled => {
+                "recreate the compiled directory from the tracked files".into()
+            }
+            Fix::RebuildManifest => "rebuild the manifest from the compiled directory".into(),
+            Fix::Install(packages) => format!("install {}", packages.join(", ")),
+            Fix::RepairLinks => "point the symlinks back into the compiled directory".into(),
+            Fix::KeyPermissions => "restrict the age key to mode 600".into(),
+        }
+    }
+}
+
+/// Items listed in a failed check's message before the rest are counted
+const LISTED_DETAILS: usize = 5;
+
+/// The outcome of one check
+pub struct Check {
+    /// Stable identifier for scripts, like `orphaned-files`
+    pub id: &'static str,
+    pub name: &'static str,
+    pub result: Result<()>,
+    /// What the check found wrong, one item per entry: a path, or the
+    /// location of a config error
+    pub details: Vec<String>,
+    /// How `--fix` repairs a failed check, if it can
+    pub fix: Option<Fix>,
+}
+
+impl Check {
+    fn new(id: &'static str, name: &'static str, result: Result<()>) -> Self {
+        Self {
+            id,
+            name,
+            result,
+            details: Vec::new(),
+            fix: None,
+        }
+    }
+
+    /// A check that fails when `found` lists anything; `problem` says what
+    /// is wrong with that many items
+    fn listing(
+        id: &'static str,
+        name: &'static str,
+        found: Result<Vec<String>>,
+        problem: impl FnOnce(usize) -> String,
+    ) -> Self {
+        let found = match found {
+            Ok(found) => found,
+            Err(e) => return Self::new(id, name, Err(e)),
+        };
+        if found.is_empty() {
+            return Self::new(id, name, Ok(()));
+        }
+        let mut listed = found
+            .iter()
+            .take(LISTED_DETAILS)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ");
+        if found.len() > LISTED_DETAILS {
+            listed.push_str(&format!(" and {} more", found.len() - LISTED_DETAILS));
+        }
+        let mut check = Self::new(
+            id,
+            name,
+            Err(anyhow!("{}: {}", problem(found.len()), listed)),
+        );
+        check.details = found;
+        check
+    }
+
+    fn with_fix(mut self, fix: Fix) -> Self {
+        self.fix = self.result.is_err().then_some(fix);
+        self
+    }
+}
+
+fn packages(names: &[&str]) -> Fix {
+    Fix::Install(names.iter().map(|s| s.to_string()).collect())
+}
+
+/// Run every check. Each one stands on its own, so one failing does not
+/// keep the others from running.
+pub fn run_checks(config: &Config, config_path: &Path) -> Vec<Check> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let mut age = Check::new(
+        "age",
+        "Age encryption available",
+        crate::secrets::check_backend(config),
+    );
+    if let Ok(crate::secrets::AgeBackend::Cli) = crate::secrets::age_backend(config) {
+        age = age.with_fix(packages(&["age"]));
+    }
+    vec![
+        Check::new("git", "Git installed", crate::vcs::check_git()).with_fix(packages(&["git"])),
+        Check::new("gh", "GitHub CLI installed", crate::vcs::check_gh())
+            .with_fix(packages(&["gh"])),
+        age,
+        Check::new(
+            "config-exists",
+            "Config file exists",
+            crate::cfg::check_exists(config_path),
+        ),
+        check_config(config_path),
+        Check::listing(
+            "tracked-files-exist",
+            "Tracked files exist",
+            Ok(missing_tracked(config, &home)),
+            |n| format!("{} tracked path(s) no longer exist", n),
+        ),
+        Check::new(
+            "compiled-exists",
+            "Compiled directory exists",
+            crate::repo::check_compiled(),
+        )
+        .with_fix(Fix::RecreateCompiled),
+        check_manifest(),
+        Check::listing(
+            "orphaned-files",
+            "No orphaned compiled files",
+            crate::repo::orphaned_files()
+                .map(|files| files.iter().map(|f| f.display().to_string()).collect()),
+            |n| {
+                format!(
+                    "{} file(s) in compiled/ are not in the manifest (dotdipper gc removes them)",
+                    n
+                )
+            },
+        ),
+        Check::new(
+            "mounts",
+            "No network/removable mounts under $HOME",
+            crate::mounts::check_home_mounts(config),
+        ),
+        Check::new(
+            "immutable-targets",
+            "No immutable apply targets",
+            crate::attrs::check_immutable_targets(config),
+        ),
+        check_packages(config),
+        Check::listing(
+            "broken-links",
+            "No broken symlinks into compiled",
+            broken_links(&home),
+            |n| format!("{} symlink(s) point at a missing compiled file", n),
+        )
+        .with_fix(Fix::RepairLinks),
+        check_key(config),
+    ]
+}
+
+/// The config parses and has no unknown keys, invalid patterns or
+/// conflicting settings, each listed with its line. Skipped when there is
+/// no config file, which its own check reports.
+fn check_config(config_path: &Path) -> Check {
+    let problems = match config_path.exists() {
+        true => crate::cfg::check(config_path),
+        false => Ok(Vec::new()),
+    };
+    Check::listing(
+        "config-valid",
+        "Config valid",
+        problems.map(|problems| problems.iter().map(|p| p.to_string()).collect()),
+        |n| format!("{} problem(s) in the config", n),
+    )
+}
+
+/// Tracked files and `[tracked_dirs]` directories that are gone from $HOME
+fn missing_tracked(config: &Config, home: &Path) -> Vec<String> {
+    let dirs = config
+        .tracked_dirs
+        .keys()
+        .map(|dir| crate::matching::expand_tilde(dir, home));
+    config
+        .general
+        .tracked_files
+        .iter()
+        .cloned()
+        .chain(dirs)
+        .filter(|path| fs::symlink_metadata(path).is_err())
+        .map(|path| match path.strip_prefix(home) {
+            Ok(rel) => crate::matching::display_tilde(rel),
+            Err(_) => path.display().to_string(),
+        })
+        .collect()
+}
+
+fn broken_links(home: &Path) -> Result<Vec<String>> {
+    let manifest_path = crate::paths::manifest_file()?;
+    if !manifest_path.exists() {
+        return Ok(Vec::new());
+    }
+    let manifest = Manifest::load(&manifest_path)?;
+    Ok(crate::links::broken_links(home, &manifest)
+        .iter()
+        .filter_map(|link| link.strip_prefix(home).ok())
+        .map(crate::matching::display_tilde)
+        .collect())
+}
+
+/// A key owned by someone else needs `chown`, which doctor leaves to the
+/// user; loose permissions are fixed
+fn check_key(config: &Config) -> Check {
+    let (id, name) = ("age-key", "Age key private");
+    match crate::secrets::check_key_owner(config) {
+        Err(e) => Check::new(id, name, Err(e)),
+        Ok(()) => Check::new(id, name, crate::secrets::check_key_permissions(config))
+            .with_fix(Fix::KeyPermissions),
+    }
+}
+
+/// A manifest that is missing or unreadable is rebuilt, unless the compiled
+/// directory is missing too: recreating that writes a new manifest
+fn check_manifest() -> Check {
+    let result = crate::repo::check_manifest();
+    let rebuildable = result.is_err()
+        && crate::repo::check_compiled().is_ok()
+        && crate::paths::manifest_file()
+            .map(|path| Manifest::load(&path).is_err())
+            .unwrap_or(false);
+    let mut check = Check::new("manifest", "Manifest valid", result);
+    if rebuildable {
+        check = check.with_fix(Fix::RebuildManifest);
+    }
+    check
+}
+
+fn check_packages(config: &Config) -> Check {
+    let missing = crate::install::validators::missing_packages(config).unwrap_or_default();
+    let mut check = Check::new(
+        "packages",
+        "Configured packages installed",
+        crate::install::validators::check_installed(config),
+    )
+    .with_fix(Fix::Install(missing.clone()));
+    if check.result.is_err() {
+        check.details = missing;
+    }
+    check
+}
+
+/// Make the repair, returning what was done
+pub fn apply(config: &Config, fix: &Fix) -> Result<String> {
+    match fix {
+        Fix::RecreateCompiled => {
+            fs::create_dir_all(crate::paths::compiled_dir()?)?;
+            if crate::repo::planned_files(config)?.is_empty() {
+                return Ok("Created an empty compiled directory".to_string());
+            }
+            let snapshot = crate::repo::snapshot(config, true)?;
+            Ok(format!(
+                "Recreated the compiled directory with {} file(s)",
+                snapshot.file_count
+            ))
+        }
+        Fix::RebuildManifest => {
+            let count = crate::repo::rebuild_manifest(config)?;
+            Ok(format!("Rebuilt the manifest with {} file(s)", count))
+        }
+        Fix::Install(packages) => install(packages),
+        Fix::RepairLinks => {
+            let home = dirs::home_dir().context("Failed to find home directory")?;
+            let compiled = crate::paths::compiled_dir()?;
+            let manifest = Manifest::load(&crate::paths::manifest_file()?)?;
+            let repaired = crate::links::repair_broken(
+                &home,
+                &compiled,
+                &manifest,
+                config.general.relative_symlinks,
+            )?;
+            let left = crate::links::broken_links(&home, &manifest).len();
+            if repaired.is_empty() {
+                bail!("The compiled copies are missing too. Run 'dotdipper pull' to restore them");
+            }
+            let mut done = format!("Repaired {} symlink(s)", repaired.len());
+            if left > 0 {
+                done.push_str(&format!(
+                    "; {} more have no compiled copy, run 'dotdipper pull' to restore them",
+                    left
+                ));
+            }
+            Ok(done)
+        }
+        Fix::KeyPermissions => {
+            crate::secrets::fix_key_permissions(config)?;
+            Ok(format!(
+                "Restricted {} to mode 600",
+                crate::secrets::age_key_path(config).display()
+            ))
+        }
+    }
+}
+
+/// Install `packages` with the package manager, once the user agrees
+fn install(packages: &[String]) -> Result<String> {
+    let os = crate::install::detect_os();
+    let Some(command) = crate::install::install_command(&os, packages) else {
+        bail!(
+            "No known package manager for {}. Install {} manually",
+            os,
+            packages.join(", ")
+        );
+    };
+    let shown = command.join(" ");
+    if !ui::prompt_confirm(&format!("Run '{}'?", shown), false) {
+        bail!("Skipped; run '{}' to install them", shown);
+    }
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .status()
+        .with_context(|| format!("Failed to run {}", command[0]))?;
+    if !status.success() {
+        bail!("'{}' failed", shown);
+    }
+    Ok(format!("Installed {}", packages.join(", ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_listing_checks_keep_every_item_and_fix_only_failures() {
+        let ok = Check::listing("orphaned-files", "No orphans", Ok(Vec::new()), |n| {
+            format!("{} orphan(s)", n)
+        })
+        .with_fix(Fix::RebuildManifest);
+        assert!(ok.result.is_ok() && ok.fix.is_none());
+
+        let found: Vec<String> = (1..=7).map(|i| format!("~/.file{}", i)).collect();
+        let failed = Check::listing("orphaned-files", "No orphans", Ok(found.clone()), |n| {
+            format!("{} orphan(s)", n)
+        })
+        .with_fix(Fix::RebuildManifest);
+        assert_eq!(failed.details, found);
+        assert_eq!(failed.fix, Some(Fix::RebuildManifest));
+        let message = failed.result.unwrap_err().to_string();
+        assert!(message.starts_with("7 orphan(s): ~/.file1, ~/.file2"));
+        assert!(message.ends_with("~/.file5 and 2 more"));
+    }
+}
@@ -1,26 +1,148 @@
-use anyhow::{Context, Result};
+//! Content hashes of tracked files and the manifest that records them.
+//!
+//! The manifest (`manifest.lock`) lists every snapshotted file with its
+//! hash, size, mode and modification time. Format 2 also records what a
+//! plain file list loses: symlinks inside tracked directories with their
+//! targets (written back as links, not copies of what they point to),
+//! empty directories, and, with `[attributes] preserve_xattrs`, each file's
+//! extended attributes. Manifests of format 1 read as format 2 with none of
+//! those; `dotdipper migrate` rewrites them.
+
+use anyhow::{bail, Context, Result};
 use blake3::Hasher;
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::{self, File};
 use std::io::{BufReader, Read};
 use std::path::{Path, PathBuf};
 
+pub mod raw_path;
+
+/// Format of the manifests this version writes. A manifest of a newer major
+/// version is refused rather than misread.
+pub const MANIFEST_VERSION: &str = "2.0.0";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FileHash {
+    #[serde(with = "raw_path")]
     pub path: PathBuf,
     pub hash: String,
     pub size: u64,
     pub mode: u32,
     pub modified: DateTime<Utc>,
+    /// Extended attributes by name, values in base64 (see
+    /// [`crate::attrs::to_manifest`])
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub xattrs: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Manifest {
     pub version: String,
     pub created: DateTime<Utc>,
+    #[serde(with = "raw_path::keys")]
     pub files: HashMap<PathBuf, FileHash>,
+    /// Summaries of the `[tracked_dirs]` directories, by relative path
+    #[serde(
+        default,
+        with = "raw_path::keys",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub dirs: BTreeMap<PathBuf, DirHash>,
+    /// Symlinks in tracked directories, by relative path
+    #[serde(
+        default,
+        with = "raw_path::keys",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub links: BTreeMap<PathBuf, LinkEntry>,
+    /// Empty directories in tracked directories, by relative path
+    #[serde(
+        default,
+        with = "raw_path::keys",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub empty_dirs: BTreeMap<PathBuf, EmptyDir>,
+}
+
+/// A symlink, recorded as its target rather than what it points to
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LinkEntry {
+    /// The target as the link holds it, relative or absolute
+    #[serde(with = "raw_path")]
+    pub target: PathBuf,
+}
+
+/// A directory with nothing in it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EmptyDir {
+    pub mode: u32,
+}
+
+/// Summary of a tracked directory's files, to tell whether anything in it
+/// changed without comparing every file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DirHash {
+    /// Hash over the files' paths (relative to the directory), hashes and modes
+    pub hash: String,
+    pub files: usize,
+    pub size: u64,
+    /// Newest modification time of the files
+    pub modified: DateTime<Utc>,
+}
+
+impl DirHash {
+    /// Summarize the entries of `files` under `dir` (both relative to the
+    /// same base)
+    pub fn of<'a>(dir: &Path, files: impl IntoIterator<Item = &'a FileHash>) -> Self {
+        let mut entries: Vec<&FileHash> = files
+            .into_iter()
+            .filter(|f| f.path.starts_with(dir))
+            .collect();
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+
+        let mut hasher = Hasher::new();
+        for entry in &entries {
+            let rel = entry.path.strip_prefix(dir).unwrap_or(&entry.path);
+            hasher.update(rel.as_os_str().as_encoded_bytes());
+            hasher.update(&[0]);
+            hasher.update(entry.hash.as_bytes());
+            hasher.update(&entry.mode.to_le_bytes());
+        }
+        DirHash {
+            hash: hasher.finalize().to_hex().to_string(),
+            files: entries.len(),
+            size: entries.iter().map(|e| e.size).sum(),
+            modified: entries
+                .iter()
+                .map(|e| e.modified)
+                .max()
+                .unwrap_or(DateTime::<Utc>::UNIX_EPOCH),
+        }
+    }
+
+    /// Whether `files` (the directory's current files) have the same count,
+    /// total size and newest modification time, so can be assumed unchanged
+    /// without reading them
+    pub fn same_metadata(&self, files: &[PathBuf]) -> bool {
+        if files.len() != self.files {
+            return false;
+        }
+        let mut size = 0;
+        let mut modified = DateTime::<Utc>::UNIX_EPOCH;
+        for file in files {
+            let Some(metadata) = fs::metadata(file).ok() else {
+                return false;
+            };
+            let Ok(mtime) = metadata.modified() else {
+                return false;
+            };
+            size += metadata.len();
+            modified = modified.max(DateTime::from(mtime));
+        }
+        size == self.size && modified == self.modified
+    }
 }
 
 impl Default for Manifest {
@@ -32,9 +154,12 @@ impl Default for Manifest {
 impl Manifest {
     pub fn new() -> Self {
         Manifest {
-            version: "1.0.0".to_string(),
+            version: MANIFEST_VERSION.to_string(),
             created: Utc::now(),
             files: HashMap::new(),
+            dirs: BTreeMap::new(),
+            links: BTreeMap::new(),
+            empty_dirs: BTreeMap::new(),
         }
     }
 
@@ -43,9 +168,27 @@ impl Manifest {
             .with_context(|| format!("Failed to read manifest from {}", path.display()))?;
         let manifest: Manifest =
             serde_json::from_str(&content).context("Failed to parse manifest JSON")?;
+        if major_version(&manifest.version) > major_version(MANIFEST_VERSION) {
+            bail!(
+                "{} has manifest format {}, newer than this dotdipper reads ({}); upgrade dotdipper",
+                path.display(),
+                manifest.version,
+                MANIFEST_VERSION
+            );
+        }
         Ok(manifest)
     }
 
+    /// Whether it records no files, links or empty directories
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.links.is_empty() && self.empty_dirs.is_empty()
+    }
+
+    /// Whether the links and empty directories recorded are `other`'s
+    pub fn same_tree(&self, other: &Manifest) -> bool {
+        self.links == other.links && self.empty_dirs == other.empty_dirs
+    }
+
     pub fn save(&self, path: &Path) -> Result<()> {
         let content = serde_json::to_string_pretty(self).context("Failed to serialize manifest")?;
         fs::write(path, content)
@@ -64,6 +207,74 @@ impl Manifest {
     pub fn has_file(&self, path: &Path) -> bool {
         self.files.contains_key(path)
     }
+
+    /// Changes from `self` to `newer`. A path that disappeared and a path that
+    /// appeared with the same hash are reported as a rename instead of a
+    /// removal plus an addition.
+    pub fn diff(&self, newer: &Manifest) -> ManifestDiff {
+        let mut diff = ManifestDiff::default();
+        let mut removed: BTreeMap<&PathBuf, &FileHash> = BTreeMap::new();
+        let mut added: BTreeMap<&PathBuf, &FileHash> = BTreeMap::new();
+
+        for (path, old) in &self.files {
+            match newer.files.get(path) {
+                Some(new) if new.hash != old.hash => diff.modified.push(path.clone()),
+                Some(_) => {}
+                None => {
+                    removed.insert(path, old);
+                }
+            }
+        }
+        for (path, new) in &newer.files {
+            if !self.files.contains_key(path) {
+                added.insert(path, new);
+            }
+        }
+
+        for (old_path, old) in removed {
+            let moved_to = added
+                .iter()
+                .find(|(_, new)| new.hash == old.hash)
+                .map(|(path, _)| *path);
+            match moved_to {
+                Some(new_path) => {
+                    added.remove(new_path);
+                    diff.renamed.push((old_path.clone(), new_path.clone()));
+                }
+                None => diff.removed.push(old_path.clone()),
+            }
+        }
+        diff.added = added.into_keys().cloned().collect();
+        diff.modified.sort();
+        diff
+    }
+}
+
+fn major_version(version: &str) -> u32 {
+    version
+        .split('.')
+        .next()
+        .and_then(|major| major.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Path-level changes between two manifests, each list sorted by path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ManifestDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+    /// `(old, new)` paths of files that moved without changing
+    pub renamed: Vec<(PathBuf, PathBuf)>,
+}
+
+impl ManifestDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.modified.is_empty()
+            && self.renamed.is_empty()
+    }
 }
 
 pub fn hash_file(path: &Path) -> Result<FileHash> {
@@ -99,6 +310,7 @@ pub fn hash_file(path: &Path) -> Result<FileHash> {
         size: metadata.len(),
         mode: get_file_mode(&metadata),
         modified: DateTime::from(modified),
+        xattrs: BTreeMap::new(),
     })
 }
 
@@ -127,6 +339,57 @@ pub fn hash_files(paths: &[PathBuf], progress: bool) -> Result<Vec<FileHash>> {
     Ok(hashes)
 }
 
+/// Like [`hash_files`], but a file whose size, modification time and mode
+/// match its entry in `previous` keeps the recorded hash instead of being
+/// read again. Entries are looked up by the path relative to `base`.
+pub fn hash_files_since(
+    paths: &[PathBuf],
+    progress: bool,
+    previous: &Manifest,
+    base: &Path,
+) -> Result<Vec<FileHash>> {
+    let mut hashes = Vec::new();
+
+    let pb = if progress {
+        Some(crate::ui::progress_bar(paths.len() as u64, "Hashing files"))
+    } else {
+        None
+    };
+
+    for path in paths {
+        let rel_path = path.strip_prefix(base).unwrap_or(path);
+        let cached = fs::metadata(path).ok().and_then(|metadata| {
+            let stored = previous.get_file(rel_path)?;
+            let modified = DateTime::<Utc>::from(metadata.modified().ok()?);
+            (metadata.is_file()
+                && stored.size == metadata.len()
+                && stored.modified == modified
+                && stored.mode == get_file_mode(&metadata))
+            .then(|| FileHash {
+                path: path.clone(),
+                ..stored.clone()
+            })
+        });
+        match cached {
+            Some(hash) => hashes.push(hash),
+            None => {
+                if let Ok(hash) = hash_file(path) {
+                    hashes.push(hash);
+                }
+            }
+        }
+        if let Some(ref pb) = pb {
+            pb.inc(1);
+        }
+    }
+
+    if let Some(pb) = pb {
+        pb.finish_with_message("Hashing complete");
+    }
+
+    Ok(hashes)
+}
+
 pub fn verify_file(file_hash: &FileHash) -> Result<bool> {
     if !file_hash.path.exists() {
         return Ok(false);
@@ -178,4 +441,137 @@ mod tests {
         assert_eq!(hash.size, 13);
         assert!(!hash.hash.is_empty());
     }
+
+    #[test]
+    fn test_hash_files_since_skips_files_with_unchanged_metadata() {
+        let temp_dir = TempDir::new().unwrap();
+        let base = temp_dir.path();
+        let path = base.join(".zshrc");
+        fs::write(&path, "export A=1\n").unwrap();
+
+        let mut previous = Manifest::new();
+        let mut entry = hash_file(&path).unwrap();
+        let mtime = filetime::FileTime::from_last_modification_time(&fs::metadata(&path).unwrap());
+        entry.path = PathBuf::from(".zshrc");
+        previous.add_file(entry.clone());
+
+        // Same size and mtime: the recorded hash is trusted
+        fs::write(&path, "export B=1\n").unwrap();
+        filetime::set_file_mtime(&path, mtime).unwrap();
+        let hashes = hash_files_since(std::slice::from_ref(&path), false, &previous, base).unwrap();
+        assert_eq!(hashes[0].hash, entry.hash);
+        assert_eq!(hashes[0].path, path);
+
+        // A different size is hashed again
+        fs::write(&path, "export EDITOR=vim\n").unwrap();
+        filetime::set_file_mtime(&path, mtime).unwrap();
+        let hashes = hash_files_since(std::slice::from_ref(&path), false, &previous, base).unwrap();
+        assert_ne!(hashes[0].hash, entry.hash);
+        assert_eq!(hashes[0].hash, hash_file(&path).unwrap().hash);
+    }
+
+    #[test]
+    fn test_manifest_round_trips_non_utf8_and_emoji_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let latin1 = PathBuf::from(OsStr::from_bytes(b".config/caf\xe9.conf"));
+        let emoji = PathBuf::from(".config/🚀/launch.toml");
+        let mut manifest = Manifest::new();
+        for path in [&latin1, &emoji] {
+            manifest.add_file(FileHash {
+                path: path.clone(),
+                hash: "h".to_string(),
+                size: 1,
+                mode: 0o644,
+                modified: Utc::now(),
+                xattrs: Default::default(),
+            });
+        }
+
+        let path = temp_dir.path().join("manifest.lock");
+        manifest.save(&path).unwrap();
+        let json = fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\"\\u0000base64:LmNvbmZpZy9jYWbpLmNvbmY=\""));
+        assert!(json.contains("\".config/🚀/launch.toml\""));
+
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded.get_file(&latin1).unwrap().path, latin1);
+        assert_eq!(loaded.get_file(&emoji).unwrap().path, emoji);
+    }
+
+    #[test]
+    fn test_manifest_v2_keeps_links_empty_dirs_and_xattrs() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("manifest.lock");
+        let mut manifest = Manifest::new();
+        manifest.add_file(FileHash {
+            path: PathBuf::from(".local/bin/sync"),
+            hash: "h".to_string(),
+            size: 1,
+            mode: 0o100755,
+            modified: Utc::now(),
+            xattrs: BTreeMap::from([("user.origin".to_string(), "aGVyZQ==".to_string())]),
+        });
+        let link = LinkEntry {
+            target: PathBuf::from("../shared"),
+        };
+        manifest
+            .links
+            .insert(PathBuf::from(".config/nvim/lua"), link.clone());
+        manifest
+            .empty_dirs
+            .insert(PathBuf::from(".config/nvim/undo"), EmptyDir { mode: 0o700 });
+        manifest.save(&path).unwrap();
+
+        let loaded = Manifest::load(&path).unwrap();
+        assert_eq!(loaded.version, MANIFEST_VERSION);
+        assert!(loaded.same_tree(&manifest));
+        assert_eq!(loaded.links[Path::new(".config/nvim/lua")], link);
+        let file = loaded.get_file(Path::new(".local/bin/sync")).unwrap();
+        assert_eq!(file.xattrs["user.origin"], "aGVyZQ==");
+
+        // Format 1 has none of them; a newer major format is refused
+        let v1 = r#"{"version":"1.0.0","created":"2025-01-01T00:00:00Z","files":{}}"#;
+        fs::write(&path, v1).unwrap();
+        let loaded = Manifest::load(&path).unwrap();
+        assert!(loaded.is_empty() && loaded.same_tree(&Manifest::new()));
+        fs::write(&path, v1.replace("1.0.0", "3.0.0")).unwrap();
+        let err = Manifest::load(&path).unwrap_err().to_string();
+        assert!(err.contains("newer than this dotdipper reads"), "{}", err);
+    }
+
+    #[test]
+    fn test_manifest_diff_detects_renames() {
+        let entry = |path: &str, hash: &str| FileHash {
+            path: PathBuf::from(path),
+            hash: hash.to_string(),
+            size: 1,
+            mode: 0o644,
+            modified: Utc::now(),
+            xattrs: Default::default(),
+        };
+        let mut old = Manifest::new();
+        old.add_file(entry(".vimrc", "vim"));
+        old.add_file(entry(".zshrc", "zsh-1"));
+        old.add_file(entry(".bashrc", "bash"));
+        let mut new = Manifest::new();
+        new.add_file(entry(".config/nvim/legacy.vim", "vim"));
+        new.add_file(entry(".zshrc", "zsh-2"));
+        new.add_file(entry(".gitconfig", "git"));
+
+        let diff = old.diff(&new);
+        assert_eq!(
+            diff.renamed,
+            vec![(
+                PathBuf::from(".vimrc"),
+                PathBuf::from(".config/nvim/legacy.vim")
+            )]
+        );
+        assert_eq!(diff.modified, vec![PathBuf::from(".zshrc")]);
+        assert_eq!(diff.added, vec![PathBuf::from(".gitconfig")]);
+        assert_eq!(diff.removed, vec![PathBuf::from(".bashrc")]);
+        assert!(new.diff(&new).is_empty());
+    }
 }
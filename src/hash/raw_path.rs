@@ -0,0 +1,110 @@
+//! Serde helpers that keep paths byte-exact.
+//!
+//! JSON strings must be UTF-8, but Unix file names are arbitrary bytes.
+//! UTF-8 paths are written as plain strings, as before; any other path is
+//! written as a NUL byte, `base64:` and the base64 of its raw bytes. A NUL
+//! can never appear in a real path, so the two forms can't be confused.
+//!
+//! Use `#[serde(with = "crate::hash::raw_path")]` on a `PathBuf` field and
+//! `#[serde(with = "crate::hash::raw_path::keys")]` on a map keyed by paths.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+const RAW_PREFIX: &str = "\0base64:";
+
+/// String form of a path
+pub fn encode(path: &Path) -> String {
+    match path.to_str() {
+        Some(s) => s.to_string(),
+        None => format!(
+            "{}{}",
+            RAW_PREFIX,
+            STANDARD.encode(path.as_os_str().as_bytes())
+        ),
+    }
+}
+
+/// Path back from [`encode`]
+pub fn decode(s: &str) -> Result<PathBuf, String> {
+    match s.strip_prefix(RAW_PREFIX) {
+        Some(encoded) => STANDARD
+            .decode(encoded)
+            .map(|bytes| PathBuf::from(OsStr::from_bytes(&bytes)))
+            .map_err(|e| format!("invalid raw path {:?}: {}", encoded, e)),
+        None => Ok(PathBuf::from(s)),
+    }
+}
+
+pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&encode(path))
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+    decode(&String::deserialize(deserializer)?).map_err(D::Error::custom)
+}
+
+/// For optional paths.
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        path: &Option<PathBuf>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match path {
+            Some(path) => serializer.serialize_some(&encode(path)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<PathBuf>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|s| decode(&s).map_err(D::Error::custom))
+            .transpose()
+    }
+}
+
+/// For lists of paths (serialization only).
+pub mod list {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(paths: &[PathBuf], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(paths.iter().map(|p| encode(p)))
+    }
+}
+
+/// For maps keyed by path. Keys are written in sorted order.
+pub mod keys {
+    use super::*;
+
+    pub fn serialize<'a, S, M, V>(map: &'a M, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        &'a M: IntoIterator<Item = (&'a PathBuf, &'a V)>,
+        V: Serialize + 'a,
+    {
+        let sorted: BTreeMap<String, &V> = map.into_iter().map(|(k, v)| (encode(k), v)).collect();
+        sorted.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D, M, V>(deserializer: D) -> Result<M, D::Error>
+    where
+        D: Deserializer<'de>,
+        M: FromIterator<(PathBuf, V)>,
+        V: Deserialize<'de>,
+    {
+        BTreeMap::<String, V>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(k, v)| decode(&k).map(|k| (k, v)).map_err(D::Error::custom))
+            .collect()
+    }
+}
@@ -0,0 +1,251 @@
+//! Append-only history of what dotdipper did on this machine.
+//!
+//! Every snapshot, apply, push, pull, daemon action and failed command is
+//! appended to `history.jsonl` in the data directory, one JSON object per
+//! line. Entries are numbered, and the number can be handed to other
+//! commands, such as `dotdipper undo <id>` for a push or an apply. The file
+//! is never rewritten, so it doubles as an audit trail.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::ui;
+
+/// What kind of operation an entry records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    Snapshot,
+    Apply,
+    Push,
+    Pull,
+    RemotePush,
+    RemotePull,
+    Sync,
+    Undo,
+    Rollback,
+    Daemon,
+    Error,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Kind::Snapshot => "snapshot",
+            Kind::Apply => "apply",
+            Kind::Push => "push",
+            Kind::Pull => "pull",
+            Kind::RemotePush => "remote-push",
+            Kind::RemotePull => "remote-pull",
+            Kind::Sync => "sync",
+            Kind::Undo => "undo",
+            Kind::Rollback => "rollback",
+            Kind::Daemon => "daemon",
+            Kind::Error => "error",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub id: u64,
+    pub time: DateTime<Utc>,
+    pub kind: Kind,
+    pub summary: String,
+    /// Number of files involved
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files: Option<usize>,
+    /// Commit, snapshot id or bundle the operation produced
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reference: Option<String>,
+}
+
+/// An entry about to be recorded
+pub struct Event {
+    kind: Kind,
+    summary: String,
+    files: Option<usize>,
+    reference: Option<String>,
+}
+
+impl Event {
+    pub fn new(kind: Kind, summary: impl Into<String>) -> Self {
+        Self {
+            kind,
+            summary: summary.into(),
+            files: None,
+            reference: None,
+        }
+    }
+
+    pub fn files(mut self, files: usize) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    pub fn reference(mut self, reference: impl Into<String>) -> Self {
+        self.reference = Some(reference.into());
+        self
+    }
+
+    /// Append the event to the history and return its id.
+    ///
+    /// Best effort: nothing is recorded before dotdipper has a data
+    /// directory, and a failure only produces a warning.
+    pub fn record(self) -> Option<u64> {
+        let base = crate::paths::data_dir().ok()?;
+        if !base.exists() {
+            return None;
+        }
+        match append(&crate::paths::history_file().ok()?, self) {
+            Ok(id) => Some(id),
+            Err(e) => {
+                ui::warn(&format!("Could not record history: {:#}", e));
+                None
+            }
+        }
+    }
+}
+
+fn append(path: &Path, event: Event) -> Result<u64> {
+    let (last_id, complete) = tail(path)?;
+    let entry = Entry {
+        id: last_id + 1,
+        time: Utc::now(),
+        kind: event.kind,
+        summary: event.summary,
+        files: event.files,
+        reference: event.reference,
+    };
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    if !complete {
+        // Start a fresh line after one cut short by a crash
+        writeln!(file)?;
+    }
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(entry.id)
+}
+
+/// Id of the newest entry (0 for an empty history), read from the file's
+/// tail, and whether the file ends with a complete line
+fn tail(path: &Path) -> Result<(u64, bool)> {
+    let Ok(mut file) = File::open(path) else {
+        return Ok((0, true));
+    };
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(4096)))?;
+    let mut tail = Vec::new();
+    file.read_to_end(&mut tail)?;
+    let complete = tail.last().is_none_or(|&b| b == b'\n');
+    let last = String::from_utf8_lossy(&tail)
+        .lines()
+        .rev()
+        .find_map(|line| serde_json::from_str::<Entry>(line).ok());
+    let id = match last {
+        Some(entry) => entry.id,
+        None => read(path)?.last().map_or(0, |e| e.id),
+    };
+    Ok((id, complete))
+}
+
+fn read(path: &Path) -> Result<Vec<Entry>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    // A line cut short by a crash is skipped rather than failing the rest
+    Ok(contents
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect())
+}
+
+/// Every entry, oldest first
+pub fn load() -> Result<Vec<Entry>> {
+    read(&crate::paths::history_file()?)
+}
+
+/// The entry with `id`
+pub fn find(id: u64) -> Result<Entry> {
+    load()?.into_iter().find(|e| e.id == id).with_context(|| {
+        format!(
+            "No history entry #{}. Run 'dotdipper history' to list them",
+            id
+        )
+    })
+}
+
+/// Parse `--since`: an age like "7d" or "12h", or a date (YYYY-MM-DD)
+pub fn parse_since(s: &str) -> Result<DateTime<Utc>> {
+    if let Some(age) = crate::snapshots::parse_duration(s) {
+        return Ok(Utc::now() - age);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Some(start) = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .ok()
+        .and_then(|d| d.and_hms_opt(0, 0, 0))
+    {
+        return Ok(start.and_utc());
+    }
+    bail!(
+        "Invalid --since '{}' (expected an age like 7d or a date like 2024-01-31)",
+        s
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_are_numbered_and_survive_a_torn_line() {
+        let temp = tempfile::tempdir().unwrap();
+        let path = temp.path().join("history.jsonl");
+
+        assert_eq!(
+            append(
+                &path,
+                Event::new(Kind::Snapshot, "Created snapshot").files(3)
+            )
+            .unwrap(),
+            1
+        );
+        assert_eq!(
+            append(&path, Event::new(Kind::Push, "Pushed").reference("abc1234")).unwrap(),
+            2
+        );
+        // A write interrupted halfway
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        write!(file, "{{\"id\":3,\"ti").unwrap();
+        drop(file);
+        assert_eq!(
+            append(&path, Event::new(Kind::Apply, "Applied")).unwrap(),
+            3
+        );
+
+        let entries = read(&path).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].kind, Kind::Apply);
+        assert_eq!(entries[0].files, Some(3));
+        assert_eq!(entries[1].reference.as_deref(), Some("abc1234"));
+        assert!(parse_since("7d").unwrap() < Utc::now());
+        assert_eq!(
+            parse_since("2024-01-31").unwrap().to_rfc3339(),
+            "2024-01-31T00:00:00+00:00"
+        );
+        assert!(parse_since("last week").is_err());
+    }
+}
@@ -0,0 +1,124 @@
+//! Git hooks for project repositories that hold tracked dotfiles.
+//!
+//! `dotdipper hooks install git <repo>` writes two hooks into the repository:
+//!
+//! - `pre-commit` shows `status` for the tracked files inside the repository,
+//!   so a commit that touches them reminds you to snapshot. It never blocks
+//!   the commit.
+//! - `post-merge` runs `apply` for the same files, bringing them back in line
+//!   with the stored dotfiles after a merge. Changed files are confirmed as
+//!   usual.
+//!
+//! Hooks written by dotdipper carry a marker line and are replaced freely;
+//! any other existing hook is only replaced with `--force`, and kept as
+//! `<hook>.bak`.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+
+const MARKER: &str = "# dotdipper-managed hook";
+
+/// Tracked files that live inside `repo`
+pub fn mapped_files(config: &Config, repo: &Path) -> Vec<PathBuf> {
+    let canonical = repo.canonicalize().unwrap_or_else(|_| repo.to_path_buf());
+    config
+        .general
+        .tracked_files
+        .iter()
+        .filter(|f| f.starts_with(repo) || f.starts_with(&canonical))
+        .cloned()
+        .collect()
+}
+
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn script(dotdipper: &Path, config_path: &Path, repo: &Path, command: &str) -> String {
+    format!(
+        "#!/bin/sh\n{}\n# Reinstall with: dotdipper hooks install git {}\n{} --config {} {} --only {} || true\n",
+        MARKER,
+        quote(&repo.to_string_lossy()),
+        quote(&dotdipper.to_string_lossy()),
+        quote(&config_path.to_string_lossy()),
+        command,
+        quote(&repo.to_string_lossy()),
+    )
+}
+
+/// Install the `pre-commit` and `post-merge` hooks into `repo`, returning
+/// the hook files written
+pub fn install(repo: &Path, config_path: &Path, force: bool) -> Result<Vec<PathBuf>> {
+    let repo = repo
+        .canonicalize()
+        .with_context(|| format!("Repository not found: {}", repo.display()))?;
+    let repo_str = repo
+        .to_str()
+        .with_context(|| format!("Repository path is not valid UTF-8: {}", repo.display()))?;
+    if repo_str.contains(',') {
+        bail!(
+            "Repository paths containing ',' are not supported: {}",
+            repo_str
+        );
+    }
+    let dotdipper = std::env::current_exe().context("Failed to locate the dotdipper binary")?;
+    let config_path = config_path
+        .canonicalize()
+        .unwrap_or_else(|_| config_path.to_path_buf());
+
+    let hooks_dir = crate::vcs::hooks_dir(&repo)?;
+    fs::create_dir_all(&hooks_dir)
+        .with_context(|| format!("Failed to create {}", hooks_dir.display()))?;
+
+    let hooks = [("pre-commit", "status --detailed"), ("post-merge", "apply")];
+    // Check every hook before writing any, so a refusal leaves nothing half-installed
+    for (name, _) in hooks {
+        let path = hooks_dir.join(name);
+        let foreign = fs::read_to_string(&path).is_ok_and(|s| !s.contains(MARKER));
+        if foreign && !force {
+            bail!(
+                "{} already exists and was not installed by dotdipper (use --force to replace it; it is kept as {}.bak)",
+                path.display(),
+                name
+            );
+        }
+    }
+
+    let mut written = Vec::new();
+    for (name, command) in hooks {
+        let path = hooks_dir.join(name);
+        if fs::read_to_string(&path).is_ok_and(|s| !s.contains(MARKER)) {
+            fs::rename(&path, hooks_dir.join(format!("{}.bak", name)))
+                .with_context(|| format!("Failed to back up {}", path.display()))?;
+        }
+        fs::write(&path, script(&dotdipper, &config_path, &repo, command))
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script_quotes_paths() {
+        let script = script(
+            Path::new("/usr/bin/dotdipper"),
+            Path::new("/home/me/.config/dotdipper/config.toml"),
+            Path::new("/home/me/it's here"),
+            "apply",
+        );
+        assert!(script.starts_with("#!/bin/sh\n"));
+        assert!(script.contains(MARKER));
+        assert!(script.ends_with(
+            "'/usr/bin/dotdipper' --config '/home/me/.config/dotdipper/config.toml' apply --only '/home/me/it'\\''s here' || true\n"
+        ));
+    }
+}
@@ -0,0 +1,489 @@
+//! Running `[hooks]` commands.
+//!
+//! Hooks run before and after apply, snapshot, pull and push. `[hooks.on_change]`
+//! hooks run after an apply only when it wrote files matching their pattern,
+//! and get those files in `DOTDIPPER_FILES`.
+//!
+//! Hooks run through `sh -c` and get context about the run:
+//!
+//! - `DOTDIPPER_EVENT`: the stage, e.g. `pre_apply`, `post_push` or `on_change`
+//! - `DOTDIPPER_PROFILE`: the active profile
+//! - `DOTDIPPER_CHANGED_FILES`: newline-separated absolute paths the run is
+//!   about to write (pre-apply), wrote (post-apply, on-change) or is
+//!   snapshotting; empty for pull and push
+//!
+//! Snapshot hooks also get:
+//!
+//! - `DOTDIPPER_FILES`: the same files as `DOTDIPPER_CHANGED_FILES`
+//! - `DOTDIPPER_CONTEXT`: path to a JSON file with the event, files, excluded
+//!   files, message and (post-snapshot) snapshot id
+//! - `DOTDIPPER_SKIP_FILE` (pre-snapshot only): a pre-snapshot hook may write
+//!   paths or patterns to this file, one per line, to leave those files out of
+//!   the snapshot
+//!
+//! With `[hooks.sandbox]`, hooks and install scripts get only the allowlisted
+//! environment, run in a fixed working directory, are killed after
+//! `timeout_secs` and, with `no_network`, run in a fresh network namespace.
+//!
+//! A hook that fails or times out aborts the command, unless it is marked
+//! `continue_on_error = true`. `timeout_secs` on a hook overrides the
+//! sandbox timeout for it.
+//!
+//! A pull marks the stored dotfiles as unreviewed. Until `dotdipper hooks
+//! trust`, only hooks marked `trusted = true` run, so a hook pointing at a
+//! script that the pull just replaced does not run unseen.
+//!
+//! [`git`] installs git hooks that keep dotfiles inside project repositories
+//! in sync.
+
+pub mod git;
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+use crate::cfg::{Hook, HooksConfig, SandboxConfig};
+use crate::matching::{expand_tilde, parse_pattern_lines, PathMatcher};
+use crate::ui;
+
+/// What a snapshot hook is told about the current run
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotContext {
+    pub event: &'static str,
+    #[serde(serialize_with = "crate::hash::raw_path::list::serialize")]
+    pub files: Vec<PathBuf>,
+    #[serde(serialize_with = "crate::hash::raw_path::list::serialize")]
+    pub excluded: Vec<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot_id: Option<String>,
+}
+
+fn pulled_marker() -> Result<PathBuf> {
+    Ok(crate::paths::data_dir()?.join("hooks-untrusted"))
+}
+
+/// Record that a pull brought in dotfiles that have not been reviewed yet
+pub fn mark_pulled() -> Result<()> {
+    let marker = pulled_marker()?;
+    fs::write(&marker, chrono::Utc::now().to_rfc3339())
+        .with_context(|| format!("Failed to write {}", marker.display()))
+}
+
+/// Whether untrusted hooks are held back after a pull
+pub fn pulled_unreviewed() -> bool {
+    pulled_marker().map(|m| m.exists()).unwrap_or(false)
+}
+
+/// `dotdipper hooks trust`: let every hook run again. Returns whether
+/// anything was held back.
+pub fn trust_pulled() -> Result<bool> {
+    let marker = pulled_marker()?;
+    if !marker.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(&marker)?;
+    Ok(true)
+}
+
+/// Hooks allowed to run right now, warning about the ones held back
+fn runnable<'a>(label: &str, hooks: &'a [Hook]) -> Vec<&'a Hook> {
+    if !pulled_unreviewed() {
+        return hooks.iter().collect();
+    }
+    let (trusted, held): (Vec<&Hook>, Vec<&Hook>) = hooks.iter().partition(|h| h.trusted());
+    for hook in &held {
+        ui::warn(&format!(
+            "Skipping untrusted {} hook after pull: {}",
+            label, hook
+        ));
+    }
+    if !held.is_empty() {
+        ui::hint(
+            "Review the pulled dotfiles, then run 'dotdipper hooks trust' (or mark the hook trusted = true)",
+        );
+    }
+    trusted
+}
+
+/// Command running `program args` under the sandbox, if any
+pub fn command<I, S>(program: &str, args: I, sandbox: Option<&SandboxConfig>) -> Result<Command>
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let Some(sandbox) = sandbox else {
+        let mut cmd = Command::new(program);
+        cmd.args(args);
+        return Ok(cmd);
+    };
+
+    let mut cmd = if sandbox.no_network {
+        if !cfg!(target_os = "linux") {
+            bail!("hooks.sandbox.no_network is only supported on Linux");
+        }
+        let mut cmd = Command::new("unshare");
+        cmd.args(["--net", "--map-root-user", program]);
+        cmd
+    } else {
+        Command::new(program)
+    };
+    cmd.args(args);
+
+    cmd.env_clear();
+    for key in &sandbox.env_allowlist {
+        if let Some(value) = std::env::var_os(key) {
+            cmd.env(key, value);
+        }
+    }
+
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let dir = match &sandbox.working_dir {
+        Some(dir) => expand_tilde(dir, &home),
+        None => home,
+    };
+    if !dir.is_dir() {
+        bail!("Hook working directory does not exist: {}", dir.display());
+    }
+    cmd.current_dir(dir);
+    Ok(cmd)
+}
+
+/// Wait for a child, killing it once `timeout` passes
+pub fn wait(mut child: Child, timeout: Option<Duration>, what: &str) -> Result<ExitStatus> {
+    let Some(timeout) = timeout else {
+        return Ok(child.wait()?);
+    };
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("{} timed out after {}s", what, timeout.as_secs());
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Timeout configured for a sandbox
+pub fn timeout(sandbox: Option<&SandboxConfig>) -> Option<Duration> {
+    sandbox
+        .and_then(|s| s.timeout_secs)
+        .map(Duration::from_secs)
+}
+
+fn join_paths<P: AsRef<Path>>(paths: &[P]) -> String {
+    paths
+        .iter()
+        .map(|p| p.as_ref().display().to_string())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Environment every hook of a stage gets
+fn context_env<P: AsRef<Path>>(label: &str, changed: &[P]) -> Vec<(&'static str, String)> {
+    let profile = crate::profiles::active_profile_name().unwrap_or_else(|_| "default".into());
+    vec![
+        ("DOTDIPPER_EVENT", label.replace('-', "_")),
+        ("DOTDIPPER_PROFILE", profile),
+        ("DOTDIPPER_CHANGED_FILES", join_paths(changed)),
+    ]
+}
+
+fn run_with_env(
+    hook: &Hook,
+    env: &[(&str, String)],
+    sandbox: Option<&SandboxConfig>,
+) -> Result<()> {
+    let child = command("sh", ["-c", hook.command()], sandbox)?
+        .envs(env.iter().map(|(k, v)| (*k, v.as_str())))
+        .spawn()
+        .with_context(|| format!("Failed to run hook: {}", hook))?;
+    let timeout = hook
+        .timeout_secs()
+        .map(Duration::from_secs)
+        .or_else(|| timeout(sandbox));
+    let status = wait(child, timeout, &format!("Hook '{}'", hook))?;
+
+    if !status.success() {
+        bail!("Hook failed with exit code: {:?}", status.code());
+    }
+
+    Ok(())
+}
+
+/// Run one hook of a stage. A failure is only a warning for hooks marked
+/// `continue_on_error`.
+fn run_hook(
+    label: &str,
+    hook: &Hook,
+    env: &[(&str, String)],
+    sandbox: Option<&SandboxConfig>,
+) -> Result<()> {
+    match run_with_env(hook, env, sandbox) {
+        Err(e) if hook.continue_on_error() => {
+            ui::warn(&format!(
+                "{} hook '{}' failed, continuing: {:#}",
+                label, hook, e
+            ));
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Run the hooks of one stage (`label` like `pre-apply`), failing on the
+/// first one that exits non-zero. `changed` is passed to the hooks in
+/// `DOTDIPPER_CHANGED_FILES`.
+pub fn run_all(
+    label: &str,
+    hooks: &[Hook],
+    config: &HooksConfig,
+    changed: &[PathBuf],
+) -> Result<()> {
+    let runnable = runnable(label, hooks);
+    if runnable.is_empty() {
+        return Ok(());
+    }
+    let env = context_env(label, changed);
+    for hook in runnable {
+        ui::info(&format!("Running {} hook: {}", label, hook));
+        run_hook(label, hook, &env, config.sandbox.as_ref())?;
+    }
+    Ok(())
+}
+
+/// Run the `[hooks.on_change]` hooks whose pattern matches any of the
+/// `changed` files (absolute paths an apply just wrote)
+pub fn run_on_change(config: &HooksConfig, changed: &[PathBuf]) -> Result<()> {
+    if config.on_change.is_empty() || changed.is_empty() {
+        return Ok(());
+    }
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    for (pattern, hook) in &config.on_change {
+        let matcher = PathMatcher::for_paths(&home, &[pattern.as_str()])
+            .with_context(|| format!("Invalid on_change pattern: {}", pattern))?;
+        let matched: Vec<&PathBuf> = changed.iter().filter(|p| matcher.is_match(p)).collect();
+        if matched.is_empty() {
+            continue;
+        }
+        let mut env = context_env("on-change", &matched);
+        env.push(("DOTDIPPER_FILES", join_paths(&matched)));
+        for hook in runnable("on-change", std::slice::from_ref(hook)) {
+            ui::info(&format!(
+                "Running on-change hook for {} ({} file(s)): {}",
+                pattern,
+                matched.len(),
+                hook
+            ));
+            run_hook("on-change", hook, &env, config.sandbox.as_ref())?;
+        }
+    }
+    Ok(())
+}
+
+/// Run snapshot hooks with the run's context.
+///
+/// Returns the files vetoed through `DOTDIPPER_SKIP_FILE` (always empty for
+/// post-snapshot hooks). Vetoes only apply to files in `ctx.files`.
+pub fn run_snapshot_hooks(
+    hooks: &[Hook],
+    config: &HooksConfig,
+    ctx: &SnapshotContext,
+) -> Result<Vec<PathBuf>> {
+    if hooks.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let temp_dir = tempfile::tempdir().context("Failed to create hook context directory")?;
+    let context_path = temp_dir.path().join("context.json");
+    fs::write(&context_path, serde_json::to_string_pretty(ctx)?)?;
+    let skip_path = temp_dir.path().join("skip");
+
+    let label = ctx.event.replace('_', "-");
+    let mut env = context_env(&label, &ctx.files);
+    env.push(("DOTDIPPER_FILES", join_paths(&ctx.files)));
+    env.push(("DOTDIPPER_CONTEXT", context_path.display().to_string()));
+    let vetoes_allowed = ctx.event == "pre_snapshot";
+    if vetoes_allowed {
+        env.push(("DOTDIPPER_SKIP_FILE", skip_path.display().to_string()));
+    }
+
+    for hook in runnable(&label, hooks) {
+        ui::info(&format!("Running {} hook: {}", label, hook));
+        run_hook(&label, hook, &env, config.sandbox.as_ref())?;
+    }
+
+    if !vetoes_allowed {
+        return Ok(Vec::new());
+    }
+    read_skip_list(&skip_path, &home, &ctx.files)
+}
+
+/// Print the hooks of every stage with their trust, then the sandbox settings
+pub fn print(config: &HooksConfig) {
+    let stages = [
+        ("pre-apply", &config.pre_apply),
+        ("post-apply", &config.post_apply),
+        ("pre-snapshot", &config.pre_snapshot),
+        ("post-snapshot", &config.post_snapshot),
+        ("pre-pull", &config.pre_pull),
+        ("post-pull", &config.post_pull),
+        ("pre-push", &config.pre_push),
+        ("post-push", &config.post_push),
+    ];
+    let held_back = pulled_unreviewed();
+    let trust = |hook: &Hook| match (hook.trusted(), held_back) {
+        (true, _) => "trusted",
+        (false, true) => "held back",
+        (false, false) => "-",
+    };
+    let rows = stages
+        .iter()
+        .flat_map(|(stage, hooks)| {
+            hooks
+                .iter()
+                .map(move |hook| vec![stage.to_string(), hook.to_string(), trust(hook).to_string()])
+        })
+        .chain(config.on_change.iter().map(|(pattern, hook)| {
+            vec![
+                format!("on-change {}", pattern),
+                hook.to_string(),
+                trust(hook).to_string(),
+            ]
+        }))
+        .collect();
+    ui::print_table(&["STAGE", "COMMAND", "TRUST"], rows);
+
+    if held_back {
+        ui::warn("Dotfiles were pulled since the last review; only trusted hooks run");
+        ui::hint("Run 'dotdipper hooks trust' after reviewing them");
+    }
+    match &config.sandbox {
+        Some(sandbox) => {
+            let dir = sandbox.working_dir.as_deref().unwrap_or("~");
+            let timeout = sandbox
+                .timeout_secs
+                .map(|t| format!("{}s", t))
+                .unwrap_or_else(|| "none".to_string());
+            ui::info(&format!(
+                "Sandbox: env {}; working dir {}; timeout {}; network {}",
+                sandbox.env_allowlist.join(","),
+                dir,
+                timeout,
+                if sandbox.no_network { "off" } else { "on" }
+            ));
+        }
+        None => ui::info("Sandbox: off (hooks inherit the full environment)"),
+    }
+}
+
+/// Files from `candidates` matched by the paths/patterns in a skip file
+pub fn read_skip_list(
+    skip_path: &Path,
+    home: &Path,
+    candidates: &[PathBuf],
+) -> Result<Vec<PathBuf>> {
+    if !skip_path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents = fs::read_to_string(skip_path)?;
+    let entries = parse_pattern_lines(&contents);
+    if entries.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let matcher = PathMatcher::for_paths(home, &entries)?;
+    Ok(candidates
+        .iter()
+        .filter(|p| matcher.is_match(p))
+        .cloned()
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pre_snapshot_hook_can_veto_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path();
+        let files = vec![
+            home.join(".zshrc"),
+            home.join(".config/nvim/init.lua"),
+            home.join(".config/nvim/lua/plugins.lua"),
+        ];
+
+        let skip = home.join("skip");
+        fs::write(&skip, "# broken right now\n.config/nvim\n").unwrap();
+        let vetoed = read_skip_list(&skip, home, &files).unwrap();
+        assert_eq!(vetoed, files[1..].to_vec());
+
+        assert!(read_skip_list(&home.join("missing"), home, &files)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sandbox_scrubs_env_and_enforces_timeout() {
+        let temp_dir = TempDir::new().unwrap();
+        let sandbox = SandboxConfig {
+            env_allowlist: vec!["PATH".to_string()],
+            working_dir: Some(temp_dir.path().display().to_string()),
+            timeout_secs: Some(1),
+            no_network: false,
+        };
+
+        let output = command("sh", ["-c", "env; pwd"], Some(&sandbox))
+            .unwrap()
+            .env("DOTDIPPER_EVENT", "pre_apply")
+            .output()
+            .unwrap();
+        let output = String::from_utf8_lossy(&output.stdout);
+        assert!(output.contains("PATH="));
+        assert!(output.contains("DOTDIPPER_EVENT=pre_apply"));
+        assert!(!output.contains("HOME="));
+        assert!(output.contains(&temp_dir.path().display().to_string()));
+
+        let child = command("sh", ["-c", "sleep 5"], Some(&sandbox))
+            .unwrap()
+            .spawn()
+            .unwrap();
+        let err = wait(child, timeout(Some(&sandbox)), "Hook 'sleep 5'").unwrap_err();
+        assert!(err.to_string().contains("timed out after 1s"));
+    }
+
+    #[test]
+    fn test_hook_failure_policy_and_timeout() {
+        let detailed = |run: &str, continue_on_error, timeout_secs| Hook::Detailed {
+            run: run.to_string(),
+            trusted: false,
+            continue_on_error,
+            timeout_secs,
+        };
+
+        assert!(run_hook("post-apply", &Hook::from("exit 3"), &[], None).is_err());
+        assert!(run_hook("post-apply", &detailed("exit 3", true, None), &[], None).is_ok());
+
+        let err = run_hook(
+            "post-apply",
+            &detailed("sleep 5", false, Some(1)),
+            &[],
+            None,
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("timed out after 1s"));
+        assert!(run_hook("post-apply", &detailed("sleep 5", true, Some(1)), &[], None).is_ok());
+    }
+}
@@ -0,0 +1,287 @@
+//! Host groups and host targeting.
+//!
+//! `[hosts.<group>]` entries give the machines matching their hostname
+//! `patterns` membership in the group plus its `tags`. File overrides
+//! (`only_hosts`), `[packages.hosts]` and `[hooks.hosts]` then target machines
+//! with selectors:
+//!
+//! - `role=server`: hosts carrying that tag
+//! - `web`: members of the `web` group
+//! - anything else: a glob on the hostname (`db-*`)
+//!
+//! File overrides can also set `hostname` (a glob), `os` and `profile`
+//! conditions; a file is only snapshotted and applied where all of them hold.
+//!
+//! The local host is resolved at snapshot/apply time from its hostname
+//! (`DOTDIPPER_HOSTNAME` overrides it) plus any tags in `DOTDIPPER_HOST_TAGS`
+//! (`role=server,env=prod`) for machines whose names follow no pattern.
+
+use anyhow::{bail, Result};
+use glob::Pattern;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::{Config, FileOverride, HooksConfig, PackagesConfig};
+use crate::matching::to_home_relative;
+
+const HOSTNAME_ENV: &str = "DOTDIPPER_HOSTNAME";
+const HOST_TAGS_ENV: &str = "DOTDIPPER_HOST_TAGS";
+
+/// This machine's name, or `DOTDIPPER_HOSTNAME` when set
+pub fn local_hostname() -> String {
+    std::env::var(HOSTNAME_ENV).ok().unwrap_or_else(|| {
+        hostname::get()
+            .ok()
+            .and_then(|h| h.into_string().ok())
+            .unwrap_or_else(|| "unknown".to_string())
+    })
+}
+
+/// Who the local machine is, as far as targeting is concerned
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostIdentity {
+    pub name: String,
+    pub groups: Vec<String>,
+    pub tags: BTreeMap<String, String>,
+    /// Profile in use, for `profile` conditions
+    pub profile: String,
+}
+
+impl HostIdentity {
+    /// Identity of `name` under the config's `[hosts]` groups
+    pub fn resolve(config: &Config, name: &str) -> Result<Self> {
+        let mut identity = HostIdentity {
+            name: name.to_string(),
+            profile: "default".to_string(),
+            ..Default::default()
+        };
+        for (group, def) in &config.hosts {
+            let mut member = false;
+            for pattern in &def.patterns {
+                let Ok(glob) = Pattern::new(pattern) else {
+                    bail!(
+                        "Invalid hostname pattern '{}' in [hosts.{}]",
+                        pattern,
+                        group
+                    );
+                };
+                member |= glob.matches(name);
+            }
+            if member {
+                identity.groups.push(group.clone());
+                identity.tags.extend(def.tags.clone());
+            }
+        }
+        Ok(identity)
+    }
+
+    /// Identity of this machine
+    pub fn local(config: &Config) -> Result<Self> {
+        let mut identity = Self::resolve(config, &local_hostname())?;
+        if let Ok(profile) = crate::profiles::active_profile_name() {
+            identity.profile = profile;
+        }
+        if let Ok(tags) = std::env::var(HOST_TAGS_ENV) {
+            for tag in tags.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+                let Some((key, value)) = tag.split_once('=') else {
+                    bail!(
+                        "Invalid tag '{}' in {}: expected key=value",
+                        tag,
+                        HOST_TAGS_ENV
+                    );
+                };
+                identity
+                    .tags
+                    .insert(key.trim().to_string(), value.trim().to_string());
+            }
+        }
+        Ok(identity)
+    }
+
+    /// Whether a single selector targets this host
+    pub fn matches(&self, selector: &str) -> bool {
+        if let Some((key, value)) = selector.split_once('=') {
+            return self.tags.get(key.trim()).map(String::as_str) == Some(value.trim());
+        }
+        if self.groups.iter().any(|g| g == selector) {
+            return true;
+        }
+        Pattern::new(selector)
+            .map(|p| p.matches(&self.name))
+            .unwrap_or(false)
+    }
+
+    /// Whether any selector targets this host; no selectors means every host
+    pub fn matches_any(&self, selectors: &[String]) -> bool {
+        selectors.is_empty() || selectors.iter().any(|s| self.matches(s))
+    }
+
+    /// Whether every condition of a `[files]` override (`only_hosts`,
+    /// `hostname`, `os`, `profile`) holds here
+    pub fn allows(&self, file_override: &FileOverride) -> bool {
+        let os = std::env::consts::OS;
+        self.matches_any(&file_override.only_hosts)
+            && file_override
+                .hostname
+                .as_deref()
+                .is_none_or(|glob| Pattern::new(glob).is_ok_and(|p| p.matches(&self.name)))
+            && file_override
+                .os
+                .as_deref()
+                .is_none_or(|want| want == os || want == std::env::consts::FAMILY)
+            && file_override
+                .profile
+                .as_deref()
+                .is_none_or(|want| want == self.profile)
+    }
+
+    /// Whether a `[files]` override keeps `rel_path` off this host
+    pub fn excludes_file(&self, config: &Config, rel_path: &Path) -> bool {
+        config
+            .file_override(rel_path)
+            .is_some_and(|o| !self.allows(o))
+    }
+
+    /// One-line description for messages, e.g. `web-01 (web, role=server)`
+    pub fn describe(&self) -> String {
+        let mut details = self.groups.clone();
+        details.extend(self.tags.iter().map(|(k, v)| format!("{}={}", k, v)));
+        if details.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, details.join(", "))
+        }
+    }
+}
+
+/// Split tracked files into the ones for this host and the ones that
+/// `only_hosts` overrides keep on other hosts
+pub fn partition_files(
+    config: &Config,
+    host: &HostIdentity,
+    home: &Path,
+    files: Vec<PathBuf>,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    files
+        .into_iter()
+        .partition(|f| !host.excludes_file(config, &to_home_relative(f, home)))
+}
+
+/// Packages for this host: the base lists plus matching `[packages.hosts]` entries
+pub fn packages_for(packages: &PackagesConfig, host: &HostIdentity) -> PackagesConfig {
+    let mut resolved = packages.clone();
+    for (selector, extra) in &packages.hosts {
+        if host.matches(selector) {
+            resolved.common.extend(extra.iter().cloned());
+        }
+    }
+    resolved.hosts.clear();
+    resolved
+}
+
+/// Hooks for this host: the base hooks followed by matching `[hooks.hosts]` entries
+pub fn hooks_for(hooks: &HooksConfig, host: &HostIdentity) -> HooksConfig {
+    let mut resolved = hooks.clone();
+    resolved.hosts.clear();
+    for (selector, extra) in &hooks.hosts {
+        if host.matches(selector) {
+            resolved.pre_apply.extend(extra.pre_apply.iter().cloned());
+            resolved.post_apply.extend(extra.post_apply.iter().cloned());
+            resolved
+                .pre_snapshot
+                .extend(extra.pre_snapshot.iter().cloned());
+            resolved
+                .post_snapshot
+                .extend(extra.post_snapshot.iter().cloned());
+            resolved.pre_pull.extend(extra.pre_pull.iter().cloned());
+            resolved.post_pull.extend(extra.post_pull.iter().cloned());
+            resolved.pre_push.extend(extra.pre_push.iter().cloned());
+            resolved.post_push.extend(extra.post_push.iter().cloned());
+            resolved.on_change.extend(
+                extra
+                    .on_change
+                    .iter()
+                    .map(|(pattern, hook)| (pattern.clone(), hook.clone())),
+            );
+        }
+    }
+    resolved
+}
+
+/// `[hooks]` resolved for the local host
+pub fn local_hooks(config: &Config) -> Result<Option<HooksConfig>> {
+    let Some(hooks) = &config.hooks else {
+        return Ok(None);
+    };
+    Ok(Some(hooks_for(hooks, &HostIdentity::local(config)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::HostGroup;
+
+    #[test]
+    fn test_selectors_resolve_against_groups_tags_and_hostname() {
+        let mut config = Config::default();
+        config.hosts.insert(
+            "web".to_string(),
+            HostGroup {
+                patterns: vec!["web-*".to_string()],
+                tags: BTreeMap::from([("role".to_string(), "server".to_string())]),
+                ..Default::default()
+            },
+        );
+        config.hosts.insert(
+            "laptops".to_string(),
+            HostGroup {
+                patterns: vec!["*-mbp".to_string()],
+                tags: BTreeMap::from([("role".to_string(), "laptop".to_string())]),
+                ..Default::default()
+            },
+        );
+        config
+            .packages
+            .hosts
+            .insert("role=server".to_string(), vec!["nginx".to_string()]);
+
+        let web = HostIdentity::resolve(&config, "web-01").unwrap();
+        assert_eq!(web.groups, vec!["web".to_string()]);
+        assert!(web.matches("role=server"));
+        assert!(web.matches("web"));
+        assert!(web.matches("web-0?"));
+        assert!(!web.matches("role=laptop"));
+        assert!(web.matches_any(&[]));
+        assert!(packages_for(&config.packages, &web)
+            .common
+            .contains(&"nginx".to_string()));
+
+        let laptop = HostIdentity::resolve(&config, "ana-mbp").unwrap();
+        assert!(!laptop.matches_any(&["role=server".to_string(), "web".to_string()]));
+        assert!(!packages_for(&config.packages, &laptop)
+            .common
+            .contains(&"nginx".to_string()));
+    }
+
+    #[test]
+    fn test_file_override_conditions() {
+        let mut host = HostIdentity::resolve(&Config::default(), "work-laptop").unwrap();
+        let cond = |hostname: Option<&str>, os: Option<&str>, profile: Option<&str>| FileOverride {
+            hostname: hostname.map(String::from),
+            os: os.map(String::from),
+            profile: profile.map(String::from),
+            ..Default::default()
+        };
+
+        assert!(host.allows(&FileOverride::default()));
+        assert!(host.allows(&cond(Some("work-*"), None, None)));
+        assert!(!host.allows(&cond(Some("arch-desktop"), None, None)));
+        assert!(host.allows(&cond(None, Some(std::env::consts::OS), None)));
+        assert!(host.allows(&cond(None, Some(std::env::consts::FAMILY), None)));
+        assert!(!host.allows(&cond(None, Some("plan9"), None)));
+        assert!(host.allows(&cond(None, None, Some("default"))));
+        assert!(!host.allows(&cond(Some("work-*"), None, Some("server"))));
+        host.profile = "server".to_string();
+        assert!(host.allows(&cond(Some("work-*"), None, Some("server"))));
+    }
+}
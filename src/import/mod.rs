@@ -0,0 +1,578 @@
+//! Importing an existing dotfiles setup.
+//!
+//! `dotdipper import --from <tool> <path>` reads the files another tool
+//! manages and lays them out in the compiled directory, as if they had been
+//! snapshotted from `$HOME`:
+//!
+//! - `chezmoi`: a chezmoi source directory. Attribute prefixes are decoded
+//!   (`dot_`, `private_`, `executable_`, ...), `.tmpl` files become
+//!   templates with simple `{{ .chezmoi.hostname }}`-style expressions
+//!   converted to Tera, and scripts, symlinks and encrypted files are
+//!   skipped.
+//! - `stow`: a GNU stow directory. Every package in it mirrors `$HOME`;
+//!   `dot-` prefixes (stow's `--dotfiles`) are decoded.
+//! - `git`: a bare dotfiles repository (`git --git-dir=~/.cfg
+//!   --work-tree=$HOME`) or a checkout of one. The files committed in
+//!   `HEAD` are imported.
+//!
+//! The files are added to `tracked_files` and the manifest, templates get a
+//! `[files]` override, and an initial snapshot is taken. Nothing in `$HOME`
+//! is touched; `dotdipper diff` and `apply` take it from there.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::matching::{display_tilde, expand_tilde};
+use crate::ui;
+
+/// Tools dotdipper can import from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Chezmoi,
+    Stow,
+    Git,
+}
+
+impl Source {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "chezmoi" => Some(Source::Chezmoi),
+            "stow" => Some(Source::Stow),
+            "git" => Some(Source::Git),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Source::Chezmoi => "chezmoi",
+            Source::Stow => "stow",
+            Source::Git => "git",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ImportOpts {
+    /// Only list what would be imported
+    pub dry_run: bool,
+    /// Replace compiled files that differ from the imported ones
+    pub force: bool,
+}
+
+/// A file to import, at its place relative to `$HOME`
+#[derive(Debug)]
+struct ImportedFile {
+    rel_path: PathBuf,
+    contents: Vec<u8>,
+    /// Unix permission bits
+    mode: u32,
+    template: bool,
+}
+
+/// What was found in the source
+#[derive(Debug, Default)]
+struct Scan {
+    files: Vec<ImportedFile>,
+    /// Entries that are not imported, with the reason
+    skipped: Vec<(PathBuf, String)>,
+    /// Templates whose syntax could not be converted
+    unconverted: Vec<PathBuf>,
+    /// Variables the converted templates use besides the built-ins
+    variables: BTreeSet<String>,
+}
+
+impl Scan {
+    fn push(&mut self, file: ImportedFile, source: &Path) {
+        if !crate::confine::is_plain(&file.rel_path) {
+            self.skipped
+                .push((source.to_path_buf(), "not a plain path".to_string()));
+        } else if self.files.iter().any(|f| f.rel_path == file.rel_path) {
+            self.skipped.push((
+                source.to_path_buf(),
+                format!("{} is imported already", display_tilde(&file.rel_path)),
+            ));
+        } else {
+            self.files.push(file);
+        }
+    }
+}
+
+/// Chezmoi attributes collected from a source path
+#[derive(Debug, Default)]
+struct Attributes {
+    executable: bool,
+    private: bool,
+    template: bool,
+}
+
+/// Target name of a chezmoi source entry, or why it is not imported
+fn chezmoi_target(name: &str, is_file: bool, attrs: &mut Attributes) -> Result<String, String> {
+    let mut rest = name;
+    let mut dot = false;
+    loop {
+        if let Some(r) = rest.strip_prefix("literal_") {
+            rest = r;
+            break;
+        }
+        if let Some(r) = rest.strip_prefix("dot_") {
+            rest = r;
+            dot = true;
+            break;
+        }
+        let Some((prefix, r)) = rest.split_once('_') else {
+            break;
+        };
+        match prefix {
+            "encrypted" => return Err("encrypted with chezmoi's key".to_string()),
+            "run" => return Err("chezmoi script".to_string()),
+            "modify" => return Err("chezmoi modify script".to_string()),
+            "remove" => return Err("chezmoi removal entry".to_string()),
+            "symlink" => return Err("symlink".to_string()),
+            "external" => return Err("chezmoi external".to_string()),
+            "executable" => attrs.executable = true,
+            "private" => attrs.private = true,
+            "readonly" | "empty" | "exact" | "create" | "once" | "onchange" | "before"
+            | "after" => {}
+            _ => break,
+        }
+        rest = r;
+    }
+    if is_file {
+        if let Some(r) = rest.strip_suffix(".tmpl") {
+            attrs.template = true;
+            rest = r;
+        } else if let Some(r) = rest.strip_suffix(".literal") {
+            rest = r;
+        }
+    }
+    if rest.is_empty() {
+        return Err("empty target name".to_string());
+    }
+    Ok(if dot {
+        format!(".{}", rest)
+    } else {
+        rest.to_string()
+    })
+}
+
+/// Convert a chezmoi (Go) template to Tera. Only plain variable
+/// expressions such as `{{ .email }}` and `{{ .chezmoi.hostname }}` are
+/// supported; anything else returns `None`. Custom variables used are added
+/// to `variables`.
+fn convert_chezmoi_template(text: &str, variables: &mut BTreeSet<String>) -> Option<String> {
+    if text.contains("{%") || text.contains("{#") {
+        return None;
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let end = start + rest[start..].find("}}")?;
+        let expr = rest[start + 2..end].trim_matches(|c: char| c == '-' || c.is_whitespace());
+        let var = expr.strip_prefix('.')?;
+        if var.is_empty()
+            || !var
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        {
+            return None;
+        }
+        let var = match var.strip_prefix("chezmoi.") {
+            Some("hostname") | Some("fqdnHostname") => "hostname",
+            Some("os") => "os",
+            Some("arch") => "arch",
+            Some("username") => "user",
+            Some("homeDir") => "home",
+            Some(_) => return None,
+            None => {
+                variables.insert(var.split('.').next().unwrap_or(var).to_string());
+                var
+            }
+        };
+        out.push_str("{{ ");
+        out.push_str(var);
+        out.push_str(" }}");
+        rest = &rest[end + 2..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o777
+}
+
+#[cfg(not(unix))]
+fn file_mode(_metadata: &fs::Metadata) -> u32 {
+    0o644
+}
+
+/// Directory entries to leave out of every walk
+fn is_vcs_dir(entry: &walkdir::DirEntry) -> bool {
+    entry.file_type().is_dir() && matches!(entry.file_name().to_str(), Some(".git" | ".hg"))
+}
+
+fn read_chezmoi(dir: &Path) -> Result<Scan> {
+    // .chezmoiroot moves the source state into a subdirectory
+    let root = match fs::read_to_string(dir.join(".chezmoiroot")) {
+        Ok(sub) => dir.join(sub.trim()),
+        Err(_) => dir.to_path_buf(),
+    };
+
+    let mut scan = Scan::default();
+    let walker = walkdir::WalkDir::new(&root)
+        .min_depth(1)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            // .chezmoiignore, .chezmoiscripts/, .chezmoidata.* and friends
+            !is_vcs_dir(e) && !e.file_name().to_string_lossy().starts_with(".chezmoi")
+        });
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let source = entry.path().strip_prefix(&root)?.to_path_buf();
+        if entry.file_type().is_symlink() {
+            scan.skipped.push((source, "symlink".to_string()));
+            continue;
+        }
+
+        let mut attrs = Attributes::default();
+        let components: Vec<String> = source
+            .iter()
+            .map(|c| c.to_string_lossy().into_owned())
+            .collect();
+        let mut rel_path = PathBuf::new();
+        let mut reason = None;
+        for (i, component) in components.iter().enumerate() {
+            let is_file = i + 1 == components.len();
+            // Of a directory's attributes only private_ carries over
+            let mut component_attrs = Attributes::default();
+            match chezmoi_target(component, is_file, &mut component_attrs) {
+                Ok(name) => rel_path.push(name),
+                Err(why) => {
+                    reason = Some(why);
+                    break;
+                }
+            }
+            attrs.private |= component_attrs.private;
+            if is_file {
+                attrs.executable = component_attrs.executable;
+                attrs.template = component_attrs.template;
+            }
+        }
+        if let Some(reason) = reason {
+            scan.skipped.push((source, reason));
+            continue;
+        }
+
+        let mut contents = fs::read(entry.path())
+            .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+        if attrs.template {
+            let converted = std::str::from_utf8(&contents)
+                .ok()
+                .and_then(|text| convert_chezmoi_template(text, &mut scan.variables));
+            match converted {
+                Some(text) => contents = text.into_bytes(),
+                None => scan.unconverted.push(rel_path.clone()),
+            }
+        }
+        let mode = match (attrs.executable, attrs.private) {
+            (true, true) => 0o700,
+            (true, false) => 0o755,
+            (false, true) => 0o600,
+            (false, false) => 0o644,
+        };
+        scan.push(
+            ImportedFile {
+                rel_path,
+                contents,
+                mode,
+                template: attrs.template,
+            },
+            &source,
+        );
+    }
+    Ok(scan)
+}
+
+fn read_stow(dir: &Path) -> Result<Scan> {
+    let mut packages: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read {}", dir.display()))?
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter(|e| !matches!(e.file_name().to_str(), Some(".git" | ".hg")))
+        .map(|e| e.path())
+        .collect();
+    packages.sort();
+
+    let mut scan = Scan::default();
+    for package in &packages {
+        let walker = walkdir::WalkDir::new(package)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| !is_vcs_dir(e));
+        for entry in walker {
+            let entry = entry?;
+            if entry.file_type().is_dir() || entry.file_name() == ".stow-local-ignore" {
+                continue;
+            }
+            let source = entry.path().strip_prefix(dir)?.to_path_buf();
+            if entry.file_type().is_symlink() {
+                scan.skipped.push((source, "symlink".to_string()));
+                continue;
+            }
+            let rel_path: PathBuf = entry
+                .path()
+                .strip_prefix(package)?
+                .iter()
+                .map(|c| {
+                    let c = c.to_string_lossy();
+                    match c.strip_prefix("dot-") {
+                        Some(rest) => format!(".{}", rest),
+                        None => c.into_owned(),
+                    }
+                })
+                .collect();
+            let contents = fs::read(entry.path())
+                .with_context(|| format!("Failed to read {}", entry.path().display()))?;
+            scan.push(
+                ImportedFile {
+                    rel_path,
+                    contents,
+                    mode: file_mode(&entry.metadata()?),
+                    template: false,
+                },
+                &source,
+            );
+        }
+    }
+    Ok(scan)
+}
+
+fn read_git(dir: &Path) -> Result<Scan> {
+    let entries = crate::vcs::tree_entries(dir, "HEAD")
+        .with_context(|| format!("{} is not a git repository with commits", dir.display()))?;
+
+    let mut scan = Scan::default();
+    for (mode, path) in entries {
+        let mode = match mode.as_str() {
+            "100755" => 0o755,
+            "100644" => 0o644,
+            "120000" => {
+                scan.skipped.push((path, "symlink".to_string()));
+                continue;
+            }
+            _ => {
+                scan.skipped.push((path, "submodule".to_string()));
+                continue;
+            }
+        };
+        let contents = crate::vcs::file_at(dir, "HEAD", &path)?;
+        scan.push(
+            ImportedFile {
+                rel_path: path.clone(),
+                contents,
+                mode,
+                template: false,
+            },
+            &path,
+        );
+    }
+    Ok(scan)
+}
+
+fn write_file(dest: &Path, file: &ImportedFile) -> Result<()> {
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(dest, &file.contents)
+        .with_context(|| format!("Failed to write {}", dest.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dest, fs::Permissions::from_mode(file.mode))?;
+    }
+    Ok(())
+}
+
+/// `dotdipper import --from <source> <path>`
+pub fn import(config_path: &Path, source: Source, path: &str, opts: &ImportOpts) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let dir = expand_tilde(path, &home);
+    if !dir.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+
+    let scan = match source {
+        Source::Chezmoi => read_chezmoi(&dir)?,
+        Source::Stow => read_stow(&dir)?,
+        Source::Git => read_git(&dir)?,
+    };
+    for (entry, reason) in &scan.skipped {
+        ui::warn(&format!("Skipping {}: {}", entry.display(), reason));
+    }
+    if scan.files.is_empty() {
+        bail!("No files to import in {}", dir.display());
+    }
+
+    let mut config = crate::cfg::load(config_path)?;
+    let compiled = crate::paths::compiled_dir()?;
+
+    let mut files = Vec::new();
+    for file in &scan.files {
+        let dest = compiled.join(&file.rel_path);
+        let differs = fs::read(&dest).is_ok_and(|current| current != file.contents);
+        if differs && !opts.force {
+            ui::warn(&format!(
+                "Skipping {}: the compiled copy differs (use --force to replace it)",
+                display_tilde(&file.rel_path)
+            ));
+            continue;
+        }
+        files.push(file);
+    }
+
+    ui::section(&format!("Importing from {}", source.name()));
+    for file in &files {
+        let suffix = if file.template { " (template)" } else { "" };
+        println!("  + {}{}", display_tilde(&file.rel_path), suffix);
+    }
+    if opts.dry_run {
+        ui::info(&format!("Would import {} file(s)", files.len()));
+        return Ok(());
+    }
+
+    let mut stored = Vec::new();
+    for file in &files {
+        let dest = compiled.join(&file.rel_path);
+        write_file(&dest, file)?;
+        stored.push(dest);
+
+        let target = home.join(&file.rel_path);
+        if !config.general.tracked_files.contains(&target) {
+            config.general.tracked_files.push(target);
+        }
+        if file.template {
+            config
+                .files
+                .entry(display_tilde(&file.rel_path))
+                .or_default()
+                .template = true;
+        }
+    }
+
+    let manifest_path = crate::paths::manifest_file()?;
+    let mut manifest = if manifest_path.exists() {
+        crate::hash::Manifest::load(&manifest_path)?
+    } else {
+        crate::hash::Manifest::new()
+    };
+    for mut file_hash in crate::hash::hash_files(&stored, false)? {
+        file_hash.path = file_hash
+            .path
+            .strip_prefix(&compiled)
+            .unwrap_or(&file_hash.path)
+            .to_path_buf();
+        manifest.add_file(file_hash);
+    }
+    manifest.save(&manifest_path)?;
+    crate::cfg::save(config_path, &config)?;
+
+    ui::success(&format!(
+        "Imported {} file(s) from {}",
+        files.len(),
+        source.name()
+    ));
+    crate::snapshots::create(&config, Some(format!("Imported from {}", source.name())))?;
+
+    if !scan.unconverted.is_empty() {
+        ui::warn("These templates use chezmoi syntax that needs converting to Tera by hand:");
+        for rel_path in &scan.unconverted {
+            println!("  {}", display_tilde(rel_path));
+        }
+    }
+    let missing: Vec<&String> = scan
+        .variables
+        .iter()
+        .filter(|v| !config.variables.contains_key(*v))
+        .collect();
+    if !missing.is_empty() {
+        let names: Vec<&str> = missing.iter().map(|v| v.as_str()).collect();
+        ui::hint(&format!(
+            "Define these template variables under [variables]: {}",
+            names.join(", ")
+        ));
+    }
+    ui::hint("Run 'dotdipper diff' to compare with $HOME, then 'dotdipper apply'");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chezmoi_names_and_templates() {
+        let mut attrs = Attributes::default();
+        assert_eq!(
+            chezmoi_target("private_executable_dot_local-bin.tmpl", true, &mut attrs).unwrap(),
+            ".local-bin"
+        );
+        assert!(attrs.private && attrs.executable && attrs.template);
+        let mut attrs = Attributes::default();
+        assert_eq!(
+            chezmoi_target("literal_dot_keep", true, &mut attrs).unwrap(),
+            "dot_keep"
+        );
+        assert_eq!(
+            chezmoi_target("exact_dot_config", false, &mut attrs).unwrap(),
+            ".config"
+        );
+        assert!(chezmoi_target("run_once_install.sh", true, &mut attrs).is_err());
+        assert!(chezmoi_target("encrypted_private_dot_netrc.age", true, &mut attrs).is_err());
+
+        let mut variables = BTreeSet::new();
+        assert_eq!(
+            convert_chezmoi_template(
+                "[user]\n\temail = {{ .email }}\n# {{- .chezmoi.hostname -}}\n",
+                &mut variables
+            )
+            .unwrap(),
+            "[user]\n\temail = {{ email }}\n# {{ hostname }}\n"
+        );
+        assert_eq!(variables, BTreeSet::from(["email".to_string()]));
+        assert!(convert_chezmoi_template(
+            "{{ if eq .chezmoi.os \"darwin\" }}x{{ end }}",
+            &mut variables
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_read_stow_packages() {
+        let temp = tempfile::tempdir().unwrap();
+        let stow = temp.path();
+        fs::create_dir_all(stow.join("zsh")).unwrap();
+        fs::create_dir_all(stow.join("nvim/dot-config/nvim")).unwrap();
+        fs::write(stow.join("zsh/.zshrc"), "export A=1").unwrap();
+        fs::write(stow.join("zsh/.stow-local-ignore"), "README").unwrap();
+        fs::write(stow.join("nvim/dot-config/nvim/init.lua"), "-- nvim").unwrap();
+        fs::write(stow.join("README.md"), "my dotfiles").unwrap();
+
+        let scan = read_stow(stow).unwrap();
+        let paths: Vec<&Path> = scan.files.iter().map(|f| f.rel_path.as_path()).collect();
+        assert_eq!(
+            paths,
+            vec![Path::new(".config/nvim/init.lua"), Path::new(".zshrc")]
+        );
+    }
+}
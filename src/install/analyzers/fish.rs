@@ -0,0 +1,114 @@
+//! Fish shell analyzer for detecting binary dependencies.
+//!
+//! Analyzes config.fish, conf.d and function files for command checks,
+//! `<tool> init fish | source` lines, abbreviations, aliases and editor
+//! variables, and `fish_plugins` for fisher plugins that call tools.
+
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashSet;
+
+use super::command_name;
+
+/// Analyze fish configuration content for binary dependencies
+pub fn analyze(content: &str) -> Result<HashSet<String>> {
+    let mut binaries = HashSet::new();
+
+    // Pattern 1: command checks: type -q, command -q/-v/-s, which
+    let check = Regex::new(r"(?:\btype\s+-q|\bcommand\s+-[qvs]|\bwhich)\s+([a-zA-Z0-9_.-]+)")?;
+    for cap in check.captures_iter(content) {
+        if let Some(name) = cap.get(1).and_then(|b| command_name(b.as_str())) {
+            binaries.insert(name);
+        }
+    }
+
+    // Pattern 2: tool initialization, e.g. starship init fish | source
+    let init =
+        Regex::new(r"(?m)^\s*([a-zA-Z0-9_-]+)\s+(?:init|activate|hook|env)\b[^|\n]*\|\s*source")?;
+    for cap in init.captures_iter(content) {
+        if let Some(name) = cap.get(1).and_then(|b| command_name(b.as_str())) {
+            binaries.insert(name);
+        }
+    }
+
+    // Pattern 3: abbreviations and aliases: abbr -a ll 'eza -l', alias vim=nvim
+    let abbr = Regex::new(
+        r#"(?m)^\s*(?:abbr(?:\s+-[a-z-]+)*|alias)\s+[a-zA-Z0-9_.-]+(?:\s+|=)['"]?([^'"\n]+)"#,
+    )?;
+    for cap in abbr.captures_iter(content) {
+        if let Some(name) = cap.get(1).and_then(|b| command_name(b.as_str())) {
+            binaries.insert(name);
+        }
+    }
+
+    // Pattern 4: editor and pager variables
+    let editor = Regex::new(
+        r#"(?m)^\s*set\s+(?:-[a-zA-Z]+\s+)*(?:EDITOR|VISUAL|PAGER|MANPAGER)\s+['"]?([^'"\n]+)"#,
+    )?;
+    for cap in editor.captures_iter(content) {
+        if let Some(name) = cap.get(1).and_then(|b| command_name(b.as_str())) {
+            binaries.insert(name);
+        }
+    }
+
+    // Pattern 5: toolchain directories added to PATH
+    let add_path = Regex::new(r"(?m)^\s*fish_add_path\s+(.+)$")?;
+    for cap in add_path.captures_iter(content) {
+        let paths = cap.get(1).map(|p| p.as_str()).unwrap_or("");
+        for (fragment, binary) in [(".cargo", "cargo"), ("go/bin", "go"), (".pyenv", "pyenv")] {
+            if paths.contains(fragment) {
+                binaries.insert(binary.to_string());
+            }
+        }
+    }
+
+    binaries.remove("fish");
+    Ok(binaries)
+}
+
+/// Analyze a fisher `fish_plugins` file: one plugin per line
+pub fn analyze_plugins(content: &str) -> HashSet<String> {
+    let mut binaries = HashSet::new();
+    for line in content.lines().map(str::trim) {
+        let name = line.rsplit('/').next().unwrap_or(line).to_lowercase();
+        let needs: &[&str] = match name.as_str() {
+            "fzf.fish" | "fzf" => &["fzf", "fd", "bat"],
+            "z" | "zoxide.fish" => &["zoxide"],
+            "nvm.fish" => &["curl"],
+            _ => &[],
+        };
+        binaries.extend(needs.iter().map(|b| b.to_string()));
+    }
+    binaries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fish_config() {
+        let content = r#"
+if type -q eza
+    abbr -a ll 'eza -l --git'
+end
+starship init fish | source
+zoxide init fish | source
+alias vim=nvim
+set -gx EDITOR hx
+fish_add_path ~/.cargo/bin
+"#;
+        let binaries = analyze(content).unwrap();
+        for binary in ["eza", "starship", "zoxide", "nvim", "hx", "cargo"] {
+            assert!(binaries.contains(binary), "missing {}", binary);
+        }
+        assert!(!binaries.contains("fish"));
+    }
+
+    #[test]
+    fn test_fisher_plugins() {
+        let binaries = analyze_plugins("jorgebucaran/fisher\nPatrickF1/fzf.fish\n");
+        assert!(binaries.contains("fzf"));
+        assert!(binaries.contains("fd"));
+    }
+}
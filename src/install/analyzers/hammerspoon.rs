@@ -0,0 +1,48 @@
+//! Hammerspoon configuration analyzer for detecting binary dependencies.
+//!
+//! Analyzes `~/.hammerspoon/*.lua` for the programs it runs through
+//! `hs.execute`, `hs.task.new` and `os.execute`, e.g. yabai or skhd.
+
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashSet;
+
+use super::command_name;
+
+/// Analyze Hammerspoon Lua content for binary dependencies
+pub fn analyze(content: &str) -> Result<HashSet<String>> {
+    let mut binaries = HashSet::new();
+    binaries.insert("hammerspoon".to_string());
+
+    let run = Regex::new(
+        r#"\b(?:hs\.execute|hs\.task\.new|os\.execute|io\.popen)\s*\(\s*(?:\[\[|['"])([^'"\]]+)"#,
+    )?;
+    for cap in run.captures_iter(content) {
+        if let Some(name) = cap.get(1).and_then(|c| command_name(c.as_str())) {
+            binaries.insert(name);
+        }
+    }
+
+    Ok(binaries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hammerspoon_commands() {
+        let content = r#"
+hs.hotkey.bind({"alt"}, "1", function()
+  hs.execute("/opt/homebrew/bin/yabai -m space --focus 1")
+end)
+hs.task.new("/usr/local/bin/skhd", nil, {"--reload"}):start()
+os.execute([[open -a Kitty]])
+"#;
+        let binaries = analyze(content).unwrap();
+        assert!(binaries.contains("hammerspoon"));
+        assert!(binaries.contains("yabai"));
+        assert!(binaries.contains("skhd"));
+        assert_eq!(binaries.len(), 3);
+    }
+}
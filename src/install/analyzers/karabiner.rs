@@ -0,0 +1,66 @@
+//! Karabiner-Elements configuration analyzer for detecting binary dependencies.
+//!
+//! Analyzes karabiner.json for the programs its rules run through
+//! `shell_command`.
+
+use anyhow::Result;
+use std::collections::HashSet;
+
+use super::command_name;
+
+/// Analyze karabiner.json content for binary dependencies
+pub fn analyze(content: &str) -> Result<HashSet<String>> {
+    let mut binaries = HashSet::new();
+    binaries.insert("karabiner-elements".to_string());
+
+    let json: serde_json::Value = serde_json::from_str(content)?;
+    let mut commands = Vec::new();
+    collect_shell_commands(&json, &mut commands);
+    binaries.extend(commands.iter().filter_map(|c| command_name(c)));
+
+    Ok(binaries)
+}
+
+fn collect_shell_commands<'a>(value: &'a serde_json::Value, out: &mut Vec<&'a str>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                match value.as_str() {
+                    Some(command) if key == "shell_command" => out.push(command),
+                    _ => collect_shell_commands(value, out),
+                }
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_shell_commands(item, out);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_karabiner_shell_commands() {
+        let content = r#"{
+  "profiles": [{
+    "complex_modifications": {
+      "rules": [{
+        "manipulators": [
+          { "to": [{ "shell_command": "/opt/homebrew/bin/yabai -m window --focus west" }] },
+          { "to": [{ "shell_command": "open -a 'Kitty'" }] }
+        ]
+      }]
+    }
+  }]
+}"#;
+        let binaries = analyze(content).unwrap();
+        assert!(binaries.contains("karabiner-elements"));
+        assert!(binaries.contains("yabai"));
+        assert!(!binaries.contains("open"));
+    }
+}
@@ -3,9 +3,13 @@
 //! Each analyzer is specialized for a particular file type and knows how to
 //! extract binary/tool references from configuration files.
 
+pub mod fish;
 pub mod generic;
 pub mod git;
+pub mod hammerspoon;
+pub mod karabiner;
 pub mod shell;
+pub mod tmux;
 pub mod vim;
 
 use anyhow::Result;
@@ -52,14 +56,31 @@ pub fn analyze_file(file_path: &Path) -> Result<HashSet<String>> {
     let file_name = file_path.file_name().and_then(|n| n.to_str()).unwrap_or("");
     let extension = file_path.extension().and_then(|e| e.to_str()).unwrap_or("");
 
+    // Tool directories first: ~/.hammerspoon/init.lua is not a neovim config
+    if file_path
+        .components()
+        .any(|c| c.as_os_str() == ".hammerspoon")
+    {
+        return hammerspoon::analyze(&content);
+    }
+    if file_name == "karabiner.json" {
+        return karabiner::analyze(&content);
+    }
+
     // Determine file type and use appropriate analyzer
     let binaries = match extension {
         "zsh" | "bash" | "sh" => shell::analyze(&content)?,
+        "fish" => fish::analyze(&content)?,
+        "tmux" => tmux::analyze(&content)?,
         "vim" | "nvim" => vim::analyze(&content)?,
         _ => {
             // Try to detect file type from filename
             if is_shell_config(file_name) {
                 shell::analyze(&content)?
+            } else if matches!(file_name, ".tmux.conf" | "tmux.conf") {
+                tmux::analyze(&content)?
+            } else if file_name == "fish_plugins" {
+                fish::analyze_plugins(&content)
             } else if is_vim_config(file_name) {
                 vim::analyze(&content)?
             } else if is_git_config(file_name) {
@@ -74,6 +95,37 @@ pub fn analyze_file(file_path: &Path) -> Result<HashSet<String>> {
     Ok(binaries)
 }
 
+/// Name of the program a command line runs: its first word, without leading
+/// `VAR=value` assignments, quotes or directories. `None` for shell builtins,
+/// tools every system has and the ones macOS ships.
+pub(crate) fn command_name(command: &str) -> Option<String> {
+    let word = command
+        .split_whitespace()
+        .map(|w| w.trim_matches(|c| c == '"' || c == '\'' || c == '(' || c == ')'))
+        .find(|w| !w.is_empty() && !w.contains('=') && *w != "exec" && *w != "sudo")?;
+    // Scripts in the user's own directories aren't packages
+    if word.starts_with(['$', '~']) {
+        return None;
+    }
+    let name = word.rsplit('/').next().unwrap_or(word);
+    let valid = name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if name.is_empty()
+        || !valid
+        || name.starts_with(['-', '.'])
+        || shell::is_shell_builtin(name)
+        || shell::is_always_available(name)
+        || matches!(
+            name,
+            "open" | "osascript" | "pbcopy" | "pbpaste" | "defaults" | "launchctl" | "say"
+        )
+    {
+        return None;
+    }
+    Some(name.to_string())
+}
+
 /// Check if a filename indicates a shell configuration file
 fn is_shell_config(name: &str) -> bool {
     matches!(
@@ -106,6 +158,40 @@ fn is_git_config(name: &str) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_command_name() {
+        assert_eq!(
+            command_name("/opt/homebrew/bin/yabai -m space --focus 1").as_deref(),
+            Some("yabai")
+        );
+        assert_eq!(
+            command_name("FZF_DEFAULT_OPTS=--reverse fzf-tmux -p").as_deref(),
+            Some("fzf-tmux")
+        );
+        assert_eq!(command_name("'lazygit'").as_deref(), Some("lazygit"));
+        assert_eq!(command_name("echo hi"), None);
+        assert_eq!(command_name("$HOME/bin/x"), None);
+    }
+
+    #[test]
+    fn test_dispatch_by_tool_directory() {
+        let temp = tempfile::tempdir().unwrap();
+        let init = temp.path().join(".hammerspoon/init.lua");
+        std::fs::create_dir_all(init.parent().unwrap()).unwrap();
+        std::fs::write(
+            &init,
+            "hs.execute('/opt/homebrew/bin/yabai -m space --focus 1')\n",
+        )
+        .unwrap();
+        let binaries = analyze_file(&init).unwrap();
+        assert!(binaries.contains("hammerspoon"));
+        assert!(binaries.contains("yabai"));
+
+        let tmux = temp.path().join(".tmux.conf");
+        std::fs::write(&tmux, "set -g @plugin 'tmux-plugins/tpm'\n").unwrap();
+        assert!(analyze_file(&tmux).unwrap().contains("git"));
+    }
+
     #[test]
     fn test_is_shell_config() {
         assert!(is_shell_config(".zshrc"));
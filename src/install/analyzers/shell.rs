@@ -156,7 +156,7 @@ fn analyze_plugin_managers(content: &str, binaries: &mut HashSet<String>) {
 }
 
 /// Check if a command is a shell builtin
-fn is_shell_builtin(cmd: &str) -> bool {
+pub(super) fn is_shell_builtin(cmd: &str) -> bool {
     matches!(
         cmd,
         "echo"
@@ -236,7 +236,7 @@ fn is_shell_builtin(cmd: &str) -> bool {
 }
 
 /// Check if a command is always available on Unix systems
-fn is_always_available(cmd: &str) -> bool {
+pub(super) fn is_always_available(cmd: &str) -> bool {
     matches!(
         cmd,
         "ls" | "cat"
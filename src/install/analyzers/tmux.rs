@@ -0,0 +1,111 @@
+//! Tmux configuration analyzer for detecting binary dependencies.
+//!
+//! Analyzes tmux.conf for tpm plugins and the programs it runs through
+//! `run-shell`, `#(...)` status segments, popups, new windows and copy
+//! commands.
+
+use anyhow::Result;
+use regex::Regex;
+use std::collections::HashSet;
+
+use super::command_name;
+
+/// Analyze tmux configuration content for binary dependencies
+pub fn analyze(content: &str) -> Result<HashSet<String>> {
+    let mut binaries = HashSet::new();
+    binaries.insert("tmux".to_string());
+
+    // Pattern 1: tpm plugins. tpm clones them with git, and some call tools.
+    let plugin = Regex::new(r#"(?m)^\s*set(?:-option)?\s+-g\s+@plugin\s+['"]([^'"]+)['"]"#)?;
+    for cap in plugin.captures_iter(content) {
+        binaries.insert("git".to_string());
+        if let Some(name) = cap.get(1) {
+            binaries.extend(plugin_binaries(name.as_str()).iter().map(|b| b.to_string()));
+        }
+    }
+
+    // Pattern 2: run-shell / run, e.g. run '~/.tmux/plugins/tpm/tpm'
+    let run_shell = Regex::new(r#"(?m)\brun(?:-shell)?\s+(?:-b\s+)?['"]?([^'"\n]+)"#)?;
+    for cap in run_shell.captures_iter(content) {
+        if let Some(cmd) = cap.get(1) {
+            let cmd = cmd.as_str();
+            if cmd.contains("tpm") {
+                binaries.insert("git".to_string());
+            } else if let Some(name) = command_name(cmd) {
+                binaries.insert(name);
+            }
+        }
+    }
+
+    // Pattern 3: #(command) segments in the status line
+    let status_cmd = Regex::new(r"#\(([^)]+)\)")?;
+    for cap in status_cmd.captures_iter(content) {
+        if let Some(name) = cap.get(1).and_then(|c| command_name(c.as_str())) {
+            binaries.insert(name);
+        }
+    }
+
+    // Pattern 4: programs opened in popups, windows and panes
+    let spawned = Regex::new(
+        r#"\b(?:display-popup|popup|new-window|neww|split-window|splitw)\b[^'"\n]*['"]([^'"]+)['"]"#,
+    )?;
+    for cap in spawned.captures_iter(content) {
+        if let Some(name) = cap.get(1).and_then(|c| command_name(c.as_str())) {
+            binaries.insert(name);
+        }
+    }
+
+    // Pattern 5: clipboard programs and the default shell/command
+    let piped = Regex::new(
+        r#"\b(?:copy-command|copy-pipe(?:-and-cancel|-no-clear)?|default-shell|default-command)\s+['"]?([^'"\n]+)"#,
+    )?;
+    for cap in piped.captures_iter(content) {
+        if let Some(name) = cap.get(1).and_then(|c| command_name(c.as_str())) {
+            binaries.insert(name);
+        }
+    }
+
+    Ok(binaries)
+}
+
+/// Binaries well-known tmux plugins call
+fn plugin_binaries(plugin: &str) -> &'static [&'static str] {
+    let name = plugin.rsplit('/').next().unwrap_or(plugin);
+    match name {
+        "tmux-fzf" | "tmux-fzf-url" | "tmux-sessionx" => &["fzf"],
+        "extrakto" => &["fzf", "python3"],
+        "t-smart-tmux-session-manager" => &["fzf", "zoxide"],
+        "tmux-thumbs" => &["cargo"],
+        "tmux-fingers" => &["gawk"],
+        "tmux-yank" if cfg!(target_os = "linux") => &["xclip"],
+        _ => &[],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tpm_plugins_and_run_shell() {
+        let content = r##"
+set -g @plugin 'tmux-plugins/tpm'
+set -g @plugin 'sainnhe/tmux-fzf'
+set -g status-right '#(gitmux -cfg ~/.gitmux.conf "#{pane_current_path}") %H:%M'
+bind g display-popup -E -w 90% "lazygit"
+set -s copy-command 'wl-copy'
+run '~/.tmux/plugins/tpm/tpm'
+"##;
+        let binaries = analyze(content).unwrap();
+        for binary in ["tmux", "git", "fzf", "gitmux", "lazygit", "wl-copy"] {
+            assert!(binaries.contains(binary), "missing {}", binary);
+        }
+        assert!(!binaries.contains("tpm"));
+    }
+
+    #[test]
+    fn test_default_shell() {
+        let binaries = analyze("set -g default-shell /opt/homebrew/bin/fish\n").unwrap();
+        assert!(binaries.contains("fish"));
+    }
+}
@@ -0,0 +1,334 @@
+//! Self-contained bootstrap scripts for brand new machines.
+//!
+//! The script installs dotdipper from a release (or with cargo), writes a
+//! config pointing at the dotfiles repository, clones it into `compiled/` and
+//! runs `install` and `apply`. It is meant to be served from a URL and run
+//! with `curl -fsSL <url> | sh`.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::PathBuf;
+
+use crate::cfg::Config;
+use crate::ui;
+
+#[derive(Debug, Clone, Default)]
+pub struct EmitOpts {
+    /// Where to write the script (default: `install/bootstrap.sh`)
+    pub file: Option<PathBuf>,
+    /// URL the script will be served from, for the one-liner
+    pub url: Option<String>,
+    /// Embed the whole config instead of just the `[github]` settings
+    pub with_config: bool,
+}
+
+/// GitHub owner and repository name of the dotfiles repository
+fn repo_info(config: &Config) -> Result<(String, String)> {
+    let Some(owner) = config
+        .github
+        .username
+        .as_deref()
+        .map(str::trim)
+        .filter(|u| !u.is_empty())
+    else {
+        bail!("No GitHub username configured; set [github] username in config.toml");
+    };
+    let repo = config
+        .github
+        .repo_name
+        .clone()
+        .unwrap_or_else(|| "dotfiles".to_string());
+    Ok((owner.to_string(), repo))
+}
+
+fn custom_remote(config: &Config) -> Option<&str> {
+    config
+        .github
+        .remote_url
+        .as_deref()
+        .map(str::trim)
+        .filter(|u| !u.is_empty())
+}
+
+/// URL the script clones: `[github] remote_url`, or the GitHub repository
+/// over HTTPS, which needs no SSH key on the new machine
+fn clone_url(config: &Config) -> Result<String> {
+    if let Some(url) = custom_remote(config) {
+        return Ok(url.to_string());
+    }
+    let (owner, repo) = repo_info(config)?;
+    Ok(crate::vcs::github_url(
+        &owner,
+        &repo,
+        crate::cfg::GitProtocol::Https,
+    ))
+}
+
+/// Raw URL of `bootstrap.sh` at the root of the dotfiles repository
+pub fn default_url(config: &Config) -> Result<String> {
+    if custom_remote(config).is_some() {
+        bail!("Only GitHub repositories have a known raw URL; pass --url");
+    }
+    let (owner, repo) = repo_info(config)?;
+    Ok(format!(
+        "https://raw.githubusercontent.com/{}/{}/{}/bootstrap.sh",
+        owner,
+        repo,
+        crate::vcs::branch(config)
+    ))
+}
+
+/// Render the bootstrap script for `config`
+pub fn render(config: &Config, with_config: bool) -> Result<String> {
+    let clone_url = clone_url(config)?;
+
+    let embedded = if with_config {
+        Config {
+            include: Vec::new(),
+            ..config.clone()
+        }
+    } else {
+        Config {
+            github: config.github.clone(),
+            ..Config::default()
+        }
+    };
+    let embedded = toml::to_string_pretty(&embedded).context("Failed to serialize config")?;
+
+    Ok(format!(
+        r#"#!/bin/sh
+#
+# Dotdipper bootstrap for {clone_url}
+# Generated: {generated} by dotdipper {version}
+#
+# Usage: curl -fsSL <url> | sh
+#
+# Environment:
+#   DOTDIPPER_REPO_URL      clone URL (default: {clone_url})
+#   DOTDIPPER_BIN_DIR       where to install dotdipper (default: ~/.local/bin)
+#   DOTDIPPER_RELEASES_URL  release download mirror
+#
+
+set -eu
+
+REPO_URL="${{DOTDIPPER_REPO_URL:-{clone_url}}}"
+BRANCH="{branch}"
+VERSION="v{version}"
+RELEASES_URL="${{DOTDIPPER_RELEASES_URL:-{releases_url}}}"
+BIN_DIR="${{DOTDIPPER_BIN_DIR:-$HOME/.local/bin}}"
+DOTDIPPER_DIR="${{DOTDIPPER_HOME:-${{XDG_CONFIG_HOME:-$HOME/.config}}/dotdipper}}"
+DATA_DIR="${{DOTDIPPER_HOME:-${{XDG_DATA_HOME:-$HOME/.local/share}}/dotdipper}}"
+COMPILED_DIR="$DATA_DIR/compiled"
+
+info() {{
+    printf '\033[0;32m[INFO]\033[0m %s\n' "$1"
+}}
+
+fail() {{
+    printf '\033[0;31m[ERROR]\033[0m %s\n' "$1" >&2
+    exit 1
+}}
+
+sha256() {{
+    if command -v sha256sum >/dev/null 2>&1; then
+        sha256sum "$1" | cut -d' ' -f1
+    else
+        shasum -a 256 "$1" | cut -d' ' -f1
+    fi
+}}
+
+# Under `curl | sh` stdin is the script, so give prompts the terminal
+dotdipper_run() {{
+    if [ ! -t 0 ] && (: </dev/tty) 2>/dev/null; then
+        "$DOTDIPPER" "$@" </dev/tty
+    else
+        "$DOTDIPPER" "$@"
+    fi
+}}
+
+install_dotdipper() {{
+    if command -v dotdipper >/dev/null 2>&1; then
+        DOTDIPPER=$(command -v dotdipper)
+        info "Using $("$DOTDIPPER" --version)"
+        return
+    fi
+
+    case "$(uname -s)-$(uname -m)" in
+        Linux-x86_64) target=x86_64-unknown-linux-gnu ;;
+        Linux-aarch64 | Linux-arm64) target=aarch64-unknown-linux-gnu ;;
+        Darwin-x86_64) target=x86_64-apple-darwin ;;
+        Darwin-arm64) target=aarch64-apple-darwin ;;
+        *) target="" ;;
+    esac
+
+    if [ -n "$target" ] && command -v curl >/dev/null 2>&1; then
+        asset="dotdipper-$target.tar.gz"
+        url="$RELEASES_URL/$VERSION/$asset"
+        tmp=$(mktemp -d)
+        info "Downloading $url"
+        curl -fsSL "$url" -o "$tmp/$asset"
+        curl -fsSL "$url.sha256" -o "$tmp/$asset.sha256"
+        [ "$(cut -d' ' -f1 < "$tmp/$asset.sha256")" = "$(sha256 "$tmp/$asset")" ] ||
+            fail "Checksum mismatch for $asset"
+        tar -xzf "$tmp/$asset" -C "$tmp"
+        mkdir -p "$BIN_DIR"
+        cp "$(find "$tmp" -type f -name dotdipper | head -n 1)" "$BIN_DIR/dotdipper"
+        chmod 755 "$BIN_DIR/dotdipper"
+        rm -rf "$tmp"
+        DOTDIPPER="$BIN_DIR/dotdipper"
+    elif command -v cargo >/dev/null 2>&1; then
+        info "No prebuilt release for $(uname -sm); building with cargo"
+        cargo install --locked dotdipper
+        DOTDIPPER="${{CARGO_HOME:-$HOME/.cargo}}/bin/dotdipper"
+    else
+        fail "No prebuilt dotdipper for $(uname -sm) and cargo is not installed"
+    fi
+    info "Installed $("$DOTDIPPER" --version)"
+}}
+
+write_config() {{
+    if [ -f "$DOTDIPPER_DIR/config.toml" ]; then
+        info "Keeping existing $DOTDIPPER_DIR/config.toml"
+        return
+    fi
+    "$DOTDIPPER" init
+    cat > "$DOTDIPPER_DIR/config.toml" <<'DOTDIPPER_CONFIG'
+{config}DOTDIPPER_CONFIG
+}}
+
+clone_repo() {{
+    if [ -d "$COMPILED_DIR/.git" ]; then
+        info "Updating $COMPILED_DIR"
+        git -C "$COMPILED_DIR" pull --ff-only
+    else
+        info "Cloning $REPO_URL"
+        git clone --branch "$BRANCH" "$REPO_URL" "$COMPILED_DIR"
+    fi
+}}
+
+main() {{
+    command -v git >/dev/null 2>&1 || fail "git is required"
+    install_dotdipper
+    write_config
+    clone_repo
+    dotdipper_run install
+    dotdipper_run apply
+    info "Bootstrap complete"
+    case ":$PATH:" in
+        *":$(dirname "$DOTDIPPER"):"*) ;;
+        *) info "Add $(dirname "$DOTDIPPER") to your PATH to use dotdipper" ;;
+    esac
+}}
+
+main "$@"
+"#,
+        branch = crate::vcs::branch(config),
+        generated = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+        version = env!("CARGO_PKG_VERSION"),
+        clone_url = clone_url,
+        releases_url = crate::upgrade::releases_url(),
+        config = embedded,
+    ))
+}
+
+/// `bootstrap --emit`: write the script and print the one-liner that runs it
+pub fn emit(config: &Config, opts: &EmitOpts) -> Result<()> {
+    let url = match &opts.url {
+        Some(url) => url.clone(),
+        None => default_url(config)?,
+    };
+    let script = render(config, opts.with_config)?;
+    let path = match &opts.file {
+        Some(path) => path.clone(),
+        None => crate::paths::install_dir()?.join("bootstrap.sh"),
+    };
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, script).with_context(|| format!("Failed to write {}", path.display()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    ui::success(&format!("Wrote bootstrap script to {}", path.display()));
+    if opts.with_config {
+        ui::warn("The script embeds your config.toml; anyone who can fetch it can read it");
+    }
+
+    ui::info("On a new machine, run:");
+    println!("  curl -fsSL {} | sh", url);
+    if opts.url.is_none() {
+        let (owner, repo) = repo_info(config)?;
+        ui::hint(&format!(
+            "Commit the script as bootstrap.sh at the root of {}/{}, or pass --url",
+            owner, repo
+        ));
+        if config.github.private {
+            ui::warn("Raw URLs of private repositories need a token; host the script elsewhere and pass --url");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_embeds_repo_and_parses() {
+        let mut config = Config::default();
+        assert!(render(&config, false).is_err());
+
+        config.github.username = Some("alice".to_string());
+        config.github.repo_name = Some("dots".to_string());
+        let script = render(&config, false).unwrap();
+        assert!(script.contains("${DOTDIPPER_REPO_URL:-https://github.com/alice/dots.git}"));
+        assert!(script.contains("BRANCH=\"main\""));
+        assert!(script.contains("username = \"alice\""));
+        assert!(script.contains("dotdipper_run install\n    dotdipper_run apply"));
+
+        let status = std::process::Command::new("sh")
+            .args(["-n", "-c", &script])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        // The repo is cloned where dotdipper keeps its data, not its config
+        let dirs: String = script
+            .lines()
+            .filter(|line| line.starts_with("DATA_DIR=") || line.starts_with("COMPILED_DIR="))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let resolve = |envs: &[(&str, &str)]| {
+            let mut cmd = std::process::Command::new("sh");
+            cmd.args(["-c", &format!("{}echo \"$COMPILED_DIR\"", dirs)])
+                .env_clear()
+                .env("HOME", "/home/alice");
+            for (name, value) in envs {
+                cmd.env(name, value);
+            }
+            String::from_utf8(cmd.output().unwrap().stdout).unwrap()
+        };
+        assert_eq!(
+            resolve(&[]),
+            "/home/alice/.local/share/dotdipper/compiled\n"
+        );
+        assert_eq!(
+            resolve(&[("XDG_DATA_HOME", "/data")]),
+            "/data/dotdipper/compiled\n"
+        );
+        assert_eq!(
+            resolve(&[("DOTDIPPER_HOME", "/opt/dots")]),
+            "/opt/dots/compiled\n"
+        );
+
+        config.github.remote_url = Some("ssh://nas.local/srv/git/dots.git".to_string());
+        config.github.branch = Some("laptop".to_string());
+        let script = render(&config, false).unwrap();
+        assert!(script.contains("${DOTDIPPER_REPO_URL:-ssh://nas.local/srv/git/dots.git}"));
+        assert!(script.contains("BRANCH=\"laptop\""));
+        assert!(default_url(&config).is_err());
+    }
+}
@@ -5,6 +5,7 @@
 //! package names.
 
 use anyhow::Result;
+use regex::Regex;
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
@@ -23,6 +24,34 @@ pub enum ConfidenceLevel {
     Low,
 }
 
+impl ConfidenceLevel {
+    fn rank(self) -> u8 {
+        match self {
+            ConfidenceLevel::High => 2,
+            ConfidenceLevel::Medium => 1,
+            ConfidenceLevel::Low => 0,
+        }
+    }
+
+    /// Whether this level is `min` or higher
+    pub fn at_least(self, min: ConfidenceLevel) -> bool {
+        self.rank() >= min.rank()
+    }
+}
+
+impl std::str::FromStr for ConfidenceLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "high" => Ok(ConfidenceLevel::High),
+            "medium" => Ok(ConfidenceLevel::Medium),
+            "low" => Ok(ConfidenceLevel::Low),
+            _ => anyhow::bail!("Unknown confidence level '{}' (high, medium or low)", s),
+        }
+    }
+}
+
 impl std::fmt::Display for ConfidenceLevel {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -48,6 +77,9 @@ pub struct DiscoveryResult {
     /// Confidence level for each binary
     pub confidence: HashMap<String, ConfidenceLevel>,
 
+    /// File each binary was detected in with its confidence level
+    pub sources: HashMap<String, PathBuf>,
+
     /// Errors encountered during analysis (file path -> error message)
     pub errors: HashMap<PathBuf, String>,
 }
@@ -60,6 +92,7 @@ impl DiscoveryResult {
             unmapped_binaries: Vec::new(),
             analyzed_files: Vec::new(),
             confidence: HashMap::new(),
+            sources: HashMap::new(),
             errors: HashMap::new(),
         }
     }
@@ -155,7 +188,19 @@ pub fn discover_packages(
         // Try to analyze the file
         match analyzers::analyze_file(file_path) {
             Ok(binaries) => {
-                all_binaries.extend(binaries);
+                let content = std::fs::read_to_string(file_path).unwrap_or_default();
+                for binary in binaries {
+                    let level = classify(&binary, &content);
+                    let stronger = result
+                        .confidence
+                        .get(&binary)
+                        .is_none_or(|known| level.rank() > known.rank());
+                    if stronger {
+                        result.confidence.insert(binary.clone(), level);
+                        result.sources.insert(binary.clone(), file_path.clone());
+                    }
+                    all_binaries.insert(binary);
+                }
                 result.analyzed_files.push(file_path.clone());
             }
             Err(e) => {
@@ -165,13 +210,16 @@ pub fn discover_packages(
         }
     }
 
+    // Low confidence matches are often false positives
+    if !discovery_config.include_low_confidence {
+        all_binaries.retain(|b| result.confidence.get(b) != Some(&ConfidenceLevel::Low));
+        result.confidence.retain(|_, c| *c != ConfidenceLevel::Low);
+        let confidence = &result.confidence;
+        result.sources.retain(|b, _| confidence.contains_key(b));
+    }
+
     // Map binaries to packages
     for binary in all_binaries {
-        // Set confidence level (default to High for now, could be enhanced)
-        result
-            .confidence
-            .insert(binary.clone(), ConfidenceLevel::High);
-
         match mapper.map_binary(&binary) {
             Some(package_name) => {
                 result.packages.insert(binary.clone(), package_name);
@@ -188,6 +236,36 @@ pub fn discover_packages(
     Ok(result)
 }
 
+/// How sure we are that `content` needs `binary`: an explicit check for it
+/// (`command -v`, `which`, `executable()`) is high, using it outside a
+/// comment is medium, and anything else (file name, plugin name, comments)
+/// is low.
+fn classify(binary: &str, content: &str) -> ConfidenceLevel {
+    let name = regex::escape(binary);
+    let check = Regex::new(&format!(
+        r#"(?:command\s+-v|\bwhich|\btype|\bhash)\s+{0}\b|executable\(\s*['"]{0}['"]"#,
+        name
+    ));
+    if check.is_ok_and(|re| re.is_match(content)) {
+        return ConfidenceLevel::High;
+    }
+
+    let word = match Regex::new(&format!(r"\b{}\b", name)) {
+        Ok(re) => re,
+        Err(_) => return ConfidenceLevel::Low,
+    };
+    let used = content.lines().any(|line| {
+        let line = line.trim_start();
+        let comment = line.starts_with('#') || line.starts_with('"') || line.starts_with("--");
+        !comment && word.is_match(line)
+    });
+    if used {
+        ConfidenceLevel::Medium
+    } else {
+        ConfidenceLevel::Low
+    }
+}
+
 /// Check if a file should be skipped based on exclude patterns
 fn should_skip_file(file_path: &Path, exclude_patterns: &[String]) -> bool {
     let path_str = file_path.to_string_lossy();
@@ -363,6 +441,21 @@ mod tests {
         assert!(result.packages.contains_key("starship"));
     }
 
+    #[test]
+    fn test_classify_confidence() {
+        let content =
+            "# bat is nice\nif command -v fzf >/dev/null; then\n  alias ll='eza -l'\nfi\n";
+        assert_eq!(classify("fzf", content), ConfidenceLevel::High);
+        assert_eq!(classify("eza", content), ConfidenceLevel::Medium);
+        assert_eq!(classify("bat", content), ConfidenceLevel::Low);
+        assert!(ConfidenceLevel::High.at_least(ConfidenceLevel::Medium));
+        assert!(!ConfidenceLevel::Low.at_least(ConfidenceLevel::Medium));
+        assert_eq!(
+            "medium".parse::<ConfidenceLevel>().unwrap(),
+            ConfidenceLevel::Medium
+        );
+    }
+
     #[test]
     fn test_discovery_config_default() {
         let config = DiscoveryConfig::default();
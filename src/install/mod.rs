@@ -1,15 +1,20 @@
 pub mod analyzers;
+pub mod bootstrap;
 pub mod discover;
 pub mod package_map;
+pub mod packages;
 pub mod validators;
+mod windows;
 
 use anyhow::{Context, Result};
 use os_info::Type as OsType;
+use std::collections::HashMap;
+use std::ffi::OsString;
 use std::fs;
 use std::path::PathBuf;
 use std::process::Command;
 
-use crate::cfg::{Config, PackagesConfig};
+use crate::cfg::{Config, PackageMapEntry, PackagesConfig, SandboxConfig};
 use crate::ui;
 
 // Re-export commonly used types
@@ -31,11 +36,92 @@ pub fn detect_os() -> String {
         OsType::Ubuntu | OsType::Debian => "ubuntu".to_string(),
         OsType::Arch | OsType::Manjaro | OsType::EndeavourOS => "arch".to_string(),
         OsType::Fedora | OsType::Redhat | OsType::CentOS => "fedora".to_string(),
+        OsType::Windows => "windows".to_string(),
         _ => "linux".to_string(),
     }
 }
 
-pub fn generate_scripts(config: &Config, target_os: &str) -> Result<Vec<InstallScript>> {
+/// Whether `target_os` is Windows: `windows` (winget) or `windows-scoop`
+pub fn is_windows(target_os: &str) -> bool {
+    matches!(target_os, "windows" | "windows-scoop")
+}
+
+/// Packages of `[packages]` that apply to `target_os`, sorted
+pub fn packages_for_os(packages: &PackagesConfig, target_os: &str) -> Vec<String> {
+    let mut all_packages = packages.common.clone();
+
+    match target_os {
+        "macos" => all_packages.extend(packages.macos.clone()),
+        "ubuntu" | "debian" => {
+            all_packages.extend(packages.linux.clone());
+            all_packages.extend(packages.ubuntu.clone());
+        }
+        "arch" | "manjaro" => {
+            all_packages.extend(packages.linux.clone());
+            all_packages.extend(packages.arch.clone());
+        }
+        "windows" | "windows-scoop" => all_packages.extend(packages.windows.clone()),
+        _ => all_packages.extend(packages.linux.clone()),
+    }
+
+    // Remove duplicates
+    all_packages.sort();
+    all_packages.dedup();
+    all_packages
+}
+
+/// `[packages.map]` overrides for `target_os`: the plain entries, then the
+/// `linux` or `windows` table, then the table named after `target_os`
+pub fn package_overrides(packages: &PackagesConfig, target_os: &str) -> HashMap<String, String> {
+    let mut overrides = HashMap::new();
+    for (binary, entry) in &packages.map {
+        if let PackageMapEntry::Package(package) = entry {
+            overrides.insert(binary.clone(), package.clone());
+        }
+    }
+
+    let family = match target_os {
+        "macos" => "macos",
+        os if is_windows(os) => "windows",
+        _ => "linux",
+    };
+    let mut tables = vec![family];
+    if target_os != family {
+        tables.push(target_os);
+    }
+    for table in tables {
+        if let Some(PackageMapEntry::Os(map)) = packages.map.get(table) {
+            overrides.extend(map.iter().map(|(b, p)| (b.clone(), p.clone())));
+        }
+    }
+    overrides
+}
+
+/// Packages this host should have on `target_os`, including the ones for
+/// matching `[packages.hosts]` selectors, with `[packages.map]` applied
+pub fn host_packages(config: &Config, target_os: &str) -> Result<Vec<String>> {
+    let host = crate::hosts::HostIdentity::local(config)?;
+    let packages = crate::hosts::packages_for(&config.packages, &host);
+    let overrides = package_overrides(&packages, target_os);
+    let mut resolved: Vec<String> = packages_for_os(&packages, target_os)
+        .into_iter()
+        .map(|p| overrides.get(&p).cloned().unwrap_or(p))
+        .collect();
+    resolved.sort();
+    resolved.dedup();
+    Ok(resolved)
+}
+
+/// Generate the install scripts; the package script installs `packages`
+pub fn generate_scripts(
+    config: &Config,
+    target_os: &str,
+    packages: &[String],
+) -> Result<Vec<InstallScript>> {
+    if is_windows(target_os) {
+        return windows::generate_scripts(config, target_os, packages);
+    }
+
     let mut scripts = Vec::new();
 
     // Generate main install script
@@ -43,19 +129,31 @@ pub fn generate_scripts(config: &Config, target_os: &str) -> Result<Vec<InstallS
     scripts.push(main_script);
 
     // Generate OS-specific package install script
-    let package_script = generate_package_script(&config.packages, target_os)?;
+    let package_script = generate_package_script(packages, target_os)?;
     scripts.push(package_script);
 
+    // Generate the cargo/npm/pipx/gem tools script, if any are configured
+    let host = crate::hosts::HostIdentity::local(config)?;
+    let resolved = crate::hosts::packages_for(&config.packages, &host);
+    if let Some(tools_script) = generate_tools_script(&resolved) {
+        scripts.push(tools_script);
+    }
+
     // Generate dotfiles setup script
     let dotfiles_script = generate_dotfiles_script(config)?;
     scripts.push(dotfiles_script);
 
-    // Save scripts to disk
+    save_scripts(&mut scripts)?;
+    Ok(scripts)
+}
+
+/// Write `scripts` to the install directory and make them executable
+fn save_scripts(scripts: &mut [InstallScript]) -> Result<()> {
     let script_dir = crate::paths::install_dir()?;
 
     fs::create_dir_all(&script_dir)?;
 
-    for script in &mut scripts {
+    for script in scripts {
         script.path = script_dir.join(&script.name);
         fs::write(&script.path, &script.content)?;
 
@@ -69,7 +167,7 @@ pub fn generate_scripts(config: &Config, target_os: &str) -> Result<Vec<InstallS
         }
     }
 
-    Ok(scripts)
+    Ok(())
 }
 
 fn generate_main_script(_config: &Config, target_os: &str) -> Result<InstallScript> {
@@ -157,7 +255,22 @@ log_info "Run 'dotdipper status' to check your dotfiles"
     })
 }
 
-fn generate_package_script(packages: &PackagesConfig, target_os: &str) -> Result<InstallScript> {
+/// Command that installs `packages` with the package manager of
+/// `target_os`, or `None` if it has no known one
+pub fn install_command(target_os: &str, packages: &[String]) -> Option<Vec<String>> {
+    let base: &[&str] = match target_os {
+        "macos" => &["brew", "install"],
+        "ubuntu" | "debian" => &["sudo", "apt-get", "install", "-y"],
+        "arch" | "manjaro" => &["sudo", "pacman", "-S", "--noconfirm"],
+        "fedora" | "redhat" => &["sudo", "dnf", "install", "-y"],
+        _ => return None,
+    };
+    let mut command: Vec<String> = base.iter().map(|s| s.to_string()).collect();
+    command.extend(packages.iter().cloned());
+    Some(command)
+}
+
+fn generate_package_script(packages: &[String], target_os: &str) -> Result<InstallScript> {
     let (package_manager, install_cmd, update_cmd) = match target_os {
         "macos" => ("brew", "brew install", "brew update"),
         "ubuntu" | "debian" => ("apt", "sudo apt install -y", "sudo apt update"),
@@ -166,25 +279,6 @@ fn generate_package_script(packages: &PackagesConfig, target_os: &str) -> Result
         _ => ("apt", "sudo apt install -y", "sudo apt update"),
     };
 
-    let mut all_packages = packages.common.clone();
-
-    match target_os {
-        "macos" => all_packages.extend(packages.macos.clone()),
-        "ubuntu" | "debian" => {
-            all_packages.extend(packages.linux.clone());
-            all_packages.extend(packages.ubuntu.clone());
-        }
-        "arch" | "manjaro" => {
-            all_packages.extend(packages.linux.clone());
-            all_packages.extend(packages.arch.clone());
-        }
-        _ => all_packages.extend(packages.linux.clone()),
-    }
-
-    // Remove duplicates
-    all_packages.sort();
-    all_packages.dedup();
-
     let content = format!(
         r#"#!/usr/bin/env bash
 #
@@ -208,25 +302,60 @@ log_error() {{
     echo -e "${{RED}}[ERROR]${{NC}} $1" >&2
 }}
 
-# Check if package manager exists
-if ! command -v {} &> /dev/null; then
-    log_error "Package manager '{}' not found"
-    exit 1
-fi
-
-# Update package lists
-log_info "Updating package lists..."
-{} || true
+log_warn() {{
+    echo -e "${{YELLOW}}[WARN]${{NC}} $1"
+}}
 
 # Packages to install
 packages=(
 {}
 )
 
+if [ ${{#packages[@]}} -eq 0 ]; then
+    log_info "All packages are already installed"
+    exit 0
+fi
+
+# Crates of the packages that are Rust tools, installed with cargo when the
+# package manager is missing or fails
+rust_crate() {{
+    case "$1" in
+{}
+        *) return 1 ;;
+    esac
+}}
+
+install_rust_tool() {{
+    local crate
+    crate=$(rust_crate "$1") || return 1
+    if command -v cargo-binstall &> /dev/null; then
+        cargo binstall -y "$crate"
+    elif command -v cargo &> /dev/null; then
+        cargo install --locked "$crate"
+    else
+        return 1
+    fi
+}}
+
+# Check if package manager exists
+has_manager=true
+if ! command -v {} &> /dev/null; then
+    log_warn "Package manager '{}' not found; installing Rust tools with cargo"
+    has_manager=false
+fi
+
+# Update package lists
+if $has_manager; then
+    log_info "Updating package lists..."
+    {} || true
+fi
+
 # Install packages
 for package in "${{packages[@]}}"; do
-    if {} "$package"; then
+    if $has_manager && {} "$package"; then
         log_info "Installed $package"
+    elif install_rust_tool "$package"; then
+        log_info "Installed $package with cargo"
     else
         log_error "Failed to install $package"
     fi
@@ -236,17 +365,23 @@ log_info "Package installation complete"
 "#,
         target_os,
         package_manager,
+        packages
+            .iter()
+            .map(|p| format!("    \"{}\"", p))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        packages
+            .iter()
+            .filter_map(|p| package_map::rust_crate(p).map(|c| (p, c)))
+            .map(|(p, c)| format!("        \"{}\") echo \"{}\" ;;", p, c))
+            .collect::<Vec<_>>()
+            .join("\n"),
         package_manager
             .split_whitespace()
             .next()
             .unwrap_or(package_manager),
         package_manager,
         update_cmd,
-        all_packages
-            .iter()
-            .map(|p| format!("    \"{}\"", p))
-            .collect::<Vec<_>>()
-            .join("\n"),
         install_cmd
     );
 
@@ -257,6 +392,145 @@ log_info "Package installation complete"
     })
 }
 
+/// A language package manager: how to list what it installed and install more
+struct Ecosystem {
+    manager: &'static str,
+    list_cmd: &'static str,
+    /// Succeeds when `$tool` is in `$installed`
+    installed_check: &'static str,
+    install_cmd: &'static str,
+    /// PowerShell regex matching `$tool` in the `list_cmd` output
+    ps_installed_pattern: &'static str,
+    ps_install_cmd: &'static str,
+}
+
+const ECOSYSTEMS: [Ecosystem; 4] = [
+    Ecosystem {
+        manager: "cargo",
+        list_cmd: "cargo install --list",
+        installed_check: r#"grep -q "^$tool " <<< "$installed""#,
+        install_cmd: r#"{ command -v cargo-binstall &> /dev/null && cargo binstall -y "$tool"; } || cargo install "$tool""#,
+        ps_installed_pattern: r#""(?m)^$([regex]::Escape($tool)) ""#,
+        ps_install_cmd: r#"if (Get-Command cargo-binstall -ErrorAction SilentlyContinue) { cargo binstall -y $tool } else { cargo install $tool }"#,
+    },
+    Ecosystem {
+        manager: "npm",
+        list_cmd: "npm ls -g --depth=0 --parseable",
+        installed_check: r#"grep -q "/node_modules/$tool\$" <<< "$installed""#,
+        install_cmd: r#"npm install -g "$tool""#,
+        ps_installed_pattern: r#""(?m)[\\/]node_modules[\\/]$([regex]::Escape($tool))$""#,
+        ps_install_cmd: "npm install -g $tool",
+    },
+    Ecosystem {
+        manager: "pipx",
+        list_cmd: "pipx list --short",
+        installed_check: r#"grep -q "^$tool " <<< "$installed""#,
+        install_cmd: r#"pipx install "$tool""#,
+        ps_installed_pattern: r#""(?m)^$([regex]::Escape($tool)) ""#,
+        ps_install_cmd: "pipx install $tool",
+    },
+    Ecosystem {
+        manager: "gem",
+        list_cmd: "gem list --no-versions",
+        installed_check: r#"grep -qx "$tool" <<< "$installed""#,
+        install_cmd: r#"gem install --user-install "$tool""#,
+        ps_installed_pattern: r#""(?m)^$([regex]::Escape($tool))$""#,
+        ps_install_cmd: "gem install --user-install $tool",
+    },
+];
+
+/// The cargo, npm_global, pipx and gem lists, in `ECOSYSTEMS` order. `None`
+/// when all are empty.
+fn tool_lists(packages: &PackagesConfig) -> Option<[&Vec<String>; 4]> {
+    let lists = [
+        &packages.cargo,
+        &packages.npm_global,
+        &packages.pipx,
+        &packages.gem,
+    ];
+    (!lists.iter().all(|l| l.is_empty())).then_some(lists)
+}
+
+/// Script installing `[packages]` cargo, npm_global, pipx and gem tools that
+/// aren't installed yet. `None` when there are none.
+fn generate_tools_script(packages: &PackagesConfig) -> Option<InstallScript> {
+    let lists = tool_lists(packages)?;
+
+    let mut sections = String::new();
+    for (eco, tools) in ECOSYSTEMS.iter().zip(lists) {
+        if tools.is_empty() {
+            continue;
+        }
+        let quoted = tools
+            .iter()
+            .map(|t| format!("\"{}\"", t))
+            .collect::<Vec<_>>()
+            .join(" ");
+        sections.push_str(&format!(
+            r#"
+# {manager}
+if command -v {manager} &> /dev/null; then
+    installed=$({list_cmd} 2>/dev/null || true)
+    for tool in {quoted}; do
+        if {installed_check}; then
+            log_info "$tool already installed ({manager})"
+        elif {install_cmd}; then
+            log_info "Installed $tool ({manager})"
+        else
+            log_error "Failed to install $tool ({manager})"
+        fi
+    done
+else
+    log_warn "{manager} not found; skipping: {names}"
+fi
+"#,
+            manager = eco.manager,
+            list_cmd = eco.list_cmd,
+            quoted = quoted,
+            installed_check = eco.installed_check,
+            install_cmd = eco.install_cmd,
+            names = tools.join(" "),
+        ));
+    }
+
+    let content = format!(
+        r#"#!/usr/bin/env bash
+#
+# Language Tool Installation Script (cargo, npm, pipx, gem)
+#
+
+set -uo pipefail
+
+# Colors for output
+RED='\033[0;31m'
+GREEN='\033[0;32m'
+YELLOW='\033[1;33m'
+NC='\033[0m'
+
+log_info() {{
+    echo -e "${{GREEN}}[INFO]${{NC}} $1"
+}}
+
+log_error() {{
+    echo -e "${{RED}}[ERROR]${{NC}} $1" >&2
+}}
+
+log_warn() {{
+    echo -e "${{YELLOW}}[WARN]${{NC}} $1"
+}}
+{}
+log_info "Tool installation complete"
+"#,
+        sections
+    );
+
+    Some(InstallScript {
+        name: "install_tools.sh".to_string(),
+        content,
+        path: PathBuf::new(),
+    })
+}
+
 fn generate_dotfiles_script(config: &Config) -> Result<InstallScript> {
     let use_symlinks = config
         .dotfiles
@@ -401,12 +675,46 @@ done"#
         .to_string()
 }
 
-pub fn run_scripts(scripts: &[InstallScript]) -> Result<()> {
+/// The program and arguments that run `script`: PowerShell for `.ps1`
+/// scripts, bash otherwise
+fn interpreter(script: &InstallScript) -> (&'static str, Vec<OsString>) {
+    if script.name.ends_with(".ps1") {
+        let args = ["-NoProfile", "-ExecutionPolicy", "Bypass", "-File"];
+        ("powershell", args.iter().map(OsString::from).collect())
+    } else {
+        ("bash", Vec::new())
+    }
+}
+
+pub fn run_scripts(scripts: &[InstallScript], sandbox: Option<&SandboxConfig>) -> Result<()> {
     for script in scripts {
         ui::info(&format!("Running {}...", script.name));
 
-        let output = Command::new("bash")
-            .arg(&script.path)
+        let (program, mut args) = interpreter(script);
+        args.push(script.path.clone().into_os_string());
+
+        if sandbox.is_some() {
+            let child = crate::hooks::command(program, &args, sandbox)?
+                .spawn()
+                .with_context(|| format!("Failed to run script: {}", script.name))?;
+            let status = crate::hooks::wait(
+                child,
+                crate::hooks::timeout(sandbox),
+                &format!("Script {}", script.name),
+            )?;
+            if !status.success() {
+                anyhow::bail!(
+                    "Script {} failed with exit code: {:?}",
+                    script.name,
+                    status.code()
+                );
+            }
+            ui::success(&format!("{} completed", script.name));
+            continue;
+        }
+
+        let output = Command::new(program)
+            .args(&args)
             .output()
             .with_context(|| format!("Failed to run script: {}", script.name))?;
 
@@ -420,3 +728,75 @@ pub fn run_scripts(scripts: &[InstallScript]) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tools_script_covers_configured_ecosystems() {
+        let mut packages = PackagesConfig::default();
+        assert!(generate_tools_script(&packages).is_none());
+
+        packages.cargo = vec!["starship".to_string()];
+        packages.npm_global = vec!["typescript-language-server".to_string()];
+        let script = generate_tools_script(&packages).unwrap();
+        assert!(script.content.contains(r#"for tool in "starship"; do"#));
+        assert!(script.content.contains(r#"npm install -g "$tool""#));
+        assert!(!script.content.contains("command -v pipx"));
+
+        let status = Command::new("bash")
+            .args(["-n", "-c", &script.content])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_package_script_falls_back_to_cargo_for_rust_tools() {
+        let packages = vec!["git".to_string(), "ripgrep".to_string()];
+        let script = generate_package_script(&packages, "ubuntu").unwrap();
+        assert!(script.content.contains(r#""ripgrep") echo "ripgrep" ;;"#));
+        assert!(!script.content.contains(r#""git") echo"#));
+        assert!(script.content.contains("cargo binstall -y"));
+
+        let status = Command::new("bash")
+            .args(["-n", "-c", &script.content])
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_package_overrides_per_os() {
+        let packages: PackagesConfig = toml::from_str(
+            r#"
+common = ["docker"]
+
+[map]
+docker = "docker-ce"
+fd = "fd"
+
+[map.macos]
+docker = "docker-desktop"
+
+[map.ubuntu]
+fd = "fd-find"
+"#,
+        )
+        .unwrap();
+
+        let ubuntu = package_overrides(&packages, "ubuntu");
+        assert_eq!(ubuntu["docker"], "docker-ce");
+        assert_eq!(ubuntu["fd"], "fd-find");
+        assert!(!ubuntu.contains_key("macos"));
+
+        let macos = package_overrides(&packages, "macos");
+        assert_eq!(macos["docker"], "docker-desktop");
+        assert_eq!(macos["fd"], "fd");
+
+        let round_trip: PackagesConfig =
+            toml::from_str(&toml::to_string(&packages).unwrap()).unwrap();
+        assert_eq!(round_trip.map, packages.map);
+    }
+}
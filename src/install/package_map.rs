@@ -70,6 +70,20 @@ impl PackageMapper {
         }
     }
 
+    /// Binaries known to come from `package`, plus the package name itself
+    pub fn binaries_for(&self, package: &str) -> Vec<String> {
+        let mut binaries: Vec<String> = self
+            .mappings
+            .iter()
+            .filter(|(_, mapped)| *mapped == package)
+            .map(|(binary, _)| binary.clone())
+            .collect();
+        binaries.push(package.to_string());
+        binaries.sort();
+        binaries.dedup();
+        binaries
+    }
+
     /// Add a custom mapping (for user overrides)
     pub fn add_custom_mapping(&mut self, binary: String, package: String) {
         self.mappings.insert(binary, package);
@@ -84,6 +98,8 @@ impl PackageMapper {
             "ubuntu" | "debian" => Self::build_debian_mappings(&mut mappings),
             "arch" | "manjaro" | "endeavouros" => Self::build_arch_mappings(&mut mappings),
             "fedora" | "redhat" | "centos" => Self::build_fedora_mappings(&mut mappings),
+            "windows" => Self::build_winget_mappings(&mut mappings),
+            "windows-scoop" => Self::build_scoop_mappings(&mut mappings),
             _ => Self::build_default_mappings(&mut mappings),
         }
 
@@ -430,6 +446,99 @@ impl PackageMapper {
         }
     }
 
+    /// Build mappings for Windows (winget package IDs)
+    fn build_winget_mappings(mappings: &mut HashMap<String, String>) {
+        let winget_packages = [
+            // Modern CLI tools
+            ("fzf", "junegunn.fzf"),
+            ("ripgrep", "BurntSushi.ripgrep.MSVC"),
+            ("rg", "BurntSushi.ripgrep.MSVC"),
+            ("bat", "sharkdp.bat"),
+            ("fd", "sharkdp.fd"),
+            ("fd-find", "sharkdp.fd"),
+            ("eza", "eza-community.eza"),
+            ("lsd", "lsd-rs.lsd"),
+            ("zoxide", "ajeetdsouza.zoxide"),
+            ("starship", "Starship.Starship"),
+            ("dust", "bootandy.dust"),
+            ("bottom", "Clement.bottom"),
+            ("btm", "Clement.bottom"),
+            ("hyperfine", "sharkdp.hyperfine"),
+            ("just", "Casey.Just"),
+            // Text processing
+            ("jq", "jqlang.jq"),
+            ("yq", "MikeFarah.yq"),
+            // Editors
+            ("nvim", "Neovim.Neovim"),
+            ("neovim", "Neovim.Neovim"),
+            ("vim", "vim.vim"),
+            ("hx", "Helix.Helix"),
+            ("helix", "Helix.Helix"),
+            ("code", "Microsoft.VisualStudioCode"),
+            // Git tools
+            ("git", "Git.Git"),
+            ("gh", "GitHub.cli"),
+            ("delta", "dandavison.delta"),
+            ("lazygit", "JesseDuffield.lazygit"),
+            ("git-lfs", "GitHub.GitLFS"),
+            ("difft", "Wilfred.difftastic"),
+            // Container tools
+            ("docker", "Docker.DockerDesktop"),
+            ("kubectl", "Kubernetes.kubectl"),
+            // Languages
+            ("node", "OpenJS.NodeJS.LTS"),
+            ("npm", "OpenJS.NodeJS.LTS"),
+            ("python3", "Python.Python.3.12"),
+            ("python", "Python.Python.3.12"),
+            ("go", "GoLang.Go"),
+            ("cargo", "Rustlang.Rustup"),
+            ("rustup", "Rustlang.Rustup"),
+            // Network tools
+            ("curl", "cURL.cURL"),
+            ("wget", "JernejSimoncic.Wget"),
+            // Shells and terminals
+            ("pwsh", "Microsoft.PowerShell"),
+            ("wezterm", "wez.wezterm"),
+            ("alacritty", "Alacritty.Alacritty"),
+            // Security
+            ("gpg", "GnuPG.GnuPG"),
+            ("age", "FiloSottile.age"),
+            ("sops", "Mozilla.SOPS"),
+            // Other
+            ("direnv", "direnv.direnv"),
+            ("7z", "7zip.7zip"),
+        ];
+
+        for (binary, package) in winget_packages {
+            mappings.insert(binary.to_string(), package.to_string());
+        }
+    }
+
+    /// Build mappings for Windows (Scoop)
+    fn build_scoop_mappings(mappings: &mut HashMap<String, String>) {
+        let scoop_packages = [
+            ("rg", "ripgrep"),
+            ("fd", "fd"),
+            ("fd-find", "fd"),
+            ("btm", "bottom"),
+            ("nvim", "neovim"),
+            ("hx", "helix"),
+            ("code", "vscode"),
+            ("difft", "difftastic"),
+            ("node", "nodejs"),
+            ("npm", "nodejs"),
+            ("python3", "python"),
+            ("cargo", "rustup"),
+            ("pwsh", "pwsh"),
+            ("gpg", "gpg"),
+            ("7z", "7zip"),
+        ];
+
+        for (binary, package) in scoop_packages {
+            mappings.insert(binary.to_string(), package.to_string());
+        }
+    }
+
     /// Build default mappings (generic Linux)
     fn build_default_mappings(mappings: &mut HashMap<String, String>) {
         // Use Debian-like mappings as default
@@ -437,6 +546,63 @@ impl PackageMapper {
     }
 }
 
+/// Tools published as Rust crates, by package or binary name (including
+/// distro and winget names), with their crate. Used to install them with
+/// cargo-binstall or cargo where the package manager is missing or fails.
+const RUST_CRATES: &[(&str, &str)] = &[
+    ("ripgrep", "ripgrep"),
+    ("rg", "ripgrep"),
+    ("BurntSushi.ripgrep.MSVC", "ripgrep"),
+    ("fd", "fd-find"),
+    ("fd-find", "fd-find"),
+    ("sharkdp.fd", "fd-find"),
+    ("bat", "bat"),
+    ("sharkdp.bat", "bat"),
+    ("eza", "eza"),
+    ("eza-community.eza", "eza"),
+    ("lsd", "lsd"),
+    ("lsd-rs.lsd", "lsd"),
+    ("zoxide", "zoxide"),
+    ("ajeetdsouza.zoxide", "zoxide"),
+    ("starship", "starship"),
+    ("Starship.Starship", "starship"),
+    ("dust", "du-dust"),
+    ("du-dust", "du-dust"),
+    ("bootandy.dust", "du-dust"),
+    ("bottom", "bottom"),
+    ("btm", "bottom"),
+    ("Clement.bottom", "bottom"),
+    ("delta", "git-delta"),
+    ("git-delta", "git-delta"),
+    ("dandavison.delta", "git-delta"),
+    ("hyperfine", "hyperfine"),
+    ("sharkdp.hyperfine", "hyperfine"),
+    ("just", "just"),
+    ("Casey.Just", "just"),
+    ("difftastic", "difftastic"),
+    ("difft", "difftastic"),
+    ("Wilfred.difftastic", "difftastic"),
+    ("procs", "procs"),
+    ("sd", "sd"),
+    ("tokei", "tokei"),
+    ("tealdeer", "tealdeer"),
+    ("atuin", "atuin"),
+    ("zellij", "zellij"),
+    ("gitui", "gitui"),
+    ("xh", "xh"),
+    ("broot", "broot"),
+    ("watchexec", "watchexec-cli"),
+    ("nushell", "nu"),
+];
+
+/// The crate that provides `package`, if it is a Rust tool
+pub fn rust_crate(package: &str) -> Option<&'static str> {
+    RUST_CRATES
+        .iter()
+        .find(|(name, _)| *name == package)
+        .map(|(_, krate)| *krate)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -455,6 +621,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_windows_mappings_and_rust_crates() {
+        let winget = PackageMapper::new("windows").unwrap();
+        assert_eq!(
+            winget.map_binary("rg"),
+            Some("BurntSushi.ripgrep.MSVC".to_string())
+        );
+        let scoop = PackageMapper::new("windows-scoop").unwrap();
+        assert_eq!(scoop.map_binary("rg"), Some("ripgrep".to_string()));
+        assert_eq!(scoop.map_binary("fd"), Some("fd".to_string()));
+
+        assert_eq!(rust_crate("BurntSushi.ripgrep.MSVC"), Some("ripgrep"));
+        assert_eq!(rust_crate("fd-find"), Some("fd-find"));
+        assert_eq!(rust_crate("git"), None);
+    }
+
     #[test]
     fn test_debian_mapping() {
         let mapper = PackageMapper::new("ubuntu").unwrap();
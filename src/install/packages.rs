@@ -0,0 +1,463 @@
+//! `dotdipper packages`: discover, list, add and remove `[packages]` entries.
+//!
+//! `discover` runs the analyzers over the tracked files, shows which binaries
+//! map to which package and how confident the match is, and asks about each
+//! package before writing the accepted ones to `[packages]`.
+//!
+//! `export` and `import` convert `[packages]` to and from the package lists
+//! other tools keep: a Brewfile, an apt list or `pacman -Qqe` output.
+
+use anyhow::{bail, Result};
+use colored::Colorize;
+use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cfg::{self, PackagesConfig};
+use crate::install::discover::{self, ConfidenceLevel, DiscoveryConfig};
+use crate::ui;
+
+/// Sections of `[packages]` that hold a plain package list: system packages,
+/// then cargo, npm, pipx and gem tools
+pub const SECTIONS: [&str; 10] = [
+    "common",
+    "macos",
+    "linux",
+    "ubuntu",
+    "arch",
+    "windows",
+    "cargo",
+    "npm_global",
+    "pipx",
+    "gem",
+];
+
+/// The package list of `[packages] <section>`
+pub fn section_mut<'a>(
+    packages: &'a mut PackagesConfig,
+    section: &str,
+) -> Result<&'a mut Vec<String>> {
+    Ok(match section {
+        "common" => &mut packages.common,
+        "macos" => &mut packages.macos,
+        "linux" => &mut packages.linux,
+        "ubuntu" => &mut packages.ubuntu,
+        "arch" => &mut packages.arch,
+        "windows" => &mut packages.windows,
+        "cargo" => &mut packages.cargo,
+        "npm_global" => &mut packages.npm_global,
+        "pipx" => &mut packages.pipx,
+        "gem" => &mut packages.gem,
+        _ => bail!(
+            "Unknown package section '{}' (expected one of: {})",
+            section,
+            SECTIONS.join(", ")
+        ),
+    })
+}
+
+fn sections(packages: &PackagesConfig) -> [(&'static str, &Vec<String>); 10] {
+    [
+        ("common", &packages.common),
+        ("macos", &packages.macos),
+        ("linux", &packages.linux),
+        ("ubuntu", &packages.ubuntu),
+        ("arch", &packages.arch),
+        ("windows", &packages.windows),
+        ("cargo", &packages.cargo),
+        ("npm_global", &packages.npm_global),
+        ("pipx", &packages.pipx),
+        ("gem", &packages.gem),
+    ]
+}
+
+/// Whether `package` is listed in any section, including host selectors,
+/// directly or through one of the `[packages.map]` `overrides`
+fn is_listed(
+    packages: &PackagesConfig,
+    package: &str,
+    overrides: &HashMap<String, String>,
+) -> bool {
+    let matches = |p: &String| p == package || overrides.get(p).is_some_and(|o| o == package);
+    sections(packages)
+        .iter()
+        .any(|(_, list)| list.iter().any(matches))
+        || packages.hosts.values().any(|list| list.iter().any(matches))
+}
+
+/// Add `names` to `[packages] <section>`. Returns the ones not there yet.
+pub fn add(config_path: &Path, section: &str, names: &[String]) -> Result<Vec<String>> {
+    let mut config = cfg::load(config_path)?;
+    let list = section_mut(&mut config.packages, section)?;
+    let added: Vec<String> = names
+        .iter()
+        .filter(|name| !list.contains(name))
+        .cloned()
+        .collect();
+    if added.is_empty() {
+        return Ok(added);
+    }
+    list.extend(added.iter().cloned());
+    list.sort();
+    list.dedup();
+    cfg::save(config_path, &config)?;
+    Ok(added)
+}
+
+/// Remove `names` from `[packages] <section>`, or from every section.
+/// Returns how many entries were removed.
+pub fn remove(config_path: &Path, section: Option<&str>, names: &[String]) -> Result<usize> {
+    let mut config = cfg::load(config_path)?;
+    let targets: Vec<&str> = match section {
+        Some(section) => vec![section],
+        None => SECTIONS.to_vec(),
+    };
+    let mut removed = 0;
+    for section in targets {
+        let list = section_mut(&mut config.packages, section)?;
+        let before = list.len();
+        list.retain(|p| !names.contains(p));
+        removed += before - list.len();
+    }
+    if removed > 0 {
+        cfg::save(config_path, &config)?;
+    }
+    Ok(removed)
+}
+
+/// `packages list`: every configured package by section
+pub fn print_list(packages: &PackagesConfig) -> Result<()> {
+    let mut rows: Vec<Vec<String>> = sections(packages)
+        .iter()
+        .flat_map(|(section, list)| {
+            list.iter()
+                .map(move |p| vec![section.to_string(), p.clone()])
+        })
+        .collect();
+    for (selector, list) in &packages.hosts {
+        rows.extend(
+            list.iter()
+                .map(|p| vec![format!("hosts.{}", selector), p.clone()]),
+        );
+    }
+
+    if ui::is_json() {
+        let json: Vec<serde_json::Value> = rows
+            .iter()
+            .map(|r| serde_json::json!({ "section": r[0], "package": r[1] }))
+            .collect();
+        return ui::print_json(&json);
+    }
+    if rows.is_empty() {
+        ui::info("No packages configured");
+        ui::hint("Find the packages your dotfiles use with: dotdipper packages discover");
+        return Ok(());
+    }
+    let count = rows.len();
+    ui::print_table(&["SECTION", "PACKAGE"], rows);
+    ui::info(&format!("{} package(s)", count));
+    Ok(())
+}
+
+/// Package list formats of other tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListFormat {
+    /// Homebrew Bundle: `brew "fzf"` and `cask "kitty"` lines
+    Brewfile,
+    /// One apt package per line
+    Aptfile,
+    /// One pacman package per line, as printed by `pacman -Qqe`
+    Pacman,
+}
+
+impl FromStr for ListFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "brewfile" => Ok(ListFormat::Brewfile),
+            "aptfile" => Ok(ListFormat::Aptfile),
+            "pacman" => Ok(ListFormat::Pacman),
+            _ => bail!(
+                "Unknown package list format '{}' (brewfile, aptfile or pacman)",
+                s
+            ),
+        }
+    }
+}
+
+impl ListFormat {
+    /// Guess the format from a file name like `Brewfile`
+    pub fn from_file_name(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.starts_with("brewfile") {
+            Some(ListFormat::Brewfile)
+        } else if name.starts_with("aptfile") || name.contains("apt") {
+            Some(ListFormat::Aptfile)
+        } else if name.contains("pacman") || name.starts_with("pkglist") {
+            Some(ListFormat::Pacman)
+        } else {
+            None
+        }
+    }
+
+    /// OS whose packages the list holds
+    pub fn target_os(self) -> &'static str {
+        match self {
+            ListFormat::Brewfile => "macos",
+            ListFormat::Aptfile => "ubuntu",
+            ListFormat::Pacman => "arch",
+        }
+    }
+
+    /// `[packages]` section imported packages go to by default
+    pub fn default_section(self) -> &'static str {
+        self.target_os()
+    }
+
+    pub fn render(self, packages: &[String]) -> String {
+        let mut out = String::from("# Generated by dotdipper from [packages]\n");
+        for package in packages {
+            match self {
+                ListFormat::Brewfile => out.push_str(&format!("brew \"{}\"\n", package)),
+                ListFormat::Aptfile | ListFormat::Pacman => {
+                    out.push_str(package);
+                    out.push('\n');
+                }
+            }
+        }
+        out
+    }
+
+    /// Package names in `text`, and the lines that aren't packages dotdipper
+    /// can install (taps, Mac App Store apps, ...)
+    pub fn parse(self, text: &str) -> (Vec<String>, Vec<String>) {
+        let mut packages = Vec::new();
+        let mut skipped = Vec::new();
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let name = match self {
+                ListFormat::Brewfile => line
+                    .strip_prefix("brew ")
+                    .or_else(|| line.strip_prefix("cask "))
+                    .and_then(|rest| rest.split(',').next())
+                    .map(|name| name.trim().trim_matches(|c| c == '"' || c == '\'')),
+                ListFormat::Aptfile | ListFormat::Pacman => line.split_whitespace().next(),
+            };
+            match name {
+                Some(name) if !name.is_empty() => packages.push(name.to_string()),
+                _ => skipped.push(line.to_string()),
+            }
+        }
+        packages.dedup();
+        (packages, skipped)
+    }
+}
+
+/// `packages export`: this host's packages for the format's OS
+pub fn export(config: &cfg::Config, format: ListFormat) -> Result<String> {
+    let packages = crate::install::host_packages(config, format.target_os())?;
+    Ok(format.render(&packages))
+}
+
+/// `packages import`: add the packages listed in `file` to `[packages] <section>`
+pub fn import(
+    config_path: &Path,
+    file: &Path,
+    format: Option<ListFormat>,
+    section: Option<&str>,
+) -> Result<()> {
+    let format = match format {
+        Some(format) => format,
+        None => ListFormat::from_file_name(file).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Can't tell the format of {}; pass --format brewfile, aptfile or pacman",
+                file.display()
+            )
+        })?,
+    };
+    let text = std::fs::read_to_string(file)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", file.display(), e))?;
+    let (packages, skipped) = format.parse(&text);
+    if !skipped.is_empty() {
+        ui::warn(&format!(
+            "Skipped {} line(s) that aren't packages:",
+            skipped.len()
+        ));
+        for line in &skipped {
+            println!("  {}", line);
+        }
+    }
+
+    let section = section.unwrap_or(format.default_section());
+    let added = add(config_path, section, &packages)?;
+    ui::success(&format!(
+        "Imported {} package(s) into [packages] {} ({} already there)",
+        added.len(),
+        section,
+        packages.len() - added.len()
+    ));
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoverOpts {
+    pub target_os: String,
+    pub min_confidence: ConfidenceLevel,
+    /// Section accepted packages are written to
+    pub section: String,
+    /// Accept every package without asking
+    pub yes: bool,
+    /// Only show what was found
+    pub dry_run: bool,
+}
+
+/// `packages discover`: find packages the tracked files need, ask about each
+/// one and write the accepted ones to `[packages]`
+pub fn discover(config_path: &Path, opts: &DiscoverOpts) -> Result<()> {
+    let config = cfg::load(config_path)?;
+    if !opts.yes && !opts.dry_run && !std::io::stdin().is_terminal() {
+        bail!("Not running interactively; pass --yes to accept every package or --dry-run to only list them");
+    }
+
+    ui::info(&format!(
+        "Analyzing {} tracked file(s) for {}...",
+        config.general.tracked_files.len(),
+        opts.target_os
+    ));
+    let overrides = crate::install::package_overrides(&config.packages, &opts.target_os);
+    let discovery_config = DiscoveryConfig {
+        target_os: opts.target_os.clone(),
+        include_low_confidence: opts.min_confidence == ConfidenceLevel::Low,
+        custom_mappings: overrides.clone(),
+        exclude_patterns: config.exclude_patterns.clone(),
+    };
+    let result = discover::discover_packages(&config, &discovery_config)?;
+    for (path, error) in &result.errors {
+        ui::warn(&format!("Could not analyze {}: {}", path.display(), error));
+    }
+
+    let home = dirs::home_dir().unwrap_or_default();
+    let found: Vec<(String, String, ConfidenceLevel)> = discover::get_package_display_list(&result)
+        .into_iter()
+        .filter_map(|(binary, package, _)| {
+            let level = *result.confidence.get(&binary)?;
+            level
+                .at_least(opts.min_confidence)
+                .then_some((binary, package, level))
+        })
+        .filter(|(_, package, _)| !is_listed(&config.packages, package, &overrides))
+        .collect();
+
+    if found.is_empty() {
+        ui::success("No new packages found");
+        return Ok(());
+    }
+
+    let rows = found
+        .iter()
+        .map(|(binary, package, level)| {
+            let source = result
+                .sources
+                .get(binary)
+                .map(|p| crate::matching::to_home_relative(p, &home))
+                .map(|p| format!("~/{}", p.display()))
+                .unwrap_or_default();
+            vec![binary.clone(), package.clone(), level.to_string(), source]
+        })
+        .collect();
+    ui::section("Discovered packages:");
+    ui::print_table(&["BINARY", "PACKAGE", "CONFIDENCE", "FOUND IN"], rows);
+    if !result.unmapped_binaries.is_empty() {
+        ui::info(&format!(
+            "No package known for: {}",
+            result.unmapped_binaries.join(", ")
+        ));
+    }
+    if opts.dry_run {
+        ui::info("Dry run: configuration not changed");
+        return Ok(());
+    }
+
+    let mut accepted: Vec<String> = Vec::new();
+    for (binary, package, level) in &found {
+        if accepted.contains(package) {
+            continue;
+        }
+        let keep = opts.yes
+            || ui::prompt_confirm(
+                &format!(
+                    "Add {} (for {}, {} confidence)?",
+                    package.green(),
+                    binary,
+                    level
+                ),
+                *level != ConfidenceLevel::Low,
+            );
+        if keep {
+            accepted.push(package.clone());
+        }
+    }
+
+    let added = add(config_path, &opts.section, &accepted)?;
+    if added.is_empty() {
+        ui::info("No packages added");
+    } else {
+        ui::success(&format!(
+            "Added {} package(s) to [packages] {}: {}",
+            added.len(),
+            opts.section,
+            added.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brewfile_round_trip() {
+        let brewfile = "tap \"homebrew/cask-fonts\"\nbrew \"fzf\"\nbrew \"neovim\", args: [\"HEAD\"] # editor\ncask \"kitty\"\nmas \"Xcode\", id: 497799835\n";
+        let (packages, skipped) = ListFormat::Brewfile.parse(brewfile);
+        assert_eq!(packages, vec!["fzf", "neovim", "kitty"]);
+        assert_eq!(skipped.len(), 2);
+
+        let rendered = ListFormat::Brewfile.render(&packages);
+        assert!(rendered.contains("brew \"neovim\"\n"));
+        assert_eq!(ListFormat::Brewfile.parse(&rendered).0, packages);
+
+        let (packages, _) = ListFormat::Pacman.parse("base\ngit\n\nripgrep\n");
+        assert_eq!(packages, vec!["base", "git", "ripgrep"]);
+        assert_eq!(
+            ListFormat::from_file_name(Path::new("/home/u/Brewfile")),
+            Some(ListFormat::Brewfile)
+        );
+    }
+
+    #[test]
+    fn test_add_and_remove_packages() {
+        let temp = tempfile::tempdir().unwrap();
+        let config_path = temp.path().join("config.toml");
+        std::fs::write(&config_path, "[packages]\ncommon = [\"git\"]\n").unwrap();
+
+        let added = add(&config_path, "common", &["fzf".into(), "git".into()]).unwrap();
+        assert_eq!(added, vec!["fzf".to_string()]);
+        add(&config_path, "macos", &["fzf".into()]).unwrap();
+        assert!(add(&config_path, "haiku", &["fzf".into()]).is_err());
+
+        let config = cfg::load(&config_path).unwrap();
+        assert_eq!(config.packages.common, vec!["fzf", "git"]);
+        assert!(is_listed(&config.packages, "fzf", &HashMap::new()));
+
+        assert_eq!(remove(&config_path, None, &["fzf".into()]).unwrap(), 2);
+        let config = cfg::load(&config_path).unwrap();
+        assert_eq!(config.packages.common, vec!["git"]);
+        assert!(config.packages.macos.is_empty());
+    }
+}
@@ -5,10 +5,12 @@
 //! alternative packages.
 
 use anyhow::Result;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::process::Command;
 
+use crate::cfg::Config;
 use crate::install::discover::DiscoveryResult;
+use crate::install::package_map::PackageMapper;
 
 /// Result of package validation
 #[derive(Debug, Clone)]
@@ -74,6 +76,113 @@ pub fn validate_packages(discovery_result: &DiscoveryResult) -> Result<Validatio
     Ok(result)
 }
 
+/// Names of the packages the package manager of `target_os` reports as
+/// installed, or `None` when it can't be queried
+pub fn installed_packages(target_os: &str) -> Option<HashSet<String>> {
+    let (program, args): (&str, &[&str]) = match target_os {
+        "macos" => ("brew", &["list", "-1"]),
+        "ubuntu" | "debian" => (
+            "dpkg-query",
+            &["-W", "-f", "${db:Status-Status} ${Package}\n"],
+        ),
+        "arch" | "manjaro" | "endeavouros" => ("pacman", &["-Qq"]),
+        "fedora" | "redhat" | "centos" => ("rpm", &["-qa", "--qf", "%{NAME}\n"]),
+        _ => return None,
+    };
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    Some(parse_installed(target_os, &stdout))
+}
+
+fn parse_installed(target_os: &str, output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .filter_map(|line| match target_os {
+            // dpkg also lists removed packages whose config files remain
+            "ubuntu" | "debian" => line.strip_prefix("installed "),
+            _ => Some(line),
+        })
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+/// Check which of the configured `packages` are installed on `target_os`:
+/// through its package manager, or by a binary of the package on `PATH`.
+/// Packages are unknown when neither finds them and the package manager
+/// could not be queried. `overrides` are the `[packages.map]` entries.
+pub fn validate_configured(
+    packages: &[String],
+    target_os: &str,
+    overrides: &HashMap<String, String>,
+) -> ValidationResult {
+    let installed = installed_packages(target_os);
+    let mapper = PackageMapper::new(target_os).ok().map(|mut mapper| {
+        for (binary, package) in overrides {
+            mapper.add_custom_mapping(binary.clone(), package.clone());
+        }
+        mapper
+    });
+    validate_with(packages, installed.as_ref(), |package| {
+        let binaries = match &mapper {
+            Some(mapper) => mapper.binaries_for(package),
+            None => vec![package.to_string()],
+        };
+        binaries
+            .iter()
+            .any(|b| is_binary_installed(b).unwrap_or(false))
+    })
+}
+
+fn validate_with(
+    packages: &[String],
+    installed: Option<&HashSet<String>>,
+    on_path: impl Fn(&str) -> bool,
+) -> ValidationResult {
+    let mut result = ValidationResult::new();
+    for package in packages {
+        let set = if installed.is_some_and(|i| i.contains(package)) || on_path(package) {
+            &mut result.installed
+        } else if installed.is_some() {
+            &mut result.missing
+        } else {
+            &mut result.unknown
+        };
+        set.insert(package.clone());
+    }
+    result
+}
+
+/// Configured packages for this host that its package manager reports
+/// missing, sorted
+pub fn missing_packages(config: &Config) -> Result<Vec<String>> {
+    let os = crate::install::detect_os();
+    let packages = crate::install::host_packages(config, &os)?;
+    let overrides = crate::install::package_overrides(&config.packages, &os);
+    let mut missing: Vec<String> = validate_configured(&packages, &os, &overrides)
+        .missing
+        .into_iter()
+        .collect();
+    missing.sort();
+    Ok(missing)
+}
+
+/// `doctor` check: every configured package for this host is installed
+pub fn check_installed(config: &Config) -> Result<()> {
+    let missing = missing_packages(config)?;
+    if missing.is_empty() {
+        return Ok(());
+    }
+    anyhow::bail!(
+        "{} missing: {} (run 'dotdipper install')",
+        missing.len(),
+        missing.join(", ")
+    )
+}
+
 /// Check if a binary is installed on the system
 pub fn is_binary_installed(binary: &str) -> Result<bool> {
     // Use the 'which' crate functionality or command
@@ -231,6 +340,27 @@ mod tests {
         assert!(no_alts.is_empty());
     }
 
+    #[test]
+    fn test_validate_configured_packages() {
+        let packages: Vec<String> = ["git", "ripgrep", "fzf", "bat"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let installed = parse_installed(
+            "ubuntu",
+            "installed git\nconfig-files fzf\ninstalled ripgrep\n",
+        );
+        let on_path = |p: &str| p == "bat";
+
+        let result = validate_with(&packages, Some(&installed), on_path);
+        assert_eq!(result.installed.len(), 3);
+        assert!(result.missing.contains("fzf"));
+
+        let result = validate_with(&packages, None, on_path);
+        assert!(result.installed.contains("bat"));
+        assert_eq!(result.unknown.len(), 3);
+    }
+
     #[test]
     fn test_is_binary_installed() {
         // 'ls' should be installed on any Unix system
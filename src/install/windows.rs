@@ -0,0 +1,257 @@
+//! PowerShell install scripts for Windows.
+//!
+//! `windows` installs packages with winget and `windows-scoop` with Scoop.
+//! Rust tools fall back to cargo-binstall or cargo when the package manager
+//! is missing or fails, like the bash scripts do.
+
+use anyhow::Result;
+use std::path::PathBuf;
+
+use super::{package_map, tool_lists, InstallScript, ECOSYSTEMS};
+use crate::cfg::{Config, PackagesConfig};
+
+const HELPERS: &str = r#"function Write-Info($msg) { Write-Host "[INFO] $msg" -ForegroundColor Green }
+function Write-Warn($msg) { Write-Host "[WARN] $msg" -ForegroundColor Yellow }
+function Write-Err($msg) { Write-Host "[ERROR] $msg" -ForegroundColor Red }
+"#;
+
+/// Generate and save the Windows scripts; the package script installs `packages`
+pub(super) fn generate_scripts(
+    config: &Config,
+    target_os: &str,
+    packages: &[String],
+) -> Result<Vec<InstallScript>> {
+    let mut scripts = vec![main_script(target_os), package_script(packages, target_os)];
+
+    let host = crate::hosts::HostIdentity::local(config)?;
+    let resolved = crate::hosts::packages_for(&config.packages, &host);
+    if let Some(tools_script) = tools_script(&resolved) {
+        scripts.push(tools_script);
+    }
+
+    super::save_scripts(&mut scripts)?;
+    Ok(scripts)
+}
+
+fn main_script(target_os: &str) -> InstallScript {
+    let content = format!(
+        r#"#
+# Dotdipper Installation Script
+# Generated: {generated}
+# Target OS: {target_os}
+#
+
+$ErrorActionPreference = 'Stop'
+
+{helpers}
+Write-Info "Starting Dotdipper installation for {target_os}"
+
+if (-not (Get-Command git -ErrorAction SilentlyContinue)) {{
+    Write-Err "Git is not installed. Please install git first."
+    exit 1
+}}
+
+# Run the package installation next to this script
+Write-Info "Installing packages..."
+$packageScript = Join-Path $PSScriptRoot "install_{target_os}.ps1"
+if (Test-Path $packageScript) {{
+    & $packageScript
+}} else {{
+    Write-Warn "Package installation script not found"
+}}
+
+Write-Info "Installation complete!"
+Write-Info "Run 'dotdipper apply' to set up your dotfiles"
+"#,
+        generated = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S"),
+        target_os = target_os,
+        helpers = HELPERS,
+    );
+
+    InstallScript {
+        name: "install.ps1".to_string(),
+        content,
+        path: PathBuf::new(),
+    }
+}
+
+fn package_script(packages: &[String], target_os: &str) -> InstallScript {
+    let (manager, install_cmd, update_cmd) = match target_os {
+        "windows-scoop" => ("scoop", "scoop install $package", "scoop update"),
+        _ => (
+            "winget",
+            "winget install --exact --id $package --silent --accept-package-agreements --accept-source-agreements",
+            "winget source update",
+        ),
+    };
+
+    let content = format!(
+        r#"#
+# Package Installation Script for {target_os}
+# Package Manager: {manager}
+#
+
+{helpers}
+# Packages to install
+$packages = @(
+{packages}
+)
+
+if ($packages.Count -eq 0) {{
+    Write-Info "All packages are already installed"
+    exit 0
+}}
+
+# Crates of the packages that are Rust tools, installed with cargo when the
+# package manager is missing or fails
+$rustCrates = @{{
+{crates}
+}}
+
+function Install-RustTool($package) {{
+    $crate = $rustCrates[$package]
+    if (-not $crate) {{ return $false }}
+    if (Get-Command cargo-binstall -ErrorAction SilentlyContinue) {{
+        cargo binstall -y $crate | Out-Host
+    }} elseif (Get-Command cargo -ErrorAction SilentlyContinue) {{
+        cargo install --locked $crate | Out-Host
+    }} else {{
+        return $false
+    }}
+    return $LASTEXITCODE -eq 0
+}}
+
+$hasManager = [bool](Get-Command {manager} -ErrorAction SilentlyContinue)
+if ($hasManager) {{
+    Write-Info "Updating package lists..."
+    {update_cmd} | Out-Host
+}} else {{
+    Write-Warn "Package manager '{manager}' not found; installing Rust tools with cargo"
+}}
+
+foreach ($package in $packages) {{
+    $installed = $false
+    if ($hasManager) {{
+        {install_cmd} | Out-Host
+        $installed = $LASTEXITCODE -eq 0
+    }}
+    if ($installed) {{
+        Write-Info "Installed $package"
+    }} elseif (Install-RustTool $package) {{
+        Write-Info "Installed $package with cargo"
+    }} else {{
+        Write-Err "Failed to install $package"
+    }}
+}}
+
+Write-Info "Package installation complete"
+"#,
+        target_os = target_os,
+        manager = manager,
+        helpers = HELPERS,
+        packages = packages
+            .iter()
+            .map(|p| format!("    \"{}\"", p))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        crates = packages
+            .iter()
+            .filter_map(|p| package_map::rust_crate(p).map(|c| (p, c)))
+            .map(|(p, c)| format!("    \"{}\" = \"{}\"", p, c))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        update_cmd = update_cmd,
+        install_cmd = install_cmd,
+    );
+
+    InstallScript {
+        name: format!("install_{}.ps1", target_os),
+        content,
+        path: PathBuf::new(),
+    }
+}
+
+/// PowerShell version of the cargo/npm/pipx/gem tools script
+fn tools_script(packages: &PackagesConfig) -> Option<InstallScript> {
+    let lists = tool_lists(packages)?;
+
+    let mut sections = String::new();
+    for (eco, tools) in ECOSYSTEMS.iter().zip(lists) {
+        if tools.is_empty() {
+            continue;
+        }
+        let quoted = tools
+            .iter()
+            .map(|t| format!("\"{}\"", t))
+            .collect::<Vec<_>>()
+            .join(", ");
+        sections.push_str(&format!(
+            r#"
+# {manager}
+if (Get-Command {manager} -ErrorAction SilentlyContinue) {{
+    $installed = ({list_cmd} 2>$null) -join "`n"
+    foreach ($tool in @({quoted})) {{
+        if ($installed -match {pattern}) {{
+            Write-Info "$tool already installed ({manager})"
+            continue
+        }}
+        {install_cmd} | Out-Host
+        if ($LASTEXITCODE -eq 0) {{
+            Write-Info "Installed $tool ({manager})"
+        }} else {{
+            Write-Err "Failed to install $tool ({manager})"
+        }}
+    }}
+}} else {{
+    Write-Warn "{manager} not found; skipping: {names}"
+}}
+"#,
+            manager = eco.manager,
+            list_cmd = eco.list_cmd,
+            quoted = quoted,
+            pattern = eco.ps_installed_pattern,
+            install_cmd = eco.ps_install_cmd,
+            names = tools.join(" "),
+        ));
+    }
+
+    let content = format!(
+        r#"#
+# Language Tool Installation Script (cargo, npm, pipx, gem)
+#
+
+{}{}
+Write-Info "Tool installation complete"
+"#,
+        HELPERS, sections
+    );
+
+    Some(InstallScript {
+        name: "install_tools.ps1".to_string(),
+        content,
+        path: PathBuf::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_script_per_manager() {
+        let packages = vec!["BurntSushi.ripgrep.MSVC".to_string(), "Git.Git".to_string()];
+        let winget = package_script(&packages, "windows");
+        assert_eq!(winget.name, "install_windows.ps1");
+        assert!(winget
+            .content
+            .contains("winget install --exact --id $package"));
+        assert!(winget
+            .content
+            .contains(r#""BurntSushi.ripgrep.MSVC" = "ripgrep""#));
+        assert!(!winget.content.contains(r#""Git.Git" ="#));
+
+        let scoop = package_script(&["ripgrep".to_string()], "windows-scoop");
+        assert!(scoop.content.contains("scoop install $package"));
+        assert!(scoop.content.contains("cargo binstall -y $crate"));
+    }
+}
@@ -0,0 +1,510 @@
+//! Garbage collection and integrity checks for the compiled directory.
+//!
+//! Copies of files that were renamed or untracked stay in the compiled
+//! directory until something removes them, as do files dropped in by hand.
+//! `dotdipper gc` moves every file and link there that no manifest entry or
+//! tracked file accounts for to the trash, along with the directories that
+//! leaves empty. Files tracked but not snapshotted yet, such as new files in
+//! a directory linked whole, stay.
+//!
+//! `dotdipper verify` hashes the compiled files against the manifest, to
+//! catch corruption and manual edits, and checks the recorded links. Each
+//! problem comes with a repair when a good copy is at hand: the file in
+//! `$HOME` if it still has the recorded contents, else the newest snapshot
+//! that does. `verify --repair` makes them, moving what it replaces to the
+//! trash. Encrypted secrets, files with sealed inline secrets and templates
+//! are stored differently from what was hashed, so only their presence is
+//! checked.
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+use crate::hash::{FileHash, Manifest};
+use crate::ui;
+use crate::usage::format_size;
+
+/// Files and links in the compiled directory nothing accounts for
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Garbage {
+    /// Relative to the compiled directory, sorted
+    pub paths: Vec<PathBuf>,
+    pub bytes: u64,
+}
+
+/// What is in `compiled` that `manifest` does not list and that is not at
+/// one of the `tracked` manifest paths either
+pub fn find_garbage(compiled: &Path, manifest: &Manifest, tracked: &HashSet<PathBuf>) -> Garbage {
+    let paths: Vec<PathBuf> = crate::repo::unlisted(compiled, manifest)
+        .into_iter()
+        .filter(|rel_path| !tracked.contains(rel_path))
+        .collect();
+    let bytes = paths
+        .iter()
+        .filter_map(|rel_path| fs::symlink_metadata(compiled.join(rel_path)).ok())
+        .map(|metadata| metadata.len())
+        .sum();
+    Garbage { paths, bytes }
+}
+
+/// Move `garbage` to the trash and remove the directories above it that
+/// are left empty, unless the manifest records them
+pub fn collect(compiled: &Path, manifest: &Manifest, garbage: &Garbage) -> Result<()> {
+    for rel_path in &garbage.paths {
+        let stored = compiled.join(rel_path);
+        if fs::symlink_metadata(&stored).is_err() {
+            continue;
+        }
+        crate::trash::put(&stored, "Removed by gc")?;
+        for dir in rel_path.ancestors().skip(1) {
+            if dir.as_os_str().is_empty() || manifest.empty_dirs.contains_key(dir) {
+                break;
+            }
+            let is_empty = fs::read_dir(compiled.join(dir)).is_ok_and(|mut d| d.next().is_none());
+            if !is_empty || fs::remove_dir(compiled.join(dir)).is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What is wrong with the compiled copy of a manifest entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Problem {
+    Missing,
+    Modified,
+    LinkChanged,
+}
+
+impl Problem {
+    fn describe(self) -> &'static str {
+        match self {
+            Problem::Missing => "missing",
+            Problem::Modified => "differs from the manifest",
+            Problem::LinkChanged => "link target changed",
+        }
+    }
+}
+
+/// Where a good copy comes from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Repair {
+    /// The file in `$HOME`, which still has the recorded contents
+    FromHome(PathBuf),
+    /// The snapshot with this id
+    FromSnapshot(String),
+    /// The target the manifest records
+    Relink,
+}
+
+impl Repair {
+    fn describe(&self, home: &Path) -> String {
+        match self {
+            Repair::FromHome(path) => {
+                format!(
+                    "copy from {}",
+                    crate::matching::display_tilde(&crate::system::manifest_path(path, home))
+                )
+            }
+            Repair::FromSnapshot(id) => format!("restore from snapshot {}", id),
+            Repair::Relink => "recreate link".to_string(),
+        }
+    }
+}
+
+/// One manifest entry whose compiled copy is not what was recorded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub rel_path: PathBuf,
+    pub problem: Problem,
+    pub repair: Option<Repair>,
+}
+
+/// What `verify` found
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    /// Files whose contents were hashed and matched
+    pub intact: usize,
+    /// Files only checked to be present
+    pub unchecked: usize,
+    pub findings: Vec<Finding>,
+}
+
+/// True if the stored copy at `stored` should hash to what the manifest
+/// recorded for `rel_path`
+fn comparable(config: &Config, rel_path: &Path, stored: &Path) -> bool {
+    !crate::template::is_template(config, rel_path)
+        && !crate::secrets::is_encrypted(stored)
+        && !fs::read_to_string(stored).is_ok_and(|text| crate::secrets::inline::has_markers(&text))
+}
+
+/// A good copy of `entry`, the file at `rel_path`, from `home` or one of
+/// `snapshots` (ids and directories, newest first)
+fn find_repair(
+    config: &Config,
+    rel_path: &Path,
+    entry: &FileHash,
+    home: &Path,
+    snapshots: &[(String, PathBuf)],
+) -> Option<Repair> {
+    let matches = |path: &Path| {
+        path.is_file()
+            && !path.is_symlink()
+            && crate::hash::hash_file(path).is_ok_and(|h| h.hash == entry.hash)
+    };
+    let target = crate::system::target(home, rel_path);
+    // A template is rendered in $HOME, not stored as it is there
+    if !crate::template::is_template(config, rel_path) && matches(&target) {
+        return Some(Repair::FromHome(target));
+    }
+    snapshots
+        .iter()
+        .find(|(_, dir)| {
+            let copy = dir.join(rel_path);
+            comparable(config, rel_path, &copy) && matches(&copy)
+        })
+        .map(|(id, _)| Repair::FromSnapshot(id.clone()))
+}
+
+/// Check the files and links in `compiled` against `manifest`
+pub fn verify(
+    config: &Config,
+    compiled: &Path,
+    manifest: &Manifest,
+    home: &Path,
+    snapshots: &[(String, PathBuf)],
+) -> Result<Report> {
+    let mut report = Report::default();
+    let mut entries: Vec<(&PathBuf, &FileHash)> = manifest.files.iter().collect();
+    entries.sort_by(|a, b| a.0.cmp(b.0));
+
+    for (rel_path, entry) in entries {
+        let stored = compiled.join(rel_path);
+        let problem = if fs::symlink_metadata(&stored).is_err() {
+            Problem::Missing
+        } else if !stored.is_file() || stored.is_symlink() {
+            Problem::Modified
+        } else if !comparable(config, rel_path, &stored) {
+            report.unchecked += 1;
+            continue;
+        } else if crate::hash::hash_file(&stored)?.hash != entry.hash {
+            Problem::Modified
+        } else {
+            report.intact += 1;
+            continue;
+        };
+        report.findings.push(Finding {
+            rel_path: rel_path.clone(),
+            problem,
+            repair: find_repair(config, rel_path, entry, home, snapshots),
+        });
+    }
+
+    for (rel_path, link) in &manifest.links {
+        let problem = match fs::read_link(compiled.join(rel_path)) {
+            Ok(target) if target == link.target => continue,
+            Ok(_) => Problem::LinkChanged,
+            Err(_) if fs::symlink_metadata(compiled.join(rel_path)).is_ok() => Problem::LinkChanged,
+            Err(_) => Problem::Missing,
+        };
+        report.findings.push(Finding {
+            rel_path: rel_path.clone(),
+            problem,
+            repair: Some(Repair::Relink),
+        });
+    }
+    Ok(report)
+}
+
+/// Make the repair of `finding`, moving what it replaces to the trash
+pub fn repair(
+    config: &Config,
+    compiled: &Path,
+    manifest: &Manifest,
+    finding: &Finding,
+    snapshots: &[(String, PathBuf)],
+) -> Result<()> {
+    let Some(repair) = &finding.repair else {
+        return Ok(());
+    };
+    let stored = compiled.join(&finding.rel_path);
+    if fs::symlink_metadata(&stored).is_ok() {
+        crate::trash::put(&stored, "Replaced by verify --repair")?;
+    }
+    if let Some(parent) = stored.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    match repair {
+        Repair::FromHome(source) => crate::repo::store_file(config, source, &stored, false),
+        Repair::FromSnapshot(id) => {
+            let dir = snapshots
+                .iter()
+                .find(|(snapshot, _)| snapshot == id)
+                .map(|(_, dir)| dir)
+                .with_context(|| format!("Snapshot not found: {}", id))?;
+            crate::repo::copy_file_with_permissions(&dir.join(&finding.rel_path), &stored)
+        }
+        Repair::Relink => {
+            let link = manifest
+                .links
+                .get(&finding.rel_path)
+                .context("Link is not in the manifest")?;
+            std::os::unix::fs::symlink(&link.target, &stored)
+                .with_context(|| format!("Failed to create link {}", stored.display()))
+        }
+    }
+}
+
+/// Ids and directories of the snapshots stored locally, newest first
+fn local_snapshots(config: &Config) -> Result<Vec<(String, PathBuf)>> {
+    Ok(crate::snapshots::load_all(config)?
+        .into_iter()
+        .filter_map(|s| {
+            let dir = crate::snapshots::store::find(config, &s.id).ok()?;
+            Some((s.id, dir))
+        })
+        .collect())
+}
+
+/// The compiled directory and its manifest, or `None` before the first
+/// snapshot
+fn load_compiled() -> Result<Option<(PathBuf, Manifest)>> {
+    let compiled = crate::paths::compiled_dir()?;
+    let manifest_path = crate::paths::manifest_file()?;
+    if !compiled.is_dir() || !manifest_path.exists() {
+        return Ok(None);
+    }
+    Ok(Some((compiled, Manifest::load(&manifest_path)?)))
+}
+
+/// `dotdipper gc`
+pub fn run_gc(config: &Config, dry_run: bool) -> Result<Garbage> {
+    let Some((compiled, manifest)) = load_compiled()? else {
+        ui::info("Nothing to collect: no snapshot has been taken yet");
+        return Ok(Garbage::default());
+    };
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let garbage = find_garbage(
+        &compiled,
+        &manifest,
+        &crate::repo::tracked_paths(config, &home)?,
+    );
+    if !dry_run {
+        collect(&compiled, &manifest, &garbage)?;
+    }
+
+    if ui::is_json() {
+        ui::print_json(&serde_json::json!({
+            "dry_run": dry_run,
+            "paths": garbage.paths,
+            "bytes": garbage.bytes,
+        }))?;
+        return Ok(garbage);
+    }
+    if garbage.paths.is_empty() {
+        ui::success("Nothing to collect: every compiled file is in the manifest");
+        return Ok(garbage);
+    }
+    for rel_path in &garbage.paths {
+        println!("  {}", rel_path.display());
+    }
+    let summary = format!(
+        "{} file(s), {}",
+        garbage.paths.len(),
+        format_size(garbage.bytes)
+    );
+    if dry_run {
+        ui::info(&format!("Would move {} to the trash", summary));
+    } else {
+        ui::success(&format!("Moved {} to the trash", summary));
+        ui::hint("Put them back with 'dotdipper trash restore <batch|file>'");
+    }
+    Ok(garbage)
+}
+
+/// `dotdipper verify`. Fails while problems remain.
+pub fn run_verify(config: &Config, repair_all: bool) -> Result<()> {
+    let Some((compiled, manifest)) = load_compiled()? else {
+        ui::info("Nothing to verify: no snapshot has been taken yet");
+        return Ok(());
+    };
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let snapshots = local_snapshots(config)?;
+    let report = verify(config, &compiled, &manifest, &home, &snapshots)?;
+
+    let mut repaired = Vec::new();
+    if repair_all {
+        for finding in report.findings.iter().filter(|f| f.repair.is_some()) {
+            repair(config, &compiled, &manifest, finding, &snapshots)
+                .with_context(|| format!("Failed to repair {}", finding.rel_path.display()))?;
+            repaired.push(&finding.rel_path);
+        }
+    }
+    let remaining = report.findings.len() - repaired.len();
+    let orphans = crate::repo::unlisted(&compiled, &manifest).len();
+
+    if ui::is_json() {
+        let findings: Vec<serde_json::Value> = report
+            .findings
+            .iter()
+            .map(|f| {
+                serde_json::json!({
+                    "path": f.rel_path,
+                    "problem": f.problem,
+                    "repair": f.repair.as_ref().map(|r| r.describe(&home)),
+                    "repaired": repaired.contains(&&f.rel_path),
+                })
+            })
+            .collect();
+        ui::print_json(&serde_json::json!({
+            "intact": report.intact,
+            "unchecked": report.unchecked,
+            "findings": findings,
+            "unlisted": orphans,
+        }))?;
+    } else if report.findings.is_empty() {
+        ui::success(&format!(
+            "{} compiled file(s) match the manifest",
+            report.intact + report.unchecked
+        ));
+    } else {
+        let rows = report
+            .findings
+            .iter()
+            .map(|f| {
+                let repair = match (&f.repair, repaired.contains(&&f.rel_path)) {
+                    (Some(repair), true) => format!("repaired: {}", repair.describe(&home)),
+                    (Some(repair), false) => repair.describe(&home),
+                    (None, _) => "-".to_string(),
+                };
+                vec![
+                    f.rel_path.display().to_string(),
+                    f.problem.describe().to_string(),
+                    repair,
+                ]
+            })
+            .collect();
+        ui::print_table(&["File", "Problem", "Repair"], rows);
+        if !repair_all && report.findings.iter().any(|f| f.repair.is_some()) {
+            ui::hint("Make the repairs with: dotdipper verify --repair");
+        }
+        if report.findings.iter().any(|f| f.repair.is_none()) {
+            ui::hint(
+                "Files without a repair have no good copy left; \
+                 record what $HOME has with: dotdipper snapshot create --force",
+            );
+        }
+    }
+    if orphans > 0 && !ui::is_json() {
+        ui::hint(&format!(
+            "{} file(s) in compiled/ are not in the manifest; remove them with: dotdipper gc",
+            orphans
+        ));
+    }
+
+    if remaining > 0 {
+        bail!("{} compiled file(s) don't match the manifest", remaining);
+    }
+    if !repaired.is_empty() && !ui::is_json() {
+        ui::success(&format!("Repaired {} file(s)", repaired.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::LinkEntry;
+
+    #[test]
+    fn test_gc_keeps_tracked_files_and_verify_finds_a_good_copy() {
+        let temp = tempfile::tempdir().unwrap();
+        let home = temp.path().join("home");
+        let compiled = temp.path().join("compiled");
+        let snapshot = temp.path().join("snapshot");
+        for dir in [&home, &compiled, &snapshot] {
+            fs::create_dir_all(dir.join(".config/old")).unwrap();
+        }
+        let mut manifest = Manifest::new();
+        for (path, contents) in [(".zshrc", "zsh"), (".vimrc", "vim"), (".gitconfig", "git")] {
+            for dir in [&home, &compiled, &snapshot] {
+                fs::write(dir.join(path), contents).unwrap();
+            }
+            let mut entry = crate::hash::hash_file(&compiled.join(path)).unwrap();
+            entry.path = PathBuf::from(path);
+            manifest.add_file(entry);
+        }
+        manifest.links.insert(
+            PathBuf::from(".config/nvim"),
+            LinkEntry {
+                target: PathBuf::from("../.zshrc"),
+            },
+        );
+        std::os::unix::fs::symlink("../.zshrc", compiled.join(".config/nvim")).unwrap();
+
+        // Left behind by a rename, and tracked but not snapshotted yet
+        fs::write(compiled.join(".config/old/init.lua"), "lua").unwrap();
+        fs::write(compiled.join(".bashrc"), "bash").unwrap();
+        fs::create_dir_all(compiled.join(".git")).unwrap();
+        fs::write(compiled.join(".git/HEAD"), "ref").unwrap();
+        let workspace = compiled.join(crate::workspace::WORKSPACES_DIR).join("rust");
+        fs::create_dir_all(&workspace).unwrap();
+        fs::write(workspace.join(".editorconfig"), "root = true").unwrap();
+        let tracked = HashSet::from([PathBuf::from(".bashrc")]);
+        let garbage = find_garbage(&compiled, &manifest, &tracked);
+        assert_eq!(garbage.paths, vec![PathBuf::from(".config/old/init.lua")]);
+        assert_eq!(garbage.bytes, 3);
+
+        // Corrupted, edited by hand with $HOME changed since, and gone
+        fs::write(compiled.join(".zshrc"), "zsh, corrupted").unwrap();
+        fs::write(compiled.join(".vimrc"), "vim, edited").unwrap();
+        fs::write(home.join(".vimrc"), "vim, newer").unwrap();
+        fs::remove_file(compiled.join(".gitconfig")).unwrap();
+        fs::remove_file(snapshot.join(".gitconfig")).unwrap();
+        fs::write(home.join(".gitconfig"), "git, newer").unwrap();
+        let config = Config::default();
+        let snapshots = vec![("20261016_120000".to_string(), snapshot.clone())];
+        let report = verify(&config, &compiled, &manifest, &home, &snapshots).unwrap();
+        assert_eq!(report.intact, 0);
+        let found: Vec<_> = report
+            .findings
+            .iter()
+            .map(|f| (f.rel_path.to_str().unwrap(), f.problem, f.repair.clone()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (".gitconfig", Problem::Missing, None),
+                (
+                    ".vimrc",
+                    Problem::Modified,
+                    Some(Repair::FromSnapshot("20261016_120000".to_string()))
+                ),
+                (
+                    ".zshrc",
+                    Problem::Modified,
+                    Some(Repair::FromHome(home.join(".zshrc")))
+                ),
+            ]
+        );
+
+        // A link pointed elsewhere can always be recreated
+        fs::remove_file(compiled.join(".config/nvim")).unwrap();
+        std::os::unix::fs::symlink(".", compiled.join(".config/nvim")).unwrap();
+        fs::write(compiled.join(".zshrc"), "zsh").unwrap();
+        let report = verify(&config, &compiled, &manifest, &home, &snapshots).unwrap();
+        assert_eq!(report.intact, 1);
+        assert_eq!(
+            report
+                .findings
+                .last()
+                .map(|f| (f.problem, f.repair.clone())),
+            Some((Problem::LinkChanged, Some(Repair::Relink)))
+        );
+    }
+}
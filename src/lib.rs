@@ -2,23 +2,86 @@
 //!
 //! This library provides the core functionality for dotdipper, including:
 //! - Configuration management
+//! - Multiple dotfile collections with their own remotes
 //! - Dotfile discovery and scanning
+//! - A knowledge base of volatile, host-specific files
+//! - Size and binary-file limits for tracked files
+//! - Shared gitignore-style path matching
+//! - Network and removable mount detection
+//! - Extended attributes, ACLs and immutable flags
+//! - Permissions and ownership enforced on apply
+//! - macOS LaunchAgents and login items
+//! - Relative symlink creation and migration
+//! - Symlink-aware checks that keep reads and writes inside their roots
+//! - A registry of the backups apply makes, with pruning and restore
+//! - A trash for the files apply and rollback replace
+//! - Tracked files outside $HOME, applied through a sudo script when needed
+//! - Hook execution with snapshot context
+//! - An append-only history of operations
+//! - Host groups and host targeting
+//! - A registry of the machines sharing the dotfiles and how current they are
 //! - Package discovery from dotfiles
 //! - Installation script generation
+//! - Workspace templates stamped into project directories
 //! - Version control integration
+//! - Two-way sync with conflict detection
+//! - Network retries and the offline push queue
+//! - Per-machine pins of files to older snapshots
+//! - Archiving files that are kept in the repo but no longer applied
+//! - Tracking and untracking individual files
+//! - Importing dotfiles from chezmoi, GNU stow and bare git repositories
 //! - Secrets management
+//! - Templates rendered with per-machine variables
+//! - Self-upgrade and state migrations
+//! - Disk usage accounting for dotdipper's own state
+//! - Garbage collection and integrity checks for the compiled directory
+//! - A sandboxed demo environment with fake dotfiles
+//! - Redacted bug report bundles
+//! - Health checks and repairs for `doctor`
 
+pub mod archive;
+pub mod attrs;
+pub mod backups;
 pub mod cfg;
+pub mod collections;
+pub mod confine;
 pub mod daemon;
+pub mod demo;
 pub mod diff;
+pub mod doctor;
 pub mod hash;
+pub mod history;
+pub mod hooks;
+pub mod hosts;
+pub mod import;
 pub mod install;
+pub mod integrity;
+pub mod limits;
+pub mod links;
+pub mod machines;
+pub mod macos;
+pub mod matching;
+pub mod mounts;
+pub mod offline;
 pub mod paths;
+pub mod perms;
+pub mod pins;
 pub mod profiles;
 pub mod remote;
 pub mod repo;
+pub mod report;
+pub mod resolve;
 pub mod scan;
 pub mod secrets;
 pub mod snapshots;
+pub mod sync;
+pub mod system;
+pub mod template;
+pub mod track;
+pub mod trash;
 pub mod ui;
+pub mod upgrade;
+pub mod usage;
 pub mod vcs;
+pub mod volatile;
+pub mod workspace;
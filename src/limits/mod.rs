@@ -0,0 +1,193 @@
+//! Size and binary-file guardrails.
+//!
+//! A broad include pattern or tracked directory can pull in an application
+//! cache or a database hundreds of megabytes large, which then slows down
+//! every snapshot and push. Discover and snapshot leave out files over
+//! `[limits] max_file_size` (10MB unless configured) and, with
+//! `skip_binaries = true`, files that look binary, listing what they skipped.
+//! `[limits] allow` lists files to sync anyway.
+
+use anyhow::{bail, Result};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::{Config, LimitsConfig};
+use crate::matching::{display_tilde, to_home_relative, PathMatcher};
+use crate::ui;
+use crate::usage::{format_size, parse_size};
+
+/// Skipped files listed by name before the rest are summarized
+const REPORTED_SKIPPED: usize = 10;
+
+/// How much of a file is read to decide whether it is binary, like git
+const BINARY_SNIFF_LEN: u64 = 8000;
+
+/// A file over the limits, with why it was skipped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Oversized {
+    pub path: PathBuf,
+    pub size: u64,
+    pub reason: String,
+}
+
+/// Compiled `[limits]` settings
+pub struct Limits {
+    max_file_size: Option<u64>,
+    skip_binaries: bool,
+    allow: PathMatcher,
+}
+
+impl Limits {
+    pub fn from_config(config: &Config, home: &Path) -> Result<Self> {
+        let limits = config.limits.clone().unwrap_or_default();
+        Self::new(&limits, home)
+    }
+
+    pub fn new(limits: &LimitsConfig, home: &Path) -> Result<Self> {
+        let Some(max_file_size) = parse_size(&limits.max_file_size) else {
+            bail!(
+                "Invalid max_file_size '{}'. Use a size like \"5MB\" or \"0\" for no limit",
+                limits.max_file_size
+            );
+        };
+        Ok(Limits {
+            max_file_size: Some(max_file_size).filter(|&max| max > 0),
+            skip_binaries: limits.skip_binaries,
+            allow: PathMatcher::for_paths(home, &limits.allow)?,
+        })
+    }
+
+    /// Why `path` is over the limits, or `None` if it may sync
+    pub fn check(&self, path: &Path) -> Option<Oversized> {
+        if self.allow.is_match(path) {
+            return None;
+        }
+        let size = fs::metadata(path).ok().filter(|m| m.is_file())?.len();
+        let reason = match self.max_file_size {
+            Some(max) if size > max => format!(
+                "{}, over max_file_size ({})",
+                format_size(size),
+                format_size(max)
+            ),
+            // Encrypted secrets are binary by design
+            _ if self.skip_binaries && is_binary(path) && !crate::secrets::is_encrypted(path) => {
+                format!("binary file, {}", format_size(size))
+            }
+            _ => return None,
+        };
+        Some(Oversized {
+            path: path.to_path_buf(),
+            size,
+            reason,
+        })
+    }
+
+    /// Split `files` into the ones to keep and the ones over the limits
+    pub fn partition(&self, files: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<Oversized>) {
+        let mut keep = Vec::new();
+        let mut skipped = Vec::new();
+        for path in files {
+            match self.check(&path) {
+                Some(oversized) => skipped.push(oversized),
+                None => keep.push(path),
+            }
+        }
+        (keep, skipped)
+    }
+}
+
+/// Whether the start of the file contains a NUL byte
+fn is_binary(path: &Path) -> bool {
+    let mut head = Vec::new();
+    fs::File::open(path)
+        .and_then(|f| f.take(BINARY_SNIFF_LEN).read_to_end(&mut head))
+        .is_ok_and(|_| head.contains(&0))
+}
+
+/// List the files skipped for being over the limits
+pub fn report(skipped: &[Oversized], home: &Path) {
+    if skipped.is_empty() {
+        return;
+    }
+    let total: u64 = skipped.iter().map(|s| s.size).sum();
+    ui::warn(&format!(
+        "Skipping {} file(s) over the size limits ({} in total):",
+        skipped.len(),
+        format_size(total)
+    ));
+    for s in skipped.iter().take(REPORTED_SKIPPED) {
+        println!(
+            "  {}: {}",
+            display_tilde(&to_home_relative(&s.path, home)),
+            s.reason
+        );
+    }
+    if skipped.len() > REPORTED_SKIPPED {
+        println!("  ... and {} more", skipped.len() - REPORTED_SKIPPED);
+    }
+    ui::hint("Raise [limits] max_file_size or add paths to [limits] allow to sync them anyway");
+}
+
+/// Drop files over the limits from `files`, listing them
+pub fn filter_and_report(
+    config: &Config,
+    home: &Path,
+    files: Vec<PathBuf>,
+) -> Result<Vec<PathBuf>> {
+    let (keep, skipped) = Limits::from_config(config, home)?.partition(files);
+    report(&skipped, home);
+    Ok(keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_skip_large_and_binary_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let home = dir.path();
+        let write = |name: &str, contents: &[u8]| {
+            let path = home.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, contents).unwrap();
+            path
+        };
+        let zshrc = write(".zshrc", b"export EDITOR=vim\n");
+        let blob = write(".config/app/cache/blob", &[b'x'; 4096]);
+        let font = write(".local/share/fonts/mono.ttf", b"\0\x01\0\0font");
+        let icon = write(".config/app/icon.png", b"\x89PNG\r\n\x1a\n\0\0");
+
+        let mut config = LimitsConfig {
+            max_file_size: "2KB".to_string(),
+            ..Default::default()
+        };
+        let limits = Limits::new(&config, home).unwrap();
+        let (keep, skipped) = limits.partition(vec![
+            zshrc.clone(),
+            blob.clone(),
+            font.clone(),
+            icon.clone(),
+        ]);
+        assert_eq!(keep, vec![zshrc.clone(), font.clone(), icon.clone()]);
+        assert_eq!(skipped[0].path, blob);
+        assert!(skipped[0].reason.contains("over max_file_size (2 KiB)"));
+
+        config.skip_binaries = true;
+        config.allow = vec!["~/.local/share/fonts/".to_string()];
+        let limits = Limits::new(&config, home).unwrap();
+        let (keep, skipped) = limits.partition(vec![zshrc.clone(), font.clone(), icon.clone()]);
+        assert_eq!(keep, vec![zshrc, font]);
+        assert_eq!(skipped[0].path, icon);
+        assert!(skipped[0].reason.starts_with("binary file"));
+
+        config.max_file_size = "0".to_string();
+        config.skip_binaries = false;
+        let limits = Limits::new(&config, home).unwrap();
+        assert!(limits.check(&blob).is_none());
+
+        config.max_file_size = "lots".to_string();
+        assert!(Limits::new(&config, home).is_err());
+    }
+}
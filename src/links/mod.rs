@@ -0,0 +1,267 @@
+//! Symlink creation and inspection for symlink-mode apply.
+//!
+//! By default apply creates links relative to the target's directory
+//! (`~/.zshrc -> .local/share/dotdipper/compiled/.zshrc`), so they keep working when
+//! the home directory moves or the user name differs between machines. Set
+//! `[general] relative_symlinks = false` for absolute links. `dotdipper relink`
+//! rewrites absolute links left by older versions, including ones that broke
+//! because the home directory moved.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs as unix_fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::hash::Manifest;
+use crate::ui;
+
+/// Lexically normalize a path: drop `.` and resolve `..` without touching the
+/// filesystem (so symlinked parents are not followed).
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !out.pop() {
+                    out.push("..");
+                }
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Path to `to`, relative to the directory `from_dir`. Both must be absolute.
+pub fn relative_path(from_dir: &Path, to: &Path) -> PathBuf {
+    let from = normalize(from_dir);
+    let to = normalize(to);
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut rel = PathBuf::new();
+    for _ in common..from.len() {
+        rel.push("..");
+    }
+    for component in &to[common..] {
+        rel.push(component);
+    }
+    rel
+}
+
+/// What a symlink at `target` should contain to point at `source`
+pub fn link_contents(source: &Path, target: &Path, relative: bool) -> PathBuf {
+    match target.parent() {
+        Some(dir) if relative && source.is_absolute() => relative_path(dir, source),
+        _ => source.to_path_buf(),
+    }
+}
+
+/// Create a symlink at `target` pointing to `source`
+pub fn create(source: &Path, target: &Path, relative: bool) -> Result<()> {
+    let contents = link_contents(source, target, relative);
+    unix_fs::symlink(&contents, target).with_context(|| {
+        format!(
+            "Failed to symlink {} -> {}",
+            source.display(),
+            target.display()
+        )
+    })
+}
+
+/// Absolute, normalized path a symlink points to (relative links are resolved
+/// against the link's directory). `None` if `link` is not a symlink.
+pub fn resolve(link: &Path) -> Option<PathBuf> {
+    let contents = fs::read_link(link).ok()?;
+    if contents.is_absolute() {
+        return Some(normalize(&contents));
+    }
+    Some(normalize(&link.parent()?.join(contents)))
+}
+
+/// True if `link` is a symlink to `source`, in either relative or absolute form
+pub fn points_to(link: &Path, source: &Path) -> bool {
+    resolve(link).is_some_and(|dest| dest == normalize(source))
+}
+
+/// True if `link` is a symlink into the directory `dir`
+pub fn points_into(link: &Path, dir: &Path) -> bool {
+    resolve(link).is_some_and(|dest| dest.starts_with(normalize(dir)))
+}
+
+#[derive(Debug, Default)]
+pub struct RelinkReport {
+    /// Links rewritten (or that would be, in a dry run)
+    pub rewritten: Vec<PathBuf>,
+    /// Broken links repaired because they pointed into a moved home
+    pub repaired: Vec<PathBuf>,
+}
+
+/// Should this absolute link be rewritten to point at `source`?
+///
+/// Yes if it already points there, or if it is broken and points at the same
+/// `compiled/<rel>` path under some other (old) home directory.
+fn needs_relink(link: &Path, source: &Path, rel_path: &Path) -> Option<bool> {
+    let contents = fs::read_link(link).ok()?;
+    if !contents.is_absolute() {
+        return None;
+    }
+    if points_to(link, source) {
+        return Some(false);
+    }
+    let moved = !contents.exists() && contents.ends_with(Path::new("compiled").join(rel_path));
+    moved.then_some(true)
+}
+
+/// Rewrite absolute symlinks into `compiled_root` as relative links.
+pub fn relink(compiled_root: &Path, manifest: &Manifest, dry_run: bool) -> Result<RelinkReport> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let mut report = RelinkReport::default();
+
+    let mut rel_paths: Vec<&PathBuf> = manifest.files.keys().collect();
+    rel_paths.sort();
+
+    for rel_path in rel_paths {
+        let target = home.join(rel_path);
+        let source = compiled_root.join(rel_path);
+        if !target.is_symlink() {
+            continue;
+        }
+        let Some(repaired) = needs_relink(&target, &source, rel_path) else {
+            continue;
+        };
+
+        if !dry_run {
+            fs::remove_file(&target)
+                .with_context(|| format!("Failed to remove {}", target.display()))?;
+            create(&source, &target, true)?;
+        }
+        if repaired {
+            report.repaired.push(target);
+        } else {
+            report.rewritten.push(target);
+        }
+    }
+
+    Ok(report)
+}
+
+/// Symlinks in `home` for files of `manifest` that point at a compiled copy
+/// that does not exist, under `compiled_root` or another compiled directory
+pub fn broken_links(home: &Path, manifest: &Manifest) -> Vec<PathBuf> {
+    let mut broken: Vec<PathBuf> = manifest
+        .files
+        .keys()
+        .filter_map(|rel_path| {
+            let target = home.join(rel_path);
+            let dest = resolve(&target)?;
+            let into_compiled = dest.ends_with(Path::new("compiled").join(rel_path));
+            (into_compiled && !target.exists()).then_some(target)
+        })
+        .collect();
+    broken.sort();
+    broken
+}
+
+/// Point the broken links of `broken_links` at the file's copy in
+/// `compiled_root`, returning the ones repaired. Links whose file is not in
+/// `compiled_root` either are left alone.
+pub fn repair_broken(
+    home: &Path,
+    compiled_root: &Path,
+    manifest: &Manifest,
+    relative: bool,
+) -> Result<Vec<PathBuf>> {
+    let mut repaired = Vec::new();
+    for target in broken_links(home, manifest) {
+        let Ok(rel_path) = target.strip_prefix(home) else {
+            continue;
+        };
+        let source = compiled_root.join(rel_path);
+        if !source.is_file() {
+            continue;
+        }
+        fs::remove_file(&target)
+            .with_context(|| format!("Failed to remove {}", target.display()))?;
+        create(&source, &target, relative)?;
+        repaired.push(target);
+    }
+    Ok(repaired)
+}
+
+/// `dotdipper relink`: rewrite the active compiled directory's absolute links
+pub fn run_relink(dry_run: bool) -> Result<RelinkReport> {
+    let compiled_root = crate::paths::compiled_dir()?;
+    let manifest_path = crate::paths::manifest_file()?;
+    if !manifest_path.exists() {
+        ui::warn("No manifest found. Run 'dotdipper snapshot' first.");
+        return Ok(RelinkReport::default());
+    }
+    let manifest = Manifest::load(&manifest_path)?;
+    let report = relink(&compiled_root, &manifest, dry_run)?;
+
+    let verb = if dry_run { "Would rewrite" } else { "Rewrote" };
+    for path in &report.rewritten {
+        println!("  {} {}", verb, path.display());
+    }
+    for path in &report.repaired {
+        println!("  {} {} (was broken, home moved)", verb, path.display());
+    }
+
+    let total = report.rewritten.len() + report.repaired.len();
+    if total == 0 {
+        ui::success("All symlinks are already relative");
+    } else if dry_run {
+        ui::info(&format!("{} symlink(s) would be made relative", total));
+    } else {
+        ui::success(&format!("Made {} symlink(s) relative", total));
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_relative_paths() {
+        assert_eq!(
+            relative_path(
+                Path::new("/home/me"),
+                Path::new("/home/me/.config/dotdipper/compiled/.zshrc")
+            ),
+            PathBuf::from(".config/dotdipper/compiled/.zshrc")
+        );
+        assert_eq!(
+            relative_path(
+                Path::new("/home/me/.config/nvim"),
+                Path::new("/home/me/.config/dotdipper/compiled/.config/nvim/init.lua")
+            ),
+            PathBuf::from("../dotdipper/compiled/.config/nvim/init.lua")
+        );
+        assert_eq!(
+            normalize(Path::new("/a/b/../c/./d")),
+            PathBuf::from("/a/c/d")
+        );
+    }
+
+    #[test]
+    fn test_relative_link_resolves_to_source() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("compiled/.config/app/conf");
+        let target = temp_dir.path().join(".config/app/conf");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        fs::write(&source, "x").unwrap();
+
+        create(&source, &target, true).unwrap();
+        assert!(fs::read_link(&target).unwrap().is_relative());
+        assert!(points_to(&target, &source));
+        assert!(points_into(&target, &temp_dir.path().join("compiled")));
+        assert_eq!(fs::read_to_string(&target).unwrap(), "x");
+    }
+}
@@ -0,0 +1,311 @@
+//! Registry of the machines sharing the dotfiles.
+//!
+//! Every snapshot and apply records this machine in
+//! `.dotdipper/machines/<hostname>.json` in the compiled directory: its OS,
+//! active profile, when it last synced, and which files it has, as a
+//! fingerprint of the manifest plus the snapshot they came from. The entries
+//! are pushed and pulled with the rest of the repo, one file per machine so
+//! they never conflict. `dotdipper machines list` and `status` compare them
+//! to the newest snapshot any machine took, to show which hosts have stale
+//! dotfiles.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hash::Manifest;
+use crate::ui;
+
+/// Where machines are recorded, relative to the compiled directory
+pub const MACHINES_DIR: &str = ".dotdipper/machines";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Machine {
+    pub hostname: String,
+    pub os: String,
+    pub profile: String,
+    pub last_sync: DateTime<Utc>,
+    /// Snapshot its files match, when known
+    pub snapshot: Option<String>,
+    /// Fingerprint of the manifest of the files it has
+    pub state: String,
+}
+
+/// How a machine compares with the newest snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Standing {
+    UpToDate,
+    Behind,
+}
+
+/// Fingerprint of the files `manifest` lists
+pub fn fingerprint(manifest: &Manifest) -> String {
+    let mut files: Vec<_> = manifest.files.iter().collect();
+    files.sort_by(|a, b| a.0.cmp(b.0));
+    let mut hasher = blake3::Hasher::new();
+    for (path, file) in files {
+        hasher.update(path.to_string_lossy().as_bytes());
+        hasher.update(&[0]);
+        hasher.update(file.hash.as_bytes());
+        hasher.update(&[0]);
+    }
+    hasher.finalize().to_hex()[..16].to_string()
+}
+
+fn machine_file(compiled: &Path, hostname: &str) -> PathBuf {
+    let name = hostname.replace(['/', '\\'], "_");
+    compiled
+        .join(MACHINES_DIR)
+        .join(format!("{}.json", name.trim_start_matches('.')))
+}
+
+/// Every machine recorded in `compiled`, sorted by hostname
+pub fn load(compiled: &Path) -> Result<Vec<Machine>> {
+    let Ok(entries) = fs::read_dir(compiled.join(MACHINES_DIR)) else {
+        return Ok(Vec::new());
+    };
+    let mut machines = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_none_or(|e| e != "json") {
+            continue;
+        }
+        let contents = fs::read_to_string(&path)?;
+        match serde_json::from_str::<Machine>(&contents) {
+            Ok(machine) => machines.push(machine),
+            Err(e) => ui::warn(&format!("Ignoring {}: {}", path.display(), e)),
+        }
+    }
+    machines.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+    Ok(machines)
+}
+
+/// Record that this machine now has the files of `compiled`'s manifest,
+/// taken as snapshot `snapshot` if it just took one
+pub fn record(compiled: &Path, manifest: &Manifest, snapshot: Option<&str>) -> Result<Machine> {
+    let profile = crate::profiles::active_profile_name().unwrap_or_else(|_| "default".into());
+    record_as(
+        compiled,
+        &crate::hosts::local_hostname(),
+        &profile,
+        manifest,
+        snapshot,
+    )
+}
+
+fn record_as(
+    compiled: &Path,
+    hostname: &str,
+    profile: &str,
+    manifest: &Manifest,
+    snapshot: Option<&str>,
+) -> Result<Machine> {
+    let state = fingerprint(manifest);
+    let machines = load(compiled)?;
+    // Applying files another machine snapshotted catches up with that snapshot
+    let snapshot = snapshot.map(str::to_string).or_else(|| {
+        machines
+            .iter()
+            .filter(|m| m.state == state)
+            .filter_map(|m| m.snapshot.clone())
+            .max()
+    });
+    let machine = Machine {
+        hostname: hostname.to_string(),
+        os: std::env::consts::OS.to_string(),
+        profile: profile.to_string(),
+        last_sync: Utc::now(),
+        snapshot,
+        state,
+    };
+
+    save(compiled, &machine)?;
+    Ok(machine)
+}
+
+fn save(compiled: &Path, machine: &Machine) -> Result<()> {
+    let path = machine_file(compiled, &machine.hostname);
+    fs::create_dir_all(path.parent().unwrap_or(compiled))?;
+    fs::write(&path, serde_json::to_string_pretty(machine)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Record this machine in the active profile's compiled directory. Best
+/// effort: a failure only produces a warning.
+pub fn record_local(snapshot: Option<&str>) {
+    let result = (|| {
+        let compiled = crate::paths::compiled_dir()?;
+        let manifest_path = crate::paths::manifest_file()?;
+        if !compiled.is_dir() || !manifest_path.exists() {
+            return Ok(());
+        }
+        record(&compiled, &Manifest::load(&manifest_path)?, snapshot).map(|_| ())
+    })();
+    if let Err(e) = result {
+        ui::warn(&format!("Could not record this machine: {:#}", e));
+    }
+}
+
+/// Write `machines` back into `compiled`, as after replacing its contents
+pub fn restore(compiled: &Path, machines: &[Machine]) -> Result<()> {
+    for machine in machines {
+        save(compiled, machine)?;
+    }
+    Ok(())
+}
+
+/// Copy the entries of `from` that `into` lacks or has older versions of,
+/// so pulling an older registry doesn't forget syncs recorded since
+pub fn keep_newer(from: &Path, into: &Path) -> Result<()> {
+    let existing = load(into)?;
+    for machine in load(from)? {
+        let newer = existing
+            .iter()
+            .find(|m| m.hostname == machine.hostname)
+            .is_none_or(|m| m.last_sync < machine.last_sync);
+        if newer {
+            save(into, &machine)?;
+        }
+    }
+    Ok(())
+}
+
+/// The machine holding the newest snapshot, or the one that synced last
+pub fn latest(machines: &[Machine]) -> Option<&Machine> {
+    machines
+        .iter()
+        .max_by_key(|m| (m.snapshot.clone(), m.last_sync))
+}
+
+/// How each of `machines` compares with the latest one
+pub fn standings(machines: &[Machine]) -> Vec<(&Machine, Standing)> {
+    let Some(latest) = latest(machines) else {
+        return Vec::new();
+    };
+    machines
+        .iter()
+        .map(|m| match m.state == latest.state {
+            true => (m, Standing::UpToDate),
+            false => (m, Standing::Behind),
+        })
+        .collect()
+}
+
+/// The hostnames of machines behind the latest snapshot
+pub fn behind(machines: &[Machine]) -> Vec<String> {
+    standings(machines)
+        .into_iter()
+        .filter(|(_, standing)| *standing == Standing::Behind)
+        .map(|(m, _)| m.hostname.clone())
+        .collect()
+}
+
+/// `dotdipper machines list`
+pub fn print_list() -> Result<()> {
+    let machines = load(&crate::paths::compiled_dir()?)?;
+    let local = crate::hosts::local_hostname();
+    let standings = standings(&machines);
+    if ui::is_json() {
+        let entries: Vec<serde_json::Value> = standings
+            .iter()
+            .map(|(m, standing)| {
+                serde_json::json!({
+                    "hostname": m.hostname,
+                    "os": m.os,
+                    "profile": m.profile,
+                    "last_sync": m.last_sync,
+                    "snapshot": m.snapshot,
+                    "state": m.state,
+                    "behind": *standing == Standing::Behind,
+                    "local": m.hostname == local,
+                })
+            })
+            .collect();
+        return ui::print_json(&entries);
+    }
+    if machines.is_empty() {
+        ui::info("No machines recorded yet");
+        ui::hint("Machines are recorded when they take a snapshot or apply");
+        return Ok(());
+    }
+
+    let rows = standings
+        .iter()
+        .map(|(m, standing)| {
+            let name = match m.hostname == local {
+                true => format!("{} (this machine)", m.hostname),
+                false => m.hostname.clone(),
+            };
+            vec![
+                name,
+                m.os.clone(),
+                m.profile.clone(),
+                m.last_sync.format("%Y-%m-%d %H:%M").to_string(),
+                m.snapshot.clone().unwrap_or_else(|| "-".to_string()),
+                match standing {
+                    Standing::UpToDate => "up to date".to_string(),
+                    Standing::Behind => "behind".to_string(),
+                },
+            ]
+        })
+        .collect();
+    ui::print_table(
+        &["Host", "OS", "Profile", "Last sync", "Snapshot", "State"],
+        rows,
+    );
+    let behind = behind(&machines);
+    if !behind.is_empty() {
+        ui::hint("Bring a machine up to date with: dotdipper pull --apply");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::FileHash;
+
+    fn manifest(hash: &str) -> Manifest {
+        let mut manifest = Manifest::new();
+        manifest.add_file(FileHash {
+            path: PathBuf::from(".zshrc"),
+            hash: hash.to_string(),
+            size: 1,
+            mode: 0o644,
+            modified: Utc::now(),
+            xattrs: Default::default(),
+        });
+        manifest
+    }
+
+    #[test]
+    fn test_machines_that_lack_the_newest_snapshot_are_behind() {
+        let temp = tempfile::tempdir().unwrap();
+        let compiled = temp.path();
+        let machine = |hostname: &str, snapshot: Option<&str>, state: &Manifest| Machine {
+            hostname: hostname.to_string(),
+            os: "linux".to_string(),
+            profile: "default".to_string(),
+            last_sync: Utc::now(),
+            snapshot: snapshot.map(str::to_string),
+            state: fingerprint(state),
+        };
+        for m in [
+            machine("desktop", Some("20261001_120000"), &manifest("a")),
+            machine("laptop", Some("20261010_090000"), &manifest("b")),
+        ] {
+            save(compiled, &m).unwrap();
+        }
+        assert_eq!(behind(&load(compiled).unwrap()), vec!["desktop"]);
+
+        // Applying the laptop's files catches up with its snapshot
+        let nas = record_as(compiled, "nas", "default", &manifest("b"), None).unwrap();
+        assert_eq!(nas.snapshot.as_deref(), Some("20261010_090000"));
+        let machines = load(compiled).unwrap();
+        assert_eq!(machines.len(), 3);
+        assert_eq!(behind(&machines), vec!["desktop"]);
+        assert_ne!(fingerprint(&manifest("a")), fingerprint(&manifest("b")));
+    }
+}
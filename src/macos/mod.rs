@@ -0,0 +1,260 @@
+//! macOS LaunchAgents and login items.
+//!
+//! `~/Library/LaunchAgents/*.plist` and the login item list are part of a
+//! machine's personality but live outside the usual dotfile paths. With
+//! `[macos] launch_agents = true` the plists are captured on snapshot, always
+//! restored as copies (launchd ignores symlinked agents at login), validated,
+//! and (re)loaded with `launchctl` when apply changes them. With
+//! `login_items = true` the login item list is stored in the compiled
+//! directory and missing items are re-added on apply.
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cfg::Config;
+use crate::ui;
+
+/// Home-relative directory holding per-user launch agents
+pub const LAUNCH_AGENTS_DIR: &str = "Library/LaunchAgents";
+
+/// Where captured login items are stored, relative to the compiled directory
+pub const LOGIN_ITEMS_FILE: &str = ".dotdipper/login_items.json";
+
+fn macos_config(config: &Config) -> crate::cfg::MacosConfig {
+    config.macos.clone().unwrap_or_default()
+}
+
+/// True for `Library/LaunchAgents/<name>.plist` (home-relative) when
+/// `[macos] launch_agents` is on; otherwise those are ordinary dotfiles
+pub fn is_launch_agent(config: &Config, rel_path: &Path) -> bool {
+    config.macos.as_ref().is_some_and(|m| m.launch_agents)
+        && rel_path.parent() == Some(Path::new(LAUNCH_AGENTS_DIR))
+        && rel_path.extension().is_some_and(|e| e == "plist")
+}
+
+/// Cheap structural check that works on every platform: XML plists must have
+/// a `<plist>` root, binary plists start with `bplist00`.
+pub fn looks_like_plist(contents: &[u8]) -> bool {
+    if contents.starts_with(b"bplist00") {
+        return true;
+    }
+    let text = String::from_utf8_lossy(contents);
+    text.contains("<plist") && text.contains("</plist>")
+}
+
+/// Validate a plist, using `plutil -lint` where available
+pub fn validate_plist(path: &Path) -> Result<()> {
+    let contents = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if !looks_like_plist(&contents) {
+        bail!("{} is not a property list", path.display());
+    }
+
+    if cfg!(target_os = "macos") {
+        let output = Command::new("plutil")
+            .arg("-lint")
+            .arg(path)
+            .output()
+            .context("Failed to run plutil")?;
+        if !output.status.success() {
+            bail!(
+                "Invalid plist {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Launch agent plists to add to the snapshot, when enabled
+pub fn launch_agents_to_capture(config: &Config, home: &Path) -> Vec<PathBuf> {
+    if !macos_config(config).launch_agents {
+        return Vec::new();
+    }
+
+    let dir = home.join(LAUNCH_AGENTS_DIR);
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return Vec::new();
+    };
+
+    let mut agents: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "plist"))
+        .filter(|p| !config.general.tracked_files.contains(p))
+        .filter(|p| match validate_plist(p) {
+            Ok(()) => true,
+            Err(e) => {
+                ui::warn(&format!("Skipping launch agent: {:#}", e));
+                false
+            }
+        })
+        .collect();
+    agents.sort();
+    agents
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LoginItems {
+    pub paths: Vec<String>,
+}
+
+/// Parse the comma-separated list AppleScript returns for `path of every login item`
+pub fn parse_login_items(output: &str) -> Vec<String> {
+    output
+        .trim()
+        .split(", ")
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+fn osascript(script: &str) -> Result<String> {
+    let output = Command::new("osascript")
+        .args(["-e", script])
+        .output()
+        .context("Failed to run osascript")?;
+    if !output.status.success() {
+        bail!(
+            "osascript failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn current_login_items() -> Result<Vec<String>> {
+    osascript("tell application \"System Events\" to get the path of every login item")
+        .map(|out| parse_login_items(&out))
+}
+
+/// Store the current login items in the compiled directory (snapshot step)
+pub fn capture_login_items(config: &Config, compiled_root: &Path) -> Result<()> {
+    if !macos_config(config).login_items {
+        return Ok(());
+    }
+    if !cfg!(target_os = "macos") {
+        ui::debug("Login item capture is only supported on macOS");
+        return Ok(());
+    }
+
+    let items = LoginItems {
+        paths: current_login_items()?,
+    };
+    let path = compiled_root.join(LOGIN_ITEMS_FILE);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(&items)?)?;
+    ui::info(&format!("Captured {} login item(s)", items.paths.len()));
+    Ok(())
+}
+
+/// Post-apply step: reload changed launch agents and re-add missing login items.
+///
+/// Failures are reported as warnings; they never fail the apply.
+pub fn after_apply(config: &Config, compiled_root: &Path, changed_agents: &[PathBuf]) {
+    let macos = macos_config(config);
+    if !cfg!(target_os = "macos") {
+        return;
+    }
+
+    if macos.launch_agents && macos.load_agents {
+        for agent in changed_agents {
+            if let Err(e) = reload_agent(agent) {
+                ui::warn(&format!("Could not load {}: {:#}", agent.display(), e));
+            }
+        }
+    }
+
+    if macos.login_items {
+        if let Err(e) = restore_login_items(compiled_root) {
+            ui::warn(&format!("Could not restore login items: {:#}", e));
+        }
+    }
+}
+
+fn reload_agent(plist: &Path) -> Result<()> {
+    // Unloading fails harmlessly when the agent was not loaded yet
+    let _ = Command::new("launchctl").arg("unload").arg(plist).output();
+
+    let output = Command::new("launchctl")
+        .args(["load", "-w"])
+        .arg(plist)
+        .output()
+        .context("Failed to run launchctl")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    ui::info(&format!("Loaded launch agent {}", plist.display()));
+    Ok(())
+}
+
+fn restore_login_items(compiled_root: &Path) -> Result<()> {
+    let path = compiled_root.join(LOGIN_ITEMS_FILE);
+    if !path.exists() {
+        return Ok(());
+    }
+    let wanted: LoginItems = serde_json::from_str(&fs::read_to_string(&path)?)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let current = current_login_items()?;
+
+    for item in wanted.paths.iter().filter(|p| !current.contains(p)) {
+        if !Path::new(item).exists() {
+            ui::warn(&format!(
+                "Login item not installed here, skipping: {}",
+                item
+            ));
+            continue;
+        }
+        let escaped = item.replace('\\', "\\\\").replace('"', "\\\"");
+        osascript(&format!(
+            "tell application \"System Events\" to make login item at end with properties {{path:\"{}\", hidden:false}}",
+            escaped
+        ))?;
+        ui::info(&format!("Added login item {}", item));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_launch_agent_detection_and_plist_checks() {
+        let agent = Path::new("Library/LaunchAgents/com.example.sync.plist");
+        let mut config = Config::default();
+        assert!(!is_launch_agent(&config, agent));
+        config.macos = Some(crate::cfg::MacosConfig {
+            launch_agents: true,
+            ..Default::default()
+        });
+        assert!(is_launch_agent(&config, agent));
+        assert!(!is_launch_agent(
+            &config,
+            Path::new("Library/LaunchAgents/nested/com.example.plist")
+        ));
+        assert!(!is_launch_agent(
+            &config,
+            Path::new("Library/LaunchAgents/README")
+        ));
+
+        let xml = br#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict><key>Label</key><string>com.example</string></dict></plist>"#;
+        assert!(looks_like_plist(xml));
+        assert!(looks_like_plist(b"bplist00\x01\x02"));
+        assert!(!looks_like_plist(b"not a plist"));
+
+        assert_eq!(
+            parse_login_items("/Applications/Rectangle.app, /Applications/Dropbox.app\n"),
+            vec!["/Applications/Rectangle.app", "/Applications/Dropbox.app"]
+        );
+        assert!(parse_login_items("\n").is_empty());
+    }
+}
@@ -1,21 +1,43 @@
+use dotdipper::archive;
+use dotdipper::backups;
 use dotdipper::cfg;
+use dotdipper::collections;
 use dotdipper::daemon;
+use dotdipper::demo;
 use dotdipper::diff;
+use dotdipper::doctor;
 use dotdipper::hash;
+use dotdipper::history;
+use dotdipper::hooks;
+use dotdipper::hosts;
+use dotdipper::import;
 use dotdipper::install;
+use dotdipper::integrity;
+use dotdipper::links;
+use dotdipper::machines;
+use dotdipper::offline;
+use dotdipper::pins;
 use dotdipper::profiles;
 use dotdipper::remote;
 use dotdipper::repo;
+use dotdipper::report;
+use dotdipper::resolve;
 use dotdipper::scan;
 use dotdipper::secrets;
 use dotdipper::snapshots;
+use dotdipper::template;
+use dotdipper::track;
+use dotdipper::trash;
 use dotdipper::ui;
+use dotdipper::upgrade;
+use dotdipper::usage;
 use dotdipper::vcs;
+use dotdipper::workspace;
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand};
 use colored::Colorize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Dotdipper - A smart dotfiles manager with GitHub sync and machine bootstrapping
 #[derive(Parser)]
@@ -26,14 +48,58 @@ struct Cli {
     #[arg(short, long, global = true)]
     verbose: bool,
 
+    /// Only print errors and final results
+    #[arg(short, long, global = true, conflicts_with_all = ["verbose", "porcelain"])]
+    quiet: bool,
+
+    /// Stable, tab-separated single-line output for scripts (`status` prints
+    /// the versioned `--porcelain=v1` file format)
+    #[arg(
+        long,
+        global = true,
+        conflicts_with = "verbose",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "v1",
+        value_parser = ["v1"]
+    )]
+    porcelain: Option<String>,
+
+    /// Output format; `json` prints one JSON document from `status`, `diff`,
+    /// `snapshot list`, `snapshot diff`, `profile list`, `log` and `doctor`
+    #[arg(
+        long = "output",
+        global = true,
+        value_enum,
+        default_value_t = OutputFormat::Text,
+        conflicts_with = "porcelain"
+    )]
+    output_format: OutputFormat,
+
     /// Path to config file (defaults to ~/.config/dotdipper/config.toml)
     #[arg(long, global = true)]
     config: Option<PathBuf>,
 
+    /// Work on this profile's compiled files and manifest instead of the
+    /// active profile's
+    #[arg(long, global = true, env = "DOTDIPPER_PROFILE", value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Override a config value for this run, e.g. `--set
+    /// general.default_mode=copy` (repeatable; goes before the command)
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Clone, Copy, PartialEq, clap::ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
     /// Initialize dotdipper in the current directory
@@ -75,6 +141,19 @@ enum Commands {
         /// Show detailed diff
         #[arg(long)]
         detailed: bool,
+
+        /// Only show specific paths (comma-separated)
+        #[arg(long)]
+        only: Option<String>,
+
+        /// Also check that applied files are still linked or copied from
+        /// the compiled repository
+        #[arg(long)]
+        applied: bool,
+
+        /// Show the status again whenever tracked files change
+        #[arg(long)]
+        watch: bool,
     },
 
     /// Show differences between compiled and system files
@@ -82,6 +161,15 @@ enum Commands {
         /// Show detailed diff for each file
         #[arg(long)]
         detailed: bool,
+
+        /// Show each file's diff with this tool: git, unified, side-by-side or
+        /// a command like delta (implies --detailed; defaults to [ui] diff_tool)
+        #[arg(long, value_name = "TOOL")]
+        tool: Option<String>,
+
+        /// Show the diff again whenever tracked files change
+        #[arg(long)]
+        watch: bool,
     },
 
     /// Apply dotfiles to system
@@ -98,9 +186,30 @@ enum Commands {
         #[arg(long)]
         only: Option<String>,
 
+        /// Only apply the files of a named `[subsets]` entry
+        #[arg(long, value_name = "NAME")]
+        subset: Option<String>,
+
         /// Allow operations outside $HOME (unsafe)
         #[arg(long)]
         unsafe_allow_outside_home: bool,
+
+        /// Apply file contents from a snapshot without touching compiled/ or the manifest
+        #[arg(long, value_name = "SNAPSHOT_ID", conflicts_with = "revert_as_of")]
+        as_of: Option<String>,
+
+        /// Restore the current version of files applied with --as-of
+        #[arg(long)]
+        revert_as_of: bool,
+
+        /// Overwrite protected files without confirming each one
+        #[arg(long)]
+        yes_protected: bool,
+
+        /// Show what would be symlinked, copied, skipped and backed up
+        /// without changing anything
+        #[arg(long, conflicts_with = "revert_as_of")]
+        dry_run: bool,
     },
 
     /// Manage encrypted secrets
@@ -115,6 +224,10 @@ enum Commands {
     #[command(subcommand)]
     Profile(ProfileCommands),
 
+    /// Manage extra dotfile collections (e.g. a team baseline)
+    #[command(subcommand)]
+    Collection(CollectionCommands),
+
     /// Manage remote backups
     #[command(subcommand)]
     Remote(RemoteCommands),
@@ -152,6 +265,43 @@ enum Commands {
         #[arg(long)]
         unsafe_allow_outside_home: bool,
 
+        /// With --apply, overwrite protected files without confirming each one
+        #[arg(long, requires = "apply")]
+        yes_protected: bool,
+
+        /// With --apply, only apply the files of a named `[subsets]` entry
+        #[arg(long, value_name = "NAME", requires = "apply")]
+        subset: Option<String>,
+
+        /// Check the integrity of the pulled repository before applying
+        #[arg(long)]
+        verify: bool,
+
+        /// With --apply, stage every file first and change nothing unless all
+        /// of them can be applied
+        #[arg(long, requires = "apply")]
+        atomic: bool,
+
+        /// Override the GitHub repository name
+        #[arg(long)]
+        repo: Option<String>,
+    },
+
+    /// Pull and push in one go, keeping local edits: files changed on both
+    /// sides are shown as conflicts to settle
+    Sync {
+        /// Only show how each file differs, change nothing
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Settle every conflict this way instead of asking
+        #[arg(long, value_name = "SIDE", value_parser = ["local", "remote", "merge"])]
+        resolve: Option<String>,
+
+        /// Don't push the result
+        #[arg(long)]
+        no_push: bool,
+
         /// Override the GitHub repository name
         #[arg(long)]
         repo: Option<String>,
@@ -159,7 +309,17 @@ enum Commands {
 
     /// Undo the last pushed commit by creating a revert commit
     Undo {
-        /// Skip confirmation prompt
+        /// History entry of the push or apply to undo instead (see 'dotdipper history')
+        #[arg(value_name = "HISTORY_ID")]
+        id: Option<u64>,
+
+        /// Undo the last apply instead, restoring backups and removing the
+        /// symlinks and copies it created
+        #[arg(long, conflicts_with = "id")]
+        apply: bool,
+
+        /// Skip confirmation prompt (with --apply: also revert files changed
+        /// since the apply)
         #[arg(short, long)]
         force: bool,
 
@@ -183,11 +343,48 @@ enum Commands {
         unsafe_allow_outside_home: bool,
     },
 
+    /// Generate a script that sets up a new machine from scratch
+    Bootstrap {
+        /// Write a self-contained bootstrap script (install dotdipper, clone
+        /// the GitHub repo, install and apply) and print a `curl | sh` one-liner
+        #[arg(long, required = true)]
+        emit: bool,
+
+        /// Where to write the script (default: install/bootstrap.sh)
+        #[arg(long, value_name = "PATH")]
+        file: Option<PathBuf>,
+
+        /// URL the script will be served from (default: bootstrap.sh in the
+        /// GitHub repo)
+        #[arg(long)]
+        url: Option<String>,
+
+        /// Embed the whole config.toml instead of only the [github] settings
+        #[arg(long)]
+        with_config: bool,
+    },
+
+    /// Discover, list, add and remove the packages in [packages]
+    #[command(subcommand)]
+    Packages(PackagesCommands),
+
     /// Run diagnostics and check system health
     Doctor {
         /// Fix issues automatically where possible
         #[arg(long)]
         fix: bool,
+
+        /// Write a redacted bug report archive (.tar.gz) to attach to issues
+        #[arg(long)]
+        report: bool,
+
+        /// Where to write the report (default: ./dotdipper-report-<time>.tar.gz)
+        #[arg(long, requires = "report")]
+        report_file: Option<PathBuf>,
+
+        /// Log lines to include per log file
+        #[arg(long, requires = "report", default_value_t = report::DEFAULT_LOG_LINES)]
+        log_lines: usize,
     },
 
     /// Edit or view configuration
@@ -203,11 +400,227 @@ enum Commands {
         /// Set a config value (format: key=value, e.g. github.repo_name=dotfiles-dotdipper)
         #[arg(long, value_name = "KEY=VALUE")]
         set: Option<String>,
+
+        /// Check the config for unknown keys, invalid patterns and conflicting
+        /// settings, and fail if it cannot be used
+        #[arg(long)]
+        check: bool,
     },
 
     /// Manage push-ignore patterns
     #[command(subcommand)]
     Ignore(IgnoreCommands),
+
+    /// Inspect hooks, trust pulled dotfiles and install git hooks
+    #[command(subcommand, alias = "hook")]
+    Hooks(HooksCommands),
+
+    /// Stamp per-project template files into directories
+    #[command(subcommand)]
+    Workspace(WorkspaceCommands),
+
+    /// Preview dotfiles rendered as templates and their variables
+    #[command(subcommand)]
+    Template(TemplateCommands),
+
+    /// Show disk usage of dotdipper's state by subsystem
+    Du,
+
+    /// Move files in compiled/ that are neither in the manifest nor tracked
+    /// to the trash
+    Gc {
+        /// List what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Check the compiled files against the manifest for corruption and
+    /// manual edits
+    Verify {
+        /// Repair what can be, from $HOME or a snapshot
+        #[arg(long)]
+        repair: bool,
+    },
+
+    /// List, restore and prune the backups apply makes of replaced files
+    #[command(subcommand)]
+    Backups(BackupsCommands),
+
+    /// List, restore and empty what apply and rollback moved to the trash
+    #[command(subcommand)]
+    Trash(TrashCommands),
+
+    /// Show the machines sharing these dotfiles and which ones are behind
+    #[command(subcommand)]
+    Machines(MachinesCommands),
+
+    /// Show the history of snapshots, applies, pushes, pulls and errors
+    History {
+        /// Only entries newer than an age (e.g. 7d, 12h) or a date (YYYY-MM-DD)
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Print entries as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the commits of the compiled repository that changed a file
+    Log {
+        /// File to show the changes of, e.g. ~/.zshrc (default: every commit)
+        path: Option<String>,
+
+        /// Show at most this many commits
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+
+    /// Restore one file from an older commit of the compiled repository
+    Restore {
+        /// File to restore, e.g. ~/.zshrc
+        path: String,
+
+        /// Commit to take the file from (see `dotdipper log <path>`)
+        #[arg(long, value_name = "COMMIT")]
+        rev: String,
+
+        /// Restore without confirming
+        #[arg(long)]
+        force: bool,
+
+        /// Print that version instead of writing it
+        #[arg(long)]
+        stdout: bool,
+    },
+
+    /// Rewrite absolute symlinks from older versions as relative ones
+    Relink {
+        /// Show what would be rewritten without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Create a sandbox home with fake dotfiles and walk through snapshot/diff/apply
+    Demo {
+        /// Directory for the demo home (must be empty; defaults to a temp directory)
+        #[arg(long, value_name = "DIR")]
+        dir: Option<PathBuf>,
+
+        /// Only create the sandbox, skip the walkthrough
+        #[arg(long)]
+        no_walkthrough: bool,
+    },
+
+    /// Pin a file or directory to a snapshot on this machine (lists pins without arguments)
+    Pin {
+        /// File or directory to pin, e.g. ~/.config/nvim
+        #[arg(requires = "snapshot")]
+        path: Option<String>,
+
+        /// Snapshot ID (or unique prefix) to pin to
+        #[arg(long, value_name = "ID")]
+        snapshot: Option<String>,
+    },
+
+    /// Remove a pin so the path tracks the latest snapshot again
+    Unpin {
+        /// Pinned file or directory
+        path: String,
+    },
+
+    /// Start tracking files or directories right away: they are copied into
+    /// the compiled files and the manifest without a full snapshot
+    Add {
+        /// Files or directories under $HOME, e.g. ~/.config/kitty
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// Also track files outside $HOME, such as /etc/hosts; apply writes
+        /// a sudo script for the ones only root can write
+        #[arg(long)]
+        system: bool,
+    },
+
+    /// Stop tracking files or directories and drop their compiled copies;
+    /// the files in $HOME are left as they are
+    #[command(alias = "remove")]
+    Rm {
+        /// Tracked files or directories
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+
+    /// Import dotfiles managed by chezmoi, GNU stow or a bare git repository
+    /// into the compiled files, and take an initial snapshot
+    Import {
+        /// Tool the dotfiles come from
+        #[arg(long, value_parser = ["chezmoi", "stow", "git"])]
+        from: String,
+
+        /// chezmoi source directory, stow directory or git repository
+        path: String,
+
+        /// Only list the files that would be imported
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Replace compiled files that differ from the imported ones
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// Stop tracking a file or directory but keep it in the repo under archive/ (lists archived files without arguments)
+    Archive {
+        /// File or directory to archive, e.g. ~/.config/alacritty
+        path: Option<String>,
+    },
+
+    /// Track an archived file or directory again
+    Unarchive {
+        /// Archived file or directory
+        path: String,
+    },
+
+    /// Download and install the latest release, then migrate state
+    SelfUpgrade {
+        /// Only check whether a newer release is available
+        #[arg(long)]
+        check: bool,
+
+        /// Install this release instead of the latest (e.g. 0.8.0)
+        #[arg(long, value_name = "VERSION")]
+        version: Option<String>,
+
+        /// Install even if the release is not newer than the running version
+        #[arg(long)]
+        force: bool,
+
+        /// Binary to replace (defaults to the running executable)
+        #[arg(long, value_name = "PATH")]
+        path: Option<PathBuf>,
+    },
+
+    /// Run pending state migrations (config schema, manifests, layout)
+    Migrate {
+        /// List pending migrations without running them
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print a shell completion script, e.g. `dotdipper completions zsh >
+    /// ~/.zfunc/_dotdipper`
+    Completions {
+        /// Shell to complete in
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    /// Write man pages for dotdipper and every subcommand
+    Manpages {
+        /// Directory to write the pages to
+        #[arg(long, value_name = "DIR", default_value = ".")]
+        out_dir: PathBuf,
+    },
 }
 
 #[derive(Subcommand)]
@@ -221,7 +634,7 @@ enum SecretsCommands {
         path: PathBuf,
 
         /// Output path (defaults to <path>.age)
-        #[arg(short, long)]
+        #[arg(short, long = "output-file")]
         output: Option<PathBuf>,
     },
 
@@ -231,7 +644,7 @@ enum SecretsCommands {
         path: PathBuf,
 
         /// Output path (defaults to removing .age suffix)
-        #[arg(short, long)]
+        #[arg(short, long = "output-file")]
         output: Option<PathBuf>,
     },
 
@@ -240,6 +653,12 @@ enum SecretsCommands {
         /// Path to encrypted file
         path: PathBuf,
     },
+
+    /// Run the decrypt agent in the foreground (normally started on demand)
+    Agent,
+
+    /// Stop the decrypt agent and drop the unlocked identity
+    Lock,
 }
 
 #[derive(Subcommand)]
@@ -253,24 +672,61 @@ enum SnapshotCommands {
         /// Force snapshot even if no changes detected
         #[arg(short, long)]
         force: bool,
+
+        /// Leave these paths out of this snapshot, keeping their previous
+        /// version (comma-separated)
+        #[arg(long)]
+        exclude: Option<String>,
+
+        /// Re-hash and re-copy every file, even with incremental snapshots on
+        #[arg(long)]
+        full: bool,
     },
 
     /// List all snapshots
     List,
 
+    /// Show the files added, removed and modified between two snapshots,
+    /// or between a snapshot and the current compiled files
+    Diff {
+        /// Older snapshot ID (a unique prefix or fragment is enough)
+        from: String,
+
+        /// Newer snapshot ID (defaults to the current compiled files)
+        to: Option<String>,
+
+        /// Also show content diffs of modified files
+        #[arg(short, long)]
+        patch: bool,
+
+        /// Show content diffs with this tool: git, unified, side-by-side or a
+        /// command like delta (implies --patch; defaults to [ui] diff_tool)
+        #[arg(long, value_name = "TOOL")]
+        tool: Option<String>,
+    },
+
     /// Rollback to a snapshot
     Rollback {
-        /// Snapshot ID
+        /// Snapshot ID (a unique prefix or fragment is enough)
         id: String,
 
         /// Skip confirmation prompt
         #[arg(short, long)]
         force: bool,
+
+        /// Only restore these paths, leaving other files as they are
+        /// (comma-separated)
+        #[arg(long)]
+        only: Option<String>,
+
+        /// Pick the files to restore from a list
+        #[arg(short, long)]
+        interactive: bool,
     },
 
     /// Delete a snapshot
     Delete {
-        /// Snapshot ID
+        /// Snapshot ID (a unique prefix or fragment is enough)
         id: String,
 
         /// Skip confirmation prompt
@@ -288,7 +744,7 @@ enum SnapshotCommands {
         #[arg(long)]
         keep_age: Option<String>,
 
-        /// Keep snapshots until total size is under limit (e.g., "1GB", "500MB")
+        /// Keep the newest snapshots that fit in this much disk space (e.g., "1GB", "500MB")
         #[arg(long)]
         keep_size: Option<String>,
 
@@ -299,94 +755,374 @@ enum SnapshotCommands {
 }
 
 #[derive(Subcommand)]
-enum ProfileCommands {
-    /// List all profiles
+enum PackagesCommands {
+    /// Find the packages tracked files need and pick which ones to add
+    Discover {
+        /// Target OS for package names (auto-detected if not specified)
+        #[arg(long)]
+        target_os: Option<String>,
+
+        /// Only show matches at least this confident
+        #[arg(long, default_value = "medium", value_parser = ["high", "medium", "low"])]
+        min_confidence: String,
+
+        /// Section accepted packages are added to
+        #[arg(long, default_value = "common", value_parser = install::packages::SECTIONS)]
+        to: String,
+
+        /// Add every package found without asking
+        #[arg(short, long)]
+        yes: bool,
+
+        /// Only show what was found
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// List the packages in [packages]
     List,
 
-    /// Create a new profile
-    Create {
-        /// Profile name
-        name: String,
+    /// Write this host's packages as a Brewfile, apt list or pacman list
+    Export {
+        #[arg(long, value_parser = ["brewfile", "aptfile", "pacman"])]
+        format: String,
+
+        /// Write to a file instead of stdout
+        #[arg(long)]
+        file: Option<PathBuf>,
     },
 
-    /// Switch to a profile
-    Switch {
-        /// Profile name
-        name: String,
+    /// Add the packages of a Brewfile, apt list or pacman list to [packages]
+    Import {
+        /// List to import, e.g. ~/Brewfile
+        file: PathBuf,
+
+        /// Format of the list (guessed from the file name if not specified)
+        #[arg(long, value_parser = ["brewfile", "aptfile", "pacman"])]
+        format: Option<String>,
+
+        /// Section to add to (default: macos for a Brewfile, ubuntu for apt,
+        /// arch for pacman)
+        #[arg(long, value_parser = install::packages::SECTIONS)]
+        to: Option<String>,
     },
 
-    /// Remove a profile
+    /// Add packages to [packages]
+    Add {
+        #[arg(required = true)]
+        packages: Vec<String>,
+
+        /// Section to add to
+        #[arg(long, default_value = "common", value_parser = install::packages::SECTIONS)]
+        to: String,
+    },
+
+    /// Remove packages from [packages]
     Remove {
-        /// Profile name
-        name: String,
+        #[arg(required = true)]
+        packages: Vec<String>,
 
-        /// Skip confirmation prompt
-        #[arg(short, long)]
-        force: bool,
+        /// Only remove from this section (default: every section)
+        #[arg(long, value_parser = install::packages::SECTIONS)]
+        from: Option<String>,
     },
 }
 
 #[derive(Subcommand)]
-enum RemoteCommands {
-    /// Configure remote backend
-    Set {
-        /// Remote kind (localfs, s3, gcs, webdav)
-        kind: String,
+enum BackupsCommands {
+    /// List backups, newest first
+    List,
 
-        /// Endpoint URL or path (required for localfs, webdav)
-        #[arg(long)]
-        endpoint: Option<String>,
+    /// Put a backup back in place; the current file is backed up in turn
+    Restore {
+        /// File to restore its newest backup of, or a backup path
+        path: String,
 
-        /// S3 bucket name (required for s3)
-        #[arg(long)]
-        bucket: Option<String>,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
 
-        /// AWS region (for s3, defaults to us-east-1)
+    /// Delete old backups
+    Prune {
+        /// Keep backups newer than duration (e.g., "30d", "7d", "2w", "1m")
         #[arg(long)]
-        region: Option<String>,
+        keep_age: Option<String>,
 
-        /// Prefix/path within bucket or endpoint
+        /// Keep the N newest backups of each file
         #[arg(long)]
-        prefix: Option<String>,
-    },
-
-    /// Show remote configuration
-    Show,
+        keep_count: Option<usize>,
 
-    /// Push to remote
-    Push {
-        /// Dry run (don't actually push)
+        /// Show what would be deleted without actually deleting
         #[arg(long)]
         dry_run: bool,
     },
-
-    /// Pull from remote
-    Pull,
 }
 
 #[derive(Subcommand)]
-enum DaemonCommands {
-    /// Start the daemon
-    Start,
+enum TrashCommands {
+    /// List trashed files, newest first
+    List,
 
-    /// Stop the daemon
-    Stop,
+    /// Put trashed files back; what is there now goes to the trash in turn
+    Restore {
+        /// Batch from 'trash list' to restore whole, or a file to restore
+        /// its newest trashed copy of
+        target: String,
 
-    /// Check daemon status
-    Status,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
 
-    /// Enable the daemon in configuration
-    Enable,
+    /// Delete trashed files for good
+    Empty {
+        /// Only empty batches older than duration (e.g., "30d", "7d", "2w")
+        #[arg(long)]
+        older_than: Option<String>,
 
-    /// Disable the daemon in configuration
-    Disable,
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
 }
 
 #[derive(Subcommand)]
-enum IgnoreCommands {
-    /// Add a pattern to push-ignore
-    Add {
-        /// Pattern to ignore on git push (e.g. ~/.config/karabiner/**)
+enum MachinesCommands {
+    /// List machines with their OS, profile, last sync and snapshot
+    List,
+}
+
+#[derive(Subcommand)]
+enum CollectionCommands {
+    /// List collections in precedence order
+    List,
+
+    /// Copy a collection's tracked files into its compiled directory
+    Snapshot {
+        /// Collection name
+        name: String,
+    },
+
+    /// Commit and push a collection to its remote_url
+    Push {
+        /// Collection name
+        name: String,
+
+        /// Commit message
+        #[arg(short, long)]
+        message: Option<String>,
+    },
+
+    /// Clone or update a collection from its remote_url
+    Pull {
+        /// Collection name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfileCommands {
+    /// List all profiles
+    List,
+
+    /// Create a new profile
+    Create {
+        /// Profile name
+        name: String,
+    },
+
+    /// Switch to a profile
+    Switch {
+        /// Profile name (a unique prefix or fragment is enough)
+        name: String,
+
+        /// Replace the previous profile's files in $HOME with the new profile's
+        #[arg(long)]
+        apply: bool,
+
+        /// Overwrite conflicting files without prompting (with --apply)
+        #[arg(short, long, requires = "apply")]
+        force: bool,
+    },
+
+    /// Show what switching between two profiles changes (config and files)
+    Diff {
+        /// Profile to switch from (a unique prefix or fragment is enough)
+        from: String,
+
+        /// Profile to switch to
+        to: String,
+    },
+
+    /// Remove a profile
+    Remove {
+        /// Profile name (a unique prefix or fragment is enough)
+        name: String,
+
+        /// Skip confirmation prompt
+        #[arg(short, long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum RemoteCommands {
+    /// Configure remote backend
+    Set {
+        /// Remote kind (localfs, s3, gcs, webdav)
+        kind: String,
+
+        /// Endpoint URL or path (required for localfs, webdav)
+        #[arg(long)]
+        endpoint: Option<String>,
+
+        /// Bucket name (required for s3, gcs)
+        #[arg(long)]
+        bucket: Option<String>,
+
+        /// AWS region (for s3, defaults to us-east-1)
+        #[arg(long)]
+        region: Option<String>,
+
+        /// Prefix/path within bucket or endpoint
+        #[arg(long)]
+        prefix: Option<String>,
+
+        /// HTTP(S) proxy for remote transfers and git (e.g. http://proxy:3128)
+        #[arg(long)]
+        proxy: Option<String>,
+
+        /// Maximum upload rate (e.g. 2MB/s)
+        #[arg(long, value_name = "RATE")]
+        upload_limit: Option<String>,
+
+        /// Maximum download rate (e.g. 10MB/s)
+        #[arg(long, value_name = "RATE")]
+        download_limit: Option<String>,
+
+        /// Encrypt bundles with the age key from 'secrets init' before upload
+        #[arg(long)]
+        encrypt: bool,
+
+        /// Upload bundles unencrypted again
+        #[arg(long, conflicts_with = "encrypt")]
+        no_encrypt: bool,
+
+        /// Mirror changed files instead of uploading bundles (localfs only)
+        #[arg(long)]
+        delta: bool,
+
+        /// Upload bundles again instead of mirroring files
+        #[arg(long, conflicts_with = "delta")]
+        no_delta: bool,
+    },
+
+    /// Show remote configuration
+    Show,
+
+    /// Push to remote
+    Push {
+        /// Dry run (don't actually push)
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// List the bundles on the remote, newest first
+    List,
+
+    /// Pull from remote (the latest bundle unless --rev is given)
+    Pull {
+        /// List available bundles instead of pulling
+        #[arg(long, conflicts_with_all = ["pick", "only"])]
+        list: bool,
+
+        /// Bundle to restore (name or unique prefix, see 'remote list')
+        #[arg(long, value_name = "BUNDLE", visible_alias = "rev")]
+        pick: Option<String>,
+
+        /// Only restore these paths from the bundle (comma-separated)
+        #[arg(long)]
+        only: Option<String>,
+
+        /// Check every file against the bundle's checksums before using it
+        #[arg(long)]
+        verify: bool,
+
+        /// Apply the pulled bundle to the system
+        #[arg(long, conflicts_with = "only")]
+        apply: bool,
+
+        /// With --apply, stage every file first and change nothing unless the
+        /// whole bundle can be applied
+        #[arg(long, requires = "apply")]
+        atomic: bool,
+
+        /// With --apply, overwrite local changes without prompting
+        #[arg(short, long, requires = "apply")]
+        force: bool,
+
+        /// With --apply, overwrite protected files without confirming each one
+        #[arg(long, requires = "apply")]
+        yes_protected: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum DaemonCommands {
+    /// Start the daemon
+    Start {
+        /// Run in the background and return once it is up, logging to
+        /// logs/daemon.log
+        #[arg(long)]
+        detach: bool,
+    },
+
+    /// Stop the daemon
+    Stop,
+
+    /// Check daemon status
+    Status,
+
+    /// Start the daemon at login, as a systemd user service (Linux) or
+    /// launchd agent (macOS)
+    Install {
+        /// Only start it at the next login, not now
+        #[arg(long)]
+        no_start: bool,
+    },
+
+    /// Stop the daemon service and remove it
+    Uninstall,
+
+    /// Enable the daemon in configuration
+    Enable,
+
+    /// Disable the daemon in configuration
+    Disable,
+
+    /// Show what the daemon would have done, without doing anything
+    Simulate {
+        /// How far back to replay file events (e.g. 12h, 2d)
+        #[arg(
+            long,
+            value_name = "DURATION",
+            default_value = "24h",
+            conflicts_with = "watch"
+        )]
+        since: String,
+
+        /// Watch live file events instead of replaying past ones
+        #[arg(long)]
+        watch: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum IgnoreCommands {
+    /// Add a pattern to push-ignore
+    Add {
+        /// Pattern to ignore on git push (e.g. ~/.config/karabiner/**)
         pattern: String,
     },
 
@@ -400,6 +1136,97 @@ enum IgnoreCommands {
     List,
 }
 
+#[derive(Subcommand)]
+enum HooksCommands {
+    /// List the hooks for this host, their trust and the sandbox settings
+    List,
+
+    /// Let untrusted hooks run again after reviewing pulled dotfiles
+    Trust,
+
+    /// Install pre-commit/post-merge hooks into a repository holding tracked
+    /// files, running `status` and `apply` scoped to that repository
+    Install {
+        /// Kind of hook to install
+        #[arg(value_parser = ["git"])]
+        kind: String,
+
+        /// Repository to install into
+        repo: PathBuf,
+
+        /// Replace existing hooks not installed by dotdipper (kept as <hook>.bak)
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum WorkspaceCommands {
+    /// List workspace templates
+    List,
+
+    /// Copy project files into a template (created if missing)
+    Add {
+        /// Template name
+        name: String,
+
+        /// Files or directories to add, relative to --from
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+
+        /// Project directory the paths are relative to (default: current directory)
+        #[arg(long, value_name = "DIR")]
+        from: Option<PathBuf>,
+    },
+
+    /// Stamp a template into a directory, or refresh an earlier stamp
+    Apply {
+        /// Template name
+        template: String,
+
+        /// Project directory (default: current directory)
+        dir: Option<PathBuf>,
+
+        /// Template variable, e.g. --var owner=platform-team (repeatable)
+        #[arg(long = "var", value_name = "KEY=VALUE")]
+        vars: Vec<String>,
+
+        /// Overwrite files that exist or were modified since stamping
+        #[arg(long)]
+        force: bool,
+
+        /// Show what would be written without writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Re-apply the template a directory was stamped from
+    Update {
+        /// Project directory (default: current directory)
+        dir: Option<PathBuf>,
+
+        /// Overwrite files modified since stamping
+        #[arg(long)]
+        force: bool,
+
+        /// Show what would be written without writing
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum TemplateCommands {
+    /// List the variables templates are rendered with on this machine
+    Vars,
+
+    /// Print a template as it would be applied on this machine
+    Render {
+        /// Templated file, e.g. ~/.gitconfig
+        path: String,
+    },
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
@@ -417,84 +1244,583 @@ async fn main() -> Result<()> {
         dotdipper::paths::config_file().expect("Could not determine dotdipper config path")
     });
 
-    let result = match cli.command {
-        Commands::Init { force } => cmd_init(config_path, force).await,
-        Commands::Discover {
-            write,
-            all,
-            packages,
-            target_os,
-            include_low_confidence,
-            validate,
-        } => {
-            cmd_discover(
-                config_path,
-                write,
-                all,
-                packages,
-                target_os,
-                include_low_confidence,
-                validate,
-            )
-            .await
+    // Overrides reach the library, hooks and the detached daemon through the
+    // environment, after any the caller already set
+    if !cli.overrides.is_empty() {
+        let mut set = std::env::var(cfg::SET_ENV).unwrap_or_default();
+        for assignment in &cli.overrides {
+            cfg::parse_assignment(assignment, "--set")?;
+            if !set.is_empty() && !set.ends_with('\n') {
+                set.push('\n');
+            }
+            set.push_str(assignment);
+        }
+        std::env::set_var(cfg::SET_ENV, set);
+    }
+
+    // Output level: flags win over [ui] config
+    let ui_config = cfg::load(&config_path)
+        .ok()
+        .and_then(|c| c.ui)
+        .unwrap_or_default();
+    if let Some(theme) = ui_config.theme {
+        ui::set_theme(theme);
+    }
+    let level = if cli.output_format == OutputFormat::Json {
+        ui::OutputLevel::Json
+    } else if cli.porcelain.is_some() {
+        ui::OutputLevel::Porcelain
+    } else if cli.quiet {
+        ui::OutputLevel::Quiet
+    } else if cli.verbose {
+        ui::OutputLevel::Verbose
+    } else {
+        ui_config.output.unwrap_or(ui::OutputLevel::Normal)
+    };
+    ui::set_level(level);
+
+    // State left in the single directory older versions used moves to the
+    // XDG data and cache directories
+    if !matches!(
+        cli.command,
+        Commands::Completions { .. } | Commands::Manpages { .. }
+    ) {
+        if let Err(e) = upgrade::layout::migrate() {
+            ui::warn(&format!("Failed to move state to the new layout: {:#}", e));
+        }
+    }
+
+    // The library resolves the active profile itself; hooks and the detached
+    // daemon inherit the choice
+    if let Some(profile) = &cli.profile {
+        if profile != "default" && !profiles::names()?.contains(profile) {
+            anyhow::bail!(
+                "Profile '{}' does not exist. Create it first with 'dotdipper profile create {}'",
+                profile,
+                profile
+            );
+        }
+        std::env::set_var(dotdipper::paths::PROFILE_ENV, profile);
+    }
+
+    // A mistyped key doesn't stop the config from loading, so point it out
+    // up front. These commands list the problems themselves, or print output
+    // that must not have warnings mixed in.
+    if !matches!(
+        cli.command,
+        Commands::Config { check: true, .. }
+            | Commands::Doctor { .. }
+            | Commands::Completions { .. }
+            | Commands::Manpages { .. }
+    ) && config_path.exists()
+    {
+        if let Ok(problems) = cfg::check(&config_path) {
+            report_config_problems(&problems, false);
+        }
+    }
+
+    // Send pushes queued while offline. Push commands handle the queue themselves.
+    if !matches!(
+        cli.command,
+        Commands::Init { .. }
+            | Commands::Push { .. }
+            | Commands::Remote(_)
+            | Commands::Demo { .. }
+            | Commands::SelfUpgrade { .. }
+            | Commands::Migrate { .. }
+            | Commands::Completions { .. }
+            | Commands::Manpages { .. }
+    ) {
+        flush_pending_pushes(&config_path).await;
+    }
+
+    let result = match cli.command {
+        Commands::Init { force } => cmd_init(config_path, force).await,
+        Commands::Discover {
+            write,
+            all,
+            packages,
+            target_os,
+            include_low_confidence,
+            validate,
+        } => {
+            cmd_discover(
+                config_path,
+                write,
+                all,
+                packages,
+                target_os,
+                include_low_confidence,
+                validate,
+            )
+            .await
+        }
+        Commands::Status {
+            detailed,
+            only,
+            applied,
+            watch,
+        } => {
+            cmd_status(
+                config_path,
+                detailed,
+                only,
+                applied,
+                watch,
+                cli.porcelain.is_some(),
+            )
+            .await
+        }
+        Commands::Diff {
+            detailed,
+            tool,
+            watch,
+        } => cmd_diff(config_path, detailed, tool, watch).await,
+        Commands::Apply {
+            force,
+            interactive,
+            only,
+            subset,
+            unsafe_allow_outside_home,
+            as_of,
+            revert_as_of,
+            yes_protected,
+            dry_run,
+        } => {
+            let opts = repo::apply::ApplyOpts {
+                force,
+                allow_outside_home: unsafe_allow_outside_home,
+                yes_protected,
+                dry_run,
+            };
+            cmd_apply(
+                config_path,
+                opts,
+                interactive,
+                only,
+                subset,
+                as_of,
+                revert_as_of,
+            )
+            .await
+        }
+        Commands::Secrets(subcmd) => cmd_secrets(config_path, subcmd).await,
+        Commands::Snapshot(subcmd) => cmd_snapshot(config_path, subcmd).await,
+        Commands::Profile(subcmd) => cmd_profile(config_path, subcmd).await,
+        Commands::Collection(subcmd) => cmd_collection(config_path, subcmd).await,
+        Commands::Remote(subcmd) => cmd_remote(config_path, subcmd).await,
+        Commands::Daemon(subcmd) => cmd_daemon(config_path, subcmd).await,
+        Commands::Push {
+            message,
+            force,
+            repo,
+        } => cmd_push(config_path, message, force, repo).await,
+        Commands::Pull {
+            apply,
+            force,
+            unsafe_allow_outside_home,
+            yes_protected,
+            subset,
+            verify,
+            atomic,
+            repo,
+        } => {
+            let opts = repo::apply::ApplyOpts {
+                force,
+                allow_outside_home: unsafe_allow_outside_home,
+                yes_protected,
+                dry_run: false,
+            };
+            cmd_pull(config_path, apply, opts, subset, verify, atomic, repo).await
+        }
+        Commands::Sync {
+            dry_run,
+            resolve,
+            no_push,
+            repo,
+        } => {
+            let config = cfg::load(&config_path)?;
+            let opts = dotdipper::sync::SyncOpts {
+                resolve: resolve
+                    .as_deref()
+                    .and_then(dotdipper::sync::Resolution::parse),
+                dry_run,
+                push: !no_push,
+                repo,
+            };
+            dotdipper::sync::run(&config, &opts)
+        }
+        Commands::Undo {
+            id,
+            apply,
+            force,
+            repo,
+        } => cmd_undo(config_path, id, apply, force, repo).await,
+        Commands::Install {
+            dry_run,
+            target_os,
+            unsafe_allow_outside_home,
+        } => cmd_install(config_path, dry_run, target_os, unsafe_allow_outside_home).await,
+        Commands::Bootstrap {
+            emit: _,
+            file,
+            url,
+            with_config,
+        } => {
+            let config = cfg::load(&config_path)?;
+            let opts = install::bootstrap::EmitOpts {
+                file,
+                url,
+                with_config,
+            };
+            install::bootstrap::emit(&config, &opts)
+        }
+        Commands::Doctor {
+            fix,
+            report,
+            report_file,
+            log_lines,
+        } => {
+            let report = report.then_some((report_file, log_lines));
+            cmd_doctor(config_path, fix, report).await
+        }
+        Commands::Config {
+            edit,
+            show,
+            set,
+            check,
+        } => cmd_config(config_path, edit, show, set, check).await,
+        Commands::Ignore(subcmd) => cmd_ignore(config_path, subcmd).await,
+        Commands::Hooks(subcmd) => cmd_hooks(config_path, subcmd),
+        Commands::Workspace(subcmd) => cmd_workspace(subcmd),
+        Commands::Template(subcmd) => cmd_template(config_path, subcmd),
+        Commands::Du => cmd_du(config_path).await,
+        Commands::Gc { dry_run } => {
+            let config = cfg::load(&config_path)?;
+            integrity::run_gc(&config, dry_run).map(|_| ())
+        }
+        Commands::Verify { repair } => {
+            let config = cfg::load(&config_path)?;
+            integrity::run_verify(&config, repair)
+        }
+        Commands::Backups(subcmd) => cmd_backups(config_path, subcmd),
+        Commands::Trash(subcmd) => cmd_trash(subcmd),
+        Commands::Machines(MachinesCommands::List) => machines::print_list(),
+        Commands::Packages(subcmd) => cmd_packages(config_path, subcmd),
+        Commands::History { since, json } => cmd_history(since, json),
+        Commands::Log { path, limit } => cmd_log(config_path, path, limit),
+        Commands::Restore {
+            path,
+            rev,
+            force,
+            stdout,
+        } => {
+            let config = cfg::load(&config_path)?;
+            let path = resolve::tracked_path(&config, &path)?;
+            let opts = vcs::log::RestoreOpts { rev, force, stdout };
+            vcs::log::restore(&config, &path, &opts)
+        }
+        Commands::Relink { dry_run } => links::run_relink(dry_run).map(|_| ()),
+        Commands::Demo {
+            dir,
+            no_walkthrough,
+        } => demo::run(dir, !no_walkthrough).map(|_| ()),
+        Commands::SelfUpgrade {
+            check,
+            version,
+            force,
+            path,
+        } => upgrade::self_upgrade(
+            &config_path,
+            upgrade::UpgradeOptions {
+                check,
+                version,
+                force,
+                path,
+            },
+        ),
+        Commands::Migrate { dry_run } => cmd_migrate(config_path, dry_run),
+        Commands::Completions { shell } => cmd_completions(shell),
+        Commands::Manpages { out_dir } => cmd_manpages(&out_dir),
+        Commands::Pin { path, snapshot } => cmd_pin(config_path, path, snapshot),
+        Commands::Unpin { path } => pins::unpin(&path),
+        Commands::Add { paths, system } => track::add(&config_path, &paths, system),
+        Commands::Rm { paths } => {
+            let config = cfg::load(&config_path)?;
+            let paths = paths
+                .iter()
+                .map(|p| resolve::tracked_path(&config, p))
+                .collect::<Result<Vec<_>>>()?;
+            track::remove(&config_path, &paths)
+        }
+        Commands::Import {
+            from,
+            path,
+            dry_run,
+            force,
+        } => {
+            let source = import::Source::parse(&from).context("Unknown import source")?;
+            import::import(
+                &config_path,
+                source,
+                &path,
+                &import::ImportOpts { dry_run, force },
+            )
+        }
+        Commands::Archive { path } => cmd_archive(config_path, path),
+        Commands::Unarchive { path } => archive::unarchive(&config_path, &path),
+    };
+
+    report::record_invocation(&result);
+
+    if let Err(e) = &result {
+        let args: Vec<String> = std::env::args().skip(1).collect();
+        history::Event::new(
+            history::Kind::Error,
+            format!(
+                "dotdipper {}: {}",
+                args.join(" "),
+                format!("{:#}", e).lines().next().unwrap_or_default()
+            ),
+        )
+        .record();
+    }
+
+    if let Err(e) = result {
+        ui::error(&format!("Error: {:#}", e));
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn cmd_init(config_path: PathBuf, force: bool) -> Result<()> {
+    ui::info("Initializing dotdipper...");
+    cfg::init(config_path, force)?;
+    upgrade::migrations::mark_current(&dotdipper::paths::data_dir()?)?;
+    ui::success("Dotdipper initialized successfully!");
+    ui::hint("Run 'dotdipper discover --write' to find and add dotfiles to track");
+    Ok(())
+}
+
+fn cmd_pin(config_path: PathBuf, path: Option<String>, snapshot: Option<String>) -> Result<()> {
+    match (path, snapshot) {
+        (Some(path), Some(snapshot)) => {
+            let config = cfg::load(&config_path)?;
+            let path = resolve::tracked_path(&config, &path)?;
+            pins::pin(&config, &path, &snapshot)?;
+        }
+        _ => {
+            let pins = pins::load()?;
+            if pins.is_empty() {
+                ui::info("No pins on this machine");
+            } else {
+                pins::print(&pins);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn cmd_archive(config_path: PathBuf, path: Option<String>) -> Result<()> {
+    if let Some(path) = path {
+        return archive::archive(&config_path, &path);
+    }
+    let files = archive::list()?;
+    if files.is_empty() {
+        ui::info("Nothing archived");
+    } else {
+        ui::section("Archived files");
+        for file in files {
+            println!("  {}", file);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_hooks(config_path: PathBuf, subcmd: HooksCommands) -> Result<()> {
+    match subcmd {
+        HooksCommands::List => {
+            let config = cfg::load(&config_path)?;
+            match hosts::local_hooks(&config)? {
+                Some(hooks) => hooks::print(&hooks),
+                None => ui::info("No hooks configured"),
+            }
+        }
+        HooksCommands::Trust => {
+            if hooks::trust_pulled()? {
+                ui::success("Pulled dotfiles trusted; all hooks will run again");
+            } else {
+                ui::info("Nothing to trust: no hooks are held back");
+            }
+        }
+        HooksCommands::Install {
+            kind: _,
+            repo,
+            force,
+        } => {
+            let config = cfg::load(&config_path)?;
+            for hook in hooks::git::install(&repo, &config_path, force)? {
+                ui::success(&format!("Installed {}", hook.display()));
+            }
+            let mapped = hooks::git::mapped_files(&config, &repo);
+            if mapped.is_empty() {
+                ui::warn(&format!(
+                    "No tracked files are inside {} yet; the hooks do nothing until some are",
+                    repo.display()
+                ));
+            } else {
+                ui::info(&format!(
+                    "{} tracked file(s) in this repository are kept in sync",
+                    mapped.len()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn cmd_template(config_path: PathBuf, subcmd: TemplateCommands) -> Result<()> {
+    let config = cfg::load(&config_path)?;
+    let variables = template::Variables::local(&config)?;
+    match subcmd {
+        TemplateCommands::Vars => {
+            ui::section("Template variables");
+            for (name, value) in variables.iter() {
+                println!("  {} = {}", name, value);
+            }
+        }
+        TemplateCommands::Render { path } => {
+            let home = dirs::home_dir().context("Failed to find home directory")?;
+            let rel_path = dotdipper::matching::to_home_relative(Path::new(&path), &home);
+            if !template::is_template(&config, &rel_path) {
+                anyhow::bail!(
+                    "{} is not a template. Set template = true for it under [files]",
+                    path
+                );
+            }
+            let compiled = dotdipper::paths::compiled_dir()?.join(&rel_path);
+            print!("{}", variables.render_file(&compiled)?);
+        }
+    }
+    Ok(())
+}
+
+fn cmd_workspace(subcmd: WorkspaceCommands) -> Result<()> {
+    let cwd = || std::env::current_dir().context("Failed to read the current directory");
+    match subcmd {
+        WorkspaceCommands::List => {
+            let templates = workspace::list()?;
+            if templates.is_empty() {
+                ui::info("No workspace templates");
+                ui::hint("Create one with 'dotdipper workspace add <name> <files>'");
+            } else {
+                let rows = templates
+                    .into_iter()
+                    .map(|(name, files)| vec![name, files.to_string()])
+                    .collect();
+                ui::print_table(&["TEMPLATE", "FILES"], rows);
+            }
         }
-        Commands::Status { detailed } => cmd_status(config_path, detailed).await,
-        Commands::Diff { detailed } => cmd_diff(config_path, detailed).await,
-        Commands::Apply {
+        WorkspaceCommands::Add { name, files, from } => {
+            let from = match from {
+                Some(dir) => dir,
+                None => cwd()?,
+            };
+            let added = workspace::add(&name, &files, &from)?;
+            ui::success(&format!(
+                "Added {} files to workspace template '{}'",
+                added, name
+            ));
+            ui::hint("Push to sync the template with your other machines");
+        }
+        WorkspaceCommands::Apply {
+            template,
+            dir,
+            vars,
             force,
-            interactive,
-            only,
-            unsafe_allow_outside_home,
+            dry_run,
         } => {
-            cmd_apply(
-                config_path,
-                force,
-                interactive,
-                only,
-                unsafe_allow_outside_home,
-            )
-            .await
+            let mut parsed = std::collections::BTreeMap::new();
+            for var in &vars {
+                let Some((key, value)) = var.split_once('=') else {
+                    anyhow::bail!("Invalid --var '{}': expected key=value", var);
+                };
+                parsed.insert(key.trim().to_string(), value.to_string());
+            }
+            let dir = match dir {
+                Some(dir) => dir,
+                None => cwd()?,
+            };
+            let results = workspace::apply(&template, &dir, parsed, force, dry_run)?;
+            workspace::print_results(&results, dry_run);
         }
-        Commands::Secrets(subcmd) => cmd_secrets(config_path, subcmd).await,
-        Commands::Snapshot(subcmd) => cmd_snapshot(config_path, subcmd).await,
-        Commands::Profile(subcmd) => cmd_profile(config_path, subcmd).await,
-        Commands::Remote(subcmd) => cmd_remote(config_path, subcmd).await,
-        Commands::Daemon(subcmd) => cmd_daemon(config_path, subcmd).await,
-        Commands::Push {
-            message,
+        WorkspaceCommands::Update {
+            dir,
             force,
-            repo,
-        } => cmd_push(config_path, message, force, repo).await,
-        Commands::Pull {
-            apply,
-            force,
-            unsafe_allow_outside_home,
-            repo,
-        } => cmd_pull(config_path, apply, force, unsafe_allow_outside_home, repo).await,
-        Commands::Undo { force, repo } => cmd_undo(config_path, force, repo).await,
-        Commands::Install {
             dry_run,
-            target_os,
-            unsafe_allow_outside_home,
-        } => cmd_install(config_path, dry_run, target_os, unsafe_allow_outside_home).await,
-        Commands::Doctor { fix } => cmd_doctor(config_path, fix).await,
-        Commands::Config { edit, show, set } => cmd_config(config_path, edit, show, set).await,
-        Commands::Ignore(subcmd) => cmd_ignore(config_path, subcmd).await,
-    };
-
-    if let Err(e) = result {
-        ui::error(&format!("Error: {:#}", e));
-        std::process::exit(1);
+        } => {
+            let dir = match dir {
+                Some(dir) => dir,
+                None => cwd()?,
+            };
+            let results = workspace::update(&dir, force, dry_run)?;
+            workspace::print_results(&results, dry_run);
+        }
     }
+    Ok(())
+}
 
+fn cmd_migrate(config_path: PathBuf, dry_run: bool) -> Result<()> {
+    let dirs = upgrade::migrations::StateDirs {
+        base: dotdipper::paths::data_dir()?,
+        config: config_path,
+        profile_configs: dotdipper::paths::profile_configs_dir()?,
+        cache: dotdipper::paths::cache_dir()?,
+        home: dirs::home_dir().context("Failed to find home directory")?,
+    };
+    upgrade::migrations::run_pending(&dirs, dry_run)?;
     Ok(())
 }
 
-async fn cmd_init(config_path: PathBuf, force: bool) -> Result<()> {
-    ui::info("Initializing dotdipper...");
-    cfg::init(config_path, force)?;
-    ui::success("Dotdipper initialized successfully!");
-    ui::hint("Run 'dotdipper discover --write' to find and add dotfiles to track");
+fn cmd_completions(shell: clap_complete::Shell) -> Result<()> {
+    // Generated into a buffer: writing to a closed pipe would panic
+    let mut script = Vec::new();
+    clap_complete::generate(shell, &mut Cli::command(), "dotdipper", &mut script);
+    std::io::Write::write_all(&mut std::io::stdout(), &script)
+        .context("Failed to write the completion script")?;
+    Ok(())
+}
+
+/// Write `dotdipper.1` and a `dotdipper-<command>.1` page for every
+/// subcommand, nested ones included
+fn cmd_manpages(out_dir: &Path) -> Result<()> {
+    fn generate(command: &clap::Command, out_dir: &Path, written: &mut usize) -> Result<()> {
+        for subcommand in command.get_subcommands().filter(|c| !c.is_hide_set()) {
+            generate(subcommand, out_dir, written)?;
+        }
+        let page = clap_mangen::Man::new(command.clone())
+            .generate_to(out_dir)
+            .with_context(|| format!("Failed to write a man page to {}", out_dir.display()))?;
+        ui::debug(&format!("Wrote {}", page.display()));
+        *written += 1;
+        Ok(())
+    }
+
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create {}", out_dir.display()))?;
+    // Building names subcommands `dotdipper-<command>` and gives them the
+    // global flags
+    let mut command = Cli::command().disable_help_subcommand(true);
+    command.build();
+    let mut written = 0;
+    generate(&command, out_dir, &mut written)?;
+    ui::success(&format!(
+        "Wrote {} man page(s) to {}",
+        written,
+        out_dir.display()
+    ));
+    ui::hint("Install them into a man1 directory on your MANPATH, e.g. /usr/local/share/man/man1");
     Ok(())
 }
 
@@ -523,7 +1849,7 @@ async fn cmd_discover(
         let discovery_config = install::DiscoveryConfig {
             target_os: os.clone(),
             include_low_confidence,
-            custom_mappings: std::collections::HashMap::new(),
+            custom_mappings: install::package_overrides(&config.packages, &os),
             exclude_patterns: config.exclude_patterns.clone(),
         };
 
@@ -638,49 +1964,245 @@ async fn cmd_snapshot_create(
     config_path: PathBuf,
     force: bool,
     message: Option<String>,
+    exclude: Option<String>,
+    full: bool,
 ) -> Result<()> {
     ui::info("Creating snapshot...");
-    let config = cfg::load(&config_path)?;
+    let mut config = cfg::load(&config_path)?;
+    if full {
+        config.general.incremental_snapshots = false;
+    }
+    let hooks_config = hosts::local_hooks(&config)?;
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+
+    // Files targeted at other hosts keep their previous version
+    let host = hosts::HostIdentity::local(&config)?;
+    let (mut files, mut excluded) =
+        hosts::partition_files(&config, &host, &home, repo::planned_files(&config)?);
+    for path in &excluded {
+        ui::info(&format!(
+            "Not for this host ({}): {}",
+            host.describe(),
+            path.display()
+        ));
+    }
 
-    // Run pre-snapshot hooks
-    if let Some(hooks) = &config.hooks {
-        for hook in &hooks.pre_snapshot {
-            ui::info(&format!("Running pre-snapshot hook: {}", hook));
-            run_hook(hook)?;
+    // Pinned files keep their latest stored version too
+    let pins = pins::load()?;
+    let (unpinned, pinned) = pins.partition(files, &home);
+    files = unpinned;
+    for path in &pinned {
+        if let Some(pin) = pins.pin_for(path, &home) {
+            ui::info(&format!(
+                "Pinned to snapshot {}: {}",
+                pin.snapshot_id,
+                path.display()
+            ));
+        }
+    }
+    excluded.extend(pinned);
+
+    // Templates are edited in the compiled directory, not captured from $HOME
+    let (captured, templates) =
+        template::partition(&config, &home, &dotdipper::paths::compiled_dir()?, files);
+    files = captured;
+
+    // Files excluded on the command line keep their previous version
+    if let Some(exclude) = exclude {
+        let patterns: Vec<String> = exclude
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let matcher = dotdipper::matching::PathMatcher::for_paths(&home, &patterns)?;
+        let (skip, keep): (Vec<PathBuf>, Vec<PathBuf>) =
+            files.into_iter().partition(|f| matcher.is_match(f));
+        if skip.is_empty() {
+            ui::warn("--exclude did not match any tracked file");
+        }
+        files = keep;
+        excluded.extend(skip);
+    }
+
+    // Run pre-snapshot hooks; they may veto files through DOTDIPPER_SKIP_FILE
+    if let Some(hooks_config) = &hooks_config {
+        let ctx = hooks::SnapshotContext {
+            event: "pre_snapshot",
+            files: files.clone(),
+            excluded: excluded.clone(),
+            message: message.clone(),
+            snapshot_id: None,
+        };
+        let vetoed = hooks::run_snapshot_hooks(&hooks_config.pre_snapshot, hooks_config, &ctx)?;
+        for path in &vetoed {
+            ui::warn(&format!(
+                "Skipping {} (vetoed by pre-snapshot hook)",
+                path.display()
+            ));
         }
+        files.retain(|f| !vetoed.contains(f));
+        excluded.extend(vetoed);
+    }
+
+    for path in &excluded {
+        ui::info(&format!("Excluded from this snapshot: {}", path.display()));
     }
+    excluded.extend(templates);
 
     // First, compile tracked files into the compiled directory
-    let snapshot_result = repo::snapshot(&config, force)?;
+    let snapshot_result = repo::snapshot_files(&config, force, &files, &excluded)?;
     ui::success(&format!("Compiled {} files", snapshot_result.file_count));
 
     // Then create a versioned snapshot with the message
-    snapshots::create(&config, message)?;
+    let snapshot = snapshots::create(&config, message.clone())?;
+    snapshots::store::upload_pending(&config).await?;
 
     // Run post-snapshot hooks
-    if let Some(hooks) = &config.hooks {
-        for hook in &hooks.post_snapshot {
-            ui::info(&format!("Running post-snapshot hook: {}", hook));
-            run_hook(hook)?;
-        }
+    if let Some(hooks_config) = &hooks_config {
+        let ctx = hooks::SnapshotContext {
+            event: "post_snapshot",
+            files,
+            excluded,
+            message,
+            snapshot_id: Some(snapshot.id),
+        };
+        hooks::run_snapshot_hooks(&hooks_config.post_snapshot, hooks_config, &ctx)?;
     }
 
     Ok(())
 }
 
-async fn cmd_status(config_path: PathBuf, detailed: bool) -> Result<()> {
-    ui::info("Checking status...");
+async fn cmd_status(
+    config_path: PathBuf,
+    detailed: bool,
+    only: Option<String>,
+    applied: bool,
+    watch: bool,
+    porcelain: bool,
+) -> Result<()> {
+    if watch && (porcelain || ui::is_json()) {
+        anyhow::bail!("--watch only works with the human-readable output");
+    }
     let config = cfg::load(&config_path)?;
-    let status = repo::status(&config)?;
+    let matcher = match &only {
+        Some(only_str) => {
+            let paths = resolve::tracked_paths(&config, only_str)?;
+            let home = dirs::home_dir().context("Failed to find home directory")?;
+            Some(dotdipper::matching::PathMatcher::for_paths(&home, &paths)?)
+        }
+        None => None,
+    };
+    let matcher = matcher.as_ref();
+
+    // Porcelain v1: one line per changed file and nothing else, so scripts can
+    // rely on it while the human-readable output evolves
+    if porcelain {
+        if applied {
+            anyhow::bail!("--applied has no porcelain format; use --output json");
+        }
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        for line in filtered_status(&config, matcher)?.porcelain_v1(&home) {
+            println!("{}", line);
+        }
+        return Ok(());
+    }
+    if ui::is_json() {
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let mut json = filtered_status(&config, matcher)?.to_json(&home);
+        if applied {
+            let (entries, checked) = filtered_drift(&config, matcher)?;
+            json["applied"] = diff::drift_to_json(&entries, checked);
+        }
+        return ui::print_json(&json);
+    }
+
+    if watch {
+        return daemon::watch::rerun_on_change(&config, || {
+            print_status(&config, matcher, detailed, applied)
+        });
+    }
+    print_status(&config, matcher, detailed, applied)
+}
+
+/// Changes since the last snapshot, limited to `--only` paths
+fn filtered_status(
+    config: &cfg::Config,
+    matcher: Option<&dotdipper::matching::PathMatcher>,
+) -> Result<repo::Status> {
+    let status = repo::status(config)?;
+    match matcher {
+        Some(matcher) => Ok(status.filter(matcher)),
+        None => Ok(status),
+    }
+}
+
+/// Drift of applied files, limited to `--only` paths
+fn filtered_drift(
+    config: &cfg::Config,
+    matcher: Option<&dotdipper::matching::PathMatcher>,
+) -> Result<(Vec<diff::DriftEntry>, usize)> {
+    let (mut entries, checked) = diff::applied_drift(&dotdipper::paths::compiled_dir()?, config)?;
+    if let Some(matcher) = matcher {
+        entries.retain(|e| matcher.is_match(&e.target_path));
+    }
+    Ok((entries, checked))
+}
+
+/// The human-readable `status` output
+fn print_status(
+    config: &cfg::Config,
+    matcher: Option<&dotdipper::matching::PathMatcher>,
+    detailed: bool,
+    applied: bool,
+) -> Result<()> {
+    ui::info("Checking status...");
+
+    if let Some(record) = snapshots::load_as_of()? {
+        ui::warn(&format!(
+            "{} file(s) are applied as of snapshot {}; run 'dotdipper apply --revert-as-of' to restore",
+            record.files.len(),
+            record.snapshot_id
+        ));
+    }
+    let status = filtered_status(config, matcher)?;
+
+    let pins = pins::load()?;
+    if !pins.is_empty() {
+        ui::info(&format!(
+            "{} path(s) pinned to older snapshots on this machine:",
+            pins.pins.len()
+        ));
+        pins::print(&pins);
+    }
+
+    let machines = machines::load(&dotdipper::paths::compiled_dir()?)?;
+    let behind = machines::behind(&machines);
+    if !behind.is_empty() {
+        let latest = machines::latest(&machines).and_then(|m| m.snapshot.clone());
+        ui::warn(&format!(
+            "{} of {} machine(s) behind the latest snapshot{}: {}",
+            behind.len(),
+            machines.len(),
+            latest.map(|id| format!(" ({})", id)).unwrap_or_default(),
+            behind.join(", ")
+        ));
+        ui::hint("See them with: dotdipper machines list");
+    }
 
     if status.is_clean() {
         ui::success("No changes detected - everything is up to date!");
     } else {
-        ui::warn(&format!(
-            "Changes detected: {} modified, {} added, {} deleted",
+        let renamed = if status.renamed.is_empty() {
+            String::new()
+        } else {
+            format!(", {} renamed", status.renamed.len())
+        };
+        ui::notice(&format!(
+            "Changes detected: {} modified, {} added, {} deleted{}",
             status.modified.len(),
             status.added.len(),
-            status.deleted.len()
+            status.deleted.len(),
+            renamed
         ));
 
         if detailed {
@@ -688,9 +2210,32 @@ async fn cmd_status(config_path: PathBuf, detailed: bool) -> Result<()> {
         }
     }
 
+    if applied {
+        let (entries, checked) = filtered_drift(config, matcher)?;
+        diff::print_drift(&entries, checked);
+    }
+
     Ok(())
 }
 
+/// Best-effort flush of the offline push queue; never fails the command
+async fn flush_pending_pushes(config_path: &Path) {
+    if !offline::load_pending().is_ok_and(|p| !p.is_empty()) {
+        return;
+    }
+    let Ok(config) = cfg::load(config_path) else {
+        return;
+    };
+    // One attempt only: this runs in the background of an unrelated command
+    let policy = offline::RetryPolicy {
+        retries: 0,
+        ..offline::RetryPolicy::from_config(&config)
+    };
+    if let Err(e) = offline::flush(&config, &policy).await {
+        ui::debug(&format!("Could not flush queued pushes: {:#}", e));
+    }
+}
+
 async fn cmd_push(
     config_path: PathBuf,
     message: Option<String>,
@@ -699,12 +2244,29 @@ async fn cmd_push(
 ) -> Result<()> {
     ui::info("Pushing to GitHub...");
     let config = cfg::load(&config_path)?;
+    let hooks = hosts::local_hooks(&config)?;
+    if let Some(hooks) = &hooks {
+        hooks::run_all("pre-push", &hooks.pre_push, hooks, &[])?;
+    }
 
     // Create snapshot first
     repo::snapshot(&config, false)?;
 
     // Push to GitHub
-    let effective_repo = vcs::push(&config, message, force, repo.as_deref())?;
+    let target = offline::PushTarget::GitHub {
+        message: message.clone(),
+        repo: repo.clone(),
+    };
+    let effective_repo = match vcs::push(&config, message, force, repo.as_deref()) {
+        Ok(effective_repo) => {
+            offline::clear(&target)?;
+            effective_repo
+        }
+        Err(e) => {
+            offline::queue_if_offline(&config, target, &e);
+            return Err(e);
+        }
+    };
 
     if repo.is_some() && config.github.repo_name.is_none() {
         cfg::set_config_value(&config_path, "github.repo_name", &effective_repo)?;
@@ -714,6 +2276,9 @@ async fn cmd_push(
         ));
     }
 
+    if let Some(hooks) = &hooks {
+        hooks::run_all("post-push", &hooks.post_push, hooks, &[])?;
+    }
     ui::success("Successfully pushed to GitHub!");
     Ok(())
 }
@@ -721,14 +2286,27 @@ async fn cmd_push(
 async fn cmd_pull(
     config_path: PathBuf,
     apply: bool,
-    force: bool,
-    allow_outside_home: bool,
+    opts: repo::apply::ApplyOpts,
+    subset: Option<String>,
+    verify: bool,
+    atomic: bool,
     repo: Option<String>,
 ) -> Result<()> {
     ui::info("Pulling from GitHub...");
     let config = cfg::load(&config_path)?;
+    if let Some(name) = &subset {
+        config.subset(name)?;
+    }
 
+    let hooks = hosts::local_hooks(&config)?;
+    if let Some(hooks) = &hooks {
+        hooks::run_all("pre-pull", &hooks.pre_pull, hooks, &[])?;
+    }
     let effective_repo = vcs::pull(&config, repo.as_deref())?;
+    hooks::mark_pulled()?;
+    if let Some(hooks) = &hooks {
+        hooks::run_all("post-pull", &hooks.post_pull, hooks, &[])?;
+    }
 
     if repo.is_some() && config.github.repo_name.is_none() {
         cfg::set_config_value(&config_path, "github.repo_name", &effective_repo)?;
@@ -740,6 +2318,11 @@ async fn cmd_pull(
 
     ui::success("Successfully pulled from GitHub!");
 
+    if verify {
+        vcs::verify_repo(&dotdipper::paths::compiled_dir()?)?;
+        ui::success("Verified the integrity of the pulled repository");
+    }
+
     if apply {
         ui::info("Applying changes to system...");
         let compiled_path = dotdipper::paths::compiled_dir()?;
@@ -747,12 +2330,25 @@ async fn cmd_pull(
 
         if manifest_path.exists() {
             let manifest = crate::hash::Manifest::load(&manifest_path)?;
-            let opts = repo::apply::ApplyOpts {
-                force,
-                allow_outside_home,
+            let layered = collections::stack(vec![(
+                collections::MAIN.to_string(),
+                compiled_path,
+                manifest,
+            )]);
+            let layered = pins::apply_to_layers(&config, layered, &pins::load()?)?;
+            let selected = match &subset {
+                Some(name) => Some(subset_files(&layered, &config, name)?),
+                None => None,
             };
-            repo::apply::apply(&compiled_path, &manifest, &config, &opts)?;
-            ui::success("Changes applied successfully!");
+            let actions = if atomic {
+                apply_layers_atomic(&layered, selected.as_deref(), &config, &opts, None, || {
+                    Ok(())
+                })?
+            } else {
+                apply_layers(&layered, selected.as_deref(), &config, &opts)?.1
+            };
+            run_on_change_hooks(&config, &actions)?;
+            ui::done("Changes applied successfully!");
         } else {
             ui::warn("No manifest found. Run 'dotdipper snapshot' first.");
         }
@@ -763,11 +2359,44 @@ async fn cmd_pull(
     Ok(())
 }
 
-async fn cmd_undo(config_path: PathBuf, force: bool, repo: Option<String>) -> Result<()> {
-    ui::info("Undoing the last pushed commit...");
+async fn cmd_undo(
+    config_path: PathBuf,
+    id: Option<u64>,
+    apply: bool,
+    force: bool,
+    repo: Option<String>,
+) -> Result<()> {
+    if apply {
+        return repo::journal::undo(None, force);
+    }
+    let commit = match id {
+        Some(id) => {
+            let entry = history::find(id)?;
+            let commit = match (entry.kind, entry.reference) {
+                (history::Kind::Push, Some(commit)) => commit,
+                (history::Kind::Apply, Some(journal)) => {
+                    return repo::journal::undo(Some(&journal), force);
+                }
+                (history::Kind::Apply, None) => {
+                    anyhow::bail!("Apply #{} has no journal to undo", id)
+                }
+                (kind, _) => anyhow::bail!(
+                    "History entry #{} is a {}, not a push or an apply",
+                    id,
+                    kind
+                ),
+            };
+            ui::info(&format!("Undoing push #{} ({})...", id, commit));
+            Some(commit)
+        }
+        None => {
+            ui::info("Undoing the last pushed commit...");
+            None
+        }
+    };
     let config = cfg::load(&config_path)?;
 
-    let effective_repo = vcs::undo_last_push(&config, force, repo.as_deref())?;
+    let effective_repo = vcs::undo_push(&config, force, repo.as_deref(), commit.as_deref())?;
 
     if repo.is_some() && config.github.repo_name.is_none() {
         cfg::set_config_value(&config_path, "github.repo_name", &effective_repo)?;
@@ -777,7 +2406,10 @@ async fn cmd_undo(config_path: PathBuf, force: bool, repo: Option<String>) -> Re
         ));
     }
 
-    ui::success("Successfully reverted the last pushed commit!");
+    ui::success(match id {
+        Some(_) => "Successfully reverted the pushed commit!",
+        None => "Successfully reverted the last pushed commit!",
+    });
     Ok(())
 }
 
@@ -827,7 +2459,7 @@ async fn cmd_install(
         let discovery_config = install::DiscoveryConfig {
             target_os: os.clone(),
             include_low_confidence: false,
-            custom_mappings: std::collections::HashMap::new(),
+            custom_mappings: install::package_overrides(&config.packages, &os),
             exclude_patterns: config.exclude_patterns.clone(),
         };
 
@@ -855,143 +2487,678 @@ async fn cmd_install(
                     println!("  {} -> {}", binary, package);
                 }
             }
-            if result.packages.len() > 10 {
-                println!("  ... and {} more", result.packages.len() - 10);
+            if result.packages.len() > 10 {
+                println!("  ... and {} more", result.packages.len() - 10);
+            }
+            println!();
+        }
+    }
+
+    // Only install what is missing; packages for another OS can't be checked here
+    let mut packages = install::host_packages(&config, &os)?;
+    if !packages.is_empty() && os == install::detect_os() {
+        let overrides = install::package_overrides(&config.packages, &os);
+        let validation = install::validators::validate_configured(&packages, &os, &overrides);
+        ui::info(&format!(
+            "{} of {} package(s) already installed",
+            validation.installed.len(),
+            packages.len()
+        ));
+        packages.retain(|p| !validation.installed.contains(p));
+        if !packages.is_empty() {
+            ui::section("Packages to install:");
+            for package in &packages {
+                if validation.unknown.contains(package) {
+                    println!("  {} {}", package, "(could not check)".dimmed());
+                } else {
+                    println!("  {}", package);
+                }
+            }
+            println!();
+        }
+    }
+
+    let scripts = install::generate_scripts(&config, &os, &packages)?;
+
+    ui::success(&format!("Generated {} installation scripts", scripts.len()));
+
+    // Show script locations
+    for script in &scripts {
+        ui::info(&format!("  {}: {}", script.name, script.path.display()));
+    }
+
+    if !dry_run {
+        ui::info("Running installation scripts...");
+        let sandbox = config.hooks.as_ref().and_then(|h| h.sandbox.as_ref());
+        install::run_scripts(&scripts, sandbox)?;
+
+        // Apply dotfiles after installation
+        ui::info("Applying dotfiles...");
+        let compiled_path = dotdipper::paths::compiled_dir()?;
+        let manifest_path = dotdipper::paths::manifest_file()?;
+
+        if compiled_path.exists() && manifest_path.exists() {
+            let manifest = crate::hash::Manifest::load(&manifest_path)?;
+            let opts = repo::apply::ApplyOpts {
+                force: false,
+                allow_outside_home,
+                yes_protected: false,
+                dry_run: false,
+            };
+            repo::apply::apply(&compiled_path, &manifest, &config, &opts)?;
+        }
+
+        ui::success("Installation completed successfully!");
+    } else {
+        ui::hint("Remove --dry-run to execute the installation scripts");
+    }
+
+    Ok(())
+}
+
+fn cmd_history(since: Option<String>, json: bool) -> Result<()> {
+    let since = since.as_deref().map(history::parse_since).transpose()?;
+    let entries: Vec<history::Entry> = history::load()?
+        .into_iter()
+        .filter(|e| since.is_none_or(|since| e.time >= since))
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+    if entries.is_empty() {
+        ui::info("No history yet");
+        return Ok(());
+    }
+
+    let rows = entries
+        .into_iter()
+        .map(|e| {
+            vec![
+                e.id.to_string(),
+                e.time
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string(),
+                e.kind.to_string(),
+                e.summary,
+                e.files.map_or_else(String::new, |n| n.to_string()),
+                e.reference.unwrap_or_default(),
+            ]
+        })
+        .collect();
+    ui::print_table(&["ID", "TIME", "KIND", "SUMMARY", "FILES", "REF"], rows);
+    Ok(())
+}
+
+fn cmd_log(config_path: PathBuf, path: Option<String>, limit: usize) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let path = match path {
+        Some(p) => {
+            let p = resolve::tracked_path(&cfg::load(&config_path)?, &p)?;
+            Some(vcs::log::resolve_path(&p, &home)?)
+        }
+        None => None,
+    };
+    let changes = vcs::log::changes(&dotdipper::paths::compiled_dir()?, path.as_deref(), limit)?;
+    vcs::log::print(&changes, path.as_deref())
+}
+
+async fn cmd_du(config_path: PathBuf) -> Result<()> {
+    let config = cfg::load(&config_path)?;
+    let report = usage::measure(&config)?;
+    usage::print_report(&config, &report)
+}
+
+fn cmd_packages(config_path: PathBuf, subcmd: PackagesCommands) -> Result<()> {
+    match subcmd {
+        PackagesCommands::Discover {
+            target_os,
+            min_confidence,
+            to,
+            yes,
+            dry_run,
+        } => install::packages::discover(
+            &config_path,
+            &install::packages::DiscoverOpts {
+                target_os: target_os.unwrap_or_else(install::detect_os),
+                min_confidence: min_confidence.parse()?,
+                section: to,
+                yes,
+                dry_run,
+            },
+        ),
+        PackagesCommands::List => install::packages::print_list(&cfg::load(&config_path)?.packages),
+        PackagesCommands::Export { format, file } => {
+            let config = cfg::load(&config_path)?;
+            let list = install::packages::export(&config, format.parse()?)?;
+            match file {
+                Some(path) => {
+                    std::fs::write(&path, list)
+                        .with_context(|| format!("Failed to write {}", path.display()))?;
+                    ui::success(&format!("Wrote {}", path.display()));
+                }
+                None => print!("{}", list),
+            }
+            Ok(())
+        }
+        PackagesCommands::Import { file, format, to } => install::packages::import(
+            &config_path,
+            &file,
+            format.as_deref().map(str::parse).transpose()?,
+            to.as_deref(),
+        ),
+        PackagesCommands::Add { packages, to } => {
+            let added = install::packages::add(&config_path, &to, &packages)?;
+            if added.is_empty() {
+                ui::info(&format!("Already in [packages] {}", to));
+            } else {
+                ui::success(&format!("Added to [packages] {}: {}", to, added.join(", ")));
             }
-            println!();
+            Ok(())
+        }
+        PackagesCommands::Remove { packages, from } => {
+            match install::packages::remove(&config_path, from.as_deref(), &packages)? {
+                0 => ui::warn("None of these packages are in [packages]"),
+                n => ui::success(&format!("Removed {} package(s)", n)),
+            }
+            Ok(())
         }
     }
+}
 
-    let scripts = install::generate_scripts(&config, &os)?;
-
-    ui::success(&format!("Generated {} installation scripts", scripts.len()));
+fn cmd_backups(config_path: PathBuf, subcmd: BackupsCommands) -> Result<()> {
+    let config = cfg::load(&config_path)?;
+    match subcmd {
+        BackupsCommands::List => backups::print_list(&config),
+        BackupsCommands::Restore { path, force } => backups::restore(&config, &path, force),
+        BackupsCommands::Prune {
+            keep_age,
+            keep_count,
+            dry_run,
+        } => backups::prune(
+            &config,
+            &backups::PruneOpts {
+                keep_count,
+                keep_age,
+                dry_run,
+            },
+        ),
+    }
+}
 
-    // Show script locations
-    for script in &scripts {
-        ui::info(&format!("  {}: {}", script.name, script.path.display()));
+fn cmd_trash(subcmd: TrashCommands) -> Result<()> {
+    match subcmd {
+        TrashCommands::List => trash::print_list(),
+        TrashCommands::Restore { target, force } => trash::restore(&target, force),
+        TrashCommands::Empty { older_than, force } => trash::empty(older_than.as_deref(), force),
     }
+}
 
-    if !dry_run {
-        ui::info("Running installation scripts...");
-        install::run_scripts(&scripts)?;
+async fn cmd_doctor(
+    config_path: PathBuf,
+    fix: bool,
+    report: Option<(Option<PathBuf>, usize)>,
+) -> Result<()> {
+    ui::info("Running diagnostics...");
 
-        // Apply dotfiles after installation
-        ui::info("Applying dotfiles...");
-        let compiled_path = dotdipper::paths::compiled_dir()?;
-        let manifest_path = dotdipper::paths::manifest_file()?;
+    let config = cfg::load(&config_path).unwrap_or_default();
 
-        if compiled_path.exists() && manifest_path.exists() {
-            let manifest = crate::hash::Manifest::load(&manifest_path)?;
-            let opts = repo::apply::ApplyOpts {
-                force: false,
-                allow_outside_home,
+    let checks = doctor::run_checks(&config, &config_path);
+
+    let outcomes: Vec<(String, String)> = checks
+        .iter()
+        .map(|check| {
+            let outcome = match &check.result {
+                Ok(_) => "ok".to_string(),
+                Err(e) => format!("FAILED: {:#}", e),
             };
-            repo::apply::apply(&compiled_path, &manifest, &config, &opts)?;
-        }
+            (check.name.to_string(), outcome)
+        })
+        .collect();
 
-        ui::success("Installation completed successfully!");
-    } else {
-        ui::hint("Remove --dry-run to execute the installation scripts");
+    if ui::is_json() {
+        let checks_json: Vec<serde_json::Value> = checks
+            .iter()
+            .map(|check| {
+                serde_json::json!({
+                    "id": check.id,
+                    "name": check.name,
+                    "ok": check.result.is_ok(),
+                    "error": check.result.as_ref().err().map(|e| format!("{:#}", e)),
+                    "details": check.details,
+                    "fix": check.fix.as_ref().map(|f| f.describe()),
+                })
+            })
+            .collect();
+        ui::print_json(&serde_json::json!({
+            "ok": checks.iter().all(|check| check.result.is_ok()),
+            "checks": checks_json,
+        }))?;
+        if let Some((output, log_lines)) = report {
+            report::generate(&config_path, &config, &outcomes, output, log_lines)?;
+        }
+        return Ok(());
     }
 
-    Ok(())
-}
-
-async fn cmd_doctor(config_path: PathBuf, fix: bool) -> Result<()> {
-    ui::info("Running diagnostics...");
-
-    let issues = vec![
-        ("Git installed", vcs::check_git()),
-        ("GitHub CLI installed", vcs::check_gh()),
-        ("Age encryption tools installed", secrets::check_age()),
-        ("Config file exists", cfg::check_exists(&config_path)),
-        ("Manifest valid", repo::check_manifest(&config_path)),
-    ];
-
-    let mut has_issues = false;
-    for (check, result) in issues {
-        match result {
-            Ok(_) => ui::success(&format!("✓ {}", check)),
-            Err(e) => {
-                has_issues = true;
-                ui::error(&format!("✗ {}: {}", check, e));
-                if fix {
-                    ui::info("  Attempting to fix...");
-                    // Implement fix logic here
+    let mut remaining = 0;
+    let mut fixable = 0;
+    for check in &checks {
+        let Err(e) = &check.result else {
+            ui::success(&format!("✓ {}", check.name));
+            continue;
+        };
+        ui::error(&format!("✗ {}: {}", check.name, e));
+        match (&check.fix, fix) {
+            (Some(repair), true) => {
+                ui::info(&format!("  Attempting to fix: {}...", repair.describe()));
+                match doctor::apply(&config, repair) {
+                    Ok(done) => ui::success(&format!("  {}", done)),
+                    Err(e) => {
+                        remaining += 1;
+                        ui::warn(&format!("  Could not fix: {:#}", e));
+                    }
                 }
             }
+            (Some(_), false) => {
+                remaining += 1;
+                fixable += 1;
+            }
+            (None, _) => remaining += 1,
         }
     }
 
-    if !has_issues {
+    if checks.iter().all(|check| check.result.is_ok()) {
         ui::success("All checks passed!");
-    } else {
-        ui::hint("Install missing tools:");
-        ui::hint("  macOS: brew install age git gh");
-        ui::hint("  Linux: apt install age git gh (or equivalent)");
+    } else if remaining == 0 {
+        ui::success("Fixed every issue found");
+    } else if fixable > 0 {
+        ui::hint(&format!(
+            "Run 'dotdipper doctor --fix' to repair {} of them",
+            fixable
+        ));
+    }
+
+    if let Some((output, log_lines)) = report {
+        ui::section("Bug report");
+        report::generate(&config_path, &config, &outcomes, output, log_lines)?;
     }
 
     Ok(())
 }
 
-async fn cmd_diff(config_path: PathBuf, detailed: bool) -> Result<()> {
+async fn cmd_diff(
+    config_path: PathBuf,
+    detailed: bool,
+    tool: Option<String>,
+    watch: bool,
+) -> Result<()> {
+    let detailed = detailed || tool.is_some();
+    if watch {
+        if ui::is_json() {
+            anyhow::bail!("--watch only works with the human-readable output");
+        }
+        let config = cfg::load(&config_path)?;
+        return daemon::watch::rerun_on_change(&config, || {
+            show_diff(&config_path, detailed, tool.as_deref(), true)
+        });
+    }
+    show_diff(&config_path, detailed, tool.as_deref(), false)
+}
+
+/// The `diff` output, as JSON or colored text. When `watching`, file diffs
+/// are not paged so the output can be redrawn.
+fn show_diff(config_path: &Path, detailed: bool, tool: Option<&str>, watching: bool) -> Result<()> {
     ui::info("Computing diff...");
-    let config = cfg::load(&config_path)?;
+    let config = cfg::load(config_path)?;
+    let view = match detailed {
+        true => {
+            let mut view = diff::DiffView::new(&config, tool)?;
+            if watching {
+                view.pager = None;
+            }
+            Some(view)
+        }
+        false => None,
+    };
 
     let compiled_path = dotdipper::paths::compiled_dir()?;
     let manifest_path = dotdipper::paths::manifest_file()?;
 
-    if !manifest_path.exists() {
+    if !manifest_path.exists() && config.collections.is_empty() {
+        if ui::is_json() {
+            anyhow::bail!("No manifest found. Run 'dotdipper pull' or 'dotdipper snapshot' first.");
+        }
         ui::warn("No manifest found. Run 'dotdipper pull' or 'dotdipper snapshot' first.");
         return Ok(());
     }
 
-    let manifest = crate::hash::Manifest::load(&manifest_path)?;
-    let _entries = diff::diff(&compiled_path, &manifest, &config, detailed)?;
+    let manifest = if manifest_path.exists() {
+        crate::hash::Manifest::load(&manifest_path)?
+    } else {
+        crate::hash::Manifest::new()
+    };
+    let layered = collections::layered(&config, &compiled_path, manifest)?;
+    let layered = pins::apply_to_layers(&config, layered, &pins::load()?)?;
+    if ui::is_json() {
+        let entries = layered_entries(&layered, &config)?;
+        return ui::print_json(&diff::to_json(&entries, &config));
+    }
+    let entries = layered_diff(&layered, &config, view.as_ref())?;
+    if ui::level() == ui::OutputLevel::Quiet {
+        ui::success(&diff::result_line(&entries));
+    }
 
     Ok(())
 }
 
+/// Diff entries of every layer (main store and collections), sorted by path
+fn layered_entries(
+    layered: &collections::Layered,
+    config: &cfg::Config,
+) -> Result<Vec<diff::DiffEntry>> {
+    let mut entries = Vec::new();
+    for layer in &layered.layers {
+        entries.extend(diff::compute(&layer.compiled, &layer.manifest, config)?);
+    }
+    entries.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+    Ok(entries)
+}
+
+/// Diff every layer (main store and collections) as one combined view
+fn layered_diff(
+    layered: &collections::Layered,
+    config: &cfg::Config,
+    view: Option<&diff::DiffView>,
+) -> Result<Vec<diff::DiffEntry>> {
+    ui::info("Computing differences...");
+    let entries = layered_entries(layered, config)?;
+
+    diff::print_diff_summary(&entries, config, view)?;
+    collections::print_layers(layered);
+    Ok(entries)
+}
+
+/// Files of all layers that belong to a named `[subsets]` entry
+fn subset_files(
+    layered: &collections::Layered,
+    config: &cfg::Config,
+    name: &str,
+) -> Result<Vec<PathBuf>> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let matcher = dotdipper::matching::PathMatcher::for_paths(&home, config.subset(name)?)?;
+    let files: Vec<PathBuf> = layered
+        .layers
+        .iter()
+        .flat_map(|l| l.manifest.files.keys())
+        .filter(|path| matcher.is_match(path))
+        .cloned()
+        .collect();
+    ui::info(&format!("Subset '{}': {} files", name, files.len()));
+    Ok(files)
+}
+
+/// Apply each layer's files (only `selected` ones, if given) from that
+/// layer's own directory. Returns the applied files and the actions taken.
+fn apply_layers(
+    layered: &collections::Layered,
+    selected: Option<&[PathBuf]>,
+    config: &cfg::Config,
+    opts: &repo::apply::ApplyOpts,
+) -> Result<(hash::Manifest, Vec<repo::apply::AppliedAction>)> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let previous = repo::apply::load_applied()?;
+    let mut applied = hash::Manifest::new();
+    let mut actions = Vec::new();
+    let mut moved_from = Vec::new();
+    for layer in &layered.layers {
+        let mut layer_manifest = hash::Manifest::new();
+        for (path, hash) in &layer.manifest.files {
+            if selected.is_none_or(|s| s.contains(path)) {
+                layer_manifest.add_file(hash.clone());
+            }
+        }
+        for (path, link) in &layer.manifest.links {
+            if selected.is_none_or(|s| s.contains(path)) {
+                layer_manifest.links.insert(path.clone(), link.clone());
+            }
+        }
+        for (path, dir) in &layer.manifest.empty_dirs {
+            if selected.is_none_or(|s| s.contains(path)) {
+                layer_manifest.empty_dirs.insert(path.clone(), dir.clone());
+            }
+        }
+        if layer_manifest.is_empty() {
+            continue;
+        }
+
+        // Follow renames made on another machine before laying files down
+        let moves = repo::apply::pending_moves(&previous, &layer_manifest, &home);
+        let move_actions = repo::apply::apply_moves(&moves, config, opts)?;
+        for ((old, _), action) in moves.into_iter().zip(&move_actions) {
+            if action.mode == repo::apply::AppliedMode::Moved {
+                moved_from.push(old);
+            }
+        }
+        actions.extend(move_actions);
+
+        actions.extend(repo::apply::apply(
+            &layer.compiled,
+            &layer_manifest,
+            config,
+            opts,
+        )?);
+        applied.files.extend(layer_manifest.files);
+    }
+    if opts.dry_run {
+        return Ok((applied, actions));
+    }
+    repo::apply::record_applied(&applied, &moved_from)?;
+    record_apply(&actions, "Applied");
+    Ok((applied, actions))
+}
+
+/// Add an apply to the history
+fn record_apply(actions: &[repo::apply::AppliedAction], verb: &str) {
+    let changed = actions
+        .iter()
+        .filter(|a| a.skipped_reason.is_none())
+        .count();
+    let mut event = history::Event::new(
+        history::Kind::Apply,
+        format!("{} {} of {} file(s)", verb, changed, actions.len()),
+    )
+    .files(changed);
+    if let Some(journal) = repo::journal::current() {
+        event = event.reference(journal);
+    }
+    event.record();
+}
+
+/// Apply `layered` all or nothing (see `repo::staged`), following renames
+/// like `apply_layers`. Files of a layer served from `relocated.0` are linked
+/// to `relocated.1`, where `install` puts them just before they are swapped
+/// into place.
+fn apply_layers_atomic(
+    layered: &collections::Layered,
+    selected: Option<&[PathBuf]>,
+    config: &cfg::Config,
+    opts: &repo::apply::ApplyOpts,
+    relocated: Option<(&Path, &Path)>,
+    install: impl FnOnce() -> Result<()>,
+) -> Result<Vec<repo::apply::AppliedAction>> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let previous = repo::apply::load_applied()?;
+    let mut staged = repo::staged::Staged::new();
+    let mut applied = hash::Manifest::new();
+    for layer in &layered.layers {
+        let mut layer_manifest = hash::Manifest::new();
+        for (path, hash) in &layer.manifest.files {
+            if selected.is_none_or(|s| s.contains(path)) {
+                layer_manifest.add_file(hash.clone());
+            }
+        }
+        for (path, link) in &layer.manifest.links {
+            if selected.is_none_or(|s| s.contains(path)) {
+                layer_manifest.links.insert(path.clone(), link.clone());
+            }
+        }
+        for (path, dir) in &layer.manifest.empty_dirs {
+            if selected.is_none_or(|s| s.contains(path)) {
+                layer_manifest.empty_dirs.insert(path.clone(), dir.clone());
+            }
+        }
+        let installed = match relocated {
+            Some((from, to)) if layer.compiled == from => to,
+            _ => layer.compiled.as_path(),
+        };
+        let moves = repo::apply::pending_moves(&previous, &layer_manifest, &home);
+        staged.add_moves(moves, opts)?;
+        staged.add(&layer.compiled, installed, &layer_manifest, config, opts)?;
+        applied.files.extend(layer_manifest.files);
+    }
+
+    ui::info(&format!(
+        "Staged {} file(s); swapping them into place",
+        staged.len()
+    ));
+    install()?;
+    let actions = staged.commit(config)?;
+    let moved_from: Vec<PathBuf> = actions
+        .iter()
+        .filter(|a| a.mode == repo::apply::AppliedMode::Moved)
+        .filter_map(|a| a.source.strip_prefix(&home).ok().map(Path::to_path_buf))
+        .collect();
+    repo::apply::record_applied(&applied, &moved_from)?;
+    record_apply(&actions, "Atomically applied");
+    Ok(actions)
+}
+
 async fn cmd_apply(
     config_path: PathBuf,
-    force: bool,
+    opts: repo::apply::ApplyOpts,
     interactive: bool,
     only: Option<String>,
-    allow_outside_home: bool,
+    subset: Option<String>,
+    as_of: Option<String>,
+    revert_as_of: bool,
 ) -> Result<()> {
-    ui::info("Applying dotfiles...");
     let config = cfg::load(&config_path)?;
+    let subset_paths = match &subset {
+        Some(name) => Some(config.subset(name)?.to_vec()),
+        None => None,
+    };
 
-    let compiled_path = dotdipper::paths::compiled_dir()?;
-    let manifest_path = dotdipper::paths::manifest_file()?;
-
-    if !manifest_path.exists() {
-        ui::warn("No manifest found. Run 'dotdipper pull' first.");
-        return Ok(());
+    if revert_as_of {
+        return snapshots::revert_as_of(&config, opts.force, opts.allow_outside_home);
     }
 
-    let manifest = crate::hash::Manifest::load(&manifest_path)?;
+    ui::info("Applying dotfiles...");
+
+    let (layered, config) = if let Some(id) = &as_of {
+        snapshots::store::ensure_local(&config, id).await?;
+        let id = resolve::snapshot_id(&config, id)?;
+        let (snapshot_dir, manifest) = snapshots::snapshot_manifest(&config, &id)?;
+        ui::info(&format!(
+            "Using files from snapshot {} (compiled/ and manifest are left untouched)",
+            id
+        ));
+        let layered = collections::stack(vec![(id, snapshot_dir, manifest)]);
+        (layered, snapshots::as_of_config(&config))
+    } else {
+        let compiled_path = dotdipper::paths::compiled_dir()?;
+        let manifest_path = dotdipper::paths::manifest_file()?;
+
+        if let Some(count) = snapshots::adopt_compiled(&compiled_path, &manifest_path)? {
+            ui::info(&format!(
+                "No manifest found; created one for the {} file(s) in compiled/",
+                count
+            ));
+        }
+        if !manifest_path.exists() && config.collections.is_empty() {
+            ui::warn("No manifest found. Run 'dotdipper pull' first.");
+            return Ok(());
+        }
+
+        let manifest = if manifest_path.exists() {
+            crate::hash::Manifest::load(&manifest_path)?
+        } else {
+            crate::hash::Manifest::new()
+        };
+        let layered = collections::layered(&config, &compiled_path, manifest)?;
+        (
+            pins::apply_to_layers(&config, layered, &pins::load()?)?,
+            config,
+        )
+    };
 
     // Get diff entries
-    let mut entries = diff::diff(&compiled_path, &manifest, &config, false)?;
+    let mut entries = layered_diff(&layered, &config, None)?;
+
+    // Files already in place count as applied, so later renames can find them
+    let mut in_place = hash::Manifest::new();
+    for entry in entries
+        .iter()
+        .filter(|e| e.status == diff::DiffStatus::Identical)
+    {
+        if let Some(file) = layered
+            .layers
+            .iter()
+            .find_map(|l| l.manifest.get_file(&entry.rel_path))
+        {
+            in_place.add_file(file.clone());
+        }
+    }
+    if !opts.dry_run {
+        repo::apply::record_applied(&in_place, &[])?;
+    }
+
+    // Links and empty directories go along unless the files are narrowed down
+    let narrowed = only.is_some() || subset.is_some();
 
     // Filter by paths if --only specified
     if let Some(only_str) = only {
-        let paths: Vec<String> = only_str.split(',').map(|s| s.trim().to_string()).collect();
+        let paths = resolve::tracked_paths(&config, &only_str)?;
         entries = diff::filter_by_paths(entries, &paths)?;
         ui::info(&format!("Filtered to {} matching files", entries.len()));
     }
 
+    // Restrict to a named subset if --subset specified
+    if let (Some(name), Some(paths)) = (&subset, &subset_paths) {
+        entries = diff::filter_by_paths(entries, paths)?;
+        ui::info(&format!(
+            "Subset '{}': {} matching files",
+            name,
+            entries.len()
+        ));
+    }
+
     // Interactive selection if requested
     let selected_paths = if interactive {
         diff::interactive_select(&entries)?
     } else {
-        // Apply all non-identical files
-        entries
+        // Apply all non-identical files, and identical ones whose
+        // permissions or ownership the config sets or whose directory is
+        // still to be linked as a whole
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let mut selected: Vec<PathBuf> = entries
             .iter()
-            .filter(|e| e.status != diff::DiffStatus::Identical)
+            .filter(|e| {
+                e.status != diff::DiffStatus::Identical
+                    || config
+                        .file_override(&e.rel_path)
+                        .is_some_and(|o| o.sets_perms())
+                    || repo::apply::awaits_dir_link(&config, &home, &e.rel_path)
+            })
             .map(|e| e.rel_path.clone())
-            .collect()
+            .collect();
+        if !narrowed {
+            for layer in &layered.layers {
+                selected.extend(repo::apply::pending_tree(&layer.manifest, &home));
+            }
+        }
+        selected
     };
 
     if selected_paths.is_empty() {
@@ -999,39 +3166,85 @@ async fn cmd_apply(
         return Ok(());
     }
 
+    // A dry run only prints the plan
+    if opts.dry_run {
+        apply_layers(&layered, Some(&selected_paths), &config, &opts)?;
+        ui::info("Dry run: nothing was changed");
+        return Ok(());
+    }
+
     // Run pre-apply hooks
-    if let Some(hooks) = &config.hooks {
-        for hook in &hooks.pre_apply {
-            ui::info(&format!("Running pre-apply hook: {}", hook));
-            run_hook(hook)?;
-        }
+    if let Some(hooks) = &hosts::local_hooks(&config)? {
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let planned: Vec<PathBuf> = selected_paths
+            .iter()
+            .map(|p| dotdipper::system::target(&home, p))
+            .collect();
+        hooks::run_all("pre-apply", &hooks.pre_apply, hooks, &planned)?;
     }
 
-    // Filter manifest to only selected paths
-    let mut filtered_manifest = crate::hash::Manifest::new();
-    for (path, hash) in &manifest.files {
-        if selected_paths.contains(path) {
-            filtered_manifest.add_file(hash.clone());
+    let (filtered_manifest, actions) =
+        apply_layers(&layered, Some(&selected_paths), &config, &opts)?;
+    if actions.iter().any(|a| a.backup_created) {
+        usage::check_quota(&config);
+    }
+
+    // Remember what --as-of laid down so it can be reverted
+    if let Some(id) = &as_of {
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let applied: std::collections::BTreeMap<PathBuf, String> = actions
+            .iter()
+            .filter(|a| a.mode != repo::apply::AppliedMode::Skipped)
+            .filter_map(|a| {
+                let rel = dotdipper::system::manifest_path(&a.target, &home);
+                let hash = filtered_manifest.get_file(&rel)?;
+                Some((rel.to_path_buf(), hash.hash.clone()))
+            })
+            .collect();
+        if !applied.is_empty() {
+            let id = layered.layers[0]
+                .compiled
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| id.clone());
+            snapshots::record_as_of(&id, applied)?;
+            ui::hint("Run 'dotdipper apply --revert-as-of' to restore the current versions");
         }
     }
 
-    let opts = repo::apply::ApplyOpts {
-        force,
-        allow_outside_home,
-    };
+    // Run post-apply hooks, then the ones for files that changed
+    if let Some(hooks) = &hosts::local_hooks(&config)? {
+        let changed = changed_files(&actions);
+        hooks::run_all("post-apply", &hooks.post_apply, hooks, &changed)?;
+        hooks::run_on_change(hooks, &changed)?;
+    }
 
-    repo::apply::apply(&compiled_path, &filtered_manifest, &config, &opts)?;
+    ui::done("Apply completed successfully!");
+    Ok(())
+}
 
-    // Run post-apply hooks
-    if let Some(hooks) = &config.hooks {
-        for hook in &hooks.post_apply {
-            ui::info(&format!("Running post-apply hook: {}", hook));
-            run_hook(hook)?;
-        }
+/// Run the `[hooks.on_change]` hooks matching files that `actions` wrote
+fn run_on_change_hooks(config: &cfg::Config, actions: &[repo::apply::AppliedAction]) -> Result<()> {
+    match hosts::local_hooks(config)? {
+        Some(hooks) => hooks::run_on_change(&hooks, &changed_files(actions)),
+        None => Ok(()),
     }
+}
 
-    ui::success("Apply completed successfully!");
-    Ok(())
+/// Files that `actions` wrote. A directory linked as a whole changed every
+/// file in it.
+fn changed_files(actions: &[repo::apply::AppliedAction]) -> Vec<PathBuf> {
+    actions
+        .iter()
+        .filter(|a| a.skipped_reason.is_none() && a.mode != repo::apply::AppliedMode::Skipped)
+        .flat_map(|a| {
+            if a.target.is_dir() {
+                dotdipper::confine::walk(&a.target).files
+            } else {
+                vec![a.target.clone()]
+            }
+        })
+        .collect()
 }
 
 async fn cmd_secrets(config_path: PathBuf, subcmd: SecretsCommands) -> Result<()> {
@@ -1053,6 +3266,16 @@ async fn cmd_secrets(config_path: PathBuf, subcmd: SecretsCommands) -> Result<()
         SecretsCommands::Edit { path } => {
             secrets::edit(&config, &path)?;
         }
+        SecretsCommands::Agent => {
+            secrets::agent::run(&config)?;
+        }
+        SecretsCommands::Lock => {
+            if secrets::agent::lock()? {
+                ui::success("Secrets agent stopped");
+            } else {
+                ui::info("No secrets agent running");
+            }
+        }
     }
 
     Ok(())
@@ -1060,20 +3283,82 @@ async fn cmd_secrets(config_path: PathBuf, subcmd: SecretsCommands) -> Result<()
 
 async fn cmd_snapshot(config_path: PathBuf, subcmd: SnapshotCommands) -> Result<()> {
     match subcmd {
-        SnapshotCommands::Create { message, force } => {
-            cmd_snapshot_create(config_path, force, message).await?;
+        SnapshotCommands::Create {
+            message,
+            force,
+            exclude,
+            full,
+        } => {
+            cmd_snapshot_create(config_path, force, message, exclude, full).await?;
         }
         SnapshotCommands::List => {
             let config = cfg::load(&config_path)?;
+            if ui::is_json() {
+                let snaps = snapshots::load_all(&config)?;
+                let remote_only = snapshots::store::remote_only(&config).await?;
+                return ui::print_json(&serde_json::json!({
+                    "snapshots": snaps,
+                    "remote_only": remote_only,
+                }));
+            }
             let snaps = snapshots::list(&config)?;
-            ui::info(&format!("Found {} snapshots", snaps.len()));
+            let remote_only = snapshots::store::remote_only(&config).await?;
+            if !remote_only.is_empty() {
+                ui::section("Only in the snapshot store:");
+                for id in &remote_only {
+                    println!("  {}", id);
+                }
+            }
+            ui::info(&format!(
+                "Found {} snapshots",
+                snaps.len() + remote_only.len()
+            ));
         }
-        SnapshotCommands::Rollback { id, force } => {
+        SnapshotCommands::Diff {
+            from,
+            to,
+            patch,
+            tool,
+        } => {
+            let config = cfg::load(&config_path)?;
+            let view = match patch || tool.is_some() {
+                true => Some(diff::DiffView::new(&config, tool.as_deref())?),
+                false => None,
+            };
+            snapshots::store::ensure_local(&config, &from).await?;
+            let from = resolve::snapshot_id(&config, &from)?;
+            let to = match to {
+                Some(to) => {
+                    snapshots::store::ensure_local(&config, &to).await?;
+                    Some(resolve::snapshot_id(&config, &to)?)
+                }
+                None => None,
+            };
+            snapshots::print_diff(&config, &from, to.as_deref(), view.as_ref())?;
+        }
+        SnapshotCommands::Rollback {
+            id,
+            force,
+            only,
+            interactive,
+        } => {
             let config = cfg::load(&config_path)?;
-            snapshots::rollback(&config, &id, force)?;
+            snapshots::store::ensure_local(&config, &id).await?;
+            let id = resolve::snapshot_id(&config, &id)?;
+            if only.is_some() || interactive {
+                let only: Vec<String> = only
+                    .iter()
+                    .flat_map(|s| s.split(','))
+                    .map(|s| s.trim().to_string())
+                    .collect();
+                snapshots::rollback_files(&config, &id, &only, interactive, force)?;
+            } else {
+                snapshots::rollback(&config, &id, force)?;
+            }
         }
         SnapshotCommands::Delete { id, force } => {
             let config = cfg::load(&config_path)?;
+            let id = resolve::snapshot_id(&config, &id)?;
             snapshots::delete(&config, &id, force)?;
         }
         SnapshotCommands::Prune {
@@ -1100,6 +3385,14 @@ async fn cmd_profile(config_path: PathBuf, subcmd: ProfileCommands) -> Result<()
     let config = cfg::load(&config_path)?;
 
     match subcmd {
+        ProfileCommands::List if ui::is_json() => {
+            let active = profiles::active_profile_name()?;
+            let profs: Vec<serde_json::Value> = profiles::all()?
+                .iter()
+                .map(|p| serde_json::json!({ "name": p.name, "active": p.name == active }))
+                .collect();
+            ui::print_json(&profs)?;
+        }
         ProfileCommands::List => {
             let profs = profiles::list(&config)?;
             ui::info(&format!("Found {} profiles", profs.len()));
@@ -1107,10 +3400,31 @@ async fn cmd_profile(config_path: PathBuf, subcmd: ProfileCommands) -> Result<()
         ProfileCommands::Create { name } => {
             profiles::create(&config, &name)?;
         }
-        ProfileCommands::Switch { name } => {
+        ProfileCommands::Switch { name, apply, force } => {
+            let name = resolve::profile(&name)?;
+            if apply {
+                let from = profiles::active_profile_name()?;
+
+                if let Some(hooks) = &hosts::local_hooks(&config)? {
+                    hooks::run_all("pre-apply", &hooks.pre_apply, hooks, &[])?;
+                }
+
+                profiles::transition(&config, &from, &name, force)?;
+
+                if let Some(hooks) = &hosts::local_hooks(&config)? {
+                    hooks::run_all("post-apply", &hooks.post_apply, hooks, &[])?;
+                }
+            }
             profiles::switch(&config, &name)?;
         }
+        ProfileCommands::Diff { from, to } => {
+            let from = resolve::profile(&from)?;
+            let to = resolve::profile(&to)?;
+            let diff = profiles::diff::diff(&config_path, &from, &to)?;
+            profiles::diff::print(&diff);
+        }
         ProfileCommands::Remove { name, force } => {
+            let name = resolve::profile(&name)?;
             profiles::remove(&config, &name, force)?;
         }
     }
@@ -1118,6 +3432,33 @@ async fn cmd_profile(config_path: PathBuf, subcmd: ProfileCommands) -> Result<()
     Ok(())
 }
 
+async fn cmd_collection(config_path: PathBuf, subcmd: CollectionCommands) -> Result<()> {
+    let config = cfg::load(&config_path)?;
+
+    match subcmd {
+        CollectionCommands::List => collections::list(&config)?,
+        CollectionCommands::Snapshot { name } => {
+            let count = collections::snapshot(&config, &name)?;
+            ui::success(&format!(
+                "Compiled {} files into collection '{}'",
+                count, name
+            ));
+        }
+        CollectionCommands::Push { name, message } => {
+            collections::push(&config, &name, message)?;
+            ui::success(&format!("Pushed collection '{}'", name));
+        }
+        CollectionCommands::Pull { name } => {
+            let count = collections::pull(&config, &name)?;
+            hooks::mark_pulled()?;
+            ui::success(&format!("Pulled collection '{}' ({} files)", name, count));
+            ui::hint("Run 'dotdipper diff' to review, then 'dotdipper apply'");
+        }
+    }
+
+    Ok(())
+}
+
 async fn cmd_remote(config_path: PathBuf, subcmd: RemoteCommands) -> Result<()> {
     let config = cfg::load(&config_path)?;
 
@@ -1128,6 +3469,13 @@ async fn cmd_remote(config_path: PathBuf, subcmd: RemoteCommands) -> Result<()>
             bucket,
             region,
             prefix,
+            proxy,
+            upload_limit,
+            download_limit,
+            encrypt,
+            no_encrypt,
+            delta,
+            no_delta,
         } => {
             let mut options = Vec::new();
             if let Some(e) = endpoint {
@@ -1142,27 +3490,152 @@ async fn cmd_remote(config_path: PathBuf, subcmd: RemoteCommands) -> Result<()>
             if let Some(p) = prefix {
                 options.push(("prefix".to_string(), p));
             }
+            if let Some(p) = proxy {
+                options.push(("proxy".to_string(), p));
+            }
+            if let Some(l) = upload_limit {
+                options.push(("upload_limit".to_string(), l));
+            }
+            if let Some(l) = download_limit {
+                options.push(("download_limit".to_string(), l));
+            }
+            if encrypt || no_encrypt {
+                options.push(("encrypt".to_string(), encrypt.to_string()));
+            }
+            if delta || no_delta {
+                options.push(("delta".to_string(), delta.to_string()));
+            }
             remote::set(&config, &kind, options)?;
         }
         RemoteCommands::Show => {
             remote::show(&config)?;
         }
+        RemoteCommands::List => {
+            remote::list(&config).await?;
+        }
         RemoteCommands::Push { dry_run } => {
-            remote::push(&config, dry_run).await?;
+            let hooks = hosts::local_hooks(&config)?.filter(|_| !dry_run);
+            if let Some(hooks) = &hooks {
+                hooks::run_all("pre-push", &hooks.pre_push, hooks, &[])?;
+            }
+            match remote::push(&config, dry_run).await {
+                Ok(()) if !dry_run => offline::clear(&offline::PushTarget::Remote)?,
+                Ok(()) => {}
+                Err(e) => {
+                    offline::queue_if_offline(&config, offline::PushTarget::Remote, &e);
+                    return Err(e);
+                }
+            }
+            if let Some(hooks) = &hooks {
+                hooks::run_all("post-push", &hooks.post_push, hooks, &[])?;
+            }
         }
-        RemoteCommands::Pull => {
-            remote::pull(&config).await?;
+        RemoteCommands::Pull {
+            list,
+            pick,
+            only,
+            verify,
+            apply,
+            atomic,
+            force,
+            yes_protected,
+        } => {
+            let hooks = hosts::local_hooks(&config)?.filter(|_| !list);
+            if let Some(hooks) = &hooks {
+                hooks::run_all("pre-pull", &hooks.pre_pull, hooks, &[])?;
+            }
+            if list {
+                remote::list(&config).await?;
+            } else if apply {
+                let opts = repo::apply::ApplyOpts {
+                    force,
+                    allow_outside_home: false,
+                    yes_protected,
+                    dry_run: false,
+                };
+                remote_pull_apply(&config, pick.as_deref(), verify, atomic, &opts).await?;
+                hooks::mark_pulled()?;
+            } else {
+                let only: Vec<String> = only
+                    .as_deref()
+                    .unwrap_or("")
+                    .split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
+                remote::pull(&config, pick.as_deref(), &only, verify).await?;
+                hooks::mark_pulled()?;
+                ui::hint("Apply changes with: dotdipper apply");
+            }
+            if let Some(hooks) = &hooks {
+                hooks::run_all("post-pull", &hooks.post_pull, hooks, &[])?;
+            }
         }
     }
 
     Ok(())
 }
 
+/// Pull a bundle and apply it. With `atomic`, every file is staged from the
+/// downloaded bundle first, and the profile and `$HOME` are only updated once
+/// all of them are ready.
+async fn remote_pull_apply(
+    config: &cfg::Config,
+    pick: Option<&str>,
+    verify: bool,
+    atomic: bool,
+    opts: &repo::apply::ApplyOpts,
+) -> Result<()> {
+    let extracted = remote::fetch(config, pick, verify).await?;
+    let meta = &extracted.meta;
+    let profile = profiles::profile_paths(&meta.profile_name)?;
+
+    let (compiled, manifest) = if atomic {
+        (extracted.compiled(), extracted.manifest()?)
+    } else {
+        extracted.install()?;
+        (
+            profile.compiled.clone(),
+            hash::Manifest::load(&profile.manifest)?,
+        )
+    };
+    let layered = collections::stack(vec![(
+        collections::MAIN.to_string(),
+        compiled.clone(),
+        manifest,
+    )]);
+    let layered = pins::apply_to_layers(config, layered, &pins::load()?)?;
+
+    ui::info("Applying changes to system...");
+    let actions = if atomic {
+        apply_layers_atomic(
+            &layered,
+            None,
+            config,
+            opts,
+            Some((&compiled, &profile.compiled)),
+            || extracted.install(),
+        )?
+    } else {
+        apply_layers(&layered, None, config, opts)?.1
+    };
+    run_on_change_hooks(config, &actions)?;
+    ui::success(&format!(
+        "Applied {} files from profile: {}",
+        meta.file_count, meta.profile_name
+    ));
+    Ok(())
+}
+
 async fn cmd_daemon(config_path: PathBuf, subcmd: DaemonCommands) -> Result<()> {
     match subcmd {
-        DaemonCommands::Start => {
+        DaemonCommands::Start { detach } => {
             let config = cfg::load(&config_path)?;
-            daemon::start(&config)?;
+            if detach {
+                daemon::start_detached(&config, &config_path)?;
+            } else {
+                daemon::start(&config)?;
+            }
         }
         DaemonCommands::Stop => {
             let config = cfg::load(&config_path)?;
@@ -1172,6 +3645,21 @@ async fn cmd_daemon(config_path: PathBuf, subcmd: DaemonCommands) -> Result<()>
             let config = cfg::load(&config_path)?;
             daemon::status(&config)?;
         }
+        DaemonCommands::Install { no_start } => {
+            let config = cfg::load(&config_path)?;
+            daemon::service::install(&config, &config_path, !no_start)?;
+        }
+        DaemonCommands::Uninstall => {
+            daemon::service::uninstall()?;
+        }
+        DaemonCommands::Simulate { since, watch } => {
+            let config = cfg::load(&config_path)?;
+            if watch {
+                daemon::simulate::watch(&config)?;
+            } else {
+                daemon::simulate::replay(&config, &since)?;
+            }
+        }
         DaemonCommands::Enable => {
             daemon::enable(&config_path)?;
         }
@@ -1188,6 +3676,7 @@ async fn cmd_config(
     edit: bool,
     show: bool,
     set: Option<String>,
+    check: bool,
 ) -> Result<()> {
     if let Some(kv) = set {
         let (key, value) = kv
@@ -1198,28 +3687,55 @@ async fn cmd_config(
     } else if edit {
         cfg::edit(&config_path)?;
         ui::success("Configuration edited");
+        report_config_problems(&cfg::check(&config_path)?, true);
+    } else if check {
+        if !config_path.exists() {
+            anyhow::bail!(
+                "Config not found at {}. Run 'dotdipper init' first.",
+                config_path.display()
+            );
+        }
+        let problems = cfg::check(&config_path)?;
+        report_config_problems(&problems, true);
+        let errors = problems.iter().filter(|p| p.is_error()).count();
+        if errors > 0 {
+            anyhow::bail!("The config has {} error(s)", errors);
+        }
+        if problems.is_empty() {
+            ui::success(&format!("{} is valid", config_path.display()));
+        }
     } else if show {
         let config = cfg::load(&config_path)?;
+        let layers = cfg::layers(&config_path)?;
+        let overrides = cfg::overrides()?;
+        if layers.len() > 1 || !overrides.is_empty() {
+            println!("# Merged from, lowest precedence first:");
+            for (layer, path) in layers {
+                println!("#   {} ({})", path.display(), layer);
+            }
+            for o in overrides {
+                println!("#   {} = {} ({})", o.key, o.value, o.source);
+            }
+        }
         println!("{}", toml::to_string_pretty(&config)?);
     } else {
-        ui::hint("Use --edit to modify, --show to view, or --set key=value to set a value");
+        ui::hint(
+            "Use --edit to modify, --show to view, --set key=value to set a value, \
+             or --check to validate",
+        );
     }
 
     Ok(())
 }
 
-fn run_hook(hook: &str) -> Result<()> {
-    use std::process::Command;
-
-    let status = Command::new("sh")
-        .arg("-c")
-        .arg(hook)
-        .status()
-        .with_context(|| format!("Failed to run hook: {}", hook))?;
-
-    if !status.success() {
-        anyhow::bail!("Hook failed with exit code: {:?}", status.code());
+/// Print what `cfg::check` found. Errors are left to whoever loads the
+/// config unless `errors` is set.
+fn report_config_problems(problems: &[cfg::Problem], errors: bool) {
+    for problem in problems {
+        match problem.severity {
+            cfg::Severity::Warning => ui::warn(&problem.to_string()),
+            cfg::Severity::Error if errors => ui::error(&problem.to_string()),
+            cfg::Severity::Error => {}
+        }
     }
-
-    Ok(())
 }
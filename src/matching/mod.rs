@@ -0,0 +1,379 @@
+//! Shared path matching for dotdipper.
+//!
+//! Every user-facing pattern (include/exclude patterns, `.dotdipperignore`,
+//! `--only` filters, `[files]` override keys, push-ignore entries) goes through
+//! this module so the same pattern means the same thing everywhere.
+//!
+//! Semantics follow gitignore, anchored at `$HOME`:
+//! - `~/foo` and `/home/me/foo` (absolute paths under `$HOME`) are anchored at `$HOME`
+//! - patterns without a leading `~/` follow plain gitignore rules
+//!   (`*.log` matches at any depth, `.config/nvim` is anchored because it contains a `/`)
+//! - a pattern matching a directory also matches everything below it
+
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::{Component, Path, PathBuf};
+
+/// A compiled set of gitignore-style patterns anchored at `$HOME`.
+#[derive(Debug, Clone)]
+pub struct PathMatcher {
+    home: PathBuf,
+    gitignore: Gitignore,
+    empty: bool,
+}
+
+impl PathMatcher {
+    /// Build a matcher from user patterns.
+    pub fn new<S: AsRef<str>>(home: &Path, patterns: &[S]) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(home);
+        let mut empty = true;
+
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            if let Some(line) = to_gitignore_line(pattern, home) {
+                builder
+                    .add_line(None, &line)
+                    .with_context(|| format!("Invalid pattern: {}", pattern))?;
+                empty = false;
+            }
+        }
+
+        Ok(Self {
+            home: home.to_path_buf(),
+            gitignore: builder.build()?,
+            empty,
+        })
+    }
+
+    /// Build a matcher where literal (non-glob) relative entries are treated as
+    /// paths relative to `$HOME` rather than unanchored gitignore names.
+    ///
+    /// This is what CLI path arguments (`--only .config/nvim,.zshrc`) and
+    /// tracked-file lists use: `.zshrc` means `~/.zshrc`, not any `.zshrc`.
+    pub fn for_paths<S: AsRef<str>>(home: &Path, paths: &[S]) -> Result<Self> {
+        let anchored: Vec<String> = paths
+            .iter()
+            .map(|p| {
+                let p = p.as_ref().trim();
+                if p.is_empty()
+                    || contains_glob_chars(p)
+                    || p.starts_with('~')
+                    || p.starts_with('!')
+                    || Path::new(p).is_absolute()
+                {
+                    p.to_string()
+                } else {
+                    format!("~/{}", p.trim_start_matches("./"))
+                }
+            })
+            .collect();
+        Self::new(home, &anchored)
+    }
+
+    /// Build a matcher from patterns followed by gitignore-style files.
+    ///
+    /// The patterns come first and the files are read after them in order,
+    /// so a later file can re-include (`!pattern`) what anything before it
+    /// excludes. Blank lines and `#` comments are skipped and missing files
+    /// are treated as empty.
+    pub fn from_patterns_and_files<S: AsRef<str>>(
+        home: &Path,
+        patterns: &[S],
+        files: &[PathBuf],
+    ) -> Result<Self> {
+        let mut all: Vec<String> = patterns.iter().map(|p| p.as_ref().to_string()).collect();
+
+        for file in files.iter().filter(|f| f.exists()) {
+            let contents = std::fs::read_to_string(file)
+                .with_context(|| format!("Failed to read {}", file.display()))?;
+            all.extend(parse_pattern_lines(&contents));
+        }
+
+        Self::new(home, &all)
+    }
+
+    /// Returns true when no patterns were supplied.
+    pub fn is_empty(&self) -> bool {
+        self.empty
+    }
+
+    /// Check whether `path` (absolute, `~/`-prefixed, or home-relative) matches.
+    ///
+    /// A path also matches when any of its parent directories match.
+    pub fn is_match(&self, path: &Path) -> bool {
+        if self.empty {
+            return false;
+        }
+
+        let rel = to_home_relative(path, &self.home);
+        if rel.as_os_str().is_empty() {
+            return false;
+        }
+
+        let abs = self.home.join(&rel);
+        if !abs.starts_with(&self.home) {
+            return false;
+        }
+
+        let is_dir = abs.is_dir() && !abs.is_symlink();
+        self.gitignore
+            .matched_path_or_any_parents(&abs, is_dir)
+            .is_ignore()
+    }
+}
+
+/// Parse the contents of a gitignore-style file into individual patterns.
+pub fn parse_pattern_lines(contents: &str) -> Vec<String> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Expand a leading `~` or `~/` to the given home directory.
+pub fn expand_tilde(path: &str, home: &Path) -> PathBuf {
+    if path == "~" {
+        home.to_path_buf()
+    } else if let Some(stripped) = path.strip_prefix("~/") {
+        home.join(stripped)
+    } else {
+        PathBuf::from(path)
+    }
+}
+
+/// Normalize a path to be relative to `$HOME`.
+///
+/// Accepts absolute paths under `home`, `~/`-prefixed paths, and paths that are
+/// already relative. `.` components and trailing slashes are dropped. Absolute
+/// paths outside `home` are returned unchanged.
+pub fn to_home_relative(path: &Path, home: &Path) -> PathBuf {
+    let expanded = match path.to_str() {
+        Some(s) => expand_tilde(s, home),
+        None => path.to_path_buf(),
+    };
+
+    let rel = if expanded.is_absolute() {
+        match expanded.strip_prefix(home) {
+            Ok(stripped) => stripped.to_path_buf(),
+            Err(_) => return expanded,
+        }
+    } else {
+        expanded
+    };
+
+    rel.components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect()
+}
+
+/// Render a home-relative path in the `~/...` form used in config keys.
+/// Files outside `$HOME` are shown by their absolute path.
+pub fn display_tilde(rel_path: &Path) -> String {
+    if crate::system::is_system(rel_path) {
+        return crate::system::target(Path::new("~"), rel_path)
+            .display()
+            .to_string();
+    }
+    format!("~/{}", rel_path.display())
+}
+
+/// Returns true if the pattern contains glob metacharacters.
+pub fn contains_glob_chars(pattern: &str) -> bool {
+    pattern.contains('*') || pattern.contains('?') || pattern.contains('[')
+}
+
+/// Convert a user pattern into a gitignore line rooted at `home`.
+fn to_gitignore_line(pattern: &str, home: &Path) -> Option<String> {
+    let trimmed = pattern.trim();
+    if trimmed.is_empty() || trimmed.starts_with('#') {
+        return None;
+    }
+
+    let (negated, body) = match trimmed.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed),
+    };
+
+    let anchored = if body == "~" {
+        "/**".to_string()
+    } else if let Some(stripped) = body.strip_prefix("~/") {
+        format!("/{}", stripped)
+    } else if Path::new(body).is_absolute() {
+        match Path::new(body).strip_prefix(home) {
+            Ok(stripped) => format!("/{}", stripped.to_string_lossy()),
+            // Outside $HOME can never match a home-anchored path
+            Err(_) => return None,
+        }
+    } else {
+        body.to_string()
+    };
+
+    Some(if negated {
+        format!("!{}", anchored)
+    } else {
+        anchored
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+    use tempfile::TempDir;
+
+    /// Home-relative paths of one to four plain or dot-prefixed segments
+    fn rel_path() -> impl Strategy<Value = PathBuf> {
+        prop::collection::vec("\\.?[a-z]{1,6}", 1..=4)
+            .prop_map(|segments| segments.iter().collect())
+    }
+
+    proptest! {
+        /// `~/p`, `$HOME/p` and a bare `p` path argument are the same pattern,
+        /// whichever way the checked path is spelled
+        #[test]
+        fn prop_spellings_of_a_path_agree(pattern in rel_path(), path in rel_path()) {
+            let home = Path::new("/home/me");
+            let matchers = [
+                PathMatcher::new(home, &[format!("~/{}", pattern.display())]).unwrap(),
+                PathMatcher::new(home, &[home.join(&pattern).display().to_string()]).unwrap(),
+                PathMatcher::for_paths(home, &[pattern.display().to_string()]).unwrap(),
+            ];
+            let forms = [
+                path.clone(),
+                PathBuf::from(format!("~/{}", path.display())),
+                home.join(&path),
+            ];
+
+            let expected = path.starts_with(&pattern);
+            for matcher in &matchers {
+                for form in &forms {
+                    prop_assert_eq!(matcher.is_match(form), expected, "{:?} vs {:?}", pattern, form);
+                }
+            }
+        }
+
+        /// A path pattern never matches a sibling that merely shares its prefix
+        #[test]
+        fn prop_name_prefixes_do_not_match(pattern in rel_path(), suffix in "[a-z-]{1,4}") {
+            let home = Path::new("/home/me");
+            let matcher = PathMatcher::for_paths(home, &[pattern.display().to_string()]).unwrap();
+            let sibling = PathBuf::from(format!("{}{}", pattern.display(), suffix));
+            prop_assert!(!matcher.is_match(&sibling));
+        }
+
+        /// Normalizing is idempotent and ignores how the path was written
+        #[test]
+        fn prop_to_home_relative_is_stable(path in rel_path()) {
+            let home = Path::new("/home/me");
+            let rel = to_home_relative(&path, home);
+            prop_assert_eq!(&to_home_relative(&rel, home), &rel);
+            prop_assert_eq!(&to_home_relative(&home.join(&path), home), &rel);
+            prop_assert_eq!(&to_home_relative(Path::new(&format!("~/{}/", path.display())), home), &rel);
+            prop_assert_eq!(&to_home_relative(&Path::new(".").join(&path), home), &rel);
+        }
+    }
+
+    /// The same location spelled differently must always produce the same answer.
+    #[test]
+    fn equivalent_spellings_match_consistently() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path();
+
+        let patterns = [
+            "~/.config/nvim",
+            "~/.config/nvim/**",
+            "~/.zshrc",
+            "*.log",
+            ".config/kitty",
+            "~/.config/*/cache/**",
+        ];
+        let paths = [
+            ".config/nvim/init.lua",
+            ".config/nvim",
+            ".zshrc",
+            ".bashrc",
+            ".cache/app/debug.log",
+            ".config/kitty/kitty.conf",
+            "work/.config/kitty/kitty.conf",
+            ".config/app/cache/blob",
+            ".config/app/settings.json",
+        ];
+
+        for pattern in patterns {
+            let abs_pattern = home.join(pattern.trim_start_matches("~/"));
+            let spellings = if pattern.starts_with("~/") {
+                vec![
+                    pattern.to_string(),
+                    abs_pattern.to_string_lossy().to_string(),
+                ]
+            } else {
+                vec![pattern.to_string()]
+            };
+
+            for path in paths {
+                let forms = [
+                    PathBuf::from(path),
+                    PathBuf::from(format!("~/{}", path)),
+                    home.join(path),
+                ];
+
+                let mut results = Vec::new();
+                for spelling in &spellings {
+                    let matcher = PathMatcher::new(home, &[spelling.as_str()]).unwrap();
+                    for form in &forms {
+                        results.push(matcher.is_match(form));
+                    }
+                }
+
+                assert!(
+                    results.iter().all(|r| *r == results[0]),
+                    "inconsistent results for pattern {:?} and path {:?}: {:?}",
+                    pattern,
+                    path,
+                    results
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn directory_patterns_cover_descendants_and_negation_works() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path();
+
+        let matcher = PathMatcher::new(home, &["~/.config/nvim"]).unwrap();
+        assert!(matcher.is_match(Path::new(".config/nvim/lua/plugins.lua")));
+        assert!(!matcher.is_match(Path::new(".config/nvim-old/init.lua")));
+
+        let matcher = PathMatcher::new(home, &["*.log", "!keep.log"]).unwrap();
+        assert!(matcher.is_match(Path::new("~/logs/debug.log")));
+        assert!(!matcher.is_match(Path::new("~/logs/keep.log")));
+
+        let matcher = PathMatcher::new(home, &["/etc/hosts"]).unwrap();
+        assert!(matcher.is_empty());
+        assert!(!matcher.is_match(Path::new("/etc/hosts")));
+    }
+
+    #[test]
+    fn to_home_relative_normalizes_inputs() {
+        let home = Path::new("/home/me");
+        assert_eq!(
+            to_home_relative(Path::new("~/.zshrc"), home),
+            PathBuf::from(".zshrc")
+        );
+        assert_eq!(
+            to_home_relative(Path::new("/home/me/.config/nvim/"), home),
+            PathBuf::from(".config/nvim")
+        );
+        assert_eq!(
+            to_home_relative(Path::new("./.vimrc"), home),
+            PathBuf::from(".vimrc")
+        );
+        assert_eq!(
+            to_home_relative(Path::new("/etc/hosts"), home),
+            PathBuf::from("/etc/hosts")
+        );
+    }
+}
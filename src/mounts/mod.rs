@@ -0,0 +1,359 @@
+//! Mount awareness for apply and discover.
+//!
+//! Applying into (or scanning) a `$HOME` that contains NFS/sshfs/SMB mounts or
+//! plugged-in drives can hang on a dead server or copy far more than intended.
+//! This module reads the mount table, classifies each mount, and answers
+//! "should dotdipper touch this path?" according to the `[mounts]` config.
+
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+
+/// Filesystem types that live on another machine.
+const NETWORK_FS_TYPES: &[&str] = &[
+    "nfs",
+    "nfs4",
+    "cifs",
+    "smb",
+    "smb3",
+    "smbfs",
+    "afpfs",
+    "webdav",
+    "davfs",
+    "sshfs",
+    "fuse.sshfs",
+    "fuse.rclone",
+    "fuse.s3fs",
+    "fuse.gcsfuse",
+    "9p",
+    "ceph",
+    "glusterfs",
+    "fuse.glusterfs",
+    "lustre",
+    "afs",
+];
+
+/// Filesystem types that are almost always removable media.
+const REMOVABLE_FS_TYPES: &[&str] = &[
+    "vfat", "exfat", "msdos", "ntfs", "ntfs3", "fuseblk", "udf", "iso9660", "cd9660",
+];
+
+/// Mount point prefixes where desktops put removable media.
+const REMOVABLE_MOUNT_ROOTS: &[&str] = &["/media/", "/run/media/", "/Volumes/"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MountKind {
+    Local,
+    Network,
+    Removable,
+}
+
+impl fmt::Display for MountKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MountKind::Local => write!(f, "local"),
+            MountKind::Network => write!(f, "network"),
+            MountKind::Removable => write!(f, "removable"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MountInfo {
+    pub device: String,
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+}
+
+impl MountInfo {
+    pub fn kind(&self) -> MountKind {
+        let fs_type = self.fs_type.to_lowercase();
+        if NETWORK_FS_TYPES.contains(&fs_type.as_str())
+            || fs_type.contains("sshfs")
+            // `host:/export` and `//server/share` style sources
+            || self.device.starts_with("//")
+            || (self.device.contains(":/") && !self.device.starts_with('/'))
+        {
+            return MountKind::Network;
+        }
+
+        let mount_point = self.mount_point.to_string_lossy();
+        if REMOVABLE_FS_TYPES.contains(&fs_type.as_str())
+            || REMOVABLE_MOUNT_ROOTS
+                .iter()
+                .any(|root| mount_point.starts_with(root))
+        {
+            return MountKind::Removable;
+        }
+
+        MountKind::Local
+    }
+}
+
+/// A snapshot of the system mount table.
+#[derive(Debug, Clone, Default)]
+pub struct MountTable {
+    mounts: Vec<MountInfo>,
+}
+
+impl MountTable {
+    /// Read the current mount table. Unsupported platforms yield an empty table.
+    pub fn load() -> Self {
+        Self {
+            mounts: read_system_mounts().unwrap_or_default(),
+        }
+    }
+
+    pub fn from_mounts(mounts: Vec<MountInfo>) -> Self {
+        Self { mounts }
+    }
+
+    pub fn mounts(&self) -> &[MountInfo] {
+        &self.mounts
+    }
+
+    /// The mount that contains `path` (longest matching mount point).
+    pub fn mount_for(&self, path: &Path) -> Option<&MountInfo> {
+        self.mounts
+            .iter()
+            .filter(|m| path.starts_with(&m.mount_point))
+            .max_by_key(|m| m.mount_point.components().count())
+    }
+
+    /// Non-local mounts located strictly below `dir`.
+    pub fn foreign_mounts_under(&self, dir: &Path) -> Vec<&MountInfo> {
+        self.mounts
+            .iter()
+            .filter(|m| m.mount_point != dir && m.mount_point.starts_with(dir))
+            .filter(|m| m.kind() != MountKind::Local)
+            .collect()
+    }
+}
+
+/// Decides which paths dotdipper may read from or write to.
+#[derive(Debug, Clone)]
+pub struct MountPolicy {
+    table: MountTable,
+    skip_network: bool,
+    skip_removable: bool,
+    allow: Vec<PathBuf>,
+}
+
+impl MountPolicy {
+    /// Build the policy from `[mounts]` config and the live mount table.
+    pub fn from_config(config: &Config, home: &Path) -> Self {
+        Self::with_table(config, home, MountTable::load())
+    }
+
+    pub fn with_table(config: &Config, home: &Path, table: MountTable) -> Self {
+        let mounts = config.mounts.clone().unwrap_or_default();
+        Self {
+            table,
+            skip_network: mounts.skip_network,
+            skip_removable: mounts.skip_removable,
+            allow: mounts
+                .allow
+                .iter()
+                .map(|p| crate::matching::expand_tilde(p, home))
+                .collect(),
+        }
+    }
+
+    /// Return a skip reason if `path` lives on a mount the policy excludes.
+    ///
+    /// The path does not need to exist; its nearest existing ancestor decides.
+    pub fn check(&self, path: &Path) -> Option<String> {
+        let mount = self.table.mount_for(path)?;
+        let kind = mount.kind();
+
+        let blocked = match kind {
+            MountKind::Local => false,
+            MountKind::Network => self.skip_network,
+            MountKind::Removable => self.skip_removable,
+        };
+        if !blocked || self.allow.iter().any(|a| mount.mount_point.starts_with(a)) {
+            return None;
+        }
+
+        Some(format!(
+            "On {} mount ({} at {})",
+            kind,
+            mount.fs_type,
+            mount.mount_point.display()
+        ))
+    }
+
+    /// Directories (`[mounts].allow`) whose mounts may be used and walked into
+    pub fn allowed(&self) -> &[PathBuf] {
+        &self.allow
+    }
+
+    pub fn table(&self) -> &MountTable {
+        &self.table
+    }
+}
+
+/// Doctor check: fail if `$HOME` contains mounts that apply/discover will skip.
+pub fn check_home_mounts(config: &Config) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let policy = MountPolicy::from_config(config, &home);
+
+    let skipped: Vec<String> = policy
+        .table()
+        .foreign_mounts_under(&home)
+        .into_iter()
+        .filter_map(|m| {
+            policy
+                .check(&m.mount_point)
+                .map(|_| format!("{} ({}, {})", m.mount_point.display(), m.kind(), m.fs_type))
+        })
+        .collect();
+
+    if skipped.is_empty() {
+        Ok(())
+    } else {
+        bail!(
+            "{} mount(s) under $HOME will be skipped: {}. Add them to [mounts].allow to include them",
+            skipped.len(),
+            skipped.join(", ")
+        )
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_system_mounts() -> Result<Vec<MountInfo>> {
+    let contents = std::fs::read_to_string("/proc/self/mounts")?;
+    Ok(parse_proc_mounts(&contents))
+}
+
+#[cfg(target_os = "macos")]
+fn read_system_mounts() -> Result<Vec<MountInfo>> {
+    let output = std::process::Command::new("mount").output()?;
+    Ok(parse_bsd_mount_output(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn read_system_mounts() -> Result<Vec<MountInfo>> {
+    Ok(Vec::new())
+}
+
+/// Parse `/proc/self/mounts` (`device mount_point fs_type options 0 0`).
+pub fn parse_proc_mounts(contents: &str) -> Vec<MountInfo> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let device = fields.next()?;
+            let mount_point = fields.next()?;
+            let fs_type = fields.next()?;
+            Some(MountInfo {
+                device: unescape_octal(device),
+                mount_point: PathBuf::from(unescape_octal(mount_point)),
+                fs_type: fs_type.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Parse BSD/macOS `mount` output (`device on /point (fs_type, options...)`).
+pub fn parse_bsd_mount_output(contents: &str) -> Vec<MountInfo> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (device, rest) = line.split_once(" on ")?;
+            let (mount_point, opts) = rest.rsplit_once(" (")?;
+            let fs_type = opts.split([',', ')']).next()?.trim();
+            Some(MountInfo {
+                device: device.to_string(),
+                mount_point: PathBuf::from(mount_point),
+                fs_type: fs_type.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Decode the `\040`-style escapes the kernel uses for spaces and tabs.
+fn unescape_octal(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() {
+            if let Ok(code) = u8::from_str_radix(&field[i + 1..i + 4], 8) {
+                out.push(code);
+                i += 4;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROC_MOUNTS: &str = "\
+/dev/nvme0n1p2 / ext4 rw,relatime 0 0
+nas.local:/export/home /home/me/nas nfs4 rw,relatime 0 0
+me@box:/srv /home/me/remote\\040box fuse.sshfs rw 0 0
+/dev/sdb1 /run/media/me/USB vfat rw 0 0
+tmpfs /home/me/.cache tmpfs rw 0 0
+";
+
+    #[test]
+    fn parses_and_classifies_mount_tables() {
+        let mounts = parse_proc_mounts(PROC_MOUNTS);
+        assert_eq!(mounts.len(), 5);
+        assert_eq!(mounts[2].mount_point, PathBuf::from("/home/me/remote box"));
+
+        let kinds: Vec<MountKind> = mounts.iter().map(|m| m.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                MountKind::Local,
+                MountKind::Network,
+                MountKind::Network,
+                MountKind::Removable,
+                MountKind::Local
+            ]
+        );
+
+        let bsd = parse_bsd_mount_output(
+            "/dev/disk3s1s1 on / (apfs, sealed, local, read-only, journaled)\n\
+             //me@nas/share on /Users/me/share (smbfs, nodev, nosuid, mounted by me)\n",
+        );
+        assert_eq!(bsd[0].fs_type, "apfs");
+        assert_eq!(bsd[1].mount_point, PathBuf::from("/Users/me/share"));
+        assert_eq!(bsd[1].kind(), MountKind::Network);
+    }
+
+    #[test]
+    fn policy_skips_foreign_mounts_unless_allowed() {
+        let home = Path::new("/home/me");
+        let table = MountTable::from_mounts(parse_proc_mounts(PROC_MOUNTS));
+        let mut config = Config::default();
+
+        let policy = MountPolicy::with_table(&config, home, table.clone());
+        assert!(policy.check(Path::new("/home/me/.zshrc")).is_none());
+        assert!(policy.check(Path::new("/home/me/.cache/x")).is_none());
+        let reason = policy.check(Path::new("/home/me/nas/.vimrc")).unwrap();
+        assert!(reason.contains("network") && reason.contains("nfs4"));
+
+        config.mounts = Some(crate::cfg::MountsConfig {
+            allow: vec!["~/nas".to_string()],
+            ..Default::default()
+        });
+        let policy = MountPolicy::with_table(&config, home, table);
+        assert!(policy.check(Path::new("/home/me/nas/.vimrc")).is_none());
+        assert!(policy
+            .check(Path::new("/home/me/remote box/.vimrc"))
+            .is_some());
+    }
+}
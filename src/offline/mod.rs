@@ -0,0 +1,408 @@
+//! Retries and the offline push queue.
+//!
+//! Network operations (git push/pull/fetch/clone, remote uploads and
+//! downloads) are retried with exponential backoff when they fail in a way
+//! that looks transient. A push that still fails is recorded in
+//! `pending_push.json`; the next dotdipper invocation, or the daemon, sends it
+//! once the network is back.
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::cfg::{Config, NetworkConfig};
+use crate::ui;
+
+/// Upper bound for a single backoff delay
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// Lower-cased fragments of errors worth retrying (DNS, timeouts, resets).
+const TRANSIENT_MARKERS: &[&str] = &[
+    "could not resolve host",
+    "could not resolve hostname",
+    "temporary failure in name resolution",
+    "name or service not known",
+    "dns error",
+    "timed out",
+    "connection refused",
+    "connection reset",
+    "connection closed",
+    "network is unreachable",
+    "no route to host",
+    "early eof",
+    "the remote end hung up unexpectedly",
+    "ssl_connect",
+    "gnutls_handshake",
+    "broken pipe",
+    "error sending request",
+    "error connecting to",
+];
+
+/// Returns true if an error message looks like a flaky network rather than a
+/// real failure (bad credentials, rejected push, missing repo, ...).
+pub fn is_transient(message: &str) -> bool {
+    let message = message.to_lowercase();
+    TRANSIENT_MARKERS.iter().any(|m| message.contains(m))
+}
+
+/// How often and how patiently to retry network operations.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub backoff: Duration,
+    /// Proxy git is told about (`[remote] proxy`)
+    pub proxy: Option<String>,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        let network = config.network.clone().unwrap_or_default();
+        Self {
+            retries: network.retries,
+            backoff: Duration::from_millis(network.backoff_ms),
+            proxy: config.remote.as_ref().and_then(|r| r.proxy.clone()),
+        }
+    }
+
+    /// Try once, never wait
+    pub fn single_attempt() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::ZERO,
+            proxy: None,
+        }
+    }
+
+    /// Delay before retry number `attempt + 1`
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.backoff
+            .saturating_mul(2u32.saturating_pow(attempt))
+            .min(MAX_DELAY)
+    }
+
+    /// Whether a failure on attempt `attempt` (0-based) should be retried
+    pub fn should_retry(&self, attempt: u32, message: &str) -> bool {
+        attempt < self.retries && is_transient(message)
+    }
+
+    fn announce(&self, attempt: u32, what: &str, message: &str) -> Duration {
+        let delay = self.delay(attempt);
+        ui::warn(&format!(
+            "{} failed: {} (retry {}/{} in {:.1}s)",
+            what,
+            message.lines().find(|l| !l.trim().is_empty()).unwrap_or(""),
+            attempt + 1,
+            self.retries,
+            delay.as_secs_f32()
+        ));
+        delay
+    }
+
+    /// Log the failure and sleep before the next attempt
+    pub fn wait(&self, attempt: u32, what: &str, message: &str) {
+        std::thread::sleep(self.announce(attempt, what, message));
+    }
+
+    /// Run `op`, retrying transient failures
+    pub fn run<T>(&self, what: &str, mut op: impl FnMut() -> Result<T>) -> Result<T> {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    let message = format!("{:#}", e);
+                    if !self.should_retry(attempt, &message) {
+                        return Err(e);
+                    }
+                    self.wait(attempt, what, &message);
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    /// Async version of [`RetryPolicy::run`] for remote backends
+    pub async fn run_async<T, F, Fut>(&self, what: &str, mut op: F) -> Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    let message = format!("{:#}", e);
+                    if !self.should_retry(attempt, &message) {
+                        return Err(e);
+                    }
+                    tokio::time::sleep(self.announce(attempt, what, &message)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Where a queued push should go.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "target", rename_all = "lowercase")]
+pub enum PushTarget {
+    /// `dotdipper push` to the GitHub repository
+    GitHub {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        repo: Option<String>,
+    },
+    /// `dotdipper remote push` to the configured cloud remote
+    Remote,
+}
+
+impl PushTarget {
+    fn describe(&self) -> &'static str {
+        match self {
+            PushTarget::GitHub { .. } => "GitHub push",
+            PushTarget::Remote => "remote push",
+        }
+    }
+
+    fn same_destination(&self, other: &PushTarget) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingPush {
+    #[serde(flatten)]
+    pub target: PushTarget,
+    pub queued_at: DateTime<Utc>,
+    pub last_error: String,
+}
+
+/// Whether failed pushes should be queued
+pub fn queue_enabled(config: &Config) -> bool {
+    config
+        .network
+        .as_ref()
+        .map(|n| n.offline_queue)
+        .unwrap_or(NetworkConfig::default().offline_queue)
+}
+
+/// Load the pending pushes (empty when nothing is queued)
+pub fn load_pending() -> Result<Vec<PendingPush>> {
+    let path = crate::paths::pending_push_file()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_pending(pending: &[PendingPush]) -> Result<()> {
+    let path = crate::paths::pending_push_file()?;
+    if pending.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(pending)?)?;
+    Ok(())
+}
+
+/// Queue a push; a newer entry for the same destination replaces the old one
+/// since pushing always sends the latest state.
+pub fn enqueue(target: PushTarget, error: &anyhow::Error) -> Result<()> {
+    let mut pending = load_pending().unwrap_or_default();
+    pending.retain(|p| !p.target.same_destination(&target));
+    pending.push(PendingPush {
+        target,
+        queued_at: Utc::now(),
+        last_error: format!("{:#}", error),
+    });
+    save_pending(&pending)
+}
+
+/// Drop the queued push for a destination after it was pushed directly
+pub fn clear(target: &PushTarget) -> Result<()> {
+    let mut pending = load_pending()?;
+    let before = pending.len();
+    pending.retain(|p| !p.target.same_destination(target));
+    if pending.len() != before {
+        save_pending(&pending)?;
+    }
+    Ok(())
+}
+
+/// Handle a failed push: queue it if the failure looks like a network problem.
+///
+/// Returns true when the push was queued.
+pub fn queue_if_offline(config: &Config, target: PushTarget, error: &anyhow::Error) -> bool {
+    if !queue_enabled(config) || !is_transient(&format!("{:#}", error)) {
+        return false;
+    }
+    match enqueue(target.clone(), error) {
+        Ok(()) => {
+            ui::warn(&format!(
+                "Network unavailable; queued the {} to run automatically later",
+                target.describe()
+            ));
+            true
+        }
+        Err(e) => {
+            ui::warn(&format!("Could not queue push: {:#}", e));
+            false
+        }
+    }
+}
+
+/// Try to send queued pushes. Entries that fail again stay queued.
+///
+/// Returns the number of pushes that went through.
+pub async fn flush(config: &Config, policy: &RetryPolicy) -> Result<usize> {
+    let pending = load_pending()?;
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    ui::info(&format!("Sending {} queued push(es)...", pending.len()));
+    let mut remaining = Vec::new();
+    let mut sent = 0;
+
+    for entry in pending {
+        let result = match &entry.target {
+            PushTarget::GitHub { message, repo } => crate::vcs::push_with_policy(
+                config,
+                message.clone(),
+                false,
+                repo.as_deref(),
+                policy,
+            )
+            .map(|_| ()),
+            PushTarget::Remote => crate::remote::push_with_policy(config, false, policy).await,
+        };
+
+        match result {
+            Ok(()) => {
+                ui::success(&format!(
+                    "Sent queued {} (queued {})",
+                    entry.target.describe(),
+                    entry.queued_at.format("%Y-%m-%d %H:%M")
+                ));
+                sent += 1;
+            }
+            Err(e) => {
+                ui::debug(&format!(
+                    "Queued {} still failing: {:#}",
+                    entry.target.describe(),
+                    e
+                ));
+                remaining.push(PendingPush {
+                    last_error: format!("{:#}", e),
+                    ..entry
+                });
+            }
+        }
+    }
+
+    if !remaining.is_empty() {
+        ui::hint(&format!(
+            "{} push(es) still queued; they will be retried on the next run",
+            remaining.len()
+        ));
+    }
+    save_pending(&remaining)?;
+    Ok(sent)
+}
+
+/// Push to `target` from synchronous code (the daemon loop), queueing the
+/// push if the network is down
+pub fn push_blocking(config: &Config, target: PushTarget) -> Result<()> {
+    let config = config.clone();
+    std::thread::spawn(move || {
+        let result = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(async {
+                match &target {
+                    PushTarget::GitHub { message, repo } => {
+                        crate::vcs::push(&config, message.clone(), false, repo.as_deref())
+                            .map(|_| ())
+                    }
+                    PushTarget::Remote => crate::remote::push(&config, false).await,
+                }
+            });
+        match result {
+            Ok(()) => clear(&target),
+            Err(e) => {
+                queue_if_offline(&config, target, &e);
+                Err(e)
+            }
+        }
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("Push panicked"))?
+}
+
+/// Flush from synchronous code (the daemon loop) that may already be running
+/// inside a tokio runtime.
+pub fn flush_blocking(config: &Config) -> Result<usize> {
+    let config = config.clone();
+    std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(flush(&config, &RetryPolicy::single_attempt()))
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("Queue flush panicked"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transient_errors_and_backoff() {
+        assert!(is_transient(
+            "fatal: unable to access 'https://github.com/x/y.git/': Could not resolve host: github.com"
+        ));
+        assert!(is_transient(
+            "ssh: connect to host github.com port 22: Connection timed out"
+        ));
+        assert!(!is_transient("remote: Permission to x/y.git denied to me."));
+        assert!(!is_transient("! [rejected] main -> main (fetch first)"));
+
+        let policy = RetryPolicy {
+            retries: 3,
+            backoff: Duration::from_millis(500),
+            proxy: None,
+        };
+        assert_eq!(policy.delay(0), Duration::from_millis(500));
+        assert_eq!(policy.delay(2), Duration::from_millis(2000));
+        assert_eq!(policy.delay(20), MAX_DELAY);
+        assert!(policy.should_retry(2, "Connection reset by peer"));
+        assert!(!policy.should_retry(3, "Connection reset by peer"));
+
+        let mut calls = 0;
+        let fast = RetryPolicy {
+            retries: 2,
+            backoff: Duration::ZERO,
+            proxy: None,
+        };
+        let result: Result<()> = fast.run("test", || {
+            calls += 1;
+            anyhow::bail!("network is unreachable")
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+    }
+}
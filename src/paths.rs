@@ -1,58 +1,223 @@
 use anyhow::{Context, Result};
 use std::path::PathBuf;
 
-/// Returns the dotdipper base directory.
+/// Environment variable that keeps config, data and cache in one directory
+pub const HOME_ENV: &str = "DOTDIPPER_HOME";
+
+/// Entries of the data directory. Older versions kept them next to the
+/// config, and `upgrade::layout` moves them out from there.
+pub const DATA_ENTRIES: &[&str] = &[
+    "compiled",
+    "manifest.lock",
+    "snapshots",
+    "collections",
+    "install",
+    "applied.lock",
+    "applied_as_of.json",
+    "journal",
+    "backups",
+    "backups.json",
+    "trash",
+    "system",
+    "pending_push.json",
+    "history.jsonl",
+    "logs",
+    "pins.json",
+    "hooks-untrusted",
+    "state_version",
+    "daemon.pid",
+];
+
+/// `DOTDIPPER_HOME`, relative to `$HOME` unless absolute, if it is set
+fn custom_home() -> Result<Option<PathBuf>> {
+    let Some(custom) = std::env::var_os(HOME_ENV).filter(|v| !v.is_empty()) else {
+        return Ok(None);
+    };
+    let p = PathBuf::from(custom);
+    if p.is_absolute() {
+        return Ok(Some(p));
+    }
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    Ok(Some(home.join(p)))
+}
+
+/// `$<var>/dotdipper`, or `~/<fallback>/dotdipper` if the variable is unset
+/// or not an absolute path, as the XDG base directory spec asks
+fn xdg_dir(var: &str, fallback: &str) -> Result<PathBuf> {
+    match std::env::var_os(var).map(PathBuf::from) {
+        Some(dir) if dir.is_absolute() => Ok(dir.join("dotdipper")),
+        _ => {
+            let home = dirs::home_dir().context("Failed to find home directory")?;
+            Ok(home.join(fallback).join("dotdipper"))
+        }
+    }
+}
+
+/// Directory holding `config.toml`, its layers and `.dotdipperignore`.
 ///
 /// Resolution order:
 /// 1. `DOTDIPPER_HOME` environment variable (if set)
 /// 2. `$XDG_CONFIG_HOME/dotdipper` (if `XDG_CONFIG_HOME` is set)
 /// 3. `~/.config/dotdipper`
-pub fn base_dir() -> Result<PathBuf> {
-    if let Ok(custom) = std::env::var("DOTDIPPER_HOME") {
-        let p = PathBuf::from(custom);
-        if p.is_absolute() {
-            return Ok(p);
-        }
-        let home = dirs::home_dir().context("Failed to find home directory")?;
-        return Ok(home.join(p));
+pub fn config_dir() -> Result<PathBuf> {
+    match custom_home()? {
+        Some(dir) => Ok(dir),
+        None => xdg_dir("XDG_CONFIG_HOME", ".config"),
     }
+}
 
-    if let Ok(xdg) = std::env::var("XDG_CONFIG_HOME") {
-        return Ok(PathBuf::from(xdg).join("dotdipper"));
+/// Directory holding the compiled files, manifests, snapshots and the rest
+/// of dotdipper's state.
+///
+/// Resolution order:
+/// 1. `DOTDIPPER_HOME` environment variable (if set)
+/// 2. `$XDG_DATA_HOME/dotdipper` (if `XDG_DATA_HOME` is set)
+/// 3. `~/.local/share/dotdipper`
+pub fn data_dir() -> Result<PathBuf> {
+    match custom_home()? {
+        Some(dir) => Ok(dir),
+        None => xdg_dir("XDG_DATA_HOME", ".local/share"),
     }
+}
 
-    let home = dirs::home_dir().context("Failed to find home directory")?;
-    Ok(home.join(".config").join("dotdipper"))
+/// Directory for files that can be recreated, such as downloaded bundles.
+///
+/// Resolution order:
+/// 1. `$DOTDIPPER_HOME/cache` (if `DOTDIPPER_HOME` is set)
+/// 2. `$XDG_CACHE_HOME/dotdipper` (if `XDG_CACHE_HOME` is set)
+/// 3. `~/.cache/dotdipper`
+pub fn cache_dir() -> Result<PathBuf> {
+    match custom_home()? {
+        Some(dir) => Ok(dir.join("cache")),
+        None => xdg_dir("XDG_CACHE_HOME", ".cache"),
+    }
 }
 
 pub fn config_file() -> Result<PathBuf> {
-    Ok(base_dir()?.join("config.toml"))
+    Ok(config_dir()?.join("config.toml"))
+}
+
+/// Environment variable pointing at the system config, for machines that
+/// keep it outside `/etc`
+pub const SYSTEM_CONFIG_ENV: &str = "DOTDIPPER_SYSTEM_CONFIG";
+
+/// Config shared by every user of the machine, merged under their own
+pub fn system_config_file() -> PathBuf {
+    match std::env::var_os(SYSTEM_CONFIG_ENV) {
+        Some(path) if !path.is_empty() => PathBuf::from(path),
+        _ => PathBuf::from("/etc/dotdipper/config.toml"),
+    }
 }
 
 pub fn ignore_file() -> Result<PathBuf> {
-    Ok(base_dir()?.join(".dotdipperignore"))
+    Ok(config_dir()?.join(".dotdipperignore"))
+}
+
+/// `~/.dotdipperignore`, for exclusions specific to this machine
+pub fn home_ignore_file() -> Result<PathBuf> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    Ok(home.join(".dotdipperignore"))
+}
+
+/// Environment variable selecting the profile for one invocation, like the
+/// global `--profile` flag
+pub const PROFILE_ENV: &str = "DOTDIPPER_PROFILE";
+
+/// Directory holding a profile's `compiled/` and `manifest.lock`.
+///
+/// The default profile keeps them at the top of the data directory, where
+/// setups without profiles have always had them; other profiles keep them in
+/// `profiles/<name>/`.
+pub fn profile_store(name: &str) -> Result<PathBuf> {
+    if name == "default" {
+        data_dir()
+    } else {
+        Ok(profiles_dir()?.join(name))
+    }
+}
+
+fn active_store() -> Result<PathBuf> {
+    let profile = crate::profiles::active_profile_name().unwrap_or_else(|_| "default".to_string());
+    profile_store(&profile)
 }
 
+/// Compiled files of the active profile
 pub fn compiled_dir() -> Result<PathBuf> {
-    Ok(base_dir()?.join("compiled"))
+    Ok(active_store()?.join("compiled"))
 }
 
+/// Manifest of the active profile
 pub fn manifest_file() -> Result<PathBuf> {
-    Ok(base_dir()?.join("manifest.lock"))
+    Ok(active_store()?.join("manifest.lock"))
 }
 
 pub fn snapshots_dir() -> Result<PathBuf> {
-    Ok(base_dir()?.join("snapshots"))
-}
-
-pub fn cache_dir() -> Result<PathBuf> {
-    Ok(base_dir()?.join("cache"))
+    Ok(data_dir()?.join("snapshots"))
 }
 
 pub fn install_dir() -> Result<PathBuf> {
-    Ok(base_dir()?.join("install"))
+    Ok(data_dir()?.join("install"))
 }
 
+/// Compiled files and manifests of the profiles other than the default
 pub fn profiles_dir() -> Result<PathBuf> {
-    Ok(base_dir()?.join("profiles"))
+    Ok(data_dir()?.join("profiles"))
+}
+
+/// Configs of the profiles, `profiles/<name>/config.toml` next to the
+/// main config
+pub fn profile_configs_dir() -> Result<PathBuf> {
+    Ok(config_dir()?.join("profiles"))
+}
+
+pub fn collections_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("collections"))
+}
+
+/// Manifest of the files last applied on this machine
+pub fn applied_manifest_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("applied.lock"))
+}
+
+/// Journals of what each apply changed, for `dotdipper undo --apply`
+pub fn journal_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("journal"))
+}
+
+/// Backups of replaced files, with `[backups] relocate = true` (next to
+/// the state archives `migrate` keeps in `backups/`)
+pub fn backups_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("backups").join("files"))
+}
+
+/// Files apply and rollback replaced, kept until `dotdipper trash empty`
+pub fn trash_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("trash"))
+}
+
+/// Scripts apply wrote for files outside $HOME it couldn't write itself
+pub fn system_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("system"))
+}
+
+/// Registry of the backups apply made (`dotdipper backups list`)
+pub fn backups_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("backups.json"))
+}
+
+pub fn as_of_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("applied_as_of.json"))
+}
+
+pub fn pending_push_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("pending_push.json"))
+}
+
+/// Append-only history of operations (`dotdipper history`)
+pub fn history_file() -> Result<PathBuf> {
+    Ok(data_dir()?.join("history.jsonl"))
+}
+
+pub fn logs_dir() -> Result<PathBuf> {
+    Ok(data_dir()?.join("logs"))
 }
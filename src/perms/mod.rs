@@ -0,0 +1,206 @@
+//! Permissions and ownership that apply enforces.
+//!
+//! A copy gets the mode of its compiled file, which is whatever the tracked
+//! file had when it was snapshotted, maybe on another machine with another
+//! umask. A `[files]` override with `chmod = "0600"` sets the mode on every
+//! apply instead, even when the contents are already in place, and `owner`
+//! and `group` do the same for ownership (changing those usually needs
+//! root). Symlinks are followed, so in symlink mode the compiled file gets
+//! the mode the link resolves to.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::process::Command;
+
+use crate::cfg::FileOverride;
+
+/// Permission bits from `chmod`-style octal, e.g. "0600" or "644"
+pub fn parse_mode(mode: &str) -> Option<u32> {
+    let digits = mode.strip_prefix("0o").unwrap_or(mode);
+    if !(3..=4).contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 8).ok()
+}
+
+/// What apply enforces on one file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Policy {
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+}
+
+impl Policy {
+    /// The policy of a `[files]` override, if it sets one
+    pub fn of(file_override: &FileOverride) -> Result<Option<Self>> {
+        let mode = match &file_override.chmod {
+            Some(chmod) => match parse_mode(chmod) {
+                Some(mode) => Some(mode),
+                None => bail!("Invalid chmod \"{}\", use octal like \"0600\"", chmod),
+            },
+            None => None,
+        };
+        let policy = Self {
+            mode,
+            owner: file_override.owner.clone(),
+            group: file_override.group.clone(),
+        };
+        Ok((policy != Self::default()).then_some(policy))
+    }
+}
+
+/// Give `target` the executable bits of `mode`, the mode the manifest
+/// recorded, leaving the other bits alone. Returns whether it changed.
+pub fn restore_exec_bits(target: &Path, mode: u32) -> Result<bool> {
+    let current = fs::metadata(target)?.permissions().mode() & 0o7777;
+    let wanted = (current & !0o111) | (mode & 0o111);
+    if wanted == current {
+        return Ok(false);
+    }
+    fs::set_permissions(target, fs::Permissions::from_mode(wanted))
+        .with_context(|| format!("Failed to chmod {}", target.display()))?;
+    Ok(true)
+}
+
+/// Give `target` the mode and ownership of `policy`. Returns what was
+/// changed, e.g. "mode 0644 -> 0600" or "owner 1000:1000 -> root:wheel".
+pub fn enforce(target: &Path, policy: &Policy) -> Result<Vec<String>> {
+    let mut changed = Vec::new();
+    if let Some(mode) = policy.mode {
+        let current = fs::metadata(target)?.permissions().mode() & 0o7777;
+        if current != mode {
+            fs::set_permissions(target, fs::Permissions::from_mode(mode))
+                .with_context(|| format!("Failed to chmod {}", target.display()))?;
+            changed.push(format!("mode {:04o} -> {:04o}", current, mode));
+        }
+    }
+
+    let spec = match (&policy.owner, &policy.group) {
+        (Some(owner), Some(group)) => format!("{}:{}", owner, group),
+        (Some(owner), None) => owner.clone(),
+        (None, Some(group)) => format!(":{}", group),
+        (None, None) => return Ok(changed),
+    };
+    // Names that can't be resolved are left for chown to check
+    let metadata = fs::metadata(target)?;
+    let owner_differs = policy
+        .owner
+        .as_ref()
+        .is_some_and(|owner| user_id(owner) != Some(metadata.uid()));
+    let group_differs = policy
+        .group
+        .as_ref()
+        .is_some_and(|group| group_id(group) != Some(metadata.gid()));
+    if !owner_differs && !group_differs {
+        return Ok(changed);
+    }
+
+    let output = Command::new("chown")
+        .arg(&spec)
+        .arg(target)
+        .output()
+        .context("Failed to run chown")?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    changed.push(format!(
+        "owner {}:{} -> {}",
+        metadata.uid(),
+        metadata.gid(),
+        spec
+    ));
+    Ok(changed)
+}
+
+/// Numeric id of a user given by name or number
+fn user_id(owner: &str) -> Option<u32> {
+    if let Ok(uid) = owner.parse() {
+        return Some(uid);
+    }
+    let output = Command::new("id").arg("-u").arg(owner).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// Numeric id of a group given by name or number, from `/etc/group` or
+/// else `getent`
+fn group_id(group: &str) -> Option<u32> {
+    if let Ok(gid) = group.parse() {
+        return Some(gid);
+    }
+    let gid_in = |text: &str| {
+        text.lines().find_map(|line| {
+            let mut fields = line.split(':');
+            (fields.next() == Some(group))
+                .then(|| fields.nth(1)?.parse().ok())
+                .flatten()
+        })
+    };
+    if let Some(gid) = fs::read_to_string("/etc/group")
+        .ok()
+        .and_then(|t| gid_in(&t))
+    {
+        return Some(gid);
+    }
+    let output = Command::new("getent")
+        .arg("group")
+        .arg(group)
+        .output()
+        .ok()?;
+    gid_in(&String::from_utf8_lossy(&output.stdout))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chmod_is_enforced_whatever_mode_the_file_had() {
+        assert_eq!(parse_mode("0600"), Some(0o600));
+        assert_eq!(parse_mode("755"), Some(0o755));
+        assert_eq!(parse_mode("0o640"), Some(0o640));
+        for invalid in ["", "0999", "rw-r--r--", "60", "00600", "+600"] {
+            assert_eq!(parse_mode(invalid), None, "{}", invalid);
+        }
+
+        let temp = tempfile::tempdir().unwrap();
+        let file = temp.path().join("config");
+        fs::write(&file, "Host *\n").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o644)).unwrap();
+        let link = temp.path().join("link");
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        let metadata = fs::metadata(&file).unwrap();
+        let policy = Policy {
+            mode: Some(0o600),
+            // Owning what it already owns needs no privileges
+            owner: Some(metadata.uid().to_string()),
+            group: Some(metadata.gid().to_string()),
+        };
+        assert_eq!(
+            enforce(&link, &policy).unwrap(),
+            vec!["mode 0644 -> 0600".to_string()]
+        );
+        assert_eq!(fs::metadata(&file).unwrap().mode() & 0o7777, 0o600);
+        assert!(enforce(&file, &policy).unwrap().is_empty());
+
+        // Only the executable bits come from the recorded mode
+        assert!(restore_exec_bits(&file, 0o100755).unwrap());
+        assert_eq!(fs::metadata(&file).unwrap().mode() & 0o7777, 0o711);
+        assert!(!restore_exec_bits(&file, 0o755).unwrap());
+    }
+
+    #[test]
+    fn test_owner_and_group_names_resolve_to_ids() {
+        assert_eq!(user_id("0"), Some(0));
+        assert_eq!(user_id("root"), Some(0));
+        assert_eq!(user_id("no-such-user-here"), None);
+        assert_eq!(group_id("42"), Some(42));
+        assert_eq!(group_id("no-such-group-here"), None);
+    }
+}
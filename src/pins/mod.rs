@@ -0,0 +1,240 @@
+//! Pinning files to a snapshot on one machine.
+//!
+//! `dotdipper pin ~/.config/nvim --snapshot <id>` records in `pins.json` (local
+//! to this machine, never pushed) that files under that path come from the
+//! given snapshot. Until `dotdipper unpin`, apply and `pull --apply` lay them
+//! down from the snapshot instead of the latest compiled files, snapshots keep
+//! the latest stored version instead of capturing the pinned one, and status
+//! lists the pins instead of reporting the pinned files as modified.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+use crate::collections::Layered;
+use crate::hash::Manifest;
+use crate::matching::{display_tilde, expand_tilde, to_home_relative, PathMatcher};
+use crate::ui;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Pin {
+    /// Pinned file or directory, as `~/...`
+    pub path: String,
+    pub snapshot_id: String,
+    pub pinned_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Pins {
+    #[serde(default)]
+    pub pins: Vec<Pin>,
+}
+
+impl Pins {
+    pub fn is_empty(&self) -> bool {
+        self.pins.is_empty()
+    }
+
+    /// The pin covering a path (absolute or home-relative), if any
+    pub fn pin_for(&self, path: &Path, home: &Path) -> Option<&Pin> {
+        self.pins.iter().find(|pin| {
+            PathMatcher::for_paths(home, &[pin.path.as_str()])
+                .map(|m| m.is_match(path))
+                .unwrap_or(false)
+        })
+    }
+
+    /// Split files into unpinned and pinned ones
+    pub fn partition(&self, files: Vec<PathBuf>, home: &Path) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        files
+            .into_iter()
+            .partition(|f| self.pin_for(f, home).is_none())
+    }
+}
+
+fn pins_file() -> Result<PathBuf> {
+    Ok(crate::paths::data_dir()?.join("pins.json"))
+}
+
+pub fn load() -> Result<Pins> {
+    let path = pins_file()?;
+    if !path.exists() {
+        return Ok(Pins::default());
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save(pins: &Pins) -> Result<()> {
+    let path = pins_file()?;
+    if pins.is_empty() {
+        if path.exists() {
+            fs::remove_file(&path)?;
+        }
+        return Ok(());
+    }
+    fs::write(&path, serde_json::to_string_pretty(pins)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// `~/...` form of a path given on the command line
+fn normalize(input: &str, home: &Path) -> Result<String> {
+    let rel = to_home_relative(&expand_tilde(input, home), home);
+    if rel.is_absolute() || rel.as_os_str().is_empty() {
+        bail!(
+            "Only files and directories under $HOME can be pinned: {}",
+            input
+        );
+    }
+    Ok(display_tilde(&rel))
+}
+
+/// Files of a snapshot under a pinned path
+fn pinned_files(config: &Config, pin: &Pin, home: &Path) -> Result<(PathBuf, Manifest)> {
+    let (snapshot_dir, manifest) = crate::snapshots::snapshot_manifest(config, &pin.snapshot_id)
+        .with_context(|| format!("Snapshot {} pinned for {}", pin.snapshot_id, pin.path))?;
+    let matcher = PathMatcher::for_paths(home, &[pin.path.as_str()])?;
+    let mut pinned = Manifest::new();
+    for file in manifest.files.into_values() {
+        if matcher.is_match(&file.path) {
+            pinned.add_file(file);
+        }
+    }
+    Ok((snapshot_dir, pinned))
+}
+
+/// `dotdipper pin <path> --snapshot <id>`
+pub fn pin(config: &Config, path: &str, snapshot: &str) -> Result<Pin> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let pin = Pin {
+        path: normalize(path, &home)?,
+        snapshot_id: crate::resolve::snapshot_id(config, snapshot)?,
+        pinned_at: Utc::now(),
+    };
+
+    let (_, files) = pinned_files(config, &pin, &home)?;
+    if files.files.is_empty() {
+        bail!(
+            "Snapshot {} has no files under {}",
+            pin.snapshot_id,
+            pin.path
+        );
+    }
+
+    let mut pins = load()?;
+    pins.pins.retain(|p| p.path != pin.path);
+    pins.pins.push(pin.clone());
+    pins.pins.sort_by(|a, b| a.path.cmp(&b.path));
+    save(&pins)?;
+
+    ui::success(&format!(
+        "Pinned {} ({} files) to snapshot {}",
+        pin.path,
+        files.files.len(),
+        pin.snapshot_id
+    ));
+    ui::hint("Run 'dotdipper apply' to lay down the pinned versions");
+    Ok(pin)
+}
+
+/// `dotdipper unpin <path>`
+pub fn unpin(path: &str) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let path = normalize(path, &home)?;
+    let mut pins = load()?;
+    let before = pins.pins.len();
+    pins.pins.retain(|p| p.path != path);
+    if pins.pins.len() == before {
+        bail!("{} is not pinned", path);
+    }
+    save(&pins)?;
+    ui::success(&format!("Unpinned {}", path));
+    ui::hint("Run 'dotdipper apply' to go back to the latest versions");
+    Ok(())
+}
+
+/// Print the pins as a table
+pub fn print(pins: &Pins) {
+    let rows = pins
+        .pins
+        .iter()
+        .map(|p| {
+            vec![
+                p.path.clone(),
+                p.snapshot_id.clone(),
+                p.pinned_at.format("%Y-%m-%d %H:%M").to_string(),
+            ]
+        })
+        .collect();
+    ui::print_table(&["PATH", "SNAPSHOT", "PINNED"], rows);
+}
+
+/// Put pinned files on top of `layered`: each pin becomes a layer serving its
+/// files from the snapshot, and the other layers no longer provide them.
+pub fn apply_to_layers(config: &Config, layered: Layered, pins: &Pins) -> Result<Layered> {
+    if pins.is_empty() {
+        return Ok(layered);
+    }
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+
+    let mut sources = Vec::new();
+    for pin in &pins.pins {
+        let (snapshot_dir, files) = pinned_files(config, pin, &home)?;
+        sources.push((format!("pin {}", pin.snapshot_id), snapshot_dir, files));
+    }
+    for layer in layered.layers {
+        let mut manifest = Manifest::new();
+        for file in layer.manifest.files.into_values() {
+            if pins.pin_for(&file.path, &home).is_none() {
+                manifest.add_file(file);
+            }
+        }
+        sources.push((layer.name, layer.compiled, manifest));
+    }
+
+    let mut pinned = crate::collections::stack(sources);
+    pinned.shadowed.extend(layered.shadowed);
+    Ok(pinned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pins_cover_files_under_the_pinned_path() {
+        let home = Path::new("/home/me");
+        let pins = Pins {
+            pins: vec![Pin {
+                path: "~/.config/nvim".to_string(),
+                snapshot_id: "20240101_120000".to_string(),
+                pinned_at: Utc::now(),
+            }],
+        };
+
+        assert!(pins
+            .pin_for(Path::new(".config/nvim/init.lua"), home)
+            .is_some());
+        assert!(pins
+            .pin_for(&home.join(".config/nvim/lua/plugins.lua"), home)
+            .is_some());
+        assert!(pins.pin_for(Path::new(".config/nvim-old"), home).is_none());
+
+        let (unpinned, pinned) = pins.partition(
+            vec![home.join(".zshrc"), home.join(".config/nvim/init.lua")],
+            home,
+        );
+        assert_eq!(unpinned, vec![home.join(".zshrc")]);
+        assert_eq!(pinned, vec![home.join(".config/nvim/init.lua")]);
+
+        assert_eq!(
+            normalize("~/.config/nvim/", home).unwrap(),
+            "~/.config/nvim"
+        );
+        assert!(normalize("/etc/hosts", home).is_err());
+    }
+}
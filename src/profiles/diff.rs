@@ -0,0 +1,373 @@
+//! `dotdipper profile diff <a> <b>`: what switching profiles actually changes.
+//!
+//! A profile's effective config is the main config with the profile's own
+//! `config.toml` merged on top. The diff compares those effective configs
+//! (tracked files, `[files]` overrides, packages, hooks, remotes and any other
+//! setting) and the files compiled into each profile.
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use super::{plan_transition, profile_paths, TransitionPlan};
+use crate::cfg::{self, Config};
+use crate::hash::Manifest;
+use crate::matching::{display_tilde, to_home_relative};
+use crate::ui;
+
+const TRACKED_FILES_KEY: &str = "general.tracked_files";
+
+/// Diff sections in display order, with the config key prefixes they cover
+const SECTIONS: &[(&str, &[&str])] = &[
+    ("Tracked files", &[TRACKED_FILES_KEY]),
+    ("File overrides", &["files."]),
+    ("Packages", &["packages."]),
+    ("Hooks", &["hooks."]),
+    ("Remotes", &["remote.", "github."]),
+];
+
+const OTHER_SECTION: &str = "Other settings";
+
+/// Keys that are expected to differ and say nothing about the profiles
+const IGNORED_KEYS: &[&str] = &["general.active_profile", "include"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// Value or list entry only in the first profile
+    Removed(String),
+    /// Value or list entry only in the second profile
+    Added(String),
+    /// Different values in the first and second profile
+    Changed(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigChange {
+    pub key: String,
+    pub change: Change,
+}
+
+#[derive(Debug, Default)]
+pub struct ProfileDiff {
+    pub from: String,
+    pub to: String,
+    /// Config changes grouped by section title, in display order
+    pub sections: Vec<(String, Vec<ConfigChange>)>,
+    /// Compiled files: `removed` are only in `from`, `added` only in `to`
+    pub files: TransitionPlan,
+}
+
+impl ProfileDiff {
+    pub fn is_empty(&self) -> bool {
+        self.sections.is_empty()
+            && self.files.removed.is_empty()
+            && self.files.added.is_empty()
+            && self.files.replaced.is_empty()
+    }
+}
+
+/// A flattened config value
+#[derive(Debug, Clone, PartialEq)]
+enum Leaf {
+    Scalar(String),
+    List(Vec<String>),
+}
+
+fn render(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn key_segment(key: &str) -> String {
+    if !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        key.to_string()
+    } else {
+        format!("{:?}", key)
+    }
+}
+
+/// Flatten a config into dotted keys (`packages.common`, `files."~/.ssh/config".mode`)
+fn flatten(value: &toml::Value, prefix: &str, out: &mut BTreeMap<String, Leaf>) {
+    match value {
+        toml::Value::Table(table) => {
+            for (key, value) in table {
+                let key = if prefix.is_empty() {
+                    key_segment(key)
+                } else {
+                    format!("{}.{}", prefix, key_segment(key))
+                };
+                flatten(value, &key, out);
+            }
+        }
+        toml::Value::Array(items) if !items.iter().any(|i| i.is_table()) => {
+            out.insert(
+                prefix.to_string(),
+                Leaf::List(items.iter().map(render).collect()),
+            );
+        }
+        other => {
+            out.insert(prefix.to_string(), Leaf::Scalar(render(other)));
+        }
+    }
+}
+
+fn compare_leaves(key: &str, a: Option<&Leaf>, b: Option<&Leaf>) -> Vec<ConfigChange> {
+    let change = |change| ConfigChange {
+        key: key.to_string(),
+        change,
+    };
+    let list = |leaf: Option<&Leaf>| -> BTreeSet<String> {
+        match leaf {
+            Some(Leaf::List(items)) => items.iter().cloned().collect(),
+            _ => BTreeSet::new(),
+        }
+    };
+
+    match (a, b) {
+        (Some(Leaf::Scalar(a)), Some(Leaf::Scalar(b))) if a == b => Vec::new(),
+        (Some(Leaf::Scalar(a)), Some(Leaf::Scalar(b))) => {
+            vec![change(Change::Changed(a.clone(), b.clone()))]
+        }
+        (Some(Leaf::Scalar(a)), None) => vec![change(Change::Removed(a.clone()))],
+        (None, Some(Leaf::Scalar(b))) => vec![change(Change::Added(b.clone()))],
+        (Some(Leaf::List(_)) | None, Some(Leaf::List(_)) | None) => {
+            let (a, b) = (list(a), list(b));
+            a.difference(&b)
+                .map(|v| change(Change::Removed(v.clone())))
+                .chain(b.difference(&a).map(|v| change(Change::Added(v.clone()))))
+                .collect()
+        }
+        (a, b) => {
+            let show = |leaf: Option<&Leaf>| match leaf {
+                Some(Leaf::Scalar(s)) => s.clone(),
+                Some(Leaf::List(items)) => format!("[{}]", items.join(", ")),
+                None => String::new(),
+            };
+            vec![change(Change::Changed(show(a), show(b)))]
+        }
+    }
+}
+
+fn section_for(key: &str) -> &'static str {
+    SECTIONS
+        .iter()
+        .find(|(_, prefixes)| {
+            prefixes
+                .iter()
+                .any(|p| key == *p || (p.ends_with('.') && key.starts_with(p)))
+        })
+        .map(|(title, _)| *title)
+        .unwrap_or(OTHER_SECTION)
+}
+
+/// Compare two effective configs, grouped into sections in display order
+pub fn compare_configs(
+    a: &Config,
+    b: &Config,
+    home: &Path,
+) -> Result<Vec<(String, Vec<ConfigChange>)>> {
+    let mut a_leaves = BTreeMap::new();
+    let mut b_leaves = BTreeMap::new();
+    flatten(&toml::Value::try_from(a)?, "", &mut a_leaves);
+    flatten(&toml::Value::try_from(b)?, "", &mut b_leaves);
+
+    let keys: BTreeSet<&String> = a_leaves.keys().chain(b_leaves.keys()).collect();
+    let mut grouped: BTreeMap<&str, Vec<ConfigChange>> = BTreeMap::new();
+    for key in keys {
+        if IGNORED_KEYS.contains(&key.as_str()) {
+            continue;
+        }
+        for mut change in compare_leaves(key, a_leaves.get(key), b_leaves.get(key)) {
+            if key == TRACKED_FILES_KEY {
+                change.change = match change.change {
+                    Change::Removed(p) => Change::Removed(tilde(&p, home)),
+                    Change::Added(p) => Change::Added(tilde(&p, home)),
+                    other => other,
+                };
+            }
+            grouped.entry(section_for(key)).or_default().push(change);
+        }
+    }
+
+    let order = SECTIONS
+        .iter()
+        .map(|(title, _)| *title)
+        .chain(std::iter::once(OTHER_SECTION));
+    Ok(order
+        .filter_map(|title| grouped.remove(title).map(|c| (title.to_string(), c)))
+        .collect())
+}
+
+fn tilde(path: &str, home: &Path) -> String {
+    let rel = to_home_relative(Path::new(path), home);
+    if rel.is_absolute() {
+        path.to_string()
+    } else {
+        display_tilde(&rel)
+    }
+}
+
+/// Effective config of a profile: the config as it loads with the profile
+/// active
+pub fn effective_config(config_path: &Path, name: &str) -> Result<Config> {
+    profile_paths(name)?;
+    cfg::load_for_profile(config_path, name)
+}
+
+fn load_manifest(name: &str) -> Result<Manifest> {
+    let path = profile_paths(name)?.manifest;
+    if path.exists() {
+        Manifest::load(&path)
+    } else {
+        Ok(Manifest::new())
+    }
+}
+
+/// Diff profile `from` against profile `to`
+pub fn diff(config_path: &Path, from: &str, to: &str) -> Result<ProfileDiff> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let sections = compare_configs(
+        &effective_config(config_path, from)?,
+        &effective_config(config_path, to)?,
+        &home,
+    )?;
+
+    Ok(ProfileDiff {
+        from: from.to_string(),
+        to: to.to_string(),
+        sections,
+        files: plan_transition(&load_manifest(from)?, &load_manifest(to)?),
+    })
+}
+
+fn removed_line(text: &str) -> String {
+    format!("  - {}", text).red().to_string()
+}
+
+fn added_line(text: &str) -> String {
+    format!("  + {}", text).green().to_string()
+}
+
+fn changed_line(text: &str) -> String {
+    format!("  ~ {}", text).yellow().to_string()
+}
+
+fn compiled_path(rel: &Path) -> String {
+    display_tilde(rel)
+}
+
+/// Print a diff as colored `-`/`+`/`~` lines grouped by section
+pub fn print(diff: &ProfileDiff) {
+    if diff.is_empty() {
+        ui::success(&format!(
+            "Profiles '{}' and '{}' have no effective differences",
+            diff.from, diff.to
+        ));
+        return;
+    }
+
+    println!(
+        "{} only in {}, {} only in {}, {} changed",
+        "-".red(),
+        diff.from.bold(),
+        "+".green(),
+        diff.to.bold(),
+        "~".yellow()
+    );
+
+    for (title, changes) in &diff.sections {
+        ui::section(title);
+        for c in changes {
+            let label = |value: &str| {
+                if c.key == TRACKED_FILES_KEY {
+                    value.to_string()
+                } else {
+                    format!("{} = {}", c.key, value)
+                }
+            };
+            let line = match &c.change {
+                Change::Removed(v) => removed_line(&label(v)),
+                Change::Added(v) => added_line(&label(v)),
+                Change::Changed(a, b) => changed_line(&format!("{}: {} -> {}", c.key, a, b)),
+            };
+            println!("{}", line);
+        }
+    }
+
+    let files = &diff.files;
+    if !(files.removed.is_empty() && files.added.is_empty() && files.replaced.is_empty()) {
+        ui::section("Compiled files");
+        for path in &files.removed {
+            println!("{}", removed_line(&compiled_path(path)));
+        }
+        for path in &files.added {
+            println!("{}", added_line(&compiled_path(path)));
+        }
+        for path in &files.replaced {
+            println!(
+                "{}",
+                changed_line(&format!("{} (content differs)", compiled_path(path)))
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::HooksConfig;
+
+    #[test]
+    fn test_compare_configs_groups_changes_by_section() {
+        let home = Path::new("/home/me");
+        let mut work = Config::default();
+        work.general.tracked_files = vec![home.join(".zshrc"), home.join(".work-vpn")];
+        work.packages.common = vec!["git".to_string(), "awscli".to_string()];
+        let mut personal = Config::default();
+        personal.general.tracked_files = vec![home.join(".zshrc")];
+        personal.packages.common = vec!["git".to_string()];
+        personal.hooks = Some(HooksConfig {
+            pre_apply: Vec::new(),
+            post_apply: vec!["tmux source ~/.tmux.conf".into()],
+            pre_snapshot: Vec::new(),
+            post_snapshot: Vec::new(),
+            pre_pull: Vec::new(),
+            post_pull: Vec::new(),
+            pre_push: Vec::new(),
+            post_push: Vec::new(),
+            on_change: BTreeMap::new(),
+            hosts: BTreeMap::new(),
+            sandbox: None,
+        });
+        personal.general.backup = !work.general.backup;
+
+        let sections = compare_configs(&work, &personal, home).unwrap();
+        let titles: Vec<&str> = sections.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(
+            titles,
+            vec!["Tracked files", "Packages", "Hooks", "Other settings"]
+        );
+        assert_eq!(
+            sections[0].1[0].change,
+            Change::Removed("~/.work-vpn".to_string())
+        );
+        assert_eq!(
+            sections[1].1,
+            vec![ConfigChange {
+                key: "packages.common".to_string(),
+                change: Change::Removed("awscli".to_string()),
+            }]
+        );
+        assert_eq!(sections[2].1[0].key, "hooks.post_apply");
+        assert!(matches!(sections[3].1[0].change, Change::Changed(_, _)));
+
+        assert!(compare_configs(&work, &work, home).unwrap().is_empty());
+    }
+}
@@ -5,12 +5,17 @@
 /// - Switching between profiles
 /// - Profile-specific configurations with base + overlay merging
 /// - Per-profile manifest and compiled directories
-use anyhow::{bail, Result};
+/// - Diffing the effective configs and files of two profiles
+pub mod diff;
+
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::cfg::{Config, GeneralConfig, RestoreMode};
+use crate::cfg::Config;
+use crate::hash::Manifest;
+use crate::repo::apply::{AppliedMode, ApplyOpts};
 use crate::ui;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,10 +33,47 @@ pub struct ProfilePaths {
     pub root: PathBuf,
 }
 
+/// How the files in `$HOME` change when moving from one profile to another
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TransitionPlan {
+    /// Only in the old profile
+    pub removed: Vec<PathBuf>,
+    /// In both profiles with different content
+    pub replaced: Vec<PathBuf>,
+    /// Only in the new profile
+    pub added: Vec<PathBuf>,
+    /// In both profiles with identical content
+    pub unchanged: Vec<PathBuf>,
+}
+
+/// Outcome of [`transition`], used for the summary
+#[derive(Debug, Clone, Default)]
+pub struct TransitionReport {
+    pub plan: TransitionPlan,
+    /// Old-profile files that were modified locally and left in place
+    pub kept: Vec<PathBuf>,
+    pub applied: usize,
+    pub skipped: usize,
+}
+
 /// List all profiles
 pub fn list(_config: &Config) -> Result<Vec<Profile>> {
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let profiles_dir = dotdipper_dir.join("profiles");
+    let profiles = all()?;
+
+    // Display profiles
+    let active = active_profile_name()?;
+    ui::section(&format!("Found {} profiles:", profiles.len()));
+    for prof in &profiles {
+        let marker = if prof.name == active { " (active)" } else { "" };
+        println!("  {}{}", prof.name, marker);
+    }
+
+    Ok(profiles)
+}
+
+/// All profiles, sorted by name, without printing them
+pub fn all() -> Result<Vec<Profile>> {
+    let profiles_dir = crate::paths::profile_configs_dir()?;
 
     if !profiles_dir.exists() {
         // Create default profile if none exist
@@ -52,11 +94,12 @@ pub fn list(_config: &Config) -> Result<Vec<Profile>> {
                 .unwrap_or("unknown")
                 .to_string();
 
+            let store = crate::paths::profile_store(&name)?;
             let profile = Profile {
                 name: name.clone(),
                 config_path: path.join("config.toml"),
-                manifest_path: path.join("manifest.lock"),
-                compiled_path: path.join("compiled"),
+                manifest_path: store.join("manifest.lock"),
+                compiled_path: store.join("compiled"),
             };
 
             profiles.push(profile);
@@ -64,15 +107,6 @@ pub fn list(_config: &Config) -> Result<Vec<Profile>> {
     }
 
     profiles.sort_by(|a, b| a.name.cmp(&b.name));
-
-    // Display profiles
-    let active = active_profile_name()?;
-    ui::section(&format!("Found {} profiles:", profiles.len()));
-    for prof in &profiles {
-        let marker = if prof.name == active { " (active)" } else { "" };
-        println!("  {}{}", prof.name, marker);
-    }
-
     Ok(profiles)
 }
 
@@ -83,9 +117,8 @@ pub fn create(_config: &Config, name: &str) -> Result<Profile> {
         bail!("Invalid profile name: {}", name);
     }
 
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let profiles_dir = dotdipper_dir.join("profiles");
-    let profile_dir = profiles_dir.join(name);
+    let profile_dir = crate::paths::profile_configs_dir()?.join(name);
+    let store = crate::paths::profile_store(name)?;
 
     if profile_dir.exists() {
         bail!("Profile '{}' already exists", name);
@@ -95,23 +128,11 @@ pub fn create(_config: &Config, name: &str) -> Result<Profile> {
 
     // Create profile directories
     fs::create_dir_all(&profile_dir)?;
-    let compiled_dir = profile_dir.join("compiled");
+    let compiled_dir = store.join("compiled");
     fs::create_dir_all(&compiled_dir)?;
 
-    // Create profile config (inherits from root defaults)
-    let profile_config = Config {
-        general: GeneralConfig {
-            default_mode: RestoreMode::Symlink,
-            backup: true,
-            tracked_files: Vec::new(),
-            active_profile: None,
-        },
-        ..Default::default()
-    };
-
     let config_path = profile_dir.join("config.toml");
-    let config_toml = toml::to_string_pretty(&profile_config)?;
-    fs::write(&config_path, config_toml)?;
+    fs::write(&config_path, profile_config_template(name))?;
 
     ui::success(&format!("Profile '{}' created", name));
     ui::hint(&format!(
@@ -122,16 +143,14 @@ pub fn create(_config: &Config, name: &str) -> Result<Profile> {
     Ok(Profile {
         name: name.to_string(),
         config_path,
-        manifest_path: profile_dir.join("manifest.lock"),
+        manifest_path: store.join("manifest.lock"),
         compiled_path: compiled_dir,
     })
 }
 
 /// Switch to a different profile
 pub fn switch(_config: &Config, name: &str) -> Result<()> {
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let profiles_dir = dotdipper_dir.join("profiles");
-    let profile_dir = profiles_dir.join(name);
+    let profile_dir = crate::paths::profile_configs_dir()?.join(name);
 
     if !profile_dir.exists() {
         bail!(
@@ -142,7 +161,7 @@ pub fn switch(_config: &Config, name: &str) -> Result<()> {
     }
 
     // Update main config to set active profile
-    let main_config_path = dotdipper_dir.join("config.toml");
+    let main_config_path = crate::paths::config_file()?;
     let mut config = if main_config_path.exists() {
         crate::cfg::load(&main_config_path)?
     } else {
@@ -157,15 +176,181 @@ pub fn switch(_config: &Config, name: &str) -> Result<()> {
     Ok(())
 }
 
+/// Compare two profile manifests (paths are home-relative)
+pub fn plan_transition(old: &Manifest, new: &Manifest) -> TransitionPlan {
+    let mut plan = TransitionPlan::default();
+
+    for (path, old_hash) in &old.files {
+        match new.get_file(path) {
+            None => plan.removed.push(path.clone()),
+            Some(new_hash) if new_hash.hash != old_hash.hash => plan.replaced.push(path.clone()),
+            Some(_) => plan.unchanged.push(path.clone()),
+        }
+    }
+    for path in new.files.keys() {
+        if !old.has_file(path) {
+            plan.added.push(path.clone());
+        }
+    }
+
+    plan.removed.sort();
+    plan.replaced.sort();
+    plan.added.sort();
+    plan.unchanged.sort();
+    plan
+}
+
+/// Swap the files in `$HOME` from profile `from` to profile `to`.
+///
+/// The new profile's files are applied first, replacing the ones `from` put
+/// there (symlinks into its compiled tree, or copies that still match its
+/// manifest) without prompting. Only then are the files `to` doesn't provide
+/// removed if `from` still owns them; locally modified ones are kept.
+pub fn transition(config: &Config, from: &str, to: &str, force: bool) -> Result<TransitionReport> {
+    if !crate::paths::profile_configs_dir()?.join(to).exists() {
+        bail!(
+            "Profile '{}' does not exist. Create it first with 'dotdipper profile create {}'",
+            to,
+            to
+        );
+    }
+
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let old_paths = profile_paths(from)?;
+    let new_paths = profile_paths(to)?;
+    let old_manifest = load_manifest_or_empty(&old_paths.manifest)?;
+    let new_manifest = load_manifest_or_empty(&new_paths.manifest)?;
+
+    let plan = plan_transition(&old_manifest, &new_manifest);
+    let mut report = TransitionReport::default();
+
+    let owned = |rel_path: &Path| {
+        let target = target_for(&home, rel_path);
+        let expected = old_manifest.get_file(rel_path).map(|h| h.hash.as_str());
+        points_into(&target, &old_paths.compiled) || matches_hash(&target, expected)
+    };
+
+    // Files the old profile put in place are replaced without prompting and
+    // go to the trash rather than a backup, since the old profile keeps them.
+    // Unchanged copies are among them; apply sees them as already applied.
+    let mut taken_over = Manifest::new();
+    let mut rest = new_manifest.clone();
+    rest.files.retain(|rel_path, file_hash| {
+        let is_owned = owned(rel_path);
+        if is_owned {
+            taken_over.add_file(file_hash.clone());
+        }
+        !is_owned
+    });
+    let mut takeover_config = config.clone();
+    takeover_config.general.backup = false;
+    for (manifest, config, force) in [
+        (&taken_over, &takeover_config, true),
+        (&rest, config, force),
+    ] {
+        if manifest.is_empty() {
+            continue;
+        }
+        let opts = ApplyOpts {
+            force,
+            allow_outside_home: false,
+            yes_protected: false,
+            dry_run: false,
+        };
+        let actions = crate::repo::apply::apply(&new_paths.compiled, manifest, config, &opts)?;
+        let skipped = actions
+            .iter()
+            .filter(|a| a.mode == AppliedMode::Skipped)
+            .count();
+        report.skipped += skipped;
+        report.applied += actions.len() - skipped;
+    }
+
+    // Now that the new profile is in place, clear out what only the old one
+    // provided
+    for rel_path in &plan.removed {
+        let target = target_for(&home, rel_path);
+        if !target.exists() && !target.is_symlink() {
+            continue;
+        }
+        if owned(rel_path) {
+            fs::remove_file(&target)
+                .with_context(|| format!("Failed to remove {}", target.display()))?;
+        } else {
+            report.kept.push(rel_path.clone());
+        }
+    }
+
+    report.plan = plan;
+    print_transition_summary(from, to, &report);
+    Ok(report)
+}
+
+fn print_transition_summary(from: &str, to: &str, report: &TransitionReport) {
+    ui::section(&format!("Profile transition: {} → {}", from, to));
+    println!(
+        "  Removed:   {}",
+        report.plan.removed.len() - report.kept.len()
+    );
+    println!("  Replaced:  {}", report.plan.replaced.len());
+    println!("  Added:     {}", report.plan.added.len());
+    println!("  Unchanged: {}", report.plan.unchanged.len());
+
+    for path in &report.kept {
+        ui::warn(&format!(
+            "Kept ~/{} (modified locally, not owned by '{}' anymore)",
+            path.display(),
+            from
+        ));
+    }
+    if report.skipped > 0 {
+        ui::warn(&format!(
+            "{} file(s) from '{}' were not applied",
+            report.skipped, to
+        ));
+    }
+}
+
+fn load_manifest_or_empty(path: &Path) -> Result<Manifest> {
+    if path.exists() {
+        Manifest::load(path)
+    } else {
+        Ok(Manifest::new())
+    }
+}
+
+/// Apply strips the `.age` suffix for encrypted files
+fn target_for(home: &Path, rel_path: &Path) -> PathBuf {
+    let target = home.join(rel_path);
+    if rel_path.extension().is_some_and(|ext| ext == "age") {
+        target.with_extension("")
+    } else {
+        target
+    }
+}
+
+fn points_into(target: &Path, compiled: &Path) -> bool {
+    crate::links::points_into(target, compiled)
+}
+
+fn matches_hash(target: &Path, expected: Option<&str>) -> bool {
+    match expected {
+        Some(expected) if target.is_file() && !target.is_symlink() => {
+            crate::hash::hash_file(target)
+                .map(|h| h.hash == expected)
+                .unwrap_or(false)
+        }
+        _ => false,
+    }
+}
+
 /// Remove a profile
 pub fn remove(_config: &Config, name: &str, force: bool) -> Result<()> {
     if name == "default" {
         bail!("Cannot remove the default profile");
     }
 
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let profiles_dir = dotdipper_dir.join("profiles");
-    let profile_dir = profiles_dir.join(name);
+    let profile_dir = crate::paths::profile_configs_dir()?.join(name);
 
     if !profile_dir.exists() {
         bail!("Profile '{}' does not exist", name);
@@ -195,15 +380,47 @@ pub fn remove(_config: &Config, name: &str, force: bool) -> Result<()> {
     }
 
     fs::remove_dir_all(&profile_dir)?;
+    let store = crate::paths::profile_store(name)?;
+    if store.exists() {
+        fs::remove_dir_all(&store)?;
+    }
     ui::success(&format!("Profile '{}' removed", name));
 
     Ok(())
 }
 
-/// Get the currently active profile name
+/// Names of all existing profiles, sorted
+pub fn names() -> Result<Vec<String>> {
+    let profiles_dir = crate::paths::profile_configs_dir()?;
+
+    if !profiles_dir.exists() {
+        return Ok(vec!["default".to_string()]);
+    }
+
+    let mut names = Vec::new();
+    for entry in fs::read_dir(&profiles_dir)? {
+        let entry = entry?;
+        if entry.path().is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                names.push(name.to_string());
+            }
+        }
+    }
+
+    names.sort();
+    Ok(names)
+}
+
+/// Get the currently active profile name: the one selected with `--profile`
+/// (or `DOTDIPPER_PROFILE`) for this invocation, else the one switched to
 pub fn active_profile_name() -> Result<String> {
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let main_config_path = dotdipper_dir.join("config.toml");
+    if let Ok(name) = std::env::var(crate::paths::PROFILE_ENV) {
+        if !name.is_empty() {
+            return Ok(name);
+        }
+    }
+
+    let main_config_path = crate::paths::config_file()?;
 
     if main_config_path.exists() {
         let config = crate::cfg::load(&main_config_path)?;
@@ -217,71 +434,84 @@ pub fn active_profile_name() -> Result<String> {
 
 /// Ensure a profile exists, create if not
 pub fn ensure_exists(name: &str) -> Result<()> {
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let profiles_dir = dotdipper_dir.join("profiles");
-    let profile_dir = profiles_dir.join(name);
+    let profile_dir = crate::paths::profile_configs_dir()?.join(name);
 
     if !profile_dir.exists() {
         fs::create_dir_all(&profile_dir)?;
-        fs::create_dir_all(profile_dir.join("compiled"))?;
+        fs::create_dir_all(crate::paths::profile_store(name)?.join("compiled"))?;
 
-        // Create minimal config
-        let config = Config::default();
-        let config_toml = toml::to_string_pretty(&config)?;
-        fs::write(profile_dir.join("config.toml"), config_toml)?;
+        fs::write(
+            profile_dir.join("config.toml"),
+            profile_config_template(name),
+        )?;
     }
 
     Ok(())
 }
 
-/// Get paths for a profile (with overlay semantics)
+/// A new profile's config: empty, so it inherits everything from the main
+/// config until settings are added
+pub fn profile_config_template(name: &str) -> String {
+    format!(
+        "# Settings for the '{}' profile, merged over the main config while it is\n\
+         # active: tables merge key by key, arrays are concatenated and other\n\
+         # values set here win.\n",
+        name
+    )
+}
+
+/// Get paths for a profile (with overlay semantics). `root` holds the
+/// profile's config overlay; see [`crate::paths::profile_store`] for where
+/// its compiled files and manifest live.
 pub fn profile_paths(name: &str) -> Result<ProfilePaths> {
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let profiles_dir = dotdipper_dir.join("profiles");
-    let profile_dir = profiles_dir.join(name);
+    let profile_dir = crate::paths::profile_configs_dir()?.join(name);
+    let store = crate::paths::profile_store(name)?;
 
     ensure_exists(name)?;
 
     Ok(ProfilePaths {
-        compiled: profile_dir.join("compiled"),
-        manifest: profile_dir.join("manifest.lock"),
+        compiled: store.join("compiled"),
+        manifest: store.join("manifest.lock"),
         root: profile_dir,
     })
 }
 
-fn get_dotdipper_dir() -> Result<PathBuf> {
-    crate::paths::base_dir()
+fn ensure_default_profile() -> Result<()> {
+    ensure_exists("default")
 }
 
-fn ensure_default_profile() -> Result<()> {
-    ensure_exists("default")?;
-
-    // Migrate legacy config/compiled to default profile if they exist
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let legacy_compiled = dotdipper_dir.join("compiled");
-    let legacy_manifest = dotdipper_dir.join("manifest.lock");
-
-    let default_profile = profile_paths("default")?;
-
-    // Only migrate if default profile is empty and legacy exists
-    if legacy_compiled.exists() && !default_profile.compiled.exists() {
-        ui::info("Migrating legacy compiled/ to default profile...");
-        fs_extra::dir::copy(
-            &legacy_compiled,
-            &default_profile.root,
-            &fs_extra::dir::CopyOptions::new(),
-        )?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::FileHash;
+
+    fn manifest(entries: &[(&str, &str)]) -> Manifest {
+        let mut manifest = Manifest::new();
+        for (path, hash) in entries {
+            manifest.add_file(FileHash {
+                path: PathBuf::from(path),
+                hash: hash.to_string(),
+                size: 0,
+                mode: 0o644,
+                modified: chrono::Utc::now(),
+                xattrs: Default::default(),
+            });
+        }
+        manifest
     }
 
-    if legacy_manifest.exists() && !default_profile.manifest.exists() {
-        fs::copy(&legacy_manifest, &default_profile.manifest)?;
+    #[test]
+    fn test_plan_transition_classifies_paths() {
+        let old = manifest(&[(".zshrc", "a"), (".gitconfig", "b"), (".work", "c")]);
+        let new = manifest(&[(".zshrc", "a"), (".gitconfig", "x"), (".home", "d")]);
+
+        let plan = plan_transition(&old, &new);
+        assert_eq!(plan.removed, vec![PathBuf::from(".work")]);
+        assert_eq!(plan.replaced, vec![PathBuf::from(".gitconfig")]);
+        assert_eq!(plan.added, vec![PathBuf::from(".home")]);
+        assert_eq!(plan.unchanged, vec![PathBuf::from(".zshrc")]);
     }
 
-    Ok(())
-}
-
-#[cfg(test)]
-mod tests {
     #[test]
     fn test_profile_name_validation() {
         // Valid names would not trigger errors in actual create
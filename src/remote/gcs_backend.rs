@@ -0,0 +1,880 @@
+use anyhow::{bail, Context, Result};
+/// Google Cloud Storage remote backend (feature-gated)
+/// Authenticates with a service account key or Application Default Credentials
+use async_trait::async_trait;
+use reqwest::{Body, Client, RequestBuilder, Response, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::OnceCell;
+
+use super::throttle::{Limits, Throttled};
+use super::transfer::{Partial, UploadState};
+use super::{BundleEntry, Remote, RemoteObject};
+
+const STORAGE_URL: &str = "https://storage.googleapis.com";
+const TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_write";
+
+/// A credentials file: a service account key, or the user credentials
+/// `gcloud auth application-default login` writes
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum CredentialsFile {
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+        #[serde(default = "default_token_uri")]
+        token_uri: String,
+    },
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+    },
+}
+
+fn default_token_uri() -> String {
+    TOKEN_URL.to_string()
+}
+
+#[derive(Debug)]
+enum Credentials {
+    /// An access token from GOOGLE_OAUTH_ACCESS_TOKEN
+    Token(String),
+    File(CredentialsFile),
+    /// The metadata server of a Compute Engine VM, Cloud Run, GKE, ...
+    Metadata,
+    /// No authentication, for an emulator (STORAGE_EMULATOR_HOST)
+    Anonymous,
+}
+
+impl Credentials {
+    /// Find credentials in the order Google's client libraries use
+    fn discover() -> Result<Self> {
+        if std::env::var_os("STORAGE_EMULATOR_HOST").is_some() {
+            return Ok(Self::Anonymous);
+        }
+        if let Ok(token) = std::env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+            return Ok(Self::Token(token));
+        }
+        let path = match std::env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => adc_path().filter(|p| p.exists()),
+        };
+        let Some(path) = path else {
+            return Ok(Self::Metadata);
+        };
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read GCS credentials {}", path.display()))?;
+        let file = serde_json::from_str(&text).with_context(|| {
+            format!(
+                "Unsupported GCS credentials {} (expected a service account key or authorized user)",
+                path.display()
+            )
+        })?;
+        Ok(Self::File(file))
+    }
+}
+
+/// Application Default Credentials written by gcloud
+fn adc_path() -> Option<PathBuf> {
+    let config = std::env::var_os("CLOUDSDK_CONFIG")
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config/gcloud")))?;
+    Some(config.join("application_default_credentials.json"))
+}
+
+#[derive(Serialize)]
+struct Claims<'a> {
+    iss: &'a str,
+    scope: &'a str,
+    aud: &'a str,
+    iat: u64,
+    exp: u64,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+/// A signed JWT asserting the service account, exchanged for a token
+fn service_account_assertion(
+    client_email: &str,
+    private_key: &str,
+    token_uri: &str,
+) -> Result<String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let claims = Claims {
+        iss: client_email,
+        scope: SCOPE,
+        aud: token_uri,
+        iat: now,
+        exp: now + 3600,
+    };
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(private_key.as_bytes())
+        .context("Invalid private key in GCS service account credentials")?;
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256),
+        &claims,
+        &key,
+    )
+    .context("Failed to sign GCS service account assertion")
+}
+
+async fn request_token(request: RequestBuilder) -> Result<String> {
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        bail!(
+            "{}: {}",
+            response.status(),
+            response.text().await.unwrap_or_default().trim()
+        );
+    }
+    Ok(response.json::<TokenResponse>().await?.access_token)
+}
+
+/// Exchange `credentials` for an access token, `None` for an emulator
+async fn fetch_token(client: &Client, credentials: &Credentials) -> Result<Option<String>> {
+    let token = match credentials {
+        Credentials::Anonymous => return Ok(None),
+        Credentials::Token(token) => token.clone(),
+        Credentials::File(CredentialsFile::ServiceAccount {
+            client_email,
+            private_key,
+            token_uri,
+        }) => {
+            let assertion = service_account_assertion(client_email, private_key, token_uri)?;
+            request_token(client.post(token_uri).form(&[
+                ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                ("assertion", assertion.as_str()),
+            ]))
+            .await
+            .context("Failed to get a GCS access token for the service account")?
+        }
+        Credentials::File(CredentialsFile::AuthorizedUser {
+            client_id,
+            client_secret,
+            refresh_token,
+        }) => request_token(client.post(TOKEN_URL).form(&[
+            ("grant_type", "refresh_token"),
+            ("client_id", client_id.as_str()),
+            ("client_secret", client_secret.as_str()),
+            ("refresh_token", refresh_token.as_str()),
+        ]))
+        .await
+        .context("Failed to refresh GCS application default credentials")?,
+        Credentials::Metadata => request_token(
+            client
+                .get(METADATA_TOKEN_URL)
+                .header("Metadata-Flavor", "Google")
+                .timeout(Duration::from_secs(3)),
+        )
+        .await
+        .context(
+            "No GCS credentials found. Set GOOGLE_APPLICATION_CREDENTIALS to a service account key \
+             or run 'gcloud auth application-default login'",
+        )?,
+    };
+    Ok(Some(token))
+}
+
+#[derive(Deserialize)]
+struct ObjectList {
+    #[serde(default)]
+    items: Vec<ObjectInfo>,
+    #[serde(rename = "nextPageToken")]
+    next_page_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ObjectInfo {
+    name: String,
+    /// The JSON API sends sizes and generations as strings
+    #[serde(default)]
+    size: String,
+    #[serde(default)]
+    generation: String,
+}
+
+/// The object a finished upload created
+async fn object_from(response: Response, size: u64) -> Result<RemoteObject> {
+    let object: ObjectInfo = response
+        .json()
+        .await
+        .context("Unexpected response to GCS upload")?;
+    Ok(RemoteObject {
+        etag_or_rev: object.generation,
+        size_bytes: object.size.parse().unwrap_or(size),
+    })
+}
+
+/// State of an interrupted resumable upload
+enum Received {
+    Bytes(u64),
+    Complete(RemoteObject),
+}
+
+pub struct GcsRemote {
+    bucket: String,
+    prefix: String,
+    base_url: String,
+    client: Client,
+    credentials: Credentials,
+    token: OnceCell<Option<String>>,
+    limits: Limits,
+}
+
+impl GcsRemote {
+    /// `endpoint` replaces the storage URL, e.g. for an emulator; so does
+    /// STORAGE_EMULATOR_HOST
+    pub fn new(
+        bucket: &str,
+        prefix: Option<&str>,
+        endpoint: Option<&str>,
+        proxy: Option<&str>,
+    ) -> Result<Self> {
+        let mut builder = Client::builder()
+            // No overall timeout: a rate-limited upload may take a while
+            .connect_timeout(Duration::from_secs(30));
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy)
+                    .with_context(|| format!("Invalid proxy URL: {}", proxy))?,
+            );
+        }
+        let client = builder.build().context("Failed to create HTTP client")?;
+
+        let base_url = endpoint
+            .map(str::to_string)
+            .or_else(|| std::env::var("STORAGE_EMULATOR_HOST").ok())
+            .unwrap_or_else(|| STORAGE_URL.to_string());
+        let base_url = if base_url.contains("://") {
+            base_url
+        } else {
+            format!("http://{}", base_url)
+        };
+
+        Ok(Self {
+            bucket: bucket.to_string(),
+            prefix: prefix.unwrap_or("dotdipper").trim_matches('/').to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client,
+            credentials: Credentials::discover()?,
+            token: OnceCell::new(),
+            limits: Limits::default(),
+        })
+    }
+
+    /// Transfer bundles at no more than the configured rates
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Add the access token to `request`, fetching it on first use
+    async fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        let token = self
+            .token
+            .get_or_try_init(|| fetch_token(&self.client, &self.credentials))
+            .await?;
+        Ok(match token {
+            Some(token) => request.bearer_auth(token),
+            None => request,
+        })
+    }
+
+    fn object_name(&self, filename: &str) -> String {
+        if self.prefix.is_empty() {
+            filename.to_string()
+        } else {
+            format!("{}/{}", self.prefix, filename)
+        }
+    }
+
+    /// `<base>/<api>/storage/v1/b/<bucket>/o[/<object>]`, with the object
+    /// name as one escaped path segment
+    fn url(&self, api: Option<&str>, object: Option<&str>) -> Result<Url> {
+        let mut url = Url::parse(&self.base_url)
+            .with_context(|| format!("Invalid GCS endpoint: {}", self.base_url))?;
+        {
+            let mut segments = url
+                .path_segments_mut()
+                .map_err(|_| anyhow::anyhow!("Invalid GCS endpoint: {}", self.base_url))?;
+            segments.pop_if_empty();
+            segments.extend(api);
+            segments.extend(["storage", "v1", "b", &self.bucket, "o"]);
+            segments.extend(object);
+        }
+        Ok(url)
+    }
+
+    /// Upload `path` as `filename` in a resumable session, streamed at the
+    /// upload rate. An interrupted upload of the same bundle continues from
+    /// the last byte GCS received.
+    async fn upload(&self, filename: &str, path: &Path) -> Result<RemoteObject> {
+        let name = self.object_name(filename);
+        let size = tokio::fs::metadata(path)
+            .await
+            .context("Failed to read bundle file")?
+            .len();
+
+        let (session, offset) = match UploadState::load(path, &name) {
+            Some(state) => match self.received(&state.session, size).await? {
+                Received::Bytes(offset) => (state.session, offset),
+                Received::Complete(object) => {
+                    UploadState::clear(path);
+                    return Ok(object);
+                }
+            },
+            None => {
+                let session = self.start_session(&name, size).await?;
+                UploadState {
+                    key: name.clone(),
+                    session: session.clone(),
+                    parts: Vec::new(),
+                }
+                .save(path)?;
+                (session, 0)
+            }
+        };
+
+        let mut file = tokio::fs::File::open(path)
+            .await
+            .context("Failed to read bundle file")?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let progress = crate::ui::transfer_bar(size, "Uploading");
+        progress.set_position(offset);
+        let stream = tokio_util::io::ReaderStream::new(
+            Throttled::new(file, self.limits.upload).with_progress(&progress),
+        );
+        let range = match size {
+            0 => "bytes */0".to_string(),
+            _ => format!("bytes {}-{}/{}", offset, size - 1, size),
+        };
+        let response = self
+            .authorize(self.client.put(&session))
+            .await?
+            .header(reqwest::header::CONTENT_RANGE, range)
+            .header(reqwest::header::CONTENT_LENGTH, size - offset)
+            .body(Body::wrap_stream(stream))
+            .send()
+            .await
+            .context("Failed to upload bundle to GCS")?;
+        progress.finish_and_clear();
+        if !response.status().is_success() {
+            bail!(
+                "Upload to GCS failed: {} {}",
+                response.status(),
+                response.text().await.unwrap_or_default().trim()
+            );
+        }
+        UploadState::clear(path);
+        object_from(response, size).await
+    }
+
+    /// Open a resumable upload session for `name`, returning its URI
+    async fn start_session(&self, name: &str, size: u64) -> Result<String> {
+        let mut url = self.url(Some("upload"), None)?;
+        url.query_pairs_mut()
+            .append_pair("uploadType", "resumable")
+            .append_pair("name", name);
+        let response = self
+            .authorize(self.client.post(url))
+            .await?
+            .header("X-Upload-Content-Type", "application/octet-stream")
+            .header("X-Upload-Content-Length", size)
+            .header(reqwest::header::CONTENT_LENGTH, 0)
+            .send()
+            .await
+            .context("Failed to start GCS upload")?;
+        if !response.status().is_success() {
+            bail!(
+                "Starting the upload to GCS failed: {} {}",
+                response.status(),
+                response.text().await.unwrap_or_default().trim()
+            );
+        }
+        response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .context("GCS did not return an upload session")
+    }
+
+    /// How much of a `size` byte upload the session has received
+    async fn received(&self, session: &str, size: u64) -> Result<Received> {
+        let response = self
+            .authorize(self.client.put(session))
+            .await?
+            .header(reqwest::header::CONTENT_RANGE, format!("bytes */{}", size))
+            .header(reqwest::header::CONTENT_LENGTH, 0)
+            .send()
+            .await
+            .context("Failed to resume GCS upload")?;
+        match response.status().as_u16() {
+            200 | 201 => Ok(Received::Complete(object_from(response, size).await?)),
+            // "Range: bytes=0-<last byte received>", absent before the first byte
+            308 => Ok(Received::Bytes(
+                response
+                    .headers()
+                    .get(reqwest::header::RANGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| v.rsplit('-').next()?.parse::<u64>().ok())
+                    .map_or(0, |last| last + 1),
+            )),
+            status => bail!(
+                "The interrupted GCS upload can no longer be resumed ({}); retrying starts over",
+                status
+            ),
+        }
+    }
+
+    /// Copy `from` to `to` within the bucket, without downloading it
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let mut url = self.url(None, Some(&self.object_name(from)))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Invalid GCS endpoint: {}", self.base_url))?
+            .extend(["copyTo", "b", &self.bucket, "o", &self.object_name(to)]);
+        let response = self
+            .authorize(self.client.post(url))
+            .await?
+            .header(reqwest::header::CONTENT_LENGTH, 0)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            bail!("Copy to {} failed: {}", to, response.status());
+        }
+        Ok(())
+    }
+
+    /// GET `filename`'s contents from byte `offset`, only if it is still
+    /// `generation` when given; the response may be a 404
+    async fn get(&self, filename: &str, offset: u64, generation: Option<&str>) -> Result<Response> {
+        let mut url = self.url(None, Some(&self.object_name(filename)))?;
+        url.query_pairs_mut().append_pair("alt", "media");
+        if let Some(generation) = generation {
+            url.query_pairs_mut()
+                .append_pair("ifGenerationMatch", generation);
+        }
+        let mut request = self.authorize(self.client.get(url)).await?;
+        if offset > 0 {
+            request = request.header(reqwest::header::RANGE, format!("bytes={}-", offset));
+        }
+        request
+            .send()
+            .await
+            .context("Failed to download bundle from GCS")
+    }
+
+    /// Download `filename` to `dest`, continuing an interrupted download of
+    /// the same generation. `None` if the object does not exist.
+    async fn download(&self, filename: &str, dest: &Path) -> Result<Option<RemoteObject>> {
+        let mut partial = Partial::resume(dest, &self.object_name(filename))?;
+        let mut response = match partial.etag.clone().filter(|_| partial.offset > 0) {
+            Some(generation) => {
+                let response = self
+                    .get(filename, partial.offset, Some(&generation))
+                    .await?;
+                // The object changed since; start over
+                if response.status() == StatusCode::PRECONDITION_FAILED {
+                    self.get(filename, 0, None).await?
+                } else {
+                    response
+                }
+            }
+            None => self.get(filename, 0, None).await?,
+        };
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            bail!("Download of {} failed: {}", filename, response.status());
+        }
+
+        let generation = response
+            .headers()
+            .get("x-goog-generation")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+        let restart = response.status() != StatusCode::PARTIAL_CONTENT;
+        let file = tokio::fs::File::from_std(partial.open(Some(&generation), restart)?);
+        let progress = crate::ui::transfer_bar(
+            partial.offset + response.content_length().unwrap_or_default(),
+            "Downloading",
+        );
+        progress.set_position(partial.offset);
+        let mut writer = Throttled::new(file, self.limits.download).with_progress(&progress);
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .context("Failed to read response body")?
+        {
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        progress.finish_and_clear();
+
+        Ok(Some(RemoteObject {
+            etag_or_rev: generation,
+            size_bytes: partial.finish()?,
+        }))
+    }
+
+    /// Bundles under the prefix, as (file name, size)
+    async fn list_objects(&self) -> Result<Vec<(String, u64)>> {
+        let prefix = self.object_name("");
+        let mut bundles = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut url = self.url(None, None)?;
+            url.query_pairs_mut().append_pair("prefix", &prefix);
+            if let Some(token) = &page_token {
+                url.query_pairs_mut().append_pair("pageToken", token);
+            }
+            let response = self
+                .authorize(self.client.get(url))
+                .await?
+                .send()
+                .await
+                .context("Failed to list GCS objects")?;
+            if !response.status().is_success() {
+                bail!(
+                    "Listing GCS bucket {} failed: {}",
+                    self.bucket,
+                    response.status()
+                );
+            }
+            let page: ObjectList = response
+                .json()
+                .await
+                .context("Unexpected response listing GCS objects")?;
+
+            for object in page.items {
+                // Only bundles directly under the prefix, not the "latest" copy
+                let Some(name) = object.name.strip_prefix(&prefix) else {
+                    continue;
+                };
+                if name.ends_with(".tar.zst") && !name.contains('/') && name != super::LATEST_BUNDLE
+                {
+                    bundles.push((name.to_string(), object.size.parse().unwrap_or(0)));
+                }
+            }
+
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+        Ok(bundles)
+    }
+}
+
+#[async_trait]
+impl Remote for GcsRemote {
+    fn name(&self) -> &str {
+        "GCS"
+    }
+
+    async fn push_bundle(&self, bundle_path: &Path) -> Result<RemoteObject> {
+        // Bundles are named bundle_<timestamp>_<host>.tar.zst by the caller
+        let remote_filename = bundle_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid bundle path")?;
+
+        crate::ui::info(&format!(
+            "Uploading to GCS: gs://{}/{}",
+            self.bucket,
+            self.object_name(remote_filename)
+        ));
+        let object = self.upload(remote_filename, bundle_path).await?;
+
+        // Also copy to "latest" on the server; don't fail if that doesn't work
+        let _ = self.copy(remote_filename, super::LATEST_BUNDLE).await;
+
+        Ok(object)
+    }
+
+    async fn pull_latest(&self, dest_bundle: &Path) -> Result<RemoteObject> {
+        if let Some(object) = self.download(super::LATEST_BUNDLE, dest_bundle).await? {
+            return Ok(object);
+        }
+
+        // Fall back to the newest timestamped bundle
+        let bundles = self.list_bundles().await?;
+        let newest = bundles.first().with_context(|| {
+            format!(
+                "No bundles found in gs://{}/{}",
+                self.bucket,
+                self.object_name("")
+            )
+        })?;
+        crate::ui::info(&format!("Downloading: {}", newest.name));
+        self.pull_bundle(&newest.name, dest_bundle).await
+    }
+
+    async fn list_bundles(&self) -> Result<Vec<BundleEntry>> {
+        let mut bundles: Vec<BundleEntry> = self
+            .list_objects()
+            .await?
+            .into_iter()
+            .map(|(name, size)| BundleEntry::from_name(&name, size))
+            .collect();
+        super::sort_bundles(&mut bundles);
+        Ok(bundles)
+    }
+
+    async fn pull_bundle(&self, name: &str, dest_bundle: &Path) -> Result<RemoteObject> {
+        if name.contains('/') {
+            bail!("Invalid bundle name: {}", name);
+        }
+        self.download(name, dest_bundle)
+            .await?
+            .with_context(|| format!("Download of {} failed: {}", name, StatusCode::NOT_FOUND))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    type Bucket = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+    /// Serve the few JSON API calls the backend makes from an in-memory
+    /// bucket, including resumable uploads and ranged downloads
+    fn fake_gcs() -> (String, Bucket) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let objects = Bucket::default();
+        let store = objects.clone();
+        let session_base = address.clone();
+        std::thread::spawn(move || {
+            // Upload sessions: object name and the bytes received so far
+            let mut sessions: Vec<(String, Vec<u8>)> = Vec::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut headers = HashMap::new();
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        headers.insert(name.to_lowercase(), value.trim().to_string());
+                    }
+                }
+                let length = headers
+                    .get("content-length")
+                    .map_or(0, |v| v.parse().unwrap());
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+
+                let target = request_line.split(' ').nth(1).unwrap();
+                let url = Url::parse(&format!("http://fake{}", target)).unwrap();
+                let query: HashMap<String, String> = url.query_pairs().into_owned().collect();
+                let segments: Vec<String> =
+                    url.path_segments().unwrap().map(percent_decode).collect();
+                let mut store = store.lock().unwrap();
+                let info = |name: &str, size: usize| {
+                    format!(
+                        r#"{{"name":"{}","size":"{}","generation":"17"}}"#,
+                        name, size
+                    )
+                };
+                let mut extra = String::new();
+                let (status, response) = if request_line.starts_with("POST")
+                    && query.get("uploadType").map(String::as_str) == Some("resumable")
+                {
+                    sessions.push((query["name"].clone(), Vec::new()));
+                    extra = format!(
+                        "Location: {}/session/{}\r\n",
+                        session_base,
+                        sessions.len() - 1
+                    );
+                    ("200 OK", Vec::new())
+                } else if request_line.starts_with("POST") {
+                    // <object>/copyTo/b/<bucket>/o/<object>
+                    let data = store[&segments[5]].clone();
+                    let response = info(&segments[10], data.len()).into_bytes();
+                    store.insert(segments[10].clone(), data);
+                    ("200 OK", response)
+                } else if request_line.starts_with("PUT") {
+                    let (name, received) = &mut sessions[segments[1].parse::<usize>().unwrap()];
+                    let range = &headers["content-range"];
+                    let total: usize = range.rsplit('/').next().unwrap().parse().unwrap();
+                    received.extend(body);
+                    if received.len() < total {
+                        if !received.is_empty() {
+                            extra = format!("Range: bytes=0-{}\r\n", received.len() - 1);
+                        }
+                        ("308 Resume Incomplete", Vec::new())
+                    } else {
+                        store.insert(name.clone(), received.clone());
+                        ("200 OK", info(name, total).into_bytes())
+                    }
+                } else if segments.len() > 5 {
+                    extra = "x-goog-generation: 17\r\n".to_string();
+                    match (store.get(&segments[5]), headers.get("range")) {
+                        (Some(data), Some(range)) => {
+                            let start: usize = range
+                                .trim_start_matches("bytes=")
+                                .trim_end_matches('-')
+                                .parse()
+                                .unwrap();
+                            ("206 Partial Content", data[start..].to_vec())
+                        }
+                        (Some(data), None) => ("200 OK", data.clone()),
+                        (None, _) => ("404 Not Found", Vec::new()),
+                    }
+                } else {
+                    let items: Vec<String> = store
+                        .iter()
+                        .filter(|(name, _)| name.starts_with(&query["prefix"]))
+                        .map(|(name, data)| {
+                            format!(r#"{{"name":"{}","size":"{}"}}"#, name, data.len())
+                        })
+                        .collect();
+                    (
+                        "200 OK",
+                        format!(r#"{{"items":[{}]}}"#, items.join(",")).into_bytes(),
+                    )
+                };
+                write!(
+                    stream,
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    extra,
+                    response.len()
+                )
+                .unwrap();
+                stream.write_all(&response).unwrap();
+            }
+        });
+        (address, objects)
+    }
+
+    fn percent_decode(s: &str) -> String {
+        s.replace("%2F", "/")
+    }
+
+    #[test]
+    fn test_push_list_and_pull_against_a_fake_bucket() {
+        let (address, objects) = fake_gcs();
+        let mut remote = GcsRemote::new("dots", None, Some(&address), None).unwrap();
+        remote.credentials = Credentials::Anonymous;
+
+        let temp = tempfile::tempdir().unwrap();
+        let bundle = temp.path().join("bundle_20250301_093000_mbp.tar.zst");
+        std::fs::write(&bundle, b"bundle contents").unwrap();
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let pushed = runtime.block_on(remote.push_bundle(&bundle)).unwrap();
+        assert_eq!(pushed.etag_or_rev, "17");
+        assert_eq!(pushed.size_bytes, 15);
+        assert!(objects
+            .lock()
+            .unwrap()
+            .contains_key("dotdipper/bundle_20250301_093000_mbp.tar.zst"));
+
+        let bundles = runtime.block_on(remote.list_bundles()).unwrap();
+        assert_eq!(bundles.len(), 1);
+        assert_eq!(bundles[0].host.as_deref(), Some("mbp"));
+
+        let dest = temp.path().join("pulled.tar.zst");
+        runtime.block_on(remote.pull_latest(&dest)).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"bundle contents");
+
+        // Without "latest", the newest timestamped bundle is pulled
+        objects.lock().unwrap().remove("dotdipper/latest.tar.zst");
+        std::fs::remove_file(&dest).unwrap();
+        runtime.block_on(remote.pull_latest(&dest)).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"bundle contents");
+        assert!(runtime
+            .block_on(remote.pull_bundle("../x.tar.zst", &dest))
+            .is_err());
+    }
+
+    #[test]
+    fn test_interrupted_transfers_resume() {
+        let (address, objects) = fake_gcs();
+        let mut remote = GcsRemote::new("dots", None, Some(&address), None).unwrap();
+        remote.credentials = Credentials::Anonymous;
+        let temp = tempfile::tempdir().unwrap();
+        let bundle = temp.path().join("bundle_20250301_093000_mbp.tar.zst");
+        std::fs::write(&bundle, b"bundle contents").unwrap();
+        let name = "dotdipper/bundle_20250301_093000_mbp.tar.zst";
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+
+        // An upload that stopped after 7 bytes sends only the rest
+        let session = runtime.block_on(remote.start_session(name, 15)).unwrap();
+        let response = runtime
+            .block_on(
+                remote
+                    .client
+                    .put(&session)
+                    .header(reqwest::header::CONTENT_RANGE, "bytes 0-6/15")
+                    .body("bundle ")
+                    .send(),
+            )
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 308);
+        UploadState {
+            key: name.to_string(),
+            session,
+            parts: Vec::new(),
+        }
+        .save(&bundle)
+        .unwrap();
+        runtime.block_on(remote.push_bundle(&bundle)).unwrap();
+        assert_eq!(objects.lock().unwrap()[name], b"bundle contents");
+        assert!(UploadState::load(&bundle, name).is_none());
+
+        // A download with the first bytes on disk asks for the rest only
+        let dest = temp.path().join("pulled.tar.zst");
+        let mut partial = Partial::resume(&dest, name).unwrap();
+        std::io::Write::write_all(&mut partial.open(Some("17"), false).unwrap(), b"bund").unwrap();
+        objects
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), b"XXXXle contents".to_vec());
+        runtime
+            .block_on(remote.pull_bundle("bundle_20250301_093000_mbp.tar.zst", &dest))
+            .unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), b"bundle contents");
+    }
+
+    #[test]
+    fn test_credentials_files_parse() {
+        let user: CredentialsFile = serde_json::from_str(
+            r#"{"type":"authorized_user","client_id":"id","client_secret":"s","refresh_token":"r"}"#,
+        )
+        .unwrap();
+        assert!(matches!(user, CredentialsFile::AuthorizedUser { .. }));
+
+        let account: CredentialsFile = serde_json::from_str(
+            r#"{"type":"service_account","client_email":"a@p.iam.gserviceaccount.com","private_key":"k"}"#,
+        )
+        .unwrap();
+        let CredentialsFile::ServiceAccount { token_uri, .. } = account else {
+            panic!("expected a service account");
+        };
+        assert_eq!(token_uri, TOKEN_URL);
+        assert!(service_account_assertion("a", "not a key", TOKEN_URL).is_err());
+    }
+}
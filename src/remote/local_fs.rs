@@ -3,12 +3,16 @@ use anyhow::{Context, Result};
 /// Useful for testing and local backups
 use async_trait::async_trait;
 use std::fs;
+use std::io::{Seek, SeekFrom};
 use std::path::{Path, PathBuf};
 
-use super::{Remote, RemoteObject};
+use super::throttle::{Limits, Throttled};
+use super::transfer::Partial;
+use super::{BundleEntry, Remote, RemoteObject};
 
 pub struct LocalFsRemote {
     storage_dir: PathBuf,
+    limits: Limits,
 }
 
 impl LocalFsRemote {
@@ -19,10 +23,50 @@ impl LocalFsRemote {
         fs::create_dir_all(&path)
             .with_context(|| format!("Failed to create storage directory: {}", path.display()))?;
 
-        Ok(Self { storage_dir: path })
+        Ok(Self {
+            storage_dir: path,
+            limits: Limits::default(),
+        })
+    }
+
+    /// Copy bundles at no more than the configured rates
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
     }
 }
 
+/// Copy `source` to `dest` at no more than `bytes_per_sec`, continuing an
+/// interrupted copy of the same file
+fn copy_resuming(source: &Path, dest: &Path, bytes_per_sec: Option<u64>) -> Result<u64> {
+    let metadata =
+        fs::metadata(source).with_context(|| format!("Failed to open {}", source.display()))?;
+    // Size and modification time stand in for an ETag
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let etag = format!("{}-{}", metadata.len(), mtime.as_nanos());
+
+    let mut partial = Partial::resume(dest, &source.to_string_lossy())?;
+    let restart = !partial.matches(Some(&etag));
+    let mut writer = partial.open(Some(&etag), restart)?;
+    let mut reader = fs::File::open(source)?;
+    reader.seek(SeekFrom::Start(partial.offset))?;
+
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+    let progress = crate::ui::transfer_bar(metadata.len(), &name);
+    progress.set_position(partial.offset);
+    std::io::copy(
+        &mut Throttled::new(reader, bytes_per_sec).with_progress(&progress),
+        &mut writer,
+    )
+    .with_context(|| format!("Failed to copy {} to {}", source.display(), dest.display()))?;
+    progress.finish_and_clear();
+    partial.finish()
+}
+
 #[async_trait]
 impl Remote for LocalFsRemote {
     fn name(&self) -> &str {
@@ -35,7 +79,7 @@ impl Remote for LocalFsRemote {
         let dest_path = self.storage_dir.join(filename);
 
         // Copy bundle to storage
-        fs::copy(bundle_path, &dest_path)
+        copy_resuming(bundle_path, &dest_path, self.limits.upload)
             .with_context(|| format!("Failed to copy bundle to {}", dest_path.display()))?;
 
         let metadata = fs::metadata(&dest_path)?;
@@ -78,7 +122,7 @@ impl Remote for LocalFsRemote {
         let latest = &bundles[0];
 
         // Copy to destination
-        fs::copy(latest, dest_bundle)
+        copy_resuming(latest, dest_bundle, self.limits.download)
             .with_context(|| format!("Failed to copy bundle from {}", latest.display()))?;
 
         let metadata = fs::metadata(dest_bundle)?;
@@ -88,6 +132,43 @@ impl Remote for LocalFsRemote {
             size_bytes: metadata.len(),
         })
     }
+
+    async fn list_bundles(&self) -> Result<Vec<BundleEntry>> {
+        let mut bundles = Vec::new();
+
+        for entry in fs::read_dir(&self.storage_dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !path.is_file() || !name.ends_with(".tar.zst") || name == super::LATEST_BUNDLE {
+                continue;
+            }
+
+            let metadata = entry.metadata()?;
+            let mut bundle = BundleEntry::from_name(&name, metadata.len());
+            if bundle.timestamp.is_none() {
+                bundle.timestamp = metadata.modified().ok().map(Into::into);
+            }
+            bundles.push(bundle);
+        }
+
+        super::sort_bundles(&mut bundles);
+        Ok(bundles)
+    }
+
+    async fn pull_bundle(&self, name: &str, dest_bundle: &Path) -> Result<RemoteObject> {
+        if name.contains('/') || name.contains("..") {
+            anyhow::bail!("Invalid bundle name: {}", name);
+        }
+        let source = self.storage_dir.join(name);
+        copy_resuming(&source, dest_bundle, self.limits.download)
+            .with_context(|| format!("Failed to copy bundle from {}", source.display()))?;
+
+        Ok(RemoteObject {
+            etag_or_rev: format!("local:{}", source.display()),
+            size_bytes: fs::metadata(dest_bundle)?.len(),
+        })
+    }
 }
 
 #[cfg(test)]
@@ -120,5 +201,18 @@ mod tests {
             .unwrap();
         assert!(obj2.size_bytes > 0);
         assert!(download_path.exists());
+
+        // List and pull by name
+        let bundles = runtime.block_on(remote.list_bundles()).unwrap();
+        assert_eq!(bundles.len(), 1);
+        assert_eq!(bundles[0].name, "test.tar.zst");
+        let picked = temp_bundle.path().join("picked.tar.zst");
+        runtime
+            .block_on(remote.pull_bundle("test.tar.zst", &picked))
+            .unwrap();
+        assert!(picked.exists());
+        assert!(runtime
+            .block_on(remote.pull_bundle("../escape.tar.zst", &picked))
+            .is_err());
     }
 }
@@ -0,0 +1,316 @@
+//! Delta sync for LocalFS remotes.
+//!
+//! With `[remote] delta = true`, push mirrors the profile's compiled tree
+//! to `<endpoint>/mirror/<profile>/` instead of packing a bundle. The
+//! mirror's `meta.json` records the BLAKE3 hash of every file, so only the
+//! files whose hash changed are copied and files that are gone are deleted;
+//! `manifest.lock` and `meta.json` are written last. Pull likewise copies
+//! only the files that differ from the local compiled tree.
+
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::bundle::{self, BundleMeta, Extracted};
+use super::throttle::Throttled;
+
+/// What a push or pull copied, or would copy in a dry run
+#[derive(Debug, Default)]
+pub struct Delta {
+    pub copied: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub unchanged: usize,
+    pub bytes: u64,
+}
+
+/// Directory a profile is mirrored to under `storage_dir`
+pub fn dir(storage_dir: &Path, profile_name: &str) -> PathBuf {
+    storage_dir.join("mirror").join(profile_name)
+}
+
+/// Metadata of the last push to a mirror, if there was one
+pub fn load_meta(mirror: &Path) -> Option<BundleMeta> {
+    serde_json::from_str(&fs::read_to_string(mirror.join("meta.json")).ok()?).ok()
+}
+
+/// Symlinks under `dir` with their targets, by relative path
+fn symlinks(dir: &Path) -> BTreeMap<PathBuf, PathBuf> {
+    WalkDir::new(dir)
+        .into_iter()
+        .flatten()
+        .filter(|e| e.path_is_symlink())
+        .filter_map(|e| {
+            let rel = e.path().strip_prefix(dir).ok()?.to_path_buf();
+            Some((rel, fs::read_link(e.path()).ok()?))
+        })
+        .collect()
+}
+
+/// Make `to` hold the files of `from` listed in `wanted`, copying those
+/// whose hash in `have` differs. Symlinks are recreated when their target
+/// changed, and anything in `to` that `from` lacks is deleted.
+fn sync_tree(
+    from: &Path,
+    to: &Path,
+    wanted: &BTreeMap<PathBuf, String>,
+    have: &BTreeMap<PathBuf, String>,
+    bytes_per_sec: Option<u64>,
+    dry_run: bool,
+) -> Result<Delta> {
+    let mut delta = Delta::default();
+    let changed: Vec<&PathBuf> = wanted
+        .iter()
+        .filter(|(rel, hash)| have.get(*rel) != Some(*hash) || !to.join(rel).is_file())
+        .map(|(rel, _)| rel)
+        .collect();
+    delta.unchanged = wanted.len() - changed.len();
+    for rel in &changed {
+        delta.bytes += fs::metadata(from.join(rel))?.len();
+    }
+
+    let links = symlinks(from);
+    let stale_links: Vec<&PathBuf> = links
+        .iter()
+        .filter(|(rel, target)| fs::read_link(to.join(rel)).ok().as_ref() != Some(*target))
+        .map(|(rel, _)| rel)
+        .collect();
+
+    // Anything in the destination the source no longer has
+    if to.is_dir() {
+        for entry in WalkDir::new(to).min_depth(1).contents_first(true) {
+            let entry = entry?;
+            let rel = entry.path().strip_prefix(to)?;
+            let keep = if entry.file_type().is_dir() {
+                from.join(rel).is_dir()
+            } else {
+                wanted.contains_key(rel) || links.contains_key(rel)
+            };
+            if keep {
+                continue;
+            }
+            if !entry.file_type().is_dir() {
+                delta.removed.push(rel.to_path_buf());
+            }
+            if !dry_run {
+                remove(entry.path())?;
+            }
+        }
+    }
+
+    delta.copied = changed.into_iter().cloned().collect();
+    delta
+        .copied
+        .extend(stale_links.iter().map(|rel| rel.to_path_buf()));
+    if dry_run {
+        return Ok(delta);
+    }
+
+    let progress = crate::ui::transfer_bar(delta.bytes, "delta sync");
+    for rel in &delta.copied {
+        let dest = to.join(rel);
+        if let Some(target) = links.get(rel) {
+            replace_with_symlink(target, &dest)?;
+        } else {
+            copy_file(&from.join(rel), &dest, bytes_per_sec, &progress)?;
+        }
+    }
+    progress.finish_and_clear();
+    Ok(delta)
+}
+
+/// Remove a file, link or empty directory
+fn remove(path: &Path) -> Result<()> {
+    let is_dir = fs::symlink_metadata(path).is_ok_and(|m| m.is_dir());
+    let result = match is_dir {
+        true => fs::remove_dir(path),
+        false => fs::remove_file(path),
+    };
+    result.with_context(|| format!("Failed to remove {}", path.display()))
+}
+
+/// Clear whatever is at `dest` unless it is a regular file, and create its
+/// parent directories
+fn prepare(dest: &Path) -> Result<()> {
+    if let Ok(metadata) = fs::symlink_metadata(dest) {
+        if metadata.is_dir() {
+            fs::remove_dir_all(dest)?;
+        } else if metadata.is_symlink() {
+            fs::remove_file(dest)?;
+        }
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// Copy `source` over `dest` through a temporary file, so `dest` is never
+/// left half-written
+fn copy_file(
+    source: &Path,
+    dest: &Path,
+    bytes_per_sec: Option<u64>,
+    progress: &ProgressBar,
+) -> Result<()> {
+    prepare(dest)?;
+    let parent = dest.parent().context("Invalid mirror path")?;
+    let mut staged = tempfile::NamedTempFile::new_in(parent)?;
+    let reader =
+        File::open(source).with_context(|| format!("Failed to read {}", source.display()))?;
+    std::io::copy(
+        &mut Throttled::new(reader, bytes_per_sec).with_progress(progress),
+        staged.as_file_mut(),
+    )
+    .with_context(|| format!("Failed to copy {} to {}", source.display(), dest.display()))?;
+    fs::set_permissions(staged.path(), fs::metadata(source)?.permissions())?;
+    staged.persist(dest)?;
+    Ok(())
+}
+
+fn replace_with_symlink(target: &Path, dest: &Path) -> Result<()> {
+    prepare(dest)?;
+    if dest.exists() {
+        fs::remove_file(dest)?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, dest)
+        .with_context(|| format!("Failed to create symlink {}", dest.display()))?;
+    Ok(())
+}
+
+/// Write `contents` to `path` through a temporary file
+fn write_atomic(path: &Path, contents: &[u8]) -> Result<()> {
+    let parent = path.parent().context("Invalid mirror path")?;
+    fs::create_dir_all(parent)?;
+    let mut staged = tempfile::NamedTempFile::new_in(parent)?;
+    std::io::Write::write_all(&mut staged, contents)?;
+    staged.persist(path)?;
+    Ok(())
+}
+
+/// Bring the mirror of `profile_name` up to date with `compiled_root` and
+/// `manifest_path`
+pub fn push(
+    mirror: &Path,
+    compiled_root: &Path,
+    manifest_path: &Path,
+    profile_name: &str,
+    bytes_per_sec: Option<u64>,
+    dry_run: bool,
+) -> Result<Delta> {
+    let meta = bundle::describe(compiled_root, profile_name)?;
+    let previous = load_meta(mirror).map(|m| m.checksums).unwrap_or_default();
+    let delta = sync_tree(
+        compiled_root,
+        &mirror.join("compiled"),
+        &meta.checksums,
+        &previous,
+        bytes_per_sec,
+        dry_run,
+    )?;
+    if !dry_run {
+        write_atomic(&mirror.join("manifest.lock"), &fs::read(manifest_path)?)?;
+        write_atomic(
+            &mirror.join("meta.json"),
+            serde_json::to_string_pretty(&meta)?.as_bytes(),
+        )?;
+    }
+    Ok(delta)
+}
+
+/// Assemble the mirror of a profile as an extracted bundle, copying from
+/// the mirror only the files that differ from `local_compiled`
+pub fn fetch(
+    mirror: &Path,
+    local_compiled: &Path,
+    bytes_per_sec: Option<u64>,
+) -> Result<(Delta, Extracted)> {
+    let meta = load_meta(mirror).with_context(|| {
+        format!(
+            "Nothing has been pushed to {} yet. Run 'dotdipper remote push' first",
+            mirror.display()
+        )
+    })?;
+    let temp_dir = tempfile::tempdir()?;
+    let root = temp_dir.path().join("dotdipper_bundle");
+    let compiled = root.join("compiled");
+
+    // Unchanged files come from the local tree, the rest from the mirror
+    let local = match local_compiled.is_dir() {
+        true => bundle::checksums(local_compiled)?,
+        false => BTreeMap::new(),
+    };
+    let progress = ProgressBar::hidden();
+    for (rel, hash) in &meta.checksums {
+        if local.get(rel) == Some(hash) {
+            copy_file(
+                &local_compiled.join(rel),
+                &compiled.join(rel),
+                None,
+                &progress,
+            )?;
+        }
+    }
+    let delta = sync_tree(
+        &mirror.join("compiled"),
+        &compiled,
+        &meta.checksums,
+        &local,
+        bytes_per_sec,
+        false,
+    )?;
+    fs::copy(mirror.join("manifest.lock"), root.join("manifest.lock"))
+        .with_context(|| format!("Mirror {} has no manifest.lock", mirror.display()))?;
+    fs::copy(mirror.join("meta.json"), root.join("meta.json"))?;
+    Ok((delta, Extracted::open(temp_dir, root)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_copies_only_changed_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let compiled = temp.path().join("compiled");
+        let manifest = temp.path().join("manifest.lock");
+        let mirror = dir(&temp.path().join("storage"), "default");
+        fs::create_dir_all(compiled.join(".config/nvim")).unwrap();
+        fs::write(compiled.join(".zshrc"), "export EDITOR=vim\n").unwrap();
+        fs::write(
+            compiled.join(".config/nvim/init.lua"),
+            "vim.o.number = true\n",
+        )
+        .unwrap();
+        fs::write(compiled.join(".gitconfig"), "[user]\n").unwrap();
+        fs::write(&manifest, "{}").unwrap();
+
+        let delta = push(&mirror, &compiled, &manifest, "default", None, false).unwrap();
+        assert_eq!((delta.copied.len(), delta.unchanged), (3, 0));
+        assert!(mirror.join("meta.json").exists());
+
+        fs::write(compiled.join(".zshrc"), "export EDITOR=nvim\n").unwrap();
+        fs::remove_file(compiled.join(".gitconfig")).unwrap();
+        let delta = push(&mirror, &compiled, &manifest, "default", None, true).unwrap();
+        assert_eq!(delta.copied, vec![PathBuf::from(".zshrc")]);
+        assert_eq!(delta.removed, vec![PathBuf::from(".gitconfig")]);
+        assert!(mirror.join("compiled/.gitconfig").exists(), "dry run");
+
+        push(&mirror, &compiled, &manifest, "default", None, false).unwrap();
+        assert_eq!(
+            fs::read_to_string(mirror.join("compiled/.zshrc")).unwrap(),
+            "export EDITOR=nvim\n"
+        );
+        assert!(!mirror.join("compiled/.gitconfig").exists());
+
+        // Pulling into a tree that only lacks one change copies just that file
+        fs::write(compiled.join(".zshrc"), "export EDITOR=vim\n").unwrap();
+        let (delta, extracted) = fetch(&mirror, &compiled, None).unwrap();
+        assert_eq!(delta.copied, vec![PathBuf::from(".zshrc")]);
+        assert_eq!(delta.unchanged, 1);
+        assert_eq!(extracted.verify().unwrap(), 2);
+    }
+}
@@ -13,14 +13,22 @@ mod s3_backend;
 #[cfg(feature = "webdav")]
 mod webdav_backend;
 
+#[cfg(feature = "gcs")]
+mod gcs_backend;
+
 mod local_fs;
+mod mirror;
+pub mod throttle;
+mod transfer;
 
 use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::path::{Path, PathBuf};
 
 use crate::cfg::Config;
+use crate::offline::RetryPolicy;
 use crate::ui;
 
 /// Remote backend trait
@@ -29,6 +37,10 @@ pub trait Remote: Send + Sync {
     fn name(&self) -> &str;
     async fn push_bundle(&self, bundle_path: &Path) -> Result<RemoteObject>;
     async fn pull_latest(&self, dest_bundle: &Path) -> Result<RemoteObject>;
+    /// Bundles stored on the remote, newest first
+    async fn list_bundles(&self) -> Result<Vec<BundleEntry>>;
+    /// Download one bundle by the name returned from `list_bundles`
+    async fn pull_bundle(&self, name: &str, dest_bundle: &Path) -> Result<RemoteObject>;
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +49,68 @@ pub struct RemoteObject {
     pub size_bytes: u64,
 }
 
+/// Name of the "latest" copy S3, GCS and WebDAV keep next to the timestamped bundles
+pub const LATEST_BUNDLE: &str = "latest.tar.zst";
+
+/// A bundle stored on a remote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundleEntry {
+    pub name: String,
+    pub size_bytes: u64,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub host: Option<String>,
+}
+
+impl BundleEntry {
+    /// Build an entry, reading timestamp and host from `bundle_<ts>[_<host>].tar.zst`
+    pub fn from_name(name: &str, size_bytes: u64) -> Self {
+        let stem = name.strip_suffix(".tar.zst").unwrap_or(name);
+        let rest = stem.strip_prefix("bundle_").unwrap_or("");
+
+        // Timestamp is "YYYYmmdd_HHMMSS" (15 chars), optionally followed by "_<host>"
+        let timestamp = rest
+            .get(..15)
+            .and_then(|ts| NaiveDateTime::parse_from_str(ts, "%Y%m%d_%H%M%S").ok())
+            .map(|ts| ts.and_utc());
+        let host = rest
+            .get(15..)
+            .and_then(|h| h.strip_prefix('_'))
+            .filter(|h| !h.is_empty())
+            .map(str::to_string);
+
+        Self {
+            name: name.to_string(),
+            size_bytes,
+            timestamp,
+            host,
+        }
+    }
+}
+
+/// File name for a new bundle: `bundle_<timestamp>_<host>.tar.zst`
+pub fn bundle_file_name(host: &str) -> String {
+    let host: String = host
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '.' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!(
+        "bundle_{}_{}.tar.zst",
+        Utc::now().format("%Y%m%d_%H%M%S"),
+        host
+    )
+}
+
+/// Sort newest first; bundles without a timestamp go last, by name
+pub fn sort_bundles(bundles: &mut [BundleEntry]) {
+    bundles.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(b.name.cmp(&a.name)));
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum RemoteKind {
     GitHub,
@@ -99,33 +173,79 @@ pub fn set(_config: &Config, kind_str: &str, options: Vec<(String, String)>) ->
                 );
             }
         }
+        RemoteKind::GCS => {
+            if !opts.contains_key("bucket") {
+                bail!(
+                    "GCS remote requires --bucket.\n\
+                       Example: dotdipper remote set gcs --bucket my-dotfiles"
+                );
+            }
+        }
         RemoteKind::WebDAV => {
             if endpoint.is_none() {
                 bail!("WebDAV remote requires --endpoint (URL).\n\
                        Example: dotdipper remote set webdav --endpoint https://dav.example.com/dotfiles");
             }
         }
-        RemoteKind::GitHub | RemoteKind::GCS => {
-            // GitHub uses vcs module, GCS may have different requirements
+        RemoteKind::GitHub => {
+            // GitHub uses vcs module
         }
     }
 
     // Update config with remote settings
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let config_path = dotdipper_dir.join("config.toml");
+    let config_path = crate::paths::config_file()?;
     let mut cfg = if config_path.exists() {
         crate::cfg::load(&config_path)?
     } else {
         Config::default()
     };
 
+    // Network and encryption settings carry over unless given again
+    let previous = cfg.remote.take();
+    let previous = previous.as_ref();
     let remote_config = crate::cfg::RemoteConfig {
         kind: kind_str.to_lowercase(),
         bucket: opts.get("bucket").cloned(),
         prefix: opts.get("prefix").cloned(),
         region: opts.get("region").cloned(),
         endpoint,
+        proxy: opts
+            .get("proxy")
+            .cloned()
+            .or_else(|| previous.and_then(|p| p.proxy.clone())),
+        upload_limit: opts
+            .get("upload_limit")
+            .cloned()
+            .or_else(|| previous.and_then(|p| p.upload_limit.clone())),
+        download_limit: opts
+            .get("download_limit")
+            .cloned()
+            .or_else(|| previous.and_then(|p| p.download_limit.clone())),
+        encrypt: match opts.get("encrypt") {
+            Some(encrypt) => encrypt == "true",
+            None => previous.is_some_and(|p| p.encrypt),
+        },
+        delta: match opts.get("delta") {
+            Some(delta) => delta == "true",
+            None => previous.is_some_and(|p| p.delta),
+        },
     };
+    throttle::Limits::from_config(&remote_config)?;
+    if remote_config.delta {
+        delta_storage(&remote_config)?;
+        if remote_config.encrypt {
+            bail!("Delta sync copies files as they are and can't be combined with encryption");
+        }
+    }
+    if remote_config.encrypt {
+        let key_path = crate::secrets::age_key_path(&cfg);
+        if !key_path.exists() {
+            bail!(
+                "Encrypted bundles need an age key, but none was found at {}. Run 'dotdipper secrets init' first",
+                key_path.display()
+            );
+        }
+    }
 
     cfg.remote = Some(remote_config);
     crate::cfg::save(&config_path, &cfg)?;
@@ -146,6 +266,13 @@ pub fn set(_config: &Config, kind_str: &str, options: Vec<(String, String)>) ->
         if let Some(ref p) = remote.prefix {
             ui::info(&format!("  Prefix: {}", p));
         }
+        print_network(remote, |line| ui::info(&format!("  {}", line)));
+        if remote.encrypt {
+            ui::info("  Encrypted: yes (age)");
+        }
+        if remote.delta {
+            ui::info("  Delta sync: yes");
+        }
     }
 
     if matches!(kind, RemoteKind::S3) {
@@ -153,6 +280,11 @@ pub fn set(_config: &Config, kind_str: &str, options: Vec<(String, String)>) ->
             "Set credentials via environment variables (AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY)",
         );
     }
+    if matches!(kind, RemoteKind::GCS) {
+        ui::hint(
+            "Set GOOGLE_APPLICATION_CREDENTIALS to a service account key, or run 'gcloud auth application-default login'",
+        );
+    }
 
     Ok(())
 }
@@ -175,6 +307,13 @@ pub fn show(config: &Config) -> Result<()> {
         if let Some(endpoint) = &remote_cfg.endpoint {
             println!("  Endpoint: {}", endpoint);
         }
+        print_network(remote_cfg, |line| println!("  {}", line));
+        if remote_cfg.encrypt {
+            println!("  Encrypted: yes (age)");
+        }
+        if remote_cfg.delta {
+            println!("  Delta sync: yes");
+        }
     } else {
         ui::warn("No remote configured");
         ui::hint("Configure with: dotdipper remote set <kind>");
@@ -183,13 +322,34 @@ pub fn show(config: &Config) -> Result<()> {
     Ok(())
 }
 
+fn print_network(remote: &crate::cfg::RemoteConfig, mut print: impl FnMut(&str)) {
+    if let Some(proxy) = &remote.proxy {
+        print(&format!("Proxy: {}", proxy));
+    }
+    if let Some(limit) = &remote.upload_limit {
+        print(&format!("Upload limit: {}", limit));
+    }
+    if let Some(limit) = &remote.download_limit {
+        print(&format!("Download limit: {}", limit));
+    }
+}
+
 /// Push to remote
 pub async fn push(config: &Config, dry_run: bool) -> Result<()> {
+    push_with_policy(config, dry_run, &RetryPolicy::from_config(config)).await
+}
+
+/// Like [`push`], with an explicit retry policy for the upload
+pub async fn push_with_policy(config: &Config, dry_run: bool, policy: &RetryPolicy) -> Result<()> {
     let remote_cfg = config
         .remote
         .as_ref()
         .context("No remote configured. Run 'dotdipper remote set <kind>' first")?;
 
+    if remote_cfg.delta {
+        return push_delta(remote_cfg, dry_run);
+    }
+
     let remote = create_remote(remote_cfg)?;
 
     ui::info(&format!("Pushing to remote: {}", remote.name()));
@@ -202,34 +362,68 @@ pub async fn push(config: &Config, dry_run: bool) -> Result<()> {
         bail!("No compiled directory found. Run 'dotdipper snapshot' first");
     }
 
-    // Create bundle
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let bundle_path = dotdipper_dir.join("bundle.tar.zst");
-
-    ui::info("Creating bundle...");
-    let meta = bundle::pack(
+    // A push of the same files that failed left its bundle to resume
+    let bundle_dir = bundle_dir()?;
+    let remote_id = remote_id(remote_cfg);
+    let fingerprint = bundle::fingerprint(
         &profile_paths.compiled,
         &profile_paths.manifest,
-        &bundle_path,
         &profile_name,
     )?;
+    let pending = match dry_run {
+        true => None,
+        false => transfer::pending_bundle(&bundle_dir, &remote_id, &fingerprint),
+    };
+    let (bundle_path, meta) = match pending {
+        Some((path, meta)) => {
+            ui::info(&format!(
+                "Resuming the upload of {} from an earlier push",
+                path.display()
+            ));
+            (path, meta)
+        }
+        None => {
+            let hostname = hostname::get()
+                .ok()
+                .and_then(|h| h.into_string().ok())
+                .unwrap_or_else(|| "unknown".to_string());
+            let bundle_path = bundle_dir.join(bundle_file_name(&hostname));
+
+            ui::info("Creating bundle...");
+            let meta = bundle::pack(
+                &profile_paths.compiled,
+                &profile_paths.manifest,
+                &bundle_path,
+                &profile_name,
+            )?;
+
+            let size_str = humansize::format_size(meta.size_bytes, humansize::DECIMAL);
+            ui::success(&format!(
+                "Bundle created: {} ({} files, {})",
+                bundle_path.display(),
+                meta.file_count,
+                size_str
+            ));
+
+            seal(config, remote_cfg, &bundle_path)?;
+
+            if dry_run {
+                ui::info("Dry run - skipping actual push");
+                return Ok(());
+            }
+            transfer::mark_pending(&bundle_path, &remote_id, &fingerprint, &meta)?;
+            (bundle_path, meta)
+        }
+    };
 
-    let size_str = humansize::format_size(meta.size_bytes, humansize::DECIMAL);
-    ui::success(&format!(
-        "Bundle created: {} ({} files, {})",
-        bundle_path.display(),
-        meta.file_count,
-        size_str
-    ));
-
-    if dry_run {
-        ui::info("Dry run - skipping actual push");
-        return Ok(());
-    }
-
-    // Push bundle
+    // Push bundle; on failure it is kept for the next push to resume
     ui::info("Uploading bundle...");
-    let obj = remote.push_bundle(&bundle_path).await?;
+    let obj = policy
+        .run_async("Upload", || remote.push_bundle(&bundle_path))
+        .await
+        .inspect_err(|_| {
+            ui::hint("The upload resumes on the next 'dotdipper remote push' of the same files");
+        })?;
 
     let uploaded_size = humansize::format_size(obj.size_bytes, humansize::DECIMAL);
     ui::success(&format!(
@@ -238,54 +432,312 @@ pub async fn push(config: &Config, dry_run: bool) -> Result<()> {
     ));
 
     // Clean up bundle
-    std::fs::remove_file(&bundle_path)?;
+    transfer::discard_bundle(&bundle_path);
+
+    crate::history::Event::new(
+        crate::history::Kind::RemotePush,
+        format!("Pushed profile {} to {}", profile_name, remote.name()),
+    )
+    .files(meta.file_count)
+    .reference(obj.etag_or_rev)
+    .record();
+    Ok(())
+}
+
+/// Push by mirroring the compiled tree file by file (`[remote] delta`)
+fn push_delta(remote_cfg: &crate::cfg::RemoteConfig, dry_run: bool) -> Result<()> {
+    let storage = delta_storage(remote_cfg)?;
+    let limits = throttle::Limits::from_config(remote_cfg)?;
+    let profile_name = crate::profiles::active_profile_name()?;
+    let profile_paths = crate::profiles::profile_paths(&profile_name)?;
+
+    if !profile_paths.compiled.exists() {
+        bail!("No compiled directory found. Run 'dotdipper snapshot' first");
+    }
+
+    let mirror = mirror::dir(&storage, &profile_name);
+    ui::info(&format!("Syncing changes to {}", mirror.display()));
+    let delta = mirror::push(
+        &mirror,
+        &profile_paths.compiled,
+        &profile_paths.manifest,
+        &profile_name,
+        limits.upload,
+        dry_run,
+    )?;
+
+    let size_str = humansize::format_size(delta.bytes, humansize::DECIMAL);
+    if dry_run {
+        for path in &delta.copied {
+            println!("  + {}", path.display());
+        }
+        for path in &delta.removed {
+            println!("  - {}", path.display());
+        }
+        ui::info(&format!(
+            "Dry run - would copy {} changed file(s) ({}) and remove {}",
+            delta.copied.len(),
+            size_str,
+            delta.removed.len()
+        ));
+        return Ok(());
+    }
+    ui::success(&format!(
+        "Synced {} changed file(s) ({}); {} unchanged, {} removed",
+        delta.copied.len(),
+        size_str,
+        delta.unchanged,
+        delta.removed.len()
+    ));
 
+    crate::history::Event::new(
+        crate::history::Kind::RemotePush,
+        format!("Pushed profile {} to LocalFS", profile_name),
+    )
+    .files(delta.copied.len() + delta.removed.len())
+    .reference(format!("local:{}", mirror.display()))
+    .record();
     Ok(())
 }
 
-/// Pull from remote
-pub async fn pull(config: &Config) -> Result<()> {
+/// Print the bundles available on the remote
+pub async fn list(config: &Config) -> Result<()> {
     let remote_cfg = config.remote.as_ref().context("No remote configured")?;
+    if remote_cfg.delta {
+        return list_mirrors(remote_cfg);
+    }
+    let remote = create_remote(remote_cfg)?;
+
+    let bundles = RetryPolicy::from_config(config)
+        .run_async("List", || remote.list_bundles())
+        .await?;
+    if bundles.is_empty() {
+        ui::warn(&format!("No bundles found on remote: {}", remote.name()));
+        return Ok(());
+    }
+
+    ui::section(&format!("Bundles on {}", remote.name()));
+    let rows = bundles
+        .iter()
+        .map(|b| {
+            vec![
+                b.name.clone(),
+                b.timestamp
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                b.host.clone().unwrap_or_else(|| "-".to_string()),
+                humansize::format_size(b.size_bytes, humansize::DECIMAL),
+            ]
+        })
+        .collect();
+    ui::print_table(&["Bundle", "Created", "Host", "Size"], rows);
+    ui::hint("Restore one with: dotdipper remote pull --rev <bundle> [--only <paths>]");
+
+    Ok(())
+}
+
+/// Print the profiles mirrored to a delta-synced remote
+fn list_mirrors(remote_cfg: &crate::cfg::RemoteConfig) -> Result<()> {
+    let mirrors = delta_storage(remote_cfg)?.join("mirror");
+    let mut rows = Vec::new();
+    for entry in std::fs::read_dir(&mirrors).into_iter().flatten().flatten() {
+        let Some(meta) = mirror::load_meta(&entry.path()) else {
+            continue;
+        };
+        let pushed = DateTime::parse_from_rfc3339(&meta.timestamp)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or(meta.timestamp);
+        rows.push(vec![
+            meta.profile_name,
+            pushed,
+            meta.hostname,
+            meta.file_count.to_string(),
+            humansize::format_size(meta.size_bytes, humansize::DECIMAL),
+        ]);
+    }
+    if rows.is_empty() {
+        ui::warn(&format!(
+            "Nothing has been pushed to {} yet",
+            mirrors.display()
+        ));
+        return Ok(());
+    }
+    rows.sort();
+
+    ui::section(&format!("Profiles mirrored to {}", mirrors.display()));
+    ui::print_table(&["Profile", "Pushed", "Host", "Files", "Size"], rows);
+    Ok(())
+}
+
+/// Download a bundle (the latest unless `pick` is given) and extract it to a
+/// temporary directory. With `verify`, every file must match the checksums
+/// recorded when the bundle was packed.
+pub async fn fetch(config: &Config, pick: Option<&str>, verify: bool) -> Result<bundle::Extracted> {
+    let remote_cfg = config.remote.as_ref().context("No remote configured")?;
+
+    let (extracted, remote_name, reference) = match remote_cfg.delta {
+        true => fetch_delta(remote_cfg, pick)?,
+        false => fetch_bundle(config, remote_cfg, pick).await?,
+    };
+
+    if verify {
+        let checked = extracted.verify()?;
+        ui::success(&format!(
+            "Verified {} file(s) against bundle checksums",
+            checked
+        ));
+    }
+
+    crate::history::Event::new(
+        crate::history::Kind::RemotePull,
+        format!(
+            "Pulled profile {} from {}",
+            extracted.meta.profile_name, remote_name
+        ),
+    )
+    .files(extracted.meta.file_count)
+    .reference(reference)
+    .record();
+    Ok(extracted)
+}
 
+/// Download and extract a bundle, returning it with the remote's name and
+/// the bundle's ETag or revision
+async fn fetch_bundle(
+    config: &Config,
+    remote_cfg: &crate::cfg::RemoteConfig,
+    pick: Option<&str>,
+) -> Result<(bundle::Extracted, String, String)> {
     let remote = create_remote(remote_cfg)?;
+    let policy = RetryPolicy::from_config(config);
 
     ui::info(&format!("Pulling from remote: {}", remote.name()));
 
     // Download bundle
-    let dotdipper_dir = get_dotdipper_dir()?;
-    let bundle_path = dotdipper_dir.join("bundle_download.tar.zst");
-
-    ui::info("Downloading bundle...");
-    let obj = remote.pull_latest(&bundle_path).await?;
+    let bundle_dir = bundle_dir()?;
+    let bundle_path = bundle_dir.join("bundle_download.tar.zst");
+
+    let obj = match pick {
+        Some(input) => {
+            let names: Vec<String> = policy
+                .run_async("List", || remote.list_bundles())
+                .await?
+                .into_iter()
+                .map(|b| b.name)
+                .collect();
+            let name = crate::resolve::resolve("bundle", input, &names)?;
+            if !names.contains(&name) {
+                bail!(
+                    "Bundle '{}' not found. Run 'dotdipper remote list' to see available bundles",
+                    name
+                );
+            }
+            ui::info(&format!("Downloading bundle {}...", name));
+            policy
+                .run_async("Download", || remote.pull_bundle(&name, &bundle_path))
+                .await?
+        }
+        None => {
+            ui::info("Downloading bundle...");
+            policy
+                .run_async("Download", || remote.pull_latest(&bundle_path))
+                .await?
+        }
+    };
 
     let size_str = humansize::format_size(obj.size_bytes, humansize::DECIMAL);
     ui::success(&format!("Downloaded: {} ({})", obj.etag_or_rev, size_str));
 
-    // Extract bundle
-    ui::info("Extracting bundle...");
-    let extracted_meta = bundle::unpack(&bundle_path, &dotdipper_dir)?;
-
-    ui::success(&format!(
-        "Extracted {} files to profile: {}",
-        extracted_meta.file_count, extracted_meta.profile_name
-    ));
+    let extracted = unseal(config, remote_cfg, &bundle_path).and_then(|()| {
+        ui::info("Extracting bundle...");
+        bundle::extract(&bundle_path)
+    });
 
     // Clean up bundle
     std::fs::remove_file(&bundle_path)?;
+    Ok((extracted?, remote.name().to_string(), obj.etag_or_rev))
+}
+
+/// Copy the files of the active profile's mirror that differ locally
+/// (`[remote] delta`)
+fn fetch_delta(
+    remote_cfg: &crate::cfg::RemoteConfig,
+    pick: Option<&str>,
+) -> Result<(bundle::Extracted, String, String)> {
+    if pick.is_some() {
+        bail!(
+            "Delta sync keeps only the latest files; picking a bundle needs [remote] delta = false"
+        );
+    }
+    let profile_name = crate::profiles::active_profile_name()?;
+    let mirror = mirror::dir(&delta_storage(remote_cfg)?, &profile_name);
+    let local = crate::profiles::profile_paths(&profile_name)?.compiled;
+    let limits = throttle::Limits::from_config(remote_cfg)?;
 
-    ui::hint("Apply changes with: dotdipper apply");
+    ui::info(&format!("Pulling changes from {}", mirror.display()));
+    let (delta, extracted) = mirror::fetch(&mirror, &local, limits.download)?;
+    ui::success(&format!(
+        "Copied {} changed file(s) ({}); {} unchanged",
+        delta.copied.len(),
+        humansize::format_size(delta.bytes, humansize::DECIMAL),
+        delta.unchanged
+    ));
+    Ok((
+        extracted,
+        "LocalFS".to_string(),
+        format!("local:{}", mirror.display()),
+    ))
+}
 
-    Ok(())
+/// Pull from remote into the bundle's profile.
+///
+/// `pick` selects a bundle by (partial) name instead of the latest one, and
+/// `only` restricts extraction to matching home-relative paths, which are
+/// merged into the existing compiled directory and manifest. See [`fetch`]
+/// for `verify`.
+pub async fn pull(
+    config: &Config,
+    pick: Option<&str>,
+    only: &[String],
+    verify: bool,
+) -> Result<bundle::BundleMeta> {
+    let extracted = fetch(config, pick, verify).await?;
+    let meta = &extracted.meta;
+
+    if only.is_empty() {
+        extracted.install()?;
+        ui::success(&format!(
+            "Extracted {} files to profile: {}",
+            meta.file_count, meta.profile_name
+        ));
+    } else {
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let matcher = crate::matching::PathMatcher::for_paths(&home, only)?;
+        let restored = extracted.install_selected(&matcher)?;
+        if restored == 0 {
+            ui::warn("No files in the bundle matched --only");
+        } else {
+            ui::success(&format!(
+                "Extracted {} selected file(s) to profile: {}",
+                restored, meta.profile_name
+            ));
+        }
+    }
+
+    Ok(extracted.meta)
 }
 
-fn create_remote(remote_cfg: &crate::cfg::RemoteConfig) -> Result<Box<dyn Remote>> {
+pub(crate) fn create_remote(remote_cfg: &crate::cfg::RemoteConfig) -> Result<Box<dyn Remote>> {
+    let limits = throttle::Limits::from_config(remote_cfg)?;
     match remote_cfg.kind.as_str() {
         "localfs" | "local" => {
             let path = remote_cfg
                 .endpoint
                 .as_ref()
                 .context("LocalFS remote requires 'endpoint' (directory path)")?;
-            Ok(Box::new(local_fs::LocalFsRemote::new(path)?))
+            Ok(Box::new(
+                local_fs::LocalFsRemote::new(path)?.with_limits(limits),
+            ))
         }
         #[cfg(feature = "s3")]
         "s3" => {
@@ -295,9 +747,31 @@ fn create_remote(remote_cfg: &crate::cfg::RemoteConfig) -> Result<Box<dyn Remote
                 .context("S3 remote requires 'bucket'")?;
             let region = remote_cfg.region.as_deref().unwrap_or("us-east-1");
             let prefix = remote_cfg.prefix.as_deref();
-            Ok(Box::new(s3_backend::S3Remote::new_with_prefix(
-                bucket, region, prefix,
-            )?))
+            // rust-s3 builds its HTTP clients internally; they honor the
+            // standard proxy variables
+            if let Some(proxy) = &remote_cfg.proxy {
+                std::env::set_var("HTTPS_PROXY", proxy);
+                std::env::set_var("HTTP_PROXY", proxy);
+            }
+            Ok(Box::new(
+                s3_backend::S3Remote::new_with_prefix(bucket, region, prefix)?.with_limits(limits),
+            ))
+        }
+        #[cfg(feature = "gcs")]
+        "gcs" => {
+            let bucket = remote_cfg
+                .bucket
+                .as_ref()
+                .context("GCS remote requires 'bucket'")?;
+            Ok(Box::new(
+                gcs_backend::GcsRemote::new(
+                    bucket,
+                    remote_cfg.prefix.as_deref(),
+                    remote_cfg.endpoint.as_deref(),
+                    remote_cfg.proxy.as_deref(),
+                )?
+                .with_limits(limits),
+            ))
         }
         #[cfg(feature = "webdav")]
         "webdav" => {
@@ -305,7 +779,10 @@ fn create_remote(remote_cfg: &crate::cfg::RemoteConfig) -> Result<Box<dyn Remote
                 .endpoint
                 .as_ref()
                 .context("WebDAV remote requires 'endpoint' URL")?;
-            Ok(Box::new(webdav_backend::WebDavRemote::new(endpoint)?))
+            Ok(Box::new(
+                webdav_backend::WebDavRemote::new(endpoint, remote_cfg.proxy.as_deref())?
+                    .with_limits(limits),
+            ))
         }
         _ => {
             bail!(
@@ -316,14 +793,102 @@ fn create_remote(remote_cfg: &crate::cfg::RemoteConfig) -> Result<Box<dyn Remote
     }
 }
 
-fn get_dotdipper_dir() -> Result<PathBuf> {
-    crate::paths::base_dir()
+/// Storage directory of a delta-synced remote; only LocalFS supports it
+fn delta_storage(remote_cfg: &crate::cfg::RemoteConfig) -> Result<PathBuf> {
+    if !matches!(remote_cfg.kind.as_str(), "localfs" | "local") {
+        bail!(
+            "Delta sync is only supported by localfs remotes, not {}; set [remote] delta = false",
+            remote_cfg.kind
+        );
+    }
+    let endpoint = remote_cfg
+        .endpoint
+        .as_ref()
+        .context("LocalFS remote requires 'endpoint' (directory path)")?;
+    Ok(PathBuf::from(shellexpand::tilde(endpoint).as_ref()))
+}
+
+/// Encrypt a packed bundle in place when the remote is set to `encrypt`
+pub(crate) fn seal(
+    config: &Config,
+    remote_cfg: &crate::cfg::RemoteConfig,
+    bundle_path: &Path,
+) -> Result<()> {
+    if remote_cfg.encrypt {
+        ui::info("Encrypting bundle...");
+        bundle::encrypt(config, bundle_path)?;
+    }
+    Ok(())
+}
+
+/// Decrypt a downloaded bundle in place if it was encrypted. Bundles pushed
+/// before `encrypt` was turned on are used as they are.
+pub(crate) fn unseal(
+    config: &Config,
+    remote_cfg: &crate::cfg::RemoteConfig,
+    bundle_path: &Path,
+) -> Result<()> {
+    if crate::secrets::is_encrypted(bundle_path) {
+        ui::info("Decrypting bundle...");
+        bundle::decrypt(config, bundle_path)?;
+    } else if remote_cfg.encrypt {
+        ui::warn("The bundle is not encrypted; it was pushed before encryption was turned on");
+    }
+    Ok(())
+}
+
+/// Where a remote stores bundles and whether they are encrypted, to match
+/// pending pushes to it
+fn remote_id(remote_cfg: &crate::cfg::RemoteConfig) -> String {
+    [
+        Some(remote_cfg.kind.as_str()),
+        remote_cfg.bucket.as_deref(),
+        remote_cfg.prefix.as_deref(),
+        remote_cfg.endpoint.as_deref(),
+        remote_cfg.encrypt.then_some("age"),
+    ]
+    .map(|part| part.unwrap_or_default())
+    .join(":")
+}
+
+/// Where bundles are built and downloaded, and failed uploads wait to resume
+fn bundle_dir() -> Result<PathBuf> {
+    let dir = crate::paths::cache_dir()?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    Ok(dir)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_bundle_entry_from_name() {
+        let entry = BundleEntry::from_name("bundle_20250301_093000_work-laptop.tar.zst", 42);
+        assert_eq!(entry.host.as_deref(), Some("work-laptop"));
+        assert_eq!(
+            entry
+                .timestamp
+                .unwrap()
+                .format("%Y-%m-%d %H:%M")
+                .to_string(),
+            "2025-03-01 09:30"
+        );
+
+        // Bundles pushed before hosts were recorded
+        let old = BundleEntry::from_name("bundle_20240101_120000.tar.zst", 1);
+        assert!(old.timestamp.is_some());
+        assert!(old.host.is_none());
+
+        let odd = BundleEntry::from_name("bundle.tar.zst", 1);
+        assert!(odd.timestamp.is_none() && odd.host.is_none());
+
+        let mut bundles = vec![odd, old, entry];
+        sort_bundles(&mut bundles);
+        assert_eq!(bundles[0].host.as_deref(), Some("work-laptop"));
+        assert_eq!(bundles[2].name, "bundle.tar.zst");
+    }
+
     #[test]
     fn test_remote_kind_parse() {
         assert!(matches!(
@@ -2,16 +2,29 @@ use anyhow::{bail, Context, Result};
 /// S3 remote backend (feature-gated)
 /// Supports AWS S3 and S3-compatible storage (MinIO, DigitalOcean Spaces, etc.)
 use async_trait::async_trait;
+use indicatif::ProgressBar;
 use s3::creds::Credentials;
+use s3::serde_types::Part;
 use s3::Bucket;
 use s3::Region;
+use std::io::SeekFrom;
 use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
 
-use super::{Remote, RemoteObject};
+use super::throttle::{Limits, Throttled};
+use super::transfer::{Partial, UploadState};
+use super::{BundleEntry, Remote, RemoteObject};
+
+/// Bundles larger than this are uploaded in parts of this size, so an
+/// interrupted upload only repeats the part it was sending
+const PART_SIZE: u64 = 8 << 20;
+
+const CONTENT_TYPE: &str = "application/octet-stream";
 
 pub struct S3Remote {
     bucket: Box<Bucket>,
     prefix: String,
+    limits: Limits,
 }
 
 impl S3Remote {
@@ -45,9 +58,162 @@ impl S3Remote {
         Ok(Self {
             bucket,
             prefix: prefix.unwrap_or("dotdipper").to_string(),
+            limits: Limits::default(),
         })
     }
 
+    /// Transfer bundles at no more than the configured rates
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Upload a file as `key`, in parts once it is larger than one part
+    async fn upload(&self, key: &str, path: &Path) -> Result<String> {
+        let size = std::fs::metadata(path)
+            .context("Failed to read bundle file")?
+            .len();
+        let progress = crate::ui::transfer_bar(size, "Uploading");
+        let etag = if size > PART_SIZE {
+            self.upload_parts(key, path, size, &progress).await?
+        } else {
+            let file = tokio::fs::File::open(path)
+                .await
+                .context("Failed to read bundle file")?;
+            let mut reader = Throttled::new(file, self.limits.upload).with_progress(&progress);
+            self.bucket
+                .put_object_stream(&mut reader, key)
+                .await
+                .context("Failed to upload bundle to S3")?;
+            "unknown".to_string()
+        };
+        progress.finish_and_clear();
+        Ok(etag)
+    }
+
+    /// Multipart upload that skips the parts an interrupted upload of the
+    /// same bundle already sent
+    async fn upload_parts(
+        &self,
+        key: &str,
+        path: &Path,
+        size: u64,
+        progress: &ProgressBar,
+    ) -> Result<String> {
+        let mut state = match UploadState::load(path, key) {
+            Some(state) => state,
+            None => {
+                let upload = self
+                    .bucket
+                    .initiate_multipart_upload(key, CONTENT_TYPE)
+                    .await
+                    .context("Failed to start multipart upload to S3")?;
+                let state = UploadState {
+                    key: key.to_string(),
+                    session: upload.upload_id,
+                    parts: Vec::new(),
+                };
+                state.save(path)?;
+                state
+            }
+        };
+
+        for (index, offset) in (0..size).step_by(PART_SIZE as usize).enumerate() {
+            let number = index as u32 + 1;
+            let len = PART_SIZE.min(size - offset);
+            if state.parts.iter().any(|(n, _)| *n == number) {
+                progress.inc(len);
+                continue;
+            }
+
+            let mut file = tokio::fs::File::open(path)
+                .await
+                .context("Failed to read bundle file")?;
+            file.seek(SeekFrom::Start(offset)).await?;
+            let mut chunk = Vec::with_capacity(len as usize);
+            Throttled::new(file.take(len), self.limits.upload)
+                .read_to_end(&mut chunk)
+                .await?;
+            let part = match self
+                .bucket
+                .put_multipart_chunk(chunk, key, number, &state.session, CONTENT_TYPE)
+                .await
+            {
+                Ok(part) => part,
+                Err(e) => {
+                    // The upload was aborted or expired; the next attempt starts over
+                    if e.to_string().contains("NoSuchUpload") {
+                        UploadState::clear(path);
+                    }
+                    return Err(e).context("Failed to upload bundle part to S3");
+                }
+            };
+            state.parts.push((number, part.etag));
+            state.save(path)?;
+            progress.inc(len);
+        }
+
+        let mut parts: Vec<Part> = state
+            .parts
+            .iter()
+            .map(|(part_number, etag)| Part {
+                part_number: *part_number,
+                etag: etag.clone(),
+            })
+            .collect();
+        parts.sort_by_key(|p| p.part_number);
+        let response = self
+            .bucket
+            .complete_multipart_upload(key, &state.session, parts)
+            .await
+            .context("Failed to complete multipart upload to S3")?;
+        if response.status_code() >= 300 {
+            bail!(
+                "Completing the multipart upload to S3 failed: {}",
+                response.as_str().unwrap_or_default()
+            );
+        }
+        UploadState::clear(path);
+        Ok("unknown".to_string())
+    }
+
+    /// Download `key` to `dest`, continuing an interrupted download of the
+    /// same object, and return its ETag when known and the size
+    async fn download(&self, key: &str, dest: &Path) -> Result<(String, u64)> {
+        let (head, _) = self
+            .bucket
+            .head_object(key)
+            .await
+            .with_context(|| format!("Failed to download {} from S3", key))?;
+        let etag = head.e_tag.clone();
+        let size = head.content_length.unwrap_or_default().max(0) as u64;
+
+        let mut partial = Partial::resume(dest, key)?;
+        let restart = !partial.matches(etag.as_deref());
+        let file = tokio::fs::File::from_std(partial.open(etag.as_deref(), restart)?);
+        let progress = crate::ui::transfer_bar(size, "Downloading");
+        progress.set_position(partial.offset);
+        let mut writer = Throttled::new(file, self.limits.download).with_progress(&progress);
+        let status = if partial.offset == 0 {
+            self.bucket.get_object_to_writer(key, &mut writer).await
+        } else if partial.offset < size {
+            self.bucket
+                .get_object_range_to_writer(key, partial.offset, None, &mut writer)
+                .await
+        } else {
+            Ok(200)
+        }
+        .with_context(|| format!("Failed to download {} from S3", key))?;
+        if status >= 300 {
+            bail!("Download of {} from S3 failed: {}", key, status);
+        }
+        writer.flush().await?;
+        progress.finish_and_clear();
+
+        let size = partial.finish()?;
+        Ok((etag.unwrap_or_else(|| "unknown".to_string()), size))
+    }
+
     fn bundle_key(&self, filename: &str) -> String {
         if self.prefix.is_empty() {
             filename.to_string()
@@ -56,7 +222,7 @@ impl S3Remote {
         }
     }
 
-    async fn list_bundles(&self) -> Result<Vec<(String, u64, String)>> {
+    async fn list_objects(&self) -> Result<Vec<(String, u64, String)>> {
         // List objects with our prefix
         let results = self
             .bucket
@@ -68,8 +234,11 @@ impl S3Remote {
 
         for list in results {
             for object in list.contents {
-                // Filter for .tar.zst files
-                if object.key.ends_with(".tar.zst") {
+                // Filter for .tar.zst files, skipping the "latest" copy
+                if object.key.ends_with(".tar.zst")
+                    && !object.key.ends_with(&format!("/{}", super::LATEST_BUNDLE))
+                    && object.key != super::LATEST_BUNDLE
+                {
                     bundles.push((
                         object.key.clone(),
                         object.size,
@@ -93,9 +262,12 @@ impl Remote for S3Remote {
     }
 
     async fn push_bundle(&self, bundle_path: &Path) -> Result<RemoteObject> {
-        // Generate timestamped key
-        let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S").to_string();
-        let key = self.bundle_key(&format!("bundle_{}.tar.zst", timestamp));
+        // Bundles are named bundle_<timestamp>_<host>.tar.zst by the caller
+        let filename = bundle_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid bundle path")?;
+        let key = self.bundle_key(filename);
 
         crate::ui::info(&format!(
             "Uploading to S3: s3://{}/{}",
@@ -103,27 +275,17 @@ impl Remote for S3Remote {
             key
         ));
 
-        // Read bundle data
-        let data = std::fs::read(bundle_path).context("Failed to read bundle file")?;
-        let size = data.len() as u64;
+        let size = std::fs::metadata(bundle_path)
+            .context("Failed to read bundle file")?
+            .len();
+        let etag = self.upload(&key, bundle_path).await?;
 
-        // Upload to S3
-        let response = self
-            .bucket
-            .put_object(&key, &data)
+        // Also update "latest" pointer, copied on the server side
+        let latest_key = self.bundle_key(super::LATEST_BUNDLE);
+        self.bucket
+            .copy_object_internal(&key, &latest_key)
             .await
-            .context("Failed to upload bundle to S3")?;
-
-        // Get ETag from response
-        let etag = response
-            .headers()
-            .get("etag")
-            .cloned()
-            .unwrap_or_else(|| "unknown".to_string());
-
-        // Also update "latest" pointer
-        let latest_key = self.bundle_key("latest.tar.zst");
-        self.bucket.put_object(&latest_key, &data).await.ok(); // Don't fail if latest update fails
+            .ok(); // Don't fail if latest update fails
 
         Ok(RemoteObject {
             etag_or_rev: etag,
@@ -139,7 +301,7 @@ impl Remote for S3Remote {
         ));
 
         // List all bundles and get the latest
-        let bundles = self.list_bundles().await?;
+        let bundles = self.list_objects().await?;
 
         if bundles.is_empty() {
             bail!("No bundles found in S3 bucket at prefix: {}", self.prefix);
@@ -149,27 +311,41 @@ impl Remote for S3Remote {
 
         crate::ui::info(&format!("Downloading latest bundle: {}", latest_key));
 
-        // Download from S3
-        let response = self
-            .bucket
-            .get_object(latest_key)
-            .await
-            .context("Failed to download bundle from S3")?;
+        let (etag, _) = self.download(latest_key, dest_bundle).await?;
+
+        Ok(RemoteObject {
+            etag_or_rev: etag,
+            size_bytes: *size,
+        })
+    }
 
-        // Write to destination
-        std::fs::write(dest_bundle, response.bytes())
-            .context("Failed to write downloaded bundle")?;
+    async fn list_bundles(&self) -> Result<Vec<BundleEntry>> {
+        let mut bundles: Vec<BundleEntry> = self
+            .list_objects()
+            .await?
+            .into_iter()
+            .map(|(key, size, last_modified)| {
+                let name = key.rsplit('/').next().unwrap_or(&key);
+                let mut bundle = BundleEntry::from_name(name, size);
+                if bundle.timestamp.is_none() {
+                    bundle.timestamp = chrono::DateTime::parse_from_rfc3339(&last_modified)
+                        .ok()
+                        .map(|t| t.with_timezone(&chrono::Utc));
+                }
+                bundle
+            })
+            .collect();
+        super::sort_bundles(&mut bundles);
+        Ok(bundles)
+    }
 
-        // Get ETag from response
-        let etag = response
-            .headers()
-            .get("etag")
-            .cloned()
-            .unwrap_or_else(|| "unknown".to_string());
+    async fn pull_bundle(&self, name: &str, dest_bundle: &Path) -> Result<RemoteObject> {
+        let key = self.bundle_key(name);
+        let (etag, size) = self.download(&key, dest_bundle).await?;
 
         Ok(RemoteObject {
             etag_or_rev: etag,
-            size_bytes: *size,
+            size_bytes: size,
         })
     }
 }
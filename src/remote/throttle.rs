@@ -0,0 +1,234 @@
+//! Bandwidth limits for remote transfers.
+//!
+//! `[remote] upload_limit` and `download_limit` take a rate such as
+//! `"2MB/s"` (or just `"2MB"`). Transfers are wrapped in [`Throttled`],
+//! which sleeps whenever more bytes have passed than the rate allows since
+//! the transfer started, and advances the transfer's progress bar.
+
+use anyhow::{Context, Result};
+use indicatif::ProgressBar;
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+use crate::cfg::RemoteConfig;
+
+/// Parse a rate like "2MB/s", "500KB/s" or "1M" into bytes per second
+pub fn parse_rate(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let size = s.strip_suffix("/s").unwrap_or(s);
+    crate::usage::parse_size(size).filter(|&bytes| bytes > 0)
+}
+
+fn limit(value: Option<&String>, key: &str) -> Result<Option<u64>> {
+    value
+        .map(|v| {
+            parse_rate(v)
+                .with_context(|| format!("Invalid [remote] {} '{}' (expected e.g. 2MB/s)", key, v))
+        })
+        .transpose()
+}
+
+/// Upload and download rates in bytes per second (`None`: unlimited)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub upload: Option<u64>,
+    pub download: Option<u64>,
+}
+
+impl Limits {
+    pub fn from_config(config: &RemoteConfig) -> Result<Self> {
+        Ok(Self {
+            upload: limit(config.upload_limit.as_ref(), "upload_limit")?,
+            download: limit(config.download_limit.as_ref(), "download_limit")?,
+        })
+    }
+}
+
+/// Paces a transfer to a fixed rate
+#[derive(Debug)]
+struct Pace {
+    bytes_per_sec: Option<u64>,
+    started: Instant,
+    transferred: u64,
+}
+
+impl Pace {
+    fn new(bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            bytes_per_sec,
+            started: Instant::now(),
+            transferred: 0,
+        }
+    }
+
+    /// Record `n` more bytes and return how long to wait to stay on pace
+    fn advance(&mut self, n: usize) -> Duration {
+        self.transferred += n as u64;
+        let Some(rate) = self.bytes_per_sec else {
+            return Duration::ZERO;
+        };
+        let due = Duration::from_secs_f64(self.transferred as f64 / rate as f64);
+        due.saturating_sub(self.started.elapsed())
+    }
+
+    /// Largest chunk to move at once, so pauses stay short
+    fn chunk(&self, len: usize) -> usize {
+        match self.bytes_per_sec {
+            Some(rate) => len.min((rate / 10).max(1) as usize),
+            None => len,
+        }
+    }
+}
+
+/// A reader (or, for S3 and GCS, an async reader or writer) limited to a number of
+/// bytes per second. `None` passes everything through.
+pub struct Throttled<T> {
+    inner: T,
+    pace: Pace,
+    progress: Option<ProgressBar>,
+    #[cfg(any(feature = "s3", feature = "gcs", test))]
+    pause: Option<std::pin::Pin<Box<tokio::time::Sleep>>>,
+}
+
+impl<T> Throttled<T> {
+    pub fn new(inner: T, bytes_per_sec: Option<u64>) -> Self {
+        Self {
+            inner,
+            pace: Pace::new(bytes_per_sec),
+            progress: None,
+            #[cfg(any(feature = "s3", feature = "gcs", test))]
+            pause: None,
+        }
+    }
+
+    /// Advance `progress` by the bytes passing through
+    pub fn with_progress(mut self, progress: &ProgressBar) -> Self {
+        self.progress = Some(progress.clone());
+        self
+    }
+
+    fn advance(&mut self, n: usize) -> Duration {
+        if let Some(progress) = &self.progress {
+            progress.inc(n as u64);
+        }
+        self.pace.advance(n)
+    }
+}
+
+impl<R: Read> Read for Throttled<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.pace.chunk(buf.len());
+        let n = self.inner.read(&mut buf[..len])?;
+        std::thread::sleep(self.advance(n));
+        Ok(n)
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "gcs", test))]
+mod tokio_io {
+    use super::Throttled;
+    use std::future::Future;
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{ready, Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+    impl<T> Throttled<T> {
+        /// Wait out the pause owed by the previous chunk
+        fn poll_pause(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+            if let Some(pause) = self.pause.as_mut() {
+                ready!(pause.as_mut().poll(cx));
+                self.pause = None;
+            }
+            Poll::Ready(())
+        }
+
+        fn owe(&mut self, n: usize) {
+            let wait = self.advance(n);
+            if !wait.is_zero() {
+                self.pause = Some(Box::pin(tokio::time::sleep(wait)));
+            }
+        }
+    }
+
+    impl<R: AsyncRead + Unpin> AsyncRead for Throttled<R> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            let this = self.get_mut();
+            ready!(this.poll_pause(cx));
+            let len = this.pace.chunk(buf.remaining());
+            let mut limited = buf.take(len);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited))?;
+            let n = limited.filled().len();
+            // SAFETY: the inner reader initialized `n` bytes of `buf`'s unfilled part
+            unsafe { buf.assume_init(n) };
+            buf.advance(n);
+            this.owe(n);
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<W: AsyncWrite + Unpin> AsyncWrite for Throttled<W> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let this = self.get_mut();
+            ready!(this.poll_pause(cx));
+            let len = this.pace.chunk(buf.len());
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]))?;
+            this.owe(n);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throttled_reader_keeps_to_the_rate() {
+        assert_eq!(parse_rate("2MB/s"), Some(2 << 20));
+        assert_eq!(parse_rate("500KB"), Some(500 << 10));
+        assert_eq!(parse_rate("0/s"), None);
+        assert_eq!(parse_rate("fast"), None);
+
+        let data = vec![7u8; 20 * 1024];
+        let started = Instant::now();
+        let mut out = Vec::new();
+        io::copy(
+            &mut Throttled::new(data.as_slice(), Some(100 * 1024)),
+            &mut out,
+        )
+        .unwrap();
+        assert_eq!(out, data);
+        // 20 KiB at 100 KiB/s takes about 200ms
+        assert!(started.elapsed() >= Duration::from_millis(190));
+
+        // The async reader used for S3 paces the same way
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let started = Instant::now();
+        let mut out = Vec::new();
+        runtime
+            .block_on(tokio::io::copy(
+                &mut Throttled::new(data.as_slice(), Some(100 * 1024)),
+                &mut out,
+            ))
+            .unwrap();
+        assert_eq!(out, data);
+        assert!(started.elapsed() >= Duration::from_millis(190));
+    }
+}
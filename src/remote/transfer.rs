@@ -0,0 +1,271 @@
+//! Resumable bundle transfers.
+//!
+//! Downloads are written to `<dest>.part`, with `<dest>.part.json` recording
+//! which object the bytes came from and its ETag or generation. A retry, or
+//! the next pull, continues from the end of the partial file as long as the
+//! object is unchanged. Uploads that the remote can continue (S3 in parts,
+//! GCS as a resumable session) record their session in
+//! `<bundle>.upload.json`, and a push that fails keeps its bundle with a
+//! `<bundle>.pending.json` marker so the next push of the same files resumes
+//! the upload instead of starting over.
+
+use anyhow::{Context, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use super::bundle::BundleMeta;
+
+/// `path` with `suffix` appended to its file name
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn load<T: DeserializeOwned>(path: &Path) -> Option<T> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+fn save<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    fs::write(path, serde_json::to_string_pretty(value)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PartialSource {
+    source: String,
+    etag: Option<String>,
+}
+
+/// A download to `dest` that may continue an earlier, interrupted one
+#[derive(Debug)]
+pub struct Partial {
+    dest: PathBuf,
+    part: PathBuf,
+    source: String,
+    /// Bytes already downloaded
+    pub offset: u64,
+    /// ETag or generation of the object the bytes came from
+    pub etag: Option<String>,
+}
+
+impl Partial {
+    /// Continue an earlier download of `source` to `dest`, or start over if
+    /// there is none or it was of another object
+    pub fn resume(dest: &Path, source: &str) -> Result<Self> {
+        let part = sidecar(dest, ".part");
+        let earlier = load::<PartialSource>(&sidecar(&part, ".json"))
+            .filter(|earlier| earlier.source == source)
+            .and_then(|earlier| Some((fs::metadata(&part).ok()?.len(), earlier.etag)));
+        let (offset, etag) = earlier.unwrap_or_default();
+        Ok(Self {
+            dest: dest.to_path_buf(),
+            part,
+            source: source.to_string(),
+            offset,
+            etag,
+        })
+    }
+
+    /// Whether the partial file holds the start of the object tagged `etag`
+    pub fn matches(&self, etag: Option<&str>) -> bool {
+        self.offset > 0 && etag.is_some() && self.etag.as_deref() == etag
+    }
+
+    /// Open the partial file to append the rest of the object tagged `etag`;
+    /// with `restart`, what was downloaded before is discarded
+    pub fn open(&mut self, etag: Option<&str>, restart: bool) -> Result<File> {
+        if restart {
+            self.offset = 0;
+        }
+        self.etag = etag.map(str::to_string);
+        save(
+            &sidecar(&self.part, ".json"),
+            &PartialSource {
+                source: self.source.clone(),
+                etag: self.etag.clone(),
+            },
+        )?;
+        let mut options = OpenOptions::new();
+        options.create(true);
+        if self.offset == 0 {
+            options.write(true).truncate(true);
+        } else {
+            options.append(true);
+        }
+        options
+            .open(&self.part)
+            .with_context(|| format!("Failed to write {}", self.part.display()))
+    }
+
+    /// Move the completed download into place, returning its size
+    pub fn finish(self) -> Result<u64> {
+        fs::rename(&self.part, &self.dest)
+            .with_context(|| format!("Failed to move {} into place", self.part.display()))?;
+        let _ = fs::remove_file(sidecar(&self.part, ".json"));
+        Ok(fs::metadata(&self.dest)?.len())
+    }
+}
+
+/// A resumable upload session for one object
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UploadState {
+    /// Object key or name
+    pub key: String,
+    /// S3 upload ID or GCS session URI
+    pub session: String,
+    /// Parts uploaded so far, as part number and ETag (S3 only)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<(u32, String)>,
+}
+
+impl UploadState {
+    /// The session of an earlier upload of `bundle` as `key`
+    #[cfg(any(feature = "s3", feature = "gcs", test))]
+    pub fn load(bundle: &Path, key: &str) -> Option<Self> {
+        load::<Self>(&sidecar(bundle, ".upload.json")).filter(|state| state.key == key)
+    }
+
+    #[cfg(any(feature = "s3", feature = "gcs", test))]
+    pub fn save(&self, bundle: &Path) -> Result<()> {
+        save(&sidecar(bundle, ".upload.json"), self)
+    }
+
+    pub fn clear(bundle: &Path) {
+        let _ = fs::remove_file(sidecar(bundle, ".upload.json"));
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PendingPush {
+    remote: String,
+    fingerprint: String,
+    meta: BundleMeta,
+}
+
+/// A bundle in `dir` left by a failed push of the same files to the same
+/// remote, with its metadata. Bundles left for other files or remotes are
+/// deleted.
+pub fn pending_bundle(
+    dir: &Path,
+    remote: &str,
+    fingerprint: &str,
+) -> Option<(PathBuf, BundleMeta)> {
+    let mut found = None;
+    for entry in fs::read_dir(dir).ok()?.flatten() {
+        let bundle = entry.path();
+        let Some(pending) = load::<PendingPush>(&sidecar(&bundle, ".pending.json")) else {
+            continue;
+        };
+        if found.is_none()
+            && pending.remote == remote
+            && pending.fingerprint == fingerprint
+            && bundle.is_file()
+        {
+            found = Some((bundle, pending.meta));
+        } else {
+            discard_bundle(&bundle);
+        }
+    }
+    found
+}
+
+/// Keep `bundle` for the next push of the same files if this one fails
+pub fn mark_pending(
+    bundle: &Path,
+    remote: &str,
+    fingerprint: &str,
+    meta: &BundleMeta,
+) -> Result<()> {
+    save(
+        &sidecar(bundle, ".pending.json"),
+        &PendingPush {
+            remote: remote.to_string(),
+            fingerprint: fingerprint.to_string(),
+            meta: meta.clone(),
+        },
+    )
+}
+
+/// Delete a pushed or abandoned bundle along with its upload state
+pub fn discard_bundle(bundle: &Path) {
+    let _ = fs::remove_file(bundle);
+    let _ = fs::remove_file(sidecar(bundle, ".pending.json"));
+    UploadState::clear(bundle);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_partial_download_resumes_only_the_same_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("bundle.tar.zst");
+
+        let mut partial = Partial::resume(&dest, "dotdipper/latest.tar.zst").unwrap();
+        assert_eq!(partial.offset, 0);
+        partial
+            .open(Some("v1"), false)
+            .unwrap()
+            .write_all(b"bund")
+            .unwrap();
+
+        // The same object continues where the download stopped
+        let mut partial = Partial::resume(&dest, "dotdipper/latest.tar.zst").unwrap();
+        assert_eq!(partial.offset, 4);
+        assert!(partial.matches(Some("v1")));
+        assert!(!partial.matches(Some("v2")));
+        partial
+            .open(Some("v1"), false)
+            .unwrap()
+            .write_all(b"le")
+            .unwrap();
+        assert_eq!(partial.finish().unwrap(), 6);
+        assert_eq!(fs::read(&dest).unwrap(), b"bundle");
+
+        // Another object, or a changed one, starts over
+        Partial::resume(&dest, "a")
+            .unwrap()
+            .open(None, false)
+            .unwrap();
+        assert_eq!(Partial::resume(&dest, "b").unwrap().offset, 0);
+        let mut partial = Partial::resume(&dest, "a").unwrap();
+        partial
+            .open(Some("v2"), true)
+            .unwrap()
+            .write_all(b"x")
+            .unwrap();
+        assert_eq!(partial.offset, 0);
+
+        // A pending push is found for the same files and remote only
+        let bundle = dir.path().join("bundle_20250301_093000_mbp.tar.zst");
+        fs::write(&bundle, b"bundle").unwrap();
+        let meta = BundleMeta {
+            profile_name: "default".to_string(),
+            timestamp: "2025-03-01T09:30:00Z".to_string(),
+            hostname: "mbp".to_string(),
+            dotdipper_version: "0.0.0".to_string(),
+            file_count: 3,
+            size_bytes: 6,
+            checksums: Default::default(),
+        };
+        mark_pending(&bundle, "s3:dots", "abc", &meta).unwrap();
+        UploadState {
+            key: "dotdipper/b".to_string(),
+            session: "id".to_string(),
+            parts: vec![(1, "etag".to_string())],
+        }
+        .save(&bundle)
+        .unwrap();
+        let (pending, pending_meta) = pending_bundle(dir.path(), "s3:dots", "abc").unwrap();
+        assert_eq!((pending, pending_meta.file_count), (bundle.clone(), 3));
+        assert!(UploadState::load(&bundle, "dotdipper/b").is_some());
+        assert!(UploadState::load(&bundle, "other").is_none());
+        assert!(pending_bundle(dir.path(), "s3:dots", "def").is_none());
+        assert!(!bundle.exists());
+        assert!(UploadState::load(&bundle, "dotdipper/b").is_none());
+    }
+}
@@ -2,21 +2,26 @@ use anyhow::{bail, Context, Result};
 /// WebDAV remote backend (feature-gated)
 /// Supports standard WebDAV servers (Nextcloud, ownCloud, etc.)
 use async_trait::async_trait;
-use reqwest::blocking::Client;
-use reqwest::header::CONTENT_TYPE;
+use reqwest::blocking::{Body, Client, RequestBuilder, Response};
+use reqwest::header::{CONTENT_TYPE, ETAG, IF_RANGE, RANGE};
+use reqwest::StatusCode;
+use std::fs::File;
 use std::path::Path;
 
-use super::{Remote, RemoteObject};
+use super::throttle::{Limits, Throttled};
+use super::transfer::Partial;
+use super::{BundleEntry, Remote, RemoteObject};
 
 pub struct WebDavRemote {
     endpoint: String,
     client: Client,
     username: Option<String>,
     password: Option<String>,
+    limits: Limits,
 }
 
 impl WebDavRemote {
-    pub fn new(endpoint: &str) -> Result<Self> {
+    pub fn new(endpoint: &str, proxy: Option<&str>) -> Result<Self> {
         // Get credentials from environment
         let username = std::env::var("WEBDAV_USERNAME").ok();
         let password = std::env::var("WEBDAV_PASSWORD").ok();
@@ -27,10 +32,16 @@ impl WebDavRemote {
             );
         }
 
-        let client = Client::builder()
-            .timeout(std::time::Duration::from_secs(300)) // 5 min timeout for large uploads
-            .build()
-            .context("Failed to create HTTP client")?;
+        let mut builder = Client::builder()
+            // No overall timeout: a rate-limited upload may take a while
+            .connect_timeout(std::time::Duration::from_secs(30));
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(
+                reqwest::Proxy::all(proxy)
+                    .with_context(|| format!("Invalid proxy URL: {}", proxy))?,
+            );
+        }
+        let client = builder.build().context("Failed to create HTTP client")?;
 
         // Normalize endpoint URL
         let endpoint = endpoint.trim_end_matches('/').to_string();
@@ -40,14 +51,92 @@ impl WebDavRemote {
             client,
             username,
             password,
+            limits: Limits::default(),
         })
     }
 
+    /// Transfer bundles at no more than the configured rates
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        match (&self.username, &self.password) {
+            (Some(username), Some(password)) => request.basic_auth(username, Some(password)),
+            _ => request,
+        }
+    }
+
+    /// PUT a file, streamed at the upload rate. WebDAV has no standard way
+    /// to continue an upload, so a retry sends the whole file again.
+    fn upload(&self, url: &str, path: &Path) -> Result<Response> {
+        let file = File::open(path).context("Failed to read bundle file")?;
+        let size = file.metadata()?.len();
+        let progress = crate::ui::transfer_bar(size, "Uploading");
+        let reader = Throttled::new(file, self.limits.upload).with_progress(&progress);
+
+        let response = self
+            .authorize(self.client.put(url))
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(Body::sized(reader, size))
+            .send()
+            .context("Failed to upload bundle to WebDAV");
+        progress.finish_and_clear();
+        response
+    }
+
+    /// GET `url` into `dest` at the download rate, continuing an interrupted
+    /// download while the server reports the same ETag. `None` for a 404.
+    fn download(&self, url: &str, dest: &Path) -> Result<Option<RemoteObject>> {
+        let mut partial = Partial::resume(dest, url)?;
+        let mut request = self.authorize(self.client.get(url));
+        if let Some(etag) = partial.etag.as_deref().filter(|_| partial.offset > 0) {
+            request = request
+                .header(RANGE, format!("bytes={}-", partial.offset))
+                .header(IF_RANGE, etag);
+        }
+        let resp = request
+            .send()
+            .context("Failed to download bundle from WebDAV")?;
+        if resp.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !resp.status().is_success() {
+            bail!("Download failed: {}", resp.status());
+        }
+
+        let etag = resp
+            .headers()
+            .get(ETAG)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        // A changed bundle comes back whole
+        let restart = resp.status() != StatusCode::PARTIAL_CONTENT;
+        let mut file = partial.open(etag.as_deref(), restart)?;
+        let progress = crate::ui::transfer_bar(
+            partial.offset + resp.content_length().unwrap_or_default(),
+            "Downloading",
+        );
+        progress.set_position(partial.offset);
+        std::io::copy(
+            &mut Throttled::new(resp, self.limits.download).with_progress(&progress),
+            &mut file,
+        )
+        .context("Failed to read response body")?;
+        progress.finish_and_clear();
+
+        Ok(Some(RemoteObject {
+            etag_or_rev: etag.unwrap_or_else(|| "unknown".to_string()),
+            size_bytes: partial.finish()?,
+        }))
+    }
+
     fn bundle_url(&self, filename: &str) -> String {
         format!("{}/dotdipper/{}", self.endpoint, filename)
     }
 
-    fn list_bundles(&self) -> Result<Vec<(String, u64, String)>> {
+    fn list_files(&self) -> Result<Vec<(String, u64, String)>> {
         let propfind_url = format!("{}/dotdipper/", self.endpoint);
 
         // WebDAV PROPFIND request to list files
@@ -60,15 +149,11 @@ impl WebDavRemote {
   </D:prop>
 </D:propfind>"#;
 
-        let mut request = self
-            .client
-            .request(reqwest::Method::from_bytes(b"PROPFIND")?, &propfind_url);
-
-        if let (Some(username), Some(password)) = (&self.username, &self.password) {
-            request = request.basic_auth(username, Some(password));
-        }
-
-        let response = request
+        let response = self
+            .authorize(
+                self.client
+                    .request(reqwest::Method::from_bytes(b"PROPFIND")?, &propfind_url),
+            )
             .header("Depth", "1")
             .header(CONTENT_TYPE, "application/xml")
             .body(propfind_body)
@@ -119,42 +204,29 @@ impl Remote for WebDavRemote {
     }
 
     async fn push_bundle(&self, bundle_path: &Path) -> Result<RemoteObject> {
-        // Generate timestamped filename
-        let timestamp = chrono::Utc::now().format("%Y%m%d_%H%M%S").to_string();
-        let remote_filename = format!("bundle_{}.tar.zst", timestamp);
-        let url = self.bundle_url(&remote_filename);
+        // Bundles are named bundle_<timestamp>_<host>.tar.zst by the caller
+        let remote_filename = bundle_path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .context("Invalid bundle path")?;
+        let url = self.bundle_url(remote_filename);
 
         crate::ui::info(&format!("Uploading to WebDAV: {}", url));
 
         // Ensure dotdipper directory exists
         let dir_url = format!("{}/dotdipper/", self.endpoint);
-        let mut mkcol_req = self
+        let mkcol_req = self
             .client
             .request(reqwest::Method::from_bytes(b"MKCOL")?, &dir_url);
 
-        if let (Some(username), Some(password)) = (&self.username, &self.password) {
-            mkcol_req = mkcol_req.basic_auth(username, Some(password));
-        }
-
         // Try to create directory (ignore error if it exists)
-        let _ = mkcol_req.send();
-
-        // Read bundle data
-        let data = std::fs::read(bundle_path).context("Failed to read bundle file")?;
-        let size = data.len() as u64;
+        let _ = self.authorize(mkcol_req).send();
 
         // Upload with PUT
-        let mut put_req = self.client.put(&url);
-
-        if let (Some(username), Some(password)) = (&self.username, &self.password) {
-            put_req = put_req.basic_auth(username, Some(password));
-        }
-
-        let response = put_req
-            .header(CONTENT_TYPE, "application/octet-stream")
-            .body(data.clone())
-            .send()
-            .context("Failed to upload bundle to WebDAV")?;
+        let size = std::fs::metadata(bundle_path)
+            .context("Failed to read bundle file")?
+            .len();
+        let response = self.upload(&url, bundle_path)?;
 
         if !response.status().is_success() {
             bail!("Upload failed: {}", response.status());
@@ -168,18 +240,16 @@ impl Remote for WebDavRemote {
             .unwrap_or("unknown")
             .to_string();
 
-        // Also upload as "latest"
-        let latest_url = self.bundle_url("latest.tar.zst");
-        let mut latest_req = self.client.put(&latest_url);
-
-        if let (Some(username), Some(password)) = (&self.username, &self.password) {
-            latest_req = latest_req.basic_auth(username, Some(password));
-        }
-
-        let _ = latest_req
-            .header(CONTENT_TYPE, "application/octet-stream")
-            .body(data)
-            .send(); // Don't fail if latest update fails
+        // Also copy to "latest" on the server; don't fail if that doesn't work
+        let latest_url = self.bundle_url(super::LATEST_BUNDLE);
+        let _ = self
+            .authorize(
+                self.client
+                    .request(reqwest::Method::from_bytes(b"COPY")?, &url),
+            )
+            .header("Destination", &latest_url)
+            .header("Overwrite", "T")
+            .send();
 
         Ok(RemoteObject {
             etag_or_rev: etag,
@@ -188,84 +258,43 @@ impl Remote for WebDavRemote {
     }
 
     async fn pull_latest(&self, dest_bundle: &Path) -> Result<RemoteObject> {
-        crate::ui::info(&format!("Listing bundles from WebDAV: {}", self.endpoint));
-
         // Try to download "latest.tar.zst" first
-        let latest_url = self.bundle_url("latest.tar.zst");
-
-        let mut get_req = self.client.get(&latest_url);
-
-        if let (Some(username), Some(password)) = (&self.username, &self.password) {
-            get_req = get_req.basic_auth(username, Some(password));
+        let latest_url = self.bundle_url(super::LATEST_BUNDLE);
+        if let Some(object) = self.download(&latest_url, dest_bundle)? {
+            return Ok(object);
         }
 
-        let response = get_req.send();
-
-        let (data, etag, size) = if let Ok(resp) = response {
-            if resp.status().is_success() {
-                let etag = resp
-                    .headers()
-                    .get("etag")
-                    .and_then(|v| v.to_str().ok())
-                    .unwrap_or("unknown")
-                    .to_string();
-
-                let bytes = resp.bytes().context("Failed to read response body")?;
-
-                let size = bytes.len() as u64;
-
-                (bytes.to_vec(), etag, size)
-            } else {
-                bail!("Failed to download latest bundle: {}", resp.status());
-            }
-        } else {
-            // Fallback: list and get the most recent bundle
-            let bundles = self.list_bundles()?;
-
-            if bundles.is_empty() {
-                bail!("No bundles found on WebDAV server at {}", self.endpoint);
-            }
-
-            let (latest_name, _, _) = &bundles[0];
-            let url = self.bundle_url(latest_name);
-
-            crate::ui::info(&format!("Downloading: {}", latest_name));
-
-            let mut get_req = self.client.get(&url);
-
-            if let (Some(username), Some(password)) = (&self.username, &self.password) {
-                get_req = get_req.basic_auth(username, Some(password));
-            }
-
-            let resp = get_req
-                .send()
-                .context("Failed to download bundle from WebDAV")?;
-
-            if !resp.status().is_success() {
-                bail!("Download failed: {}", resp.status());
-            }
-
-            let etag = resp
-                .headers()
-                .get("etag")
-                .and_then(|v| v.to_str().ok())
-                .unwrap_or("unknown")
-                .to_string();
-
-            let bytes = resp.bytes().context("Failed to read response body")?;
-
-            let size = bytes.len() as u64;
-
-            (bytes.to_vec(), etag, size)
+        // Fallback: list and get the most recent bundle
+        crate::ui::info(&format!("Listing bundles from WebDAV: {}", self.endpoint));
+        let bundles = self.list_files()?;
+        let Some((latest_name, _, _)) = bundles.first() else {
+            bail!("No bundles found on WebDAV server at {}", self.endpoint);
         };
+        crate::ui::info(&format!("Downloading: {}", latest_name));
+        self.download(&self.bundle_url(latest_name), dest_bundle)?
+            .with_context(|| {
+                format!(
+                    "Download of {} failed: {}",
+                    latest_name,
+                    StatusCode::NOT_FOUND
+                )
+            })
+    }
 
-        // Write to destination
-        std::fs::write(dest_bundle, &data).context("Failed to write downloaded bundle")?;
+    async fn list_bundles(&self) -> Result<Vec<BundleEntry>> {
+        let mut bundles: Vec<BundleEntry> = self
+            .list_files()?
+            .into_iter()
+            .filter(|(name, _, _)| name != super::LATEST_BUNDLE)
+            .map(|(name, size, _)| BundleEntry::from_name(&name, size))
+            .collect();
+        super::sort_bundles(&mut bundles);
+        Ok(bundles)
+    }
 
-        Ok(RemoteObject {
-            etag_or_rev: etag,
-            size_bytes: size,
-        })
+    async fn pull_bundle(&self, name: &str, dest_bundle: &Path) -> Result<RemoteObject> {
+        self.download(&self.bundle_url(name), dest_bundle)?
+            .with_context(|| format!("Download of {} failed: {}", name, StatusCode::NOT_FOUND))
     }
 }
 
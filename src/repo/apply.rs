@@ -3,17 +3,28 @@ use chrono::Utc;
 use colored::*;
 use std::collections::BTreeMap;
 use std::fs;
-use std::os::unix::fs as unix_fs;
 use std::path::{Path, PathBuf};
 
+use super::journal;
 use crate::cfg::{Config, RestoreMode};
-use crate::hash::Manifest;
+use crate::hash::{LinkEntry, Manifest};
+use crate::mounts::MountPolicy;
 use crate::ui;
 
+/// Skip reason for protected files that were not confirmed
+pub const PROTECTED_SKIP: &str = "Protected (confirm or pass --yes-protected)";
+
+/// Skip reason for files outside $HOME that only root can write
+pub const NEEDS_ROOT_SKIP: &str = "Needs root";
+
 #[derive(Debug, Clone)]
 pub struct ApplyOpts {
     pub force: bool,
     pub allow_outside_home: bool,
+    /// Overwrite `protected` files without asking for each one
+    pub yes_protected: bool,
+    /// Work out every action without touching the filesystem
+    pub dry_run: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -29,6 +40,10 @@ pub struct AppliedAction {
 pub enum AppliedMode {
     Symlinked,
     Copied,
+    /// Moved from its old path after a rename between snapshots
+    Moved,
+    /// Three-way merged with the copy edited here
+    Merged,
     Skipped,
 }
 
@@ -37,11 +52,136 @@ impl AppliedMode {
         match self {
             AppliedMode::Symlinked => "Symlinked".green(),
             AppliedMode::Copied => "Copied".blue(),
+            AppliedMode::Moved => "Moved".cyan(),
+            AppliedMode::Merged => "Merged".yellow(),
             AppliedMode::Skipped => "Skipped".dimmed(),
         }
     }
 }
 
+/// What was last applied on this machine (empty before the first apply)
+pub fn load_applied() -> Result<Manifest> {
+    let path = crate::paths::applied_manifest_file()?;
+    if path.exists() {
+        Manifest::load(&path)
+    } else {
+        Ok(Manifest::new())
+    }
+}
+
+/// Remember applied files, forgetting the old paths of moved ones
+pub fn record_applied(applied: &Manifest, moved_from: &[PathBuf]) -> Result<()> {
+    let mut record = load_applied()?;
+    for path in moved_from {
+        record.files.remove(path);
+    }
+    record.files.extend(
+        applied
+            .files
+            .iter()
+            .map(|(path, hash)| (path.clone(), hash.clone())),
+    );
+    record.created = Utc::now();
+    record.save(&crate::paths::applied_manifest_file()?)?;
+    crate::machines::record_local(None);
+    Ok(())
+}
+
+/// Renames from `previous` to `manifest` whose old path is still in `home`
+/// and whose new path is free, i.e. moves this machine hasn't made yet
+pub fn pending_moves(
+    previous: &Manifest,
+    manifest: &Manifest,
+    home: &Path,
+) -> Vec<(PathBuf, PathBuf)> {
+    let present = |p: &Path| p.exists() || p.is_symlink();
+    let encrypted = |p: &Path| p.extension().is_some_and(|e| e == "age");
+    previous
+        .diff(manifest)
+        .renamed
+        .into_iter()
+        .filter(|(old, new)| !encrypted(old) && !encrypted(new))
+        .filter(|(old, new)| present(&home.join(old)) && !present(&home.join(new)))
+        .collect()
+}
+
+/// Move files renamed between snapshots to their new paths. Copies are
+/// backed up at the old path first; links into the store are just removed,
+/// since apply links the new path.
+pub fn apply_moves(
+    moves: &[(PathBuf, PathBuf)],
+    cfg: &Config,
+    opts: &ApplyOpts,
+) -> Result<Vec<AppliedAction>> {
+    let home_dir = dirs::home_dir().context("Failed to find home directory")?;
+    let mut actions = Vec::new();
+    for (old, new) in moves {
+        let source = home_dir.join(old);
+        let target = home_dir.join(new);
+        let escape = [&source, &target]
+            .into_iter()
+            .find_map(|path| crate::confine::check_target(path, &home_dir));
+        if let Some(reason) = escape.filter(|_| !opts.allow_outside_home) {
+            actions.push(AppliedAction {
+                mode: AppliedMode::Skipped,
+                target,
+                source,
+                backup_created: false,
+                skipped_reason: Some(reason.to_string()),
+            });
+            continue;
+        }
+
+        let mut backup_created = false;
+        if opts.dry_run {
+            backup_created = cfg.general.backup && !source.is_symlink();
+        } else if source.is_symlink() {
+            journal::record([journal::Entry::written(
+                &source,
+                &target,
+                journal::Change::Removed,
+                journal::prior(&source, None),
+            )])?;
+            crate::trash::put(&source, "Moved by apply")?;
+        } else {
+            if cfg.general.backup {
+                create_backup(cfg, &source)?;
+                backup_created = true;
+            }
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&source, &target).with_context(|| {
+                format!(
+                    "Failed to move {} to {}",
+                    source.display(),
+                    target.display()
+                )
+            })?;
+            journal::record([journal::Entry {
+                moved_from: Some(source.clone()),
+                ..journal::Entry::written(
+                    &target,
+                    &source,
+                    journal::Change::Moved,
+                    journal::Prior::Missing,
+                )
+            }])?;
+        }
+        if !opts.dry_run {
+            ui::info(&format!("Moved ~/{} -> ~/{}", old.display(), new.display()));
+        }
+        actions.push(AppliedAction {
+            mode: AppliedMode::Moved,
+            target,
+            source,
+            backup_created,
+            skipped_reason: None,
+        });
+    }
+    Ok(actions)
+}
+
 pub fn apply(
     compiled_root: &Path,
     manifest: &Manifest,
@@ -49,13 +189,156 @@ pub fn apply(
     opts: &ApplyOpts,
 ) -> Result<Vec<AppliedAction>> {
     let home_dir = dirs::home_dir().context("Failed to find home directory")?;
+    let mount_policy = MountPolicy::from_config(cfg, &home_dir);
     let mut actions = Vec::new();
+    let mut mount_skips = 0;
+    let mut changed_agents = Vec::new();
+    let attrs_cfg = crate::attrs::attributes_config(cfg);
+    let mut attr_warnings = Vec::new();
+    let host = crate::hosts::HostIdentity::local(cfg)?;
+    let variables = crate::template::Variables::local(cfg)?;
+    let excluder = crate::scan::Excluder::new(cfg, &home_dir)?;
+    let applied = load_applied()?;
+    let mut root_steps = Vec::new();
+    let mut root_temps = Vec::new();
 
     let pb = ui::progress_bar(manifest.files.len() as u64, "Applying dotfiles");
 
+    // Tracked directories that can be linked as a whole
+    let linked_dirs: Vec<PathBuf> =
+        linkable_dirs(compiled_root, manifest, cfg, &host, &excluder, &home_dir)
+            .into_iter()
+            .filter(|rel_dir| {
+                let target = home_dir.join(rel_dir);
+                (opts.allow_outside_home
+                    || crate::confine::check_target(&target, &home_dir).is_none())
+                    && mount_policy.check(&target).is_none()
+            })
+            .collect();
+    for rel_dir in &linked_dirs {
+        let source = compiled_root.join(rel_dir);
+        let target = home_dir.join(rel_dir);
+        // Asked first, since nothing may be adopted unless the link is made
+        let replaces = (target.exists() || target.is_symlink())
+            && !is_already_applied(&source, &target, RestoreMode::Symlink)?;
+        if replaces
+            && !opts.force
+            && !opts.dry_run
+            && !ui::prompt_confirm(&format!("Overwrite {}?", target.display()), false)
+        {
+            actions.push(AppliedAction {
+                mode: AppliedMode::Skipped,
+                target,
+                source,
+                backup_created: false,
+                skipped_reason: Some("User declined".to_string()),
+            });
+            continue;
+        }
+        // Files only in the directory here are copied into compiled, so the
+        // link keeps them
+        let adopted = adopt_hidden(&source, &target, opts.dry_run)?;
+        if !adopted.is_empty() {
+            ui::info(&format!(
+                "{} {} file(s) from {} into compiled",
+                if opts.dry_run {
+                    "Would adopt"
+                } else {
+                    "Adopted"
+                },
+                adopted.len(),
+                target.display()
+            ));
+        }
+        let applied = apply_file(
+            &source,
+            &target,
+            RestoreMode::Symlink,
+            cfg,
+            true,
+            opts.dry_run,
+        );
+        // Without the link, the adopted copies would only be strays
+        if applied.is_err() && !opts.dry_run {
+            for rel in &adopted {
+                let _ = fs::remove_file(source.join(rel));
+            }
+        }
+        let (action, entry) = applied?;
+        journal::record(entry)?;
+        actions.push(action);
+    }
+
+    // Empty directories and symlinks recorded in tracked directories, except
+    // in the ones linked whole, which have them already
+    let in_linked_dir = |rel_path: &Path| linked_dirs.iter().any(|dir| rel_path.starts_with(dir));
+    let mut created_dirs = 0;
+    for (rel_path, empty) in &manifest.empty_dirs {
+        let target = home_dir.join(rel_path);
+        let escapes = crate::confine::check_target(&target, &home_dir).is_some();
+        if in_linked_dir(rel_path)
+            || (escapes && !opts.allow_outside_home)
+            || excluder.is_excluded(rel_path)
+            || mount_policy.check(&target).is_some()
+            || fs::symlink_metadata(&target).is_ok()
+        {
+            continue;
+        }
+        created_dirs += 1;
+        if !opts.dry_run {
+            use std::os::unix::fs::PermissionsExt;
+            fs::create_dir_all(&target)?;
+            fs::set_permissions(&target, fs::Permissions::from_mode(empty.mode))?;
+        }
+    }
+    if created_dirs > 0 {
+        ui::info(&format!(
+            "{} {} empty {}",
+            if opts.dry_run {
+                "Would create"
+            } else {
+                "Created"
+            },
+            created_dirs,
+            if created_dirs == 1 {
+                "directory"
+            } else {
+                "directories"
+            }
+        ));
+    }
+    for (rel_path, link) in &manifest.links {
+        if in_linked_dir(rel_path) || excluder.is_excluded(rel_path) {
+            continue;
+        }
+        let target = home_dir.join(rel_path);
+        let skipped_reason = crate::confine::check_target(&target, &home_dir)
+            .filter(|_| !opts.allow_outside_home)
+            .map(str::to_string)
+            .or_else(|| mount_policy.check(&target));
+        if skipped_reason.is_some() {
+            actions.push(AppliedAction {
+                mode: AppliedMode::Skipped,
+                target,
+                source: link.target.clone(),
+                backup_created: false,
+                skipped_reason,
+            });
+            continue;
+        }
+        let (action, entry) = apply_link(link, &target, cfg, opts.force, opts.dry_run)?;
+        journal::record(entry)?;
+        actions.push(action);
+    }
+
     for rel_path in manifest.files.keys() {
+        if linked_dirs.iter().any(|dir| rel_path.starts_with(dir)) {
+            pb.inc(1);
+            continue;
+        }
         let mut source_path = compiled_root.join(rel_path);
-        let mut target_path = home_dir.join(rel_path);
+        let mut target_path = crate::system::target(&home_dir, rel_path);
+        let system = crate::system::is_system(rel_path);
 
         // Check if this is an encrypted file (.age suffix)
         let is_encrypted = source_path
@@ -70,18 +353,7 @@ pub fn apply(
 
             match crate::secrets::decrypt_to_memory(cfg, &source_path) {
                 Ok(decrypted_content) => {
-                    // Create temp file with decrypted content
-                    let mut temp = tempfile::NamedTempFile::new()
-                        .context("Failed to create temporary file for decrypted content")?;
-                    use std::io::Write;
-                    temp.write_all(&decrypted_content)?;
-                    temp.flush()?;
-
-                    // Update source path to temp file
-                    let (file, temp_path) = temp
-                        .keep()
-                        .context("Failed to persist temporary decrypted file")?;
-                    drop(file);
+                    let temp_path = write_decrypted(&decrypted_content, None)?;
 
                     // Remove .age suffix from target path
                     if let Some(stem) = target_path.file_stem().map(|s| s.to_owned()) {
@@ -105,29 +377,123 @@ pub fn apply(
                     continue;
                 }
             }
+        } else if crate::secrets::is_tracked(cfg, rel_path)
+            && crate::secrets::is_encrypted(&source_path)
+        {
+            // `[secrets] tracked` files are stored encrypted at their own path
+            match crate::secrets::decrypt_to_memory(cfg, &source_path) {
+                Ok(decrypted_content) => {
+                    let perms = fs::metadata(&source_path)?.permissions();
+                    let temp_path = write_decrypted(&decrypted_content, Some(perms))?;
+                    source_path = temp_path.clone();
+                    Some(temp_path)
+                }
+                Err(e) => {
+                    ui::warn(&format!("Failed to decrypt {}: {}", rel_path.display(), e));
+                    ui::hint("Skipping encrypted file. Run 'dotdipper secrets init' if needed.");
+                    pb.inc(1);
+                    actions.push(AppliedAction {
+                        mode: AppliedMode::Skipped,
+                        target: target_path.clone(),
+                        source: source_path.clone(),
+                        backup_created: false,
+                        skipped_reason: Some("Decryption failed".to_string()),
+                    });
+                    continue;
+                }
+            }
         } else {
-            None
+            // Files with inline secret regions are decrypted region by region
+            match crate::secrets::inline::unseal_file(cfg, &source_path) {
+                Ok(Some(plain)) => {
+                    let perms = fs::metadata(&source_path)?.permissions();
+                    let temp_path = write_decrypted(plain.as_bytes(), Some(perms))?;
+                    source_path = temp_path.clone();
+                    Some(temp_path)
+                }
+                Ok(None) => None,
+                Err(e) => {
+                    ui::warn(&format!("{:#}", e));
+                    ui::hint("Skipping file with secret regions. Run 'dotdipper secrets init' if needed.");
+                    pb.inc(1);
+                    actions.push(AppliedAction {
+                        mode: AppliedMode::Skipped,
+                        target: target_path.clone(),
+                        source: source_path.clone(),
+                        backup_created: false,
+                        skipped_reason: Some("Decryption failed".to_string()),
+                    });
+                    continue;
+                }
+            }
         };
 
-        // Safety check: refuse to operate outside $HOME
-        if !opts.allow_outside_home && !target_path.starts_with(&home_dir) {
+        // Templates are rendered with this machine's variables, then copied
+        let temp_decrypted = if crate::template::is_template(cfg, rel_path) {
+            let rendered = fs::read_to_string(&source_path)
+                .map_err(anyhow::Error::new)
+                .and_then(|text| variables.render(&rel_path.display().to_string(), &text));
+            if let Some(temp) = &temp_decrypted {
+                let _ = fs::remove_file(temp);
+            }
+            match rendered {
+                Ok(rendered) => {
+                    let perms = fs::metadata(compiled_root.join(rel_path))?.permissions();
+                    let temp_path = write_decrypted(rendered.as_bytes(), Some(perms))?;
+                    source_path = temp_path.clone();
+                    Some(temp_path)
+                }
+                Err(e) => {
+                    ui::warn(&format!("{:#}", e));
+                    pb.inc(1);
+                    actions.push(AppliedAction {
+                        mode: AppliedMode::Skipped,
+                        target: target_path.clone(),
+                        source: compiled_root.join(rel_path),
+                        backup_created: false,
+                        skipped_reason: Some("Template error".to_string()),
+                    });
+                    continue;
+                }
+            }
+        } else {
+            temp_decrypted
+        };
+
+        // Safety check: refuse to operate outside $HOME, including through
+        // `..` components or symlinked parent directories, unless the file
+        // was tracked outside it
+        let escape = crate::system::check_target(cfg, rel_path, &target_path, &home_dir);
+        if let Some(reason) = escape.filter(|_| !opts.allow_outside_home) {
             pb.inc(1);
             actions.push(AppliedAction {
                 mode: AppliedMode::Skipped,
                 target: target_path.clone(),
                 source: source_path.clone(),
                 backup_created: false,
-                skipped_reason: Some("Outside $HOME".to_string()),
+                skipped_reason: Some(reason.to_string()),
             });
             continue;
         }
 
         // Check for file-specific overrides
-        let path_str = format!("~/{}", rel_path.display());
-        let file_override = cfg.files.get(&path_str);
+        let file_override = cfg.file_override(rel_path);
 
-        // Check if excluded
-        if file_override.is_some_and(|o| o.exclude) {
+        // Files targeted at other hosts, OSes or profiles
+        if file_override.is_some_and(|o| !host.allows(o)) {
+            pb.inc(1);
+            actions.push(AppliedAction {
+                mode: AppliedMode::Skipped,
+                target: target_path.clone(),
+                source: source_path.clone(),
+                backup_created: false,
+                skipped_reason: Some(format!("Not for this host ({})", host.name)),
+            });
+            continue;
+        }
+
+        // Excluded by exclude_patterns or the override
+        if excluder.is_excluded(rel_path) {
             pb.inc(1);
             actions.push(AppliedAction {
                 mode: AppliedMode::Skipped,
@@ -139,24 +505,218 @@ pub fn apply(
             continue;
         }
 
-        // Determine mode (override or default)
-        let mode = file_override
-            .and_then(|o| o.mode)
-            .unwrap_or(cfg.general.default_mode);
+        // Don't write into network or removable mounts unless allowlisted
+        if let Some(reason) = mount_policy.check(&target_path) {
+            pb.inc(1);
+            mount_skips += 1;
+            actions.push(AppliedAction {
+                mode: AppliedMode::Skipped,
+                target: target_path.clone(),
+                source: source_path.clone(),
+                backup_created: false,
+                skipped_reason: Some(reason),
+            });
+            continue;
+        }
+
+        // Launch agents must be real, valid plists for launchd to load them
+        let is_agent = crate::macos::is_launch_agent(cfg, rel_path);
+        if is_agent {
+            if let Err(e) = crate::macos::validate_plist(&source_path) {
+                pb.inc(1);
+                actions.push(AppliedAction {
+                    mode: AppliedMode::Skipped,
+                    target: target_path.clone(),
+                    source: source_path.clone(),
+                    backup_created: false,
+                    skipped_reason: Some(format!("{:#}", e)),
+                });
+                continue;
+            }
+        }
+        let agent_changed = is_agent && !same_contents(&source_path, &target_path);
+
+        // Determine mode (override or default). Decrypted contents and
+        // rendered templates only exist in a temporary file, so they are
+        // always copied, and so are files outside $HOME.
+        let mode = if is_agent || temp_decrypted.is_some() || system {
+            RestoreMode::Copy
+        } else {
+            file_override
+                .and_then(|o| o.mode)
+                .unwrap_or(cfg.general.default_mode)
+        };
+
+        // Immutable targets can't be replaced; unlock them only when allowed
+        let needs_write = !is_already_applied(&source_path, &target_path, mode).unwrap_or(false);
+
+        // Protected files are only overwritten after a per-file confirmation
+        let mut force = opts.force;
+        let exists = target_path.exists() || target_path.is_symlink();
+        if needs_write && exists && file_override.is_some_and(|o| o.protected) {
+            if !opts.yes_protected
+                && (opts.dry_run
+                    || !ui::prompt_confirm(
+                        &format!("{} is protected. Overwrite it?", target_path.display()),
+                        false,
+                    ))
+            {
+                pb.inc(1);
+                actions.push(AppliedAction {
+                    mode: AppliedMode::Skipped,
+                    target: target_path.clone(),
+                    source: source_path.clone(),
+                    backup_created: false,
+                    skipped_reason: Some(PROTECTED_SKIP.to_string()),
+                });
+                continue;
+            }
+            force = true;
+        }
+
+        // Files outside $HOME this user can't write go into the sudo script
+        if system && needs_write && !crate::system::writable(&target_path) {
+            pb.inc(1);
+            actions.push(AppliedAction {
+                mode: AppliedMode::Skipped,
+                target: target_path.clone(),
+                source: compiled_root.join(rel_path),
+                backup_created: false,
+                skipped_reason: Some(NEEDS_ROOT_SKIP.to_string()),
+            });
+            root_steps.push(crate::system::Step {
+                source: source_path.clone(),
+                target: target_path.clone(),
+            });
+            root_temps.extend(temp_decrypted);
+            continue;
+        }
+
+        // Copies edited here while the compiled file changed too can be
+        // merged instead of overwritten
+        let mut merged_temp = None;
+        let mergeable = needs_write
+            && !force
+            && !opts.dry_run
+            && mode == RestoreMode::Copy
+            && temp_decrypted.is_none();
+        let sides = mergeable
+            .then(|| {
+                super::merge::sides(
+                    cfg,
+                    compiled_root,
+                    &applied,
+                    rel_path,
+                    &source_path,
+                    &target_path,
+                )
+            })
+            .flatten();
+        if let Some(sides) = sides {
+            match super::merge::ask(&target_path, &sides)? {
+                super::merge::Choice::Overwrite => force = true,
+                super::merge::Choice::Keep => {
+                    pb.inc(1);
+                    actions.push(AppliedAction {
+                        mode: AppliedMode::Skipped,
+                        target: target_path.clone(),
+                        source: source_path.clone(),
+                        backup_created: false,
+                        skipped_reason: Some("Kept local changes".to_string()),
+                    });
+                    continue;
+                }
+                super::merge::Choice::Merged { contents, .. } => {
+                    let perms = fs::metadata(&source_path)?.permissions();
+                    let temp_path = write_decrypted(&contents, Some(perms))?;
+                    source_path = temp_path.clone();
+                    merged_temp = Some(temp_path);
+                    force = true;
+                }
+            }
+        }
+        let relock = needs_write && crate::attrs::is_immutable(&target_path);
+        if relock {
+            let reason = if !attrs_cfg.unlock_immutable {
+                Some("Immutable (set [attributes] unlock_immutable to replace)".to_string())
+            } else if opts.dry_run {
+                None
+            } else {
+                crate::attrs::set_immutable(&target_path, false)
+                    .err()
+                    .map(|e| format!("Immutable, could not unlock: {:#}", e))
+            };
+            if let Some(reason) = reason {
+                pb.inc(1);
+                actions.push(AppliedAction {
+                    mode: AppliedMode::Skipped,
+                    target: target_path.clone(),
+                    source: source_path.clone(),
+                    backup_created: false,
+                    skipped_reason: Some(reason),
+                });
+                continue;
+            }
+        }
+
+        // Attributes of the file being replaced, so they aren't lost silently
+        let old_xattrs = if needs_write {
+            crate::attrs::read_xattrs(&target_path)
+        } else {
+            Vec::new()
+        };
 
         // Apply the file
-        let action = apply_file(
-            &source_path,
-            &target_path,
-            mode,
-            cfg.general.backup,
-            opts.force,
-        )?;
+        let result = apply_file(&source_path, &target_path, mode, cfg, force, opts.dry_run);
+
+        if relock && !opts.dry_run {
+            if target_path.is_symlink() {
+                attr_warnings.push(format!(
+                    "{}: immutable flag cannot be set on a symlink",
+                    target_path.display()
+                ));
+            } else if let Err(e) = crate::attrs::set_immutable(&target_path, true) {
+                attr_warnings.push(format!(
+                    "{}: could not restore immutable flag: {:#}",
+                    target_path.display(),
+                    e
+                ));
+            }
+        }
+        let (mut action, entry) = result?;
+        journal::record(entry)?;
+        if merged_temp.is_some() && action.skipped_reason.is_none() {
+            action.mode = AppliedMode::Merged;
+            action.source = compiled_root.join(rel_path);
+        }
+
+        if action.skipped_reason.is_none() && !opts.dry_run {
+            let recorded = manifest.get_file(rel_path);
+            attr_warnings.extend(restore_xattrs(
+                &attrs_cfg,
+                &source_path,
+                &target_path,
+                mode,
+                recorded.map_or_else(Vec::new, |f| crate::attrs::from_manifest(&f.xattrs)),
+                old_xattrs,
+            ));
+            // Copies that lost the executable bit on the way, as through a
+            // remote that doesn't keep modes, get it back from the manifest
+            if let Some(recorded) = recorded.filter(|_| action.mode == AppliedMode::Copied) {
+                if let Err(e) = crate::perms::restore_exec_bits(&target_path, recorded.mode) {
+                    attr_warnings.push(format!("{}: {:#}", target_path.display(), e));
+                }
+            }
+        }
+
+        if agent_changed && action.mode != AppliedMode::Skipped {
+            changed_agents.push(target_path.clone());
+        }
 
         actions.push(action);
 
-        // Clean up temporary decrypted file if it exists
-        if let Some(temp_path) = temp_decrypted {
+        // Clean up temporary decrypted or merged file if it exists
+        for temp_path in temp_decrypted.into_iter().chain(merged_temp) {
             let _ = fs::remove_file(temp_path);
         }
 
@@ -165,72 +725,383 @@ pub fn apply(
 
     pb.finish_with_message("Application complete");
 
+    if !opts.dry_run {
+        crate::macos::after_apply(cfg, compiled_root, &changed_agents);
+    }
+
+    if !root_steps.is_empty() && !opts.dry_run {
+        let plan = crate::system::Plan::write(root_steps);
+        for temp_path in root_temps {
+            let _ = fs::remove_file(temp_path);
+        }
+        let plan = plan?;
+        let ran = plan.offer()?;
+        for action in actions.iter_mut() {
+            if action.skipped_reason.as_deref() != Some(NEEDS_ROOT_SKIP) {
+                continue;
+            }
+            if ran {
+                action.mode = AppliedMode::Copied;
+                action.skipped_reason = None;
+            } else {
+                action.skipped_reason = Some(format!(
+                    "{}: run {}",
+                    NEEDS_ROOT_SKIP,
+                    plan.script.display()
+                ));
+            }
+        }
+    }
+
+    let perm_failures = match opts.dry_run {
+        true => Vec::new(),
+        false => enforce_perms(cfg, &actions, &home_dir),
+    };
+
     // Print summary
-    print_summary(&actions);
+    print_summary(&actions, cfg, &home_dir, opts.dry_run);
+    warn_perms(&perm_failures);
+
+    if !attr_warnings.is_empty() {
+        ui::warn(&format!(
+            "File attributes could not be preserved on {} file(s):",
+            attr_warnings.len()
+        ));
+        for warning in &attr_warnings {
+            println!("  {}", warning);
+        }
+        if !attrs_cfg.preserve_xattrs {
+            ui::hint("Set [attributes] preserve_xattrs = true to keep xattrs and ACLs");
+        }
+    }
+
+    if mount_skips > 0 {
+        ui::warn(&format!(
+            "{} file(s) skipped because they live on network or removable mounts",
+            mount_skips
+        ));
+        ui::hint("Add the mount point to [mounts].allow in config.toml to apply there");
+    }
 
     Ok(actions)
 }
 
+/// The mode of the tracked directory `rel_dir`: its `[files]` override's, or
+/// the default
+fn dir_mode(cfg: &Config, rel_dir: &Path) -> RestoreMode {
+    cfg.file_override(rel_dir)
+        .and_then(|o| o.mode)
+        .unwrap_or(cfg.general.default_mode)
+}
+
+/// Whether `rel_path` is in a `symlink_dir` directory that isn't linked as a
+/// whole yet, so apply has work to do even when the file is in place
+pub fn awaits_dir_link(cfg: &Config, home: &Path, rel_path: &Path) -> bool {
+    cfg.tracked_dirs.keys().any(|key| {
+        let rel_dir = crate::matching::to_home_relative(Path::new(key), home);
+        rel_path.starts_with(&rel_dir)
+            && dir_mode(cfg, &rel_dir) == RestoreMode::SymlinkDir
+            && !home.join(&rel_dir).is_symlink()
+    })
+}
+
+/// `[tracked_dirs]` directories that apply links as a whole instead of file
+/// by file. In symlink mode that takes every compiled file under them in
+/// `manifest`, none that has to be decrypted, rendered or handled by its own
+/// `[files]` override, and nothing in the directory in `$HOME` that the link
+/// would hide. In `symlink_dir` mode only files that have to be decrypted or
+/// rendered keep a directory from being linked.
+fn linkable_dirs(
+    compiled_root: &Path,
+    manifest: &Manifest,
+    cfg: &Config,
+    host: &crate::hosts::HostIdentity,
+    excluder: &crate::scan::Excluder,
+    home: &Path,
+) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for key in cfg.tracked_dirs.keys() {
+        let rel_dir = crate::matching::to_home_relative(Path::new(key), home);
+        let dir_override = cfg.file_override(&rel_dir);
+        let mode = dir_mode(cfg, &rel_dir);
+        let source = compiled_root.join(&rel_dir);
+        if mode == RestoreMode::Copy
+            || !source.is_dir()
+            || source.is_symlink()
+            || dir_override
+                .is_some_and(|o| o.exclude || o.protected || o.sets_perms() || !host.allows(o))
+        {
+            continue;
+        }
+
+        let stored = crate::confine::walk(&source).files;
+        let in_manifest = manifest
+            .files
+            .keys()
+            .filter(|rel_path| rel_path.starts_with(&rel_dir))
+            .count();
+        let rendered = |path: &PathBuf| {
+            let rel_path = path.strip_prefix(compiled_root).unwrap_or(path);
+            path.extension().is_some_and(|ext| ext == "age")
+                || crate::secrets::is_tracked(cfg, rel_path)
+                || crate::template::is_template(cfg, rel_path)
+                || crate::macos::is_launch_agent(cfg, rel_path)
+                || fs::read_to_string(path)
+                    .is_ok_and(|text| crate::secrets::inline::has_markers(&text))
+        };
+
+        if mode == RestoreMode::SymlinkDir {
+            if in_manifest == 0 {
+                continue;
+            }
+            if stored.iter().any(rendered) {
+                ui::warn(&format!(
+                    "~/{} holds templates or secrets, so its files are linked one by one",
+                    rel_dir.display()
+                ));
+                continue;
+            }
+            dirs.push(rel_dir);
+            continue;
+        }
+
+        let plain = !stored.is_empty()
+            && stored.len() == in_manifest
+            && stored.iter().all(|path| {
+                let rel_path = path.strip_prefix(compiled_root).unwrap_or(path);
+                manifest.has_file(rel_path)
+                    && !excluder.is_excluded(rel_path)
+                    && !rendered(path)
+                    && match (cfg.file_override(rel_path), dir_override) {
+                        (None, None) => true,
+                        (Some(a), Some(b)) => std::ptr::eq(a, b),
+                        _ => false,
+                    }
+            });
+
+        let target = home.join(&rel_dir);
+        if plain && hidden_files(&source, &target).is_empty() {
+            dirs.push(rel_dir);
+        }
+    }
+    dirs
+}
+
+/// Files in the directory `target` that linking it to `source` would hide
+fn hidden_files(source: &Path, target: &Path) -> Vec<PathBuf> {
+    if !target.is_dir() || target.is_symlink() {
+        return Vec::new();
+    }
+    crate::confine::walk(target)
+        .files
+        .into_iter()
+        .filter_map(|file| file.strip_prefix(target).ok().map(Path::to_path_buf))
+        .filter(|rel| !source.join(rel).is_file())
+        .collect()
+}
+
+/// Copy the files in `target` that linking it to `source` would hide into
+/// `source`, so they stay visible through the link and get snapshotted
+fn adopt_hidden(source: &Path, target: &Path, dry_run: bool) -> Result<Vec<PathBuf>> {
+    let hidden = hidden_files(source, target);
+    if !dry_run {
+        for rel in &hidden {
+            let dest = source.join(rel);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            copy_file_with_metadata(&target.join(rel), &dest)?;
+        }
+    }
+    Ok(hidden)
+}
+
+/// Give the files just applied, or already in place, the permissions and
+/// ownership their `[files]` override asks for. Returns one message per file
+/// that could not be given them.
+pub(super) fn enforce_perms(cfg: &Config, actions: &[AppliedAction], home: &Path) -> Vec<String> {
+    let mut failures = Vec::new();
+    for action in actions {
+        let in_place = matches!(
+            action.skipped_reason.as_deref(),
+            None | Some("Already applied")
+        );
+        // Links to whole directories are left alone
+        if !in_place || !action.target.is_file() {
+            continue;
+        }
+        let rel_path = crate::system::manifest_path(&action.target, home);
+        let Some(file_override) = cfg.file_override(&rel_path) else {
+            continue;
+        };
+        let result = crate::perms::Policy::of(file_override).and_then(|policy| match policy {
+            Some(policy) => crate::perms::enforce(&action.target, &policy),
+            None => Ok(Vec::new()),
+        });
+        match result {
+            Ok(changes) => {
+                for change in changes {
+                    ui::info(&format!("{}: {}", action.target.display(), change));
+                }
+            }
+            Err(e) => failures.push(format!("{}: {:#}", action.target.display(), e)),
+        }
+    }
+    failures
+}
+
+pub(super) fn warn_perms(failures: &[String]) {
+    if failures.is_empty() {
+        return;
+    }
+    ui::warn(&format!(
+        "Permissions or ownership could not be set on {} file(s):",
+        failures.len()
+    ));
+    for failure in failures {
+        println!("  {}", failure);
+    }
+}
+
+/// Put extended attributes and ACLs back on a freshly written target.
+///
+/// With `preserve_xattrs`, copies get the compiled file's attributes, then
+/// the ones the manifest recorded and the replaced file had that the compiled
+/// copy lacks. Returns one message per file whose attributes were dropped or
+/// could not be written.
+pub(super) fn restore_xattrs(
+    attrs_cfg: &crate::cfg::AttributesConfig,
+    source: &Path,
+    target: &Path,
+    mode: RestoreMode,
+    recorded: Vec<crate::attrs::Xattr>,
+    old_xattrs: Vec<crate::attrs::Xattr>,
+) -> Vec<String> {
+    match mode {
+        RestoreMode::Copy if attrs_cfg.preserve_xattrs => {
+            let mut wanted = crate::attrs::read_xattrs(source);
+            for (name, value) in recorded.into_iter().chain(old_xattrs) {
+                if !wanted.iter().any(|(n, _)| *n == name) {
+                    wanted.push((name, value));
+                }
+            }
+            crate::attrs::write_xattrs(target, &wanted)
+                .into_iter()
+                .map(|e| format!("{}: {}", target.display(), e))
+                .collect()
+        }
+        // Symlinks resolve to the compiled copy, which snapshot gave the attributes
+        RestoreMode::Symlink | RestoreMode::SymlinkDir if attrs_cfg.preserve_xattrs => Vec::new(),
+        _ if old_xattrs.is_empty() => Vec::new(),
+        _ => vec![format!(
+            "{}: dropped {}",
+            target.display(),
+            crate::attrs::describe(&old_xattrs)
+        )],
+    }
+}
+
+/// True if both files exist as regular files with identical contents
+/// Write decrypted contents to a temporary file to apply from
+fn write_decrypted(contents: &[u8], permissions: Option<fs::Permissions>) -> Result<PathBuf> {
+    use std::io::Write;
+    let mut temp = tempfile::NamedTempFile::new()
+        .context("Failed to create temporary file for decrypted content")?;
+    temp.write_all(contents)?;
+    temp.flush()?;
+    if let Some(permissions) = permissions {
+        fs::set_permissions(temp.path(), permissions)?;
+    }
+    let (_, temp_path) = temp
+        .keep()
+        .context("Failed to persist temporary decrypted file")?;
+    Ok(temp_path)
+}
+
+fn same_contents(a: &Path, b: &Path) -> bool {
+    match (fs::read(a), fs::read(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
 fn apply_file(
     source: &Path,
     target: &Path,
     mode: RestoreMode,
-    backup_enabled: bool,
+    cfg: &Config,
     force: bool,
-) -> Result<AppliedAction> {
+    dry_run: bool,
+) -> Result<(AppliedAction, Option<journal::Entry>)> {
+    let backup_enabled = cfg.general.backup;
     // Check if source exists
     if !source.exists() {
-        return Ok(AppliedAction {
-            mode: AppliedMode::Skipped,
-            target: target.to_path_buf(),
-            source: source.to_path_buf(),
-            backup_created: false,
-            skipped_reason: Some("Source not found".to_string()),
-        });
+        return Ok((
+            AppliedAction {
+                mode: AppliedMode::Skipped,
+                target: target.to_path_buf(),
+                source: source.to_path_buf(),
+                backup_created: false,
+                skipped_reason: Some("Source not found".to_string()),
+            },
+            None,
+        ));
     }
 
     // Check if we need to do anything (idempotency)
     if is_already_applied(source, target, mode)? {
-        return Ok(AppliedAction {
-            mode: match mode {
-                RestoreMode::Symlink => AppliedMode::Symlinked,
-                RestoreMode::Copy => AppliedMode::Copied,
+        return Ok((
+            AppliedAction {
+                mode: match mode {
+                    RestoreMode::Symlink | RestoreMode::SymlinkDir => AppliedMode::Symlinked,
+                    RestoreMode::Copy => AppliedMode::Copied,
+                },
+                target: target.to_path_buf(),
+                source: source.to_path_buf(),
+                backup_created: false,
+                skipped_reason: Some("Already applied".to_string()),
             },
-            target: target.to_path_buf(),
-            source: source.to_path_buf(),
-            backup_created: false,
-            skipped_reason: Some("Already applied".to_string()),
-        });
+            None,
+        ));
+    }
+
+    let exists = target.exists() || target.is_symlink();
+    if dry_run {
+        return Ok((
+            AppliedAction {
+                mode: match mode {
+                    RestoreMode::Symlink | RestoreMode::SymlinkDir => AppliedMode::Symlinked,
+                    RestoreMode::Copy => AppliedMode::Copied,
+                },
+                target: target.to_path_buf(),
+                source: source.to_path_buf(),
+                backup_created: exists && backup_enabled && !target.is_symlink(),
+                skipped_reason: None,
+            },
+            None,
+        ));
     }
 
     // Handle existing target
     let mut backup_created = false;
-    if target.exists() || target.is_symlink() {
+    let mut prior = journal::Prior::Missing;
+    if exists {
         if !force {
             // Prompt user
             if !ui::prompt_confirm(&format!("Overwrite {}?", target.display()), false) {
-                return Ok(AppliedAction {
-                    mode: AppliedMode::Skipped,
-                    target: target.to_path_buf(),
-                    source: source.to_path_buf(),
-                    backup_created: false,
-                    skipped_reason: Some("User declined".to_string()),
-                });
+                return Ok((
+                    AppliedAction {
+                        mode: AppliedMode::Skipped,
+                        target: target.to_path_buf(),
+                        source: source.to_path_buf(),
+                        backup_created: false,
+                        skipped_reason: Some("User declined".to_string()),
+                    },
+                    None,
+                ));
             }
         }
-
-        // Create backup if enabled
-        if backup_enabled && !target.is_symlink() {
-            create_backup(target)?;
-            backup_created = true;
-        }
-
-        // Remove existing target
-        if target.is_dir() && !target.is_symlink() {
-            fs::remove_dir_all(target)?;
-        } else {
-            fs::remove_file(target)?;
-        }
+        (backup_created, prior) = set_aside(cfg, target)?;
     }
 
     // Ensure parent directory exists
@@ -240,14 +1111,8 @@ fn apply_file(
 
     // Apply based on mode
     let applied_mode = match mode {
-        RestoreMode::Symlink => {
-            unix_fs::symlink(source, target).with_context(|| {
-                format!(
-                    "Failed to symlink {} -> {}",
-                    source.display(),
-                    target.display()
-                )
-            })?;
+        RestoreMode::Symlink | RestoreMode::SymlinkDir => {
+            crate::links::create(source, target, cfg.general.relative_symlinks)?;
             AppliedMode::Symlinked
         }
         RestoreMode::Copy => {
@@ -260,29 +1125,119 @@ fn apply_file(
         }
     };
 
-    Ok(AppliedAction {
-        mode: applied_mode,
+    let change = match applied_mode {
+        AppliedMode::Symlinked => journal::Change::Symlinked,
+        _ => journal::Change::Copied,
+    };
+    Ok((
+        AppliedAction {
+            mode: applied_mode,
+            target: target.to_path_buf(),
+            source: source.to_path_buf(),
+            backup_created,
+            skipped_reason: None,
+        },
+        Some(journal::Entry::written(target, source, change, prior)),
+    ))
+}
+
+/// Back up the existing `target` if enabled, or else move it to the trash,
+/// so nothing is lost. Returns whether a backup was made and what was there.
+pub(super) fn set_aside(cfg: &Config, target: &Path) -> Result<(bool, journal::Prior)> {
+    if cfg.general.backup && !target.is_symlink() {
+        let prior = journal::prior(target, Some(create_backup(cfg, target)?));
+        if target.is_dir() {
+            fs::remove_dir_all(target)?;
+        } else {
+            fs::remove_file(target)?;
+        }
+        return Ok((true, prior));
+    }
+    let prior = journal::prior(target, None);
+    let trashed = crate::trash::put(target, "Replaced by apply")?;
+    match prior {
+        journal::Prior::Lost => Ok((false, journal::Prior::Backup { path: trashed })),
+        prior => Ok((false, prior)),
+    }
+}
+
+/// Put the symlink recorded as `link` at `target`, pointing where it pointed
+/// when snapshotted, relative or absolute alike
+fn apply_link(
+    link: &LinkEntry,
+    target: &Path,
+    cfg: &Config,
+    force: bool,
+    dry_run: bool,
+) -> Result<(AppliedAction, Option<journal::Entry>)> {
+    let action = |mode, backup_created, skipped_reason: Option<&str>| AppliedAction {
+        mode,
         target: target.to_path_buf(),
-        source: source.to_path_buf(),
+        source: link.target.clone(),
         backup_created,
-        skipped_reason: None,
-    })
+        skipped_reason: skipped_reason.map(str::to_string),
+    };
+    if fs::read_link(target).is_ok_and(|t| t == link.target) {
+        let action = action(AppliedMode::Symlinked, false, Some("Already applied"));
+        return Ok((action, None));
+    }
+
+    let exists = target.exists() || target.is_symlink();
+    if dry_run {
+        let backup = exists && cfg.general.backup && !target.is_symlink();
+        return Ok((action(AppliedMode::Symlinked, backup, None), None));
+    }
+    let mut backup_created = false;
+    let mut prior = journal::Prior::Missing;
+    if exists {
+        if !force && !ui::prompt_confirm(&format!("Overwrite {}?", target.display()), false) {
+            let action = action(AppliedMode::Skipped, false, Some("User declined"));
+            return Ok((action, None));
+        }
+        (backup_created, prior) = set_aside(cfg, target)?;
+    }
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    std::os::unix::fs::symlink(&link.target, target)
+        .with_context(|| format!("Failed to create link {}", target.display()))?;
+    // Journaled by where it leads, which is what undo checks
+    let leads_to = crate::links::resolve(target).unwrap_or_else(|| link.target.clone());
+    let entry = journal::Entry::written(target, &leads_to, journal::Change::Symlinked, prior);
+    Ok((
+        action(AppliedMode::Symlinked, backup_created, None),
+        Some(entry),
+    ))
+}
+
+/// Manifest paths of the links and empty directories `manifest` records that
+/// aren't in `$HOME` as recorded
+pub fn pending_tree(manifest: &Manifest, home: &Path) -> Vec<PathBuf> {
+    let links = manifest
+        .links
+        .iter()
+        .filter(|(rel_path, link)| {
+            fs::read_link(home.join(rel_path)).ok().as_ref() != Some(&link.target)
+        })
+        .map(|(rel_path, _)| rel_path);
+    let empty_dirs = manifest
+        .empty_dirs
+        .keys()
+        .filter(|rel_path| fs::symlink_metadata(home.join(rel_path)).is_err());
+    links.chain(empty_dirs).cloned().collect()
 }
 
-fn is_already_applied(source: &Path, target: &Path, mode: RestoreMode) -> Result<bool> {
+pub(super) fn is_already_applied(source: &Path, target: &Path, mode: RestoreMode) -> Result<bool> {
     if !target.exists() && !target.is_symlink() {
         return Ok(false);
     }
 
     match mode {
-        RestoreMode::Symlink => {
-            // Check if target is a symlink pointing to source
-            if target.is_symlink() {
-                let link_target = fs::read_link(target)?;
-                Ok(link_target == source)
-            } else {
-                Ok(false)
-            }
+        RestoreMode::Symlink | RestoreMode::SymlinkDir => {
+            // Check if target is a symlink pointing to source (relative or
+            // absolute; `dotdipper relink` converts old absolute links)
+            Ok(crate::links::points_to(target, source))
         }
         RestoreMode::Copy => {
             // For copy mode, check hash to determine if content is the same
@@ -297,13 +1252,13 @@ fn is_already_applied(source: &Path, target: &Path, mode: RestoreMode) -> Result
     }
 }
 
-fn create_backup(path: &Path) -> Result<()> {
-    let timestamp = Utc::now().format("%Y%m%d-%H%M%S");
-    let backup_path = PathBuf::from(format!("{}.bak.{}", path.display(), timestamp));
+fn create_backup(cfg: &Config, path: &Path) -> Result<PathBuf> {
+    let backup_path = crate::backups::path_for(cfg, path)?;
 
     if path.is_dir() {
-        // Use fs_extra for directory copying with better control
-        let options = fs_extra::dir::CopyOptions::new();
+        // The backup is the directory itself, not a directory holding it
+        fs::create_dir_all(&backup_path)?;
+        let options = fs_extra::dir::CopyOptions::new().content_only(true);
         fs_extra::dir::copy(path, &backup_path, &options)
             .with_context(|| format!("Failed to backup directory {}", path.display()))?;
     } else {
@@ -311,11 +1266,12 @@ fn create_backup(path: &Path) -> Result<()> {
             .with_context(|| format!("Failed to backup file {}", path.display()))?;
     }
 
+    crate::backups::register(path, &backup_path)?;
     ui::info(&format!("Backed up to {}", backup_path.display()));
-    Ok(())
+    Ok(backup_path)
 }
 
-fn copy_file_with_metadata(source: &Path, target: &Path) -> Result<()> {
+pub(super) fn copy_file_with_metadata(source: &Path, target: &Path) -> Result<()> {
     // Copy file
     fs::copy(source, target).with_context(|| {
         format!(
@@ -347,7 +1303,12 @@ fn copy_dir_recursive(source: &Path, target: &Path) -> Result<()> {
         let file_name = entry.file_name();
         let target_path = target.join(&file_name);
 
-        if source_path.is_dir() {
+        // Symlinks are copied as links, never followed, so a link to an
+        // ancestor can't recurse forever
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            std::os::unix::fs::symlink(fs::read_link(&source_path)?, &target_path)?;
+        } else if file_type.is_dir() {
             copy_dir_recursive(&source_path, &target_path)?;
         } else {
             copy_file_with_metadata(&source_path, &target_path)?;
@@ -362,11 +1323,61 @@ fn copy_dir_recursive(source: &Path, target: &Path) -> Result<()> {
     Ok(())
 }
 
-fn print_summary(actions: &[AppliedAction]) {
-    ui::section("Application Summary");
+/// Print what apply did, or with `planned` what it would do
+pub(super) fn print_summary(actions: &[AppliedAction], cfg: &Config, home: &Path, planned: bool) {
+    let mut counts = BTreeMap::new();
+    for action in actions {
+        *counts.entry(action.mode).or_insert(0) += 1;
+    }
+
+    // --quiet keeps the result, on one line
+    if ui::level() == ui::OutputLevel::Quiet {
+        let parts: Vec<String> = counts
+            .iter()
+            .map(|(mode, count)| format!("{} {}", count, mode.color_str().to_lowercase()))
+            .collect();
+        let parts = if parts.is_empty() {
+            "nothing".to_string()
+        } else {
+            parts.join(", ")
+        };
+        ui::success(&format!(
+            "{}: {}",
+            if planned { "Planned" } else { "Applied" },
+            parts
+        ));
+        return;
+    }
+
+    ui::section(if planned {
+        "Planned Changes (dry run)"
+    } else {
+        "Application Summary"
+    });
+
+    // Protected files go first so they can't get lost in a long table
+    let protected: Vec<&AppliedAction> = actions
+        .iter()
+        .filter(|a| {
+            let rel = a.target.strip_prefix(home).unwrap_or(&a.target);
+            cfg.file_override(rel).is_some_and(|o| o.protected)
+        })
+        .filter(|a| a.skipped_reason.as_deref() != Some("Already applied"))
+        .collect();
+    if !protected.is_empty() {
+        ui::line(&"Protected files:".red().bold().to_string());
+        for action in &protected {
+            let outcome = match &action.skipped_reason {
+                Some(reason) => format!("kept ({})", reason).yellow(),
+                None if planned => "would be overwritten".red(),
+                None => "overwritten".red(),
+            };
+            ui::line(&format!("  ! {} {}", action.target.display(), outcome));
+        }
+        ui::line("");
+    }
 
     let mut table_rows = Vec::new();
-    let mut counts = BTreeMap::new();
 
     for action in actions {
         let mode_str = action.mode.color_str().to_string();
@@ -376,6 +1387,8 @@ fn print_summary(actions: &[AppliedAction]) {
 
         let status = if let Some(ref reason) = action.skipped_reason {
             format!("({})", reason).dimmed().to_string()
+        } else if action.backup_created && planned {
+            "(would back up)".yellow().to_string()
         } else if action.backup_created {
             "(backed up)".yellow().to_string()
         } else {
@@ -387,24 +1400,22 @@ fn print_summary(actions: &[AppliedAction]) {
             format!("{} {} {}", target, arrow, source),
             status,
         ]);
-
-        *counts.entry(action.mode).or_insert(0) += 1;
     }
 
     // Print count summary
-    println!();
+    ui::line("");
     for (mode, count) in counts {
-        let mode_str = match mode {
-            AppliedMode::Symlinked => "Symlinked".green(),
-            AppliedMode::Copied => "Copied".blue(),
-            AppliedMode::Skipped => "Skipped".dimmed(),
-        };
-        println!("{}: {}", mode_str, count);
+        ui::line(&format!("{}: {}", mode.color_str(), count));
     }
 
     // Print detailed table if not too long
     if table_rows.len() <= 20 {
-        println!();
+        ui::line("");
         ui::print_table(&["Mode", "Path", "Status"], table_rows);
     }
+
+    if actions.iter().any(|a| a.mode == AppliedMode::Merged) {
+        ui::line("");
+        ui::hint("Run 'dotdipper snapshot create' to keep the merged files in compiled");
+    }
 }
@@ -0,0 +1,392 @@
+//! Journal of what each apply changed in `$HOME`, so it can be undone.
+//!
+//! Every apply that writes something records one JSON file under
+//! `journal/` in the data directory: for each target, what was put there
+//! and what was there before (nothing, a symlink, or a file saved as a
+//! `.bak.<timestamp>` backup or moved to the trash). All the applies of one
+//! dotdipper invocation, e.g. one per collection layer, share a journal.
+//! `dotdipper undo --apply` walks the latest journal backwards, removing what
+//! apply created and putting the previous files back.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::ui;
+
+/// What was at a target before apply replaced it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Prior {
+    /// Nothing; apply created the target
+    Missing,
+    /// A file or directory, saved at `path` as a backup or in the trash
+    Backup {
+        #[serde(with = "crate::hash::raw_path")]
+        path: PathBuf,
+    },
+    /// A symlink to `link`
+    Link {
+        #[serde(with = "crate::hash::raw_path")]
+        link: PathBuf,
+    },
+    /// A file that was overwritten without a backup (`backup = false`)
+    Lost,
+}
+
+/// What apply put at a target
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    Symlinked,
+    Copied,
+    /// Moved from `moved_from` after a rename between snapshots
+    Moved,
+    /// A link at the old path of a renamed file, removed
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    #[serde(with = "crate::hash::raw_path")]
+    pub target: PathBuf,
+    pub change: Change,
+    /// Where a symlink points or a copy came from
+    #[serde(with = "crate::hash::raw_path")]
+    pub source: PathBuf,
+    /// Hash of a copied file, to notice edits made after the apply
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Old path of a moved file
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "crate::hash::raw_path::option"
+    )]
+    pub moved_from: Option<PathBuf>,
+    pub prior: Prior,
+}
+
+impl Entry {
+    /// Record a symlink or copy at `target`, replacing `prior`
+    pub fn written(target: &Path, source: &Path, change: Change, prior: Prior) -> Self {
+        let hash = match change {
+            Change::Copied => crate::hash::hash_file(target).ok().map(|h| h.hash),
+            _ => None,
+        };
+        Entry {
+            target: target.to_path_buf(),
+            change,
+            source: source.to_path_buf(),
+            hash,
+            moved_from: None,
+            prior,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Journal {
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub entries: Vec<Entry>,
+    /// Set once the journal has been undone
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undone_at: Option<DateTime<Utc>>,
+}
+
+/// The journal of this invocation, shared by all its applies
+fn run_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| Utc::now().format("%Y%m%d_%H%M%S").to_string())
+}
+
+fn journal_path(id: &str) -> Result<PathBuf> {
+    Ok(crate::paths::journal_dir()?.join(format!("{}.json", id)))
+}
+
+/// What was at `target` before it is replaced, given the backup made of it
+pub fn prior(target: &Path, backup: Option<PathBuf>) -> Prior {
+    if let Some(path) = backup {
+        Prior::Backup { path }
+    } else if let Ok(link) = fs::read_link(target) {
+        Prior::Link { link }
+    } else if target.exists() {
+        Prior::Lost
+    } else {
+        Prior::Missing
+    }
+}
+
+/// Add `entries` to this invocation's journal. Returns the journal id.
+///
+/// Apply records each change as soon as it is made, so a failure later in
+/// the run still leaves everything before it undoable.
+pub fn record(entries: impl IntoIterator<Item = Entry>) -> Result<Option<String>> {
+    let entries: Vec<Entry> = entries.into_iter().collect();
+    if entries.is_empty() {
+        return Ok(current());
+    }
+    let path = journal_path(run_id())?;
+    let mut journal = if path.exists() {
+        load(&path)?
+    } else {
+        Journal {
+            id: run_id().to_string(),
+            created_at: Utc::now(),
+            entries: Vec::new(),
+            undone_at: None,
+        }
+    };
+    journal.entries.extend(entries);
+    save(&path, &journal)?;
+    Ok(Some(journal.id))
+}
+
+/// Id of the journal this invocation wrote, if any
+pub fn current() -> Option<String> {
+    journal_path(run_id())
+        .ok()
+        .filter(|p| p.exists())
+        .map(|_| run_id().to_string())
+}
+
+fn load(path: &Path) -> Result<Journal> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save(path: &Path, journal: &Journal) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(journal)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// All journals, oldest first
+pub fn load_all() -> Result<Vec<Journal>> {
+    let dir = crate::paths::journal_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+    paths.iter().map(|p| load(p)).collect()
+}
+
+/// Whether `entry.target` still holds what apply put there
+fn unchanged_since_apply(entry: &Entry) -> bool {
+    match entry.change {
+        Change::Symlinked => crate::links::points_to(&entry.target, &entry.source),
+        Change::Copied => {
+            entry.hash.is_some()
+                && crate::hash::hash_file(&entry.target)
+                    .is_ok_and(|h| Some(&h.hash) == entry.hash.as_ref())
+        }
+        Change::Moved => entry.target.exists() || entry.target.is_symlink(),
+        Change::Removed => !entry.target.exists() && !entry.target.is_symlink(),
+    }
+}
+
+fn remove(path: &Path) -> Result<()> {
+    if path.is_dir() && !path.is_symlink() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() || path.is_symlink() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Put back what `entry` replaced
+fn revert(entry: &Entry) -> Result<()> {
+    if let Some(from) = &entry.moved_from {
+        fs::rename(&entry.target, from).with_context(|| {
+            format!(
+                "Failed to move {} back to {}",
+                entry.target.display(),
+                from.display()
+            )
+        })?;
+        return Ok(());
+    }
+
+    match &entry.prior {
+        Prior::Missing => remove(&entry.target),
+        Prior::Backup { path } => {
+            remove(&entry.target)?;
+            fs::rename(path, &entry.target).with_context(|| {
+                format!(
+                    "Failed to restore {} from {}",
+                    entry.target.display(),
+                    path.display()
+                )
+            })?;
+            crate::backups::forget(path)
+        }
+        Prior::Link { link } => {
+            remove(&entry.target)?;
+            std::os::unix::fs::symlink(link, &entry.target)?;
+            Ok(())
+        }
+        Prior::Lost => bail!("no backup was made"),
+    }
+}
+
+/// Undo the journal `id`, or the latest one that hasn't been undone.
+/// Targets changed since the apply are left alone unless `force`.
+pub fn undo(id: Option<&str>, force: bool) -> Result<()> {
+    let journals = load_all()?;
+    let mut journal = match id {
+        Some(id) => journals
+            .into_iter()
+            .find(|j| j.id == id)
+            .with_context(|| format!("No apply journal {}", id))?,
+        None => journals
+            .into_iter()
+            .rev()
+            .find(|j| j.undone_at.is_none())
+            .context("No apply to undo")?,
+    };
+    if let Some(at) = journal.undone_at {
+        bail!(
+            "Apply {} was already undone on {}",
+            journal.id,
+            at.format("%Y-%m-%d %H:%M")
+        );
+    }
+
+    ui::info(&format!(
+        "Undoing apply {} ({} file(s))...",
+        journal.id,
+        journal.entries.len()
+    ));
+    let mut reverted = 0;
+    let mut kept = Vec::new();
+    for entry in journal.entries.iter().rev() {
+        if !force && !unchanged_since_apply(entry) {
+            kept.push((entry.target.clone(), "changed since the apply".to_string()));
+            continue;
+        }
+        match revert(entry) {
+            Ok(()) => {
+                println!("  ↩ {}", entry.target.display());
+                reverted += 1;
+            }
+            Err(e) => kept.push((entry.target.clone(), format!("{:#}", e))),
+        }
+    }
+
+    journal.undone_at = Some(Utc::now());
+    save(&journal_path(&journal.id)?, &journal)?;
+    crate::history::Event::new(
+        crate::history::Kind::Undo,
+        format!("Undid apply {}", journal.id),
+    )
+    .files(reverted)
+    .reference(&journal.id)
+    .record();
+
+    if !kept.is_empty() {
+        ui::warn(&format!("Left {} file(s) as they are:", kept.len()));
+        for (target, reason) in &kept {
+            println!("  {} ({})", target.display(), reason);
+        }
+        if kept.iter().any(|(_, r)| r == "changed since the apply") {
+            ui::hint("Pass --force to revert files changed since the apply too");
+        }
+    }
+    ui::success(&format!("Reverted {} file(s)", reverted));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_with_non_utf8_paths_round_trip() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let odd = Path::new("/home/me").join(OsStr::from_bytes(b"caf\xe9.conf"));
+        let entry = Entry {
+            moved_from: Some(odd.with_extension("old")),
+            ..Entry::written(
+                &odd,
+                &odd.with_extension("src"),
+                Change::Moved,
+                Prior::Backup {
+                    path: odd.with_extension("bak"),
+                },
+            )
+        };
+        let text = serde_json::to_string(&entry).unwrap();
+        let read: Entry = serde_json::from_str(&text).unwrap();
+        assert_eq!(read.target, entry.target);
+        assert_eq!(read.source, entry.source);
+        assert_eq!(read.moved_from, entry.moved_from);
+        assert_eq!(read.prior, entry.prior);
+    }
+
+    #[test]
+    fn test_revert_restores_backups_links_and_removes_created_files() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path();
+        let source = dir.join("compiled.zshrc");
+        fs::write(&source, "new\n").unwrap();
+
+        // A copy over a backed up file
+        let target = dir.join(".zshrc");
+        let backup = dir.join(".zshrc.bak.1");
+        fs::write(&backup, "old\n").unwrap();
+        fs::write(&target, "new\n").unwrap();
+        let entry = Entry::written(
+            &target,
+            &source,
+            Change::Copied,
+            Prior::Backup {
+                path: backup.clone(),
+            },
+        );
+        assert!(unchanged_since_apply(&entry));
+        revert(&entry).unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "old\n");
+        assert!(!backup.exists());
+
+        // A symlink that replaced another symlink
+        let linked = dir.join(".vimrc");
+        std::os::unix::fs::symlink(&source, &linked).unwrap();
+        let entry = Entry::written(
+            &linked,
+            &source,
+            Change::Symlinked,
+            Prior::Link {
+                link: PathBuf::from("elsewhere"),
+            },
+        );
+        assert!(unchanged_since_apply(&entry));
+        revert(&entry).unwrap();
+        assert_eq!(fs::read_link(&linked).unwrap(), PathBuf::from("elsewhere"));
+
+        // A file apply created, edited afterwards
+        let created = dir.join(".gitconfig");
+        fs::write(&created, "new\n").unwrap();
+        let entry = Entry::written(&created, &source, Change::Copied, Prior::Missing);
+        fs::write(&created, "edited\n").unwrap();
+        assert!(!unchanged_since_apply(&entry));
+        revert(&entry).unwrap();
+        assert!(!created.exists());
+
+        let lost = Entry::written(&target, &source, Change::Copied, Prior::Lost);
+        assert!(revert(&lost).is_err());
+    }
+}
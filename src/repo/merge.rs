@@ -0,0 +1,245 @@
+//! Three-way merges of files changed both in `$HOME` and in compiled.
+//!
+//! When apply would replace a copy that was edited here since it was last
+//! applied, and the compiled file changed too, overwriting or skipping loses
+//! one side's edits. The version both started from is looked up by the hash
+//! in the applied record, first in the snapshots and then in the compiled
+//! repository's history, and the two sides are merged against it: with
+//! conflict markers where both changed the same lines, or by `$MERGE_TOOL`.
+//!
+//! `$MERGE_TOOL` runs through `sh` with `BASE`, `LOCAL`, `REMOTE` (the
+//! compiled version) and `MERGED` set to the paths of the versions, like
+//! git's `mergetool.<tool>.cmd`. A command that uses none of them gets the
+//! four paths appended in that order. `MERGED` starts out with the conflict
+//! markers, and whatever the tool leaves in it is applied.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::cfg::Config;
+use crate::hash::Manifest;
+use crate::ui;
+
+/// Environment variable naming the merge tool
+pub const MERGE_TOOL_ENV: &str = "MERGE_TOOL";
+
+/// Commits of the compiled history searched for the common version
+const HISTORY_DEPTH: usize = 50;
+
+/// Label of the compiled side in conflict markers
+const COMPILED_LABEL: &str = "compiled";
+
+/// The three versions of a file changed on both sides
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sides {
+    /// What was last applied here
+    pub base: Vec<u8>,
+    /// The file in `$HOME`
+    pub local: Vec<u8>,
+    pub compiled: Vec<u8>,
+}
+
+/// How a file changed on both sides is settled
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Choice {
+    Overwrite,
+    Keep,
+    /// Apply these contents instead of the compiled file
+    Merged {
+        contents: Vec<u8>,
+        conflicted: bool,
+    },
+}
+
+fn hash(contents: &[u8]) -> String {
+    blake3::hash(contents).to_hex().to_string()
+}
+
+/// The versions of `rel_path` when its copy at `target` and the compiled
+/// file at `source` both changed since `applied` recorded it, and the common
+/// version can still be found. `None` for symlinks, binary files and files
+/// only one side changed.
+pub fn sides(
+    cfg: &Config,
+    compiled_root: &Path,
+    applied: &Manifest,
+    rel_path: &Path,
+    source: &Path,
+    target: &Path,
+) -> Option<Sides> {
+    let base_hash = &applied.get_file(rel_path)?.hash;
+    if target.is_symlink() || !target.is_file() || !source.is_file() {
+        return None;
+    }
+    let local = fs::read(target).ok()?;
+    let compiled = fs::read(source).ok()?;
+    if local == compiled || local.contains(&0) || compiled.contains(&0) {
+        return None;
+    }
+    if hash(&local) == *base_hash || hash(&compiled) == *base_hash {
+        return None;
+    }
+    let base = find_base(cfg, compiled_root, rel_path, base_hash)?;
+    Some(Sides {
+        base,
+        local,
+        compiled,
+    })
+}
+
+/// Contents of `rel_path` with hash `wanted`, from the newest snapshot that
+/// has them or else from the compiled repository's history
+fn find_base(cfg: &Config, compiled_root: &Path, rel_path: &Path, wanted: &str) -> Option<Vec<u8>> {
+    let snapshots = crate::snapshots::load_all(cfg).unwrap_or_default();
+    let from_snapshots = snapshots.iter().find_map(|snapshot| {
+        let dir = crate::snapshots::store::find(cfg, &snapshot.id).ok()?;
+        fs::read(dir.join(rel_path))
+            .ok()
+            .filter(|contents| hash(contents) == wanted)
+    });
+    if from_snapshots.is_some() || !compiled_root.join(".git").exists() {
+        return from_snapshots;
+    }
+    crate::vcs::log::changes(compiled_root, Some(rel_path), HISTORY_DEPTH)
+        .unwrap_or_default()
+        .iter()
+        .find_map(|change| {
+            crate::vcs::file_at(compiled_root, &change.commit, rel_path)
+                .ok()
+                .filter(|contents| hash(contents) == wanted)
+        })
+}
+
+/// Ask how to settle `target`, changed here and in compiled. Keeps the local
+/// file when there is no one to ask.
+pub fn ask(target: &Path, sides: &Sides) -> Result<Choice> {
+    let tool = std::env::var(MERGE_TOOL_ENV)
+        .ok()
+        .filter(|t| !t.trim().is_empty());
+    let mut labels = vec!["Merge (conflict markers where both changed the same lines)".to_string()];
+    if let Some(tool) = &tool {
+        labels.push(format!("Merge with {}", tool));
+    }
+    labels.push("Overwrite with the compiled version".to_string());
+    labels.push("Keep the local file".to_string());
+
+    let Some(picked) = ui::prompt_select(
+        &format!("{} changed here and in compiled", target.display()),
+        &labels,
+    ) else {
+        return Ok(Choice::Keep);
+    };
+    let (contents, conflicted) = match (picked, &tool) {
+        (0, _) => merge(sides)?,
+        (1, Some(tool)) => run_tool(tool, target, sides)?,
+        _ if picked == labels.len() - 2 => return Ok(Choice::Overwrite),
+        _ => return Ok(Choice::Keep),
+    };
+    if conflicted {
+        ui::warn(&format!(
+            "{} has conflict markers; edit it to finish the merge",
+            target.display()
+        ));
+    }
+    Ok(Choice::Merged {
+        contents,
+        conflicted,
+    })
+}
+
+/// Merge with conflict markers
+fn merge(sides: &Sides) -> Result<(Vec<u8>, bool)> {
+    crate::vcs::merge_labeled(&sides.local, &sides.base, &sides.compiled, COMPILED_LABEL)
+}
+
+/// Let `tool` merge the versions of `target`
+fn run_tool(tool: &str, target: &Path, sides: &Sides) -> Result<(Vec<u8>, bool)> {
+    let dir = tempfile::tempdir()?;
+    let name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (premerged, _) = merge(sides)?;
+    let versions = [
+        ("BASE", &sides.base),
+        ("LOCAL", &sides.local),
+        ("REMOTE", &sides.compiled),
+        ("MERGED", &premerged),
+    ];
+
+    let mut command = tool.to_string();
+    let uses =
+        |var: &str| tool.contains(&format!("${}", var)) || tool.contains(&format!("${{{}}}", var));
+    if !versions.iter().any(|(var, _)| uses(var)) {
+        command.push_str(" \"$BASE\" \"$LOCAL\" \"$REMOTE\" \"$MERGED\"");
+    }
+    let mut child = Command::new("sh");
+    child.arg("-c").arg(&command);
+    for (var, contents) in versions {
+        let path = dir.path().join(format!("{}_{}", var, name));
+        fs::write(&path, contents)?;
+        child.env(var, &path);
+    }
+    let status = child
+        .status()
+        .with_context(|| format!("Failed to run merge tool '{}'", tool))?;
+    match status.code() {
+        Some(0) => {}
+        Some(127) => bail!(
+            "Merge tool '{}' was not found. Install it, or set ${} to another",
+            tool,
+            MERGE_TOOL_ENV
+        ),
+        _ => bail!("Merge tool '{}' failed ({})", tool, status),
+    }
+
+    let merged = fs::read(dir.path().join(format!("MERGED_{}", name)))?;
+    let conflicted = merged.windows(8).any(|w| w == b"<<<<<<< ");
+    Ok((merged, conflicted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sides() -> Sides {
+        Sides {
+            base: b"one\ntwo\nthree\n".to_vec(),
+            local: b"uno\ntwo\nthree\n".to_vec(),
+            compiled: b"eins\ntwo\nTHREE\n".to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_merge_tool_gets_every_version_and_its_result_is_applied() {
+        let target = Path::new("/home/alice/.bashrc");
+        let (merged, conflicted) = merge(&sides()).unwrap();
+        assert!(conflicted);
+        let merged = String::from_utf8(merged).unwrap();
+        assert!(merged.contains("<<<<<<< local\nuno\n"));
+        assert!(merged.contains(">>>>>>> compiled\n"));
+        assert!(merged.ends_with("THREE\n"));
+
+        // Paths are appended when the command doesn't use them
+        let (merged, conflicted) =
+            run_tool("f() { cat \"$2\" > \"$4\"; }; f", target, &sides()).unwrap();
+        assert!(!conflicted);
+        assert_eq!(merged, b"uno\ntwo\nthree\n");
+
+        let (merged, conflicted) = run_tool(
+            "test \"$(basename \"$BASE\")\" = BASE_.bashrc && cat \"$REMOTE\" > \"$MERGED\"",
+            target,
+            &sides(),
+        )
+        .unwrap();
+        assert!(!conflicted);
+        assert_eq!(merged, b"eins\ntwo\nTHREE\n");
+
+        // Leaving the markers in place is a conflicted merge
+        let (_, conflicted) = run_tool("true", target, &sides()).unwrap();
+        assert!(conflicted);
+        assert!(run_tool("exit 3", target, &sides()).is_err());
+    }
+}
@@ -1,12 +1,16 @@
 pub mod apply;
+pub mod journal;
+pub mod merge;
+pub mod staged;
 
 use anyhow::{Context, Result};
+use std::collections::HashSet;
 use std::fs::{self, File};
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
 
 use crate::cfg::Config;
-use crate::hash::{hash_files, Manifest};
+use crate::hash::{hash_files, DirHash, EmptyDir, FileHash, LinkEntry, Manifest};
 use crate::ui;
 
 pub struct Snapshot {
@@ -17,11 +21,33 @@ pub struct Status {
     pub modified: Vec<PathBuf>,
     pub added: Vec<PathBuf>,
     pub deleted: Vec<PathBuf>,
+    /// `(old, new)`: a deleted file reappearing unchanged at a new tracked path
+    pub renamed: Vec<(PathBuf, PathBuf)>,
 }
 
 impl Status {
     pub fn is_clean(&self) -> bool {
-        self.modified.is_empty() && self.added.is_empty() && self.deleted.is_empty()
+        self.modified.is_empty()
+            && self.added.is_empty()
+            && self.deleted.is_empty()
+            && self.renamed.is_empty()
+    }
+
+    /// Only the changes to paths `matcher` matches
+    pub fn filter(self, matcher: &crate::matching::PathMatcher) -> Status {
+        let keep = |paths: Vec<PathBuf>| -> Vec<PathBuf> {
+            paths.into_iter().filter(|p| matcher.is_match(p)).collect()
+        };
+        Status {
+            modified: keep(self.modified),
+            added: keep(self.added),
+            deleted: keep(self.deleted),
+            renamed: self
+                .renamed
+                .into_iter()
+                .filter(|(old, new)| matcher.is_match(old) || matcher.is_match(new))
+                .collect(),
+        }
     }
 
     pub fn print_detailed(&self) {
@@ -45,78 +71,346 @@ impl Status {
                 println!("  D {}", file.display());
             }
         }
+
+        if !self.renamed.is_empty() {
+            ui::section("Renamed files:");
+            for (old, new) in &self.renamed {
+                println!("  R {} -> {}", old.display(), new.display());
+            }
+        }
     }
+
+    /// Lines for `status --porcelain=v1`: `<code><TAB><path>` with code `M`,
+    /// `A` or `D`, sorted by path. Paths under `home` are shown as `~/...`;
+    /// tabs, newlines and backslashes in paths are escaped. This format is a
+    /// stable interface and must not change within v1.
+    pub fn porcelain_v1(&self, home: &Path) -> Vec<String> {
+        let mut entries: Vec<(&PathBuf, &str)> = self
+            .modified
+            .iter()
+            .map(|p| (p, "M"))
+            .chain(self.added.iter().map(|p| (p, "A")))
+            .chain(self.deleted.iter().map(|p| (p, "D")))
+            // v1 predates rename detection: a rename is a deletion plus an addition
+            .chain(self.renamed.iter().map(|(old, _)| (old, "D")))
+            .chain(self.renamed.iter().map(|(_, new)| (new, "A")))
+            .collect();
+        entries.sort();
+
+        entries
+            .into_iter()
+            .map(|(path, code)| {
+                let shown = match path.strip_prefix(home) {
+                    Ok(rel) => crate::matching::display_tilde(rel),
+                    Err(_) => path.display().to_string(),
+                };
+                ui::porcelain_line(code, &shown)
+            })
+            .collect()
+    }
+
+    /// The changes as JSON for `status --output json`, paths under `home`
+    /// shown as `~/...`
+    pub fn to_json(&self, home: &Path) -> serde_json::Value {
+        let shown = |path: &PathBuf| match path.strip_prefix(home) {
+            Ok(rel) => crate::matching::display_tilde(rel),
+            Err(_) => path.display().to_string(),
+        };
+        let list = |paths: &[PathBuf]| paths.iter().map(shown).collect::<Vec<_>>();
+        serde_json::json!({
+            "clean": self.is_clean(),
+            "modified": list(&self.modified),
+            "added": list(&self.added),
+            "deleted": list(&self.deleted),
+            "renamed": self
+                .renamed
+                .iter()
+                .map(|(old, new)| serde_json::json!({ "from": shown(old), "to": shown(new) }))
+                .collect::<Vec<_>>(),
+        })
+    }
+}
+
+/// Files a snapshot captures: the tracked files, the files in tracked
+/// directories and `[secrets] tracked` files plus enabled extras such as
+/// launch agents, minus known volatile files (explained as they are skipped)
+pub fn planned_files(config: &Config) -> Result<Vec<PathBuf>> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let (mut files, excluded) = tracked_and_excluded(config, &home)?;
+    crate::scan::report_excluded(&excluded, &home);
+    files.extend(crate::macos::launch_agents_to_capture(config, &home));
+    let files = crate::volatile::filter_and_report(config, &home, files)?;
+    crate::limits::filter_and_report(config, &home, files)
+}
+
+/// `tracked_files` plus the files in `[tracked_dirs]` and the `[secrets]
+/// tracked` files not already in it, minus excluded files
+fn all_tracked_files(config: &Config, home: &Path) -> Result<Vec<PathBuf>> {
+    Ok(tracked_and_excluded(config, home)?.0)
+}
+
+/// The tracked files split into the ones to snapshot and the ones
+/// `exclude_patterns` or a `[files]` override excludes
+fn tracked_and_excluded(config: &Config, home: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut files = config.general.tracked_files.clone();
+    let dir_files = crate::scan::tracked_dirs(config, home)?
+        .into_iter()
+        .flat_map(|dir| dir.files);
+    for file in dir_files.chain(crate::secrets::tracked_files(config, home)) {
+        if !files.contains(&file) {
+            files.push(file);
+        }
+    }
+    Ok(crate::scan::Excluder::new(config, home)?.partition(files))
+}
+
+/// `[tracked_dirs]` directories, relative to `home`
+fn tracked_dir_paths(config: &Config, home: &Path) -> Vec<PathBuf> {
+    config
+        .tracked_dirs
+        .keys()
+        .map(|dir| crate::matching::to_home_relative(Path::new(dir), home))
+        .collect()
+}
+
+/// Record the symlinks and empty directories of the `[tracked_dirs]`
+/// directories in `manifest`, leaving out excluded ones
+fn record_tree(config: &Config, home: &Path, manifest: &mut Manifest) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let excluder = crate::scan::Excluder::new(config, home)?;
+    for dir in crate::scan::tracked_dirs(config, home)? {
+        for link in dir.links.iter().filter(|p| !excluder.is_excluded(p)) {
+            let Ok(target) = fs::read_link(link) else {
+                continue;
+            };
+            let rel_path = crate::matching::to_home_relative(link, home);
+            manifest.links.insert(rel_path, LinkEntry { target });
+        }
+        for empty in dir.empty_dirs.iter().filter(|p| !excluder.is_excluded(p)) {
+            let Ok(metadata) = fs::metadata(empty) else {
+                continue;
+            };
+            let rel_path = crate::matching::to_home_relative(empty, home);
+            let mode = metadata.permissions().mode() & 0o7777;
+            manifest.empty_dirs.insert(rel_path, EmptyDir { mode });
+        }
+    }
+    Ok(())
+}
+
+/// Recreate the links and empty directories `manifest` records in the
+/// compiled directory, so it holds the same tree as `$HOME`
+fn mirror_tree(compiled: &Path, manifest: &Manifest) -> Result<()> {
+    for (rel_path, link) in &manifest.links {
+        let stored = compiled.join(rel_path);
+        if fs::read_link(&stored).is_ok_and(|target| target == link.target) {
+            continue;
+        }
+        if stored.is_dir() && !stored.is_symlink() {
+            fs::remove_dir_all(&stored)?;
+        } else if fs::symlink_metadata(&stored).is_ok() {
+            fs::remove_file(&stored)?;
+        }
+        if let Some(parent) = stored.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        std::os::unix::fs::symlink(&link.target, &stored)
+            .with_context(|| format!("Failed to create link {}", stored.display()))?;
+    }
+    for rel_path in manifest.empty_dirs.keys() {
+        fs::create_dir_all(compiled.join(rel_path))?;
+    }
+    Ok(())
+}
+
+/// Remove compiled files and links under the tracked directory `rel_dir`
+/// that are no longer in `manifest`, so the compiled copy mirrors the
+/// directory. Skipped while the directory in `$HOME` is a link to the
+/// compiled one, since the files left out of it then live there.
+fn prune_compiled_dir(
+    compiled: &Path,
+    home: &Path,
+    rel_dir: &Path,
+    manifest: &Manifest,
+) -> Result<()> {
+    let stored_dir = compiled.join(rel_dir);
+    if !stored_dir.is_dir() || crate::links::points_to(&home.join(rel_dir), &stored_dir) {
+        return Ok(());
+    }
+    let walk = crate::confine::walk(&stored_dir);
+    // A link to a file shows up as both
+    for stored in walk.files.iter().chain(&walk.links) {
+        let rel_path = stored.strip_prefix(compiled).unwrap_or(stored);
+        let recorded = manifest.has_file(rel_path) || manifest.links.contains_key(rel_path);
+        if !recorded && fs::symlink_metadata(stored).is_ok() {
+            fs::remove_file(stored)?;
+        }
+    }
+    Ok(())
+}
+
+/// Copy `files` into the compiled directory and record them in the
+/// manifest, leaving every other compiled file and manifest entry as it is
+pub fn add_files(config: &Config, files: &[PathBuf]) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let manifest_path = get_manifest_path()?;
+    let mut manifest = if manifest_path.exists() {
+        Manifest::load(&manifest_path)?
+    } else {
+        Manifest::new()
+    };
+    let repo_path = get_compiled_path()?;
+
+    for mut file_hash in hash_files(files, false)? {
+        let rel_path = crate::system::manifest_path(&file_hash.path, &home);
+        let dest_path = repo_path.join(&rel_path);
+        if let Some(parent) = dest_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        store_file(config, &file_hash.path, &dest_path, false)?;
+        file_hash.path = rel_path;
+        manifest.add_file(file_hash);
+    }
+
+    manifest.save(&manifest_path)
 }
 
 pub fn snapshot(config: &Config, force: bool) -> Result<Snapshot> {
+    // Files meant for other hosts keep their previous version
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let host = crate::hosts::HostIdentity::local(config)?;
+    let (files, mut held) =
+        crate::hosts::partition_files(config, &host, &home, planned_files(config)?);
+    // So do files pinned to an older snapshot on this machine
+    let (files, pinned) = crate::pins::load()?.partition(files, &home);
+    held.extend(pinned);
+    // And templates, which $HOME only has the rendered output of
+    let (files, templates) =
+        crate::template::partition(config, &home, &get_compiled_path()?, files);
+    held.extend(templates);
+    snapshot_files(config, force, &files, &held)
+}
+
+/// Snapshot `files` into the compiled directory.
+///
+/// `excluded` files are left out of this snapshot: their previous compiled
+/// copy and manifest entry (if any) are kept as they are.
+pub fn snapshot_files(
+    config: &Config,
+    force: bool,
+    files: &[PathBuf],
+    excluded: &[PathBuf],
+) -> Result<Snapshot> {
     let manifest_path = get_manifest_path()?;
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let repo_path = get_compiled_path()?;
 
-    // Check if we need to create a snapshot
-    if !force && manifest_path.exists() {
-        let current_manifest = Manifest::load(&manifest_path)?;
-        let tracked_files = &config.general.tracked_files;
-
-        // Quick check if any files have changed
-        let mut has_changes = false;
-        for file in tracked_files {
-            if !file.exists() {
-                has_changes = true;
-                break;
-            }
+    let previous = if manifest_path.exists() {
+        Some(Manifest::load(&manifest_path)?)
+    } else {
+        None
+    };
+    // Incremental snapshots trust the previous manifest for files whose
+    // size, mtime and mode are unchanged
+    let incremental = previous
+        .as_ref()
+        .filter(|_| config.general.incremental_snapshots);
 
-            if let Some(stored_hash) = current_manifest.get_file(file) {
-                if let Ok(current_hash) = crate::hash::hash_file(file) {
-                    if stored_hash.hash != current_hash.hash {
-                        has_changes = true;
-                        break;
-                    }
-                }
-            } else {
-                has_changes = true;
-                break;
-            }
-        }
+    // Hash all tracked files
+    let hashes = match incremental {
+        Some(previous) => crate::hash::hash_files_since(files, true, previous, &home)?,
+        None => hash_files(files, true)?,
+    };
+
+    // Symlinks and empty directories in tracked directories
+    let mut tree = Manifest::new();
+    record_tree(config, &home, &mut tree)?;
 
-        if !has_changes {
+    // Check if we need to create a snapshot
+    if let Some(previous) = previous.as_ref().filter(|_| !force) {
+        if !has_changes(
+            config, previous, &hashes, files, excluded, &home, &repo_path,
+        ) && previous.same_tree(&tree)
+        {
             ui::info("No changes detected, skipping snapshot");
             return Ok(Snapshot {
-                file_count: current_manifest.files.len(),
+                file_count: previous.files.len(),
             });
         }
     }
 
     // Create new manifest
-    let mut manifest = Manifest::new();
-    let tracked_files = &config.general.tracked_files;
-
-    // Hash all tracked files
-    let hashes = hash_files(tracked_files, true)?;
+    let mut manifest = tree;
+
+    // Excluded files keep what the previous snapshot recorded
+    if let Some(previous) = previous.as_ref().filter(|_| !excluded.is_empty()) {
+        for file in excluded {
+            let rel_path = crate::system::manifest_path(file, &home);
+            if let Some(entry) = previous.files.get(&rel_path) {
+                manifest.add_file(entry.clone());
+            }
+        }
+    }
 
     // Copy files to repo and add to manifest
-    let repo_path = get_compiled_path()?;
     fs::create_dir_all(&repo_path)?;
 
     let pb = ui::progress_bar(hashes.len() as u64, "Creating snapshot");
+    let preserve_xattrs = crate::attrs::attributes_config(config).preserve_xattrs;
 
     for file_hash in hashes {
-        // Calculate relative path from home
-        let home = dirs::home_dir().context("Failed to find home directory")?;
-        let rel_path = file_hash
-            .path
-            .strip_prefix(&home)
-            .unwrap_or(&file_hash.path);
+        // Relative to home, or under `@root/` for files outside it
+        let rel_path = &crate::system::manifest_path(&file_hash.path, &home);
+        // The copy must land inside the compiled directory
+        if !crate::confine::is_plain(rel_path) {
+            ui::warn(&format!(
+                "Skipping {}: not a plain path",
+                file_hash.path.display()
+            ));
+            pb.inc(1);
+            continue;
+        }
 
         // Copy file to repo
         let dest_path = repo_path.join(rel_path);
         if let Some(parent) = dest_path.parent() {
             fs::create_dir_all(parent)?;
         }
+        // What was a link last time must not be written through
+        if dest_path.is_symlink() {
+            fs::remove_file(&dest_path)?;
+        }
 
-        copy_file_with_permissions(&file_hash.path, &dest_path)?;
+        let stored = previous.as_ref().and_then(|p| p.get_file(rel_path));
+        let unchanged = stored.is_some_and(|stored| stored.hash == file_hash.hash);
+        // An unchanged file whose compiled copy is in place is not copied
+        // again, unless it became a secret since and is still in plain text
+        let in_place = incremental.is_some()
+            && stored.is_some_and(|stored| {
+                stored.hash == file_hash.hash && stored.mode == file_hash.mode
+            })
+            && dest_path.is_file()
+            && (!crate::secrets::is_tracked(config, &file_hash.path)
+                || crate::secrets::is_encrypted(&dest_path));
+        if !in_place {
+            store_file(config, &file_hash.path, &dest_path, unchanged)?;
+            for failure in crate::attrs::preserve_on_snapshot(config, &file_hash.path, &dest_path) {
+                ui::warn(&format!(
+                    "Could not preserve attribute on {}: {}",
+                    rel_path.display(),
+                    failure
+                ));
+            }
+        }
 
         // Add to manifest with relative path
         let mut relative_hash = file_hash.clone();
         relative_hash.path = rel_path.to_path_buf();
+        if preserve_xattrs {
+            relative_hash.xattrs =
+                crate::attrs::to_manifest(&crate::attrs::read_xattrs(&file_hash.path));
+        }
         manifest.add_file(relative_hash);
 
         pb.inc(1);
@@ -124,9 +418,39 @@ pub fn snapshot(config: &Config, force: bool) -> Result<Snapshot> {
 
     pb.finish_with_message("Snapshot created");
 
+    // Copies of files excluded or grown over the limits since an earlier
+    // snapshot leave the compiled directory, so they are not pushed
+    if let Some(previous) = &previous {
+        let excluder = crate::scan::Excluder::new(config, &home)?;
+        let limits = crate::limits::Limits::from_config(config, &home)?;
+        for rel_path in previous.files.keys() {
+            let stored = repo_path.join(rel_path);
+            let dropped = excluder.is_excluded(rel_path)
+                || limits
+                    .check(&crate::system::target(&home, rel_path))
+                    .is_some();
+            if !manifest.has_file(rel_path) && dropped && stored.is_file() {
+                fs::remove_file(&stored)?;
+            }
+        }
+    }
+
+    mirror_tree(&repo_path, &manifest)?;
+
+    // Tracked directories are summarized for fast change detection
+    for rel_dir in tracked_dir_paths(config, &home) {
+        prune_compiled_dir(&repo_path, &home, &rel_dir, &manifest)?;
+        let summary = DirHash::of(&rel_dir, manifest.files.values());
+        manifest.dirs.insert(rel_dir, summary);
+    }
+
     // Save manifest
     manifest.save(&manifest_path)?;
 
+    if let Err(e) = crate::macos::capture_login_items(config, &repo_path) {
+        ui::warn(&format!("Could not capture login items: {:#}", e));
+    }
+
     write_push_gitignore(&repo_path, config)?;
 
     Ok(Snapshot {
@@ -134,15 +458,62 @@ pub fn snapshot(config: &Config, force: bool) -> Result<Snapshot> {
     })
 }
 
+/// Whether snapshotting `hashes` would change anything compared to the
+/// `previous` manifest
+fn has_changes(
+    config: &Config,
+    previous: &Manifest,
+    hashes: &[FileHash],
+    files: &[PathBuf],
+    excluded: &[PathBuf],
+    home: &Path,
+    compiled: &Path,
+) -> bool {
+    // A file that vanished or could not be read
+    if hashes.len() != files.len() {
+        return true;
+    }
+
+    for file_hash in hashes {
+        let rel_path = &crate::system::manifest_path(&file_hash.path, home);
+
+        // A secret still stored in plain text needs encrypting
+        if crate::secrets::is_tracked(config, rel_path)
+            && !crate::secrets::is_encrypted(&compiled.join(rel_path))
+        {
+            return true;
+        }
+
+        match previous.get_file(rel_path) {
+            Some(stored) if stored.hash == file_hash.hash && stored.mode == file_hash.mode => {}
+            _ => return true,
+        }
+    }
+
+    // A directory that started or stopped being tracked
+    let dirs = tracked_dir_paths(config, home);
+    if dirs.len() != previous.dirs.len() || dirs.iter().any(|d| !previous.dirs.contains_key(d)) {
+        return true;
+    }
+
+    // A file that is no longer tracked
+    previous.files.keys().any(|rel_path| {
+        let path = crate::system::target(home, rel_path);
+        !files.contains(&path) && !excluded.contains(&path)
+    })
+}
+
 pub fn status(config: &Config) -> Result<Status> {
     let manifest_path = get_manifest_path()?;
 
     if !manifest_path.exists() {
         // No snapshot yet, all files are "added"
+        let home = dirs::home_dir().context("Failed to find home directory")?;
         return Ok(Status {
             modified: vec![],
-            added: config.general.tracked_files.clone(),
+            added: all_tracked_files(config, &home)?,
             deleted: vec![],
+            renamed: vec![],
         });
     }
 
@@ -151,13 +522,45 @@ pub fn status(config: &Config) -> Result<Status> {
         modified: vec![],
         added: vec![],
         deleted: vec![],
+        renamed: vec![],
     };
 
     let home = dirs::home_dir().context("Failed to find home directory")?;
 
+    // A tracked directory that looks as the last snapshot recorded is not
+    // compared file by file
+    let mut clean_dirs = Vec::new();
+    for dir in crate::scan::tracked_dirs(config, &home)? {
+        let rel_dir = dir.dir.strip_prefix(&home).unwrap_or(&dir.dir);
+        let Some(stored) = manifest.dirs.get(rel_dir) else {
+            continue;
+        };
+        if stored.same_metadata(&dir.files)
+            || DirHash::of(
+                &dir.dir,
+                &crate::hash::hash_files_since(&dir.files, false, &manifest, &home)?,
+            )
+            .hash
+                == stored.hash
+        {
+            clean_dirs.push(rel_dir.to_path_buf());
+        }
+    }
+    let in_clean_dir = |rel_path: &Path| clean_dirs.iter().any(|d| rel_path.starts_with(d));
+
+    // Volatile files and files over the limits are never snapshotted, so
+    // they are not "added" either
+    let volatile = crate::volatile::VolatileFiles::from_config(config, &home)?;
+    let (tracked, _) = volatile.partition(all_tracked_files(config, &home)?);
+    let limits = crate::limits::Limits::from_config(config, &home)?;
+    let (mut tracked, _) = limits.partition(tracked);
+    tracked.retain(|f| !in_clean_dir(&crate::system::manifest_path(f, &home)));
+    let variables = crate::template::Variables::local(config)?;
+    let compiled = get_compiled_path()?;
+
     // Check tracked files
-    for file_path in &config.general.tracked_files {
-        let rel_path = file_path.strip_prefix(&home).unwrap_or(file_path);
+    for file_path in &tracked {
+        let rel_path = &crate::system::manifest_path(file_path, &home);
 
         if !file_path.exists() {
             // File was deleted
@@ -165,8 +568,12 @@ pub fn status(config: &Config) -> Result<Status> {
                 status.deleted.push(file_path.clone());
             }
         } else if let Some(stored_hash) = manifest.get_file(rel_path) {
-            // Check if modified
-            if let Ok(current_hash) = crate::hash::hash_file(file_path) {
+            // Check if modified; templates against what they render to here
+            if crate::template::is_template(config, rel_path) {
+                if !variables.is_rendered(&compiled.join(rel_path), file_path) {
+                    status.modified.push(file_path.clone());
+                }
+            } else if let Ok(current_hash) = crate::hash::hash_file(file_path) {
                 if stored_hash.hash != current_hash.hash {
                     status.modified.push(file_path.clone());
                 }
@@ -179,20 +586,42 @@ pub fn status(config: &Config) -> Result<Status> {
 
     // Check for files in manifest that are no longer tracked
     for rel_path in manifest.files.keys() {
-        let full_path = home.join(rel_path);
-        if !config.general.tracked_files.contains(&full_path) {
+        let full_path = crate::system::target(&home, rel_path);
+        if !tracked.contains(&full_path) && !in_clean_dir(rel_path) {
             status.deleted.push(full_path);
         }
     }
 
+    // Pinned files differ from the latest snapshot on purpose
+    let pins = crate::pins::load()?;
+    for files in [&mut status.modified, &mut status.added, &mut status.deleted] {
+        files.retain(|f| pins.pin_for(f, &home).is_none());
+    }
+
+    // A deleted file whose content shows up at an added path was moved
+    status.deleted.sort();
+    status.deleted.dedup();
+    for old in std::mem::take(&mut status.deleted) {
+        let stored = manifest.get_file(&crate::system::manifest_path(&old, &home));
+        let moved_to = stored.and_then(|stored| {
+            status.added.iter().position(|new| {
+                crate::hash::hash_file(new).is_ok_and(|current| current.hash == stored.hash)
+            })
+        });
+        match moved_to {
+            Some(i) => {
+                let new = status.added.remove(i);
+                status.renamed.push((old, new));
+            }
+            None => status.deleted.push(old),
+        }
+    }
+
     Ok(status)
 }
 
-pub fn check_manifest(config_path: &Path) -> Result<()> {
-    let manifest_path = config_path
-        .parent()
-        .context("Invalid config path")?
-        .join("manifest.lock");
+pub fn check_manifest() -> Result<()> {
+    let manifest_path = crate::paths::manifest_file()?;
 
     if !manifest_path.exists() {
         anyhow::bail!("Manifest not found");
@@ -211,6 +640,100 @@ pub fn check_manifest(config_path: &Path) -> Result<()> {
     Ok(())
 }
 
+/// `doctor` check: the compiled directory exists
+pub fn check_compiled() -> Result<()> {
+    let compiled = get_compiled_path()?;
+    if !compiled.is_dir() {
+        anyhow::bail!("Compiled directory {} is missing", compiled.display());
+    }
+    Ok(())
+}
+
+/// Whether `rel_path` in the compiled directory is one of dotdipper's own
+/// files rather than a dotfile
+fn is_bookkeeping(rel_path: &Path) -> bool {
+    rel_path.starts_with(".git")
+        || rel_path == Path::new(".gitignore")
+        || rel_path == Path::new(crate::macos::LOGIN_ITEMS_FILE)
+        || rel_path.starts_with(crate::machines::MACHINES_DIR)
+        || rel_path.starts_with(crate::workspace::WORKSPACES_DIR)
+        || rel_path.starts_with(crate::archive::ARCHIVE_DIR)
+}
+
+/// Files in the compiled directory the manifest does not list, relative to
+/// it and sorted
+pub fn orphaned_files() -> Result<Vec<PathBuf>> {
+    let repo_path = get_compiled_path()?;
+    let manifest_path = get_manifest_path()?;
+    if !repo_path.is_dir() || !manifest_path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(unlisted(&repo_path, &Manifest::load(&manifest_path)?))
+}
+
+/// Files and links in `compiled` that `manifest` does not list, relative to
+/// it and sorted, leaving out dotdipper's own bookkeeping
+pub fn unlisted(compiled: &Path, manifest: &Manifest) -> Vec<PathBuf> {
+    let walk = crate::confine::walk(compiled);
+    let mut unlisted: Vec<PathBuf> = walk
+        .files
+        .iter()
+        .chain(&walk.links)
+        .filter_map(|f| f.strip_prefix(compiled).ok())
+        .filter(|rel| {
+            !is_bookkeeping(rel) && !manifest.has_file(rel) && !manifest.links.contains_key(*rel)
+        })
+        .map(Path::to_path_buf)
+        .collect();
+    unlisted.sort();
+    unlisted.dedup();
+    unlisted
+}
+
+/// Manifest paths of the files and links tracked now, whether or not a
+/// snapshot has recorded them yet
+pub fn tracked_paths(config: &Config, home: &Path) -> Result<HashSet<PathBuf>> {
+    let (files, _) = tracked_and_excluded(config, home)?;
+    let links = crate::scan::tracked_dirs(config, home)?
+        .into_iter()
+        .flat_map(|dir| dir.links);
+    Ok(files
+        .iter()
+        .cloned()
+        .chain(links)
+        .map(|path| crate::system::manifest_path(&path, home))
+        .collect())
+}
+
+/// Rebuild the manifest from the files in the compiled directory, returning
+/// how many it lists. The hashes are of the compiled copies, so the next
+/// snapshot copies again any file whose copy differs from $HOME.
+pub fn rebuild_manifest(config: &Config) -> Result<usize> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let repo_path = get_compiled_path()?;
+    let files: Vec<PathBuf> = crate::confine::walk(&repo_path)
+        .files
+        .into_iter()
+        .filter(|f| {
+            f.strip_prefix(&repo_path)
+                .is_ok_and(|rel| !is_bookkeeping(rel))
+        })
+        .collect();
+
+    let mut manifest = Manifest::new();
+    for file_hash in hash_files(&files, true)? {
+        let mut entry = file_hash.clone();
+        entry.path = file_hash.path.strip_prefix(&repo_path)?.to_path_buf();
+        manifest.add_file(entry);
+    }
+    for rel_dir in tracked_dir_paths(config, &home) {
+        let summary = DirHash::of(&rel_dir, manifest.files.values());
+        manifest.dirs.insert(rel_dir, summary);
+    }
+    manifest.save(&get_manifest_path()?)?;
+    Ok(manifest.files.len())
+}
+
 fn get_manifest_path() -> Result<PathBuf> {
     crate::paths::manifest_file()
 }
@@ -250,11 +773,82 @@ fn write_push_gitignore(repo_path: &Path, config: &Config) -> Result<()> {
     }
     content.push('\n');
 
+    // Files left out of tracked directories end up in the compiled copy
+    // while the directory is linked to it
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let dir_excludes: Vec<String> = config
+        .tracked_dirs
+        .iter()
+        .flat_map(|(dir, spec)| {
+            let rel_dir = crate::matching::to_home_relative(Path::new(dir), &home);
+            spec.exclude
+                .iter()
+                .filter(|p| !p.trim().is_empty() && !p.starts_with('!') && !p.starts_with('#'))
+                .map(move |p| {
+                    let p = p.trim().trim_end_matches('/');
+                    match p.strip_prefix('/') {
+                        Some(anchored) => format!("/{}/{}", rel_dir.display(), anchored),
+                        None if p.contains('/') => format!("/{}/{}", rel_dir.display(), p),
+                        None => format!("/{}/**/{}", rel_dir.display(), p),
+                    }
+                })
+        })
+        .collect();
+    if !dir_excludes.is_empty() {
+        content.push_str("\n# Left out of tracked directories\n");
+        for line in dir_excludes {
+            content.push_str(&line);
+            content.push('\n');
+        }
+    }
+
     fs::write(repo_path.join(".gitignore"), content)?;
     Ok(())
 }
 
-fn copy_file_with_permissions(source: &Path, dest: &Path) -> Result<()> {
+/// Copy a tracked file into a compiled tree, encrypting `[secrets] tracked`
+/// files whole and otherwise the regions between secret markers. An
+/// `unchanged` encrypted file keeps its stored copy so re-encryption does
+/// not rewrite it on every snapshot.
+pub(crate) fn store_file(
+    config: &Config,
+    source: &Path,
+    dest: &Path,
+    unchanged: bool,
+) -> Result<()> {
+    if crate::secrets::is_tracked(config, source) {
+        if unchanged && crate::secrets::is_encrypted(dest) {
+            return Ok(());
+        }
+        let plaintext =
+            fs::read(source).with_context(|| format!("Failed to read {}", source.display()))?;
+        fs::write(dest, crate::secrets::encrypt_to_memory(config, &plaintext)?)
+            .with_context(|| format!("Failed to create destination file: {}", dest.display()))?;
+        #[cfg(unix)]
+        fs::set_permissions(dest, source.metadata()?.permissions())?;
+        return Ok(());
+    }
+
+    let has_markers =
+        fs::read_to_string(source).is_ok_and(|text| crate::secrets::inline::has_markers(&text));
+    if !has_markers {
+        return copy_file_with_permissions(source, dest);
+    }
+    if unchanged && dest.exists() {
+        return Ok(());
+    }
+
+    let Some(sealed) = crate::secrets::inline::seal_file(config, source)? else {
+        return copy_file_with_permissions(source, dest);
+    };
+    fs::write(dest, sealed)
+        .with_context(|| format!("Failed to create destination file: {}", dest.display()))?;
+    #[cfg(unix)]
+    fs::set_permissions(dest, source.metadata()?.permissions())?;
+    Ok(())
+}
+
+pub(crate) fn copy_file_with_permissions(source: &Path, dest: &Path) -> Result<()> {
     // Read source file
     let mut source_file = File::open(source)
         .with_context(|| format!("Failed to open source file: {}", source.display()))?;
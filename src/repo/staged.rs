@@ -0,0 +1,615 @@
+//! Atomic apply: stage every file beside its target, then swap them in.
+//!
+//! Nothing in `$HOME` changes until every file has been decrypted, validated
+//! and written to a hidden staging file next to its target. Committing
+//! renames the staging files over their targets; if a rename fails, the
+//! targets already replaced get their previous contents back. A [`Staged`]
+//! dropped without being committed removes its staging files again.
+
+use anyhow::{bail, Context, Result};
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::apply::{self, AppliedAction, AppliedMode, ApplyOpts, PROTECTED_SKIP};
+use super::{journal, merge};
+use crate::cfg::{Config, RestoreMode};
+use crate::hash::Manifest;
+use crate::mounts::MountPolicy;
+use crate::ui;
+
+struct Entry {
+    /// Where the compiled file lives once committed
+    source: PathBuf,
+    target: PathBuf,
+    staged: PathBuf,
+    mode: AppliedMode,
+    /// Hard link to the replaced target, kept until the commit succeeds
+    previous: Option<PathBuf>,
+    /// Index into `Staged::roots`
+    root: usize,
+    agent: bool,
+}
+
+/// Files staged for an atomic apply
+#[derive(Default)]
+pub struct Staged {
+    entries: Vec<Entry>,
+    /// Files that are skipped or already in place
+    unchanged: Vec<AppliedAction>,
+    /// Compiled directories the staged files come from
+    roots: Vec<PathBuf>,
+    /// Directories created for staging files, outermost first
+    created_dirs: Vec<PathBuf>,
+    /// Empty directories to create once committed, with their modes
+    empty_dirs: Vec<(PathBuf, u32)>,
+    /// Old and new paths of renamed files, whose old paths are set aside
+    /// once the new ones are in place
+    moves: Vec<(PathBuf, PathBuf)>,
+}
+
+/// `.<name>.<suffix>` in the same directory as `path`
+fn sidecar(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+impl Staged {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of files that will be written
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Stage the files in `manifest`, read from `compiled_root`. Symlinks
+    /// point into `installed_root`, where the compiled files will be by the
+    /// time the apply is committed. The links `manifest` records are staged
+    /// as they were snapshotted, and its empty directories are created on
+    /// commit.
+    ///
+    /// Fails without touching any target if a file is missing, can't be
+    /// decrypted or rendered, is an invalid launch agent, or has a target that can't be
+    /// replaced by a rename (a directory or an immutable file).
+    pub fn add(
+        &mut self,
+        compiled_root: &Path,
+        installed_root: &Path,
+        manifest: &Manifest,
+        cfg: &Config,
+        opts: &ApplyOpts,
+    ) -> Result<()> {
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let mount_policy = MountPolicy::from_config(cfg, &home);
+        let host = crate::hosts::HostIdentity::local(cfg)?;
+        let variables = crate::template::Variables::local(cfg)?;
+        let excluder = crate::scan::Excluder::new(cfg, &home)?;
+        let applied = apply::load_applied()?;
+        let attrs_cfg = crate::attrs::attributes_config(cfg);
+        let root = self.roots.len();
+        self.roots.push(installed_root.to_path_buf());
+
+        let mut rel_paths: Vec<&PathBuf> = manifest.files.keys().collect();
+        rel_paths.sort();
+
+        for rel_path in rel_paths {
+            let source = compiled_root.join(rel_path);
+            let installed = installed_root.join(rel_path);
+            let mut target = crate::system::target(&home, rel_path);
+            let system = crate::system::is_system(rel_path);
+            if !source.is_file() {
+                bail!("{} is missing from the compiled files", rel_path.display());
+            }
+
+            let decrypted = if source.extension().is_some_and(|ext| ext == "age") {
+                if let Some(stem) = target.file_stem().map(|s| s.to_owned()) {
+                    target.set_file_name(stem);
+                }
+                Some(
+                    crate::secrets::decrypt_to_memory(cfg, &source)
+                        .with_context(|| format!("Failed to decrypt {}", rel_path.display()))?,
+                )
+            } else if crate::secrets::is_tracked(cfg, rel_path)
+                && crate::secrets::is_encrypted(&source)
+            {
+                Some(
+                    crate::secrets::decrypt_to_memory(cfg, &source)
+                        .with_context(|| format!("Failed to decrypt {}", rel_path.display()))?,
+                )
+            } else {
+                crate::secrets::inline::unseal_file(cfg, &source)?.map(String::into_bytes)
+            };
+            // Rendered templates are staged like decrypted contents
+            let mut decrypted = if crate::template::is_template(cfg, rel_path) {
+                let text = match decrypted {
+                    Some(bytes) => String::from_utf8(bytes)
+                        .with_context(|| format!("{} is not UTF-8", rel_path.display()))?,
+                    None => fs::read_to_string(&source)
+                        .with_context(|| format!("Failed to read {}", rel_path.display()))?,
+                };
+                Some(
+                    variables
+                        .render(&rel_path.display().to_string(), &text)?
+                        .into_bytes(),
+                )
+            } else {
+                decrypted
+            };
+
+            let skipped = |mode: AppliedMode, reason: String| AppliedAction {
+                mode,
+                target: target.clone(),
+                source: installed.clone(),
+                backup_created: false,
+                skipped_reason: Some(reason),
+            };
+
+            let file_override = cfg.file_override(rel_path);
+            let escape = crate::system::check_target(cfg, rel_path, &target, &home);
+            let skip_reason = if let Some(reason) = escape.filter(|_| !opts.allow_outside_home) {
+                Some(reason.to_string())
+            } else if system && !crate::system::writable(&target) {
+                // Only a plain apply writes the sudo script for these
+                Some(format!(
+                    "{} (run 'dotdipper apply' for the sudo script)",
+                    apply::NEEDS_ROOT_SKIP
+                ))
+            } else if file_override.is_some_and(|o| !host.allows(o)) {
+                Some(format!("Not for this host ({})", host.name))
+            } else if excluder.is_excluded(rel_path) {
+                Some("Excluded".to_string())
+            } else {
+                mount_policy.check(&target)
+            };
+            if let Some(reason) = skip_reason {
+                self.unchanged.push(skipped(AppliedMode::Skipped, reason));
+                continue;
+            }
+
+            let agent = crate::macos::is_launch_agent(cfg, rel_path);
+            if agent {
+                crate::macos::validate_plist(&source)
+                    .with_context(|| format!("Invalid launch agent {}", rel_path.display()))?;
+            }
+
+            // Decrypted contents, launch agents and files outside $HOME are
+            // always copied
+            let mode = if agent || decrypted.is_some() || system {
+                RestoreMode::Copy
+            } else {
+                file_override
+                    .and_then(|o| o.mode)
+                    .unwrap_or(cfg.general.default_mode)
+            };
+            let mut applied_mode = match mode {
+                RestoreMode::Symlink | RestoreMode::SymlinkDir => AppliedMode::Symlinked,
+                RestoreMode::Copy => AppliedMode::Copied,
+            };
+
+            let in_place = match (&decrypted, mode) {
+                (Some(contents), _) => fs::read(&target).is_ok_and(|current| &current == contents),
+                (None, RestoreMode::Symlink | RestoreMode::SymlinkDir) => {
+                    crate::links::points_to(&target, &installed)
+                }
+                (None, RestoreMode::Copy) => {
+                    apply::is_already_applied(&source, &target, mode).unwrap_or(false)
+                }
+            };
+            if in_place {
+                self.unchanged
+                    .push(skipped(applied_mode, "Already applied".to_string()));
+                continue;
+            }
+
+            if target.exists() || target.is_symlink() {
+                if target.is_dir() && !target.is_symlink() {
+                    bail!("{} is a directory", target.display());
+                }
+                if crate::attrs::is_immutable(&target) {
+                    bail!(
+                        "{} is immutable and can't be replaced atomically",
+                        target.display()
+                    );
+                }
+                let protected = file_override.is_some_and(|o| o.protected);
+                if protected
+                    && !opts.yes_protected
+                    && !ui::prompt_confirm(
+                        &format!("{} is protected. Overwrite it?", target.display()),
+                        false,
+                    )
+                {
+                    self.unchanged
+                        .push(skipped(AppliedMode::Skipped, PROTECTED_SKIP.to_string()));
+                    continue;
+                }
+                // Copies edited here while the compiled file changed too can
+                // be merged instead of overwritten
+                let sides = (!protected
+                    && !opts.force
+                    && decrypted.is_none()
+                    && mode == RestoreMode::Copy)
+                    .then(|| merge::sides(cfg, compiled_root, &applied, rel_path, &source, &target))
+                    .flatten();
+                if let Some(sides) = sides {
+                    match merge::ask(&target, &sides)? {
+                        merge::Choice::Overwrite => {}
+                        merge::Choice::Keep => {
+                            self.unchanged.push(skipped(
+                                AppliedMode::Skipped,
+                                "Kept local changes".to_string(),
+                            ));
+                            continue;
+                        }
+                        merge::Choice::Merged { contents, .. } => {
+                            decrypted = Some(contents);
+                            applied_mode = AppliedMode::Merged;
+                        }
+                    }
+                } else if !protected
+                    && !opts.force
+                    && !ui::prompt_confirm(&format!("Overwrite {}?", target.display()), false)
+                {
+                    self.unchanged
+                        .push(skipped(AppliedMode::Skipped, "User declined".to_string()));
+                    continue;
+                }
+            }
+
+            let parent = target.parent().context("Target has no parent directory")?;
+            self.create_dirs(parent)?;
+            let staged = sidecar(&target, "dotdipper-staged");
+            let _ = fs::remove_file(&staged);
+            self.entries.push(Entry {
+                source: installed.clone(),
+                target: target.clone(),
+                staged: staged.clone(),
+                mode: applied_mode,
+                previous: None,
+                root,
+                agent,
+            });
+
+            match (decrypted, mode) {
+                (Some(contents), _) => {
+                    fs::write(&staged, contents)
+                        .with_context(|| format!("Failed to stage {}", target.display()))?;
+                    fs::set_permissions(&staged, source.metadata()?.permissions())?;
+                }
+                (None, RestoreMode::Symlink | RestoreMode::SymlinkDir) => {
+                    crate::links::create(&installed, &staged, cfg.general.relative_symlinks)?
+                }
+                (None, RestoreMode::Copy) => apply::copy_file_with_metadata(&source, &staged)?,
+            }
+            // Renaming the staged copy keeps what it is given here
+            if mode == RestoreMode::Copy {
+                let recorded = &manifest.files[rel_path];
+                let xattrs = crate::attrs::from_manifest(&recorded.xattrs);
+                for warning in
+                    apply::restore_xattrs(&attrs_cfg, &source, &staged, mode, xattrs, Vec::new())
+                {
+                    ui::warn(&format!("Could not restore attribute: {}", warning));
+                }
+                crate::perms::restore_exec_bits(&staged, recorded.mode)?;
+            }
+        }
+
+        self.add_tree(manifest, opts, &home, &mount_policy, &excluder, root)
+    }
+
+    /// Stage the links of `manifest` and note its empty directories
+    fn add_tree(
+        &mut self,
+        manifest: &Manifest,
+        opts: &ApplyOpts,
+        home: &Path,
+        mount_policy: &MountPolicy,
+        excluder: &crate::scan::Excluder,
+        root: usize,
+    ) -> Result<()> {
+        let refused = |rel_path: &Path, target: &Path| {
+            crate::confine::check_target(target, home)
+                .filter(|_| !opts.allow_outside_home)
+                .map(str::to_string)
+                .or_else(|| {
+                    excluder
+                        .is_excluded(rel_path)
+                        .then(|| "Excluded".to_string())
+                })
+                .or_else(|| mount_policy.check(target))
+        };
+
+        for (rel_path, empty) in &manifest.empty_dirs {
+            let target = home.join(rel_path);
+            if refused(rel_path, &target).is_none() && fs::symlink_metadata(&target).is_err() {
+                self.empty_dirs.push((target, empty.mode));
+            }
+        }
+
+        for (rel_path, link) in &manifest.links {
+            let target = home.join(rel_path);
+            let skipped = |mode: AppliedMode, reason: String| AppliedAction {
+                mode,
+                target: target.clone(),
+                source: link.target.clone(),
+                backup_created: false,
+                skipped_reason: Some(reason),
+            };
+            if let Some(reason) = refused(rel_path, &target) {
+                self.unchanged.push(skipped(AppliedMode::Skipped, reason));
+                continue;
+            }
+            if fs::read_link(&target).is_ok_and(|t| t == link.target) {
+                self.unchanged.push(skipped(
+                    AppliedMode::Symlinked,
+                    "Already applied".to_string(),
+                ));
+                continue;
+            }
+            if target.exists() || target.is_symlink() {
+                if target.is_dir() && !target.is_symlink() {
+                    bail!("{} is a directory", target.display());
+                }
+                if crate::attrs::is_immutable(&target) {
+                    bail!(
+                        "{} is immutable and can't be replaced atomically",
+                        target.display()
+                    );
+                }
+                if !opts.force
+                    && !ui::prompt_confirm(&format!("Overwrite {}?", target.display()), false)
+                {
+                    self.unchanged
+                        .push(skipped(AppliedMode::Skipped, "User declined".to_string()));
+                    continue;
+                }
+            }
+
+            let parent = target.parent().context("Target has no parent directory")?;
+            self.create_dirs(parent)?;
+            let staged = sidecar(&target, "dotdipper-staged");
+            let _ = fs::remove_file(&staged);
+            self.entries.push(Entry {
+                source: link.target.clone(),
+                target: target.clone(),
+                staged: staged.clone(),
+                mode: AppliedMode::Symlinked,
+                previous: None,
+                root,
+                agent: false,
+            });
+            std::os::unix::fs::symlink(&link.target, &staged)
+                .with_context(|| format!("Failed to stage {}", target.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Create `dir` and any missing parents, remembering them for cleanup
+    /// Follow renames made on another machine (see `apply::pending_moves`):
+    /// once committed, the files are at their new paths, so the old ones are
+    /// backed up or moved to the trash like replaced targets.
+    pub fn add_moves(&mut self, moves: Vec<(PathBuf, PathBuf)>, opts: &ApplyOpts) -> Result<()> {
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        for (old, new) in moves {
+            let (source, target) = (home.join(&old), home.join(&new));
+            let escape = [&source, &target]
+                .into_iter()
+                .find_map(|path| crate::confine::check_target(path, &home));
+            if let Some(reason) = escape.filter(|_| !opts.allow_outside_home) {
+                self.unchanged.push(AppliedAction {
+                    mode: AppliedMode::Skipped,
+                    target,
+                    source,
+                    backup_created: false,
+                    skipped_reason: Some(reason.to_string()),
+                });
+                continue;
+            }
+            self.moves.push((old, new));
+        }
+        Ok(())
+    }
+
+    fn create_dirs(&mut self, dir: &Path) -> Result<()> {
+        let missing: Vec<PathBuf> = dir
+            .ancestors()
+            .take_while(|d| !d.exists())
+            .map(Path::to_path_buf)
+            .collect();
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        self.created_dirs.extend(missing.into_iter().rev());
+        Ok(())
+    }
+
+    fn swap(entry: &mut Entry) -> Result<()> {
+        if entry.target.exists() || entry.target.is_symlink() {
+            let previous = sidecar(&entry.target, "dotdipper-previous");
+            let _ = fs::remove_file(&previous);
+            fs::hard_link(&entry.target, &previous)
+                .with_context(|| format!("Failed to keep {}", entry.target.display()))?;
+            entry.previous = Some(previous);
+        }
+        fs::rename(&entry.staged, &entry.target)
+            .with_context(|| format!("Failed to replace {}", entry.target.display()))
+    }
+
+    /// Put back the targets of the first `swapped` entries
+    fn roll_back(&mut self, swapped: usize) {
+        for (index, entry) in self.entries.iter_mut().enumerate().rev() {
+            let previous = entry.previous.take();
+            if index >= swapped {
+                if let Some(previous) = previous {
+                    let _ = fs::remove_file(previous);
+                }
+                continue;
+            }
+            let restored = match previous {
+                Some(previous) => fs::rename(&previous, &entry.target),
+                None => fs::remove_file(&entry.target),
+            };
+            if let Err(e) = restored {
+                ui::warn(&format!(
+                    "Could not restore {}: {}",
+                    entry.target.display(),
+                    e
+                ));
+            }
+        }
+    }
+
+    /// Swap every staged file into place, all or nothing
+    pub fn commit(mut self, cfg: &Config) -> Result<Vec<AppliedAction>> {
+        for index in 0..self.entries.len() {
+            if let Err(e) = Self::swap(&mut self.entries[index]) {
+                self.roll_back(index);
+                return Err(e.context("Atomic apply failed; no files were changed"));
+            }
+        }
+
+        let mut actions = std::mem::take(&mut self.unchanged);
+        let mut agents: Vec<Vec<PathBuf>> = vec![Vec::new(); self.roots.len()];
+        for entry in &mut self.entries {
+            let mut backup_created = false;
+            let mut prior = journal::Prior::Missing;
+            if let Some(previous) = entry.previous.take() {
+                if cfg.general.backup && !previous.is_symlink() {
+                    let backup = crate::backups::path_for(cfg, &entry.target)?;
+                    fs::rename(&previous, &backup)?;
+                    crate::backups::register(&entry.target, &backup)?;
+                    ui::info(&format!("Backed up to {}", backup.display()));
+                    backup_created = true;
+                    prior = journal::Prior::Backup { path: backup };
+                } else {
+                    let link = fs::read_link(&previous).ok();
+                    let trashed =
+                        crate::trash::put_as(&previous, &entry.target, "Replaced by apply")?;
+                    prior = match link {
+                        Some(link) => journal::Prior::Link { link },
+                        None => journal::Prior::Backup { path: trashed },
+                    };
+                }
+            }
+            // Links are journaled by where they lead, which is what undo checks
+            let (change, source) = match entry.mode {
+                AppliedMode::Symlinked => (
+                    journal::Change::Symlinked,
+                    crate::links::resolve(&entry.target).unwrap_or_else(|| entry.source.clone()),
+                ),
+                _ => (journal::Change::Copied, entry.source.clone()),
+            };
+            journal::record([journal::Entry::written(
+                &entry.target,
+                &source,
+                change,
+                prior,
+            )])?;
+            if entry.agent {
+                agents[entry.root].push(entry.target.clone());
+            }
+            actions.push(AppliedAction {
+                mode: entry.mode,
+                target: entry.target.clone(),
+                source: entry.source.clone(),
+                backup_created,
+                skipped_reason: None,
+            });
+        }
+        self.created_dirs.clear();
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        for (old, new) in std::mem::take(&mut self.moves) {
+            let (source, target) = (home.join(&old), home.join(&new));
+            // Declined or skipped at the new path: keep the old file
+            if !target.exists() && !target.is_symlink() {
+                continue;
+            }
+            let (backup_created, prior) = apply::set_aside(cfg, &source)?;
+            journal::record([journal::Entry::written(
+                &source,
+                &target,
+                journal::Change::Removed,
+                prior,
+            )])?;
+            ui::info(&format!("Moved ~/{} -> ~/{}", old.display(), new.display()));
+            actions.push(AppliedAction {
+                mode: AppliedMode::Moved,
+                target,
+                source,
+                backup_created,
+                skipped_reason: None,
+            });
+        }
+        for (dir, mode) in std::mem::take(&mut self.empty_dirs) {
+            use std::os::unix::fs::PermissionsExt;
+            fs::create_dir_all(&dir)?;
+            fs::set_permissions(&dir, fs::Permissions::from_mode(mode))?;
+            ui::info(&format!("Created empty directory {}", dir.display()));
+        }
+
+        for (root, agents) in self.roots.iter().zip(&agents) {
+            crate::macos::after_apply(cfg, root, agents);
+        }
+        let perm_failures = apply::enforce_perms(cfg, &actions, &home);
+        apply::print_summary(&actions, cfg, &home, false);
+        apply::warn_perms(&perm_failures);
+        Ok(actions)
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        for entry in &self.entries {
+            let _ = fs::remove_file(&entry.staged);
+        }
+        for dir in self.created_dirs.iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roll_back_restores_swapped_targets() {
+        let temp = tempfile::tempdir().unwrap();
+        let (first, second) = (temp.path().join("first"), temp.path().join("second"));
+        fs::write(&first, "old").unwrap();
+
+        let mut staged = Staged::new();
+        for target in [&first, &second] {
+            let entry = Entry {
+                source: target.clone(),
+                target: target.clone(),
+                staged: sidecar(target, "dotdipper-staged"),
+                mode: AppliedMode::Copied,
+                previous: None,
+                root: 0,
+                agent: false,
+            };
+            fs::write(&entry.staged, "new").unwrap();
+            staged.entries.push(entry);
+        }
+
+        Staged::swap(&mut staged.entries[0]).unwrap();
+        Staged::swap(&mut staged.entries[1]).unwrap();
+        assert_eq!(fs::read_to_string(&first).unwrap(), "new");
+        staged.roll_back(2);
+        drop(staged);
+
+        assert_eq!(fs::read_to_string(&first).unwrap(), "old");
+        assert!(!second.exists());
+        let left: Vec<_> = fs::read_dir(temp.path()).unwrap().collect();
+        assert_eq!(left.len(), 1);
+    }
+}
@@ -0,0 +1,263 @@
+//! Shared resolver for user-supplied identifiers.
+//!
+//! Snapshot IDs, profile names and tracked paths all go through the same
+//! matching rules so partial input behaves the same in every subcommand:
+//! 1. exact match
+//! 2. prefix match
+//! 3. case-insensitive substring match
+//! 4. fuzzy subsequence match (`nvm` → `.config/nvim`)
+//!
+//! The first tier with any hits wins. A single hit is used directly; several
+//! hits open a selection prompt on a terminal, or fail with the candidate list
+//! when running non-interactively. Input that matches nothing is passed through
+//! unchanged so each command reports its own "not found" error.
+
+use anyhow::{bail, Context, Result};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+use crate::ui;
+
+/// Outcome of matching an input against a list of candidates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Exactly one candidate matched
+    Unique(String),
+    /// Several candidates matched equally well
+    Ambiguous(Vec<String>),
+    /// Nothing matched
+    NoMatch,
+}
+
+/// Match `input` against `candidates` using the tiered rules above.
+pub fn match_candidates(input: &str, candidates: &[String]) -> Resolution {
+    let input = input.trim();
+
+    if let Some(exact) = candidates.iter().find(|c| c.as_str() == input) {
+        return Resolution::Unique(exact.clone());
+    }
+
+    if input.is_empty() {
+        return to_resolution(candidates.to_vec());
+    }
+
+    let lower = input.to_lowercase();
+    let tiers: [&dyn Fn(&str) -> bool; 3] = [
+        &|c: &str| c.starts_with(input),
+        &|c: &str| c.to_lowercase().contains(&lower),
+        &|c: &str| is_subsequence(&lower, &c.to_lowercase()),
+    ];
+
+    for tier in tiers {
+        let hits: Vec<String> = candidates.iter().filter(|c| tier(c)).cloned().collect();
+        if !hits.is_empty() {
+            return to_resolution(hits);
+        }
+    }
+
+    Resolution::NoMatch
+}
+
+/// Resolve `input` to a single candidate, prompting when ambiguous.
+///
+/// `kind` is used in prompts and error messages ("snapshot", "profile", ...).
+pub fn resolve(kind: &str, input: &str, candidates: &[String]) -> Result<String> {
+    match match_candidates(input, candidates) {
+        Resolution::Unique(hit) => {
+            if hit != input.trim() {
+                ui::info(&format!("Using {} '{}'", kind, hit));
+            }
+            Ok(hit)
+        }
+        Resolution::Ambiguous(hits) => {
+            if std::io::stdin().is_terminal() && std::io::stdout().is_terminal() {
+                let idx = ui::prompt_select(
+                    &format!("'{}' matches several {}s, pick one", input, kind),
+                    &hits,
+                )
+                .with_context(|| format!("No {} selected", kind))?;
+                Ok(hits[idx].clone())
+            } else {
+                bail!(
+                    "'{}' is ambiguous, it matches {} {}s:\n  {}",
+                    input,
+                    hits.len(),
+                    kind,
+                    hits.join("\n  ")
+                );
+            }
+        }
+        Resolution::NoMatch => Ok(input.trim().to_string()),
+    }
+}
+
+/// Resolve a (possibly partial) snapshot ID.
+pub fn snapshot_id(config: &Config, input: &str) -> Result<String> {
+    let ids: Vec<String> = crate::snapshots::load_all(config)?
+        .into_iter()
+        .map(|s| s.id)
+        .collect();
+    resolve("snapshot", input, &ids)
+}
+
+/// Resolve a (possibly partial) profile name.
+pub fn profile(input: &str) -> Result<String> {
+    let names = crate::profiles::names()?;
+    resolve("profile", input, &names)
+}
+
+/// Resolve a (possibly partial) tracked path for a command that takes one.
+///
+/// Input that already names a path is returned unchanged: a glob, an existing
+/// file or directory, or a tracked file or one of its parent directories, so
+/// `~/.config/kitty` keeps meaning the whole directory. Anything else is
+/// matched against the tracked files and `[tracked_dirs]`, and the hit is
+/// returned in `~/` form.
+pub fn tracked_path(config: &Config, input: &str) -> Result<String> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let tracked: Vec<PathBuf> = config
+        .general
+        .tracked_files
+        .iter()
+        .cloned()
+        .chain(
+            config
+                .tracked_dirs
+                .keys()
+                .map(|dir| crate::matching::expand_tilde(dir, &home)),
+        )
+        .collect();
+
+    let expanded = crate::matching::expand_tilde(input.trim(), &home);
+    let names_path = crate::matching::contains_glob_chars(input)
+        || expanded.exists()
+        || expanded.is_symlink()
+        || (expanded.is_absolute() && tracked.iter().any(|p| p.starts_with(&expanded)));
+    if names_path {
+        return Ok(input.to_string());
+    }
+
+    let candidates: Vec<String> = tracked.iter().map(|p| display_path(p, &home)).collect();
+    let normalized = crate::matching::to_home_relative(Path::new(input), &home);
+    let query = if normalized.is_absolute() {
+        normalized.to_string_lossy().to_string()
+    } else if input.starts_with("~/") || Path::new(input).is_absolute() {
+        crate::matching::display_tilde(&normalized)
+    } else {
+        input.to_string()
+    };
+
+    let hit = resolve("tracked path", &query, &candidates)?;
+    match candidates.contains(&hit) {
+        true => Ok(hit),
+        false => Ok(input.to_string()),
+    }
+}
+
+/// [`tracked_path`] for each of the comma-separated paths of an `--only`
+pub fn tracked_paths(config: &Config, only: &str) -> Result<Vec<String>> {
+    only.split(',')
+        .map(str::trim)
+        .map(|input| tracked_path(config, input))
+        .collect()
+}
+
+fn display_path(path: &Path, home: &Path) -> String {
+    match path.strip_prefix(home) {
+        Ok(rel) => crate::matching::display_tilde(rel),
+        Err(_) => path.to_string_lossy().to_string(),
+    }
+}
+
+fn to_resolution(mut hits: Vec<String>) -> Resolution {
+    hits.sort();
+    hits.dedup();
+    match hits.len() {
+        0 => Resolution::NoMatch,
+        1 => Resolution::Unique(hits.remove(0)),
+        _ => Resolution::Ambiguous(hits),
+    }
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut hay = haystack.chars();
+    needle.chars().all(|n| hay.any(|h| h == n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids() -> Vec<String> {
+        vec![
+            "20240101_120000".to_string(),
+            "20240102_083000".to_string(),
+            "20250301_090000".to_string(),
+        ]
+    }
+
+    #[test]
+    fn tiers_prefer_exact_then_prefix_then_fuzzy() {
+        assert_eq!(
+            match_candidates("20250301_090000", &ids()),
+            Resolution::Unique("20250301_090000".to_string())
+        );
+        assert_eq!(
+            match_candidates("2025", &ids()),
+            Resolution::Unique("20250301_090000".to_string())
+        );
+        assert!(matches!(
+            match_candidates("2024", &ids()),
+            Resolution::Ambiguous(hits) if hits.len() == 2
+        ));
+        assert_eq!(match_candidates("1999", &ids()), Resolution::NoMatch);
+
+        let paths = vec!["~/.config/nvim".to_string(), "~/.zshrc".to_string()];
+        assert_eq!(
+            match_candidates("nvm", &paths),
+            Resolution::Unique("~/.config/nvim".to_string())
+        );
+        assert_eq!(
+            match_candidates("ZSH", &paths),
+            Resolution::Unique("~/.zshrc".to_string())
+        );
+    }
+
+    #[test]
+    fn ambiguous_input_fails_without_terminal() {
+        // Test harness stdin is not a TTY, so ambiguity must surface as an error
+        if std::io::stdin().is_terminal() {
+            return;
+        }
+        let err = resolve("snapshot", "2024", &ids()).unwrap_err();
+        assert!(err.to_string().contains("ambiguous"));
+        assert!(err.to_string().contains("20240102_083000"));
+    }
+
+    #[test]
+    fn tracked_path_keeps_real_paths_and_resolves_fragments() {
+        let home = dirs::home_dir().unwrap();
+        let mut config = Config::default();
+        config.general.tracked_files = vec![
+            home.join(".config/kitty/kitty.conf"),
+            home.join(".config/kitty/theme.conf"),
+            home.join(".zshrc"),
+        ];
+
+        assert_eq!(tracked_path(&config, "zsh").unwrap(), "~/.zshrc");
+        assert_eq!(
+            tracked_path(&config, "~/.config/kitty").unwrap(),
+            "~/.config/kitty"
+        );
+        assert_eq!(tracked_path(&config, "*.conf").unwrap(), "*.conf");
+        assert_eq!(
+            tracked_path(&config, "nothing-like-it").unwrap(),
+            "nothing-like-it"
+        );
+        assert_eq!(
+            tracked_paths(&config, "zsh, ~/.config/kitty").unwrap(),
+            vec!["~/.zshrc", "~/.config/kitty"]
+        );
+    }
+}
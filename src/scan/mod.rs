@@ -1,22 +1,36 @@
 use anyhow::{Context, Result};
 use glob::Pattern;
-use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
 
 use crate::cfg::Config;
+use crate::matching::{self, contains_glob_chars, PathMatcher};
+use crate::mounts::MountPolicy;
+
+/// Excluded files listed by name before the rest are summarized
+const REPORTED_EXCLUDED: usize = 10;
 
 pub fn discover(config: &Config, show_all: bool) -> Result<Vec<PathBuf>> {
     let home = dirs::home_dir().context("Failed to find home directory")?;
     let mut discovered = Vec::new();
 
-    let ignore_file = crate::paths::ignore_file()?;
-    let excluder = build_excluder(&config.exclude_patterns, &home, &ignore_file)?;
+    let ignore_files = [
+        crate::paths::ignore_file()?,
+        crate::paths::home_ignore_file()?,
+    ];
+    let excluder = build_excluder(&config.exclude_patterns, &home, &ignore_files)?;
+    let mount_policy = MountPolicy::from_config(config, &home);
 
     for pattern in &config.include_patterns {
         let expanded = expand_tilde(pattern, &home);
         let is_glob = pattern.contains('*');
-        discover_pattern(&expanded, &excluder, &mut discovered, show_all, is_glob)?;
+        discover_pattern(
+            &expanded,
+            &excluder,
+            &mount_policy,
+            &mut discovered,
+            show_all,
+            is_glob,
+        )?;
     }
 
     // Re-add already tracked files (they were explicitly chosen)
@@ -29,16 +43,28 @@ pub fn discover(config: &Config, show_all: bool) -> Result<Vec<PathBuf>> {
         }
     }
 
+    // Files in tracked directories are found when snapshotting instead
+    let tracked_dirs: Vec<PathBuf> = config
+        .tracked_dirs
+        .keys()
+        .map(|dir| matching::expand_tilde(dir, &home))
+        .collect();
+    discovered.retain(|file| !tracked_dirs.iter().any(|dir| file.starts_with(dir)));
+
     discovered.sort();
     discovered.dedup();
 
-    Ok(discovered)
+    if show_all {
+        return Ok(discovered);
+    }
+    let discovered = crate::volatile::filter_and_report(config, &home, discovered)?;
+    crate::limits::filter_and_report(config, &home, discovered)
 }
 
 fn should_readd_tracked_file(
     path: &Path,
     include_patterns: &[String],
-    excluder: &Gitignore,
+    excluder: &PathMatcher,
     home: &Path,
     show_all: bool,
 ) -> bool {
@@ -46,7 +72,7 @@ fn should_readd_tracked_file(
         return true;
     }
 
-    !excluder.matched(path, false).is_ignore()
+    !excluder.is_match(path)
 }
 
 fn is_explicit_file_include(path: &Path, include_patterns: &[String], home: &Path) -> bool {
@@ -57,7 +83,8 @@ fn is_explicit_file_include(path: &Path, include_patterns: &[String], home: &Pat
 
 fn discover_pattern(
     pattern: &str,
-    excluder: &Gitignore,
+    excluder: &PathMatcher,
+    mount_policy: &MountPolicy,
     discovered: &mut Vec<PathBuf>,
     show_all: bool,
     is_glob: bool,
@@ -69,50 +96,28 @@ fn discover_pattern(
             Pattern::new(pattern).with_context(|| format!("Invalid glob pattern: {}", pattern))?;
 
         let base_dir = get_base_dir_from_pattern(pattern, &home);
-
-        for entry in WalkDir::new(&base_dir)
-            .follow_links(false)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            let path = entry.path();
-
-            // Only track files, not bare directories
-            if path.is_dir() {
-                continue;
-            }
-
-            if !show_all && excluder.matched(path, false).is_ignore() {
-                continue;
-            }
-
-            if glob_pattern.matches_path(path) {
-                discovered.push(path.to_path_buf());
-            }
+        if mount_policy.check(&base_dir).is_some() {
+            return Ok(());
         }
+
+        // Never cross into another filesystem (NFS, sshfs, USB drives, ...)
+        // that [mounts].allow doesn't list
+        discovered.extend(walk_reporting(&base_dir, mount_policy, |path| {
+            (show_all || !excluder.is_match(path)) && glob_pattern.matches_path(path)
+        }));
     } else {
         let path = PathBuf::from(pattern);
-        if path.exists() {
+        // Checked before touching the path, which can hang on a dead mount
+        if mount_policy.check(&path).is_none() && path.exists() {
             if path.is_dir() {
-                for entry in WalkDir::new(&path)
-                    .follow_links(false)
-                    .into_iter()
-                    .filter_map(|e| e.ok())
-                {
-                    let entry_path = entry.path();
-
-                    if entry_path.is_file() {
-                        if !show_all && excluder.matched(entry_path, false).is_ignore() {
-                            continue;
-                        }
-                        discovered.push(entry_path.to_path_buf());
-                    }
-                }
+                discovered.extend(walk_reporting(&path, mount_policy, |entry_path| {
+                    show_all || !excluder.is_match(entry_path)
+                }));
             } else if path.is_file() {
                 // Direct file include patterns bypass exclusions — the user
                 // explicitly asked for this file (e.g. ~/.ssh/config despite
                 // ~/.ssh/** being excluded).
-                if !is_glob || show_all || !excluder.matched(&path, false).is_ignore() {
+                if !is_glob || show_all || !excluder.is_match(&path) {
                     discovered.push(path);
                 }
             }
@@ -122,52 +127,175 @@ fn discover_pattern(
     Ok(())
 }
 
-fn build_excluder(patterns: &[String], home: &Path, ignore_file: &Path) -> Result<Gitignore> {
-    let mut builder = GitignoreBuilder::new(home);
+/// The `wanted` files under `dir`, warning about wanted symlinks and
+/// loops that were not followed
+fn walk_reporting(
+    dir: &Path,
+    mount_policy: &MountPolicy,
+    wanted: impl Fn(&Path) -> bool,
+) -> Vec<PathBuf> {
+    let walk = crate::confine::walk_allowing(dir, mount_policy.allowed());
+    for (path, reason) in walk.refused.iter().filter(|(p, _)| wanted(p)) {
+        crate::ui::warn(&format!("Not following {}: {}", path.display(), reason));
+    }
+    walk.files.into_iter().filter(|p| wanted(p)).collect()
+}
+
+/// The files of one `[tracked_dirs]` entry
+#[derive(Debug, Clone)]
+pub struct DirFiles {
+    /// The directory, absolute
+    pub dir: PathBuf,
+    pub files: Vec<PathBuf>,
+    /// Symlinks, recorded as links instead of followed. Links apply made
+    /// into the compiled directory are not among them; those to files are
+    /// in `files` and read through to the compiled copy.
+    pub links: Vec<PathBuf>,
+    pub empty_dirs: Vec<PathBuf>,
+}
 
-    if ignore_file.exists() {
-        let contents =
-            std::fs::read_to_string(ignore_file).context("Failed to read .dotdipperignore")?;
-        for line in contents.lines() {
-            let trimmed = line.trim();
-            if trimmed.is_empty() || trimmed.starts_with('#') {
+/// The files of every `[tracked_dirs]` entry, with its include and exclude
+/// patterns applied relative to the directory
+pub fn tracked_dirs(config: &Config, home: &Path) -> Result<Vec<DirFiles>> {
+    let compiled = crate::paths::compiled_dir().ok();
+    let managed = |link: &Path| {
+        compiled
+            .as_ref()
+            .is_some_and(|c| crate::links::points_into(link, c))
+    };
+    let allowed_mounts: Vec<PathBuf> = config
+        .mounts
+        .iter()
+        .flat_map(|m| &m.allow)
+        .map(|a| matching::expand_tilde(a, home))
+        .collect();
+    let mut dirs = Vec::new();
+    for (key, spec) in &config.tracked_dirs {
+        let dir = matching::expand_tilde(key, home);
+        let rel = matching::to_home_relative(&dir, home);
+        if rel.is_absolute() || rel.as_os_str().is_empty() || !crate::confine::is_plain(&rel) {
+            anyhow::bail!("Tracked directories must be under $HOME: {}", key);
+        }
+        let include = PathMatcher::new(&dir, &spec.include)
+            .with_context(|| format!("Invalid include pattern for {}", key))?;
+        let exclude = PathMatcher::new(&dir, &spec.exclude)
+            .with_context(|| format!("Invalid exclude pattern for {}", key))?;
+
+        let wanted =
+            |path: &Path| (include.is_empty() || include.is_match(path)) && !exclude.is_match(path);
+        let walk = match dir.is_dir() {
+            true => crate::confine::walk_allowing(&dir, &allowed_mounts),
+            false => crate::confine::Walk::default(),
+        };
+        let mut links: Vec<PathBuf> = walk
+            .links
+            .into_iter()
+            .filter(|p| !managed(p) && wanted(p))
+            .collect();
+        let mut files: Vec<PathBuf> = walk
+            .files
+            .into_iter()
+            .filter(|p| wanted(p) && !links.contains(p))
+            .collect();
+        for (path, reason) in &walk.refused {
+            if !wanted(path) || links.contains(path) {
                 continue;
             }
-            let gitignore_pat = if let Some(stripped) = trimmed.strip_prefix("~/") {
-                format!("/{}", stripped)
-            } else {
-                trimmed.to_string()
-            };
-            builder
-                .add_line(None, &gitignore_pat)
-                .with_context(|| format!("Invalid pattern in .dotdipperignore: {}", trimmed))?;
+            // Apply links files one at a time into a directory that already
+            // holds local ones; they are still the tracked files
+            if managed(path) {
+                if path.is_file() {
+                    files.push(path.clone());
+                }
+                continue;
+            }
+            crate::ui::warn(&format!("Not following {}: {}", path.display(), reason));
         }
+        let mut empty_dirs: Vec<PathBuf> =
+            walk.empty_dirs.into_iter().filter(|p| wanted(p)).collect();
+        files.sort();
+        links.sort();
+        empty_dirs.sort();
+        dirs.push(DirFiles {
+            dir,
+            files,
+            links,
+            empty_dirs,
+        });
+    }
+    Ok(dirs)
+}
+
+/// `exclude_patterns` plus `[files]` overrides with `exclude = true`. Unlike
+/// `.dotdipperignore`, which only shapes discover, these keep files out of
+/// snapshots and apply even when they are tracked explicitly.
+pub struct Excluder<'a> {
+    config: &'a Config,
+    patterns: PathMatcher,
+    home: PathBuf,
+}
+
+impl<'a> Excluder<'a> {
+    pub fn new(config: &'a Config, home: &Path) -> Result<Self> {
+        let patterns = PathMatcher::new(home, &config.exclude_patterns)
+            .context("Invalid pattern in exclude_patterns")?;
+        Ok(Self {
+            config,
+            patterns,
+            home: home.to_path_buf(),
+        })
     }
 
-    for pattern in patterns {
-        let gitignore_pat = if let Some(stripped) = pattern.strip_prefix("~/") {
-            format!("/{}", stripped)
-        } else {
-            pattern.clone()
-        };
-        builder
-            .add_line(None, &gitignore_pat)
-            .with_context(|| format!("Invalid exclude pattern: {}", pattern))?;
+    /// Whether `path` (absolute or home-relative) is excluded
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        if self.patterns.is_match(path) {
+            return true;
+        }
+        self.config.files.values().any(|o| o.exclude)
+            && self
+                .config
+                .file_override(&matching::to_home_relative(path, &self.home))
+                .is_some_and(|o| o.exclude)
     }
 
-    Ok(builder.build()?)
+    /// Split `files` into the ones to keep and the excluded ones
+    pub fn partition(&self, files: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        files.into_iter().partition(|f| !self.is_excluded(f))
+    }
 }
 
-fn expand_tilde(path: &str, home: &Path) -> String {
-    if let Some(stripped) = path.strip_prefix("~/") {
-        home.join(stripped).to_string_lossy().to_string()
-    } else {
-        path.to_string()
+/// Explain tracked files left out by [`Excluder`]
+pub fn report_excluded(files: &[PathBuf], home: &Path) {
+    if files.is_empty() {
+        return;
+    }
+    crate::ui::info(&format!(
+        "Skipping {} excluded file(s) (exclude_patterns or [files] exclude):",
+        files.len()
+    ));
+    for file in files.iter().take(REPORTED_EXCLUDED) {
+        println!("  ~/{}", matching::to_home_relative(file, home).display());
+    }
+    if files.len() > REPORTED_EXCLUDED {
+        println!("  ... and {} more", files.len() - REPORTED_EXCLUDED);
     }
 }
 
-fn contains_glob_chars(path: &str) -> bool {
-    path.contains('*') || path.contains('?') || path.contains('[')
+/// Matcher for `exclude_patterns` followed by the `.dotdipperignore` files
+/// (dotdipper's own, then `~/.dotdipperignore` for this machine)
+fn build_excluder(
+    patterns: &[String],
+    home: &Path,
+    ignore_files: &[PathBuf],
+) -> Result<PathMatcher> {
+    PathMatcher::from_patterns_and_files(home, patterns, ignore_files)
+        .context("Invalid pattern in exclude_patterns or .dotdipperignore")
+}
+
+fn expand_tilde(path: &str, home: &Path) -> String {
+    matching::expand_tilde(path, home)
+        .to_string_lossy()
+        .to_string()
 }
 
 fn get_base_dir_from_pattern(pattern: &str, home: &Path) -> PathBuf {
@@ -176,7 +304,7 @@ fn get_base_dir_from_pattern(pattern: &str, home: &Path) -> PathBuf {
 
     let mut base_parts = Vec::new();
     for part in parts {
-        if part.contains('*') || part.contains('?') || part.contains('[') {
+        if contains_glob_chars(part) {
             break;
         }
         base_parts.push(part);
@@ -195,6 +323,40 @@ mod tests {
     use std::fs;
     use tempfile::TempDir;
 
+    #[test]
+    fn excluder_applies_patterns_negations_and_overrides() {
+        let home = Path::new("/home/me");
+        let mut config = Config {
+            exclude_patterns: vec![
+                "~/.config/app/cache/".to_string(),
+                "*.log".to_string(),
+                "!keep.log".to_string(),
+            ],
+            ..Config::default()
+        };
+        config.files.insert(
+            "~/.config/app/token.json".to_string(),
+            crate::cfg::FileOverride {
+                exclude: true,
+                ..Default::default()
+            },
+        );
+        let excluder = Excluder::new(&config, home).unwrap();
+
+        assert!(excluder.is_excluded(&home.join(".config/app/cache/blob")));
+        assert!(excluder.is_excluded(Path::new(".config/app/token.json")));
+        assert!(excluder.is_excluded(&home.join(".npm/debug.log")));
+        assert!(!excluder.is_excluded(&home.join("keep.log")));
+        assert!(!excluder.is_excluded(&home.join(".config/app/settings.json")));
+
+        let (keep, excluded) = excluder.partition(vec![
+            home.join(".zshrc"),
+            home.join(".config/app/cache/blob"),
+        ]);
+        assert_eq!(keep, vec![home.join(".zshrc")]);
+        assert_eq!(excluded, vec![home.join(".config/app/cache/blob")]);
+    }
+
     #[test]
     fn explicit_file_include_overrides_broad_ignore() {
         let temp_dir = TempDir::new().unwrap();
@@ -202,7 +364,7 @@ mod tests {
         let ignore_file = temp_dir.path().join(".dotdipperignore");
         fs::write(&ignore_file, "~/.ssh/**\n").unwrap();
 
-        let excluder = build_excluder(&[], home, &ignore_file).unwrap();
+        let excluder = build_excluder(&[], home, &[ignore_file]).unwrap();
         let ssh_config = home.join(".ssh/config");
 
         assert!(should_readd_tracked_file(
@@ -221,7 +383,7 @@ mod tests {
         let ignore_file = temp_dir.path().join(".dotdipperignore");
         fs::write(&ignore_file, "~/.config/gcloud/**\n").unwrap();
 
-        let excluder = build_excluder(&[], home, &ignore_file).unwrap();
+        let excluder = build_excluder(&[], home, &[ignore_file]).unwrap();
         let gcloud_file = home.join(".config/gcloud/credentials.db");
 
         assert!(!should_readd_tracked_file(
@@ -232,4 +394,74 @@ mod tests {
             false,
         ));
     }
+
+    #[test]
+    fn home_ignore_file_merges_with_exclude_patterns() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path();
+        let shared = home.join(".config/dotdipper/.dotdipperignore");
+        fs::create_dir_all(shared.parent().unwrap()).unwrap();
+        fs::write(&shared, "~/.config/gh/**\n").unwrap();
+        let local = home.join(".dotdipperignore");
+        fs::write(
+            &local,
+            "# this laptop only\n~/.config/work/**\n!~/.config/gh/config.yml\n!build.log\n",
+        )
+        .unwrap();
+
+        let excluder = build_excluder(
+            &["*.log".to_string()],
+            home,
+            &[shared, local, home.join("missing")],
+        )
+        .unwrap();
+        assert!(excluder.is_match(&home.join(".config/work/vpn.conf")));
+        assert!(excluder.is_match(&home.join(".config/gh/hosts.yml")));
+        assert!(!excluder.is_match(&home.join(".config/gh/config.yml")));
+        assert!(excluder.is_match(&home.join(".config/nvim/lsp.log")));
+        // The home file comes after exclude_patterns too
+        assert!(!excluder.is_match(&home.join("build.log")));
+        assert!(!excluder.is_match(&home.join(".config/nvim/init.lua")));
+    }
+
+    #[test]
+    fn tracked_dir_applies_include_and_exclude_relative_to_the_dir() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path();
+        let nvim = home.join(".config/nvim");
+        fs::create_dir_all(nvim.join("lua/plugins")).unwrap();
+        fs::create_dir_all(nvim.join("plugin")).unwrap();
+        fs::write(nvim.join("init.lua"), "").unwrap();
+        fs::write(nvim.join("lua/plugins/lsp.lua"), "").unwrap();
+        fs::write(nvim.join("plugin/packer_compiled.lua"), "").unwrap();
+        fs::write(nvim.join("notes.txt"), "").unwrap();
+        fs::create_dir_all(nvim.join("after")).unwrap();
+        fs::create_dir_all(home.join(".config/shared")).unwrap();
+        std::os::unix::fs::symlink("../shared", nvim.join("shared.lua")).unwrap();
+
+        let mut config = Config::default();
+        config.tracked_dirs.insert(
+            "~/.config/nvim".to_string(),
+            crate::cfg::TrackedDir {
+                include: vec!["*.lua".to_string()],
+                exclude: vec!["plugin/".to_string()],
+            },
+        );
+        let dirs = tracked_dirs(&config, home).unwrap();
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(dirs[0].dir, nvim);
+        assert_eq!(
+            dirs[0].files,
+            vec![nvim.join("init.lua"), nvim.join("lua/plugins/lsp.lua")]
+        );
+        // Links are kept as links, wherever they lead
+        assert_eq!(dirs[0].links, vec![nvim.join("shared.lua")]);
+        assert!(dirs[0].empty_dirs.is_empty());
+
+        config.tracked_dirs.clear();
+        config
+            .tracked_dirs
+            .insert("/etc".to_string(), crate::cfg::TrackedDir::default());
+        assert!(tracked_dirs(&config, home).is_err());
+    }
 }
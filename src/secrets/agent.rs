@@ -0,0 +1,342 @@
+//! Short-lived decrypt agent.
+//!
+//! Holds the age identity in memory behind a unix socket so a run that
+//! decrypts many `.age` files reads (and, once passphrase identities exist,
+//! unlocks) the key only once. The agent is started on demand when
+//! `[secrets_agent] enabled = true`, exits after `timeout_secs` without
+//! requests, and can be stopped immediately with `dotdipper secrets lock`.
+//!
+//! Protocol: one request line per connection (`PING`, `RECIPIENT`,
+//! `DECRYPT <path>`, `LOCK`), answered with `OK <len>\n<bytes>` or
+//! `ERR <message>\n`.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::cfg::Config;
+use crate::ui;
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 900;
+
+/// How long a connected client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the agent listens, in a directory only the user can enter
+pub fn socket_path() -> Result<PathBuf> {
+    Ok(crate::paths::cache_dir()?
+        .join("agent")
+        .join("secrets-agent.sock"))
+}
+
+/// Create `dir` (or tighten an existing one) so only the user can enter it.
+/// The socket is bound inside it, so it is never reachable by anyone else,
+/// not even between bind and chmod.
+fn private_dir(dir: &Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+        .with_context(|| format!("Failed to restrict {}", dir.display()))
+}
+
+fn is_enabled(config: &Config) -> bool {
+    config.secrets_agent.as_ref().is_some_and(|a| a.enabled)
+}
+
+fn timeout(config: &Config) -> Duration {
+    Duration::from_secs(
+        config
+            .secrets_agent
+            .as_ref()
+            .and_then(|a| a.timeout_secs)
+            .unwrap_or(DEFAULT_TIMEOUT_SECS),
+    )
+}
+
+/// Send one request and return the response payload
+pub fn request(socket: &Path, line: &str) -> Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("Failed to connect to agent at {}", socket.display()))?;
+    stream.set_read_timeout(Some(Duration::from_secs(30)))?;
+    writeln!(stream, "{}", line)?;
+    read_response(&mut BufReader::new(stream))
+}
+
+fn read_response<R: BufRead>(reader: &mut R) -> Result<Vec<u8>> {
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let header = header.trim_end();
+
+    if let Some(message) = header.strip_prefix("ERR ") {
+        bail!("{}", message);
+    }
+    let len: usize = header
+        .strip_prefix("OK ")
+        .and_then(|n| n.parse().ok())
+        .with_context(|| format!("Malformed agent response: {}", header))?;
+
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok(payload)
+}
+
+fn write_response<W: Write>(writer: &mut W, result: Result<Vec<u8>>) -> Result<()> {
+    match result {
+        Ok(payload) => {
+            writeln!(writer, "OK {}", payload.len())?;
+            writer.write_all(&payload)?;
+        }
+        Err(e) => writeln!(writer, "ERR {}", format!("{:#}", e).replace('\n', " "))?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Returns true if an agent answers on the socket
+pub fn is_running() -> bool {
+    socket_path()
+        .map(|s| request(&s, "PING").is_ok())
+        .unwrap_or(false)
+}
+
+/// Connect to the agent, starting it in the background if needed.
+///
+/// Returns `None` when the agent is disabled or could not be started; callers
+/// then fall back to reading the key file directly.
+pub fn ensure_running(config: &Config) -> Option<PathBuf> {
+    if !is_enabled(config) {
+        return None;
+    }
+    let socket = socket_path().ok()?;
+    if request(&socket, "PING").is_ok() {
+        return Some(socket);
+    }
+
+    let exe = std::env::current_exe().ok()?;
+    let mut cmd = Command::new(exe);
+    cmd.args(["secrets", "agent"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    {
+        // Detach from the terminal's process group so Ctrl-C doesn't kill it
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    if let Err(e) = cmd.spawn() {
+        ui::debug(&format!("Could not start secrets agent: {}", e));
+        return None;
+    }
+
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while Instant::now() < deadline {
+        if request(&socket, "PING").is_ok() {
+            ui::debug("Started secrets agent");
+            return Some(socket);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    None
+}
+
+/// Decrypt through the agent if it is enabled and reachable
+pub fn try_decrypt(config: &Config, encrypted_path: &Path) -> Option<Vec<u8>> {
+    let socket = ensure_running(config)?;
+    let path = encrypted_path.canonicalize().ok()?;
+    match request(&socket, &format!("DECRYPT {}", path.display())) {
+        Ok(plaintext) => Some(plaintext),
+        Err(e) => {
+            ui::debug(&format!("Agent decrypt failed, using key file: {:#}", e));
+            None
+        }
+    }
+}
+
+/// Public key of the identity held by the agent
+pub fn try_recipient(config: &Config) -> Option<String> {
+    let socket = ensure_running(config)?;
+    request(&socket, "RECIPIENT")
+        .ok()
+        .and_then(|r| String::from_utf8(r).ok())
+}
+
+/// Stop the agent and drop the identity. Returns false if none was running.
+pub fn lock() -> Result<bool> {
+    let socket = socket_path()?;
+    if !socket.exists() {
+        return Ok(false);
+    }
+    let stopped = request(&socket, "LOCK").is_ok();
+    let _ = fs::remove_file(&socket);
+    Ok(stopped)
+}
+
+/// Run the agent in the foreground until it times out or is locked
+pub fn run(config: &Config) -> Result<()> {
+    let key_path = super::age_key_path(config);
+    let identity = fs::read_to_string(&key_path)
+        .with_context(|| format!("Failed to read age key at {}", key_path.display()))?;
+
+    let socket = socket_path()?;
+    if let Some(parent) = socket.parent() {
+        private_dir(parent)?;
+    }
+    if socket.exists() {
+        if request(&socket, "PING").is_ok() {
+            bail!("Secrets agent is already running");
+        }
+        fs::remove_file(&socket)?;
+    }
+
+    let listener = UnixListener::bind(&socket)
+        .with_context(|| format!("Failed to bind {}", socket.display()))?;
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&socket, fs::Permissions::from_mode(0o600))?;
+    }
+
+    let result = serve(listener, &identity, timeout(config));
+    let _ = fs::remove_file(&socket);
+    result
+}
+
+/// Accept requests until idle for `idle_timeout` or a `LOCK` arrives
+pub fn serve(listener: UnixListener, identity: &str, idle_timeout: Duration) -> Result<()> {
+    listener.set_nonblocking(true)?;
+    let mut last_activity = Instant::now();
+
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                last_activity = Instant::now();
+                stream.set_nonblocking(false)?;
+                // A misbehaving client must not take the agent down, nor
+                // hold it by staying silent
+                let wait = REQUEST_TIMEOUT.min(idle_timeout);
+                if let Ok(true) = handle(stream, identity, wait) {
+                    return Ok(());
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                if last_activity.elapsed() >= idle_timeout {
+                    return Ok(());
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Handle one connection; returns true when the agent should exit
+fn handle(stream: UnixStream, identity: &str, wait: Duration) -> Result<bool> {
+    stream.set_read_timeout(Some(wait))?;
+    stream.set_write_timeout(Some(wait))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let line = line.trim_end();
+
+    let (command, arg) = line.split_once(' ').unwrap_or((line, ""));
+    let (result, exit) = match command {
+        "PING" => (Ok(Vec::new()), false),
+        "LOCK" => (Ok(Vec::new()), true),
+        "RECIPIENT" => (recipient(identity).map(String::into_bytes), false),
+        "DECRYPT" => (age_decrypt(identity, Path::new(arg)), false),
+        _ => (Err(anyhow::anyhow!("Unknown request: {}", command)), false),
+    };
+
+    write_response(&mut writer, result)?;
+    Ok(exit)
+}
+
+fn recipient(identity: &str) -> Result<String> {
+    identity
+        .lines()
+        .find_map(|l| l.strip_prefix("# public key: "))
+        .map(|k| k.trim().to_string())
+        .context("Could not find public key in age identity")
+}
+
+/// Decrypt with the in-memory identity
+fn age_decrypt(identity: &str, encrypted_path: &Path) -> Result<Vec<u8>> {
+    let ciphertext = fs::read(encrypted_path)
+        .with_context(|| format!("Failed to read {}", encrypted_path.display()))?;
+    super::native::decrypt(identity, &ciphertext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    const IDENTITY: &str = "# created: 2024-01-01T00:00:00Z\n\
+# public key: age1examplepublickey\n\
+AGE-SECRET-KEY-1EXAMPLE\n";
+
+    #[test]
+    fn test_agent_answers_and_exits_on_lock() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.path().join("agent.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+
+        let server = std::thread::spawn(move || serve(listener, IDENTITY, Duration::from_secs(10)));
+
+        assert!(request(&socket, "PING").unwrap().is_empty());
+        assert_eq!(
+            request(&socket, "RECIPIENT").unwrap(),
+            b"age1examplepublickey".to_vec()
+        );
+        let err = request(&socket, "BOGUS").unwrap_err();
+        assert!(err.to_string().contains("Unknown request"));
+
+        request(&socket, "LOCK").unwrap();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_agent_exits_when_idle() {
+        let temp_dir = TempDir::new().unwrap();
+        let listener = UnixListener::bind(temp_dir.path().join("agent.sock")).unwrap();
+        let started = Instant::now();
+        serve(listener, IDENTITY, Duration::from_millis(100)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_silent_client_does_not_hold_the_agent() {
+        let temp_dir = TempDir::new().unwrap();
+        let socket = temp_dir.path().join("agent.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let _silent = UnixStream::connect(&socket).unwrap();
+        let started = Instant::now();
+        serve(listener, IDENTITY, Duration::from_millis(100)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(3));
+    }
+
+    #[test]
+    fn test_socket_dir_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let temp_dir = TempDir::new().unwrap();
+        let mode = |dir: &Path| fs::metadata(dir).unwrap().permissions().mode() & 0o777;
+
+        let fresh = temp_dir.path().join("cache/agent");
+        private_dir(&fresh).unwrap();
+        assert_eq!(mode(&fresh), 0o700);
+
+        let existing = temp_dir.path().join("open");
+        fs::create_dir(&existing).unwrap();
+        fs::set_permissions(&existing, fs::Permissions::from_mode(0o755)).unwrap();
+        private_dir(&existing).unwrap();
+        assert_eq!(mode(&existing), 0o700);
+    }
+}
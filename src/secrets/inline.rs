@@ -0,0 +1,223 @@
+//! Partial-file secrets.
+//!
+//! Lines between a `dotdipper:secret-begin` and a `dotdipper:secret-end`
+//! marker line are encrypted into an armored age blob when the file is
+//! snapshotted, and decrypted again on apply. The rest of the file stays
+//! plain text, so it can still be diffed and reviewed:
+//!
+//! ```text
+//! [user]
+//!     name = Me
+//! # dotdipper:secret-begin
+//!     token = ghp_...
+//! # dotdipper:secret-end
+//! ```
+//!
+//! Whatever precedes the begin marker on its line (`# `, `// `, ...) is
+//! repeated in front of every line of the blob, so the stored file keeps
+//! the blob inside comments.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+use crate::cfg::Config;
+
+pub const BEGIN_MARKER: &str = "dotdipper:secret-begin";
+pub const END_MARKER: &str = "dotdipper:secret-end";
+const ARMOR_BEGIN: &str = "-----BEGIN AGE ENCRYPTED FILE-----";
+
+/// A marked region: the begin marker line's prefix and the enclosed lines
+struct Region<'a> {
+    prefix: &'a str,
+    body: Vec<&'a str>,
+}
+
+enum Chunk<'a> {
+    Plain(&'a str),
+    Secret {
+        begin: &'a str,
+        region: Region<'a>,
+        end: &'a str,
+    },
+}
+
+fn split(text: &str) -> Result<Vec<Chunk<'_>>> {
+    let mut chunks = Vec::new();
+    let mut open: Option<(usize, &str, Region)> = None;
+
+    for (index, line) in text.split_inclusive('\n').enumerate() {
+        match open.take() {
+            None => {
+                if let Some(pos) = line.find(BEGIN_MARKER) {
+                    let region = Region {
+                        prefix: &line[..pos],
+                        body: Vec::new(),
+                    };
+                    open = Some((index + 1, line, region));
+                } else if line.contains(END_MARKER) {
+                    bail!(
+                        "line {}: {} without a matching begin",
+                        index + 1,
+                        END_MARKER
+                    );
+                } else {
+                    chunks.push(Chunk::Plain(line));
+                }
+            }
+            Some((start, begin, mut region)) => {
+                if line.contains(END_MARKER) {
+                    chunks.push(Chunk::Secret {
+                        begin,
+                        region,
+                        end: line,
+                    });
+                } else if line.contains(BEGIN_MARKER) {
+                    bail!(
+                        "line {}: nested {} (opened on line {})",
+                        index + 1,
+                        BEGIN_MARKER,
+                        start
+                    );
+                } else {
+                    region.body.push(line);
+                    open = Some((start, begin, region));
+                }
+            }
+        }
+    }
+
+    if let Some((start, _, _)) = open {
+        bail!("line {}: {} is never closed", start, BEGIN_MARKER);
+    }
+    Ok(chunks)
+}
+
+/// Whether a file's text contains secret markers
+pub fn has_markers(text: &str) -> bool {
+    text.contains(BEGIN_MARKER)
+}
+
+fn is_sealed(region: &Region) -> bool {
+    region.body.first().is_some_and(|line| {
+        line.strip_prefix(region.prefix)
+            .is_some_and(|l| l.trim_end() == ARMOR_BEGIN)
+    })
+}
+
+/// Replace the plaintext of each marked region with `encrypt`'s armored output
+pub fn seal(text: &str, mut encrypt: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    for chunk in split(text)? {
+        match chunk {
+            Chunk::Plain(line) => out.push_str(line),
+            Chunk::Secret { begin, region, end } => {
+                out.push_str(begin);
+                if region.body.is_empty() || is_sealed(&region) {
+                    region.body.iter().for_each(|l| out.push_str(l));
+                } else {
+                    for line in encrypt(&region.body.concat())?.lines() {
+                        out.push_str(region.prefix);
+                        out.push_str(line);
+                        out.push('\n');
+                    }
+                }
+                out.push_str(end);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Replace each sealed region with `decrypt`'s plaintext
+pub fn unseal(text: &str, mut decrypt: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    for chunk in split(text)? {
+        match chunk {
+            Chunk::Plain(line) => out.push_str(line),
+            Chunk::Secret { begin, region, end } => {
+                out.push_str(begin);
+                if is_sealed(&region) {
+                    let armored: String = region
+                        .body
+                        .iter()
+                        .map(|l| l.strip_prefix(region.prefix).unwrap_or(l))
+                        .collect();
+                    out.push_str(&decrypt(&armored)?);
+                } else {
+                    region.body.iter().for_each(|l| out.push_str(l));
+                }
+                out.push_str(end);
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Sealed contents of a file with secret markers, or `None` for other files
+pub fn seal_file(config: &Config, path: &Path) -> Result<Option<String>> {
+    let Some(text) = marked_text(path) else {
+        return Ok(None);
+    };
+    let sealed = seal(&text, |plaintext| {
+        super::encrypt_to_armor(config, plaintext.as_bytes())
+    })
+    .with_context(|| format!("Failed to encrypt secret regions of {}", path.display()))?;
+    Ok(Some(sealed))
+}
+
+/// Decrypted contents of a stored file with sealed regions, or `None` for
+/// other files
+pub fn unseal_file(config: &Config, path: &Path) -> Result<Option<String>> {
+    let Some(text) = marked_text(path) else {
+        return Ok(None);
+    };
+    let plain = unseal(&text, |armored| {
+        let mut blob = tempfile::NamedTempFile::new()?;
+        blob.write_all(armored.as_bytes())?;
+        blob.flush()?;
+        let plaintext = super::decrypt_to_memory(config, blob.path())?;
+        String::from_utf8(plaintext).context("Secret region is not valid UTF-8")
+    })
+    .with_context(|| format!("Failed to decrypt secret regions of {}", path.display()))?;
+    Ok(Some(plain))
+}
+
+fn marked_text(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().filter(|t| has_markers(t))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seal_and_unseal_only_touch_marked_regions() {
+        let plain = "[user]\n  name = Me\n# dotdipper:secret-begin\n  token = abc\n# dotdipper:secret-end\n[core]\n";
+        let encrypt = |s: &str| {
+            Ok(format!(
+                "{}\n{}\n-----END AGE ENCRYPTED FILE-----\n",
+                ARMOR_BEGIN,
+                s.trim().replace(' ', "_")
+            ))
+        };
+        let sealed = seal(plain, encrypt).unwrap();
+        assert_eq!(
+            sealed,
+            "[user]\n  name = Me\n# dotdipper:secret-begin\n# -----BEGIN AGE ENCRYPTED FILE-----\n# token_=_abc\n# -----END AGE ENCRYPTED FILE-----\n# dotdipper:secret-end\n[core]\n"
+        );
+        // Already sealed regions are left alone
+        assert_eq!(seal(&sealed, |_| unreachable!()).unwrap(), sealed);
+
+        let unsealed = unseal(&sealed, |armored| {
+            assert!(armored.starts_with(ARMOR_BEGIN));
+            Ok("  token = abc\n".to_string())
+        })
+        .unwrap();
+        assert_eq!(unsealed, plain);
+
+        assert!(seal("# dotdipper:secret-begin\nx\n", encrypt).is_err());
+        assert!(seal("x\n# dotdipper:secret-end\n", encrypt).is_err());
+    }
+}
@@ -1,12 +1,21 @@
+pub mod agent;
+pub mod inline;
+pub mod native;
+
 use anyhow::{bail, Context, Result};
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use tempfile::NamedTempFile;
 
 use crate::cfg::Config;
+use crate::matching::{expand_tilde, PathMatcher};
 use crate::ui;
 
+/// First line of every binary age file
+const AGE_HEADER: &[u8] = b"age-encryption.org/v1";
+
 /// Provider for secrets encryption
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SecretsProvider {
@@ -24,6 +33,99 @@ impl SecretsProvider {
     }
 }
 
+/// How age encryption is carried out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AgeBackend {
+    /// In-process, through the `age` crate
+    Native,
+    /// The external `age` and `age-keygen` binaries
+    Cli,
+}
+
+impl AgeBackend {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.to_lowercase().as_str() {
+            "native" => Some(AgeBackend::Native),
+            "cli" => Some(AgeBackend::Cli),
+            _ => None,
+        }
+    }
+}
+
+/// Backend from `[secrets] backend`, native unless set otherwise
+pub fn age_backend(config: &Config) -> Result<AgeBackend> {
+    let backend = config
+        .secrets
+        .as_ref()
+        .and_then(|s| s.backend.as_deref())
+        .unwrap_or("native");
+    AgeBackend::parse(backend).with_context(|| {
+        format!(
+            "Unknown secrets backend: {} (expected \"native\" or \"cli\")",
+            backend
+        )
+    })
+}
+
+/// Path to the age identity, from `[secrets] key_path` or the default location
+pub fn age_key_path(config: &Config) -> PathBuf {
+    config
+        .secrets
+        .as_ref()
+        .and_then(|s| s.key_path.as_ref())
+        .map(|p| PathBuf::from(shellexpand::tilde(p).to_string()))
+        .unwrap_or_else(|| {
+            dirs::home_dir()
+                .expect("Could not find home directory")
+                .join(".config/age/keys.txt")
+        })
+}
+
+/// Whether `path` (absolute or home-relative) is listed under
+/// `[secrets] tracked`, directly or through a parent directory
+pub fn is_tracked(config: &Config, path: &Path) -> bool {
+    let tracked = config
+        .secrets
+        .as_ref()
+        .map(|s| s.tracked.as_slice())
+        .unwrap_or_default();
+    if tracked.is_empty() {
+        return false;
+    }
+    let Some(home) = dirs::home_dir() else {
+        return false;
+    };
+    PathMatcher::for_paths(&home, tracked).is_ok_and(|m| m.is_match(path))
+}
+
+/// The files under `[secrets] tracked` that exist, for snapshot to capture
+/// whether or not they are also in `tracked_files`
+pub fn tracked_files(config: &Config, home: &Path) -> Vec<PathBuf> {
+    let tracked = config
+        .secrets
+        .as_ref()
+        .map(|s| s.tracked.as_slice())
+        .unwrap_or_default();
+    let mut files = Vec::new();
+    for entry in tracked {
+        let path = expand_tilde(entry, home);
+        if path.is_file() {
+            files.push(path);
+        } else if path.is_dir() {
+            files.extend(crate::confine::walk(&path).files);
+        }
+    }
+    files
+}
+
+/// Whether `path` holds a binary age file
+pub fn is_encrypted(path: &Path) -> bool {
+    let mut header = [0u8; AGE_HEADER.len()];
+    fs::File::open(path)
+        .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut header))
+        .is_ok_and(|_| header == *AGE_HEADER)
+}
+
 /// Initialize secrets management - generate or import age keys
 pub fn init(config: &Config) -> Result<()> {
     let provider = config
@@ -71,18 +173,22 @@ fn init_age(config: &Config) -> Result<()> {
         fs::create_dir_all(parent)?;
     }
 
-    // Generate key using age-keygen
-    let output = Command::new("age-keygen")
-        .arg("-o")
-        .arg(&key_path)
-        .output()
-        .context("Failed to run age-keygen. Is age installed?")?;
-
-    if !output.status.success() {
-        bail!(
-            "Failed to generate age key: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+    if age_backend(config)? == AgeBackend::Native {
+        native::write_identity(&key_path)?;
+    } else {
+        // Generate key using age-keygen
+        let output = Command::new("age-keygen")
+            .arg("-o")
+            .arg(&key_path)
+            .output()
+            .context("Failed to run age-keygen. Is age installed?")?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to generate age key: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
     }
 
     // Set restrictive permissions (0600)
@@ -155,22 +261,13 @@ fn encrypt_age(config: &Config, input_path: &Path, output_path: Option<&Path>) -
         );
     }
 
-    // Read public key from key file
-    let key_content = fs::read_to_string(&key_path).context("Failed to read age key file")?;
-
-    let public_key = key_content
-        .lines()
-        .find(|l| l.starts_with("# public key: "))
-        .and_then(|l| l.strip_prefix("# public key: "))
-        .context("Could not find public key in age key file")?
-        .trim();
+    let public_key = age_recipient(config, &key_path)?;
 
     // Determine output path
     let out_path = output_path.map(|p| p.to_path_buf()).unwrap_or_else(|| {
-        let mut path = input_path.to_path_buf();
-        let new_name = format!("{}.age", input_path.file_name().unwrap().to_string_lossy());
-        path.set_file_name(new_name);
-        path
+        let mut path = input_path.as_os_str().to_os_string();
+        path.push(".age");
+        PathBuf::from(path)
     });
 
     ui::info(&format!(
@@ -179,26 +276,142 @@ fn encrypt_age(config: &Config, input_path: &Path, output_path: Option<&Path>) -
         out_path.display()
     ));
 
-    // Encrypt using age
+    if age_backend(config)? == AgeBackend::Native {
+        let plaintext = fs::read(input_path)
+            .with_context(|| format!("Failed to read {}", input_path.display()))?;
+        fs::write(&out_path, native::encrypt(&public_key, &plaintext)?)
+            .with_context(|| format!("Failed to write {}", out_path.display()))?;
+    } else {
+        // Encrypt using age
+        let output = Command::new("age")
+            .arg("--encrypt")
+            .arg("--recipient")
+            .arg(&public_key)
+            .arg("--output")
+            .arg(&out_path)
+            .arg(input_path)
+            .output()
+            .context("Failed to run age. Is age installed?")?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to encrypt file: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    ui::success(&format!("Encrypted to {}", out_path.display()));
+    Ok(out_path)
+}
+
+/// Public key to encrypt to, from the agent or the key file
+fn age_recipient(config: &Config, key_path: &Path) -> Result<String> {
+    if let Some(key) = agent::try_recipient(config) {
+        return Ok(key);
+    }
+    let key_content = fs::read_to_string(key_path).context("Failed to read age key file")?;
+    Ok(key_content
+        .lines()
+        .find(|l| l.starts_with("# public key: "))
+        .and_then(|l| l.strip_prefix("# public key: "))
+        .context("Could not find public key in age key file")?
+        .trim()
+        .to_string())
+}
+
+/// Encrypt in memory to an ASCII-armored age blob (for inline secret regions)
+pub fn encrypt_to_armor(config: &Config, plaintext: &[u8]) -> Result<String> {
+    let armored = encrypt_in_memory(config, plaintext, true)?;
+    String::from_utf8(armored).context("age produced invalid armored output")
+}
+
+/// Encrypt in memory to a binary age file (for `[secrets] tracked` files)
+pub fn encrypt_to_memory(config: &Config, plaintext: &[u8]) -> Result<Vec<u8>> {
+    encrypt_in_memory(config, plaintext, false)
+}
+
+fn encrypt_in_memory(config: &Config, plaintext: &[u8], armor: bool) -> Result<Vec<u8>> {
+    let key_path = age_key_path(config);
+    if !key_path.exists() {
+        bail!(
+            "Age key not found at {}. Run 'dotdipper secrets init' first",
+            key_path.display()
+        );
+    }
+    let public_key = age_recipient(config, &key_path)?;
+    if age_backend(config)? == AgeBackend::Native {
+        return if armor {
+            native::encrypt_armored(&public_key, plaintext).map(String::into_bytes)
+        } else {
+            native::encrypt(&public_key, plaintext)
+        };
+    }
+
+    let mut command = Command::new("age");
+    command.arg("--encrypt");
+    if armor {
+        command.arg("--armor");
+    }
+    let mut child = command
+        .arg("--recipient")
+        .arg(&public_key)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run age. Is age installed?")?;
+    child
+        .stdin
+        .take()
+        .context("Failed to open age stdin")?
+        .write_all(plaintext)?;
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to encrypt: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Encrypt `input_path` into `output_path` as a binary age file without
+/// reading it into memory (for remote bundles)
+pub fn encrypt_file(config: &Config, input_path: &Path, output_path: &Path) -> Result<()> {
+    let key_path = age_key_path(config);
+    if !key_path.exists() {
+        bail!(
+            "Age key not found at {}. Run 'dotdipper secrets init' first",
+            key_path.display()
+        );
+    }
+    let public_key = age_recipient(config, &key_path)?;
+    if age_backend(config)? == AgeBackend::Native {
+        let input = fs::File::open(input_path)
+            .with_context(|| format!("Failed to read {}", input_path.display()))?;
+        let output = fs::File::create(output_path)
+            .with_context(|| format!("Failed to write {}", output_path.display()))?;
+        return native::encrypt_stream(&public_key, input, output);
+    }
+
     let output = Command::new("age")
         .arg("--encrypt")
         .arg("--recipient")
-        .arg(public_key)
+        .arg(&public_key)
         .arg("--output")
-        .arg(&out_path)
+        .arg(output_path)
         .arg(input_path)
         .output()
         .context("Failed to run age. Is age installed?")?;
-
     if !output.status.success() {
         bail!(
-            "Failed to encrypt file: {}",
+            "Failed to encrypt: {}",
             String::from_utf8_lossy(&output.stderr)
         );
     }
-
-    ui::success(&format!("Encrypted to {}", out_path.display()));
-    Ok(out_path)
+    Ok(())
 }
 
 fn encrypt_sops(
@@ -252,15 +465,12 @@ fn decrypt_age(config: &Config, input_path: &Path, output_path: Option<&Path>) -
         p.to_path_buf()
     } else {
         // Remove .age suffix if present
-        let name = input_path.file_name().unwrap().to_string_lossy();
-        if let Some(stripped) = name.strip_suffix(".age") {
-            let mut path = input_path.to_path_buf();
-            path.set_file_name(stripped);
-            path
+        if input_path.extension().is_some_and(|ext| ext == "age") {
+            input_path.with_extension("")
         } else {
-            let mut path = input_path.to_path_buf();
-            path.set_file_name(format!("{}.decrypted", name));
-            path
+            let mut path = input_path.as_os_str().to_os_string();
+            path.push(".decrypted");
+            PathBuf::from(path)
         }
     };
 
@@ -270,28 +480,50 @@ fn decrypt_age(config: &Config, input_path: &Path, output_path: Option<&Path>) -
         out_path.display()
     ));
 
-    // Decrypt using age
-    let output = Command::new("age")
-        .arg("--decrypt")
-        .arg("--identity")
-        .arg(&key_path)
-        .arg("--output")
-        .arg(&out_path)
-        .arg(input_path)
-        .output()
-        .context("Failed to run age. Is age installed?")?;
+    if let Some(plaintext) = agent::try_decrypt(config, input_path) {
+        fs::write(&out_path, plaintext)
+            .with_context(|| format!("Failed to write {}", out_path.display()))?;
+        ui::success(&format!("Decrypted to {}", out_path.display()));
+        return Ok(out_path);
+    }
 
-    if !output.status.success() {
-        bail!(
-            "Failed to decrypt file: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+    if age_backend(config)? == AgeBackend::Native {
+        let plaintext = decrypt_native(&key_path, input_path)?;
+        fs::write(&out_path, plaintext)
+            .with_context(|| format!("Failed to write {}", out_path.display()))?;
+    } else {
+        // Decrypt using age
+        let output = Command::new("age")
+            .arg("--decrypt")
+            .arg("--identity")
+            .arg(&key_path)
+            .arg("--output")
+            .arg(&out_path)
+            .arg(input_path)
+            .output()
+            .context("Failed to run age. Is age installed?")?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to decrypt file: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
     }
 
     ui::success(&format!("Decrypted to {}", out_path.display()));
     Ok(out_path)
 }
 
+/// Decrypt `encrypted_path` with the identity in `key_path`, in-process
+fn decrypt_native(key_path: &Path, encrypted_path: &Path) -> Result<Vec<u8>> {
+    let identity = fs::read_to_string(key_path)
+        .with_context(|| format!("Failed to read age key at {}", key_path.display()))?;
+    let ciphertext = fs::read(encrypted_path)
+        .with_context(|| format!("Failed to read {}", encrypted_path.display()))?;
+    native::decrypt(&identity, &ciphertext)
+}
+
 fn decrypt_sops(
     _config: &Config,
     _input_path: &Path,
@@ -401,6 +633,14 @@ fn decrypt_age_to_memory(config: &Config, encrypted_path: &Path) -> Result<Vec<u
         bail!("Age key not found at {}", key_path.display());
     }
 
+    if let Some(plaintext) = agent::try_decrypt(config, encrypted_path) {
+        return Ok(plaintext);
+    }
+
+    if age_backend(config)? == AgeBackend::Native {
+        return decrypt_native(&key_path, encrypted_path);
+    }
+
     // Decrypt using age to stdout
     let output = Command::new("age")
         .arg("--decrypt")
@@ -420,6 +660,50 @@ fn decrypt_age_to_memory(config: &Config, encrypted_path: &Path) -> Result<Vec<u
     Ok(output.stdout)
 }
 
+/// Decrypt the age file `input_path` into `output_path` without reading it
+/// into memory (for remote bundles)
+pub fn decrypt_file(config: &Config, input_path: &Path, output_path: &Path) -> Result<()> {
+    let key_path = age_key_path(config);
+    if !key_path.exists() {
+        bail!(
+            "Age key not found at {}. Copy the key the file was encrypted with there",
+            key_path.display()
+        );
+    }
+
+    if let Some(plaintext) = agent::try_decrypt(config, input_path) {
+        return fs::write(output_path, plaintext)
+            .with_context(|| format!("Failed to write {}", output_path.display()));
+    }
+
+    if age_backend(config)? == AgeBackend::Native {
+        let identity = fs::read_to_string(&key_path)
+            .with_context(|| format!("Failed to read age key at {}", key_path.display()))?;
+        let input = fs::File::open(input_path)
+            .with_context(|| format!("Failed to read {}", input_path.display()))?;
+        let output = fs::File::create(output_path)
+            .with_context(|| format!("Failed to write {}", output_path.display()))?;
+        return native::decrypt_stream(&identity, std::io::BufReader::new(input), output);
+    }
+
+    let output = Command::new("age")
+        .arg("--decrypt")
+        .arg("--identity")
+        .arg(&key_path)
+        .arg("--output")
+        .arg(output_path)
+        .arg(input_path)
+        .output()
+        .context("Failed to run age")?;
+    if !output.status.success() {
+        bail!(
+            "Failed to decrypt file: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
 fn decrypt_sops_to_memory(_config: &Config, _encrypted_path: &Path) -> Result<Vec<u8>> {
     bail!("SOPS provider not implemented");
 }
@@ -431,6 +715,69 @@ pub fn check_age() -> Result<()> {
     Ok(())
 }
 
+/// Check that the configured backend can encrypt; only the CLI backend
+/// needs age installed
+/// `doctor` check: the age key, if there is one, belongs to the owner of
+/// `$HOME`
+pub fn check_key_owner(config: &Config) -> Result<()> {
+    let key_path = age_key_path(config);
+    #[cfg(unix)]
+    if let Ok(metadata) = fs::metadata(&key_path) {
+        use std::os::unix::fs::MetadataExt;
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let owner = fs::metadata(&home)?.uid();
+        if metadata.uid() != owner {
+            bail!(
+                "Age key {} is owned by uid {}, not by the owner of $HOME (uid {}). Run 'sudo chown {} {}'",
+                key_path.display(),
+                metadata.uid(),
+                owner,
+                owner,
+                key_path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `doctor` check: the age key, if there is one, is readable by its owner
+/// only
+pub fn check_key_permissions(config: &Config) -> Result<()> {
+    let key_path = age_key_path(config);
+    #[cfg(unix)]
+    if let Ok(metadata) = fs::metadata(&key_path) {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            bail!(
+                "Age key {} is accessible by other users (mode {:o})",
+                key_path.display(),
+                mode
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Make the age key readable and writable by its owner only
+pub fn fix_key_permissions(config: &Config) -> Result<()> {
+    let key_path = age_key_path(config);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&key_path, std::fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to change permissions of {}", key_path.display()))?;
+    }
+    Ok(())
+}
+
+pub fn check_backend(config: &Config) -> Result<()> {
+    match age_backend(config)? {
+        AgeBackend::Native => Ok(()),
+        AgeBackend::Cli => check_age(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -443,4 +790,67 @@ mod tests {
         assert_eq!(SecretsProvider::parse("sops"), Some(SecretsProvider::Sops));
         assert_eq!(SecretsProvider::parse("invalid"), None);
     }
+
+    #[test]
+    fn test_backend_defaults_to_native() {
+        let mut config = Config::default();
+        assert_eq!(age_backend(&config).unwrap(), AgeBackend::Native);
+
+        config.secrets = Some(crate::cfg::SecretsConfig {
+            provider: None,
+            key_path: None,
+            backend: Some("CLI".to_string()),
+            tracked: Vec::new(),
+        });
+        assert_eq!(age_backend(&config).unwrap(), AgeBackend::Cli);
+
+        config.secrets.as_mut().unwrap().backend = Some("gpg".to_string());
+        assert!(age_backend(&config).is_err());
+    }
+
+    #[test]
+    fn test_tracked_files_expand_directories() {
+        let home = tempfile::tempdir().unwrap();
+        let home = home.path();
+        fs::create_dir_all(home.join(".aws")).unwrap();
+        fs::create_dir_all(home.join(".ssh/keys")).unwrap();
+        fs::write(home.join(".aws/credentials"), "secret").unwrap();
+        fs::write(home.join(".ssh/keys/id_work"), "key").unwrap();
+
+        let config = Config {
+            secrets: Some(crate::cfg::SecretsConfig {
+                provider: None,
+                key_path: None,
+                backend: None,
+                tracked: vec![
+                    "~/.aws/credentials".to_string(),
+                    "~/.ssh/keys".to_string(),
+                    "~/.netrc".to_string(),
+                ],
+            }),
+            ..Default::default()
+        };
+        let mut files = tracked_files(&config, home);
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                home.join(".aws/credentials"),
+                home.join(".ssh/keys/id_work")
+            ]
+        );
+
+        assert!(!is_encrypted(&home.join(".aws/credentials")));
+        let identity = native::generate_identity();
+        let key = identity
+            .lines()
+            .find_map(|l| l.strip_prefix("# public key: "))
+            .unwrap();
+        fs::write(
+            home.join("sealed"),
+            native::encrypt(key, b"secret").unwrap(),
+        )
+        .unwrap();
+        assert!(is_encrypted(&home.join("sealed")));
+    }
 }
@@ -0,0 +1,147 @@
+//! age encryption in-process, through the `age` crate.
+//!
+//! This is the default backend, so secrets work on machines without the
+//! `age` binary. Keys and files are interchangeable with the age CLI: the
+//! key file has the `age-keygen` layout and ciphertexts are plain age files.
+//! Set `backend = "cli"` under `[secrets]` to shell out to `age` instead.
+
+use age::secrecy::ExposeSecret;
+use anyhow::{anyhow, Context, Result};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+fn parse_recipient(public_key: &str) -> Result<age::x25519::Recipient> {
+    public_key
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("Invalid age public key {}: {}", public_key, e))
+}
+
+/// A new identity, laid out the way `age-keygen` writes it
+pub fn generate_identity() -> String {
+    let identity = age::x25519::Identity::generate();
+    format!(
+        "# created: {}\n# public key: {}\n{}\n",
+        chrono::Local::now().format("%Y-%m-%dT%H:%M:%S%:z"),
+        identity.to_public(),
+        identity.to_string().expose_secret()
+    )
+}
+
+/// Write a new identity to `key_path`, readable only by the owner
+pub fn write_identity(key_path: &Path) -> Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(key_path)
+        .with_context(|| format!("Failed to create {}", key_path.display()))?;
+    file.write_all(generate_identity().as_bytes())?;
+    Ok(())
+}
+
+/// Encrypt `plaintext` to `public_key` as a binary age file
+pub fn encrypt(public_key: &str, plaintext: &[u8]) -> Result<Vec<u8>> {
+    age::encrypt(&parse_recipient(public_key)?, plaintext).context("Failed to encrypt")
+}
+
+/// Encrypt `plaintext` to `public_key` as an ASCII-armored age file
+pub fn encrypt_armored(public_key: &str, plaintext: &[u8]) -> Result<String> {
+    age::encrypt_and_armor(&parse_recipient(public_key)?, plaintext).context("Failed to encrypt")
+}
+
+/// Encrypt everything read from `input` to `public_key` as a binary age
+/// file, without holding it in memory
+pub fn encrypt_stream(public_key: &str, mut input: impl Read, output: impl Write) -> Result<()> {
+    let recipient = parse_recipient(public_key)?;
+    let encryptor =
+        age::Encryptor::with_recipients(std::iter::once(&recipient as &dyn age::Recipient))
+            .context("Failed to encrypt")?;
+    let mut writer = encryptor.wrap_output(output)?;
+    io::copy(&mut input, &mut writer).context("Failed to encrypt")?;
+    writer.finish()?;
+    Ok(())
+}
+
+fn parse_identities(identity: &str) -> Result<Vec<Box<dyn age::Identity>>> {
+    age::IdentityFile::from_buffer(identity.as_bytes())
+        .map_err(anyhow::Error::new)
+        .and_then(|file| file.into_identities().map_err(anyhow::Error::new))
+        .context("Failed to read age identity")
+}
+
+/// Decrypt a binary or armored age file with the identities in `identity`,
+/// the contents of a key file
+pub fn decrypt(identity: &str, ciphertext: &[u8]) -> Result<Vec<u8>> {
+    let identities = parse_identities(identity)?;
+
+    let decryptor = age::Decryptor::new_buffered(age::armor::ArmoredReader::new(ciphertext))
+        .context("Not an age encrypted file")?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref()))
+        .context("Failed to decrypt file")?;
+
+    let mut plaintext = Vec::new();
+    reader
+        .read_to_end(&mut plaintext)
+        .context("Failed to decrypt file")?;
+    Ok(plaintext)
+}
+
+/// Decrypt the binary age file read from `input` into `output`, without
+/// holding it in memory
+pub fn decrypt_stream(identity: &str, input: impl Read, mut output: impl Write) -> Result<()> {
+    let identities = parse_identities(identity)?;
+    let decryptor = age::Decryptor::new(input).context("Not an age encrypted file")?;
+    let mut reader = decryptor
+        .decrypt(identities.iter().map(|i| i.as_ref()))
+        .context("Failed to decrypt file")?;
+    io::copy(&mut reader, &mut output).context("Failed to decrypt file")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn public_key(identity: &str) -> &str {
+        identity
+            .lines()
+            .find_map(|l| l.strip_prefix("# public key: "))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_round_trip_binary_and_armored() {
+        let identity = generate_identity();
+        assert!(identity.contains("AGE-SECRET-KEY-"));
+        let key = public_key(&identity);
+
+        let binary = encrypt(key, b"aws_secret_access_key = hunter2\n").unwrap();
+        assert!(binary.starts_with(b"age-encryption.org/v1"));
+        assert_eq!(
+            decrypt(&identity, &binary).unwrap(),
+            b"aws_secret_access_key = hunter2\n"
+        );
+
+        let armored = encrypt_armored(key, b"token").unwrap();
+        assert!(armored.starts_with("-----BEGIN AGE ENCRYPTED FILE-----"));
+        assert_eq!(decrypt(&identity, armored.as_bytes()).unwrap(), b"token");
+
+        let other = generate_identity();
+        assert!(decrypt(&other, &binary).is_err());
+        assert!(encrypt("age1nope", b"").is_err());
+
+        let mut streamed = Vec::new();
+        encrypt_stream(key, &b"bundle"[..], &mut streamed).unwrap();
+        let mut plaintext = Vec::new();
+        decrypt_stream(&identity, streamed.as_slice(), &mut plaintext).unwrap();
+        assert_eq!(plaintext, b"bundle");
+        assert!(decrypt_stream(&other, streamed.as_slice(), io::sink()).is_err());
+    }
+}
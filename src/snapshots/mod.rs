@@ -1,15 +1,20 @@
 //! Snapshot management for dotfiles.
 //!
-//! This module provides functionality to create, list, rollback, and delete
-//! versioned snapshots of dotfiles.
+//! This module provides functionality to create, list, compare, rollback,
+//! and delete versioned snapshots of dotfiles.
 
-use anyhow::Result;
+pub mod space;
+pub mod store;
+
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use crate::cfg::Config;
+use crate::cfg::{Config, RestoreMode};
+use crate::hash::Manifest;
 use crate::ui;
 
 /// Represents a snapshot of dotfiles
@@ -27,6 +32,17 @@ pub struct Snapshot {
     pub size_bytes: u64,
 }
 
+/// Files laid down by `apply --as-of`, kept so they can be reverted later
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AsOfRecord {
+    /// Snapshot the files were last applied from
+    pub snapshot_id: String,
+    pub applied_at: DateTime<Utc>,
+    /// Home-relative path -> hash of the content that was applied
+    #[serde(with = "crate::hash::raw_path::keys")]
+    pub files: BTreeMap<PathBuf, String>,
+}
+
 /// Options for pruning old snapshots
 #[derive(Debug, Clone)]
 pub struct PruneOpts {
@@ -34,19 +50,15 @@ pub struct PruneOpts {
     pub keep_count: Option<usize>,
     /// Keep snapshots newer than this duration string (e.g., "30d", "7d")
     pub keep_age: Option<String>,
-    /// Keep snapshots until total size is under this limit
+    /// Keep the newest snapshots that fit in this much disk space
     pub keep_size: Option<String>,
     /// If true, just show what would be deleted without actually deleting
     pub dry_run: bool,
 }
 
-fn get_snapshots_dir() -> Result<PathBuf> {
-    crate::paths::snapshots_dir()
-}
-
 /// Create a new snapshot
 pub fn create(_config: &Config, message: Option<String>) -> Result<Snapshot> {
-    let snapshots_dir = get_snapshots_dir()?;
+    let snapshots_dir = store::dir(_config)?;
     fs::create_dir_all(&snapshots_dir)?;
 
     // Generate unique ID based on timestamp
@@ -69,6 +81,10 @@ pub fn create(_config: &Config, message: Option<String>) -> Result<Snapshot> {
         {
             if entry.file_type().is_file() {
                 let rel_path = entry.path().strip_prefix(&compiled_dir)?;
+                // The machine registry isn't part of what's snapshotted
+                if rel_path.starts_with(crate::machines::MACHINES_DIR) {
+                    continue;
+                }
                 let target_path = snapshot_dir.join(rel_path);
 
                 if let Some(parent) = target_path.parent() {
@@ -78,6 +94,8 @@ pub fn create(_config: &Config, message: Option<String>) -> Result<Snapshot> {
                 fs::copy(entry.path(), &target_path)?;
                 file_count += 1;
                 size_bytes += entry.metadata()?.len();
+            } else {
+                copy_tree_entry(&entry, &compiled_dir, &snapshot_dir)?;
             }
         }
     }
@@ -96,6 +114,17 @@ pub fn create(_config: &Config, message: Option<String>) -> Result<Snapshot> {
     fs::write(metadata_path, metadata_json)?;
 
     ui::success(&format!("Created snapshot: {} ({} files)", id, file_count));
+    crate::machines::record_local(Some(&id));
+    crate::history::Event::new(
+        crate::history::Kind::Snapshot,
+        match &snapshot.message {
+            Some(message) => format!("Created snapshot: {}", message),
+            None => "Created snapshot".to_string(),
+        },
+    )
+    .files(file_count)
+    .reference(&id)
+    .record();
 
     // Auto-prune if configured
     if let Some(opts) = build_prune_opts_from_config(_config) {
@@ -106,63 +135,88 @@ pub fn create(_config: &Config, message: Option<String>) -> Result<Snapshot> {
         }
     }
 
+    crate::usage::check_quota(_config);
+
     Ok(snapshot)
 }
 
-/// List all snapshots
-pub fn list(config: &Config) -> Result<Vec<Snapshot>> {
-    let _ = config; // Config might be used for filtering in the future
-    let snapshots_dir = get_snapshots_dir()?;
-
-    if !snapshots_dir.exists() {
-        return Ok(Vec::new());
-    }
-
-    let mut snapshots = Vec::new();
+/// Load all snapshot metadata, newest first, without printing anything
+pub fn load_all(config: &Config) -> Result<Vec<Snapshot>> {
+    let mut snapshots: Vec<Snapshot> = Vec::new();
 
-    for entry in fs::read_dir(snapshots_dir)? {
-        let entry = entry?;
-        if entry.file_type()?.is_dir() {
-            let metadata_path = entry.path().join("snapshot.json");
-            if metadata_path.exists() {
-                let content = fs::read_to_string(&metadata_path)?;
-                if let Ok(snapshot) = serde_json::from_str::<Snapshot>(&content) {
-                    snapshots.push(snapshot);
+    for snapshots_dir in store::dirs(config)? {
+        if !snapshots_dir.exists() {
+            continue;
+        }
+        for entry in fs::read_dir(snapshots_dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                let metadata_path = entry.path().join("snapshot.json");
+                if metadata_path.exists() {
+                    let content = fs::read_to_string(&metadata_path)?;
+                    if let Ok(snapshot) = serde_json::from_str::<Snapshot>(&content) {
+                        if !snapshots.iter().any(|s| s.id == snapshot.id) {
+                            snapshots.push(snapshot);
+                        }
+                    }
                 }
             }
         }
     }
 
     // Sort by creation time, newest first
-    snapshots.sort_by(|a, b| b.created_at.cmp(&a.created_at));
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+
+    Ok(snapshots)
+}
+
+/// List all snapshots
+pub fn list(config: &Config) -> Result<Vec<Snapshot>> {
+    let snapshots = load_all(config)?;
 
     // Display snapshots
     if snapshots.is_empty() {
         ui::info("No snapshots found");
     } else {
+        let usage = space::measure(config, &snapshots)?;
         ui::section("Snapshots:");
         for snap in &snapshots {
             let msg = snap.message.as_deref().unwrap_or("(no message)");
             let size = humansize::format_size(snap.size_bytes, humansize::BINARY);
+            let unique = humansize::format_size(usage.unique(&snap.id), humansize::BINARY);
             println!(
-                "  {} - {} ({} files, {})",
-                snap.id, msg, snap.file_count, size
+                "  {} - {} ({} files, {}, {} unique on disk)",
+                snap.id, msg, snap.file_count, size, unique
             );
         }
+        ui::info(&format!(
+            "Snapshot store: {} on disk",
+            humansize::format_size(usage.total(), humansize::BINARY)
+        ));
     }
 
     Ok(snapshots)
 }
 
 /// Rollback to a specific snapshot
-pub fn rollback(config: &Config, id: &str, force: bool) -> Result<()> {
-    let _ = config;
-    let snapshots_dir = get_snapshots_dir()?;
-    let snapshot_dir = snapshots_dir.join(id);
-
-    if !snapshot_dir.exists() {
-        anyhow::bail!("Snapshot not found: {}", id);
+/// Copy a symlink or directory met walking `from` to the same place under
+/// `to`: links as links, directories so empty ones are kept
+fn copy_tree_entry(entry: &walkdir::DirEntry, from: &Path, to: &Path) -> Result<()> {
+    let rel_path = entry.path().strip_prefix(from)?;
+    let target_path = to.join(rel_path);
+    if entry.file_type().is_symlink() {
+        if let Some(parent) = target_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target_path)?;
+    } else if entry.file_type().is_dir() {
+        fs::create_dir_all(&target_path)?;
     }
+    Ok(())
+}
+
+pub fn rollback(config: &Config, id: &str, force: bool) -> Result<()> {
+    let snapshot_dir = store::find(config, id)?;
 
     // Confirm with user unless force is set
     if !force {
@@ -181,9 +235,14 @@ pub fn rollback(config: &Config, id: &str, force: bool) -> Result<()> {
 
     let compiled_dir = crate::paths::compiled_dir()?;
 
-    // Clear current compiled directory
-    if compiled_dir.exists() {
-        fs::remove_dir_all(&compiled_dir)?;
+    // Machines recorded since the snapshot stay recorded
+    let machines = crate::machines::load(&compiled_dir)?;
+
+    // Move the current compiled directory to the trash
+    let reason = format!("Rolled back to snapshot {}", id);
+    let trashed = compiled_dir.exists();
+    if trashed {
+        crate::trash::put(&compiled_dir, &reason)?;
     }
     fs::create_dir_all(&compiled_dir)?;
 
@@ -208,27 +267,443 @@ pub fn rollback(config: &Config, id: &str, force: bool) -> Result<()> {
 
             fs::copy(entry.path(), &target_path)?;
             file_count += 1;
+        } else {
+            copy_tree_entry(&entry, &snapshot_dir, &compiled_dir)?;
         }
     }
+    crate::machines::restore(&compiled_dir, &machines)?;
 
     ui::success(&format!(
         "Rolled back to snapshot {} ({} files restored)",
         id, file_count
     ));
+    crate::history::Event::new(
+        crate::history::Kind::Rollback,
+        format!("Rolled back to snapshot {}", id),
+    )
+    .files(file_count)
+    .reference(id)
+    .record();
+    if trashed {
+        ui::info("The replaced compiled files are in the trash ('dotdipper trash list')");
+    }
     ui::hint("Run 'dotdipper apply' to apply the restored files to your system");
 
     Ok(())
 }
 
-/// Delete a snapshot
-pub fn delete(config: &Config, id: &str, force: bool) -> Result<()> {
-    let _ = config;
-    let snapshots_dir = get_snapshots_dir()?;
-    let snapshot_dir = snapshots_dir.join(id);
+/// Restore only some files from a snapshot: those under `only` and, with
+/// `interactive`, those picked from a list. Files added since the snapshot
+/// are removed again. The rest of the compiled files are left as they are.
+pub fn rollback_files(
+    config: &Config,
+    id: &str,
+    only: &[String],
+    interactive: bool,
+    force: bool,
+) -> Result<()> {
+    use crate::diff::{DiffEntry, DiffStatus};
+
+    let (snapshot_dir, compiled_dir, diff) = compare(config, id, None)?;
+    let entry = |rel_path: &PathBuf, status| DiffEntry {
+        rel_path: rel_path.clone(),
+        source_path: snapshot_dir.join(rel_path),
+        target_path: compiled_dir.join(rel_path),
+        status,
+        renamed_from: None,
+    };
+    let mut entries: Vec<DiffEntry> = diff
+        .modified
+        .iter()
+        .map(|p| entry(p, DiffStatus::Modified))
+        .chain(diff.removed.iter().map(|p| entry(p, DiffStatus::New)))
+        .chain(diff.added.iter().map(|p| entry(p, DiffStatus::Missing)))
+        .collect();
+    entries.sort_by(|a, b| a.rel_path.cmp(&b.rel_path));
+    let mut entries = crate::diff::filter_by_paths(entries, only)?;
+
+    if interactive {
+        let selected = crate::diff::interactive_select_with(
+            &entries,
+            &format!("Select files to restore from {}", id),
+        )?;
+        entries.retain(|e| selected.contains(&e.rel_path));
+    }
+    if entries.is_empty() {
+        ui::info("Nothing to restore: the selected files match the snapshot");
+        return Ok(());
+    }
 
-    if !snapshot_dir.exists() {
-        anyhow::bail!("Snapshot not found: {}", id);
+    if !force {
+        ui::section(&format!("Files to restore from snapshot {}:", id));
+        for entry in &entries {
+            println!("  {} ~/{}", entry.status.symbol(), entry.rel_path.display());
+        }
+        if !ui::prompt_confirm(
+            &format!("Restore {} file(s) in the compiled files?", entries.len()),
+            false,
+        ) {
+            ui::info("Rollback cancelled");
+            return Ok(());
+        }
+    }
+
+    let manifest_path = crate::paths::manifest_file()?;
+    let mut manifest = if manifest_path.exists() {
+        Some(Manifest::load(&manifest_path)?)
+    } else {
+        None
+    };
+    let reason = format!("Rolled back to snapshot {}", id);
+    for entry in &entries {
+        if entry.target_path.exists() {
+            crate::trash::put(&entry.target_path, &reason)?;
+        }
+        if entry.status == DiffStatus::Missing {
+            if let Some(manifest) = manifest.as_mut() {
+                manifest.files.remove(&entry.rel_path);
+            }
+            continue;
+        }
+        if let Some(parent) = entry.target_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::copy(&entry.source_path, &entry.target_path)
+            .with_context(|| format!("Failed to restore ~/{}", entry.rel_path.display()))?;
+        if let Some(manifest) = manifest.as_mut() {
+            let mut file_hash = crate::hash::hash_file(&entry.target_path)?;
+            file_hash.path = entry.rel_path.clone();
+            manifest.add_file(file_hash);
+        }
+    }
+    if let Some(manifest) = manifest {
+        manifest.save(&manifest_path)?;
+    }
+
+    ui::success(&format!(
+        "Restored {} file(s) from snapshot {}",
+        entries.len(),
+        id
+    ));
+    crate::history::Event::new(
+        crate::history::Kind::Rollback,
+        format!("Rolled back {} file(s) to snapshot {}", entries.len(), id),
+    )
+    .files(entries.len())
+    .reference(id)
+    .record();
+    ui::hint("Run 'dotdipper apply' to apply the restored files to your system");
+
+    Ok(())
+}
+
+/// Build a manifest for the files stored in a snapshot, without touching
+/// compiled/ or the main manifest. Returns the snapshot directory as the
+/// source root.
+pub fn snapshot_manifest(config: &Config, id: &str) -> Result<(PathBuf, Manifest)> {
+    let snapshot_dir = store::find(config, id)?;
+    let manifest = dir_manifest(&snapshot_dir)?;
+    Ok((snapshot_dir, manifest))
+}
+
+/// Write a manifest listing the files in `compiled` when there is none yet,
+/// as on a new machine that cloned the dotfiles repository itself. Returns
+/// the number of files, or `None` when there was nothing to do.
+pub fn adopt_compiled(compiled: &Path, manifest_path: &Path) -> Result<Option<usize>> {
+    if manifest_path.exists() {
+        return Ok(None);
+    }
+    let manifest = dir_manifest(compiled)?;
+    if manifest.files.is_empty() {
+        return Ok(None);
     }
+    manifest.save(manifest_path)?;
+    Ok(Some(manifest.files.len()))
+}
+
+/// Manifest of the dotfiles and symlinks in a snapshot or compiled
+/// directory, leaving out the snapshot metadata, git bookkeeping and machine
+/// registry
+fn dir_manifest(dir: &Path) -> Result<Manifest> {
+    let mut manifest = Manifest::new();
+    if !dir.exists() {
+        return Ok(manifest);
+    }
+    for entry in walkdir::WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+        .filter_map(|e| e.ok())
+    {
+        if entry.file_type().is_symlink() {
+            let rel_path = entry.path().strip_prefix(dir)?.to_path_buf();
+            let target = fs::read_link(entry.path())?;
+            manifest
+                .links
+                .insert(rel_path, crate::hash::LinkEntry { target });
+            continue;
+        }
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel_path = entry.path().strip_prefix(dir)?.to_path_buf();
+        if rel_path == Path::new("snapshot.json")
+            || rel_path == Path::new(".gitignore")
+            || rel_path.starts_with(crate::machines::MACHINES_DIR)
+        {
+            continue;
+        }
+
+        let mut file_hash = crate::hash::hash_file(entry.path())?;
+        file_hash.path = rel_path;
+        manifest.add_file(file_hash);
+    }
+
+    Ok(manifest)
+}
+
+/// Files that differ between two snapshots, as home-relative paths
+#[derive(Debug, Default, PartialEq)]
+pub struct SnapshotDiff {
+    pub added: Vec<PathBuf>,
+    pub removed: Vec<PathBuf>,
+    pub modified: Vec<PathBuf>,
+}
+
+impl SnapshotDiff {
+    fn between(old: &Manifest, new: &Manifest) -> Self {
+        let mut diff = Self::default();
+        for (rel_path, file) in &new.files {
+            match old.get_file(rel_path) {
+                None => diff.added.push(rel_path.clone()),
+                Some(previous) if previous.hash != file.hash => {
+                    diff.modified.push(rel_path.clone())
+                }
+                Some(_) => {}
+            }
+        }
+        diff.removed = old
+            .files
+            .keys()
+            .filter(|rel_path| !new.has_file(rel_path))
+            .cloned()
+            .collect();
+        diff.added.sort();
+        diff.removed.sort();
+        diff.modified.sort();
+        diff
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    pub fn to_json(&self, from: &str, to: &str) -> serde_json::Value {
+        let paths = |files: &[PathBuf]| -> Vec<String> {
+            files
+                .iter()
+                .map(|f| crate::matching::display_tilde(f))
+                .collect()
+        };
+        serde_json::json!({
+            "from": from,
+            "to": to,
+            "added": paths(&self.added),
+            "removed": paths(&self.removed),
+            "modified": paths(&self.modified),
+        })
+    }
+}
+
+/// Compare snapshot `from` with snapshot `to`, or with the current compiled
+/// files when `to` is `None`. Returns the source directories with the diff.
+pub fn compare(
+    config: &Config,
+    from: &str,
+    to: Option<&str>,
+) -> Result<(PathBuf, PathBuf, SnapshotDiff)> {
+    let (old_dir, old) = snapshot_manifest(config, from)?;
+    let (new_dir, new) = match to {
+        Some(id) => snapshot_manifest(config, id)?,
+        None => {
+            let compiled_dir = crate::paths::compiled_dir()?;
+            let manifest = dir_manifest(&compiled_dir)?;
+            (compiled_dir, manifest)
+        }
+    };
+    let diff = SnapshotDiff::between(&old, &new);
+    Ok((old_dir, new_dir, diff))
+}
+
+/// `dotdipper snapshot diff`: print what changed from `from` to `to` (or the
+/// current compiled files), with content diffs of modified files if `patch`
+pub fn print_diff(
+    config: &Config,
+    from: &str,
+    to: Option<&str>,
+    patch: Option<&crate::diff::DiffView>,
+) -> Result<()> {
+    let (old_dir, new_dir, diff) = compare(config, from, to)?;
+    let to_label = to.unwrap_or("current");
+    if ui::is_json() {
+        return ui::print_json(&diff.to_json(from, to_label));
+    }
+
+    ui::section(&format!("Changes from {} to {}:", from, to_label));
+    if diff.is_empty() {
+        ui::success("No differences");
+        return Ok(());
+    }
+    use crate::diff::DiffStatus;
+    let groups = [
+        (DiffStatus::New, &diff.added),
+        (DiffStatus::Missing, &diff.removed),
+        (DiffStatus::Modified, &diff.modified),
+    ];
+    for (status, files) in groups {
+        for rel_path in files {
+            println!("  {} ~/{}", status.symbol(), rel_path.display());
+            if let (Some(view), DiffStatus::Modified) = (patch, status) {
+                crate::diff::show_file_diff(
+                    &old_dir.join(rel_path),
+                    &new_dir.join(rel_path),
+                    view,
+                )?;
+            }
+        }
+    }
+    ui::info(&format!(
+        "{} added, {} removed, {} modified",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.modified.len()
+    ));
+    Ok(())
+}
+
+/// Config used for `apply --as-of`: files are always copied so nothing ends
+/// up symlinked into the snapshot store.
+pub fn as_of_config(config: &Config) -> Config {
+    let mut config = config.clone();
+    config.general.default_mode = RestoreMode::Copy;
+    for file_override in config.files.values_mut() {
+        file_override.mode = None;
+    }
+    config
+}
+
+/// Load the record of files applied with `--as-of`, if any
+pub fn load_as_of() -> Result<Option<AsOfRecord>> {
+    let path = crate::paths::as_of_file()?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(&path)?;
+    let record = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(record))
+}
+
+/// Remember files applied from a snapshot, merging with an earlier record
+pub fn record_as_of(snapshot_id: &str, files: BTreeMap<PathBuf, String>) -> Result<()> {
+    let mut record = load_as_of()?.unwrap_or_else(|| AsOfRecord {
+        snapshot_id: snapshot_id.to_string(),
+        applied_at: Utc::now(),
+        files: BTreeMap::new(),
+    });
+    record.snapshot_id = snapshot_id.to_string();
+    record.applied_at = Utc::now();
+    record.files.extend(files);
+
+    let path = crate::paths::as_of_file()?;
+    fs::write(&path, serde_json::to_string_pretty(&record)?)?;
+    Ok(())
+}
+
+/// Put back the current compiled version of every file applied with `--as-of`.
+///
+/// Files edited since they were applied are kept (and stay in the record)
+/// unless `force` is set. Files that only exist in the snapshot are removed
+/// again if they were not modified since.
+pub fn revert_as_of(config: &Config, force: bool, allow_outside_home: bool) -> Result<()> {
+    let Some(record) = load_as_of()? else {
+        ui::info("No files were applied with --as-of; nothing to revert");
+        return Ok(());
+    };
+
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let compiled_dir = crate::paths::compiled_dir()?;
+    let manifest_path = crate::paths::manifest_file()?;
+    let current = if manifest_path.exists() {
+        Manifest::load(&manifest_path)?
+    } else {
+        Manifest::new()
+    };
+
+    let mut to_apply = Manifest::new();
+    let mut kept = BTreeMap::new();
+    for (rel_path, applied_hash) in &record.files {
+        let target = crate::system::target(&home, rel_path);
+        let unchanged = crate::hash::hash_file(&target)
+            .map(|h| &h.hash == applied_hash)
+            .unwrap_or(false);
+
+        if let Some(file_hash) = current.get_file(rel_path) {
+            if unchanged || force || !target.exists() {
+                to_apply.add_file(file_hash.clone());
+            } else {
+                ui::warn(&format!(
+                    "Kept ~/{} (modified since it was applied from {}; use --force to overwrite)",
+                    rel_path.display(),
+                    record.snapshot_id
+                ));
+                kept.insert(rel_path.clone(), applied_hash.clone());
+            }
+            continue;
+        }
+
+        if unchanged {
+            fs::remove_file(&target)?;
+            ui::info(&format!("Removed ~/{}", rel_path.display()));
+        } else if target.exists() {
+            ui::warn(&format!(
+                "Kept ~/{} (modified since it was applied from {})",
+                rel_path.display(),
+                record.snapshot_id
+            ));
+        }
+    }
+
+    if !to_apply.files.is_empty() {
+        let opts = crate::repo::apply::ApplyOpts {
+            force: true,
+            allow_outside_home,
+            yes_protected: false,
+            dry_run: false,
+        };
+        crate::repo::apply::apply(&compiled_dir, &to_apply, config, &opts)?;
+    }
+
+    let reverted = record.files.len() - kept.len();
+    let path = crate::paths::as_of_file()?;
+    if kept.is_empty() {
+        fs::remove_file(&path)?;
+    } else {
+        let record = AsOfRecord {
+            files: kept,
+            ..record.clone()
+        };
+        fs::write(&path, serde_json::to_string_pretty(&record)?)?;
+    }
+    ui::success(&format!(
+        "Reverted {} file(s) applied from snapshot {}",
+        reverted, record.snapshot_id
+    ));
+    Ok(())
+}
+
+/// Delete a snapshot
+pub fn delete(config: &Config, id: &str, force: bool) -> Result<()> {
+    let snapshot_dir = store::find(config, id)?;
 
     // Confirm with user unless force is set
     if !force {
@@ -242,8 +717,13 @@ pub fn delete(config: &Config, id: &str, force: bool) -> Result<()> {
         }
     }
 
+    let freed = space::measure(config, &load_all(config)?)?.unique(id);
     fs::remove_dir_all(&snapshot_dir)?;
-    ui::success(&format!("Deleted snapshot: {}", id));
+    ui::success(&format!(
+        "Deleted snapshot: {} (freed {})",
+        id,
+        humansize::format_size(freed, humansize::BINARY)
+    ));
 
     Ok(())
 }
@@ -257,37 +737,60 @@ pub fn prune(config: &Config, opts: &PruneOpts) -> Result<()> {
         return Ok(());
     }
 
-    let mut to_delete: Vec<&Snapshot> = Vec::new();
-    let mut to_keep: Vec<&Snapshot> = Vec::new();
-
-    // Apply keep_count filter
-    if let Some(keep_count) = opts.keep_count {
-        for (i, snap) in snapshots.iter().enumerate() {
-            if i < keep_count {
-                to_keep.push(snap);
-            } else {
-                to_delete.push(snap);
-            }
-        }
-    } else {
-        // If no specific criteria, keep all
-        to_keep.extend(snapshots.iter());
+    // If no specific criteria, keep all
+    if opts.keep_count.is_none() && opts.keep_age.is_none() && opts.keep_size.is_none() {
+        ui::info("No snapshots to prune based on criteria");
+        return Ok(());
     }
 
-    // Apply keep_age filter
-    if let Some(age_str) = &opts.keep_age {
-        if let Some(duration) = parse_duration(age_str) {
-            let cutoff = Utc::now() - duration;
-            to_delete.retain(|snap| snap.created_at < cutoff);
-            to_keep.retain(|snap| snap.created_at >= cutoff);
+    let cutoff = match &opts.keep_age {
+        Some(age_str) => Some(
+            Utc::now()
+                - parse_duration(age_str).with_context(|| {
+                    format!("Invalid age '{}' (expected e.g. 30d, 2w, 1m)", age_str)
+                })?,
+        ),
+        None => None,
+    };
+
+    let usage = space::measure(config, &snapshots)?;
+
+    // Newest snapshots whose combined on-disk size stays within keep_size.
+    // The newest snapshot always fits.
+    let fitting = match &opts.keep_size {
+        Some(size_str) => {
+            let limit = crate::usage::parse_size(size_str).with_context(|| {
+                format!("Invalid size '{}' (expected e.g. 500MB, 1GB)", size_str)
+            })?;
+            let ids: Vec<&str> = snapshots.iter().map(|s| s.id.as_str()).collect();
+            (1..ids.len())
+                .find(|&n| usage.combined(&ids[..=n]) > limit)
+                .unwrap_or(ids.len())
         }
-    }
+        None => 0,
+    };
 
-    // Apply keep_size filter (simplified - would need proper implementation)
-    if let Some(_size_str) = &opts.keep_size {
-        // TODO: Implement size-based pruning
-        ui::warn("Size-based pruning not yet implemented");
-    }
+    // A snapshot is kept if it matches any criterion
+    let mut to_delete: Vec<&Snapshot> = snapshots
+        .iter()
+        .enumerate()
+        .filter(|(i, snap)| {
+            let by_count = opts.keep_count.is_some_and(|n| *i < n);
+            let by_age = cutoff.is_some_and(|c| snap.created_at >= c);
+            !(by_count || by_age || *i < fitting)
+        })
+        .map(|(_, snap)| snap)
+        .collect();
+
+    // Snapshots pinned on this machine are still in use
+    let pins = crate::pins::load()?;
+    to_delete.retain(|snap| {
+        let pinned = pins.pins.iter().any(|p| p.snapshot_id == snap.id);
+        if pinned {
+            ui::info(&format!("Keeping pinned snapshot {}", snap.id));
+        }
+        !pinned
+    });
 
     if to_delete.is_empty() {
         ui::info("No snapshots to prune based on criteria");
@@ -300,11 +803,14 @@ pub fn prune(config: &Config, opts: &PruneOpts) -> Result<()> {
         let msg = snap.message.as_deref().unwrap_or("(no message)");
         println!("  {} - {}", snap.id, msg);
     }
+    let ids: Vec<&str> = to_delete.iter().map(|s| s.id.as_str()).collect();
+    let freed = humansize::format_size(usage.freed(&ids), humansize::BINARY);
 
     if opts.dry_run {
         ui::info(&format!(
-            "Would delete {} snapshots (dry run)",
-            to_delete.len()
+            "Would delete {} snapshots and free {} (dry run)",
+            to_delete.len(),
+            freed
         ));
         return Ok(());
     }
@@ -314,7 +820,11 @@ pub fn prune(config: &Config, opts: &PruneOpts) -> Result<()> {
         delete(config, &snap.id, true)?;
     }
 
-    ui::success(&format!("Pruned {} snapshots", to_delete.len()));
+    ui::success(&format!(
+        "Pruned {} snapshots, freed {}",
+        to_delete.len(),
+        freed
+    ));
 
     Ok(())
 }
@@ -343,7 +853,7 @@ pub fn build_prune_opts_from_config(config: &Config) -> Option<PruneOpts> {
 }
 
 /// Parse a duration string like "30d", "7d", "2w", "1m"
-fn parse_duration(s: &str) -> Option<chrono::Duration> {
+pub(crate) fn parse_duration(s: &str) -> Option<chrono::Duration> {
     let s = s.trim();
     if s.is_empty() {
         return None;
@@ -373,4 +883,37 @@ mod tests {
         assert_eq!(parse_duration("1m"), Some(chrono::Duration::days(30)));
         assert_eq!(parse_duration("invalid"), None);
     }
+
+    #[test]
+    fn test_snapshot_diff_between_dirs() {
+        let temp = tempfile::tempdir().unwrap();
+        let (old, new) = (temp.path().join("old"), temp.path().join("new"));
+        for (dir, files) in [
+            (
+                &old,
+                [(".zshrc", "a"), (".vimrc", "set nu"), (".bashrc", "x")],
+            ),
+            (
+                &new,
+                [(".zshrc", "b"), (".vimrc", "set nu"), (".tmux.conf", "y")],
+            ),
+        ] {
+            fs::create_dir_all(dir.join(".git")).unwrap();
+            fs::write(dir.join(".git/HEAD"), dir.to_string_lossy().as_bytes()).unwrap();
+            fs::write(dir.join("snapshot.json"), "{}").unwrap();
+            for (name, contents) in files {
+                fs::write(dir.join(name), contents).unwrap();
+            }
+        }
+
+        let diff =
+            SnapshotDiff::between(&dir_manifest(&old).unwrap(), &dir_manifest(&new).unwrap());
+        assert_eq!(diff.added, vec![PathBuf::from(".tmux.conf")]);
+        assert_eq!(diff.removed, vec![PathBuf::from(".bashrc")]);
+        assert_eq!(diff.modified, vec![PathBuf::from(".zshrc")]);
+        assert!(
+            SnapshotDiff::between(&dir_manifest(&old).unwrap(), &dir_manifest(&old).unwrap())
+                .is_empty()
+        );
+    }
 }
@@ -0,0 +1,143 @@
+//! On-disk size accounting for the snapshot store.
+//!
+//! Sizes are allocated blocks rather than file lengths, and every inode is
+//! counted once: files hard-linked between snapshots count once towards the
+//! store total, and belong to a snapshot's unique size only if no other
+//! snapshot (or anything outside the store) links to them. The unique size is
+//! therefore what deleting that snapshot actually frees.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+
+use super::Snapshot;
+use crate::cfg::Config;
+
+struct Inode {
+    bytes: u64,
+    nlink: u64,
+    /// Links seen inside the store
+    seen: u64,
+    /// Indexes of the snapshots linking to this inode
+    owners: Vec<usize>,
+}
+
+/// Allocated space of a set of snapshots
+pub struct Usage {
+    ids: Vec<String>,
+    inodes: Vec<Inode>,
+}
+
+impl Usage {
+    /// Space taken by all measured snapshots together
+    pub fn total(&self) -> u64 {
+        self.inodes.iter().map(|i| i.bytes).sum()
+    }
+
+    fn indexes(&self, ids: &[&str]) -> Vec<usize> {
+        ids.iter()
+            .filter_map(|id| self.ids.iter().position(|s| s == id))
+            .collect()
+    }
+
+    /// Space taken by the given snapshots together
+    pub fn combined(&self, ids: &[&str]) -> u64 {
+        let set = self.indexes(ids);
+        self.inodes
+            .iter()
+            .filter(|i| i.owners.iter().any(|o| set.contains(o)))
+            .map(|i| i.bytes)
+            .sum()
+    }
+
+    /// Space freed by deleting the given snapshots
+    pub fn freed(&self, ids: &[&str]) -> u64 {
+        let set = self.indexes(ids);
+        self.inodes
+            .iter()
+            .filter(|i| i.seen >= i.nlink && i.owners.iter().all(|o| set.contains(o)))
+            .map(|i| i.bytes)
+            .sum()
+    }
+
+    /// Space only this snapshot takes
+    pub fn unique(&self, id: &str) -> u64 {
+        self.freed(&[id])
+    }
+}
+
+/// Measure the local snapshots among `snapshots`
+pub fn measure(config: &Config, snapshots: &[Snapshot]) -> Result<Usage> {
+    let mut dirs = Vec::new();
+    for snapshot in snapshots {
+        if let Ok(dir) = super::store::find(config, &snapshot.id) {
+            dirs.push((snapshot.id.clone(), dir));
+        }
+    }
+    Ok(measure_dirs(dirs))
+}
+
+fn measure_dirs(dirs: Vec<(String, PathBuf)>) -> Usage {
+    let mut by_inode: HashMap<(u64, u64), usize> = HashMap::new();
+    let mut inodes: Vec<Inode> = Vec::new();
+    let mut ids = Vec::new();
+
+    for (index, (id, dir)) in dirs.into_iter().enumerate() {
+        ids.push(id);
+        for entry in walkdir::WalkDir::new(&dir)
+            .follow_links(false)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let Ok(meta) = entry.metadata() else {
+                continue;
+            };
+            let slot = *by_inode.entry((meta.dev(), meta.ino())).or_insert_with(|| {
+                inodes.push(Inode {
+                    bytes: meta.blocks() * 512,
+                    // A directory's link count includes its subdirectories
+                    nlink: if meta.is_dir() { 1 } else { meta.nlink() },
+                    seen: 0,
+                    owners: Vec::new(),
+                });
+                inodes.len() - 1
+            });
+            let inode = &mut inodes[slot];
+            inode.seen += 1;
+            if !inode.owners.contains(&index) {
+                inode.owners.push(index);
+            }
+        }
+    }
+
+    Usage { ids, inodes }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_hard_linked_files_are_counted_once() {
+        let temp = tempfile::tempdir().unwrap();
+        let (a, b) = (temp.path().join("a"), temp.path().join("b"));
+        fs::create_dir_all(&a).unwrap();
+        fs::create_dir_all(&b).unwrap();
+        fs::write(a.join("shared"), vec![b'x'; 64 * 1024]).unwrap();
+        fs::hard_link(a.join("shared"), b.join("shared")).unwrap();
+        fs::write(b.join("own"), vec![b'y'; 64 * 1024]).unwrap();
+
+        let usage = measure_dirs(vec![("a".to_string(), a), ("b".to_string(), b.clone())]);
+        let shared = fs::metadata(b.join("shared")).unwrap().blocks() * 512;
+        let own = fs::metadata(b.join("own")).unwrap().blocks() * 512;
+        let dir_a = usage.combined(&["a"]) - shared;
+        let dir_b = usage.combined(&["b"]) - shared - own;
+
+        assert_eq!(usage.total(), dir_a + dir_b + shared + own);
+        assert_eq!(usage.unique("a"), dir_a);
+        assert_eq!(usage.unique("b"), dir_b + own);
+        assert_eq!(usage.freed(&["a", "b"]), usage.total());
+    }
+}
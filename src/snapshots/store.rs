@@ -0,0 +1,254 @@
+//! Where snapshots live.
+//!
+//! By default snapshots are kept in `snapshots/` under the data directory.
+//! `[snapshots] path` moves them to another existing directory, such as a
+//! NAS mount. While that directory is unreachable, new snapshots go to the
+//! local store and snapshots from both places stay visible.
+//!
+//! `[snapshots.remote]` additionally keeps every snapshot in an object store
+//! through the [`Remote`](crate::remote::Remote) trait, as
+//! `snapshot_<id>.tar.zst`. Uploads that fail are retried by the next
+//! `snapshot create`, and snapshots that only exist remotely are downloaded
+//! when they are rolled back to or applied from.
+
+use anyhow::{bail, Context, Result};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::cfg::Config;
+use crate::offline::RetryPolicy;
+use crate::ui;
+
+const ARCHIVE_PREFIX: &str = "snapshot_";
+const ARCHIVE_SUFFIX: &str = ".tar.zst";
+
+static WARNED_UNREACHABLE: AtomicBool = AtomicBool::new(false);
+
+fn configured_path(config: &Config) -> Option<PathBuf> {
+    let path = config.snapshots.as_ref()?.path.as_ref()?;
+    Some(PathBuf::from(shellexpand::tilde(path).to_string()))
+}
+
+/// Directory new snapshots are written to
+pub fn dir(config: &Config) -> Result<PathBuf> {
+    let local = crate::paths::snapshots_dir()?;
+    let Some(path) = configured_path(config) else {
+        return Ok(local);
+    };
+    if path.is_dir() {
+        return Ok(path);
+    }
+    if !WARNED_UNREACHABLE.swap(true, Ordering::Relaxed) {
+        ui::warn(&format!(
+            "Snapshot store {} is unreachable; using {}",
+            path.display(),
+            local.display()
+        ));
+    }
+    Ok(local)
+}
+
+/// Every directory snapshots can be read from, preferred one first
+pub fn dirs(config: &Config) -> Result<Vec<PathBuf>> {
+    let mut dirs = vec![dir(config)?];
+    let local = crate::paths::snapshots_dir()?;
+    if !dirs.contains(&local) {
+        dirs.push(local);
+    }
+    Ok(dirs)
+}
+
+/// Directory of an existing snapshot
+pub fn find(config: &Config, id: &str) -> Result<PathBuf> {
+    if id.is_empty() || id.contains('/') || id.contains("..") {
+        bail!("Invalid snapshot id: {}", id);
+    }
+    dirs(config)?
+        .into_iter()
+        .map(|d| d.join(id))
+        .find(|d| d.is_dir())
+        .with_context(|| format!("Snapshot not found: {}", id))
+}
+
+fn remote_config(config: &Config) -> Option<&crate::cfg::RemoteConfig> {
+    config.snapshots.as_ref().and_then(|s| s.remote.as_ref())
+}
+
+fn remote(config: &Config) -> Result<Option<Box<dyn crate::remote::Remote>>> {
+    let Some(remote_cfg) = remote_config(config) else {
+        return Ok(None);
+    };
+    if config.remote.as_ref() == Some(remote_cfg) {
+        bail!("[snapshots.remote] must point somewhere other than [remote]");
+    }
+    crate::remote::create_remote(remote_cfg).map(Some)
+}
+
+fn archive_name(id: &str) -> String {
+    format!("{}{}{}", ARCHIVE_PREFIX, id, ARCHIVE_SUFFIX)
+}
+
+/// Snapshot ids stored in the object store, newest first
+async fn remote_ids(config: &Config, remote: &dyn crate::remote::Remote) -> Result<Vec<String>> {
+    let bundles = RetryPolicy::from_config(config)
+        .run_async("List", || remote.list_bundles())
+        .await?;
+    let mut ids: Vec<String> = bundles
+        .iter()
+        .filter_map(|b| {
+            b.name
+                .strip_prefix(ARCHIVE_PREFIX)?
+                .strip_suffix(ARCHIVE_SUFFIX)
+                .map(str::to_string)
+        })
+        .collect();
+    ids.sort_by(|a, b| b.cmp(a));
+    Ok(ids)
+}
+
+/// Upload local snapshots the object store doesn't have yet. An unreachable
+/// store only produces a warning; the snapshots are uploaded next time.
+pub async fn upload_pending(config: &Config) -> Result<()> {
+    let (Some(remote), Some(remote_cfg)) = (remote(config)?, remote_config(config)) else {
+        return Ok(());
+    };
+    let result = async {
+        let stored = remote_ids(config, remote.as_ref()).await?;
+        let mut uploaded = 0;
+        for snapshot in super::load_all(config)? {
+            if stored.contains(&snapshot.id) {
+                continue;
+            }
+            let staging = tempfile::tempdir()?;
+            let archive = staging.path().join(archive_name(&snapshot.id));
+            pack(&find(config, &snapshot.id)?, &archive)?;
+            crate::remote::seal(config, remote_cfg, &archive)?;
+            RetryPolicy::from_config(config)
+                .run_async("Upload", || remote.push_bundle(&archive))
+                .await?;
+            uploaded += 1;
+        }
+        Ok::<_, anyhow::Error>(uploaded)
+    }
+    .await;
+
+    match result {
+        Ok(0) => {}
+        Ok(n) => ui::success(&format!("Uploaded {} snapshot(s) to {}", n, remote.name())),
+        Err(e) => {
+            ui::warn(&format!(
+                "Snapshot store {} is unreachable: {:#}",
+                remote.name(),
+                e
+            ));
+            ui::hint(
+                "Snapshots stay local and are uploaded by the next 'dotdipper snapshot create'",
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Snapshot ids only found in the object store (empty when it is unreachable)
+pub async fn remote_only(config: &Config) -> Result<Vec<String>> {
+    let Some(remote) = remote(config)? else {
+        return Ok(Vec::new());
+    };
+    let local: Vec<String> = super::load_all(config)?.into_iter().map(|s| s.id).collect();
+    match remote_ids(config, remote.as_ref()).await {
+        Ok(ids) => Ok(ids.into_iter().filter(|id| !local.contains(id)).collect()),
+        Err(e) => {
+            ui::warn(&format!(
+                "Snapshot store {} is unreachable: {:#}",
+                remote.name(),
+                e
+            ));
+            Ok(Vec::new())
+        }
+    }
+}
+
+/// Download the snapshot `input` refers to (an id or a prefix of one) if
+/// it is only in the object store, so it can be resolved like a local one.
+pub async fn ensure_local(config: &Config, input: &str) -> Result<()> {
+    let remote_ids = remote_only(config).await?;
+    let matches: Vec<&String> = remote_ids
+        .iter()
+        .filter(|id| id.starts_with(input))
+        .collect();
+    let [id] = matches.as_slice() else {
+        return Ok(());
+    };
+    let local_match = super::load_all(config)?
+        .iter()
+        .any(|s| s.id.starts_with(input));
+    if local_match {
+        return Ok(());
+    }
+
+    let remote_cfg = remote_config(config).context("No snapshot store configured")?;
+    let remote = remote(config)?.context("No snapshot store configured")?;
+    ui::info(&format!(
+        "Downloading snapshot {} from {}...",
+        id,
+        remote.name()
+    ));
+    let name = archive_name(id);
+    let download = tempfile::tempdir()?;
+    let archive = download.path().join(&name);
+    RetryPolicy::from_config(config)
+        .run_async("Download", || remote.pull_bundle(&name, &archive))
+        .await?;
+    crate::remote::unseal(config, remote_cfg, &archive)?;
+    unpack(&archive, &dir(config)?.join(id))
+}
+
+/// Archive a snapshot directory as `.tar.zst`
+fn pack(snapshot_dir: &Path, archive: &Path) -> Result<()> {
+    let file =
+        File::create(archive).with_context(|| format!("Failed to create {}", archive.display()))?;
+    let encoder = zstd::Encoder::new(file, 3)?;
+    let mut builder = tar::Builder::new(encoder);
+    builder.append_dir_all(".", snapshot_dir)?;
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+fn unpack(archive: &Path, snapshot_dir: &Path) -> Result<()> {
+    let file =
+        File::open(archive).with_context(|| format!("Failed to open {}", archive.display()))?;
+    fs::create_dir_all(snapshot_dir)?;
+    tar::Archive::new(zstd::Decoder::new(file)?)
+        .unpack(snapshot_dir)
+        .with_context(|| format!("Failed to extract {}", archive.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pack_round_trips_a_snapshot() {
+        let temp = tempfile::tempdir().unwrap();
+        let snapshot = temp.path().join("20240101_120000");
+        fs::create_dir_all(snapshot.join(".config/nvim")).unwrap();
+        fs::write(snapshot.join("snapshot.json"), "{}").unwrap();
+        fs::write(
+            snapshot.join(".config/nvim/init.lua"),
+            "vim.o.number = true",
+        )
+        .unwrap();
+
+        let archive = temp.path().join(archive_name("20240101_120000"));
+        pack(&snapshot, &archive).unwrap();
+        let restored = temp.path().join("restored");
+        unpack(&archive, &restored).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(restored.join(".config/nvim/init.lua")).unwrap(),
+            "vim.o.number = true"
+        );
+        assert!(restored.join("snapshot.json").exists());
+    }
+}
@@ -0,0 +1,511 @@
+//! Two-way sync with the GitHub repository.
+//!
+//! `dotdipper sync` fetches the repository and compares every file three
+//! ways: as of the last commit this machine shares with the remote (the
+//! base), as the remote has it now, and as it is in `$HOME`. Each file that
+//! is not already in sync is one of:
+//!
+//! - fast-forward: `$HOME` already has the remote version
+//! - locally changed: only edited here, so it is kept and pushed
+//! - remotely changed: only changed on the remote, so it is applied
+//! - conflicting: changed on both sides; the user keeps one side or merges
+//!
+//! The compiled repository then moves to the remote commit, the local
+//! changes are snapshotted on top of it and pushed. Unlike `pull --apply`,
+//! no local edit is overwritten without asking.
+//!
+//! Templates are compared as the stored template, since `$HOME` only has
+//! the rendered output, and tracked secrets are compared decrypted.
+
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+use crate::matching::display_tilde;
+use crate::ui;
+
+/// How a file differs between the base, `$HOME` and the remote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    /// `$HOME` already has the remote version
+    FastForward,
+    /// Changed here only
+    LocallyChanged,
+    /// Changed on the remote only
+    RemotelyChanged,
+    /// Changed on both sides, differently
+    Conflict,
+}
+
+impl Change {
+    fn title(&self) -> &'static str {
+        match self {
+            Change::FastForward => "Already up to date here (fast-forward):",
+            Change::LocallyChanged => "Changed locally (kept and pushed):",
+            Change::RemotelyChanged => "Changed on the remote (applied):",
+            Change::Conflict => "Changed on both sides (conflicts):",
+        }
+    }
+}
+
+/// Classify a file from its contents in the base, `$HOME` and the remote
+/// (`None` where it doesn't exist). Files in sync give `None`.
+pub fn classify(
+    base: Option<&[u8]>,
+    local: Option<&[u8]>,
+    remote: Option<&[u8]>,
+) -> Option<Change> {
+    if local == remote {
+        (local != base).then_some(Change::FastForward)
+    } else if local == base {
+        Some(Change::RemotelyChanged)
+    } else if remote == base {
+        Some(Change::LocallyChanged)
+    } else {
+        Some(Change::Conflict)
+    }
+}
+
+/// How to settle a conflicting file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    Local,
+    Remote,
+    /// Three-way merge, leaving conflict markers where both sides changed
+    /// the same lines
+    Merge,
+}
+
+impl Resolution {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "local" => Some(Resolution::Local),
+            "remote" => Some(Resolution::Remote),
+            "merge" => Some(Resolution::Merge),
+            _ => None,
+        }
+    }
+}
+
+/// A file that is not in sync
+#[derive(Debug)]
+pub struct Entry {
+    pub rel_path: PathBuf,
+    pub change: Change,
+    base: Option<Vec<u8>>,
+    local: Option<Vec<u8>>,
+    remote: Option<Vec<u8>>,
+    /// Compared as the stored template rather than the file in `$HOME`
+    template: bool,
+}
+
+impl Entry {
+    /// Whether the file can be merged line by line
+    fn is_mergeable(&self) -> bool {
+        [&self.base, &self.local, &self.remote]
+            .iter()
+            .all(|side| side.as_ref().is_some_and(|c| !c.contains(&0)))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SyncOpts {
+    /// Settle every conflict this way instead of asking
+    pub resolve: Option<Resolution>,
+    pub dry_run: bool,
+    pub push: bool,
+    pub repo: Option<String>,
+}
+
+/// Files compared: everything committed on either side plus the files this
+/// machine tracks, minus dotdipper's own bookkeeping in the repository
+fn candidate_paths(
+    config: &Config,
+    home: &Path,
+    trees: &[&BTreeSet<PathBuf>],
+) -> Result<BTreeSet<PathBuf>> {
+    let mut paths: BTreeSet<PathBuf> = trees.iter().flat_map(|t| t.iter().cloned()).collect();
+    for file in crate::repo::planned_files(config)? {
+        paths.insert(crate::system::manifest_path(&file, home));
+    }
+    let bookkeeping = Path::new(crate::macos::LOGIN_ITEMS_FILE);
+    paths.retain(|rel| {
+        rel != Path::new(".gitignore")
+            && rel != bookkeeping
+            && !rel.starts_with(crate::machines::MACHINES_DIR)
+            && !rel.starts_with(crate::archive::ARCHIVE_DIR)
+            && crate::confine::is_plain(rel)
+    });
+    Ok(paths)
+}
+
+/// Committed contents of `rel_path` at `rev`, decrypted if it is a secret
+fn committed(
+    config: &Config,
+    repo_path: &Path,
+    rev: &str,
+    tree: &BTreeSet<PathBuf>,
+    rel_path: &Path,
+) -> Result<Option<Vec<u8>>> {
+    if !tree.contains(rel_path) {
+        return Ok(None);
+    }
+    let contents = crate::vcs::file_at(repo_path, rev, rel_path)?;
+    if !crate::secrets::is_tracked(config, rel_path) {
+        return Ok(Some(contents));
+    }
+    let encrypted = tempfile::NamedTempFile::new()?;
+    fs::write(encrypted.path(), &contents)?;
+    if !crate::secrets::is_encrypted(encrypted.path()) {
+        return Ok(Some(contents));
+    }
+    crate::secrets::decrypt_to_memory(config, encrypted.path())
+        .with_context(|| format!("Failed to decrypt {} at {}", display_tilde(rel_path), rev))
+        .map(Some)
+}
+
+/// Fetch the remote and compare every file three ways
+pub fn plan(config: &Config, repo: Option<&str>) -> Result<Vec<Entry>> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let repo_path = crate::paths::compiled_dir()?;
+    crate::vcs::fetch(config, repo)?;
+    let remote_ref = crate::vcs::remote_ref(config);
+
+    let base_rev = crate::vcs::merge_base(&repo_path, "HEAD", &remote_ref);
+    if base_rev.is_none() {
+        ui::warn("This machine shares no history with the remote; every difference is a conflict");
+    }
+    let base_tree: BTreeSet<PathBuf> = match &base_rev {
+        Some(rev) => crate::vcs::tree_files(&repo_path, rev)?
+            .into_iter()
+            .collect(),
+        None => BTreeSet::new(),
+    };
+    let remote_tree: BTreeSet<PathBuf> = crate::vcs::tree_files(&repo_path, &remote_ref)?
+        .into_iter()
+        .collect();
+
+    let mut entries = Vec::new();
+    for rel_path in candidate_paths(config, &home, &[&base_tree, &remote_tree])? {
+        let template = crate::template::is_template(config, &rel_path);
+        let target = crate::system::target(&home, &rel_path);
+        let local = if template {
+            fs::read(repo_path.join(&rel_path)).ok()
+        } else if crate::system::check_target(config, &rel_path, &target, &home).is_some() {
+            // Not a file this machine may write, e.g. an untracked `@root/` one
+            None
+        } else {
+            fs::read(&target).ok()
+        };
+        let base = match &base_rev {
+            Some(rev) => committed(config, &repo_path, rev, &base_tree, &rel_path)?,
+            None => None,
+        };
+        let remote = committed(config, &repo_path, &remote_ref, &remote_tree, &rel_path)?;
+
+        if let Some(change) = classify(base.as_deref(), local.as_deref(), remote.as_deref()) {
+            entries.push(Entry {
+                rel_path,
+                change,
+                base,
+                local,
+                remote,
+                template,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+fn print_plan(entries: &[Entry]) {
+    if entries.is_empty() {
+        ui::success("Everything is in sync with the remote");
+        return;
+    }
+    for change in [
+        Change::FastForward,
+        Change::LocallyChanged,
+        Change::RemotelyChanged,
+        Change::Conflict,
+    ] {
+        let files: Vec<&Entry> = entries.iter().filter(|e| e.change == change).collect();
+        if files.is_empty() {
+            continue;
+        }
+        ui::section(change.title());
+        for entry in files {
+            let note = match (&entry.local, &entry.remote) {
+                (None, _) => " (deleted here)",
+                (_, None) => " (deleted on the remote)",
+                _ => "",
+            };
+            println!("  {}{}", display_tilde(&entry.rel_path), note);
+        }
+    }
+}
+
+/// Ask how to settle `entry`; `None` if the prompt was cancelled
+fn ask(entry: &Entry) -> Option<Resolution> {
+    let mut choices = vec![
+        (Resolution::Local, "Keep local"),
+        (Resolution::Remote, "Keep remote"),
+    ];
+    if entry.is_mergeable() {
+        choices.push((
+            Resolution::Merge,
+            "Merge (conflict markers where both changed the same lines)",
+        ));
+    }
+    let labels: Vec<String> = choices.iter().map(|(_, l)| l.to_string()).collect();
+    let picked = ui::prompt_select(
+        &format!(
+            "{} changed here and on the remote",
+            display_tilde(&entry.rel_path)
+        ),
+        &labels,
+    )?;
+    Some(choices[picked].0)
+}
+
+/// Settle every conflict, from `resolve` or by asking
+fn resolve_conflicts(
+    entries: &[Entry],
+    resolve: Option<Resolution>,
+) -> Result<Vec<(usize, Resolution)>> {
+    let conflicts: Vec<usize> = entries
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.change == Change::Conflict)
+        .map(|(i, _)| i)
+        .collect();
+    if conflicts.is_empty() {
+        return Ok(Vec::new());
+    }
+    if resolve.is_none() && !std::io::stdin().is_terminal() {
+        bail!(
+            "{} file(s) changed on both sides: {}. Run 'dotdipper sync' in a terminal, \
+             or pass --resolve local|remote|merge",
+            conflicts.len(),
+            conflicts
+                .iter()
+                .map(|&i| display_tilde(&entries[i].rel_path))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    let mut resolutions = Vec::new();
+    for i in conflicts {
+        let entry = &entries[i];
+        let resolution = match resolve {
+            Some(Resolution::Merge) if !entry.is_mergeable() => bail!(
+                "{} cannot be merged (binary, or deleted on one side); use --resolve local or remote",
+                display_tilde(&entry.rel_path)
+            ),
+            Some(resolution) => resolution,
+            None => ask(entry).context("Sync cancelled")?,
+        };
+        resolutions.push((i, resolution));
+    }
+    Ok(resolutions)
+}
+
+/// Write `contents` to `path` unless it already holds them
+fn write_if_changed(path: &Path, contents: &[u8]) -> Result<()> {
+    if fs::read(path).is_ok_and(|current| current == contents) {
+        return Ok(());
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, contents).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// `dotdipper sync`
+pub fn run(config: &Config, opts: &SyncOpts) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let repo_path = crate::paths::compiled_dir()?;
+
+    ui::info("Fetching from the remote...");
+    let entries = plan(config, opts.repo.as_deref())?;
+    print_plan(&entries);
+    if opts.dry_run {
+        ui::hint("Dry run: nothing was changed");
+        return Ok(());
+    }
+
+    let resolutions = resolve_conflicts(&entries, opts.resolve)?;
+    let resolution_of = |i: usize| resolutions.iter().find(|(j, _)| *j == i).map(|(_, r)| *r);
+
+    // What stays local (written back after the repository moves to the
+    // remote commit, which can replace symlinked files and templates) and
+    // what is taken from the remote
+    let mut keep: Vec<(&Entry, Vec<u8>)> = Vec::new();
+    let mut take_remote: Vec<&Entry> = Vec::new();
+    let mut with_markers = Vec::new();
+    for (i, entry) in entries.iter().enumerate() {
+        let resolution = match entry.change {
+            Change::FastForward => continue,
+            Change::LocallyChanged => Resolution::Local,
+            Change::RemotelyChanged => Resolution::Remote,
+            Change::Conflict => resolution_of(i).unwrap_or(Resolution::Local),
+        };
+        match resolution {
+            Resolution::Local => {
+                if let Some(local) = &entry.local {
+                    keep.push((entry, local.clone()));
+                }
+            }
+            Resolution::Remote => take_remote.push(entry),
+            Resolution::Merge => {
+                let side = |c: &Option<Vec<u8>>| c.clone().unwrap_or_default();
+                let (merged, conflicted) = crate::vcs::merge_contents(
+                    &side(&entry.local),
+                    &side(&entry.base),
+                    &side(&entry.remote),
+                )?;
+                if conflicted {
+                    with_markers.push(display_tilde(&entry.rel_path));
+                }
+                keep.push((entry, merged));
+            }
+        }
+    }
+
+    crate::vcs::reset_hard(&repo_path, &crate::vcs::remote_ref(config))?;
+    crate::hooks::mark_pulled()?;
+
+    for (entry, contents) in &keep {
+        let target = if entry.template {
+            repo_path.join(&entry.rel_path)
+        } else {
+            let target = crate::system::target(&home, &entry.rel_path);
+            if let Some(reason) =
+                crate::system::check_target(config, &entry.rel_path, &target, &home)
+            {
+                ui::warn(&format!("Skipping {}: {}", target.display(), reason));
+                continue;
+            }
+            target
+        };
+        write_if_changed(&target, contents)?;
+    }
+
+    let mut remote_files = crate::hash::Manifest::new();
+    for entry in &take_remote {
+        if entry.remote.is_none() {
+            ui::warn(&format!(
+                "{} was removed on the remote; left in place here",
+                display_tilde(&entry.rel_path)
+            ));
+            continue;
+        }
+        let mut file_hash = crate::hash::hash_file(&repo_path.join(&entry.rel_path))?;
+        file_hash.path = entry.rel_path.clone();
+        remote_files.add_file(file_hash);
+    }
+    if !remote_files.files.is_empty() {
+        // Only files unchanged here since the base, or chosen to be replaced
+        let opts = crate::repo::apply::ApplyOpts {
+            force: true,
+            allow_outside_home: false,
+            yes_protected: false,
+            dry_run: false,
+        };
+        crate::repo::apply::apply(&repo_path, &remote_files, config, &opts)?;
+        crate::repo::apply::record_applied(&remote_files, &[])?;
+    }
+
+    // Put the local changes on top of the remote commit
+    crate::repo::snapshot(config, true)?;
+
+    let changed = entries
+        .iter()
+        .filter(|e| e.change != Change::FastForward)
+        .count();
+    crate::history::Event::new(
+        crate::history::Kind::Sync,
+        format!(
+            "Synced: {} kept, {} from the remote",
+            keep.len(),
+            take_remote.len()
+        ),
+    )
+    .files(changed)
+    .record();
+
+    if !with_markers.is_empty() {
+        ui::warn(&format!(
+            "Conflict markers were left in: {}",
+            with_markers.join(", ")
+        ));
+        ui::hint("Edit them, then run 'dotdipper sync' again to push the result");
+        return Ok(());
+    }
+
+    if opts.push {
+        let host = crate::hosts::HostIdentity::local(config)?;
+        crate::vcs::push(
+            config,
+            Some(format!("Sync from {}", host.name)),
+            false,
+            opts.repo.as_deref(),
+        )?;
+    } else {
+        ui::hint("Not pushed; run 'dotdipper push' to publish the local changes");
+    }
+    ui::success("Sync complete");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_three_ways() {
+        let (a, b, c): (&[u8], &[u8], &[u8]) = (b"a", b"b", b"c");
+        assert_eq!(classify(Some(a), Some(a), Some(a)), None);
+        assert_eq!(
+            classify(Some(a), Some(b), Some(b)),
+            Some(Change::FastForward)
+        );
+        assert_eq!(
+            classify(Some(a), Some(b), Some(a)),
+            Some(Change::LocallyChanged)
+        );
+        assert_eq!(
+            classify(Some(a), Some(a), Some(b)),
+            Some(Change::RemotelyChanged)
+        );
+        assert_eq!(classify(Some(a), Some(b), Some(c)), Some(Change::Conflict));
+
+        // New on the remote, deleted on the remote, and added on both sides
+        assert_eq!(classify(None, None, Some(a)), Some(Change::RemotelyChanged));
+        assert_eq!(
+            classify(Some(a), Some(a), None),
+            Some(Change::RemotelyChanged)
+        );
+        assert_eq!(classify(None, Some(a), Some(b)), Some(Change::Conflict));
+        assert_eq!(classify(Some(a), None, Some(b)), Some(Change::Conflict));
+    }
+
+    #[test]
+    fn test_merge_contents() {
+        let base = b"one\ntwo\nthree\n";
+        let (merged, conflicted) =
+            crate::vcs::merge_contents(b"ONE\ntwo\nthree\n", base, b"one\ntwo\nTHREE\n").unwrap();
+        assert!(!conflicted);
+        assert_eq!(merged, b"ONE\ntwo\nTHREE\n");
+
+        let (merged, conflicted) =
+            crate::vcs::merge_contents(b"uno\ntwo\nthree\n", base, b"eins\ntwo\nthree\n").unwrap();
+        assert!(conflicted);
+        let merged = String::from_utf8(merged).unwrap();
+        assert!(merged.contains("<<<<<<< local\nuno\n"));
+        assert!(merged.contains(">>>>>>> remote\n"));
+    }
+}
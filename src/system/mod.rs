@@ -0,0 +1,318 @@
+//! Tracked files outside `$HOME`, such as `/etc/hosts`.
+//!
+//! The manifest and the compiled directory keep them under `@root/`
+//! followed by their absolute path, so `/etc/nixos/configuration.nix` is
+//! stored as `@root/etc/nixos/configuration.nix` and never mistaken for a
+//! file in `$HOME`. They are always applied as copies.
+//!
+//! Apply writes the ones this user can write like any other file. The rest,
+//! usually owned by root, go into a shell script instead: the compiled
+//! versions are staged beside it, the script copies each file it replaces
+//! aside before writing it, and apply offers to run it with `sudo` once it
+//! has been shown. Without a terminal the script is only written, to be
+//! reviewed and run by hand.
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::cfg::Config;
+use crate::ui;
+
+/// First component of the manifest path of a file outside `$HOME`
+pub const ROOT_DIR: &str = "@root";
+
+/// Name of the script in a plan's directory
+const SCRIPT_FILE: &str = "apply.sh";
+
+/// True if `rel_path` is the manifest path of a file outside `$HOME`
+pub fn is_system(rel_path: &Path) -> bool {
+    rel_path.starts_with(ROOT_DIR)
+}
+
+/// The manifest path of `path`: relative to `home` under it, and under
+/// `@root/` if it is an absolute path anywhere else
+pub fn manifest_path(path: &Path, home: &Path) -> PathBuf {
+    match path.strip_prefix(home) {
+        Ok(rel_path) => rel_path.to_path_buf(),
+        Err(_) => match path.strip_prefix("/") {
+            Ok(abs) => Path::new(ROOT_DIR).join(abs),
+            Err(_) => path.to_path_buf(),
+        },
+    }
+}
+
+/// Where the file with manifest path `rel_path` lives on this machine
+pub fn target(home: &Path, rel_path: &Path) -> PathBuf {
+    match rel_path.strip_prefix(ROOT_DIR) {
+        Ok(abs) => Path::new("/").join(abs),
+        Err(_) => home.join(rel_path),
+    }
+}
+
+/// Why `target`, the file at `rel_path`, must not be written, or `None` if
+/// it may be. A file outside `$HOME` may only be written when this
+/// machine's config tracks it, so a pulled manifest or a collection can't
+/// place files anywhere; any other file has to stay inside `home`.
+pub fn check_target(
+    config: &Config,
+    rel_path: &Path,
+    target: &Path,
+    home: &Path,
+) -> Option<&'static str> {
+    if !is_system(rel_path) {
+        crate::confine::check_target(target, home)
+    } else if !crate::confine::is_plain(rel_path) {
+        Some("Not a plain path")
+    } else if !tracked_here(config, target, home) {
+        Some("Outside $HOME and not tracked in this config")
+    } else {
+        None
+    }
+}
+
+/// True if `config` tracks `target`, a file outside `home`, itself or
+/// through a tracked directory
+fn tracked_here(config: &Config, target: &Path, home: &Path) -> bool {
+    let expand = |path: &str| crate::matching::expand_tilde(path, home);
+    config
+        .general
+        .tracked_files
+        .iter()
+        .any(|file| expand(&file.to_string_lossy()) == target)
+        || config
+            .tracked_dirs
+            .keys()
+            .map(|dir| expand(dir))
+            .any(|dir| dir.is_absolute() && !dir.starts_with(home) && target.starts_with(&dir))
+}
+
+/// True if this user can replace `target`, creating its directory if
+/// needed
+pub fn writable(target: &Path) -> bool {
+    if target.is_file() && fs::OpenOptions::new().append(true).open(target).is_err() {
+        return false;
+    }
+    // Replacing a file moves the old one away, so its directory is written too
+    target
+        .ancestors()
+        .skip(1)
+        .find(|dir| dir.is_dir())
+        .is_some_and(|dir| tempfile::tempfile_in(dir).is_ok())
+}
+
+/// A file apply couldn't write itself
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Step {
+    /// The contents to put in place
+    pub source: PathBuf,
+    pub target: PathBuf,
+}
+
+/// A script that puts files in place as root
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub dir: PathBuf,
+    pub script: PathBuf,
+    pub steps: Vec<Step>,
+}
+
+impl Plan {
+    /// Stage the sources of `steps` and write the script that installs them
+    pub fn write(steps: Vec<Step>) -> Result<Self> {
+        let stamp = Utc::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut dir = crate::paths::system_dir()?.join(&stamp);
+        let mut n = 0;
+        while dir.exists() {
+            n += 1;
+            dir = crate::paths::system_dir()?.join(format!("{}-{}", stamp, n));
+        }
+        Self::write_in(dir, steps)
+    }
+
+    fn write_in(dir: PathBuf, steps: Vec<Step>) -> Result<Self> {
+        fs::create_dir_all(&dir)?;
+
+        let quote = |path: &Path| shell_escape::unix::escape(path.to_string_lossy()).to_string();
+        let mut script = String::from(
+            "#!/bin/sh\n\
+             # Written by dotdipper apply: puts files outside $HOME in place as root.\n\
+             # Each file it replaces is copied to replaced/ first.\n\
+             set -eu\n",
+        );
+        script.push_str(&format!("cd {}\n", quote(&dir)));
+        for step in &steps {
+            let rel = step.target.strip_prefix("/").unwrap_or(&step.target);
+            let staged = Path::new("files").join(rel);
+            let replaced = Path::new("replaced").join(rel);
+            // Made here so the copies root leaves in them can be moved on
+            for made in [&staged, &replaced] {
+                fs::create_dir_all(dir.join(made).parent().unwrap_or(&dir))?;
+            }
+            fs::copy(&step.source, dir.join(&staged))
+                .with_context(|| format!("Failed to stage {}", step.target.display()))?;
+
+            let target = quote(&step.target);
+            let parent = quote(step.target.parent().unwrap_or(Path::new("/")));
+            script.push_str(&format!("\n# {}\n", step.target.display()));
+            // Copying over an existing file keeps its owner and mode
+            script.push_str(&format!(
+                "if [ -e {target} ]; then\n  cp -p {target} {replaced}\nelse\n  mkdir -p {parent}\nfi\ncp {staged} {target}\n",
+                target = target,
+                parent = parent,
+                replaced = quote(&replaced),
+                staged = quote(&staged),
+            ));
+        }
+        let script_path = dir.join(SCRIPT_FILE);
+        fs::write(&script_path, script)?;
+
+        Ok(Self {
+            dir,
+            script: script_path,
+            steps,
+        })
+    }
+
+    /// Show the script and run it with `sudo` if confirmed. Returns whether
+    /// it ran; the files it replaced are moved to the trash.
+    pub fn offer(&self) -> Result<bool> {
+        ui::section("Files that need root");
+        for step in &self.steps {
+            println!("  {}", step.target.display());
+        }
+        println!();
+        print!("{}", fs::read_to_string(&self.script)?);
+        println!();
+        if !ui::prompt_confirm("Run this script with sudo now?", false) {
+            ui::info(&format!("Wrote {}", self.script.display()));
+            ui::hint(&format!(
+                "Review it, then run: sudo sh {}",
+                self.script.display()
+            ));
+            return Ok(false);
+        }
+
+        let status = Command::new("sudo")
+            .arg("sh")
+            .arg(&self.script)
+            .status()
+            .context("Failed to run sudo")?;
+        if !status.success() {
+            bail!(
+                "{} failed ({}); the files it replaced are in {}",
+                self.script.display(),
+                status,
+                self.dir.join("replaced").display()
+            );
+        }
+        self.trash_replaced()?;
+        Ok(true)
+    }
+
+    /// Move the copies of the replaced files to the trash
+    fn trash_replaced(&self) -> Result<()> {
+        for step in &self.steps {
+            let rel = step.target.strip_prefix("/").unwrap_or(&step.target);
+            let replaced = self.dir.join("replaced").join(rel);
+            if replaced.exists() {
+                crate::trash::put_as(&replaced, &step.target, "Replaced by apply")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files_outside_home_are_anchored_at_root_and_installed_by_the_script() {
+        let home = Path::new("/home/alice");
+        let hosts = manifest_path(Path::new("/etc/hosts"), home);
+        assert_eq!(hosts, Path::new("@root/etc/hosts"));
+        assert!(is_system(&hosts));
+        assert_eq!(target(home, &hosts), Path::new("/etc/hosts"));
+
+        let zshrc = manifest_path(Path::new("/home/alice/.zshrc"), home);
+        assert_eq!(zshrc, Path::new(".zshrc"));
+        assert!(!is_system(&zshrc));
+        assert_eq!(target(home, &zshrc), home.join(".zshrc"));
+        // A file named like the prefix inside $HOME isn't one
+        assert!(!is_system(Path::new("@rootless/file")));
+
+        let mut config = Config::default();
+        assert_eq!(
+            check_target(
+                &config,
+                Path::new("@root/../home/alice/.zshrc"),
+                Path::new("/"),
+                home
+            ),
+            Some("Not a plain path")
+        );
+        // Only files this machine tracks outside $HOME may be written there
+        assert_eq!(
+            check_target(&config, &hosts, Path::new("/etc/hosts"), home),
+            Some("Outside $HOME and not tracked in this config")
+        );
+        config
+            .general
+            .tracked_files
+            .push(PathBuf::from("/etc/hosts"));
+        assert_eq!(
+            check_target(&config, &hosts, Path::new("/etc/hosts"), home),
+            None
+        );
+        let motd = Path::new("@root/etc/motd.d/motd");
+        assert!(check_target(&config, motd, Path::new("/etc/motd.d/motd"), home).is_some());
+        config
+            .tracked_dirs
+            .insert("/etc/motd.d".to_string(), Default::default());
+        assert_eq!(
+            check_target(&config, motd, Path::new("/etc/motd.d/motd"), home),
+            None
+        );
+
+        // The script keeps the file it replaces and creates missing ones
+        let temp = tempfile::tempdir().unwrap();
+        let root = temp.path().join("sys dir");
+        fs::create_dir_all(root.join("etc")).unwrap();
+        fs::write(root.join("etc/hosts"), "old").unwrap();
+        fs::write(temp.path().join("hosts"), "new").unwrap();
+        fs::write(temp.path().join("motd"), "hello").unwrap();
+        let plan = Plan::write_in(
+            temp.path().join("plan"),
+            vec![
+                Step {
+                    source: temp.path().join("hosts"),
+                    target: root.join("etc/hosts"),
+                },
+                Step {
+                    source: temp.path().join("motd"),
+                    target: root.join("etc/motd.d/motd"),
+                },
+            ],
+        )
+        .unwrap();
+        let status = Command::new("sh").arg(&plan.script).status().unwrap();
+        assert!(status.success());
+        assert_eq!(fs::read_to_string(root.join("etc/hosts")).unwrap(), "new");
+        assert_eq!(
+            fs::read_to_string(root.join("etc/motd.d/motd")).unwrap(),
+            "hello"
+        );
+        let replaced = plan
+            .dir
+            .join("replaced")
+            .join(root.strip_prefix("/").unwrap());
+        assert_eq!(
+            fs::read_to_string(replaced.join("etc/hosts")).unwrap(),
+            "old"
+        );
+        assert!(!replaced.join("etc/motd.d/motd").exists());
+    }
+}
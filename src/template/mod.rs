@@ -0,0 +1,174 @@
+//! Dotfiles rendered as templates with per-machine variables.
+//!
+//! A file marked `template = true` under `[files]` is stored in the compiled
+//! directory with placeholders, e.g. `email = {{ email }}` in `.gitconfig`,
+//! and rendered with [Tera](https://keats.github.io/tera/) syntax when it is
+//! applied. Variables come from, in increasing precedence:
+//!
+//! - built-ins: `hostname`, `os`, `arch`, `user`, `home`, `profile`,
+//!   `groups` (host groups this machine is in) and `tags` (its host tags)
+//! - `[variables]` in config.toml, which a profile's config can override
+//! - `variables` of the `[hosts.<group>]` groups this machine belongs to
+//!
+//! The template is edited in the compiled directory. `$HOME` only holds the
+//! rendered output, so snapshot keeps the stored template instead of
+//! capturing it, and status compares the file against the rendered template.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+use crate::hosts::HostIdentity;
+
+/// Variables templates are rendered with on this machine
+#[derive(Debug, Clone)]
+pub struct Variables {
+    values: BTreeMap<String, toml::Value>,
+}
+
+impl Variables {
+    /// Variables for the local machine
+    pub fn local(config: &Config) -> Result<Self> {
+        let home = dirs::home_dir().context("Failed to find home directory")?;
+        let profile = crate::profiles::active_profile_name().unwrap_or_else(|_| "default".into());
+        Ok(Self::resolve(
+            config,
+            &HostIdentity::local(config)?,
+            &home,
+            &profile,
+        ))
+    }
+
+    fn resolve(config: &Config, host: &HostIdentity, home: &Path, profile: &str) -> Self {
+        let string = |s: &str| toml::Value::String(s.to_string());
+        let mut values = BTreeMap::from([
+            ("hostname".to_string(), string(&host.name)),
+            ("os".to_string(), string(std::env::consts::OS)),
+            ("arch".to_string(), string(std::env::consts::ARCH)),
+            (
+                "user".to_string(),
+                string(&std::env::var("USER").unwrap_or_default()),
+            ),
+            ("home".to_string(), string(&home.to_string_lossy())),
+            ("profile".to_string(), string(profile)),
+            (
+                "groups".to_string(),
+                toml::Value::Array(host.groups.iter().map(|g| string(g)).collect()),
+            ),
+            (
+                "tags".to_string(),
+                toml::Value::Table(
+                    host.tags
+                        .iter()
+                        .map(|(k, v)| (k.clone(), string(v)))
+                        .collect(),
+                ),
+            ),
+        ]);
+        values.extend(config.variables.clone());
+        for group in &host.groups {
+            if let Some(def) = config.hosts.get(group) {
+                values.extend(def.variables.clone());
+            }
+        }
+        Self { values }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &toml::Value)> {
+        self.values.iter()
+    }
+
+    /// Render template `text`; `name` identifies it in errors
+    pub fn render(&self, name: &str, text: &str) -> Result<String> {
+        let mut context = tera::Context::new();
+        for (key, value) in &self.values {
+            context.insert(key, value);
+        }
+        let mut tera = tera::Tera::default();
+        tera.add_raw_template(name, text)
+            .and_then(|_| tera.render(name, &context))
+            .map_err(anyhow::Error::new)
+            .with_context(|| format!("Failed to render template {}", name))
+    }
+
+    /// Render the template file at `path`
+    pub fn render_file(&self, path: &Path) -> Result<String> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template {}", path.display()))?;
+        self.render(&path.display().to_string(), &text)
+    }
+
+    /// Whether `file` holds what `template` renders to
+    pub fn is_rendered(&self, template: &Path, file: &Path) -> bool {
+        match (self.render_file(template), fs::read_to_string(file)) {
+            (Ok(rendered), Ok(current)) => rendered == current,
+            _ => false,
+        }
+    }
+}
+
+/// Whether the file at home-relative `rel_path` is rendered as a template
+pub fn is_template(config: &Config, rel_path: &Path) -> bool {
+    config.file_override(rel_path).is_some_and(|o| o.template)
+}
+
+/// Split files into those to capture and templates whose stored copy in
+/// `compiled` is kept as it is
+pub fn partition(
+    config: &Config,
+    home: &Path,
+    compiled: &Path,
+    files: Vec<PathBuf>,
+) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    files.into_iter().partition(|file| {
+        let rel_path = file.strip_prefix(home).unwrap_or(file);
+        !(is_template(config, rel_path) && compiled.join(rel_path).exists())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::HostGroup;
+
+    #[test]
+    fn test_host_group_variables_override_config_variables() {
+        let mut config = Config::default();
+        config.variables.insert(
+            "email".to_string(),
+            toml::Value::String("me@personal.dev".to_string()),
+        );
+        config.hosts.insert(
+            "work".to_string(),
+            HostGroup {
+                patterns: vec!["*-corp".to_string()],
+                variables: BTreeMap::from([(
+                    "email".to_string(),
+                    toml::Value::String("me@corp.com".to_string()),
+                )]),
+                ..Default::default()
+            },
+        );
+        let template = "[user]\n\temail = {{ email }}\n{% if \"work\" in groups %}\t# on {{ hostname }}\n{% endif %}";
+
+        let home = Path::new("/home/me");
+        let work = HostIdentity::resolve(&config, "laptop-corp").unwrap();
+        let vars = Variables::resolve(&config, &work, home, "default");
+        assert_eq!(
+            vars.render(".gitconfig", template).unwrap(),
+            "[user]\n\temail = me@corp.com\n\t# on laptop-corp\n"
+        );
+
+        let personal = HostIdentity::resolve(&config, "mbp").unwrap();
+        let vars = Variables::resolve(&config, &personal, home, "default");
+        assert_eq!(
+            vars.render(".gitconfig", template).unwrap(),
+            "[user]\n\temail = me@personal.dev\n"
+        );
+
+        let err = vars.render(".gitconfig", "{{ nope }}").unwrap_err();
+        assert!(format!("{:#}", err).contains("Failed to render template .gitconfig"));
+    }
+}
@@ -0,0 +1,248 @@
+//! Tracking and untracking individual files.
+//!
+//! `dotdipper add ~/.config/kitty/kitty.conf` adds the file to
+//! `tracked_files`, copies it into the compiled directory and records it in
+//! the manifest straight away, without a `discover --write` and a full
+//! snapshot. A directory adds every file under it. `dotdipper rm` does the
+//! opposite: the files leave `tracked_files`, the compiled directory and the
+//! manifest, and the files in `$HOME` are left alone.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hash::Manifest;
+use crate::matching::{display_tilde, expand_tilde, to_home_relative, PathMatcher};
+use crate::ui;
+
+/// `input` as an absolute path, refusing anything outside $HOME unless
+/// `system` allows it
+fn resolve_input(input: &str, home: &Path, system: bool) -> Result<PathBuf> {
+    let path = expand_tilde(input, home);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let rel = to_home_relative(&path, home);
+    if system && rel.is_absolute() {
+        if !crate::confine::is_plain(&crate::system::manifest_path(&rel, home)) {
+            bail!("Not a plain path: {}", input);
+        }
+        return Ok(rel);
+    }
+    if rel.is_absolute() || rel.as_os_str().is_empty() || !crate::confine::is_plain(&rel) {
+        bail!(
+            "Only files under $HOME can be tracked: {} (pass --system for files outside it)",
+            input
+        );
+    }
+    Ok(home.join(rel))
+}
+
+/// The files `inputs` name, with directories expanded
+fn expand_inputs(inputs: &[String], home: &Path, system: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input in inputs {
+        let path = resolve_input(input, home, system)?;
+        if path.is_dir() && !path.is_symlink() {
+            let walk = crate::confine::walk(&path);
+            for (refused, reason) in &walk.refused {
+                ui::warn(&format!("Not following {}: {}", refused.display(), reason));
+            }
+            files.extend(walk.files);
+        } else if path.is_file() {
+            files.push(path);
+        } else {
+            bail!("No such file: {}", input);
+        }
+    }
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+/// `dotdipper add <paths>`; with `system`, paths outside $HOME too
+pub fn add(config_path: &Path, inputs: &[String], system: bool) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let mut config = crate::cfg::load(config_path)?;
+    let compiled = crate::paths::compiled_dir()?;
+
+    let files = expand_inputs(inputs, &home, system)?;
+    let files = crate::volatile::filter_and_report(&config, &home, files)?;
+    let files = crate::limits::filter_and_report(&config, &home, files)?;
+    // config.toml can only hold UTF-8 paths
+    let (files, unnamed): (Vec<PathBuf>, Vec<PathBuf>) =
+        files.into_iter().partition(|f| f.to_str().is_some());
+    for file in &unnamed {
+        ui::warn(&format!(
+            "Skipping {}: its name is not valid UTF-8 (track its directory with [tracked_dirs] instead)",
+            file.display()
+        ));
+    }
+    // Symlinks into the compiled directory are already managed
+    let (managed, files): (Vec<PathBuf>, Vec<PathBuf>) = files
+        .into_iter()
+        .partition(|f| crate::links::points_into(f, &compiled));
+    for file in &managed {
+        ui::info(&format!(
+            "{} is already applied from the compiled files",
+            display_tilde(&crate::system::manifest_path(file, &home))
+        ));
+    }
+    if files.is_empty() {
+        if managed.is_empty() && unnamed.is_empty() {
+            ui::info("Nothing to add");
+        }
+        return Ok(());
+    }
+
+    // Loaded entries are absolute whether the config says `~/` or not, and
+    // are written back with `~/`
+    let new: Vec<&PathBuf> = files
+        .iter()
+        .filter(|f| !config.general.tracked_files.contains(f))
+        .collect();
+    let new_count = new.len();
+    config
+        .general
+        .tracked_files
+        .extend(new.into_iter().cloned());
+
+    // Saved first: a tracked file missing from compiled is picked up by the
+    // next snapshot, while compiled files the config doesn't list would
+    // show up as deleted
+    crate::cfg::save(config_path, &config)?;
+    crate::repo::add_files(&config, &files)?;
+
+    for file in &files {
+        ui::line(&format!(
+            "  + {}",
+            display_tilde(&crate::system::manifest_path(file, &home))
+        ));
+    }
+    ui::success(&format!(
+        "Added {} file(s) ({} newly tracked)",
+        files.len(),
+        new_count
+    ));
+    ui::hint("Run 'dotdipper push' to publish them");
+    Ok(())
+}
+
+/// Remove `rel_path` from the compiled directory, making sure a symlink in
+/// `$HOME` that points at it becomes a regular file first
+fn remove_compiled(compiled: &Path, home: &Path, rel_path: &Path) -> Result<()> {
+    let stored = compiled.join(rel_path);
+    let target = crate::system::target(home, rel_path);
+    if crate::links::points_to(&target, &stored) {
+        fs::remove_file(&target)?;
+        fs::copy(&stored, &target)
+            .with_context(|| format!("Failed to restore {}", target.display()))?;
+    }
+    if stored.exists() || stored.is_symlink() {
+        fs::remove_file(&stored)?;
+    }
+    for dir in stored.ancestors().skip(1) {
+        if dir == compiled || !dir.starts_with(compiled) || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// `dotdipper rm <paths>`
+pub fn remove(config_path: &Path, inputs: &[String]) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let mut config = crate::cfg::load(config_path)?;
+    let compiled = crate::paths::compiled_dir()?;
+    let manifest_path = crate::paths::manifest_file()?;
+    let mut manifest = if manifest_path.exists() {
+        Manifest::load(&manifest_path)?
+    } else {
+        Manifest::new()
+    };
+
+    // Files outside $HOME are matched by path, the rest like `--only`
+    let mut patterns = Vec::new();
+    let mut system_paths = Vec::new();
+    for input in inputs {
+        let path = resolve_input(input, &home, true)?;
+        match path.starts_with(&home) {
+            true => patterns.push(display_tilde(&to_home_relative(&path, &home))),
+            false => system_paths.push(crate::system::manifest_path(&path, &home)),
+        }
+    }
+    let matcher = PathMatcher::for_paths(&home, &patterns)?;
+    let is_match = |rel_path: &Path| {
+        matcher.is_match(rel_path) || system_paths.iter().any(|p| rel_path.starts_with(p))
+    };
+
+    let mut removed: Vec<PathBuf> = config
+        .general
+        .tracked_files
+        .iter()
+        .map(|f| crate::system::manifest_path(f, &home))
+        .filter(|rel| is_match(rel))
+        .collect();
+    removed.extend(manifest.files.keys().filter(|rel| is_match(rel)).cloned());
+    removed.sort();
+    removed.dedup();
+    if removed.is_empty() {
+        bail!("Not tracked: {}", inputs.join(", "));
+    }
+
+    for rel_path in &removed {
+        remove_compiled(&compiled, &home, rel_path)?;
+        manifest.files.remove(rel_path);
+    }
+    config
+        .general
+        .tracked_files
+        .retain(|f| !is_match(&crate::system::manifest_path(f, &home)));
+    if manifest_path.exists() {
+        manifest.save(&manifest_path)?;
+    }
+    crate::cfg::save(config_path, &config)?;
+
+    for rel_path in &removed {
+        ui::line(&format!("  - {}", display_tilde(rel_path)));
+    }
+    ui::success(&format!("Stopped tracking {} file(s)", removed.len()));
+    ui::hint("The files in $HOME were left as they are");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_inputs_walks_directories_and_refuses_outside_home() {
+        let temp = tempfile::tempdir().unwrap();
+        let home = temp.path();
+        fs::create_dir_all(home.join(".config/kitty/themes")).unwrap();
+        fs::write(home.join(".config/kitty/kitty.conf"), "font_size 12").unwrap();
+        fs::write(home.join(".config/kitty/themes/dark.conf"), "bg #000").unwrap();
+        fs::write(home.join(".zshrc"), "export A=1").unwrap();
+
+        let files = expand_inputs(
+            &["~/.config/kitty".to_string(), "~/.zshrc".to_string()],
+            home,
+            false,
+        )
+        .unwrap();
+        assert_eq!(
+            files,
+            vec![
+                home.join(".config/kitty/kitty.conf"),
+                home.join(".config/kitty/themes/dark.conf"),
+                home.join(".zshrc"),
+            ]
+        );
+
+        assert!(expand_inputs(&["~/.missing".to_string()], home, false).is_err());
+        assert!(expand_inputs(&["/etc/hosts".to_string()], home, false).is_err());
+        assert!(expand_inputs(&["~/".to_string()], home, false).is_err());
+    }
+}
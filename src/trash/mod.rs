@@ -0,0 +1,453 @@
+//! Files dotdipper replaces or removes, kept until the trash is emptied.
+//!
+//! Instead of deleting them, apply and snapshot rollback move the files they
+//! replace into `trash/<timestamp>/` in the dotdipper directory, one batch per
+//! invocation: `home/<path relative to $HOME>` or `root/<absolute path>`.
+//! Each batch's `trash.json` records where every item came from and why it
+//! was trashed, so `dotdipper trash list|restore|empty` can manage them.
+//! Apply only trashes what it did not back up.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::matching::{display_tilde, expand_tilde, to_home_relative};
+use crate::ui;
+
+const STAMP_FORMAT: &str = "%Y%m%d-%H%M%S";
+
+/// Name of a batch's index
+const INDEX_FILE: &str = "trash.json";
+
+/// One trashed file, directory or symlink
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Item {
+    /// Where it was
+    #[serde(with = "crate::hash::raw_path")]
+    pub original: PathBuf,
+    /// Where it is, relative to its batch
+    #[serde(with = "crate::hash::raw_path")]
+    pub path: PathBuf,
+    /// What replaced or removed it
+    pub reason: String,
+    pub trashed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Index {
+    #[serde(default)]
+    items: Vec<Item>,
+}
+
+/// A trashed item that is still there
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The batch it was trashed in
+    pub batch: String,
+    pub item: Item,
+    /// Where it is
+    pub path: PathBuf,
+}
+
+/// The batch of this invocation, shared by everything it trashes
+fn batch_id() -> &'static str {
+    static ID: OnceLock<String> = OnceLock::new();
+    ID.get_or_init(|| {
+        let stamp = Utc::now().format(STAMP_FORMAT).to_string();
+        let dir = crate::paths::trash_dir().unwrap_or_default();
+        let mut id = stamp.clone();
+        let mut n = 0;
+        while dir.join(&id).exists() {
+            n += 1;
+            id = format!("{}-{}", stamp, n);
+        }
+        id
+    })
+}
+
+fn load_index(batch_dir: &Path) -> Result<Index> {
+    let path = batch_dir.join(INDEX_FILE);
+    if !path.exists() {
+        return Ok(Index::default());
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    serde_json::from_str(&contents).with_context(|| format!("Failed to parse {}", path.display()))
+}
+
+fn save_index(batch_dir: &Path, index: &Index) -> Result<()> {
+    let path = batch_dir.join(INDEX_FILE);
+    fs::write(&path, serde_json::to_string_pretty(index)?)
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Move `from` to `to`, copying it when they are on different filesystems
+pub(crate) fn move_path(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    let file_type = fs::symlink_metadata(from)
+        .with_context(|| format!("Failed to read {}", from.display()))?
+        .file_type();
+    if file_type.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(from)?, to)?;
+        fs::remove_file(from)?;
+    } else if file_type.is_dir() {
+        fs::create_dir_all(to)?;
+        let options = fs_extra::dir::CopyOptions::new().content_only(true);
+        fs_extra::dir::copy(from, to, &options)
+            .with_context(|| format!("Failed to copy {}", from.display()))?;
+        fs::remove_dir_all(from)?;
+    } else {
+        fs::copy(from, to).with_context(|| format!("Failed to copy {}", from.display()))?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Move `path` (a file, directory or symlink) to the trash, returning where
+/// it went
+pub fn put(path: &Path, reason: &str) -> Result<PathBuf> {
+    put_as(path, path, reason)
+}
+
+/// Move `path` to the trash as the previous contents of `original`
+pub fn put_as(path: &Path, original: &Path, reason: &str) -> Result<PathBuf> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let batch_dir = crate::paths::trash_dir()?.join(batch_id());
+    let rel = to_home_relative(original, &home);
+    let base = match rel.strip_prefix("/") {
+        Ok(outside) => batch_dir.join("root").join(outside),
+        Err(_) => batch_dir.join("home").join(rel),
+    };
+    // The same path trashed twice in one invocation keeps both
+    let mut dest = base.clone();
+    let mut n = 0;
+    while dest.exists() || dest.is_symlink() {
+        n += 1;
+        let mut name = base.clone().into_os_string();
+        name.push(format!(".{}", n));
+        dest = PathBuf::from(name);
+    }
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    move_path(path, &dest)
+        .with_context(|| format!("Failed to move {} to the trash", original.display()))?;
+
+    let mut index = load_index(&batch_dir)?;
+    index.items.push(Item {
+        original: original.to_path_buf(),
+        path: dest.strip_prefix(&batch_dir)?.to_path_buf(),
+        reason: reason.to_string(),
+        trashed_at: Utc::now(),
+    });
+    save_index(&batch_dir, &index)?;
+    Ok(dest)
+}
+
+/// Every trashed item that is still there, newest first
+pub fn list() -> Result<Vec<Entry>> {
+    let dir = crate::paths::trash_dir()?;
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut entries = Vec::new();
+    for batch in fs::read_dir(&dir)?.filter_map(|e| e.ok()) {
+        let batch_dir = batch.path();
+        if !batch_dir.is_dir() {
+            continue;
+        }
+        let batch = batch.file_name().to_string_lossy().to_string();
+        for item in load_index(&batch_dir)?.items {
+            let path = batch_dir.join(&item.path);
+            if path.exists() || path.is_symlink() {
+                entries.push(Entry {
+                    batch: batch.clone(),
+                    item,
+                    path,
+                });
+            }
+        }
+    }
+    entries.sort_by(|a, b| {
+        b.item
+            .trashed_at
+            .cmp(&a.item.trashed_at)
+            .then_with(|| b.batch.cmp(&a.batch))
+    });
+    Ok(entries)
+}
+
+fn display(path: &Path, home: &Path) -> String {
+    if path.starts_with(home) {
+        display_tilde(&to_home_relative(path, home))
+    } else {
+        path.display().to_string()
+    }
+}
+
+/// `dotdipper trash list`
+pub fn print_list() -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let entries = list()?;
+    if ui::is_json() {
+        let entries: Vec<serde_json::Value> = entries
+            .iter()
+            .map(|e| {
+                serde_json::json!({
+                    "batch": e.batch,
+                    "original": crate::hash::raw_path::encode(&e.item.original),
+                    "path": crate::hash::raw_path::encode(&e.path),
+                    "reason": e.item.reason,
+                    "trashed_at": e.item.trashed_at,
+                    "size": crate::usage::dir_size(&e.path),
+                })
+            })
+            .collect();
+        return ui::print_json(&entries);
+    }
+    if entries.is_empty() {
+        ui::info("The trash is empty");
+        return Ok(());
+    }
+
+    let rows = entries
+        .iter()
+        .map(|e| {
+            vec![
+                e.batch.clone(),
+                display(&e.item.original, &home),
+                e.item.reason.clone(),
+                crate::usage::format_size(crate::usage::dir_size(&e.path)),
+            ]
+        })
+        .collect();
+    ui::print_table(&["Batch", "File", "Reason", "Size"], rows);
+    let total: u64 = entries
+        .iter()
+        .map(|e| crate::usage::dir_size(&e.path))
+        .sum();
+    ui::info(&format!(
+        "{} item(s), {}",
+        entries.len(),
+        crate::usage::format_size(total)
+    ));
+    ui::hint("Put them back with 'dotdipper trash restore <batch|file>'");
+    Ok(())
+}
+
+/// The items `selector` names: every item of a batch, or the newest one
+/// trashed from a path. Restored in the returned order.
+fn select<'a>(entries: &'a [Entry], selector: &str, home: &Path) -> Vec<&'a Entry> {
+    let batch: Vec<&Entry> = entries.iter().filter(|e| e.batch == selector).collect();
+    if !batch.is_empty() {
+        return batch;
+    }
+    let path = expand_tilde(selector, home);
+    let path = match path.is_absolute() {
+        true => path,
+        false => std::env::current_dir().unwrap_or_default().join(path),
+    };
+    entries
+        .iter()
+        .find(|e| e.item.original == path)
+        .into_iter()
+        .collect()
+}
+
+/// Forget `entry` once it has been restored, removing its batch when that
+/// leaves it empty
+fn forget(entry: &Entry) -> Result<()> {
+    let batch_dir = crate::paths::trash_dir()?.join(&entry.batch);
+    let mut index = load_index(&batch_dir)?;
+    index.items.retain(|i| i.path != entry.item.path);
+    let left = index
+        .items
+        .iter()
+        .any(|i| batch_dir.join(&i.path).exists() || batch_dir.join(&i.path).is_symlink());
+    if left {
+        save_index(&batch_dir, &index)
+    } else {
+        fs::remove_dir_all(&batch_dir)
+            .with_context(|| format!("Failed to remove {}", batch_dir.display()))
+    }
+}
+
+/// `dotdipper trash restore <batch|file>`: put a batch, or the newest
+/// trashed copy of a file, back in place. Whatever is there now goes to the
+/// trash in turn, so a restore can itself be undone.
+pub fn restore(selector: &str, force: bool) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let entries = list()?;
+    let selected = select(&entries, selector, &home);
+    if selected.is_empty() {
+        bail!(
+            "Nothing in the trash matches {}; see 'dotdipper trash list'",
+            selector
+        );
+    }
+
+    if !force {
+        ui::section("Files to restore:");
+        for entry in &selected {
+            println!(
+                "  {} ({})",
+                display(&entry.item.original, &home),
+                entry.item.reason
+            );
+        }
+        if !ui::prompt_confirm(
+            &format!("Restore {} item(s) from the trash?", selected.len()),
+            false,
+        ) {
+            ui::info("Restore cancelled");
+            return Ok(());
+        }
+    }
+
+    for entry in &selected {
+        let original = &entry.item.original;
+        if original.exists() || original.is_symlink() {
+            put(original, "Replaced by trash restore")?;
+        } else if let Some(parent) = original.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        move_path(&entry.path, original)
+            .with_context(|| format!("Failed to restore {}", original.display()))?;
+        forget(entry)?;
+        println!("  ↩ {}", display(original, &home));
+    }
+    ui::success(&format!("Restored {} item(s)", selected.len()));
+    Ok(())
+}
+
+/// Batches whose newest item is older than `cutoff`, or all of them
+fn to_empty(entries: &[Entry], cutoff: Option<DateTime<Utc>>) -> Vec<String> {
+    let mut batches: Vec<String> = Vec::new();
+    for entry in entries {
+        if !batches.contains(&entry.batch) {
+            batches.push(entry.batch.clone());
+        }
+    }
+    batches.retain(|batch| {
+        cutoff.is_none_or(|cutoff| {
+            entries
+                .iter()
+                .filter(|e| &e.batch == batch)
+                .all(|e| e.item.trashed_at < cutoff)
+        })
+    });
+    batches
+}
+
+/// `dotdipper trash empty`: delete the trashed files for good, or only the
+/// batches older than `older_than`
+pub fn empty(older_than: Option<&str>, force: bool) -> Result<()> {
+    let cutoff = match older_than {
+        Some(age) => Some(
+            Utc::now()
+                - crate::snapshots::parse_duration(age).with_context(|| {
+                    format!("Invalid age '{}' (expected e.g. 30d, 2w, 1m)", age)
+                })?,
+        ),
+        None => None,
+    };
+    let entries = list()?;
+    let batches = to_empty(&entries, cutoff);
+    if batches.is_empty() {
+        ui::info("Nothing in the trash to delete");
+        return Ok(());
+    }
+
+    let dir = crate::paths::trash_dir()?;
+    let count = entries
+        .iter()
+        .filter(|e| batches.contains(&e.batch))
+        .count();
+    let size: u64 = batches
+        .iter()
+        .map(|b| crate::usage::dir_size(&dir.join(b)))
+        .sum();
+    if !force
+        && !ui::prompt_confirm(
+            &format!(
+                "Permanently delete {} item(s) ({}) from the trash?",
+                count,
+                crate::usage::format_size(size)
+            ),
+            false,
+        )
+    {
+        ui::info("Nothing was deleted");
+        return Ok(());
+    }
+    for batch in &batches {
+        let batch_dir = dir.join(batch);
+        fs::remove_dir_all(&batch_dir)
+            .with_context(|| format!("Failed to delete {}", batch_dir.display()))?;
+    }
+    ui::success(&format!(
+        "Deleted {} item(s) from the trash, freed {}",
+        count,
+        crate::usage::format_size(size)
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn entry(batch: &str, original: &str, days_ago: i64) -> Entry {
+        Entry {
+            batch: batch.to_string(),
+            item: Item {
+                original: PathBuf::from(original),
+                path: PathBuf::from("home").join(original.trim_start_matches('/')),
+                reason: "Replaced by apply".to_string(),
+                trashed_at: Utc::now() - Duration::days(days_ago),
+            },
+            path: PathBuf::from(original),
+        }
+    }
+
+    #[test]
+    fn test_select_and_empty_work_on_whole_batches() {
+        // Newest first, as `list` returns them
+        let entries = vec![
+            entry("20240301-000000", "/h/.zshrc", 1),
+            entry("20240201-000000", "/h/.zshrc", 40),
+            entry("20240201-000000", "/h/.vimrc", 40),
+            entry("20240101-000000", "/h/.gitconfig", 60),
+        ];
+        let home = Path::new("/h");
+        let originals = |selected: Vec<&Entry>| -> Vec<String> {
+            selected
+                .iter()
+                .map(|e| format!("{}:{}", e.batch, e.item.original.display()))
+                .collect()
+        };
+
+        assert_eq!(
+            originals(select(&entries, "20240201-000000", home)),
+            ["20240201-000000:/h/.zshrc", "20240201-000000:/h/.vimrc"]
+        );
+        // A path picks its newest trashed copy
+        assert_eq!(
+            originals(select(&entries, "/h/.zshrc", home)),
+            ["20240301-000000:/h/.zshrc"]
+        );
+        assert!(select(&entries, "/h/.bashrc", home).is_empty());
+
+        assert_eq!(to_empty(&entries, None).len(), 3);
+        assert_eq!(
+            to_empty(&entries, Some(Utc::now() - Duration::days(30))),
+            ["20240201-000000", "20240101-000000"]
+        );
+    }
+}
@@ -1,39 +1,216 @@
 use colored::*;
 use console::style;
 use indicatif::{ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::time::Duration;
 
+/// How much dotdipper prints, from `--quiet`/`--verbose`/`--porcelain`/`--output json`
+/// or `[ui].output`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputLevel {
+    /// Only errors and final results
+    Quiet,
+    Normal,
+    /// Normal output plus debug details
+    Verbose,
+    /// Stable `kind<TAB>message` lines for scripts, no colors or progress bars
+    Porcelain,
+    /// A single JSON document on stdout from commands that support it;
+    /// everything else is silent except errors. Only set by the flag.
+    #[serde(skip_deserializing)]
+    Json,
+}
+
+/// Symbol/color set used for human-readable output (`[ui].theme`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// Colored output with unicode symbols
+    Default,
+    /// Unicode symbols, no colors
+    Plain,
+    /// ASCII-only symbols for terminals without unicode fonts
+    Ascii,
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(OutputLevel::Normal as u8);
+static THEME: AtomicU8 = AtomicU8::new(Theme::Default as u8);
+
 pub fn init() {
     // Enable colored output on Windows
     #[cfg(windows)]
     colored::control::set_virtual_terminal(true).ok();
 }
 
+pub fn set_level(level: OutputLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    if matches!(level, OutputLevel::Porcelain | OutputLevel::Json) {
+        disable_colors();
+    }
+}
+
+pub fn level() -> OutputLevel {
+    match LEVEL.load(Ordering::Relaxed) {
+        0 => OutputLevel::Quiet,
+        2 => OutputLevel::Verbose,
+        3 => OutputLevel::Porcelain,
+        4 => OutputLevel::Json,
+        _ => OutputLevel::Normal,
+    }
+}
+
+pub fn set_theme(theme: Theme) {
+    THEME.store(theme as u8, Ordering::Relaxed);
+    if theme != Theme::Default {
+        disable_colors();
+    }
+}
+
+pub fn theme() -> Theme {
+    match THEME.load(Ordering::Relaxed) {
+        1 => Theme::Plain,
+        2 => Theme::Ascii,
+        _ => Theme::Default,
+    }
+}
+
+fn disable_colors() {
+    colored::control::set_override(false);
+    console::set_colors_enabled(false);
+}
+
+/// Whether decorative, human-only output (sections, hints, progress) is shown.
+fn is_chatty() -> bool {
+    matches!(level(), OutputLevel::Normal | OutputLevel::Verbose)
+}
+
+fn symbol(kind: &str) -> &'static str {
+    match (theme(), kind) {
+        (Theme::Ascii, "info") => "[i]",
+        (Theme::Ascii, "success") => "[ok]",
+        (Theme::Ascii, "error") => "[x]",
+        (Theme::Ascii, "warn") => "[!]",
+        (Theme::Ascii, "hint") => "  >",
+        (Theme::Ascii, _) => "[.]",
+        (_, "info") => "ℹ",
+        (_, "success") => "✓",
+        (_, "error") => "✗",
+        (_, "warn") => "⚠",
+        (_, "hint") => "💡",
+        _ => "·",
+    }
+}
+
+/// Whether commands should print their result as JSON (`--output json`).
+pub fn is_json() -> bool {
+    level() == OutputLevel::Json
+}
+
+/// Print `value` as the command's JSON output, on one line.
+pub fn print_json<T: Serialize>(value: &T) -> anyhow::Result<()> {
+    println!("{}", serde_json::to_string(value)?);
+    Ok(())
+}
+
+/// Single-line `kind<TAB>message` form used by porcelain output.
+pub fn porcelain_line(kind: &str, message: &str) -> String {
+    let message = message
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n");
+    format!("{}\t{}", kind, console::strip_ansi_codes(&message))
+}
+
 pub fn info(message: &str) {
-    println!("{} {}", style("ℹ").blue(), message);
+    match level() {
+        OutputLevel::Quiet | OutputLevel::Json => {}
+        OutputLevel::Porcelain => println!("{}", porcelain_line("info", message)),
+        _ => println!("{} {}", style(symbol("info")).blue(), message),
+    }
 }
 
 pub fn success(message: &str) {
-    println!("{} {}", style("✓").green(), message.green());
+    match level() {
+        OutputLevel::Json => {}
+        OutputLevel::Porcelain => println!("{}", porcelain_line("ok", message)),
+        _ => println!("{} {}", style(symbol("success")).green(), message.green()),
+    }
+}
+
+/// Closing message after a command printed its result; `--quiet` keeps
+/// only the result
+pub fn done(message: &str) {
+    if level() != OutputLevel::Quiet {
+        success(message);
+    }
+}
+
+/// A result that calls for attention, such as uncommitted changes. Shown
+/// as a warning, and kept with `--quiet` since it is the command's answer.
+pub fn notice(message: &str) {
+    match level() {
+        OutputLevel::Quiet => println!("{} {}", style(symbol("warn")).yellow(), message.yellow()),
+        _ => warn(message),
+    }
 }
 
 pub fn error(message: &str) {
-    eprintln!("{} {}", style("✗").red(), message.red());
+    match level() {
+        OutputLevel::Porcelain | OutputLevel::Json => {
+            eprintln!("{}", porcelain_line("error", message))
+        }
+        _ => eprintln!("{} {}", style(symbol("error")).red(), message.red()),
+    }
 }
 
 pub fn warn(message: &str) {
-    println!("{} {}", style("⚠").yellow(), message.yellow());
+    match level() {
+        OutputLevel::Quiet => {}
+        // stdout holds the JSON document; warnings still reach the user
+        OutputLevel::Json => eprintln!("{}", porcelain_line("warn", message)),
+        OutputLevel::Porcelain => println!("{}", porcelain_line("warn", message)),
+        _ => println!("{} {}", style(symbol("warn")).yellow(), message.yellow()),
+    }
+}
+
+/// Plain result line; porcelain writes it as `info` and drops blank lines.
+pub fn line(message: &str) {
+    match level() {
+        OutputLevel::Quiet | OutputLevel::Json => {}
+        OutputLevel::Porcelain if message.is_empty() => {}
+        OutputLevel::Porcelain => println!("{}", porcelain_line("info", message.trim())),
+        _ => println!("{}", message),
+    }
 }
 
 pub fn hint(message: &str) {
-    println!("{} {}", style("💡").cyan(), message.dimmed());
+    if is_chatty() {
+        println!("{} {}", style(symbol("hint")).cyan(), message.dimmed());
+    }
+}
+
+/// Extra detail only shown with `--verbose`.
+pub fn debug(message: &str) {
+    if level() == OutputLevel::Verbose {
+        println!("{} {}", style(symbol("debug")).dim(), message.dimmed());
+    }
 }
 
 pub fn section(title: &str) {
-    println!("\n{}", title.bold().underline());
+    match level() {
+        OutputLevel::Quiet | OutputLevel::Json => {}
+        OutputLevel::Porcelain => println!("{}", porcelain_line("section", title)),
+        _ => println!("\n{}", title.bold().underline()),
+    }
 }
 
 pub fn progress_bar(total: u64, message: &str) -> ProgressBar {
+    if !is_chatty() {
+        return ProgressBar::hidden();
+    }
+
     let pb = ProgressBar::new(total);
     pb.set_style(
         ProgressStyle::default_bar()
@@ -46,6 +223,27 @@ pub fn progress_bar(total: u64, message: &str) -> ProgressBar {
     pb
 }
 
+/// Progress bar counting bytes, with the rate and time left
+pub fn transfer_bar(total_bytes: u64, message: &str) -> ProgressBar {
+    if !is_chatty() {
+        return ProgressBar::hidden();
+    }
+
+    let pb = ProgressBar::new(total_bytes);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "{spinner:.green} [{bar:40.cyan/blue}] {bytes}/{total_bytes} \
+                 {binary_bytes_per_sec} {eta} {msg}",
+            )
+            .expect("Invalid progress bar template")
+            .progress_chars("#>-"),
+    );
+    pb.set_message(message.to_string());
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
+
 pub fn prompt_confirm(message: &str, default: bool) -> bool {
     dialoguer::Confirm::new()
         .with_prompt(message)
@@ -65,7 +263,33 @@ pub fn prompt_text(message: &str, default: Option<&str>) -> String {
     prompt.interact_text().unwrap_or_default()
 }
 
+/// Show a single-choice list; returns `None` if the prompt was cancelled.
+pub fn prompt_select(message: &str, items: &[String]) -> Option<usize> {
+    dialoguer::Select::with_theme(&dialoguer::theme::ColorfulTheme::default())
+        .with_prompt(message)
+        .items(items)
+        .default(0)
+        .interact_opt()
+        .ok()
+        .flatten()
+}
+
 pub fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    match level() {
+        OutputLevel::Quiet | OutputLevel::Json => return,
+        OutputLevel::Porcelain => {
+            for row in rows {
+                let cells: Vec<String> = row
+                    .iter()
+                    .map(|c| console::strip_ansi_codes(c).replace('\t', " "))
+                    .collect();
+                println!("{}", cells.join("\t"));
+            }
+            return;
+        }
+        _ => {}
+    }
+
     // Calculate column widths
     let mut widths = headers.iter().map(|h| h.len()).collect::<Vec<_>>();
     for row in &rows {
@@ -98,3 +322,21 @@ pub fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
         println!();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn porcelain_lines_stay_on_one_line() {
+        assert_eq!(porcelain_line("info", "Applying"), "info\tApplying");
+        assert_eq!(
+            porcelain_line("error", "bad\n\tthing"),
+            "error\tbad\\n\\tthing"
+        );
+        assert_eq!(
+            porcelain_line("ok", &"done".green().to_string()),
+            "ok\tdone"
+        );
+    }
+}
@@ -0,0 +1,349 @@
+//! Moving state out of the layout older versions used.
+//!
+//! Older versions kept everything in one directory, `~/.config/dotdipper`
+//! (or `~/.dotdipper` before that): the config next to the compiled files,
+//! manifests, snapshots and downloaded bundles. Config now stays in the
+//! config directory, state goes to the data directory and bundles to the
+//! cache directory (see [`crate::paths`]). On startup whatever is still in
+//! an old place is moved over, without replacing anything already at the
+//! new one, and symlinks in `$HOME` that pointed into the old place are
+//! retargeted.
+//!
+//! Nothing moves while `DOTDIPPER_HOME` keeps everything in one directory.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::hash::Manifest;
+use crate::paths::DATA_ENTRIES;
+use crate::ui;
+
+/// Where each kind of state belongs
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub home: PathBuf,
+    pub config: PathBuf,
+    pub data: PathBuf,
+    pub cache: PathBuf,
+}
+
+/// What moving one old directory did
+#[derive(Debug, Default)]
+pub struct Moved {
+    /// Each entry moved, and where to
+    pub entries: Vec<(PathBuf, PathBuf)>,
+    /// Entries left in place because the destination was taken
+    pub conflicts: Vec<PathBuf>,
+    /// Symlinks in `$HOME` retargeted
+    pub relinked: Vec<PathBuf>,
+}
+
+impl Layout {
+    /// The directories in use, or `None` when `DOTDIPPER_HOME` is set
+    pub fn current() -> Result<Option<Self>> {
+        if std::env::var_os(crate::paths::HOME_ENV).is_some_and(|v| !v.is_empty()) {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            home: dirs::home_dir().context("Failed to find home directory")?,
+            config: crate::paths::config_dir()?,
+            data: crate::paths::data_dir()?,
+            cache: crate::paths::cache_dir()?,
+        }))
+    }
+
+    /// Old directories that still hold state: `~/.dotdipper`, and the config
+    /// directory when it has state the data directory should
+    fn sources(&self) -> Vec<PathBuf> {
+        let mut sources = Vec::new();
+        let legacy = self.home.join(".dotdipper");
+        if legacy.is_dir() && !legacy.is_symlink() && legacy != self.data {
+            sources.push(legacy);
+        }
+        if self.config != self.data && !self.plan(&self.config).is_empty() {
+            sources.push(self.config.clone());
+        }
+        sources
+    }
+
+    /// Where everything in `old` that doesn't belong there goes
+    fn plan(&self, old: &Path) -> Vec<(PathBuf, PathBuf)> {
+        // Only the old home dir held config, which moves along with the rest
+        let config = (old != self.config).then_some(&self.config);
+        let mut moves = Vec::new();
+        for name in entry_names(old) {
+            let from = old.join(&name);
+            if DATA_ENTRIES.contains(&name.as_str()) {
+                moves.push((from, self.data.join(&name)));
+            } else if name.starts_with("bundle") && name.ends_with(".tar.zst") {
+                moves.push((from, self.cache.join(&name)));
+            } else if name == "cache" && from.is_dir() {
+                for cached in entry_names(&from) {
+                    moves.push((from.join(&cached), self.cache.join(&cached)));
+                }
+            } else if name == "profiles" && from.is_dir() {
+                for profile in entry_names(&from) {
+                    for entry in entry_names(&from.join(&profile)) {
+                        let to = match entry.as_str() {
+                            "compiled" | "manifest.lock" => &self.data,
+                            _ => match config {
+                                Some(config) => config,
+                                None => continue,
+                            },
+                        };
+                        moves.push((
+                            from.join(&profile).join(&entry),
+                            to.join("profiles").join(&profile).join(&entry),
+                        ));
+                    }
+                }
+            } else if let Some(config) = config {
+                moves.push((from, config.join(&name)));
+            }
+        }
+        moves
+    }
+
+    /// Move what `old` holds to where it belongs and retarget the links
+    /// into it
+    pub fn move_from(&self, old: &Path) -> Result<Moved> {
+        let mut moved = Moved::default();
+        for (from, to) in self.plan(old) {
+            if is_empty_dir(&to) {
+                fs::remove_dir(&to)?;
+            }
+            if fs::symlink_metadata(&to).is_ok() {
+                moved.conflicts.push(from);
+                continue;
+            }
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            crate::trash::move_path(&from, &to)
+                .with_context(|| format!("Failed to move {}", from.display()))?;
+            moved.entries.push((from, to));
+        }
+
+        // Directories emptied by the move
+        for dir in [old.join("cache"), old.join("profiles")] {
+            for profile in entry_names(&dir) {
+                remove_if_empty(&dir.join(profile));
+            }
+            remove_if_empty(&dir);
+        }
+        if old != self.config {
+            remove_if_empty(old);
+        }
+
+        moved.relinked = self.relink(&moved.entries)?;
+        Ok(moved)
+    }
+
+    /// Point symlinks in `$HOME` that led into a moved entry at its new
+    /// place, keeping them relative if they were
+    fn relink(&self, entries: &[(PathBuf, PathBuf)]) -> Result<Vec<PathBuf>> {
+        // The main store's, each profile's and each collection's
+        let mut manifests = vec![self.data.join("manifest.lock")];
+        for dir in ["profiles", "collections"] {
+            for name in entry_names(&self.data.join(dir)) {
+                manifests.push(self.data.join(dir).join(name).join("manifest.lock"));
+            }
+        }
+        // Tracked files, and the directories above them that may be links
+        let mut links = BTreeSet::new();
+        for manifest in manifests.iter().filter(|m| m.is_file()) {
+            for rel_path in Manifest::load(manifest)?.files.keys() {
+                if crate::system::is_system(rel_path) {
+                    continue;
+                }
+                let target = self.home.join(rel_path);
+                links.extend(
+                    target
+                        .ancestors()
+                        .take_while(|p| *p != self.home)
+                        .map(Path::to_path_buf),
+                );
+            }
+        }
+
+        let mut relinked = Vec::new();
+        for link in links {
+            let Some(dest) = crate::links::resolve(&link) else {
+                continue;
+            };
+            let Some(new_dest) = entries.iter().find_map(|(from, to)| {
+                dest.strip_prefix(from).ok().map(|rest| match rest {
+                    rest if rest.as_os_str().is_empty() => to.clone(),
+                    rest => to.join(rest),
+                })
+            }) else {
+                continue;
+            };
+            let relative = fs::read_link(&link)?.is_relative();
+            fs::remove_file(&link)?;
+            crate::links::create(&new_dest, &link, relative)?;
+            relinked.push(link);
+        }
+        Ok(relinked)
+    }
+}
+
+/// Move state out of the old layout, if any is left there
+pub fn migrate() -> Result<()> {
+    let Some(layout) = Layout::current()? else {
+        return Ok(());
+    };
+    for old in layout.sources() {
+        let moved = layout.move_from(&old)?;
+        if !moved.entries.is_empty() {
+            ui::info(&format!(
+                "Moved {} entries out of {}; data now lives in {}",
+                moved.entries.len(),
+                old.display(),
+                layout.data.display()
+            ));
+        }
+        if !moved.relinked.is_empty() {
+            ui::info(&format!(
+                "Retargeted {} symlinks to the new location",
+                moved.relinked.len()
+            ));
+        }
+        for conflict in &moved.conflicts {
+            ui::warn(&format!(
+                "Left {} in place: something is already where it would go",
+                conflict.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Names of the entries of `dir`, sorted
+fn entry_names(dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| e.file_name().into_string().ok())
+        .collect();
+    names.sort();
+    names
+}
+
+fn is_empty_dir(path: &Path) -> bool {
+    !path.is_symlink() && fs::read_dir(path).is_ok_and(|mut d| d.next().is_none())
+}
+
+fn remove_if_empty(path: &Path) {
+    if is_empty_dir(path) {
+        let _ = fs::remove_dir(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::FileHash;
+
+    #[test]
+    fn test_legacy_state_moves_to_data_and_cache_dirs_and_links_follow() {
+        let temp = tempfile::tempdir().unwrap();
+        let home = temp.path();
+        let layout = Layout {
+            home: home.to_path_buf(),
+            config: home.join(".config/dotdipper"),
+            data: home.join(".local/share/dotdipper"),
+            cache: home.join(".cache/dotdipper"),
+        };
+        let old = &layout.config;
+        fs::create_dir_all(old.join("compiled/.config/nvim")).unwrap();
+        fs::write(old.join("compiled/.zshrc"), "zsh").unwrap();
+        fs::write(old.join("compiled/.config/nvim/init.lua"), "lua").unwrap();
+        fs::write(old.join("config.toml"), "").unwrap();
+        fs::create_dir_all(old.join("cache")).unwrap();
+        fs::write(old.join("cache/bundle.tar.zst"), "b").unwrap();
+        fs::create_dir_all(old.join("profiles/work/compiled")).unwrap();
+        fs::write(old.join("profiles/work/config.toml"), "").unwrap();
+        fs::create_dir_all(old.join("snapshots")).unwrap();
+        // Taken at the new place already
+        fs::create_dir_all(layout.data.join("snapshots/x")).unwrap();
+
+        let mut manifest = Manifest::new();
+        for path in [".zshrc", ".config/nvim/init.lua"] {
+            manifest.add_file(FileHash {
+                path: PathBuf::from(path),
+                hash: "h".to_string(),
+                size: 1,
+                mode: 0o644,
+                modified: chrono::Utc::now(),
+                xattrs: Default::default(),
+            });
+        }
+        manifest.save(&old.join("manifest.lock")).unwrap();
+        crate::links::create(&old.join("compiled/.zshrc"), &home.join(".zshrc"), true).unwrap();
+        fs::create_dir_all(home.join(".config")).unwrap();
+        crate::links::create(
+            &old.join("compiled/.config/nvim"),
+            &home.join(".config/nvim"),
+            false,
+        )
+        .unwrap();
+        // A file applied from a collection
+        let team = old.join("collections/team");
+        fs::create_dir_all(team.join("compiled")).unwrap();
+        fs::write(team.join("compiled/.editorconfig"), "root = true").unwrap();
+        let mut team_manifest = Manifest::new();
+        team_manifest.add_file(FileHash {
+            path: PathBuf::from(".editorconfig"),
+            hash: "h".to_string(),
+            size: 1,
+            mode: 0o644,
+            modified: chrono::Utc::now(),
+            xattrs: Default::default(),
+        });
+        team_manifest.save(&team.join("manifest.lock")).unwrap();
+        crate::links::create(
+            &team.join("compiled/.editorconfig"),
+            &home.join(".editorconfig"),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(layout.sources(), vec![old.clone()]);
+        let moved = layout.move_from(old).unwrap();
+        assert_eq!(moved.conflicts, vec![old.join("snapshots")]);
+        assert_eq!(moved.relinked.len(), 3);
+
+        assert!(old.join("config.toml").exists());
+        assert!(old.join("profiles/work/config.toml").exists());
+        assert!(!old.join("compiled").exists() && !old.join("cache").exists());
+        assert!(layout.data.join("manifest.lock").exists());
+        assert!(layout.data.join("profiles/work/compiled").is_dir());
+        assert!(layout.cache.join("bundle.tar.zst").exists());
+
+        assert_eq!(
+            fs::read_link(home.join(".zshrc")).unwrap(),
+            Path::new(".local/share/dotdipper/compiled/.zshrc")
+        );
+        assert_eq!(
+            fs::read_link(home.join(".config/nvim")).unwrap(),
+            layout.data.join("compiled/.config/nvim")
+        );
+        assert_eq!(
+            fs::read_to_string(home.join(".config/nvim/init.lua")).unwrap(),
+            "lua"
+        );
+        assert_eq!(
+            fs::read_to_string(home.join(".editorconfig")).unwrap(),
+            "root = true"
+        );
+
+        // Only the snapshots that couldn't move are left
+        assert_eq!(layout.sources(), vec![old.clone()]);
+        assert!(layout.move_from(old).unwrap().entries.is_empty());
+    }
+}
@@ -0,0 +1,481 @@
+//! Ordered state migrations.
+//!
+//! Long-lived installs keep state written by older versions: legacy config
+//! sections, manifests with absolute paths, files in old locations. Each
+//! migration upgrades one such format. The applied level is stored in
+//! `state_version` in the data directory; pending migrations run in order,
+//! after the state files are archived to `backups/`, and the level is bumped
+//! after each one so an interrupted run resumes where it stopped.
+
+use anyhow::{Context, Result};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use crate::hash::Manifest;
+use crate::ui;
+
+/// File in the data directory holding the applied migration level
+pub const STATE_VERSION_FILE: &str = "state_version";
+
+/// Where the state being migrated lives
+pub struct StateDirs {
+    pub base: PathBuf,
+    pub config: PathBuf,
+    /// Directory of the profiles' `<name>/config.toml`
+    pub profile_configs: PathBuf,
+    pub cache: PathBuf,
+    pub home: PathBuf,
+}
+
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    run: fn(&StateDirs) -> Result<()>,
+}
+
+/// Registered migrations, in order. Append only; never renumber.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "config: move legacy [dotfiles] tracked_files into [general]",
+        run: migrate_legacy_dotfiles_section,
+    },
+    Migration {
+        version: 2,
+        description: "manifests: store paths relative to $HOME",
+        run: migrate_absolute_manifest_paths,
+    },
+    Migration {
+        version: 3,
+        description: "layout: move stray bundle*.tar.zst files into the cache directory",
+        run: migrate_stray_bundles,
+    },
+    Migration {
+        version: 4,
+        description: "profiles: keep the default profile's files in compiled/ and manifest.lock",
+        run: migrate_default_profile_store,
+    },
+    Migration {
+        version: 5,
+        description:
+            "profiles: drop default settings from profile configs, which now layer over config.toml",
+        run: migrate_profile_config_defaults,
+    },
+    Migration {
+        version: 6,
+        description: "manifests: upgrade to format 2 (symlinks, empty directories, xattrs)",
+        run: migrate_manifest_format,
+    },
+];
+
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
+
+/// Applied migration level; state without a `state_version` file predates
+/// migrations and counts as level 0.
+pub fn state_version(base: &Path) -> Result<u32> {
+    let path = base.join(STATE_VERSION_FILE);
+    if !path.exists() {
+        return Ok(0);
+    }
+    let contents = fs::read_to_string(&path)?;
+    contents
+        .trim()
+        .parse()
+        .with_context(|| format!("Invalid state version in {}", path.display()))
+}
+
+fn write_state_version(base: &Path, version: u32) -> Result<()> {
+    fs::write(base.join(STATE_VERSION_FILE), format!("{}\n", version))
+        .context("Failed to record state version")
+}
+
+pub fn pending(base: &Path) -> Result<Vec<&'static Migration>> {
+    let current = state_version(base)?;
+    Ok(MIGRATIONS.iter().filter(|m| m.version > current).collect())
+}
+
+/// Every manifest.lock dotdipper keeps: main, profiles and collections
+fn manifest_files(base: &Path) -> Vec<PathBuf> {
+    let mut manifests = vec![base.join("manifest.lock")];
+    for dir in ["profiles", "collections"] {
+        if let Ok(entries) = fs::read_dir(base.join(dir)) {
+            manifests.extend(
+                entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path().join("manifest.lock")),
+            );
+        }
+    }
+    manifests.retain(|p| p.is_file());
+    manifests.sort();
+    manifests
+}
+
+/// The `config.toml` of every profile
+fn profile_configs(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut configs: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().join("config.toml"))
+        .filter(|p| p.is_file())
+        .collect();
+    configs.sort();
+    configs
+}
+
+/// Small state files that migrations may rewrite or move
+fn state_files(dirs: &StateDirs) -> Vec<PathBuf> {
+    let mut files = manifest_files(&dirs.base);
+    files.push(dirs.config.clone());
+    files.extend(profile_configs(&dirs.profile_configs));
+    files.push(dirs.base.join(STATE_VERSION_FILE));
+    files.extend(stray_bundles(&dirs.base));
+    files.retain(|p| p.is_file());
+    files
+}
+
+/// Archive the state files before migrating
+fn backup(dirs: &StateDirs, from: u32, to: u32) -> Result<PathBuf> {
+    let dir = dirs.base.join("backups");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!(
+        "state-v{}-to-v{}-{}.tar.gz",
+        from,
+        to,
+        chrono::Utc::now().format("%Y%m%d-%H%M%S")
+    ));
+
+    let file = File::create(&path)
+        .with_context(|| format!("Failed to create backup {}", path.display()))?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for state_file in state_files(dirs) {
+        let name = if let Ok(rel) = state_file.strip_prefix(&dirs.base) {
+            rel.to_path_buf()
+        } else if let Ok(rel) = state_file.strip_prefix(&dirs.profile_configs) {
+            Path::new("profile-configs").join(rel)
+        } else {
+            PathBuf::from(state_file.file_name().unwrap_or_default())
+        };
+        archive
+            .append_path_with_name(&state_file, name)
+            .with_context(|| format!("Failed to back up {}", state_file.display()))?;
+    }
+    archive.into_inner()?.finish()?;
+    Ok(path)
+}
+
+/// Run pending migrations in order. Returns the backup archive, if anything ran.
+pub fn run_pending(dirs: &StateDirs, dry_run: bool) -> Result<Option<PathBuf>> {
+    let current = state_version(&dirs.base)?;
+    let pending = pending(&dirs.base)?;
+    if pending.is_empty() {
+        ui::success(&format!("State is up to date (version {})", current));
+        return Ok(None);
+    }
+
+    for migration in &pending {
+        println!("  v{}: {}", migration.version, migration.description);
+    }
+    if dry_run {
+        ui::info(&format!("{} migration(s) would run", pending.len()));
+        return Ok(None);
+    }
+
+    let backup_path = backup(dirs, current, latest_version())?;
+    ui::info(&format!("Backed up state to {}", backup_path.display()));
+
+    for migration in pending {
+        (migration.run)(dirs)
+            .with_context(|| format!("Migration v{} failed", migration.version))?;
+        write_state_version(&dirs.base, migration.version)?;
+    }
+
+    ui::success(&format!("Migrated state to version {}", latest_version()));
+    Ok(Some(backup_path))
+}
+
+/// Record that fresh state is already in the latest format
+pub fn mark_current(base: &Path) -> Result<()> {
+    write_state_version(base, latest_version())
+}
+
+fn migrate_legacy_dotfiles_section(dirs: &StateDirs) -> Result<()> {
+    let path = &dirs.config;
+    if !path.exists() {
+        return Ok(());
+    }
+    let mut value: toml::Value = toml::from_str(&fs::read_to_string(path)?)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    let Some(table) = value.as_table_mut() else {
+        return Ok(());
+    };
+    let Some(dotfiles) = table.remove("dotfiles") else {
+        return Ok(());
+    };
+
+    let legacy = dotfiles
+        .get("tracked_files")
+        .and_then(|v| v.as_array())
+        .cloned()
+        .unwrap_or_default();
+    let general = table
+        .entry("general")
+        .or_insert_with(|| toml::Value::Table(toml::map::Map::new()))
+        .as_table_mut()
+        .context("[general] in config.toml is not a table")?;
+    let tracked = general
+        .entry("tracked_files")
+        .or_insert_with(|| toml::Value::Array(Vec::new()))
+        .as_array_mut()
+        .context("general.tracked_files in config.toml is not an array")?;
+    for file in legacy {
+        if !tracked.contains(&file) {
+            tracked.push(file);
+        }
+    }
+
+    fs::write(path, toml::to_string_pretty(&value)?)?;
+    Ok(())
+}
+
+fn migrate_absolute_manifest_paths(dirs: &StateDirs) -> Result<()> {
+    for path in manifest_files(&dirs.base) {
+        let mut manifest = Manifest::load(&path)?;
+        if !manifest.files.keys().any(|p| p.is_absolute()) {
+            continue;
+        }
+
+        for mut file in std::mem::take(&mut manifest.files).into_values() {
+            if let Ok(rel) = file.path.strip_prefix(&dirs.home) {
+                file.path = rel.to_path_buf();
+            }
+            manifest.add_file(file);
+        }
+        manifest.save(&path)?;
+    }
+    Ok(())
+}
+
+fn stray_bundles(base: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(base) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| {
+            p.is_file()
+                && p.file_name()
+                    .and_then(|n| n.to_str())
+                    .is_some_and(|n| n.starts_with("bundle") && n.ends_with(".tar.zst"))
+        })
+        .collect()
+}
+
+fn migrate_stray_bundles(dirs: &StateDirs) -> Result<()> {
+    let bundles = stray_bundles(&dirs.base);
+    if bundles.is_empty() {
+        return Ok(());
+    }
+    fs::create_dir_all(&dirs.cache)?;
+    for bundle in bundles {
+        let dest = dirs.cache.join(bundle.file_name().unwrap_or_default());
+        fs::rename(&bundle, &dest)
+            .with_context(|| format!("Failed to move {}", bundle.display()))?;
+    }
+    Ok(())
+}
+
+/// Older versions kept a copy of the default profile in `profiles/default/`,
+/// which remote pull wrote to while every other command used the top-level
+/// files. Move it up where nothing is there yet; otherwise the top-level
+/// files win and the copy is left for the user to look at.
+fn migrate_default_profile_store(dirs: &StateDirs) -> Result<()> {
+    let profile = dirs.base.join("profiles").join("default");
+    for name in ["compiled", "manifest.lock"] {
+        let from = profile.join(name);
+        let to = dirs.base.join(name);
+        if !from.exists() {
+            continue;
+        }
+        let is_empty_dir = |p: &Path| fs::read_dir(p).is_ok_and(|mut d| d.next().is_none());
+        if is_empty_dir(&from) {
+            fs::remove_dir(&from)?;
+            continue;
+        }
+        if is_empty_dir(&to) {
+            fs::remove_dir(&to)?;
+        }
+        if to.exists() {
+            ui::warn(&format!(
+                "Left {} in place: the default profile uses {}",
+                from.display(),
+                to.display()
+            ));
+            continue;
+        }
+        fs::rename(&from, &to).with_context(|| format!("Failed to move {}", from.display()))?;
+    }
+    Ok(())
+}
+
+/// Older versions wrote every setting into a new profile's config. Merged
+/// over config.toml, those defaults would undo the user's own settings, so
+/// keep only what differs from them.
+fn migrate_profile_config_defaults(dirs: &StateDirs) -> Result<()> {
+    let defaults = toml::Value::try_from(crate::cfg::Config::default())?;
+    for path in profile_configs(&dirs.profile_configs) {
+        let mut value: toml::Value = toml::from_str(&fs::read_to_string(&path)?)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if !strip_defaults(&mut value, &defaults) {
+            continue;
+        }
+        let contents = match value.as_table().is_some_and(|t| t.is_empty()) {
+            true => {
+                let name = path
+                    .parent()
+                    .and_then(|p| p.file_name())
+                    .unwrap_or_default()
+                    .to_string_lossy();
+                crate::profiles::profile_config_template(&name)
+            }
+            false => toml::to_string_pretty(&value)?,
+        };
+        fs::write(&path, contents)?;
+    }
+    Ok(())
+}
+
+/// Format 1 manifests read as format 2 without links, empty directories or
+/// xattrs, which the next snapshot records; only the version changes
+fn migrate_manifest_format(dirs: &StateDirs) -> Result<()> {
+    for path in manifest_files(&dirs.base) {
+        let mut manifest = Manifest::load(&path)?;
+        if manifest.version == crate::hash::MANIFEST_VERSION {
+            continue;
+        }
+        manifest.version = crate::hash::MANIFEST_VERSION.to_string();
+        manifest.save(&path)?;
+    }
+    Ok(())
+}
+
+/// Remove the values of `value` equal to those in `defaults`, and tables left
+/// empty by that. Returns whether anything was removed.
+fn strip_defaults(value: &mut toml::Value, defaults: &toml::Value) -> bool {
+    let (Some(table), Some(defaults)) = (value.as_table_mut(), defaults.as_table()) else {
+        return false;
+    };
+    let mut stripped = false;
+    table.retain(|key, value| {
+        let Some(default) = defaults.get(key) else {
+            return true;
+        };
+        if value == default {
+            stripped = true;
+            return false;
+        }
+        if strip_defaults(value, default) {
+            stripped = true;
+        }
+        value.as_table().is_none_or(|t| !t.is_empty())
+    });
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hash::FileHash;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_pending_migrations_run_in_order_with_backup() {
+        let temp_dir = TempDir::new().unwrap();
+        let home = temp_dir.path();
+        let base = home.join(".config/dotdipper");
+        fs::create_dir_all(&base).unwrap();
+        fs::write(
+            base.join("config.toml"),
+            format!(
+                "[general]\ntracked_files = [\"{0}/.zshrc\"]\n\n[dotfiles]\nrepo_path = \"x\"\ntracked_files = [\"{0}/.vimrc\"]\n",
+                home.display()
+            ),
+        )
+        .unwrap();
+        let mut manifest = Manifest::new();
+        manifest.version = "1.0.0".to_string();
+        manifest.add_file(FileHash {
+            path: home.join(".zshrc"),
+            hash: "h".to_string(),
+            size: 1,
+            mode: 0o644,
+            modified: chrono::Utc::now(),
+            xattrs: Default::default(),
+        });
+        manifest.save(&base.join("manifest.lock")).unwrap();
+        fs::write(base.join("bundle.tar.zst"), "old").unwrap();
+        let profile = base.join("profiles/default");
+        fs::create_dir_all(profile.join("compiled")).unwrap();
+        fs::write(profile.join("compiled/.vimrc"), "set nu").unwrap();
+        fs::copy(base.join("manifest.lock"), profile.join("manifest.lock")).unwrap();
+        let generated = crate::cfg::Config::default();
+        fs::write(
+            profile.join("config.toml"),
+            toml::to_string_pretty(&generated).unwrap(),
+        )
+        .unwrap();
+        let mut work = generated.clone();
+        work.general.default_mode = crate::cfg::RestoreMode::Copy;
+        let work_dir = base.join("profiles/work");
+        fs::create_dir_all(&work_dir).unwrap();
+        fs::write(
+            work_dir.join("config.toml"),
+            toml::to_string_pretty(&work).unwrap(),
+        )
+        .unwrap();
+        let dirs = StateDirs {
+            base: base.clone(),
+            config: base.join("config.toml"),
+            profile_configs: base.join("profiles"),
+            cache: base.join("cache"),
+            home: home.to_path_buf(),
+        };
+
+        assert_eq!(pending(&base).unwrap().len(), MIGRATIONS.len());
+        let backup = run_pending(&dirs, false).unwrap().unwrap();
+        assert!(backup.exists());
+        assert_eq!(state_version(&base).unwrap(), latest_version());
+        assert!(pending(&base).unwrap().is_empty());
+
+        let config = crate::cfg::load(&base.join("config.toml")).unwrap();
+        assert!(config.dotfiles.is_none());
+        assert_eq!(
+            config.general.tracked_files,
+            vec![home.join(".zshrc"), home.join(".vimrc")]
+        );
+        let manifest = Manifest::load(&base.join("manifest.lock")).unwrap();
+        assert!(manifest.has_file(Path::new(".zshrc")));
+        assert_eq!(manifest.version, crate::hash::MANIFEST_VERSION);
+        assert!(base.join("cache/bundle.tar.zst").exists());
+        assert!(base.join("compiled/.vimrc").exists());
+        assert!(!profile.join("compiled").exists());
+        assert!(profile.join("manifest.lock").exists());
+        assert!(fs::read_to_string(profile.join("config.toml"))
+            .unwrap()
+            .starts_with("# Settings for the 'default' profile"));
+        assert_eq!(
+            fs::read_to_string(work_dir.join("config.toml")).unwrap(),
+            "[general]\ndefault_mode = \"copy\"\n"
+        );
+
+        // Nothing left to do on a second run
+        assert!(run_pending(&dirs, false).unwrap().is_none());
+    }
+}
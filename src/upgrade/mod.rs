@@ -0,0 +1,274 @@
+//! `dotdipper self-upgrade`: replace the running binary with the latest release.
+//!
+//! The release tarball for this platform (`dotdipper-<target>.tar.gz`) and its
+//! `.sha256` file are downloaded with `curl` and the checksum is verified before
+//! anything on disk changes. The new binary is written next to the current one
+//! and renamed over it, keeping the old binary as `<name>.old`. Finally the new
+//! binary runs `dotdipper migrate` to bring existing state up to date (see
+//! [`migrations`]).
+//!
+//! `DOTDIPPER_RELEASES_URL` overrides where release assets are downloaded from.
+
+pub mod layout;
+pub mod migrations;
+
+use anyhow::{bail, Context, Result};
+use flate2::read::GzDecoder;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::ui;
+
+const LATEST_RELEASE_API: &str = "https://api.github.com/repos/psyysp/dotdipper/releases/latest";
+const DEFAULT_RELEASES_URL: &str = "https://github.com/psyysp/dotdipper/releases/download";
+const RELEASES_URL_ENV: &str = "DOTDIPPER_RELEASES_URL";
+const BINARY_NAME: &str = "dotdipper";
+
+pub struct UpgradeOptions {
+    /// Only report whether a newer release exists
+    pub check: bool,
+    /// Install this release instead of the latest one
+    pub version: Option<String>,
+    /// Install even if the release is not newer than the running version
+    pub force: bool,
+    /// Binary to replace (defaults to the running executable)
+    pub path: Option<PathBuf>,
+}
+
+/// Release target triple of this build
+pub fn target() -> Result<&'static str> {
+    let target = match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("linux", "x86_64") => "x86_64-unknown-linux-gnu",
+        ("linux", "aarch64") => "aarch64-unknown-linux-gnu",
+        ("macos", "x86_64") => "x86_64-apple-darwin",
+        ("macos", "aarch64") => "aarch64-apple-darwin",
+        (os, arch) => bail!("No prebuilt releases for {}-{}", arch, os),
+    };
+    Ok(target)
+}
+
+/// Parse `v1.2.3` / `1.2.3` into comparable parts
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.trim().trim_start_matches('v');
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(|p| p.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    Some((major, minor, patch))
+}
+
+pub fn is_newer(candidate: &str, current: &str) -> bool {
+    match (parse_version(candidate), parse_version(current)) {
+        (Some(candidate), Some(current)) => candidate > current,
+        _ => false,
+    }
+}
+
+/// Release tag for a version string (`1.2.3` -> `v1.2.3`)
+fn tag(version: &str) -> String {
+    format!("v{}", version.trim().trim_start_matches('v'))
+}
+
+fn curl(url: &str) -> Command {
+    let mut cmd = Command::new("curl");
+    cmd.args(["-fsSL", "--retry", "2", url]);
+    cmd
+}
+
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    let output = curl(url)
+        .output()
+        .context("Failed to run curl. Is it installed?")?;
+    if !output.status.success() {
+        bail!(
+            "Download failed: {}\n{}",
+            url,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(output.stdout)
+}
+
+/// Tag of the latest published release
+fn latest_tag() -> Result<String> {
+    let body = fetch(LATEST_RELEASE_API).context("Failed to look up the latest release")?;
+    let release: serde_json::Value =
+        serde_json::from_slice(&body).context("Unexpected response from the releases API")?;
+    release["tag_name"]
+        .as_str()
+        .map(str::to_string)
+        .context("Latest release has no tag_name")
+}
+
+/// Base URL of release downloads, `DOTDIPPER_RELEASES_URL` if set
+pub fn releases_url() -> String {
+    std::env::var(RELEASES_URL_ENV)
+        .unwrap_or_else(|_| DEFAULT_RELEASES_URL.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Check `data` against a `shasum -a 256` style checksum file
+pub fn verify_checksum(data: &[u8], checksum_file: &str) -> Result<()> {
+    let expected = checksum_file
+        .split_whitespace()
+        .next()
+        .context("Checksum file is empty")?
+        .to_lowercase();
+    let actual = format!("{:x}", Sha256::digest(data));
+    if actual != expected {
+        bail!(
+            "Checksum mismatch: expected {}, got {}. Refusing to install.",
+            expected,
+            actual
+        );
+    }
+    Ok(())
+}
+
+/// Pull the `dotdipper` binary out of a release tarball
+fn extract_binary(tarball: &[u8]) -> Result<Vec<u8>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(tarball));
+    for entry in archive.entries().context("Invalid release archive")? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if entry.header().entry_type().is_file()
+            && path.file_name().and_then(|n| n.to_str()) == Some(BINARY_NAME)
+        {
+            let mut binary = Vec::new();
+            entry.read_to_end(&mut binary)?;
+            return Ok(binary);
+        }
+    }
+    bail!("Release archive does not contain a {} binary", BINARY_NAME)
+}
+
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+/// Swap `binary` in at `target`, keeping the previous binary as `<target>.old`.
+///
+/// The new binary is staged in the same directory so the final rename is
+/// atomic: `target` is always either the old or the new binary.
+fn replace_binary(target: &Path, binary: &[u8]) -> Result<PathBuf> {
+    let staged = sibling(target, ".new");
+    let old = sibling(target, ".old");
+
+    fs::write(&staged, binary).with_context(|| format!("Failed to write {}", staged.display()))?;
+    fs::set_permissions(&staged, fs::Permissions::from_mode(0o755))?;
+
+    // Make sure the download actually runs here before swapping it in
+    let runs = Command::new(&staged)
+        .arg("--version")
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false);
+    if !runs {
+        let _ = fs::remove_file(&staged);
+        bail!("Downloaded binary does not run on this machine; keeping the current one");
+    }
+
+    if target.exists() {
+        fs::copy(target, &old)
+            .with_context(|| format!("Failed to back up {}", target.display()))?;
+    }
+    fs::rename(&staged, target)
+        .with_context(|| format!("Failed to replace {}", target.display()))?;
+    Ok(old)
+}
+
+/// `dotdipper self-upgrade`
+pub fn self_upgrade(config_path: &Path, opts: UpgradeOptions) -> Result<()> {
+    let current = env!("CARGO_PKG_VERSION");
+    let target = target()?;
+    let tag = match &opts.version {
+        Some(version) => tag(version),
+        None => tag(&latest_tag()?),
+    };
+    let newer = is_newer(&tag, current);
+
+    if opts.check {
+        if newer {
+            ui::info(&format!(
+                "dotdipper {} is available (running {})",
+                tag, current
+            ));
+            ui::hint("Run 'dotdipper self-upgrade' to install it");
+        } else {
+            ui::success(&format!("dotdipper {} is up to date", current));
+        }
+        return Ok(());
+    }
+    if !newer && !opts.force {
+        ui::success(&format!(
+            "dotdipper {} is up to date ({} is not newer)",
+            current, tag
+        ));
+        ui::hint("Use --force to reinstall anyway");
+        return Ok(());
+    }
+
+    let binary_path = match opts.path {
+        Some(path) => path,
+        None => std::env::current_exe().context("Failed to locate the running executable")?,
+    };
+
+    let asset = format!("{}-{}.tar.gz", BINARY_NAME, target);
+    let url = format!("{}/{}/{}", releases_url(), tag, asset);
+    ui::info(&format!("Downloading {}", url));
+    let tarball = fetch(&url)?;
+    let checksum = fetch(&format!("{}.sha256", url)).context("Failed to download checksum")?;
+    verify_checksum(&tarball, &String::from_utf8_lossy(&checksum))?;
+    ui::info("Checksum verified");
+
+    let binary = extract_binary(&tarball)?;
+    let old = replace_binary(&binary_path, &binary)?;
+    ui::success(&format!(
+        "Upgraded {} from {} to {}",
+        binary_path.display(),
+        current,
+        tag
+    ));
+    ui::hint(&format!("Previous binary kept at {}", old.display()));
+
+    // The new binary knows about migrations this one may not
+    let migrated = Command::new(&binary_path)
+        .arg("--config")
+        .arg(config_path)
+        .arg("migrate")
+        .status()
+        .map(|s| s.success())
+        .unwrap_or(false);
+    if !migrated {
+        ui::warn("State migrations did not complete");
+        ui::hint("Run 'dotdipper migrate' to retry; backups are kept in the backups directory");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_versions_and_checksums() {
+        assert!(is_newer("v0.8.0", "0.7.3"));
+        assert!(is_newer("1.0", "0.7.3"));
+        assert!(!is_newer("v0.7.3", "0.7.3"));
+        assert!(!is_newer("v0.7.2", "0.7.3"));
+        assert!(!is_newer("nightly", "0.7.3"));
+
+        let data = b"dotdipper";
+        let sum = format!("{:x}", Sha256::digest(data));
+        assert!(verify_checksum(data, &format!("{}  dotdipper.tar.gz\n", sum)).is_ok());
+        assert!(verify_checksum(b"tampered", &sum).is_err());
+    }
+}
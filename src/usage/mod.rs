@@ -0,0 +1,258 @@
+//! Disk usage accounting for dotdipper's own state.
+//!
+//! Breaks usage down by subsystem (compiled, snapshots, profiles, cache,
+//! install scripts, trash, backups) for `dotdipper du`, and checks the total against
+//! `[general] max_state_size` after snapshots and backups are created.
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::cfg::Config;
+use crate::ui;
+
+/// Warn once usage passes this fraction of the quota
+const WARN_RATIO: f64 = 0.9;
+
+#[derive(Debug, Clone)]
+pub struct UsageEntry {
+    pub name: &'static str,
+    pub path: PathBuf,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UsageReport {
+    pub entries: Vec<UsageEntry>,
+}
+
+impl UsageReport {
+    pub fn total(&self) -> u64 {
+        self.entries.iter().map(|e| e.bytes).sum()
+    }
+
+    pub fn get(&self, name: &str) -> u64 {
+        self.entries
+            .iter()
+            .find(|e| e.name == name)
+            .map(|e| e.bytes)
+            .unwrap_or(0)
+    }
+}
+
+/// Parse a size like "5GB", "500MB", "1.5G" or "1024" into bytes (1KB = 1024 bytes)
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: f64 = num.parse().ok()?;
+
+    let multiplier: u64 = match unit.trim().to_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return None,
+    };
+
+    Some((num * multiplier as f64) as u64)
+}
+
+pub fn format_size(bytes: u64) -> String {
+    humansize::format_size(bytes, humansize::BINARY)
+}
+
+/// Total size of the regular files below `path` (symlinks are not followed)
+pub fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .follow_links(false)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Measure dotdipper's state directories plus apply backups, wherever they are
+pub fn measure(config: &Config) -> Result<UsageReport> {
+    let base = crate::paths::data_dir()?;
+    let subsystems: [(&'static str, PathBuf); 6] = [
+        ("compiled", crate::paths::compiled_dir()?),
+        ("snapshots", crate::paths::snapshots_dir()?),
+        ("profiles", crate::paths::profiles_dir()?),
+        ("cache", crate::paths::cache_dir()?),
+        ("install", crate::paths::install_dir()?),
+        ("trash", crate::paths::trash_dir()?),
+    ];
+
+    let mut report = UsageReport::default();
+    let mut accounted = 0;
+    for (name, path) in subsystems {
+        let bytes = dir_size(&path);
+        // The cache is only inside the data directory with DOTDIPPER_HOME
+        if path.starts_with(&base) {
+            accounted += bytes;
+        }
+        report.entries.push(UsageEntry { name, path, bytes });
+    }
+
+    // Relocated backups are counted below with the rest
+    accounted += dir_size(&crate::paths::backups_dir()?);
+    report.entries.push(UsageEntry {
+        name: "other",
+        path: base.clone(),
+        bytes: dir_size(&base).saturating_sub(accounted),
+    });
+
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let backups = crate::backups::list(config)?;
+    report.entries.push(UsageEntry {
+        name: "backups",
+        path: home,
+        bytes: backups.iter().map(|b| dir_size(&b.path)).sum(),
+    });
+
+    Ok(report)
+}
+
+/// Configured quota in bytes, if any
+pub fn max_state_size(config: &Config) -> Result<Option<u64>> {
+    match &config.general.max_state_size {
+        None => Ok(None),
+        Some(s) => match parse_size(s) {
+            Some(bytes) => Ok(Some(bytes)),
+            None => bail!(
+                "Invalid max_state_size '{}'. Use a size like \"5GB\" or \"500MB\"",
+                s
+            ),
+        },
+    }
+}
+
+/// Warn when usage is near or over `max_state_size`, with prune suggestions.
+///
+/// Never fails the calling command; problems are reported as warnings.
+pub fn check_quota(config: &Config) {
+    let limit = match max_state_size(config) {
+        Ok(Some(limit)) => limit,
+        Ok(None) => return,
+        Err(e) => {
+            ui::warn(&format!("{:#}", e));
+            return;
+        }
+    };
+    let report = match measure(config) {
+        Ok(report) => report,
+        Err(e) => {
+            ui::debug(&format!("Could not measure disk usage: {:#}", e));
+            return;
+        }
+    };
+
+    let total = report.total();
+    if (total as f64) < limit as f64 * WARN_RATIO {
+        return;
+    }
+
+    let verb = if total > limit {
+        "exceeds"
+    } else {
+        "is nearing"
+    };
+    ui::warn(&format!(
+        "dotdipper state uses {} and {} max_state_size ({})",
+        format_size(total),
+        verb,
+        format_size(limit)
+    ));
+    for suggestion in suggestions(&report) {
+        ui::hint(&suggestion);
+    }
+}
+
+fn suggestions(report: &UsageReport) -> Vec<String> {
+    let mut suggestions = Vec::new();
+    if report.get("snapshots") > 0 {
+        suggestions.push(format!(
+            "Prune snapshots ({}): dotdipper snapshot prune --keep-count 10",
+            format_size(report.get("snapshots"))
+        ));
+    }
+    if report.get("backups") > 0 {
+        suggestions.push(format!(
+            "Remove old apply backups ({}): dotdipper backups prune --keep-age 30d",
+            format_size(report.get("backups"))
+        ));
+    }
+    if report.get("trash") > 0 {
+        suggestions.push(format!(
+            "Empty the trash ({}): dotdipper trash empty --older-than 30d",
+            format_size(report.get("trash"))
+        ));
+    }
+    if report.get("cache") > 0 {
+        suggestions.push(format!(
+            "Clear the cache ({}): rm -rf {}",
+            format_size(report.get("cache")),
+            report
+                .entries
+                .iter()
+                .find(|e| e.name == "cache")
+                .map(|e| e.path.display().to_string())
+                .unwrap_or_default()
+        ));
+    }
+    suggestions.push("Run 'dotdipper du' for a full breakdown".to_string());
+    suggestions
+}
+
+/// Print the usage breakdown for `dotdipper du`
+pub fn print_report(config: &Config, report: &UsageReport) -> Result<()> {
+    ui::section("Disk usage");
+
+    let rows = report
+        .entries
+        .iter()
+        .map(|e| {
+            vec![
+                e.name.to_string(),
+                format_size(e.bytes),
+                e.path.display().to_string(),
+            ]
+        })
+        .collect();
+    ui::print_table(&["Subsystem", "Size", "Location"], rows);
+
+    let total = report.total();
+    match max_state_size(config)? {
+        Some(limit) => ui::info(&format!(
+            "Total: {} of {} max_state_size ({:.0}%)",
+            format_size(total),
+            format_size(limit),
+            total as f64 * 100.0 / limit.max(1) as f64
+        )),
+        None => ui::info(&format!("Total: {}", format_size(total))),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("500MB"), Some(500 * 1024 * 1024));
+        assert_eq!(parse_size("5GB"), Some(5 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5g"), Some(1536 * 1024 * 1024));
+        assert_eq!(parse_size("10 KB"), Some(10 * 1024));
+        assert_eq!(parse_size("lots"), None);
+        assert_eq!(parse_size("5XB"), None);
+    }
+}
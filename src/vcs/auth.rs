@@ -0,0 +1,152 @@
+//! Credentials for talking to git remotes without the git binary.
+//!
+//! SSH remotes try the SSH agent first, then the usual key files in
+//! `~/.ssh`. HTTPS remotes use a token from `DOTDIPPER_GIT_TOKEN`, then
+//! git's configured credential helper. `GITHUB_TOKEN` and `GH_TOKEN` are
+//! only offered to github.com, so they never reach another host.
+//! Every method is tried once, so a rejected key fails instead of looping.
+
+use git2::{Cred, CredentialType, RemoteCallbacks};
+use std::path::PathBuf;
+
+/// Environment variable a HTTPS token for any host is read from
+const TOKEN_VAR: &str = "DOTDIPPER_GIT_TOKEN";
+
+/// Environment variables holding a GitHub token, in order
+const GITHUB_TOKEN_VARS: &[&str] = &["GITHUB_TOKEN", "GH_TOKEN"];
+
+/// Key files tried after the agent, in order
+const KEY_FILES: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// Host of a remote URL, such as `github.com` for
+/// `https://user@github.com:443/alice/dots.git`
+fn host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://")?.1;
+    let authority = rest.split('/').next()?;
+    let host = authority.rsplit('@').next()?;
+    Some(host.split(':').next().unwrap_or(host))
+}
+
+/// The token to offer `url`
+fn token(url: &str) -> Option<String> {
+    let github = host(url).is_some_and(|host| host.eq_ignore_ascii_case("github.com"));
+    std::iter::once(&TOKEN_VAR)
+        .chain(GITHUB_TOKEN_VARS.iter().filter(|_| github))
+        .filter_map(|var| std::env::var(var).ok())
+        .map(|token| token.trim().to_string())
+        .find(|token| !token.is_empty())
+}
+
+fn key_files() -> Vec<PathBuf> {
+    let Some(ssh_dir) = dirs::home_dir().map(|home| home.join(".ssh")) else {
+        return Vec::new();
+    };
+    KEY_FILES
+        .iter()
+        .map(|name| ssh_dir.join(name))
+        .filter(|path| path.exists())
+        .rev()
+        .collect()
+}
+
+/// Callbacks that answer credential requests for fetch, push and clone
+pub(super) fn callbacks<'a>() -> RemoteCallbacks<'a> {
+    let mut tried_agent = false;
+    let mut keys: Option<Vec<PathBuf>> = None;
+    let mut tried_token = false;
+    let mut tried_helper = false;
+    let mut tried_default = false;
+
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        let user = username.unwrap_or("git");
+        if allowed.contains(CredentialType::USERNAME) {
+            return Cred::username(user);
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            if !tried_agent {
+                tried_agent = true;
+                if std::env::var_os("SSH_AUTH_SOCK").is_some() {
+                    return Cred::ssh_key_from_agent(user);
+                }
+            }
+            if let Some(key) = keys.get_or_insert_with(key_files).pop() {
+                let public = key.with_extension("pub");
+                let passphrase = std::env::var("DOTDIPPER_SSH_PASSPHRASE").ok();
+                return Cred::ssh_key(
+                    user,
+                    public.exists().then_some(public.as_path()),
+                    &key,
+                    passphrase.as_deref(),
+                );
+            }
+        }
+
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            if !tried_token {
+                tried_token = true;
+                if let Some(token) = token(url) {
+                    return Cred::userpass_plaintext(username.unwrap_or("x-access-token"), &token);
+                }
+            }
+            if !tried_helper {
+                tried_helper = true;
+                if let Ok(config) = git2::Config::open_default() {
+                    if let Ok(cred) = Cred::credential_helper(&config, url, username) {
+                        return Ok(cred);
+                    }
+                }
+            }
+        }
+
+        if allowed.contains(CredentialType::DEFAULT) && !tried_default {
+            tried_default = true;
+            return Cred::default();
+        }
+
+        Err(git2::Error::from_str(&format!(
+            "authentication failed for {}: no SSH agent key, key file in ~/.ssh or token \
+             (DOTDIPPER_GIT_TOKEN, or GITHUB_TOKEN/GH_TOKEN for github.com) was accepted",
+            url
+        )))
+    });
+    callbacks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_github_tokens_are_only_offered_to_github() {
+        assert_eq!(
+            host("https://github.com/alice/dots.git"),
+            Some("github.com")
+        );
+        assert_eq!(
+            host("https://x-access-token@GitHub.com:443/alice/dots.git"),
+            Some("GitHub.com")
+        );
+        assert_eq!(
+            host("https://git.example.com/dots.git"),
+            Some("git.example.com")
+        );
+        assert_eq!(
+            host("https://github.com.evil.example/dots.git"),
+            Some("github.com.evil.example")
+        );
+        assert_eq!(host("git@github.com:alice/dots.git"), None);
+
+        for var in std::iter::once(&TOKEN_VAR).chain(GITHUB_TOKEN_VARS) {
+            std::env::remove_var(var);
+        }
+        std::env::set_var("GH_TOKEN", "gh-secret");
+        assert_eq!(
+            token("https://github.com/alice/dots.git").as_deref(),
+            Some("gh-secret")
+        );
+        assert_eq!(token("https://git.example.com/dots.git"), None);
+        std::env::remove_var("GH_TOKEN");
+    }
+}
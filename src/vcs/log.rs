@@ -0,0 +1,327 @@
+//! Per-file change log of the compiled repository's git history, and
+//! restoring a single file from an older commit.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset, TimeZone};
+use git2::{Commit, Delta, DiffFindOptions, Sort};
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::Config;
+use crate::history::{self, Kind};
+use crate::matching::{display_tilde, expand_tilde, to_home_relative};
+use crate::ui;
+
+/// A commit of the compiled repository
+#[derive(Debug, Clone, Serialize)]
+pub struct Change {
+    pub commit: String,
+    pub time: DateTime<FixedOffset>,
+    pub message: String,
+    /// Changed files, relative to the home directory
+    pub files: Vec<PathBuf>,
+    /// Snapshot taken on this machine before the push that made the commit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+}
+
+impl Change {
+    pub fn short(&self) -> &str {
+        &self.commit[..self.commit.len().min(12)]
+    }
+}
+
+/// Commits of the repository at `repo_path`, newest first, optionally only
+/// the ones touching `path` (following renames)
+pub fn changes(repo_path: &Path, path: Option<&Path>, limit: usize) -> Result<Vec<Change>> {
+    if !repo_path.join(".git").exists() {
+        bail!(
+            "No git history yet in {}; run 'dotdipper push' first",
+            repo_path.display()
+        );
+    }
+    let repo = super::open(repo_path)?;
+    // A repository without commits has no history to show
+    if super::head_commit(&repo)?.is_none() {
+        return Ok(Vec::new());
+    }
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+    walk.push_head()?;
+
+    let mut path = path.map(Path::to_path_buf);
+    let mut changes = Vec::new();
+    for id in walk {
+        if changes.len() >= limit {
+            break;
+        }
+        let commit = repo.find_commit(id?)?;
+        // Like git log, merges list no files and don't match paths
+        if commit.parent_count() > 1 {
+            if path.is_none() {
+                changes.push(change(&commit, Vec::new())?);
+            }
+            continue;
+        }
+
+        let parent_tree = commit.parents().next().map(|p| p.tree()).transpose()?;
+        let mut diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&commit.tree()?), None)?;
+        let files = match path.clone() {
+            None => diff
+                .deltas()
+                .filter_map(|d| match d.status() {
+                    Delta::Deleted => d.old_file().path(),
+                    _ => d.new_file().path(),
+                })
+                .map(Path::to_path_buf)
+                .collect(),
+            Some(current) => {
+                diff.find_similar(Some(DiffFindOptions::new().renames(true)))?;
+                let Some(delta) = diff.deltas().find(|d| {
+                    d.new_file().path() == Some(&current)
+                        || (d.status() == Delta::Deleted && d.old_file().path() == Some(&current))
+                }) else {
+                    continue;
+                };
+                // Older commits know the file by its previous name
+                if delta.status() == Delta::Renamed {
+                    path = delta.old_file().path().map(Path::to_path_buf);
+                }
+                vec![current]
+            }
+        };
+        changes.push(change(&commit, files)?);
+    }
+
+    annotate_snapshots(&mut changes);
+    Ok(changes)
+}
+
+fn change(commit: &Commit, files: Vec<PathBuf>) -> Result<Change> {
+    let when = commit.author().when();
+    let time = FixedOffset::east_opt(when.offset_minutes() * 60)
+        .and_then(|offset| offset.timestamp_opt(when.seconds(), 0).single())
+        .context("Commit has an invalid timestamp")?;
+    Ok(Change {
+        commit: commit.id().to_string(),
+        time,
+        message: commit.summary().unwrap_or_default().to_string(),
+        files,
+        snapshot: None,
+    })
+}
+
+/// Link commits pushed from this machine to the snapshot taken before the push
+fn annotate_snapshots(changes: &mut [Change]) {
+    let Ok(entries) = history::load() else {
+        return;
+    };
+    for change in changes.iter_mut() {
+        let Some(push) = entries.iter().find(|e| {
+            e.kind == Kind::Push
+                && e.reference
+                    .as_deref()
+                    .is_some_and(|r| change.commit.starts_with(r))
+        }) else {
+            continue;
+        };
+        change.snapshot = entries
+            .iter()
+            .rfind(|e| e.kind == Kind::Snapshot && e.time <= push.time)
+            .and_then(|e| e.reference.clone());
+    }
+}
+
+/// `dotdipper log [path]`
+pub fn print(changes: &[Change], path: Option<&Path>) -> Result<()> {
+    if ui::is_json() {
+        return ui::print_json(&changes);
+    }
+    if changes.is_empty() {
+        match path {
+            Some(path) => ui::info(&format!("No commits touch {}", display_tilde(path))),
+            None => ui::info("No commits yet"),
+        }
+        return Ok(());
+    }
+
+    let rows = changes
+        .iter()
+        .map(|c| {
+            let mut row = vec![
+                c.short().to_string(),
+                c.time
+                    .with_timezone(&chrono::Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string(),
+            ];
+            if path.is_none() {
+                row.push(c.files.len().to_string());
+            }
+            row.push(c.message.clone());
+            row.push(c.snapshot.clone().unwrap_or_default());
+            row
+        })
+        .collect();
+    if path.is_some() {
+        ui::print_table(&["COMMIT", "DATE", "MESSAGE", "SNAPSHOT"], rows);
+        ui::hint("Recover a version with 'dotdipper restore <path> --rev <commit>'");
+    } else {
+        ui::print_table(&["COMMIT", "DATE", "FILES", "MESSAGE", "SNAPSHOT"], rows);
+    }
+    Ok(())
+}
+
+/// Manifest path of a file given as `~/...`, absolute or relative to the
+/// current directory: home-relative, or under `@root/` outside `$HOME`
+pub fn resolve_path(selector: &str, home: &Path) -> Result<PathBuf> {
+    if crate::system::is_system(Path::new(selector)) {
+        return Ok(PathBuf::from(selector));
+    }
+    let path = expand_tilde(selector, home);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    let rel = to_home_relative(&path, home);
+    if rel.is_absolute() {
+        return Ok(crate::system::manifest_path(&rel, home));
+    }
+    Ok(rel)
+}
+
+#[derive(Debug, Clone)]
+pub struct RestoreOpts {
+    pub rev: String,
+    /// Restore without confirming
+    pub force: bool,
+    /// Print the old version instead of writing it
+    pub stdout: bool,
+}
+
+/// `dotdipper restore <path> --rev <commit>`: put the version of one file
+/// committed in `rev` back in place, backing up the current file
+pub fn restore(config: &Config, selector: &str, opts: &RestoreOpts) -> Result<()> {
+    let home = dirs::home_dir().context("Failed to find home directory")?;
+    let rel = resolve_path(selector, &home)?;
+    let repo_path = crate::paths::compiled_dir()?;
+    let contents = super::file_at(&repo_path, &opts.rev, &rel)?;
+
+    if opts.stdout {
+        std::io::stdout().write_all(&contents)?;
+        return Ok(());
+    }
+
+    let target = crate::system::target(&home, &rel);
+    if let Some(reason) = crate::system::check_target(config, &rel, &target, &home) {
+        bail!("Can't restore {}: {}", target.display(), reason);
+    }
+    if !target.is_symlink() && fs::read(&target).is_ok_and(|current| current == contents) {
+        ui::info(&format!(
+            "{} already matches {}",
+            display_tilde(&rel),
+            opts.rev
+        ));
+        return Ok(());
+    }
+    if !opts.force
+        && !ui::prompt_confirm(
+            &format!("Restore {} from {}?", display_tilde(&rel), opts.rev),
+            false,
+        )
+    {
+        ui::info("Restore cancelled");
+        return Ok(());
+    }
+
+    if target.is_symlink() {
+        fs::remove_file(&target)?;
+    } else if target.exists() {
+        let backup = crate::backups::path_for(config, &target)?;
+        fs::rename(&target, &backup)
+            .with_context(|| format!("Failed to back up {}", target.display()))?;
+        crate::backups::register(&target, &backup)?;
+        ui::info(&format!("Backed up to {}", backup.display()));
+    } else if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&target, contents)
+        .with_context(|| format!("Failed to write {}", target.display()))?;
+
+    ui::success(&format!(
+        "Restored {} from {}",
+        display_tilde(&rel),
+        opts.rev
+    ));
+    ui::hint("Run 'dotdipper snapshot create' to keep this version");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_path_maps_files_outside_home_to_root() {
+        let home = Path::new("/home/me");
+        assert_eq!(
+            resolve_path("~/.zshrc", home).unwrap(),
+            PathBuf::from(".zshrc")
+        );
+        assert_eq!(
+            resolve_path("/etc/hosts", home).unwrap(),
+            PathBuf::from("@root/etc/hosts")
+        );
+        assert_eq!(
+            resolve_path("@root/etc/hosts", home).unwrap(),
+            PathBuf::from("@root/etc/hosts")
+        );
+    }
+
+    #[test]
+    fn test_changes_follow_renames() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        let commit = |files: &[(&str, &str)], message: &str| {
+            let mut index = repo.index().unwrap();
+            index.clear().unwrap();
+            for (name, contents) in files {
+                let path = dir.path().join(name);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(&path, contents).unwrap();
+                index.add_path(Path::new(name)).unwrap();
+            }
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let parent = repo.head().ok().map(|h| h.peel_to_commit().unwrap());
+            let parents: Vec<_> = parent.iter().collect();
+            repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)
+                .unwrap();
+        };
+        let zshrc = "export EDITOR=vim\nalias ll='ls -l'\n";
+        commit(&[(".zshrc", zshrc), (".vimrc", "set nu\n")], "Initial");
+        commit(
+            &[(".zsh/zshrc", zshrc), (".vimrc", "set nu\n")],
+            "Move zshrc",
+        );
+        commit(
+            &[(".zsh/zshrc", zshrc), (".vimrc", "set rnu\n")],
+            "Vim only",
+        );
+
+        let all = changes(dir.path(), None, 20).unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].message, "Vim only");
+        assert_eq!(all[0].files, vec![PathBuf::from(".vimrc")]);
+
+        let zsh = changes(dir.path(), Some(Path::new(".zsh/zshrc")), 20).unwrap();
+        let messages: Vec<_> = zsh.iter().map(|c| c.message.as_str()).collect();
+        assert_eq!(messages, ["Move zshrc", "Initial"]);
+        assert_eq!(zsh[1].files, vec![PathBuf::from(".zshrc")]);
+
+        assert_eq!(changes(dir.path(), None, 1).unwrap().len(), 1);
+    }
+}
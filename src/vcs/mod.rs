@@ -1,8 +1,23 @@
-use anyhow::{Context, Result};
-use std::path::Path;
+mod auth;
+pub mod log;
+mod sign;
+
+use anyhow::{bail, Context, Result};
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{
+    BranchType, Commit, ErrorCode, FetchOptions, IndexAddOption, IndexEntry, IndexTime,
+    MergeFileOptions, ObjectType, Oid, ProxyOptions, PushOptions, Repository,
+    RepositoryInitOptions, RepositoryOpenFlags, ResetType, Signature, Sort, StatusOptions, Tree,
+    TreeWalkMode, TreeWalkResult,
+};
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
-use crate::cfg::Config;
+use crate::cfg::{Config, GitProtocol};
+use crate::offline::RetryPolicy;
 use crate::ui;
 
 const BASE_GITIGNORE: &str = r#"# Temporary files
@@ -20,6 +35,80 @@ Thumbs.db
 *.backup
 "#;
 
+/// Branch used when `[github] branch` is unset, and by collections
+const DEFAULT_BRANCH: &str = "main";
+
+fn refspec(branch: &str) -> String {
+    format!("refs/heads/{0}:refs/heads/{0}", branch)
+}
+
+fn tracking_ref(branch: &str) -> String {
+    format!("refs/remotes/origin/{}", branch)
+}
+
+/// Branch `push` and `pull` sync (`[github] branch`, default `main`)
+pub fn branch(config: &Config) -> String {
+    config
+        .github
+        .branch
+        .as_deref()
+        .map(str::trim)
+        .filter(|b| !b.is_empty())
+        .unwrap_or(DEFAULT_BRANCH)
+        .to_string()
+}
+
+/// Remote-tracking ref of [`branch`], e.g. `origin/main`
+pub fn remote_ref(config: &Config) -> String {
+    format!("origin/{}", branch(config))
+}
+
+/// Clone URL of the GitHub repository over `protocol`
+pub fn github_url(username: &str, repo_name: &str, protocol: GitProtocol) -> String {
+    match protocol {
+        GitProtocol::Ssh => format!("git@github.com:{}/{}.git", username, repo_name),
+        GitProtocol::Https => format!("https://github.com/{}/{}.git", username, repo_name),
+    }
+}
+
+/// The remote `push` and `pull` sync with
+struct Target {
+    url: String,
+    branch: String,
+    /// GitHub owner and repository name, unless `remote_url` points elsewhere
+    github: Option<(String, String)>,
+}
+
+fn target(config: &Config, repo_override: Option<&str>) -> Result<Target> {
+    let branch = branch(config);
+    if let Some(url) = config
+        .github
+        .remote_url
+        .as_deref()
+        .map(str::trim)
+        .filter(|u| !u.is_empty())
+    {
+        if repo_override.is_some() {
+            ui::warn("Ignoring --repo: [github] remote_url is set");
+        }
+        return Ok(Target {
+            url: url.to_string(),
+            branch,
+            github: None,
+        });
+    }
+
+    let repo_name = resolve_repo_name(config, repo_override);
+    let username = resolve_github_username(config)?;
+    Ok(Target {
+        url: github_url(&username, &repo_name, config.github.protocol),
+        branch,
+        github: Some((username, repo_name)),
+    })
+}
+
+/// The git binary is only needed for rendering diffs; repositories are read
+/// and synced with libgit2
 pub fn check_git() -> Result<()> {
     let output = Command::new("git")
         .arg("--version")
@@ -46,316 +135,860 @@ pub fn check_gh() -> Result<()> {
     Ok(())
 }
 
-pub fn init_repo(repo_path: &Path) -> Result<()> {
+/// Open the repository at `repo_path`, honoring the environment git does
+/// (`GIT_CONFIG_GLOBAL`, `GIT_CEILING_DIRECTORIES`, ...)
+fn open(repo_path: &Path) -> Result<Repository> {
+    Repository::open_ext(
+        repo_path,
+        RepositoryOpenFlags::FROM_ENV,
+        std::iter::empty::<&OsStr>(),
+    )
+    .with_context(|| format!("{} is not a git repository", repo_path.display()))
+}
+
+/// Error of a git2 call without its class and code suffix
+fn git_err(e: git2::Error) -> anyhow::Error {
+    anyhow::anyhow!("{}", e.message())
+}
+
+fn commit_at<'r>(repo: &'r Repository, rev: &str) -> Result<Commit<'r>> {
+    repo.revparse_single(rev)
+        .and_then(|object| object.peel_to_commit())
+        .map_err(|e| anyhow::anyhow!("Unknown revision {}: {}", rev, e.message()))
+}
+
+/// The commit HEAD points at, or None before the first commit
+fn head_commit(repo: &Repository) -> Result<Option<Commit<'_>>> {
+    match repo.head() {
+        Ok(head) => Ok(Some(head.peel_to_commit()?)),
+        Err(e) if matches!(e.code(), ErrorCode::UnbornBranch | ErrorCode::NotFound) => Ok(None),
+        Err(e) => Err(git_err(e)),
+    }
+}
+
+/// Author and committer the way git picks them: `GIT_AUTHOR_*` and
+/// `GIT_COMMITTER_*`, then `user.name`/`user.email`, then a dotdipper identity
+fn signatures(repo: &Repository) -> Result<(Signature<'static>, Signature<'static>)> {
+    let config = repo.config()?;
+    let get = |var: &str, key: &str, fallback: &str| {
+        std::env::var(var)
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+            .or_else(|| config.get_string(key).ok())
+            .unwrap_or_else(|| fallback.to_string())
+    };
+    let author = Signature::now(
+        &get("GIT_AUTHOR_NAME", "user.name", "dotdipper"),
+        &get("GIT_AUTHOR_EMAIL", "user.email", "dotdipper@localhost"),
+    )?;
+    let committer = Signature::now(
+        &get("GIT_COMMITTER_NAME", "user.name", "dotdipper"),
+        &get("GIT_COMMITTER_EMAIL", "user.email", "dotdipper@localhost"),
+    )?;
+    Ok((author, committer))
+}
+
+/// Directory git runs hooks from for the repository at `repo_path`
+/// (honors `core.hooksPath` and linked worktrees)
+pub fn hooks_dir(repo_path: &Path) -> Result<PathBuf> {
+    let repo = open(repo_path)?;
+    if let Ok(path) = repo.config()?.get_path("core.hooksPath") {
+        // Relative paths are relative to the working tree, like in git
+        return Ok(repo.workdir().unwrap_or(repo.path()).join(path));
+    }
+    Ok(repo.commondir().join("hooks"))
+}
+
+pub fn init_repo(repo_path: &Path, branch: &str) -> Result<()> {
     if repo_path.join(".git").exists() {
         return Ok(());
     }
 
-    let output = Command::new("git")
-        .args(["init", "-b", "main"])
-        .current_dir(repo_path)
-        .output()
+    let mut opts = RepositoryInitOptions::new();
+    opts.initial_head(branch);
+    Repository::init_opts(repo_path, &opts)
+        .map_err(git_err)
         .context("Failed to initialize git repository")?;
 
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to initialize git repository: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
-
     std::fs::write(repo_path.join(".gitignore"), BASE_GITIGNORE)?;
 
     Ok(())
 }
 
-pub fn push(
-    config: &Config,
-    message: Option<String>,
-    force: bool,
-    repo_override: Option<&str>,
-) -> Result<String> {
-    let repo_path = crate::paths::compiled_dir()?;
-    let repo_name = resolve_repo_name(config, repo_override);
-    let username = resolve_github_username(config)?;
+/// Create a commit and point HEAD (or the branch it is on) at it, signing
+/// it when the repository's git config asks for signed commits
+fn create_commit(
+    repo: &Repository,
+    author: &Signature,
+    committer: &Signature,
+    message: &str,
+    tree: &Tree,
+    parents: &[&Commit],
+) -> Result<Oid> {
+    let buffer = repo
+        .commit_create_buffer(author, committer, message, tree, parents)
+        .map_err(git_err)?;
+    let content = buffer.as_str().context("Commit is not valid UTF-8")?;
+    let Some(signature) = sign::signature(repo, content)? else {
+        return repo
+            .commit(Some("HEAD"), author, committer, message, tree, parents)
+            .map_err(git_err);
+    };
 
-    // Ensure git is initialized
-    init_repo(&repo_path)?;
-    write_push_gitignore(&repo_path, config)?;
+    let id = repo
+        .commit_signed(content, &signature, None)
+        .map_err(git_err)?;
+    let head = repo.find_reference("HEAD")?;
+    let name = head.symbolic_target().unwrap_or("HEAD").to_string();
+    let summary = message.lines().next().unwrap_or_default();
+    repo.reference(&name, id, true, &format!("commit: {}", summary))?;
+    Ok(id)
+}
 
-    // Add all files
-    let output = Command::new("git")
-        .args(["add", "-A"])
-        .current_dir(&repo_path)
-        .output()
+/// Stage every change in the working tree (honoring `.gitignore`). Returns
+/// the staged tree and how many files it changes, or None if nothing changed.
+fn stage_all(repo: &Repository) -> Result<Option<(Oid, usize)>> {
+    let mut index = repo.index()?;
+    index
+        .add_all(["*"], IndexAddOption::DEFAULT, None)
+        .map_err(git_err)
         .context("Failed to add files to git")?;
+    index.update_all(["*"], None).map_err(git_err)?;
+    index.write()?;
+    let tree_id = index.write_tree()?;
+
+    let parent = head_commit(repo)?;
+    let unchanged = match &parent {
+        Some(parent) => parent.tree_id() == tree_id,
+        None => index.is_empty(),
+    };
+    if unchanged {
+        return Ok(None);
+    }
 
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to add files: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
+    let parent_tree = parent.map(|p| p.tree()).transpose()?;
+    let tree = repo.find_tree(tree_id)?;
+    let files = repo
+        .diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?
+        .deltas()
+        .len();
+    Ok(Some((tree_id, files)))
+}
+
+/// Commit a tree from [`stage_all`] on top of HEAD
+fn commit_staged(repo: &Repository, tree_id: Oid, message: &str) -> Result<()> {
+    let tree = repo.find_tree(tree_id)?;
+    let parent = head_commit(repo)?;
+    let (author, committer) = signatures(repo)?;
+    let parents: Vec<&Commit> = parent.iter().collect();
+    create_commit(repo, &author, &committer, message, &tree, &parents)
+        .context("Failed to commit")?;
+    Ok(())
+}
+
+/// Stage every change and commit it. Returns false if there was nothing to
+/// commit.
+fn commit_all(repo: &Repository, message: &str) -> Result<bool> {
+    match stage_all(repo)? {
+        Some((tree_id, _)) => commit_staged(repo, tree_id, message).map(|()| true),
+        None => Ok(false),
     }
+}
 
-    // Check if there are changes to commit
-    let status_output = Command::new("git")
-        .args(["status", "--porcelain"])
-        .current_dir(&repo_path)
-        .output()
-        .context("Failed to check git status")?;
+/// Message of a push without `-m`: `[github] commit_template` with
+/// `{hostname}`, `{profile}`, `{files}` and `{date}` filled in
+fn commit_message(config: &Config, files: usize) -> Result<String> {
+    let date = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let Some(template) = &config.github.commit_template else {
+        return Ok(format!("Update dotfiles - {}", date));
+    };
+    let host = crate::hosts::HostIdentity::local(config)?;
+    Ok(template
+        .replace("{hostname}", &host.name)
+        .replace("{profile}", &host.profile)
+        .replace("{files}", &files.to_string())
+        .replace("{date}", &date))
+}
 
-    if status_output.stdout.is_empty() {
-        ui::info("No changes to commit");
-    } else {
-        // Commit changes
-        let commit_message = message.unwrap_or_else(|| {
-            format!(
-                "Update dotfiles - {}",
-                chrono::Utc::now().format("%Y-%m-%d %H:%M:%S")
-            )
-        });
+/// Name the current branch `branch`, like `git branch -M <branch>`
+fn ensure_branch_name(repo: &Repository, branch: &str) -> Result<()> {
+    let Ok(head) = repo.head() else {
+        return Ok(());
+    };
+    if head.is_branch() && head.shorthand() != Some(branch) {
+        git2::Branch::wrap(head)
+            .rename(branch, true)
+            .map_err(git_err)
+            .with_context(|| format!("Failed to rename branch to {}", branch))?;
+    }
+    Ok(())
+}
 
-        let output = Command::new("git")
-            .args(["commit", "-m", commit_message.as_str()])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to commit changes")?;
+fn proxy_options<'a>(policy: &RetryPolicy) -> ProxyOptions<'a> {
+    let mut proxy = ProxyOptions::new();
+    match &policy.proxy {
+        Some(url) => proxy.url(url),
+        None => proxy.auto(),
+    };
+    proxy
+}
 
-        if !output.status.success() {
-            anyhow::bail!(
-                "Failed to commit: {}",
-                String::from_utf8_lossy(&output.stderr)
-            );
+fn fetch_options<'a>(policy: &RetryPolicy) -> FetchOptions<'a> {
+    let mut opts = FetchOptions::new();
+    opts.remote_callbacks(auth::callbacks())
+        .proxy_options(proxy_options(policy));
+    opts
+}
+
+/// Fetch `branch` from origin into `origin/<branch>`, retrying transient failures
+fn fetch_branch(repo: &Repository, branch: &str, policy: &RetryPolicy) -> Result<()> {
+    policy.run("git fetch", || {
+        let mut remote = repo.find_remote("origin").map_err(git_err)?;
+        remote
+            .fetch(
+                &[format!("+refs/heads/{}:{}", branch, tracking_ref(branch))],
+                Some(&mut fetch_options(policy)),
+                None,
+            )
+            .map_err(git_err)
+    })
+}
+
+/// Push `refspec` to origin, retrying transient failures. Returns why the
+/// remote refused the update, if it did (e.g. it has commits we don't).
+fn push_ref(repo: &Repository, refspec: &str, policy: &RetryPolicy) -> Result<Option<String>> {
+    policy.run("git push", || {
+        let mut remote = repo.find_remote("origin").map_err(git_err)?;
+        let rejected = RefCell::new(None);
+        let mut callbacks = auth::callbacks();
+        callbacks.push_update_reference(|_, status| {
+            if let Some(status) = status {
+                *rejected.borrow_mut() = Some(status.to_string());
+            }
+            Ok(())
+        });
+        let mut opts = PushOptions::new();
+        opts.remote_callbacks(callbacks)
+            .proxy_options(proxy_options(policy));
+
+        match remote.push(&[refspec], Some(&mut opts)) {
+            Ok(()) => {}
+            Err(e) if e.code() == ErrorCode::NotFastForward => {
+                return Ok(Some(e.message().to_string()))
+            }
+            Err(e) => return Err(git_err(e)),
         }
+        drop(opts);
+        Ok(rejected.into_inner())
+    })
+}
 
-        ui::success("Changes committed");
+/// Track `origin/<branch>` from `branch`, like `git push --set-upstream`
+fn set_upstream(repo: &Repository, branch: &str) {
+    if let Ok(mut local) = repo.find_branch(branch, BranchType::Local) {
+        let _ = local.set_upstream(Some(&format!("origin/{}", branch)));
     }
+}
 
-    // Ensure the branch is named 'main'
-    let branch_output = Command::new("git")
-        .args(["branch", "--show-current"])
-        .current_dir(&repo_path)
-        .output()
-        .context("Failed to get current branch name")?;
-
-    let current_branch = String::from_utf8_lossy(&branch_output.stdout)
-        .trim()
-        .to_string();
-    if !current_branch.is_empty() && current_branch != "main" {
-        let rename_output = Command::new("git")
-            .args(["branch", "-M", "main"])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to rename branch to main")?;
-
-        if !rename_output.status.success() {
-            anyhow::bail!(
-                "Failed to rename branch to main: {}",
-                String::from_utf8_lossy(&rename_output.stderr)
+/// Replay the local commits on top of `origin/<branch>`, like `git rebase`.
+/// The replayed commits go through [`create_commit`], so they are signed too.
+fn rebase_onto_origin(repo: &Repository, branch: &str, repo_path: &Path) -> Result<()> {
+    let upstream = repo
+        .find_reference(&tracking_ref(branch))?
+        .peel_to_commit()?;
+    let head = head_commit(repo)?.context("No local commits to rebase")?;
+    let mut walk = repo.revwalk()?;
+    walk.set_sorting(Sort::TOPOLOGICAL | Sort::REVERSE)?;
+    walk.push(head.id())?;
+    walk.hide(upstream.id())?;
+    let local = walk.collect::<Result<Vec<_>, _>>()?;
+
+    let (_, committer) = signatures(repo)?;
+    let empty = repo.find_tree(repo.treebuilder(None)?.write()?)?;
+    // Commits are replayed onto the branch, so it starts at the upstream tip
+    repo.reset(upstream.as_object(), ResetType::Soft, None)?;
+    let mut onto = upstream;
+    for id in local {
+        let commit = repo.find_commit(id)?;
+        // Like git rebase, merges are flattened away
+        if commit.parent_count() > 1 {
+            continue;
+        }
+        let base = commit.parents().next().map(|p| p.tree()).transpose()?;
+        let mut index = repo.merge_trees(
+            base.as_ref().unwrap_or(&empty),
+            &onto.tree()?,
+            &commit.tree()?,
+            None,
+        )?;
+        if index.has_conflicts() {
+            repo.reset(head.as_object(), ResetType::Hard, None)?;
+            bail!(
+                "Rebase failed (remote and local both changed the same files).\n\
+                 Run 'dotdipper sync' to resolve them file by file, or fix {:?} by hand, then run 'dotdipper push' again.",
+                repo_path
             );
         }
+        let tree_id = index.write_tree_to(repo)?;
+        // The remote already has this change
+        if tree_id == onto.tree_id() {
+            continue;
+        }
+        let id = create_commit(
+            repo,
+            &commit.author(),
+            &committer,
+            commit.message_raw().unwrap_or_default(),
+            &repo.find_tree(tree_id)?,
+            &[&onto],
+        )?;
+        onto = repo.find_commit(id)?;
     }
+    repo.reset(onto.as_object(), ResetType::Hard, None)?;
+    Ok(())
+}
 
-    if let Err(e) = ensure_github_repo(config, &repo_path, &username, &repo_name) {
-        ui::warn(&format!("Could not create GitHub repo: {}", e));
-        ui::hint("Create a GitHub repository manually and add it as a remote");
-        return Ok(repo_name);
+/// Bring `branch` up to date with `origin/<branch>`: fast-forward when
+/// possible, otherwise merge (or fail if `ff_only`)
+fn merge_origin(repo: &Repository, branch: &str, ff_only: bool) -> Result<()> {
+    let upstream_ref = repo
+        .find_reference(&tracking_ref(branch))
+        .map_err(git_err)
+        .with_context(|| format!("origin/{} does not exist; fetch it first", branch))?;
+    let upstream = repo.reference_to_annotated_commit(&upstream_ref)?;
+    let (analysis, _) = repo.merge_analysis(&[&upstream])?;
+
+    if analysis.is_up_to_date() {
+        return Ok(());
+    }
+    if analysis.is_fast_forward() || analysis.is_unborn() {
+        let target = repo.find_object(upstream.id(), None)?;
+        repo.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))
+            .map_err(git_err)
+            .context("Local changes would be overwritten")?;
+        match repo.head() {
+            Ok(mut head) if head.is_branch() => {
+                head.set_target(upstream.id(), "pull: fast-forward")?;
+            }
+            _ => {
+                let local = format!("refs/heads/{}", branch);
+                repo.reference(&local, upstream.id(), true, "pull: fast-forward")?;
+                repo.set_head(&local)?;
+            }
+        }
+        return Ok(());
+    }
+    if ff_only {
+        bail!(
+            "{0} has commits that are not on origin/{0}, so it cannot be fast-forwarded",
+            branch
+        );
     }
 
-    // Push to remote
-    let mut push_args = vec!["push", "origin", "main"];
-    if force {
-        push_args.push("--force");
+    repo.merge(&[&upstream], None, None).map_err(git_err)?;
+    let mut index = repo.index()?;
+    if index.has_conflicts() {
+        let files: Vec<String> = index
+            .conflicts()?
+            .filter_map(|c| c.ok())
+            .filter_map(|c| c.our.or(c.their))
+            .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+            .collect();
+        bail!(
+            "Merge conflicts in {}. Resolve them in {:?} and commit, or run 'dotdipper sync' instead",
+            files.join(", "),
+            repo.workdir().unwrap_or(repo.path())
+        );
     }
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let head = head_commit(repo)?.context("No local commit to merge into")?;
+    let theirs = repo.find_commit(upstream.id())?;
+    let (author, committer) = signatures(repo)?;
+    create_commit(
+        repo,
+        &author,
+        &committer,
+        &format!("Merge remote-tracking branch 'origin/{}'", branch),
+        &tree,
+        &[&head, &theirs],
+    )?;
+    repo.cleanup_state()?;
+    Ok(())
+}
 
-    let output = Command::new("git")
-        .args(&push_args)
-        .current_dir(&repo_path)
-        .output()
-        .context("Failed to push to GitHub")?;
+/// Clone `branch` of `url` into `dest`, retrying transient failures
+fn clone(url: &str, branch: &str, dest: &Path, policy: &RetryPolicy) -> Result<()> {
+    policy.run("git clone", || {
+        let mut builder = RepoBuilder::new();
+        builder.branch(branch).fetch_options(fetch_options(policy));
+        builder.clone(url, dest).map(|_| ()).map_err(git_err)
+    })
+}
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let need_fetch = stderr.contains("fetch first")
-            || stderr.contains("Updates were rejected")
-            || stderr.contains("integrate the remote changes");
-
-        if need_fetch {
-            // Remote has commits we don't have (e.g. repo created with README). Fetch, rebase, retry.
-            ui::info("Remote has commits you don't have locally. Syncing and retrying push...");
-            let fetch_out = Command::new("git")
-                .args(["fetch", "origin", "main"])
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to fetch from origin")?;
-            if !fetch_out.status.success() {
-                anyhow::bail!(
-                    "Failed to fetch: {}. Run 'dotdipper pull' first, then 'dotdipper push' again.",
-                    String::from_utf8_lossy(&fetch_out.stderr)
-                );
-            }
-            let rebase_out = Command::new("git")
-                .args(["rebase", "origin/main"])
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to rebase onto origin/main")?;
-            if !rebase_out.status.success() {
-                anyhow::bail!(
-                    "Rebase failed (remote and local both have changes): {}\n\
-                     Resolve conflicts in {:?} (e.g. git rebase --abort or fix and git rebase --continue), then run 'dotdipper push' again.",
-                    String::from_utf8_lossy(&rebase_out.stderr),
-                    repo_path
-                );
-            }
-            let retry_out = Command::new("git")
-                .args(&push_args)
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to push after rebase")?;
-            if !retry_out.status.success() {
-                anyhow::bail!(
-                    "Failed to push: {}",
-                    String::from_utf8_lossy(&retry_out.stderr)
-                );
-            }
-        } else if stderr.contains("failed to push") || stderr.contains("rejected") {
-            // No upstream set; try set-upstream and push again
-            let output = Command::new("git")
-                .args(["push", "--set-upstream", "origin", "main"])
-                .current_dir(&repo_path)
-                .output()
-                .context("Failed to set upstream branch")?;
-
-            if !output.status.success() {
-                anyhow::bail!(
-                    "Failed to push: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                );
+pub fn push(
+    config: &Config,
+    message: Option<String>,
+    force: bool,
+    repo_override: Option<&str>,
+) -> Result<String> {
+    let policy = RetryPolicy::from_config(config);
+    push_with_policy(config, message, force, repo_override, &policy)
+}
+
+/// Like [`push`], with an explicit retry policy for the network steps
+pub fn push_with_policy(
+    config: &Config,
+    message: Option<String>,
+    force: bool,
+    repo_override: Option<&str>,
+    policy: &RetryPolicy,
+) -> Result<String> {
+    let repo_path = crate::paths::compiled_dir()?;
+    let repo_name = resolve_repo_name(config, repo_override);
+    let target = target(config, repo_override)?;
+
+    // Ensure git is initialized
+    init_repo(&repo_path, &target.branch)?;
+    write_push_gitignore(&repo_path, config)?;
+    let repo = open(&repo_path)?;
+    sign::configure(&repo, &config.github)?;
+
+    match stage_all(&repo)? {
+        Some((tree_id, files)) => {
+            let message = match message {
+                Some(message) => message,
+                None => commit_message(config, files)?,
+            };
+            commit_staged(&repo, tree_id, &message)?;
+            ui::success("Changes committed");
+        }
+        None => ui::info("No changes to commit"),
+    }
+    ensure_branch_name(&repo, &target.branch)?;
+
+    set_origin(&repo, &target.url)?;
+    // gh is only needed to create a GitHub repository; without it we push
+    // to whatever already exists
+    if let Some((username, repo_name)) = &target.github {
+        if check_gh().is_ok() {
+            if let Err(e) = ensure_github_repo(config, username, repo_name) {
+                if crate::offline::is_transient(&format!("{:#}", e)) {
+                    return Err(e);
+                }
+                ui::warn(&format!("Could not create GitHub repo: {}", e));
+                ui::hint("Create a GitHub repository manually and add it as a remote");
+                return Ok(repo_name.clone());
             }
-        } else {
-            anyhow::bail!("Failed to push: {}", stderr);
         }
     }
 
+    let refspec = if force {
+        format!("+{}", refspec(&target.branch))
+    } else {
+        refspec(&target.branch)
+    };
+    let rejected = push_ref(&repo, &refspec, policy)
+        .map_err(|e| not_found_hint(e, &target))
+        .with_context(|| format!("Failed to push to {}", target.url))?;
+
+    if rejected.is_some() {
+        // Remote has commits we don't have (e.g. repo created with README). Fetch, rebase, retry.
+        ui::info("Remote has commits you don't have locally. Syncing and retrying push...");
+        fetch_branch(&repo, &target.branch, policy).context(
+            "Failed to fetch from origin. Run 'dotdipper pull' first, then 'dotdipper push' again.",
+        )?;
+        rebase_onto_origin(&repo, &target.branch, &repo_path)?;
+        if let Some(reason) =
+            push_ref(&repo, &refspec, policy).context("Failed to push after rebase")?
+        {
+            bail!("Failed to push: {}", reason);
+        }
+    }
+    set_upstream(&repo, &target.branch);
+
+    record_history(
+        crate::history::Kind::Push,
+        format!("Pushed to {}", repo_name),
+        &repo_path,
+    );
     Ok(repo_name)
 }
 
+/// Point at creating the repository when the remote does not exist
+fn not_found_hint(e: anyhow::Error, target: &Target) -> anyhow::Error {
+    let message = format!("{:#}", e).to_lowercase();
+    let missing = message.contains("not found") || message.contains("does not exist");
+    match &target.github {
+        Some((username, repo_name)) if missing => e.context(format!(
+            "Create {}/{} on GitHub first (or install gh to have dotdipper create it)",
+            username, repo_name
+        )),
+        None if missing => e.context(format!("Create the repository at {} first", target.url)),
+        _ => e,
+    }
+}
+
 pub fn pull(config: &Config, repo_override: Option<&str>) -> Result<String> {
     let repo_path = crate::paths::compiled_dir()?;
     let repo_name = resolve_repo_name(config, repo_override);
-    let username = resolve_github_username(config)?;
+    let target = target(config, repo_override)?;
+    let policy = RetryPolicy::from_config(config);
 
     // If repo doesn't exist, clone it
     if !repo_path.join(".git").exists() {
-        clone_repo(&username, &repo_name, &repo_path)?;
+        clone_repo(&target, &repo_path, &policy)?;
+        sign::configure(&open(&repo_path)?, &config.github)?;
     } else {
+        let repo = open(&repo_path)?;
+        sign::configure(&repo, &config.github)?;
         // Ensure current origin points at the selected repo
-        add_remote(&username, &repo_name, &repo_path)?;
+        set_origin(&repo, &target.url)?;
 
-        // Pull changes
-        let output = Command::new("git")
-            .args(["pull", "origin", "main"])
-            .current_dir(&repo_path)
-            .output()
-            .context("Failed to pull from GitHub")?;
-
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            if stderr.contains("no tracking information") {
-                // Set tracking branch
-                let output = Command::new("git")
-                    .args(["branch", "--set-upstream-to=origin/main", "main"])
-                    .current_dir(&repo_path)
-                    .output()
-                    .context("Failed to set tracking branch")?;
-
-                if output.status.success() {
-                    // Try pull again
-                    let output = Command::new("git")
-                        .args(["pull", "origin", "main"])
-                        .current_dir(&repo_path)
-                        .output()
-                        .context("Failed to pull from GitHub")?;
-
-                    if !output.status.success() {
-                        anyhow::bail!(
-                            "Failed to pull: {}",
-                            String::from_utf8_lossy(&output.stderr)
-                        );
-                    }
-                } else {
-                    anyhow::bail!(
-                        "Failed to set tracking branch: {}",
-                        String::from_utf8_lossy(&output.stderr)
-                    );
+        fetch_branch(&repo, &target.branch, &policy)
+            .with_context(|| format!("Failed to pull from {}", target.url))?;
+        merge_origin(&repo, &target.branch, false).context("Failed to pull")?;
+        set_upstream(&repo, &target.branch);
+    }
+
+    record_history(
+        crate::history::Kind::Pull,
+        format!("Pulled from {}", repo_name),
+        &repo_path,
+    );
+    Ok(repo_name)
+}
+
+/// Fetch the configured branch into [`remote_ref`] without touching the
+/// compiled repository's working tree. Returns the repository name.
+pub fn fetch(config: &Config, repo_override: Option<&str>) -> Result<String> {
+    let repo_path = crate::paths::compiled_dir()?;
+    if !repo_path.join(".git").exists() {
+        anyhow::bail!("No local repository yet. Run 'dotdipper pull' first");
+    }
+    let repo_name = resolve_repo_name(config, repo_override);
+    let target = target(config, repo_override)?;
+    let repo = open(&repo_path)?;
+    set_origin(&repo, &target.url)?;
+
+    fetch_branch(&repo, &target.branch, &RetryPolicy::from_config(config))
+        .with_context(|| format!("Failed to fetch from {}", target.url))?;
+    Ok(repo_name)
+}
+
+/// Newest common ancestor of `a` and `b`, if they share history
+pub fn merge_base(repo_path: &Path, a: &str, b: &str) -> Option<String> {
+    let repo = open(repo_path).ok()?;
+    let a = commit_at(&repo, a).ok()?.id();
+    let b = commit_at(&repo, b).ok()?.id();
+    repo.merge_base(a, b).ok().map(|oid| oid.to_string())
+}
+
+/// Files in the tree of `rev`, relative to the repository root
+pub fn tree_files(repo_path: &Path, rev: &str) -> Result<Vec<PathBuf>> {
+    Ok(tree_entries(repo_path, rev)?
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Blobs in the tree of `rev` with their git file mode (`100644`, `100755`,
+/// `120000` for symlinks, `160000` for submodules)
+pub fn tree_entries(repo_path: &Path, rev: &str) -> Result<Vec<(String, PathBuf)>> {
+    let repo = open(repo_path)?;
+    let tree = commit_at(&repo, rev)?.tree()?;
+    let mut entries = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+        if matches!(entry.kind(), Some(ObjectType::Blob | ObjectType::Commit)) {
+            let path = Path::new(root).join(String::from_utf8_lossy(entry.name_bytes()).as_ref());
+            entries.push((format!("{:o}", entry.filemode()), path));
+        }
+        TreeWalkResult::Ok
+    })?;
+    Ok(entries)
+}
+
+/// Contents of `path` as committed in `rev`
+pub fn file_at(repo_path: &Path, rev: &str, path: &Path) -> Result<Vec<u8>> {
+    let repo = open(repo_path)?;
+    let tree = commit_at(&repo, rev)?.tree()?;
+    let blob = tree
+        .get_path(path)
+        .and_then(|entry| entry.to_object(&repo))
+        .and_then(|object| object.peel_to_blob())
+        .map_err(|e| {
+            anyhow::anyhow!("Failed to read {}:{}: {}", rev, path.display(), e.message())
+        })?;
+    Ok(blob.content().to_vec())
+}
+
+/// Point the current branch and working tree at `rev`
+pub fn reset_hard(repo_path: &Path, rev: &str) -> Result<()> {
+    let repo = open(repo_path)?;
+    let commit = commit_at(&repo, rev)?;
+    repo.reset(commit.as_object(), ResetType::Hard, None)
+        .map_err(git_err)
+        .with_context(|| format!("Failed to reset to {}", rev))?;
+    Ok(())
+}
+
+/// Three-way merge of file contents. Returns the merged contents and
+/// whether they hold conflict markers.
+pub fn merge_contents(local: &[u8], base: &[u8], remote: &[u8]) -> Result<(Vec<u8>, bool)> {
+    merge_labeled(local, base, remote, "remote")
+}
+
+/// [`merge_contents`] with the conflict markers of `remote`'s side labeled
+/// `remote_label`
+pub fn merge_labeled(
+    local: &[u8],
+    base: &[u8],
+    remote: &[u8],
+    remote_label: &str,
+) -> Result<(Vec<u8>, bool)> {
+    // libgit2 merges blobs, so stage the three versions in a scratch repository
+    let dir = tempfile::tempdir()?;
+    let repo = Repository::init_bare(dir.path())?;
+    let entry = |contents: &[u8]| -> Result<IndexEntry> {
+        Ok(IndexEntry {
+            ctime: IndexTime::new(0, 0),
+            mtime: IndexTime::new(0, 0),
+            dev: 0,
+            ino: 0,
+            mode: 0o100644,
+            uid: 0,
+            gid: 0,
+            file_size: contents.len() as u32,
+            id: repo.blob(contents)?,
+            flags: 0,
+            flags_extended: 0,
+            path: b"file".to_vec(),
+        })
+    };
+
+    let mut opts = MergeFileOptions::new();
+    opts.ancestor_label("base")
+        .our_label("local")
+        .their_label(remote_label);
+    let result = repo
+        .merge_file_from_index(
+            &entry(base)?,
+            &entry(local)?,
+            &entry(remote)?,
+            Some(&mut opts),
+        )
+        .map_err(git_err)
+        .context("Failed to merge file contents")?;
+    Ok((result.content().to_vec(), !result.is_automergeable()))
+}
+
+/// Check that every object in the repository at `repo_path` can be read
+/// and matches its hash, and that everything reachable from HEAD exists
+pub fn verify_repo(repo_path: &Path) -> Result<()> {
+    let repo = open(repo_path)?;
+    let odb = repo.odb()?;
+    let mut problems = Vec::new();
+
+    let mut ids: Vec<Oid> = Vec::new();
+    odb.foreach(|id| {
+        ids.push(*id);
+        true
+    })?;
+    for id in ids {
+        if let Err(e) = odb.read(id) {
+            problems.push(format!("{}: {}", id, e.message()));
+        }
+    }
+
+    if head_commit(&repo).is_ok_and(|head| head.is_some()) {
+        let mut walk = repo.revwalk()?;
+        walk.push_head()?;
+        let mut seen = HashSet::new();
+        for id in walk {
+            let tree = match id
+                .and_then(|id| repo.find_commit(id))
+                .and_then(|c| c.tree())
+            {
+                Ok(tree) => tree,
+                Err(e) => {
+                    problems.push(e.message().to_string());
+                    continue;
                 }
-            } else {
-                anyhow::bail!("Failed to pull: {}", stderr);
-            }
+            };
+            tree.walk(TreeWalkMode::PreOrder, |root, entry| {
+                if !seen.insert(entry.id()) {
+                    return TreeWalkResult::Skip;
+                }
+                if !odb.exists(entry.id()) {
+                    problems.push(format!(
+                        "missing {} {}{}",
+                        entry.id(),
+                        root,
+                        entry.name().unwrap_or("?")
+                    ));
+                }
+                TreeWalkResult::Ok
+            })
+            .unwrap_or_else(|e| problems.push(e.message().to_string()));
         }
     }
 
-    Ok(repo_name)
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "Repository failed its integrity check:\n{}",
+            problems.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Commit everything in `repo_path` and push it to `url` (used for
+/// collections, which have their own remotes)
+pub fn push_to_url(repo_path: &Path, url: &str, message: &str, policy: &RetryPolicy) -> Result<()> {
+    init_repo(repo_path, DEFAULT_BRANCH)?;
+    let repo = open(repo_path)?;
+    if commit_all(&repo, message)? {
+        ui::success("Changes committed");
+    } else {
+        ui::info("No changes to commit");
+    }
+
+    set_origin(&repo, url)?;
+    if let Some(reason) = push_ref(&repo, &refspec(DEFAULT_BRANCH), policy)
+        .with_context(|| format!("Failed to push to {}", url))?
+    {
+        anyhow::bail!("Failed to push: {}", reason);
+    }
+    set_upstream(&repo, DEFAULT_BRANCH);
+    Ok(())
 }
 
-pub fn undo_last_push(config: &Config, force: bool, repo_override: Option<&str>) -> Result<String> {
+/// Clone `url` into `dest`, or fast-forward an existing clone
+pub fn clone_or_pull(url: &str, dest: &Path, policy: &RetryPolicy) -> Result<()> {
+    if dest.join(".git").exists() {
+        let repo = open(dest)?;
+        set_origin(&repo, url)?;
+        fetch_branch(&repo, DEFAULT_BRANCH, policy)
+            .and_then(|()| merge_origin(&repo, DEFAULT_BRANCH, true))
+            .with_context(|| format!("Failed to pull from {}", url))
+    } else {
+        let parent = dest.parent().unwrap_or(Path::new("/"));
+        std::fs::create_dir_all(parent)?;
+        clone(url, DEFAULT_BRANCH, dest, policy)
+            .with_context(|| format!("Failed to pull from {}", url))
+    }
+}
+
+/// Revert a pushed commit with a new commit and push it: the last one, or
+/// `commit` (any commit on the branch, e.g. from a history entry) if given.
+pub fn undo_push(
+    config: &Config,
+    force: bool,
+    repo_override: Option<&str>,
+    commit: Option<&str>,
+) -> Result<String> {
     let repo_path = crate::paths::compiled_dir()?;
     let repo_name = resolve_repo_name(config, repo_override);
-    let username = resolve_github_username(config)?;
+    let target = target(config, repo_override)?;
+    let branch = target.branch.as_str();
+    let policy = RetryPolicy::from_config(config);
 
     if !repo_path.join(".git").exists() {
-        clone_repo(&username, &repo_name, &repo_path)?;
+        clone_repo(&target, &repo_path, &policy)?;
     } else {
-        add_remote(&username, &repo_name, &repo_path)?;
+        set_origin(&open(&repo_path)?, &target.url)?;
     }
+    sign::configure(&open(&repo_path)?, &config.github)?;
 
     ensure_clean_worktree(&repo_path)?;
-    fetch_origin_main(&repo_path)?;
-    ensure_main_checked_out(&repo_path)?;
-    fast_forward_main_to_origin(&repo_path)?;
-    ensure_head_matches_ref(&repo_path, "origin/main")?;
-    ensure_head_is_not_merge_commit(&repo_path)?;
-
-    let commit_summary = git_stdout(&repo_path, &["log", "-1", "--pretty=%h %s", "HEAD"])?;
-
-    if !force
-        && !ui::prompt_confirm(
-            &format!(
-                "Undo last pushed commit '{}' by creating a new revert commit?",
-                commit_summary
-            ),
-            false,
+    fetch_origin(&repo_path, branch, &policy)?;
+    ensure_branch_checked_out(&repo_path, branch)?;
+    fast_forward_to_origin(&repo_path, branch)?;
+    ensure_head_matches_ref(&repo_path, &format!("origin/{}", branch))?;
+
+    let rev = commit.unwrap_or("HEAD");
+    if commit.is_some() && !is_ancestor(&repo_path, rev, "HEAD") {
+        anyhow::bail!(
+            "Commit {} is not on {}, so it was never pushed",
+            rev,
+            branch
+        );
+    }
+    ensure_not_merge_commit(&repo_path, rev)?;
+
+    let commit_summary = {
+        let repo = open(&repo_path)?;
+        let target = commit_at(&repo, rev)?;
+        let short = target.as_object().short_id()?;
+        format!(
+            "{} {}",
+            short.as_str().unwrap_or_default(),
+            target.summary().unwrap_or_default()
         )
-    {
+    };
+    let prompt = match commit {
+        Some(_) => format!(
+            "Undo pushed commit '{}' by creating a new revert commit?",
+            commit_summary
+        ),
+        None => format!(
+            "Undo last pushed commit '{}' by creating a new revert commit?",
+            commit_summary
+        ),
+    };
+
+    if !force && !ui::prompt_confirm(&prompt, false) {
         ui::info("Undo cancelled");
         return Ok(repo_name);
     }
 
-    revert_head_commit(&repo_path)?;
-    push_main(&repo_path)?;
+    revert_commit(&repo_path, rev)?;
+    push_branch(&repo_path, branch, &policy)?;
 
     ui::success(&format!(
         "Created and pushed a revert for {}",
         commit_summary
     ));
+    record_history(
+        crate::history::Kind::Undo,
+        format!("Reverted {}", commit_summary),
+        &repo_path,
+    );
     Ok(repo_name)
 }
 
+/// Record a git operation in the history, referencing the resulting commit
+fn record_history(kind: crate::history::Kind, summary: String, repo_path: &Path) {
+    let mut event = crate::history::Event::new(kind, summary);
+    if let Ok(Some(head)) = open(repo_path).and_then(|repo| Ok(head_commit(&repo)?.map(|c| c.id())))
+    {
+        event = event.reference(head.to_string()[..12].to_string());
+    }
+    event.record();
+}
+
+fn is_ancestor(repo_path: &Path, ancestor: &str, rev: &str) -> bool {
+    let Ok(repo) = open(repo_path) else {
+        return false;
+    };
+    let (Ok(ancestor), Ok(rev)) = (commit_at(&repo, ancestor), commit_at(&repo, rev)) else {
+        return false;
+    };
+    ancestor.id() == rev.id()
+        || repo
+            .graph_descendant_of(rev.id(), ancestor.id())
+            .unwrap_or(false)
+}
+
 fn ensure_clean_worktree(repo_path: &Path) -> Result<()> {
-    let output = Command::new("git")
-        .args(["status", "--porcelain"])
-        .current_dir(repo_path)
-        .output()
+    let repo = open(repo_path)?;
+    let mut opts = StatusOptions::new();
+    opts.include_untracked(true).include_ignored(false);
+    let statuses = repo
+        .statuses(Some(&mut opts))
+        .map_err(git_err)
         .context("Failed to check git status")?;
 
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to check git status: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
-
-    if !output.stdout.is_empty() {
+    if !statuses.is_empty() {
         anyhow::bail!(
             "Local repository has uncommitted changes. Commit, stash, or discard them before running undo."
         );
@@ -364,81 +997,47 @@ fn ensure_clean_worktree(repo_path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn fetch_origin_main(repo_path: &Path) -> Result<()> {
-    let output = Command::new("git")
-        .args(["fetch", "origin", "main"])
-        .current_dir(repo_path)
-        .output()
-        .context("Failed to fetch origin/main")?;
-
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to fetch origin/main: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
-
-    Ok(())
+fn fetch_origin(repo_path: &Path, branch: &str, policy: &RetryPolicy) -> Result<()> {
+    fetch_branch(&open(repo_path)?, branch, policy)
+        .with_context(|| format!("Failed to fetch origin/{}", branch))
 }
 
-fn ensure_main_checked_out(repo_path: &Path) -> Result<()> {
-    let current_branch = git_stdout(repo_path, &["branch", "--show-current"])?;
-    if current_branch == "main" {
+fn ensure_branch_checked_out(repo_path: &Path, branch: &str) -> Result<()> {
+    let repo = open(repo_path)?;
+    if repo
+        .head()
+        .is_ok_and(|head| head.is_branch() && head.shorthand() == Some(branch))
+    {
         return Ok(());
     }
 
-    let args = if git_ref_exists(repo_path, "refs/heads/main")? {
-        vec!["checkout", "main"]
-    } else {
-        vec!["checkout", "-B", "main", "origin/main"]
+    let local = match repo.find_branch(branch, BranchType::Local) {
+        Ok(local) => local,
+        Err(_) => {
+            let target = repo
+                .find_reference(&tracking_ref(branch))?
+                .peel_to_commit()?;
+            repo.branch(branch, &target, true)?
+        }
     };
-
-    let output = Command::new("git")
-        .args(&args)
-        .current_dir(repo_path)
-        .output()
-        .context("Failed to switch to main branch")?;
-
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to switch to main branch: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
+    let target = local.get().peel(ObjectType::Commit)?;
+    repo.checkout_tree(&target, Some(CheckoutBuilder::new().safe()))
+        .and_then(|()| repo.set_head(&format!("refs/heads/{}", branch)))
+        .map_err(git_err)
+        .with_context(|| format!("Failed to switch to {} branch", branch))?;
 
     Ok(())
 }
 
-fn git_ref_exists(repo_path: &Path, git_ref: &str) -> Result<bool> {
-    let output = Command::new("git")
-        .args(["show-ref", "--verify", "--quiet", git_ref])
-        .current_dir(repo_path)
-        .output()
-        .with_context(|| format!("Failed to verify git ref {}", git_ref))?;
-
-    Ok(output.status.success())
-}
-
-fn fast_forward_main_to_origin(repo_path: &Path) -> Result<()> {
-    let output = Command::new("git")
-        .args(["merge", "--ff-only", "origin/main"])
-        .current_dir(repo_path)
-        .output()
-        .context("Failed to fast-forward local main branch")?;
-
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to fast-forward local main branch: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
-
-    Ok(())
+fn fast_forward_to_origin(repo_path: &Path, branch: &str) -> Result<()> {
+    merge_origin(&open(repo_path)?, branch, true)
+        .with_context(|| format!("Failed to fast-forward local {} branch", branch))
 }
 
 fn ensure_head_matches_ref(repo_path: &Path, git_ref: &str) -> Result<()> {
-    let head = git_stdout(repo_path, &["rev-parse", "HEAD"])?;
-    let target = git_stdout(repo_path, &["rev-parse", git_ref])?;
+    let repo = open(repo_path)?;
+    let head = commit_at(&repo, "HEAD")?.id();
+    let target = commit_at(&repo, git_ref)?.id();
 
     if head != target {
         anyhow::bail!(
@@ -450,9 +1049,9 @@ fn ensure_head_matches_ref(repo_path: &Path, git_ref: &str) -> Result<()> {
     Ok(())
 }
 
-fn ensure_head_is_not_merge_commit(repo_path: &Path) -> Result<()> {
-    let parents = git_stdout(repo_path, &["rev-list", "--parents", "-n", "1", "HEAD"])?;
-    if parents.split_whitespace().count() > 2 {
+fn ensure_not_merge_commit(repo_path: &Path, rev: &str) -> Result<()> {
+    let repo = open(repo_path)?;
+    if commit_at(&repo, rev)?.parent_count() > 1 {
         anyhow::bail!(
             "Undo does not support reverting a merge commit automatically. Revert it manually with git revert -m."
         );
@@ -461,182 +1060,126 @@ fn ensure_head_is_not_merge_commit(repo_path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn revert_head_commit(repo_path: &Path) -> Result<()> {
-    let output = Command::new("git")
-        .args(["revert", "--no-edit", "HEAD"])
-        .current_dir(repo_path)
-        .output()
-        .context("Failed to create revert commit")?;
+fn revert_commit(repo_path: &Path, rev: &str) -> Result<()> {
+    let repo = open(repo_path)?;
+    let commit = commit_at(&repo, rev)?;
+    let head = head_commit(&repo)?.context("Nothing to revert")?;
 
-    if !output.status.success() {
-        let _ = Command::new("git")
-            .args(["revert", "--abort"])
-            .current_dir(repo_path)
-            .output();
+    repo.revert(&commit, None)
+        .map_err(git_err)
+        .context("Failed to create revert commit")?;
+    let mut index = repo.index()?;
+    if index.has_conflicts() {
+        // Like `git revert --abort`
+        repo.cleanup_state()?;
+        repo.reset(head.as_object(), ResetType::Hard, None)?;
         anyhow::bail!(
-            "Failed to create revert commit: {}",
-            String::from_utf8_lossy(&output.stderr)
+            "Failed to create revert commit: later commits changed the same lines of {}",
+            commit.summary().unwrap_or_default()
         );
     }
 
-    Ok(())
-}
-
-fn push_main(repo_path: &Path) -> Result<()> {
-    let output = Command::new("git")
-        .args(["push", "origin", "main"])
-        .current_dir(repo_path)
-        .output()
-        .context("Failed to push revert commit")?;
-
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to push revert commit: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
+    let tree = repo.find_tree(index.write_tree()?)?;
+    let (author, committer) = signatures(&repo)?;
+    let message = format!(
+        "Revert \"{}\"\n\nThis reverts commit {}.\n",
+        commit.summary().unwrap_or_default(),
+        commit.id()
+    );
+    create_commit(&repo, &author, &committer, &message, &tree, &[&head])
+        .context("Failed to create revert commit")?;
+    repo.cleanup_state()?;
 
     Ok(())
 }
 
-fn git_stdout(repo_path: &Path, args: &[&str]) -> Result<String> {
-    let output = Command::new("git")
-        .args(args)
-        .current_dir(repo_path)
-        .output()
-        .with_context(|| format!("Failed to run git {}", args.join(" ")))?;
-
-    if !output.status.success() {
-        anyhow::bail!(
-            "git {} failed: {}",
-            args.join(" "),
-            String::from_utf8_lossy(&output.stderr)
-        );
+fn push_branch(repo_path: &Path, branch: &str, policy: &RetryPolicy) -> Result<()> {
+    let repo = open(repo_path)?;
+    if let Some(reason) =
+        push_ref(&repo, &refspec(branch), policy).context("Failed to push revert commit")?
+    {
+        anyhow::bail!("Failed to push revert commit: {}", reason);
     }
 
-    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    Ok(())
 }
 
-fn ensure_github_repo(
-    config: &Config,
-    repo_path: &Path,
-    username: &str,
-    repo_name: &str,
-) -> Result<()> {
-    check_gh()?;
-
-    ui::info(&format!(
-        "Creating GitHub repository: {}/{}",
-        username, repo_name
-    ));
+/// Create the GitHub repository with gh if it doesn't exist yet
+fn ensure_github_repo(config: &Config, username: &str, repo_name: &str) -> Result<()> {
+    let full_name = format!("{}/{}", username, repo_name);
 
     // Check if repo already exists
     let check_output = Command::new("gh")
-        .args(["repo", "view", &format!("{}/{}", username, repo_name)])
+        .args(["repo", "view", &full_name])
         .output();
 
-    if check_output.is_ok() && check_output.unwrap().status.success() {
-        ui::info("Repository already exists on GitHub");
-    } else {
-        // Prompt to create repo
-        if ui::prompt_confirm(
-            &format!("Create private GitHub repository '{}'?", repo_name),
-            true,
-        ) {
-            let mut create_args = vec!["repo", "create", repo_name];
-
-            if config.github.private {
-                create_args.push("--private");
-            } else {
-                create_args.push("--public");
-            }
-
-            create_args.push("--source");
-            create_args.push(".");
-
-            let output = Command::new("gh")
-                .args(&create_args)
-                .current_dir(repo_path)
-                .output()
-                .context("Failed to create GitHub repository")?;
-
-            if !output.status.success() {
-                anyhow::bail!(
-                    "Failed to create repo: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                );
-            }
-
-            ui::success(&format!(
-                "Created GitHub repository: {}/{}",
-                username, repo_name
-            ));
-        } else {
-            anyhow::bail!("Repository creation cancelled");
+    if let Ok(output) = &check_output {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if !output.status.success() && crate::offline::is_transient(&stderr) {
+            anyhow::bail!("Could not reach GitHub: {}", stderr.trim());
         }
     }
 
-    // Always ensure remote URL matches selected repo
-    add_remote(username, repo_name, repo_path)?;
-
-    Ok(())
-}
+    if check_output.is_ok_and(|output| output.status.success()) {
+        ui::info("Repository already exists on GitHub");
+        return Ok(());
+    }
 
-fn add_remote(username: &str, repo_name: &str, repo_path: &Path) -> Result<()> {
-    let remote_url = format!("git@github.com:{}/{}.git", username, repo_name);
+    ui::info(&format!("Creating GitHub repository: {}", full_name));
+    if !ui::prompt_confirm(
+        &format!("Create private GitHub repository '{}'?", repo_name),
+        true,
+    ) {
+        anyhow::bail!("Repository creation cancelled");
+    }
 
-    let output = Command::new("git")
-        .args(["remote", "add", "origin", remote_url.as_str()])
-        .current_dir(repo_path)
+    let visibility = if config.github.private {
+        "--private"
+    } else {
+        "--public"
+    };
+    let output = Command::new("gh")
+        .args(["repo", "create", &full_name, visibility])
         .output()
-        .context("Failed to add remote")?;
+        .context("Failed to create GitHub repository")?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        if stderr.contains("already exists") {
-            // Update existing remote
-            let output = Command::new("git")
-                .args(["remote", "set-url", "origin", remote_url.as_str()])
-                .current_dir(repo_path)
-                .output()
-                .context("Failed to update remote")?;
-
-            if !output.status.success() {
-                anyhow::bail!(
-                    "Failed to update remote: {}",
-                    String::from_utf8_lossy(&output.stderr)
-                );
-            }
-        } else {
-            anyhow::bail!("Failed to add remote: {}", stderr);
-        }
+        anyhow::bail!(
+            "Failed to create repo: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
     }
 
+    ui::success(&format!("Created GitHub repository: {}", full_name));
     Ok(())
 }
 
-fn clone_repo(username: &str, repo_name: &str, dest_path: &Path) -> Result<()> {
-    let repo_url = format!("git@github.com:{}/{}.git", username, repo_name);
+fn set_origin(repo: &Repository, remote_url: &str) -> Result<()> {
+    match repo.find_remote("origin") {
+        Ok(remote) if remote.url() == Some(remote_url) => Ok(()),
+        Ok(_) => repo
+            .remote_set_url("origin", remote_url)
+            .map_err(git_err)
+            .context("Failed to update remote"),
+        Err(_) => repo
+            .remote("origin", remote_url)
+            .map(|_| ())
+            .map_err(git_err)
+            .context("Failed to add remote"),
+    }
+}
 
-    ui::info(&format!("Cloning repository from {}", repo_url));
+fn clone_repo(target: &Target, dest_path: &Path, policy: &RetryPolicy) -> Result<()> {
+    ui::info(&format!("Cloning repository from {}", target.url));
 
     // Create parent directory
-    if let Some(parent) = dest_path.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
+    let parent = dest_path.parent().unwrap_or(Path::new("/"));
+    std::fs::create_dir_all(parent)?;
 
-    let output = Command::new("git")
-        .args(["clone", repo_url.as_str(), dest_path.to_str().unwrap()])
-        .output()
+    clone(&target.url, &target.branch, dest_path, policy)
+        .map_err(|e| not_found_hint(e, target))
         .context("Failed to clone repository")?;
 
-    if !output.status.success() {
-        anyhow::bail!(
-            "Failed to clone: {}",
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
-
     ui::success("Repository cloned successfully");
     Ok(())
 }
@@ -726,12 +1269,96 @@ mod tests {
         );
     }
 
+    fn git_stdout(repo_path: &Path, args: &[&str]) -> Result<String> {
+        let output = git(repo_path, args);
+        anyhow::ensure!(output.status.success(), "git {} failed", args.join(" "));
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
     fn init_repo(repo_path: &Path) {
         git_ok(repo_path, &["init", "-b", "main"]);
         git_ok(repo_path, &["config", "user.email", "test@example.com"]);
         git_ok(repo_path, &["config", "user.name", "Dotdipper Tests"]);
     }
 
+    #[test]
+    fn target_honors_remote_url_branch_and_protocol() {
+        let mut config = Config::default();
+        config.github.username = Some("me".to_string());
+        config.github.repo_name = Some("dots".to_string());
+        let github = target(&config, None).unwrap();
+        assert_eq!(github.url, "git@github.com:me/dots.git");
+        assert_eq!(github.branch, "main");
+
+        config.github.protocol = GitProtocol::Https;
+        config.github.branch = Some("laptop".to_string());
+        assert_eq!(
+            target(&config, Some("other")).unwrap().url,
+            "https://github.com/me/other.git"
+        );
+        assert_eq!(remote_ref(&config), "origin/laptop");
+
+        config.github.username = None;
+        config.github.remote_url = Some("https://gitlab.com/me/dots.git".to_string());
+        let custom = target(&config, None).unwrap();
+        assert_eq!(custom.url, "https://gitlab.com/me/dots.git");
+        assert!(custom.github.is_none());
+    }
+
+    #[test]
+    fn ssh_signing_signs_dotdipper_commits() {
+        let temp = TempDir::new().unwrap();
+        let repo_path = temp.path().join("repo");
+        fs::create_dir_all(&repo_path).unwrap();
+        init_repo(&repo_path);
+        let key = temp.path().join("id_ed25519");
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", ""])
+            .arg("-f")
+            .arg(&key)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let repo = open(&repo_path).unwrap();
+        let github = crate::cfg::GitHubConfig {
+            sign: Some(true),
+            signing_key: Some(key.display().to_string()),
+            signing_format: Some(crate::cfg::SigningFormat::Ssh),
+            ..Default::default()
+        };
+        sign::configure(&repo, &github).unwrap();
+        fs::write(repo_path.join("file.txt"), "one\n").unwrap();
+        assert!(commit_all(&repo, "Signed").unwrap());
+
+        let head = head_commit(&repo).unwrap().unwrap();
+        assert_eq!(head.message(), Some("Signed"));
+        let (signature, _) = repo.extract_signature(&head.id(), None).unwrap();
+        assert!(signature
+            .as_str()
+            .unwrap()
+            .starts_with("-----BEGIN SSH SIGNATURE-----"));
+        assert_eq!(
+            git_stdout(&repo_path, &["show", "-s", "--format=%s"]).unwrap(),
+            "Signed"
+        );
+    }
+
+    #[test]
+    fn commit_message_fills_in_template() {
+        let mut config = Config::default();
+        assert!(commit_message(&config, 3)
+            .unwrap()
+            .starts_with("Update dotfiles - "));
+
+        config.github.commit_template = Some("{hostname}: {files} files ({profile})".to_string());
+        let host = crate::hosts::HostIdentity::local(&config).unwrap();
+        assert_eq!(
+            commit_message(&config, 3).unwrap(),
+            format!("{}: 3 files ({})", host.name, host.profile)
+        );
+    }
+
     #[test]
     fn revert_head_commit_restores_previous_contents() {
         if which::which("git").is_err() {
@@ -750,7 +1377,7 @@ mod tests {
         git_ok(temp_dir.path(), &["add", "-A"]);
         git_ok(temp_dir.path(), &["commit", "-m", "Update state"]);
 
-        revert_head_commit(temp_dir.path()).unwrap();
+        revert_commit(temp_dir.path(), "HEAD").unwrap();
 
         assert_eq!(fs::read_to_string(&tracked_file).unwrap(), "before\n");
         let subject = git_stdout(temp_dir.path(), &["log", "-1", "--pretty=%s"]).unwrap();
@@ -855,13 +1482,13 @@ mod tests {
         git_ok(local_dir.path(), &["push", "origin", "main"]);
 
         ensure_clean_worktree(local_dir.path()).unwrap();
-        fetch_origin_main(local_dir.path()).unwrap();
-        ensure_main_checked_out(local_dir.path()).unwrap();
-        fast_forward_main_to_origin(local_dir.path()).unwrap();
+        fetch_origin(local_dir.path(), "main", &RetryPolicy::single_attempt()).unwrap();
+        ensure_branch_checked_out(local_dir.path(), "main").unwrap();
+        fast_forward_to_origin(local_dir.path(), "main").unwrap();
         ensure_head_matches_ref(local_dir.path(), "origin/main").unwrap();
-        ensure_head_is_not_merge_commit(local_dir.path()).unwrap();
-        revert_head_commit(local_dir.path()).unwrap();
-        push_main(local_dir.path()).unwrap();
+        ensure_not_merge_commit(local_dir.path(), "HEAD").unwrap();
+        revert_commit(local_dir.path(), "HEAD").unwrap();
+        push_branch(local_dir.path(), "main", &RetryPolicy::single_attempt()).unwrap();
 
         let inspect_root = TempDir::new().unwrap();
         let inspect_repo = inspect_root.path().join("inspect");
@@ -910,7 +1537,7 @@ mod tests {
             &["merge", "--no-ff", "feature", "-m", "Merge feature"],
         );
 
-        let err = ensure_head_is_not_merge_commit(temp_dir.path()).unwrap_err();
+        let err = ensure_not_merge_commit(temp_dir.path(), "HEAD").unwrap_err();
         assert!(err.to_string().contains("merge commit"));
     }
 }
@@ -0,0 +1,131 @@
+//! Signing dotdipper's commits with GPG or SSH keys, the way git does.
+//!
+//! The `[github]` signing settings are stored in the compiled repository's
+//! git config (`commit.gpgsign`, `user.signingkey`, `gpg.format`), so the
+//! global git config applies when they are unset, and committing there by
+//! hand signs the same way.
+
+use anyhow::{bail, Context, Result};
+use git2::{ConfigLevel, Repository};
+use std::ffi::OsString;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::cfg::{GitHubConfig, SigningFormat};
+
+/// Write the `[github]` signing settings to the repository's git config
+pub(super) fn configure(repo: &Repository, github: &GitHubConfig) -> Result<()> {
+    if github.sign.is_none() && github.signing_key.is_none() && github.signing_format.is_none() {
+        return Ok(());
+    }
+    let mut config = repo.config()?.open_level(ConfigLevel::Local)?;
+    if let Some(sign) = github.sign {
+        config.set_bool("commit.gpgsign", sign)?;
+    }
+    if let Some(key) = &github.signing_key {
+        config.set_str("user.signingkey", key)?;
+    }
+    if let Some(format) = github.signing_format {
+        let format = match format {
+            SigningFormat::Gpg => "openpgp",
+            SigningFormat::Ssh => "ssh",
+        };
+        config.set_str("gpg.format", format)?;
+    }
+    Ok(())
+}
+
+/// Armored signature of the commit `content`, or None when the repository
+/// does not sign commits
+pub(super) fn signature(repo: &Repository, content: &str) -> Result<Option<String>> {
+    let config = repo.config()?.snapshot()?;
+    if !config.get_bool("commit.gpgsign").unwrap_or(false) {
+        return Ok(None);
+    }
+    let key = config
+        .get_string("user.signingkey")
+        .ok()
+        .filter(|key| !key.trim().is_empty());
+    let program = |keys: &[&str], fallback: &str| {
+        keys.iter()
+            .find_map(|key| config.get_string(key).ok())
+            .unwrap_or_else(|| fallback.to_string())
+    };
+
+    let format = config
+        .get_string("gpg.format")
+        .unwrap_or_else(|_| "openpgp".to_string());
+    match format.as_str() {
+        "openpgp" => {
+            let mut args: Vec<OsString> = vec!["--detach-sign".into(), "--armor".into()];
+            if let Some(key) = key {
+                args.extend(["--local-user".into(), key.into()]);
+            }
+            let gpg = program(&["gpg.openpgp.program", "gpg.program"], "gpg");
+            run(&gpg, &args, content).map(Some)
+        }
+        "ssh" => {
+            let Some(key) = key else {
+                bail!(
+                    "SSH commit signing needs a key; set [github] signing_key or git's user.signingkey"
+                );
+            };
+            // Like git, the key may be given inline instead of as a file
+            let literal = key
+                .strip_prefix("key::")
+                .or_else(|| key.starts_with("ssh-").then_some(key.as_str()));
+            let mut key_file = None;
+            let key_path = match literal {
+                Some(literal) => {
+                    let mut file = tempfile::NamedTempFile::new()?;
+                    writeln!(file, "{}", literal)?;
+                    key_file.insert(file).path().to_path_buf()
+                }
+                None => {
+                    let home = dirs::home_dir().context("Failed to find home directory")?;
+                    crate::matching::expand_tilde(&key, &home)
+                }
+            };
+            let args: Vec<OsString> = vec![
+                "-Y".into(),
+                "sign".into(),
+                "-n".into(),
+                "git".into(),
+                "-f".into(),
+                key_path.into(),
+            ];
+            let ssh_keygen = program(&["gpg.ssh.program"], "ssh-keygen");
+            run(&ssh_keygen, &args, content).map(Some)
+        }
+        other => bail!(
+            "Unsupported gpg.format '{}'; dotdipper signs with 'openpgp' or 'ssh'",
+            other
+        ),
+    }
+}
+
+/// Run a signing program with `content` on stdin, returning its stdout
+fn run(program: &str, args: &[OsString], content: &str) -> Result<String> {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {} to sign the commit", program))?;
+    child
+        .stdin
+        .take()
+        .context("Failed to open the signer's stdin")?
+        .write_all(content.as_bytes())?;
+    let output = child.wait_with_output()?;
+    let signature = String::from_utf8_lossy(&output.stdout).into_owned();
+    if !output.status.success() || signature.trim().is_empty() {
+        bail!(
+            "{} failed to sign the commit: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(signature)
+}
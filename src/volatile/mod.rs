@@ -0,0 +1,331 @@
+//! Knowledge base of volatile, host-specific files.
+//!
+//! Some files under `$HOME` look like configuration but should never sync:
+//! binary session databases (`~/.config/dconf/user`), caches, shell history,
+//! database journals and files holding a machine ID. They are easy to track by
+//! accident with broad include patterns. Discover and snapshot check candidate
+//! files against the built-in list below plus `[volatile] extra`, and by default
+//! leave matches out with an explanation (`action = "warn"` keeps them,
+//! `"off"` disables the check). `[volatile] allow` lists files to sync anyway.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::cfg::{Config, VolatileAction, VolatileConfig};
+use crate::matching::{to_home_relative, PathMatcher};
+use crate::ui;
+
+/// Built-in entries: gitignore-style pattern (anchored at `$HOME` when it
+/// starts with `~/`) and why the file should not sync.
+const KNOWN_VOLATILE: &[(&str, &str)] = &[
+    (
+        "~/.config/dconf/user",
+        "binary dconf database, rewritten constantly by the desktop session",
+    ),
+    ("~/.cache", "cache directory, regenerated automatically"),
+    (
+        "~/Library/Caches",
+        "cache directory, regenerated automatically",
+    ),
+    (
+        "~/.local/state",
+        "per-machine state (logs, history, session data)",
+    ),
+    (
+        "~/.local/share/recently-used.xbel",
+        "recently used files on this machine",
+    ),
+    ("~/.local/share/Trash", "trash contents"),
+    (
+        "~/.Xauthority",
+        "X session cookie tied to this machine's display",
+    ),
+    (
+        "~/.ICEauthority",
+        "X session cookie tied to this machine's display",
+    ),
+    ("~/.dbus", "D-Bus session data keyed by this machine's ID"),
+    (
+        "~/.config/pulse/cookie",
+        "PulseAudio authentication cookie for this machine",
+    ),
+    (
+        "~/.config/pulse/*.tdb",
+        "PulseAudio device database keyed by this machine's ID",
+    ),
+    (
+        "~/.config/pulse/*-runtime",
+        "PulseAudio runtime link to this machine's session",
+    ),
+    (
+        "~/.config/monitors.xml",
+        "display layout for this machine's monitors",
+    ),
+    (
+        "~/.gnupg/random_seed",
+        "GnuPG random seed, must differ between machines",
+    ),
+    ("machine-id", "random ID identifying this machine"),
+    ("machineid", "random ID identifying this machine"),
+    (
+        "SingletonLock",
+        "browser lock file naming this machine's host",
+    ),
+    (
+        "SingletonCookie",
+        "browser lock file naming this machine's host",
+    ),
+    (
+        "SingletonSocket",
+        "browser lock file naming this machine's host",
+    ),
+    (
+        "~/.config/Code/User/workspaceStorage",
+        "VS Code per-machine workspace state",
+    ),
+    (
+        "~/.config/Code/User/globalStorage",
+        "VS Code per-machine state database",
+    ),
+    ("GPUCache", "GPU shader cache, regenerated automatically"),
+    (
+        "Code Cache",
+        "compiled code cache, regenerated automatically",
+    ),
+    (
+        "*.sqlite-wal",
+        "database write-ahead log, only valid next to the live database",
+    ),
+    (
+        "*.sqlite-shm",
+        "database shared memory file, only valid next to the live database",
+    ),
+    (
+        "*.db-wal",
+        "database write-ahead log, only valid next to the live database",
+    ),
+    (
+        "*.db-shm",
+        "database shared memory file, only valid next to the live database",
+    ),
+    (
+        "*.db-journal",
+        "database journal, only valid next to the live database",
+    ),
+    (
+        "~/.zcompdump*",
+        "zsh completion cache, regenerated on startup",
+    ),
+    (
+        "~/.bash_history",
+        "shell history, changes with every command",
+    ),
+    (
+        "~/.zsh_history",
+        "shell history, changes with every command",
+    ),
+    (
+        "~/.local/share/fish/fish_history",
+        "shell history, changes with every command",
+    ),
+    (
+        "~/.python_history",
+        "REPL history, changes with every command",
+    ),
+    (
+        "~/.node_repl_history",
+        "REPL history, changes with every command",
+    ),
+    (
+        "~/.psql_history",
+        "REPL history, changes with every command",
+    ),
+    (
+        "~/.mysql_history",
+        "REPL history, changes with every command",
+    ),
+    (
+        "~/.sqlite_history",
+        "REPL history, changes with every command",
+    ),
+    ("~/.lesshst", "pager history, changes constantly"),
+    (
+        "~/.viminfo",
+        "vim history and marks, rewritten on every exit",
+    ),
+    ("~/.wget-hsts", "wget HSTS cache, rewritten on every run"),
+    (".DS_Store", "Finder metadata"),
+];
+
+/// A volatile file found among the candidates, with the reason it matched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VolatileMatch {
+    pub path: PathBuf,
+    pub reason: String,
+}
+
+/// Compiled knowledge base: built-in entries plus `[volatile] extra`
+pub struct VolatileFiles {
+    rules: Vec<(PathMatcher, String)>,
+    allow: PathMatcher,
+    action: VolatileAction,
+}
+
+impl VolatileFiles {
+    pub fn from_config(config: &Config, home: &Path) -> Result<Self> {
+        let volatile = config.volatile.clone().unwrap_or_default();
+        Self::new(&volatile, home)
+    }
+
+    pub fn new(volatile: &VolatileConfig, home: &Path) -> Result<Self> {
+        let mut rules = Vec::new();
+        let builtin = KNOWN_VOLATILE
+            .iter()
+            .map(|(pattern, reason)| (pattern.to_string(), reason.to_string()));
+        let extra = volatile
+            .extra
+            .iter()
+            .map(|(pattern, reason)| (pattern.clone(), reason.clone()));
+        for (pattern, reason) in builtin.chain(extra) {
+            rules.push((PathMatcher::new(home, &[pattern])?, reason));
+        }
+
+        Ok(VolatileFiles {
+            rules,
+            allow: PathMatcher::for_paths(home, &volatile.allow)?,
+            action: volatile.action,
+        })
+    }
+
+    pub fn action(&self) -> VolatileAction {
+        self.action
+    }
+
+    /// Why `path` should not sync, or `None` if it is not volatile (or allowed)
+    pub fn reason(&self, path: &Path) -> Option<&str> {
+        if self.action == VolatileAction::Off || self.allow.is_match(path) {
+            return None;
+        }
+        self.rules
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(path))
+            .map(|(_, reason)| reason.as_str())
+    }
+
+    /// Split `files` into the ones to keep and the volatile ones to skip.
+    ///
+    /// With `action = "warn"` nothing is skipped; the matches are still
+    /// returned so callers can warn about them.
+    pub fn partition(&self, files: Vec<PathBuf>) -> (Vec<PathBuf>, Vec<VolatileMatch>) {
+        let mut keep = Vec::new();
+        let mut matches = Vec::new();
+        for path in files {
+            match self.reason(&path) {
+                Some(reason) => {
+                    matches.push(VolatileMatch {
+                        path: path.clone(),
+                        reason: reason.to_string(),
+                    });
+                    if self.action == VolatileAction::Warn {
+                        keep.push(path);
+                    }
+                }
+                None => keep.push(path),
+            }
+        }
+        (keep, matches)
+    }
+}
+
+/// Explain skipped (or, with `action = "warn"`, kept) volatile files
+pub fn report(matches: &[VolatileMatch], action: VolatileAction, home: &Path) {
+    if matches.is_empty() {
+        return;
+    }
+
+    let verb = match action {
+        VolatileAction::Warn => "Tracking",
+        _ => "Skipping",
+    };
+    for m in matches {
+        ui::warn(&format!(
+            "{} volatile file ~/{}: {}",
+            verb,
+            to_home_relative(&m.path, home).display(),
+            m.reason
+        ));
+    }
+    if action == VolatileAction::Exclude {
+        ui::hint("Add paths to [volatile] allow in config.toml to sync them anyway");
+    }
+}
+
+/// Drop volatile files from `files`, explaining each one
+pub fn filter_and_report(
+    config: &Config,
+    home: &Path,
+    files: Vec<PathBuf>,
+) -> Result<Vec<PathBuf>> {
+    let volatile = VolatileFiles::from_config(config, home)?;
+    let (keep, matches) = volatile.partition(files);
+    report(&matches, volatile.action(), home);
+    Ok(keep)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_known_and_configured_volatile_files() {
+        let home = Path::new("/home/me");
+        let config = VolatileConfig {
+            action: VolatileAction::Exclude,
+            extra: BTreeMap::from([(
+                "~/.config/app/state.json".to_string(),
+                "rewritten on every launch".to_string(),
+            )]),
+            allow: vec!["~/.zsh_history".to_string()],
+        };
+        let volatile = VolatileFiles::new(&config, home).unwrap();
+
+        assert!(volatile
+            .reason(&home.join(".config/dconf/user"))
+            .unwrap()
+            .contains("dconf"));
+        assert!(volatile
+            .reason(&home.join(".config/google-chrome/Default/GPUCache/data_0"))
+            .is_some());
+        assert!(volatile
+            .reason(&home.join(".local/share/app/history.sqlite-wal"))
+            .is_some());
+        assert_eq!(
+            volatile.reason(&home.join(".config/app/state.json")),
+            Some("rewritten on every launch")
+        );
+        assert!(volatile.reason(&home.join(".zsh_history")).is_none());
+        assert!(volatile.reason(&home.join(".zshrc")).is_none());
+        assert!(volatile
+            .reason(&home.join(".config/dconf/user.d/00-custom"))
+            .is_none());
+
+        let (keep, skipped) = volatile.partition(vec![
+            home.join(".zshrc"),
+            home.join(".cache/fontconfig/abc"),
+        ]);
+        assert_eq!(keep, vec![home.join(".zshrc")]);
+        assert_eq!(skipped[0].path, home.join(".cache/fontconfig/abc"));
+
+        let warn_only = VolatileFiles::new(
+            &VolatileConfig {
+                action: VolatileAction::Warn,
+                ..Default::default()
+            },
+            home,
+        )
+        .unwrap();
+        let (keep, warned) = warn_only.partition(vec![home.join(".cache/x")]);
+        assert_eq!((keep.len(), warned.len()), (1, 1));
+    }
+}
@@ -0,0 +1,423 @@
+//! Workspace templates: per-project files stamped into other directories.
+//!
+//! A template is a named set of files (`.editorconfig`, `.envrc`,
+//! `.vscode/settings.json`, ...) stored under `.dotdipper/workspaces/<name>/`
+//! in the compiled directory, so it travels with the rest of the dotfiles.
+//! `dotdipper workspace apply <template> [dir]` copies it into a project,
+//! replacing `{{ var }}` placeholders in file contents and paths, and records
+//! what it wrote in `<dir>/.dotdipper-workspace.json`. Applying again (or
+//! `workspace update`) refreshes files still as stamped and leaves files
+//! edited in the project alone unless `--force` is given.
+//!
+//! Built-in variables: `project` (directory name), `dir`, `user`, `hostname`,
+//! `date` and `year`. `--var key=value` adds or overrides variables; they are
+//! remembered in the stamp for later updates.
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::ui;
+
+/// Where templates are stored, relative to the compiled directory
+pub const WORKSPACES_DIR: &str = ".dotdipper/workspaces";
+
+/// Record of stamped files, written into the project directory
+pub const STAMP_FILE: &str = ".dotdipper-workspace.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stamp {
+    pub template: String,
+    /// Variables given with `--var`, reused by updates
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    pub stamped_at: DateTime<Utc>,
+    /// Project-relative path -> blake3 hash of the content written
+    #[serde(default, with = "crate::hash::raw_path::keys")]
+    pub files: BTreeMap<PathBuf, String>,
+}
+
+/// What happened to one template file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StampAction {
+    Created,
+    Updated,
+    Unchanged,
+    /// Edited in the project since it was stamped
+    SkippedModified,
+    /// Already there and not written by this template
+    SkippedExisting,
+}
+
+pub fn templates_root() -> Result<PathBuf> {
+    Ok(crate::paths::compiled_dir()?.join(WORKSPACES_DIR))
+}
+
+fn template_dir(name: &str) -> Result<PathBuf> {
+    if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+        bail!("Invalid workspace template name: '{}'", name);
+    }
+    Ok(templates_root()?.join(name))
+}
+
+/// Template names with their file counts
+pub fn list() -> Result<Vec<(String, usize)>> {
+    let root = templates_root()?;
+    if !root.exists() {
+        return Ok(Vec::new());
+    }
+    let mut templates = Vec::new();
+    for entry in fs::read_dir(&root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            let name = entry.file_name().to_string_lossy().to_string();
+            templates.push((name, template_files(&entry.path())?.len()));
+        }
+    }
+    templates.sort();
+    Ok(templates)
+}
+
+/// Template-relative paths of a template's files
+fn template_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            files.push(entry.path().strip_prefix(dir)?.to_path_buf());
+        }
+    }
+    Ok(files)
+}
+
+/// `dotdipper workspace add <name> <files>`: copy project files into a
+/// template, keeping their paths relative to `from`
+pub fn add(name: &str, files: &[PathBuf], from: &Path) -> Result<usize> {
+    let dir = template_dir(name)?;
+    let mut added = 0;
+    for file in files {
+        let source = if file.is_absolute() {
+            file.clone()
+        } else {
+            from.join(file)
+        };
+        let Ok(rel) = source.strip_prefix(from) else {
+            bail!(
+                "{} is outside {}; run from the project root or pass --from",
+                source.display(),
+                from.display()
+            );
+        };
+        let sources: Vec<PathBuf> = if source.is_dir() {
+            WalkDir::new(&source)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(|e| e.file_type().is_file())
+                .map(|e| e.into_path())
+                .collect()
+        } else if source.is_file() {
+            vec![source.clone()]
+        } else {
+            bail!("No such file: {}", source.display());
+        };
+        for path in sources {
+            let rel = path.strip_prefix(from).unwrap_or(rel);
+            let dest = dir.join(rel);
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&path, &dest).with_context(|| format!("Failed to copy {}", path.display()))?;
+            added += 1;
+        }
+    }
+    Ok(added)
+}
+
+/// Variables every template can use
+pub fn builtin_vars(dir: &Path) -> BTreeMap<String, String> {
+    let now = Local::now();
+    let mut vars = BTreeMap::new();
+    let project = dir
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    vars.insert("project".to_string(), project);
+    vars.insert("dir".to_string(), dir.display().to_string());
+    vars.insert(
+        "user".to_string(),
+        std::env::var("USER").unwrap_or_else(|_| "unknown".to_string()),
+    );
+    vars.insert(
+        "hostname".to_string(),
+        hostname::get()
+            .ok()
+            .and_then(|h| h.into_string().ok())
+            .unwrap_or_else(|| "unknown".to_string()),
+    );
+    vars.insert("date".to_string(), now.format("%Y-%m-%d").to_string());
+    vars.insert("year".to_string(), now.format("%Y").to_string());
+    vars
+}
+
+/// Replace `{{ name }}` placeholders, failing on unknown variables
+pub fn render(text: &str, vars: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut missing = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let name = rest[start + 2..start + 2 + len].trim();
+        match vars.get(name) {
+            Some(value) => out.push_str(value),
+            None => missing.push(name.to_string()),
+        }
+        rest = &rest[start + 2 + len + 2..];
+    }
+    out.push_str(rest);
+
+    if !missing.is_empty() {
+        missing.sort();
+        missing.dedup();
+        bail!(
+            "Undefined template variables: {} (pass them with --var name=value)",
+            missing.join(", ")
+        );
+    }
+    Ok(out)
+}
+
+fn hash(bytes: &[u8]) -> String {
+    blake3::hash(bytes).to_hex().to_string()
+}
+
+pub fn load_stamp(dir: &Path) -> Result<Option<Stamp>> {
+    let path = dir.join(STAMP_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents =
+        fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let stamp = serde_json::from_str(&contents)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(stamp))
+}
+
+/// Decide what to do with one rendered file
+fn plan(target: &Path, content: &[u8], stamped: Option<&String>, force: bool) -> StampAction {
+    let Ok(current) = fs::read(target) else {
+        return StampAction::Created;
+    };
+    let current = hash(&current);
+    if current == hash(content) {
+        StampAction::Unchanged
+    } else if force || stamped == Some(&current) {
+        StampAction::Updated
+    } else if stamped.is_some() {
+        StampAction::SkippedModified
+    } else {
+        StampAction::SkippedExisting
+    }
+}
+
+/// `dotdipper workspace apply <template> [dir]`
+pub fn apply(
+    name: &str,
+    dir: &Path,
+    cli_vars: BTreeMap<String, String>,
+    force: bool,
+    dry_run: bool,
+) -> Result<Vec<(PathBuf, StampAction)>> {
+    let template = template_dir(name)?;
+    if !template.is_dir() {
+        bail!(
+            "No workspace template named '{}'. Create one with 'dotdipper workspace add {} <files>'",
+            name,
+            name
+        );
+    }
+    if !dir.is_dir() {
+        bail!("Not a directory: {}", dir.display());
+    }
+    let dir = dir.canonicalize()?;
+
+    let previous = load_stamp(&dir)?;
+    if let Some(stamp) = &previous {
+        if stamp.template != name && !force {
+            bail!(
+                "{} was stamped from template '{}'; use --force to stamp '{}' over it",
+                dir.display(),
+                stamp.template,
+                name
+            );
+        }
+    }
+    let previous = previous.filter(|s| s.template == name);
+
+    let mut remembered = previous
+        .as_ref()
+        .map(|s| s.vars.clone())
+        .unwrap_or_default();
+    remembered.extend(cli_vars);
+    let mut vars = builtin_vars(&dir);
+    vars.extend(remembered.clone());
+
+    let mut stamp = Stamp {
+        template: name.to_string(),
+        vars: remembered,
+        stamped_at: Utc::now(),
+        files: BTreeMap::new(),
+    };
+    // Render everything first so a missing variable writes nothing
+    let mut rendered = Vec::new();
+    for rel in template_files(&template)? {
+        let bytes = fs::read(template.join(&rel))?;
+        let content = match String::from_utf8(bytes) {
+            Ok(text) => render(&text, &vars)
+                .with_context(|| format!("In template file {}", rel.display()))?
+                .into_bytes(),
+            Err(binary) => binary.into_bytes(),
+        };
+        // Names that aren't UTF-8 can't hold variables; keep them byte-exact
+        let rel = match rel.to_str() {
+            Some(name) => PathBuf::from(render(name, &vars)?),
+            None => rel,
+        };
+        rendered.push((rel, content));
+    }
+
+    let mut results = Vec::new();
+    for (rel, content) in rendered {
+        let target = dir.join(&rel);
+        let stamped = previous.as_ref().and_then(|s| s.files.get(&rel));
+
+        let action = plan(&target, &content, stamped, force);
+        match action {
+            StampAction::Created | StampAction::Updated => {
+                if !dry_run {
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::write(&target, &content)
+                        .with_context(|| format!("Failed to write {}", target.display()))?;
+                }
+                stamp.files.insert(rel.clone(), hash(&content));
+            }
+            StampAction::Unchanged => {
+                stamp.files.insert(rel.clone(), hash(&content));
+            }
+            // Keep the old hash so later updates still see the local edit
+            StampAction::SkippedModified => {
+                if let Some(old) = stamped {
+                    stamp.files.insert(rel.clone(), old.clone());
+                }
+            }
+            StampAction::SkippedExisting => {}
+        }
+        results.push((rel, action));
+    }
+
+    if !dry_run {
+        fs::write(dir.join(STAMP_FILE), serde_json::to_string_pretty(&stamp)?)?;
+    }
+    Ok(results)
+}
+
+/// `dotdipper workspace update [dir]`: re-apply the template a directory was
+/// stamped from
+pub fn update(dir: &Path, force: bool, dry_run: bool) -> Result<Vec<(PathBuf, StampAction)>> {
+    let Some(stamp) = load_stamp(dir)? else {
+        bail!(
+            "{} has no {}; stamp it with 'dotdipper workspace apply <template>'",
+            dir.display(),
+            STAMP_FILE
+        );
+    };
+    apply(&stamp.template, dir, BTreeMap::new(), force, dry_run)
+}
+
+/// Print per-file results and a summary
+pub fn print_results(results: &[(PathBuf, StampAction)], dry_run: bool) {
+    let verb = |done: &'static str, planned: &'static str| if dry_run { planned } else { done };
+    for (rel, action) in results {
+        let path = rel.display();
+        match action {
+            StampAction::Created => {
+                ui::info(&format!("{} {}", verb("Created", "Would create"), path))
+            }
+            StampAction::Updated => {
+                ui::info(&format!("{} {}", verb("Updated", "Would update"), path))
+            }
+            StampAction::Unchanged => ui::debug(&format!("Unchanged {}", path)),
+            StampAction::SkippedModified => ui::warn(&format!(
+                "Skipped {}: modified since it was stamped (use --force to overwrite)",
+                path
+            )),
+            StampAction::SkippedExisting => ui::warn(&format!(
+                "Skipped {}: already exists (use --force to overwrite)",
+                path
+            )),
+        }
+    }
+    let written = results
+        .iter()
+        .filter(|(_, a)| matches!(a, StampAction::Created | StampAction::Updated))
+        .count();
+    if dry_run {
+        ui::info(&format!("Would write {} files (dry run)", written));
+    } else {
+        ui::success(&format!("Stamped {} files", written));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render_and_plan_stamped_files() {
+        let vars = BTreeMap::from([
+            ("project".to_string(), "api".to_string()),
+            ("owner".to_string(), "me".to_string()),
+        ]);
+        assert_eq!(
+            render("name = \"{{ project }}\" # {{owner}}\n", &vars).unwrap(),
+            "name = \"api\" # me\n"
+        );
+        let err = render("{{ missing }} {{ project }}", &vars).unwrap_err();
+        assert!(err.to_string().contains("missing"));
+
+        let temp_dir = TempDir::new().unwrap();
+        let target = temp_dir.path().join(".editorconfig");
+        assert_eq!(plan(&target, b"new", None, false), StampAction::Created);
+
+        fs::write(&target, "old").unwrap();
+        let stamped = hash(b"old");
+        assert_eq!(
+            plan(&target, b"new", Some(&stamped), false),
+            StampAction::Updated
+        );
+        assert_eq!(plan(&target, b"old", None, false), StampAction::Unchanged);
+        assert_eq!(
+            plan(&target, b"new", None, false),
+            StampAction::SkippedExisting
+        );
+
+        fs::write(&target, "edited").unwrap();
+        assert_eq!(
+            plan(&target, b"new", Some(&stamped), false),
+            StampAction::SkippedModified
+        );
+        assert_eq!(
+            plan(&target, b"new", Some(&stamped), true),
+            StampAction::Updated
+        );
+    }
+}
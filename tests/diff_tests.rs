@@ -30,6 +30,7 @@ fn test_diff_entry_struct() {
         source_path: PathBuf::from("/home/user/.dotdipper/compiled/.zshrc"),
         target_path: PathBuf::from("/home/user/.zshrc"),
         status: DiffStatus::Modified,
+        renamed_from: None,
     };
 
     assert_eq!(entry.rel_path, PathBuf::from(".zshrc"));
@@ -44,12 +45,14 @@ fn test_filter_by_paths_empty_filter() {
             source_path: PathBuf::from("/source/.zshrc"),
             target_path: PathBuf::from("/target/.zshrc"),
             status: DiffStatus::Modified,
+            renamed_from: None,
         },
         DiffEntry {
             rel_path: PathBuf::from(".vimrc"),
             source_path: PathBuf::from("/source/.vimrc"),
             target_path: PathBuf::from("/target/.vimrc"),
             status: DiffStatus::New,
+            renamed_from: None,
         },
     ];
 
@@ -66,18 +69,21 @@ fn test_filter_by_paths_specific() {
             source_path: PathBuf::from("/source/.zshrc"),
             target_path: PathBuf::from("/target/.zshrc"),
             status: DiffStatus::Modified,
+            renamed_from: None,
         },
         DiffEntry {
             rel_path: PathBuf::from(".vimrc"),
             source_path: PathBuf::from("/source/.vimrc"),
             target_path: PathBuf::from("/target/.vimrc"),
             status: DiffStatus::New,
+            renamed_from: None,
         },
         DiffEntry {
             rel_path: PathBuf::from(".bashrc"),
             source_path: PathBuf::from("/source/.bashrc"),
             target_path: PathBuf::from("/target/.bashrc"),
             status: DiffStatus::Missing,
+            renamed_from: None,
         },
     ];
 
@@ -96,18 +102,21 @@ fn test_filter_by_paths_directory() {
             source_path: PathBuf::from("/source/.config/nvim/init.lua"),
             target_path: PathBuf::from("/target/.config/nvim/init.lua"),
             status: DiffStatus::Modified,
+            renamed_from: None,
         },
         DiffEntry {
             rel_path: PathBuf::from(".config/nvim/lua/plugins.lua"),
             source_path: PathBuf::from("/source/.config/nvim/lua/plugins.lua"),
             target_path: PathBuf::from("/target/.config/nvim/lua/plugins.lua"),
             status: DiffStatus::New,
+            renamed_from: None,
         },
         DiffEntry {
             rel_path: PathBuf::from(".zshrc"),
             source_path: PathBuf::from("/source/.zshrc"),
             target_path: PathBuf::from("/target/.zshrc"),
             status: DiffStatus::Modified,
+            renamed_from: None,
         },
     ];
 
@@ -124,6 +133,7 @@ fn test_diff_entry_clone() {
         source_path: PathBuf::from("/source/.tmux.conf"),
         target_path: PathBuf::from("/target/.tmux.conf"),
         status: DiffStatus::Identical,
+        renamed_from: None,
     };
 
     let cloned = entry.clone();
@@ -146,18 +156,21 @@ fn test_filter_by_paths_multiple_filters() {
             source_path: PathBuf::from("/source/.zshrc"),
             target_path: PathBuf::from("/target/.zshrc"),
             status: DiffStatus::Modified,
+            renamed_from: None,
         },
         DiffEntry {
             rel_path: PathBuf::from(".vimrc"),
             source_path: PathBuf::from("/source/.vimrc"),
             target_path: PathBuf::from("/target/.vimrc"),
             status: DiffStatus::New,
+            renamed_from: None,
         },
         DiffEntry {
             rel_path: PathBuf::from(".bashrc"),
             source_path: PathBuf::from("/source/.bashrc"),
             target_path: PathBuf::from("/target/.bashrc"),
             status: DiffStatus::Missing,
+            renamed_from: None,
         },
     ];
 
@@ -174,6 +187,7 @@ fn test_filter_by_paths_no_match() {
         source_path: PathBuf::from("/source/.zshrc"),
         target_path: PathBuf::from("/target/.zshrc"),
         status: DiffStatus::Modified,
+        renamed_from: None,
     }];
 
     let filter_paths = vec!["nonexistent".to_string()];
@@ -129,6 +129,8 @@ mod secrets_config_tests {
             secrets: Some(SecretsConfig {
                 provider: Some("age".to_string()),
                 key_path: Some("~/.config/age/keys.txt".to_string()),
+                backend: None,
+                tracked: Vec::new(),
             }),
             ..Config::default()
         };
@@ -143,6 +145,8 @@ mod secrets_config_tests {
         let secrets = SecretsConfig {
             provider: Some("age".to_string()),
             key_path: Some("/path/to/keys.txt".to_string()),
+            backend: None,
+            tracked: Vec::new(),
         };
 
         let toml = toml::to_string(&secrets).unwrap();